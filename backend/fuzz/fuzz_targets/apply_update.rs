@@ -0,0 +1,129 @@
+//! Builds a valid update from arbitrary edits to a project-shaped doc, mutates
+//! its bytes and sends it, framed as a client's sync_update, through decoding
+//! and into a doc that already holds tasks.
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use yrs::{
+    Array as _, ArrayRef, Doc, Map as _, MapRef, Out, ReadTxn as _, StateVector, Text as _,
+    TextRef, Transact as _, TransactionMut, Update, updates::decoder::Decode as _,
+};
+
+#[allow(dead_code)]
+#[path = "../../src/api/collab/msg_sync.rs"]
+mod msg_sync;
+
+#[derive(Arbitrary, Debug)]
+enum Edit {
+    SetName { task: u8, name: String },
+    SetDesc { task: u8, desc: String },
+    AddChild { task: u8, child: u8 },
+    RemoveTask { task: u8 },
+    SetConfig { key: String, value: String },
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    edits: Vec<Edit>,
+    /// Byte offsets into the encoded update, and what to xor them with.
+    mutations: Vec<(u16, u8)>,
+    /// Whether to edit a copy of the target doc, so the update builds on its
+    /// state, or an unrelated doc.
+    shared_history: bool,
+}
+
+const TASKS: [&str; 3] = ["root", "1", "2"];
+
+fn seed() -> Doc {
+    let doc = Doc::new();
+    let graph = doc.get_or_insert_map("graph");
+    doc.get_or_insert_map("config");
+    let mut txn = doc.transact_mut();
+    for id in TASKS {
+        let task: MapRef = graph.get_or_init(&mut txn, id);
+        task.insert(&mut txn, "id", id);
+        task.insert(&mut txn, "name", format!("Task {id}"));
+        let _: TextRef = task.get_or_init(&mut txn, "desc");
+        let _: ArrayRef = task.get_or_init(&mut txn, "children");
+    }
+    drop(txn);
+    doc
+}
+
+fn task(graph: &MapRef, txn: &TransactionMut, task: u8) -> Option<(&'static str, MapRef)> {
+    let id = TASKS[usize::from(task) % TASKS.len()];
+    match graph.get(txn, id)? {
+        Out::YMap(map) => Some((id, map)),
+        _ => None,
+    }
+}
+
+fn edit(doc: &Doc, edits: &[Edit]) {
+    let graph = doc.get_or_insert_map("graph");
+    let config = doc.get_or_insert_map("config");
+    let mut txn = doc.transact_mut();
+    for edit in edits {
+        match edit {
+            Edit::SetName { task: t, name } => {
+                if let Some((_, map)) = task(&graph, &txn, *t) {
+                    map.insert(&mut txn, "name", name.as_str());
+                }
+            }
+            Edit::SetDesc { task: t, desc } => {
+                if let Some((_, map)) = task(&graph, &txn, *t) {
+                    if let Some(Out::YText(text)) = map.get(&txn, "desc") {
+                        text.push(&mut txn, desc);
+                    }
+                }
+            }
+            Edit::AddChild { task: t, child } => {
+                let child = task(&graph, &txn, *child).map(|(id, _)| id);
+                if let (Some((_, map)), Some(child)) = (task(&graph, &txn, *t), child) {
+                    if let Some(Out::YArray(children)) = map.get(&txn, "children") {
+                        children.push_back(&mut txn, child);
+                    }
+                }
+            }
+            Edit::RemoveTask { task: t } => {
+                if let Some((id, _)) = task(&graph, &txn, *t) {
+                    graph.remove(&mut txn, id);
+                }
+            }
+            Edit::SetConfig { key, value } => {
+                config.insert(&mut txn, key.as_str(), value.as_str());
+            }
+        }
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let target = seed();
+    let source = if input.shared_history {
+        let source = Doc::new();
+        let state = target
+            .transact()
+            .encode_state_as_update_v2(&StateVector::default());
+        if let Ok(update) = Update::decode_v2(&state) {
+            let _ = source.transact_mut().apply_update(update);
+        }
+        source
+    } else {
+        seed()
+    };
+    let sv = target.transact().state_vector();
+    edit(&source, &input.edits);
+
+    let mut update = source.transact().encode_state_as_update_v2(&sv);
+    for (offset, mask) in &input.mutations {
+        if !update.is_empty() {
+            let i = usize::from(*offset) % update.len();
+            update[i] ^= mask;
+        }
+    }
+
+    let msg = msg_sync::sync_update(&update);
+    if let Ok(msg_sync::ClientFrame::Sync { update, .. }) = msg_sync::decode_client_frame(&msg) {
+        let _ = target.transact_mut().apply_update(update);
+    }
+});
@@ -0,0 +1,17 @@
+//! Feeds arbitrary bytes to the client message decoder and applies any update
+//! it accepts, as the server would, to a fresh doc.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use yrs::{Doc, Transact as _};
+
+#[allow(dead_code)]
+#[path = "../../src/api/collab/msg_sync.rs"]
+mod msg_sync;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(msg_sync::ClientFrame::Sync { update, .. }) = msg_sync::decode_client_frame(data) {
+        let doc = Doc::new();
+        let _ = doc.transact_mut().apply_update(update);
+    }
+});
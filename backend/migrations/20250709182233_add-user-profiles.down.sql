@@ -0,0 +1,6 @@
+DROP TABLE user_avatars;
+
+ALTER TABLE users
+DROP COLUMN display_name,
+DROP COLUMN locale,
+DROP COLUMN working_hours;
@@ -0,0 +1,12 @@
+ALTER TABLE users
+ADD COLUMN display_name varchar(255),
+ADD COLUMN locale varchar(35),
+ADD COLUMN working_hours jsonb;
+
+CREATE TABLE user_avatars (
+    email varchar(320) NOT NULL,
+    content_type varchar(64) NOT NULL,
+    data bytea NOT NULL,
+    update_time timestamptz NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (email)
+);
@@ -0,0 +1,8 @@
+CREATE TABLE project_groups (
+    project_id varchar(36) NOT NULL,
+    name varchar(64) NOT NULL,
+    members varchar(320)[] NOT NULL,
+    policy varchar(32) NOT NULL,
+    next_index integer NOT NULL DEFAULT 0,
+    PRIMARY KEY (project_id, name)
+);
@@ -0,0 +1,16 @@
+CREATE TABLE task_projections (
+    project_id varchar(36) NOT NULL,
+    task_id varchar(64) NOT NULL,
+    num varchar(16) NOT NULL,
+    name text NOT NULL,
+    assignee varchar(320),
+    status varchar(32),
+    kind varchar(32),
+    deadline jsonb,
+    deadline_millis bigint,
+    archived boolean NOT NULL DEFAULT FALSE,
+    update_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (project_id, task_id)
+);
+
+CREATE INDEX task_projections_assignee_idx ON task_projections (assignee);
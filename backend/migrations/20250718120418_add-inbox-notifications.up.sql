@@ -0,0 +1,14 @@
+CREATE TABLE inbox_notifications (
+    id varchar(36) NOT NULL,
+    email varchar(320) NOT NULL,
+    kind varchar(32) NOT NULL,
+    project_id varchar(36),
+    task_id varchar(64),
+    actor varchar(320),
+    text text NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    read_time timestamp with time zone,
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX inbox_notifications_email_idx ON inbox_notifications (email, create_time DESC);
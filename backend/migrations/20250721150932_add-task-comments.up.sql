@@ -0,0 +1,14 @@
+CREATE TABLE task_comments (
+    id varchar(36) NOT NULL,
+    project_id varchar(36) NOT NULL,
+    task_id varchar(64) NOT NULL,
+    parent_id varchar(36),
+    author varchar(320) NOT NULL,
+    body text NOT NULL,
+    resolved boolean NOT NULL DEFAULT FALSE,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    update_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX task_comments_task_idx ON task_comments (project_id, task_id, create_time);
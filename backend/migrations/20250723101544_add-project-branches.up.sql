@@ -0,0 +1,12 @@
+CREATE TABLE project_branches (
+    branch_project_id varchar(36) NOT NULL,
+    project_id varchar(36) NOT NULL,
+    name varchar(36) NOT NULL,
+    creator varchar(320) NOT NULL,
+    -- State vector of the project's doc when the branch was created.
+    base_state_vector bytea NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (branch_project_id)
+);
+
+CREATE INDEX project_branches_project_idx ON project_branches (project_id);
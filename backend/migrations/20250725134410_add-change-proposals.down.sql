@@ -0,0 +1,4 @@
+DROP TABLE proposal_updates;
+DROP TABLE change_proposals;
+ALTER TABLE project_permissions DROP COLUMN admin;
+ALTER TABLE projects DROP COLUMN require_review;
@@ -0,0 +1,26 @@
+ALTER TABLE projects ADD COLUMN require_review boolean NOT NULL DEFAULT FALSE;
+
+-- Existing members keep full edit rights.
+ALTER TABLE project_permissions ADD COLUMN admin boolean NOT NULL DEFAULT FALSE;
+UPDATE project_permissions SET admin = TRUE;
+
+CREATE TABLE change_proposals (
+    id varchar(36) NOT NULL,
+    project_id varchar(36) NOT NULL,
+    author varchar(320) NOT NULL,
+    status varchar(16) NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    update_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    reviewer varchar(320),
+    PRIMARY KEY (id)
+);
+
+-- At most one pending proposal per author and project.
+CREATE UNIQUE INDEX change_proposals_pending_idx ON change_proposals (project_id, author) WHERE status = 'pending';
+
+CREATE TABLE proposal_updates (
+    proposal_id varchar(36) NOT NULL,
+    seq SERIAL NOT NULL,
+    update_v2 bytea NOT NULL,
+    PRIMARY KEY (proposal_id, seq)
+);
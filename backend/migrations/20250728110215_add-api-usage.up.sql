@@ -0,0 +1,13 @@
+-- Request counts per project, aggregated into hourly buckets.
+CREATE TABLE api_usage (
+    project_id varchar(36) NOT NULL,
+    bucket_start timestamp with time zone NOT NULL,
+    channel varchar(8) NOT NULL,
+    email varchar(320) NOT NULL,
+    user_agent varchar(256) NOT NULL,
+    requests bigint NOT NULL,
+    errors bigint NOT NULL,
+    PRIMARY KEY (project_id, bucket_start, channel, email, user_agent)
+);
+
+CREATE INDEX api_usage_bucket_start_idx ON api_usage (bucket_start);
@@ -0,0 +1,2 @@
+DROP TABLE security_audit_log;
+DROP TABLE security_policies;
@@ -0,0 +1,21 @@
+-- Security policies set by a subscription's owner and applied to all of its members.
+CREATE TABLE security_policies (
+    owner_email varchar(320) NOT NULL,
+    ip_allowlist varchar(64)[] NOT NULL,
+    reauth_interval_secs integer,
+    update_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (owner_email)
+);
+
+CREATE TABLE security_audit_log (
+    id varchar(36) NOT NULL,
+    owner_email varchar(320) NOT NULL,
+    email varchar(320) NOT NULL,
+    event varchar(32) NOT NULL,
+    client_ip varchar(64),
+    details text NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX security_audit_log_owner_idx ON security_audit_log (owner_email, create_time);
@@ -0,0 +1,6 @@
+-- Users who recently re-authenticated and may perform sensitive operations until expire_time.
+CREATE TABLE step_ups (
+    email varchar(320) NOT NULL,
+    expire_time timestamp with time zone NOT NULL,
+    PRIMARY KEY (email)
+);
@@ -0,0 +1,13 @@
+-- Tasks removed by large transactions, kept for a day so admins can revert them.
+CREATE TABLE task_removals (
+    id varchar(36) NOT NULL,
+    project_id varchar(36) NOT NULL,
+    actor varchar(320) NOT NULL,
+    tasks jsonb NOT NULL,
+    placements jsonb NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    reverted_time timestamp with time zone,
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX task_removals_project_idx ON task_removals (project_id, create_time);
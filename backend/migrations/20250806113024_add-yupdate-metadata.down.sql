@@ -0,0 +1 @@
+DROP TABLE yupdate_metadata;
@@ -0,0 +1,18 @@
+-- Who and what produced each stored update. Unlike yupdates, rows are not
+-- merged by compaction.
+CREATE TABLE yupdate_metadata (
+    project_id varchar(36) NOT NULL,
+    seq integer NOT NULL,
+    txn_id varchar NOT NULL,
+    who varchar NOT NULL,
+    actor varchar(16) NOT NULL,
+    email varchar(320),
+    device varchar(256),
+    request_id varchar(256),
+    feature varchar NOT NULL,
+    update_len integer NOT NULL,
+    create_time timestamp with time zone NOT NULL,
+    PRIMARY KEY (project_id, seq)
+);
+
+CREATE INDEX yupdate_metadata_time_idx ON yupdate_metadata (project_id, create_time);
@@ -0,0 +1 @@
+ALTER TABLE yupdate_metadata DROP COLUMN changes;
@@ -0,0 +1,2 @@
+-- The tasks and fields changed by each update, for the changefeed.
+ALTER TABLE yupdate_metadata ADD COLUMN changes jsonb NOT NULL DEFAULT '[]';
@@ -0,0 +1,12 @@
+-- Named copies of a project's doc, e.g. at the start of a sprint, for diffing.
+CREATE TABLE project_snapshots (
+    id varchar(36) NOT NULL,
+    project_id varchar(36) NOT NULL,
+    name varchar(36) NOT NULL,
+    creator varchar(320) NOT NULL,
+    update_v2 bytea NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX project_snapshots_project_idx ON project_snapshots (project_id, create_time);
@@ -0,0 +1,9 @@
+-- Users who receive a weekly status report for a project.
+CREATE TABLE report_subscriptions (
+    project_id varchar(36) NOT NULL,
+    email varchar(320) NOT NULL,
+    -- 0 for Monday through 6 for Sunday, in UTC.
+    weekday smallint NOT NULL,
+    last_sent_time timestamp with time zone,
+    PRIMARY KEY (project_id, email)
+);
@@ -0,0 +1,3 @@
+DROP TABLE task_completions;
+DROP TABLE task_estimates;
+DROP TABLE task_status_transitions;
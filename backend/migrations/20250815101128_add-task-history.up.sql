@@ -0,0 +1,35 @@
+-- Every status a task has moved into, for flow analytics.
+CREATE TABLE task_status_transitions (
+    project_id varchar(36) NOT NULL,
+    task_id varchar NOT NULL,
+    status varchar,
+    -- The status's workflow category, if known, e.g. inProgress.
+    category varchar(16),
+    transition_time timestamp with time zone NOT NULL
+);
+
+CREATE INDEX task_status_transitions_task_idx ON task_status_transitions (project_id, task_id, transition_time);
+
+-- The first estimate given to each task.
+CREATE TABLE task_estimates (
+    project_id varchar(36) NOT NULL,
+    task_id varchar NOT NULL,
+    original_estimate bigint NOT NULL,
+    estimate_time timestamp with time zone NOT NULL,
+    PRIMARY KEY (project_id, task_id)
+);
+
+-- Estimates and actuals of tasks as of their most recent completion.
+CREATE TABLE task_completions (
+    project_id varchar(36) NOT NULL,
+    task_id varchar NOT NULL,
+    assignee varchar(320),
+    labels jsonb NOT NULL,
+    original_estimate bigint,
+    final_estimate bigint,
+    start_time timestamp with time zone,
+    done_time timestamp with time zone NOT NULL,
+    PRIMARY KEY (project_id, task_id)
+);
+
+CREATE INDEX task_completions_time_idx ON task_completions (project_id, done_time);
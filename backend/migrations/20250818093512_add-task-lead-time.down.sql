@@ -0,0 +1 @@
+ALTER TABLE task_completions DROP COLUMN create_time;
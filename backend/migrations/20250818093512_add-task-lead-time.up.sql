@@ -0,0 +1,2 @@
+-- When each completed task was first seen, for lead time.
+ALTER TABLE task_completions ADD COLUMN create_time timestamp with time zone;
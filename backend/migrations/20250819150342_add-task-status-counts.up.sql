@@ -0,0 +1,8 @@
+-- Daily counts of tasks per status, for cumulative flow diagrams.
+CREATE TABLE task_status_counts (
+    project_id varchar(36) NOT NULL,
+    day date NOT NULL,
+    status varchar NOT NULL,
+    count bigint NOT NULL,
+    PRIMARY KEY (project_id, day, status)
+);
@@ -0,0 +1,16 @@
+-- Goals, such as OKR objectives, whose progress derives from linked task subtrees.
+CREATE TABLE goals (
+    id varchar(36) PRIMARY KEY,
+    project_id varchar(36) NOT NULL,
+    title varchar(200) NOT NULL,
+    description text,
+    owner varchar(320),
+    due_date date,
+    -- Roots of the task subtrees the goal tracks, as a JSON array of task ids.
+    task_ids jsonb NOT NULL,
+    creator varchar(320) NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    update_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX goals_project_idx ON goals (project_id);
@@ -0,0 +1,17 @@
+-- Milestones with a target date, tracked by the completion of linked task subtrees.
+CREATE TABLE milestones (
+    id varchar(36) PRIMARY KEY,
+    project_id varchar(36) NOT NULL,
+    name varchar(200) NOT NULL,
+    target_date date NOT NULL,
+    owner varchar(320),
+    -- Roots of the task subtrees the milestone tracks, as a JSON array of task ids.
+    task_ids jsonb NOT NULL,
+    creator varchar(320) NOT NULL,
+    -- The most recently computed health, used to alert when it degrades.
+    health varchar(16),
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    update_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX milestones_project_idx ON milestones (project_id);
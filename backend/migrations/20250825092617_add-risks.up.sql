@@ -0,0 +1,22 @@
+-- A register of delivery risks, each linked to the task subtrees it threatens.
+CREATE TABLE risks (
+    id varchar(36) PRIMARY KEY,
+    project_id varchar(36) NOT NULL,
+    title varchar(200) NOT NULL,
+    description text,
+    -- Both from 1 (lowest) to 5 (highest).
+    likelihood smallint NOT NULL,
+    impact smallint NOT NULL,
+    mitigation text,
+    owner varchar(320),
+    -- Roots of the task subtrees the risk threatens, as a JSON array of task ids.
+    task_ids jsonb NOT NULL,
+    closed boolean NOT NULL DEFAULT FALSE,
+    -- Linked tasks whose slip has already been escalated, as a JSON array of task ids.
+    escalated_task_ids jsonb NOT NULL DEFAULT '[]'::jsonb,
+    creator varchar(320) NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    update_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX risks_project_idx ON risks (project_id);
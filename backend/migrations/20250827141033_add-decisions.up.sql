@@ -0,0 +1,19 @@
+-- Decision records attached to tasks, e.g. architectural decisions.
+CREATE TABLE decisions (
+    id varchar(36) PRIMARY KEY,
+    project_id varchar(36) NOT NULL,
+    task_id varchar NOT NULL,
+    title varchar(200) NOT NULL,
+    context text NOT NULL,
+    decision text NOT NULL,
+    -- JSON array of the alternatives considered.
+    alternatives jsonb NOT NULL,
+    -- JSON array of the emails of those who made the decision.
+    deciders jsonb NOT NULL,
+    decision_date date NOT NULL,
+    author varchar(320) NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    update_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX decisions_task_idx ON decisions (project_id, task_id);
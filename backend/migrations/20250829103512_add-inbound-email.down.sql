@@ -0,0 +1,3 @@
+DROP TABLE task_attachments;
+DROP TABLE inbound_emails;
+DROP TABLE inbound_email_addresses;
@@ -0,0 +1,42 @@
+-- Per project addresses that turn received emails into tasks.
+CREATE TABLE inbound_email_addresses (
+    project_id varchar(36) PRIMARY KEY,
+    -- The local part of the address, e.g. <token>@inbound.koso.app.
+    token varchar(32) NOT NULL UNIQUE,
+    -- Whether emails from senders outside the project are accepted.
+    allow_non_members boolean NOT NULL DEFAULT FALSE,
+    creator varchar(320) NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
+
+-- Emails received at inbound addresses, including rejected ones.
+CREATE TABLE inbound_emails (
+    id varchar(36) PRIMARY KEY,
+    project_id varchar(36) NOT NULL,
+    message_id varchar NOT NULL,
+    sender varchar(320) NOT NULL,
+    subject varchar NOT NULL,
+    -- The task created from the email, if it was accepted.
+    task_id varchar,
+    -- Why the email was rejected, e.g. spam or rate_limited.
+    rejection varchar,
+    receive_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    UNIQUE (project_id, message_id)
+);
+
+CREATE INDEX inbound_emails_receive_time_idx ON inbound_emails (project_id, receive_time);
+
+-- Files attached to tasks.
+CREATE TABLE task_attachments (
+    id varchar(36) PRIMARY KEY,
+    project_id varchar(36) NOT NULL,
+    task_id varchar NOT NULL,
+    filename varchar(255) NOT NULL,
+    content_type varchar(255) NOT NULL,
+    size bigint NOT NULL,
+    content bytea NOT NULL,
+    uploader varchar(320) NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX task_attachments_task_idx ON task_attachments (project_id, task_id);
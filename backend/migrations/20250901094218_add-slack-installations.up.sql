@@ -0,0 +1,9 @@
+-- Slack workspaces that installed the Koso app.
+CREATE TABLE slack_installations (
+    team_id varchar PRIMARY KEY,
+    team_name varchar NOT NULL,
+    -- Bot token used to call the Slack API on behalf of the workspace.
+    bot_token varchar NOT NULL,
+    installer varchar(320) NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
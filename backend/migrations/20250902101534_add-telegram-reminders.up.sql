@@ -0,0 +1,11 @@
+-- Assignment notifications snoozed from Telegram, to be resent later.
+CREATE TABLE telegram_reminders (
+    id varchar(36) PRIMARY KEY,
+    chat_id bigint NOT NULL,
+    project_id varchar(36) NOT NULL,
+    task_id varchar NOT NULL,
+    message text NOT NULL,
+    remind_time timestamp with time zone NOT NULL
+);
+
+CREATE INDEX telegram_reminders_remind_time_idx ON telegram_reminders (remind_time);
@@ -0,0 +1,18 @@
+-- One row per message sent to a user with one of their notifiers.
+CREATE TABLE notification_deliveries (
+    id varchar(36) PRIMARY KEY,
+    email varchar(320) NOT NULL,
+    notifier varchar NOT NULL,
+    message jsonb NOT NULL,
+    -- pending, sent, failed (awaiting retry) or dead (out of attempts).
+    status varchar NOT NULL,
+    attempts integer NOT NULL DEFAULT 0,
+    last_error varchar,
+    next_attempt_time timestamp with time zone,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    update_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX notification_deliveries_email_idx ON notification_deliveries (email, create_time DESC);
+CREATE INDEX notification_deliveries_retry_idx ON notification_deliveries (next_attempt_time)
+WHERE status = 'failed';
@@ -0,0 +1,2 @@
+DROP TABLE held_notifications;
+DROP TABLE notification_preferences;
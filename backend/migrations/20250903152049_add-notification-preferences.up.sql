@@ -0,0 +1,19 @@
+-- Per-user quiet hours and batching policy. Users without a row get the defaults.
+CREATE TABLE notification_preferences (
+    email varchar(320) PRIMARY KEY,
+    quiet_hours jsonb,
+    batch_threshold integer NOT NULL,
+    batch_window_minutes integer NOT NULL
+);
+
+-- Messages held during quiet hours or batching, to be sent later as one summary.
+CREATE TABLE held_notifications (
+    id varchar(36) PRIMARY KEY,
+    email varchar(320) NOT NULL,
+    message jsonb NOT NULL,
+    -- quiet or batched.
+    reason varchar NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX held_notifications_email_idx ON held_notifications (email);
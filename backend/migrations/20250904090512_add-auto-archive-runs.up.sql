@@ -0,0 +1,8 @@
+-- Daily record of the tasks archived in each project by auto-archive.
+CREATE TABLE auto_archive_runs (
+    project_id varchar(36) NOT NULL,
+    day date NOT NULL,
+    archived jsonb NOT NULL,
+    run_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (project_id, day)
+);
@@ -0,0 +1,3 @@
+-- Optional prefix of task keys, e.g. KOSO in KOSO-123. Existing projects keep
+-- plain #123 keys until a prefix is set.
+ALTER TABLE projects ADD COLUMN task_key_prefix varchar(10);
@@ -0,0 +1,3 @@
+DROP TABLE deployed_tasks;
+DROP TABLE deployments;
+DROP TABLE deploy_tokens;
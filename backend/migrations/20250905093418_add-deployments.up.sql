@@ -0,0 +1,34 @@
+-- Per project tokens CI systems use to report deployments.
+CREATE TABLE deploy_tokens (
+    project_id varchar(36) PRIMARY KEY,
+    -- Hex encoded SHA-256 of the token. The token itself is only shown when created.
+    token_hash varchar(64) NOT NULL UNIQUE,
+    creator varchar(320) NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
+
+-- Deployments reported by CI systems.
+CREATE TABLE deployments (
+    id varchar(36) PRIMARY KEY,
+    project_id varchar(36) NOT NULL,
+    -- e.g. prod or staging.
+    env varchar(64) NOT NULL,
+    version varchar(128) NOT NULL,
+    deploy_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX deployments_time_idx ON deployments (project_id, deploy_time);
+
+-- The tasks shipped in each deployment, as they were when deployed.
+CREATE TABLE deployed_tasks (
+    deployment_id varchar(36) NOT NULL,
+    project_id varchar(36) NOT NULL,
+    task_id varchar NOT NULL,
+    num varchar NOT NULL,
+    name varchar NOT NULL,
+    labels jsonb NOT NULL,
+    assignee varchar(320),
+    PRIMARY KEY (deployment_id, task_id)
+);
+
+CREATE INDEX deployed_tasks_task_idx ON deployed_tasks (project_id, task_id);
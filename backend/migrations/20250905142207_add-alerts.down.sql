@@ -0,0 +1,2 @@
+DROP TABLE alert_fingerprints;
+DROP TABLE alert_integrations;
@@ -0,0 +1,25 @@
+-- Per project integrations that turn alerts, e.g. from Sentry, into tasks.
+CREATE TABLE alert_integrations (
+    project_id varchar(36) PRIMARY KEY,
+    -- Alerts are posted to /api/alerts/<token>.
+    token varchar(32) NOT NULL UNIQUE,
+    -- The task alert tasks are created under, e.g. a Triage task. The root when null.
+    parent_task_id varchar,
+    creator varchar(320) NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
+
+-- Received alerts, deduplicated by fingerprint.
+CREATE TABLE alert_fingerprints (
+    project_id varchar(36) NOT NULL,
+    fingerprint varchar(255) NOT NULL,
+    title varchar NOT NULL,
+    -- The task created for the alert. Null if creation was rate limited.
+    task_id varchar,
+    occurrences bigint NOT NULL DEFAULT 1,
+    first_seen timestamp with time zone NOT NULL DEFAULT NOW(),
+    last_seen timestamp with time zone NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (project_id, fingerprint)
+);
+
+CREATE INDEX alert_fingerprints_first_seen_idx ON alert_fingerprints (project_id, first_seen);
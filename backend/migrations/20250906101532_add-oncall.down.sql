@@ -0,0 +1 @@
+DROP TABLE oncall_integrations;
@@ -0,0 +1,17 @@
+-- Per project integrations that assign incident tasks to whoever is on-call.
+CREATE TABLE oncall_integrations (
+    project_id varchar(36) PRIMARY KEY,
+    -- A PagerDuty REST API key.
+    api_key varchar NOT NULL,
+    schedule_id varchar NOT NULL,
+    -- Tasks with this label are assigned to the on-call.
+    label varchar NOT NULL DEFAULT 'incident',
+    -- Takes precedence over the schedule until override_until.
+    override_email varchar(320),
+    override_until timestamp with time zone,
+    -- The scheduled on-call, cached until cached_until.
+    cached_email varchar(320),
+    cached_until timestamp with time zone,
+    creator varchar(320) NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,3 @@
+DROP INDEX task_projections_seq_idx;
+ALTER TABLE task_projections DROP COLUMN seq;
+DROP TABLE zapier_keys;
@@ -0,0 +1,12 @@
+-- Per user API keys used by automation platforms like Zapier and Make.
+CREATE TABLE zapier_keys (
+    email varchar(320) PRIMARY KEY,
+    -- Hex encoded SHA-256 of the key. The key itself is never stored.
+    key_hash varchar(64) NOT NULL UNIQUE,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    last_used_time timestamp with time zone
+);
+
+-- Increases as tasks are first projected, letting automations poll for new tasks.
+ALTER TABLE task_projections ADD COLUMN seq bigserial;
+CREATE INDEX task_projections_seq_idx ON task_projections (project_id, seq);
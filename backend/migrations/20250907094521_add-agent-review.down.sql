@@ -0,0 +1,5 @@
+DELETE FROM change_proposals WHERE agent IS NOT NULL AND status = 'pending';
+DROP INDEX change_proposals_pending_idx;
+CREATE UNIQUE INDEX change_proposals_pending_idx ON change_proposals (project_id, author) WHERE status = 'pending';
+ALTER TABLE change_proposals DROP COLUMN agent;
+ALTER TABLE projects DROP COLUMN require_agent_review;
@@ -0,0 +1,9 @@
+-- Whether changes made by agents, e.g. over MCP, must be reviewed before being applied.
+ALTER TABLE projects ADD COLUMN require_agent_review boolean NOT NULL DEFAULT FALSE;
+
+-- The automation that made the changes on behalf of the author, if any.
+ALTER TABLE change_proposals ADD COLUMN agent varchar(64);
+
+-- At most one pending proposal per author, agent and project.
+DROP INDEX change_proposals_pending_idx;
+CREATE UNIQUE INDEX change_proposals_pending_idx ON change_proposals (project_id, author, COALESCE(agent, '')) WHERE status = 'pending';
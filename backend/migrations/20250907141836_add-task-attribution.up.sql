@@ -0,0 +1,11 @@
+-- Who created and last modified each task, recorded from transaction origins.
+CREATE TABLE task_attribution (
+    project_id varchar(36) NOT NULL,
+    task_id varchar(64) NOT NULL,
+    -- Null for tasks created before attribution was recorded.
+    created_by varchar(320),
+    created_at timestamp with time zone,
+    last_modified_by varchar(320) NOT NULL,
+    last_modified_at timestamp with time zone NOT NULL,
+    PRIMARY KEY (project_id, task_id)
+);
@@ -0,0 +1,29 @@
+-- Imports running, or run, in the background.
+CREATE TABLE import_jobs (
+    job_id varchar(64) NOT NULL PRIMARY KEY,
+    project_id varchar(36) NOT NULL,
+    source varchar(32) NOT NULL,
+    created_by varchar(320) NOT NULL,
+    dry_run boolean NOT NULL,
+    status varchar(16) NOT NULL,
+    rows_read integer NOT NULL DEFAULT 0,
+    rows_failed integer NOT NULL DEFAULT 0,
+    -- The first few rows that couldn't be imported.
+    errors jsonb NOT NULL DEFAULT '[]',
+    result jsonb,
+    failure text,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    finish_time timestamp with time zone
+);
+CREATE INDEX import_jobs_project_id_create_time_idx ON import_jobs (project_id, create_time);
+
+-- The tasks created by imports, by their ids in the source, so re-runs update them.
+CREATE TABLE import_sources (
+    project_id varchar(36) NOT NULL,
+    source varchar(32) NOT NULL,
+    -- Identifies what was imported within the source, e.g. a Trello board.
+    source_key text NOT NULL,
+    source_id text NOT NULL,
+    task_id varchar(64) NOT NULL,
+    PRIMARY KEY (project_id, source, source_key, source_id)
+);
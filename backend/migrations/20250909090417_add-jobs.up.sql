@@ -0,0 +1,20 @@
+-- Durable background work, claimed and run by the job queue.
+CREATE TABLE jobs (
+    job_id varchar(64) NOT NULL PRIMARY KEY,
+    kind varchar(64) NOT NULL,
+    -- Deduplicates jobs waiting to run, e.g. compaction of a project.
+    key text,
+    payload jsonb NOT NULL,
+    -- queued, running, succeeded or dead.
+    status varchar(16) NOT NULL DEFAULT 'queued',
+    attempts integer NOT NULL DEFAULT 0,
+    run_at timestamp with time zone NOT NULL DEFAULT NOW(),
+    -- When the running attempt started, to requeue attempts abandoned by a crashed server.
+    start_time timestamp with time zone,
+    last_error text,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    finish_time timestamp with time zone
+);
+CREATE INDEX jobs_kind_run_at_idx ON jobs (kind, run_at) WHERE status = 'queued';
+CREATE INDEX jobs_status_idx ON jobs (status, finish_time);
+CREATE UNIQUE INDEX jobs_kind_key_idx ON jobs (kind, key) WHERE status = 'queued' AND last_error IS NULL;
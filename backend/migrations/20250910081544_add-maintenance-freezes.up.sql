@@ -0,0 +1,11 @@
+-- Projects, or the whole instance when project_id is null, frozen read-only by operators.
+CREATE TABLE maintenance_freezes (
+    project_id varchar(36),
+    -- Shown to users whose writes are rejected.
+    reason text NOT NULL,
+    -- When the freeze is expected to lift, used to advise clients when to retry.
+    end_time timestamp with time zone,
+    created_by varchar NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
+CREATE UNIQUE INDEX maintenance_freezes_project_id_idx ON maintenance_freezes ((COALESCE(project_id, '')));
@@ -0,0 +1,21 @@
+-- Per-project overrides of the deployment's retention periods, in days. Null
+-- columns fall back to the deployment's default.
+CREATE TABLE retention_policies (
+    project_id varchar(36) PRIMARY KEY,
+    update_days integer,
+    activity_days integer,
+    history_days integer,
+    notification_days integer,
+    update_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
+
+-- What each pruning run deleted, reported to admins as reclaimed space.
+CREATE TABLE retention_runs (
+    category varchar(16) NOT NULL,
+    table_name varchar NOT NULL,
+    rows_pruned bigint NOT NULL,
+    bytes_reclaimed bigint NOT NULL,
+    run_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX retention_runs_time_idx ON retention_runs (run_time);
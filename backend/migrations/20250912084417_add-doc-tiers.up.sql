@@ -0,0 +1,12 @@
+-- Which storage tier each project's doc was last moved to. Cold docs are the
+-- object's snapshot merged with any rows still in yupdates. Rows stay once a
+-- doc is rehydrated, so it isn't moved straight back.
+CREATE TABLE doc_tiers (
+    project_id varchar(36) PRIMARY KEY,
+    -- hot or cold.
+    tier varchar(8) NOT NULL,
+    object_key varchar,
+    -- The cold snapshot's size in bytes.
+    size bigint,
+    move_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1 @@
+DROP TABLE doc_migration_snapshots;
@@ -0,0 +1,14 @@
+-- Project docs as they were before doc migrations changed them, kept for rollback.
+CREATE TABLE doc_migration_snapshots (
+    id varchar(36) PRIMARY KEY,
+    project_id varchar(36) NOT NULL,
+    from_version integer NOT NULL,
+    to_version integer NOT NULL,
+    update_v2 bytea NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    -- Set once rolled back, which stops the project being migrated again.
+    rollback_time timestamp with time zone,
+    rolled_back_by varchar(320)
+);
+
+CREATE INDEX doc_migration_snapshots_project_idx ON doc_migration_snapshots (project_id, create_time);
@@ -0,0 +1,2 @@
+DROP TABLE impersonation_audit_log;
+DROP TABLE impersonation_sessions;
@@ -0,0 +1,32 @@
+-- Sessions in which an admin views Koso as another user, with the user's consent.
+CREATE TABLE impersonation_sessions (
+    id varchar(36) PRIMARY KEY,
+    admin_email varchar(320) NOT NULL,
+    user_email varchar(320) NOT NULL,
+    reason text NOT NULL,
+    -- Hash of the token the admin presents to act as the user.
+    token_hash varchar(64) NOT NULL UNIQUE,
+    -- How long the session lasts once the user consents.
+    duration_secs integer NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    consent_time timestamp with time zone,
+    expire_time timestamp with time zone,
+    end_time timestamp with time zone,
+    ended_by varchar(320)
+);
+
+CREATE INDEX impersonation_sessions_user_idx ON impersonation_sessions (user_email, create_time);
+CREATE INDEX impersonation_sessions_admin_idx ON impersonation_sessions (admin_email, create_time);
+
+-- Every request made while impersonating, including rejected ones.
+CREATE TABLE impersonation_audit_log (
+    id bigserial PRIMARY KEY,
+    session_id varchar(36) NOT NULL,
+    method varchar(16) NOT NULL,
+    path text NOT NULL,
+    status integer NOT NULL,
+    request_id varchar(64),
+    create_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX impersonation_audit_log_session_idx ON impersonation_audit_log (session_id, create_time);
@@ -0,0 +1,9 @@
+-- Windows in which users are away, e.g. on vacation, and who covers for them.
+CREATE TABLE away_statuses (
+    email varchar(320) PRIMARY KEY,
+    start_time timestamp with time zone NOT NULL,
+    end_time timestamp with time zone NOT NULL,
+    -- Notified of assignments to the user while they're away.
+    delegate varchar(320),
+    message text
+);
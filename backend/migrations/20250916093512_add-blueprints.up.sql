@@ -0,0 +1,12 @@
+-- Starter structures, tasks and project configuration, shared within an
+-- organization. Organizations are identified by their subscription's owner.
+CREATE TABLE blueprints (
+    id varchar(36) PRIMARY KEY,
+    org varchar(320) NOT NULL,
+    name text NOT NULL,
+    description text NOT NULL,
+    content jsonb NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX blueprints_org_idx ON blueprints (org);
@@ -0,0 +1,4 @@
+DROP TABLE blueprint_applications;
+DROP TABLE blueprint_versions;
+ALTER TABLE blueprints DROP COLUMN update_time;
+ALTER TABLE blueprints DROP COLUMN version;
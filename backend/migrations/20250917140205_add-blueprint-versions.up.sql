@@ -0,0 +1,25 @@
+ALTER TABLE blueprints ADD COLUMN version integer NOT NULL DEFAULT 1;
+ALTER TABLE blueprints ADD COLUMN update_time timestamp with time zone NOT NULL DEFAULT NOW();
+
+-- The content of every version of each blueprint.
+CREATE TABLE blueprint_versions (
+    blueprint_id varchar(36) NOT NULL,
+    version integer NOT NULL,
+    content jsonb NOT NULL,
+    author varchar(320) NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (blueprint_id, version)
+);
+
+-- The blueprint versions applied to each project, with the ids of the tasks
+-- created from them, so later versions can be applied as diffs.
+CREATE TABLE blueprint_applications (
+    project_id varchar(36) NOT NULL,
+    blueprint_id varchar(36) NOT NULL,
+    version integer NOT NULL,
+    content jsonb NOT NULL,
+    -- Maps blueprint task keys to task ids.
+    task_ids jsonb NOT NULL,
+    apply_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (project_id, blueprint_id)
+);
@@ -0,0 +1,17 @@
+-- Endpoints notified of a project's task changes. Changes are only sent if
+-- their origin matches the filters; empty filters match everything.
+CREATE TABLE project_webhooks (
+    id varchar(36) PRIMARY KEY,
+    project_id varchar(36) NOT NULL,
+    url varchar NOT NULL,
+    -- Signs each delivery's body, in the X-Koso-Signature header.
+    secret varchar NOT NULL,
+    -- Actor kinds, e.g. "user" or "github", whose changes are sent.
+    actors jsonb NOT NULL DEFAULT '[]'::jsonb,
+    -- Plugin ids, e.g. "github", whose changes are sent.
+    plugins jsonb NOT NULL DEFAULT '[]'::jsonb,
+    creator varchar(320) NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX project_webhooks_project_id_idx ON project_webhooks (project_id);
@@ -0,0 +1 @@
+DROP TABLE demo_projects;
@@ -0,0 +1,10 @@
+-- Projects anyone can edit anonymously, e.g. a playground embedded in the
+-- marketing site, reset to one of their snapshots on a schedule.
+CREATE TABLE demo_projects (
+    project_id varchar(36) PRIMARY KEY,
+    snapshot_id varchar(36) NOT NULL,
+    reset_interval_mins integer NOT NULL,
+    last_reset_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    creator varchar(320) NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
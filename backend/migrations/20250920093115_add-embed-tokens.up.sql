@@ -0,0 +1,13 @@
+-- Tokens addressing read-only embeds of a project's tasks, e.g. in wikis.
+CREATE TABLE embed_tokens (
+    id varchar(36) PRIMARY KEY,
+    project_id varchar(36) NOT NULL,
+    -- Hex encoded SHA-256 of the token. The token itself is never stored.
+    token_hash varchar(64) NOT NULL UNIQUE,
+    -- If set, only this task and its subtasks can be embedded.
+    root_task_id varchar(64),
+    creator varchar(320) NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX embed_tokens_project_id_idx ON embed_tokens (project_id);
@@ -0,0 +1,13 @@
+-- Public status pages publishing a project's milestones, at most one per project.
+CREATE TABLE status_pages (
+    project_id varchar(36) PRIMARY KEY,
+    -- Addresses the page at /status/{slug}.
+    slug varchar(64) NOT NULL UNIQUE,
+    title varchar(200) NOT NULL,
+    -- The published milestones, as a JSON array of milestone ids.
+    milestone_ids jsonb NOT NULL,
+    theme jsonb NOT NULL,
+    published boolean NOT NULL DEFAULT FALSE,
+    editor varchar(320) NOT NULL,
+    update_time timestamp with time zone NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,19 @@
+-- When each user last marked all of a project read, or first opened it.
+-- Tasks modified before then by others aren't unread.
+CREATE TABLE project_reads (
+    email varchar(320) NOT NULL,
+    project_id varchar(36) NOT NULL,
+    read_time timestamp with time zone NOT NULL,
+    PRIMARY KEY (email, project_id)
+);
+
+-- Per-user read and flagged state of tasks.
+CREATE TABLE task_reads (
+    email varchar(320) NOT NULL,
+    project_id varchar(36) NOT NULL,
+    task_id varchar(64) NOT NULL,
+    -- Null for tasks flagged but never read.
+    read_time timestamp with time zone,
+    flagged boolean NOT NULL DEFAULT FALSE,
+    PRIMARY KEY (email, project_id, task_id)
+);
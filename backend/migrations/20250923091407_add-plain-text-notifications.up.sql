@@ -0,0 +1,2 @@
+-- Users who prefer notifications as plain text rather than formatted HTML.
+ALTER TABLE notification_preferences ADD COLUMN plain_text boolean NOT NULL DEFAULT FALSE;
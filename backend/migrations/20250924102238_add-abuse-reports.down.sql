@@ -0,0 +1,2 @@
+ALTER TABLE task_comments DROP COLUMN quarantined;
+DROP TABLE abuse_reports;
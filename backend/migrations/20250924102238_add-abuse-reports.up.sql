@@ -0,0 +1,26 @@
+-- Reports of spam or abusive content, reviewed by the deployment's admins.
+CREATE TABLE abuse_reports (
+    id varchar(36) PRIMARY KEY,
+    project_id varchar(36) NOT NULL,
+    reporter varchar(320) NOT NULL,
+    -- The author of the reported content.
+    reported varchar(320) NOT NULL,
+    -- The reported task or comment. Exactly one is set.
+    task_id varchar(64),
+    comment_id varchar(36),
+    -- spam, abuse or other.
+    reason varchar(16) NOT NULL,
+    details text NOT NULL,
+    -- open, dismissed or upheld.
+    status varchar(16) NOT NULL,
+    reviewer varchar(320),
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    review_time timestamp with time zone
+);
+
+-- A reporter has at most one open report about each user in a project.
+CREATE UNIQUE INDEX abuse_reports_open_idx ON abuse_reports (project_id, reported, reporter)
+WHERE status = 'open';
+
+-- Comments from quarantined users are hidden from others until reviewed.
+ALTER TABLE task_comments ADD COLUMN quarantined boolean NOT NULL DEFAULT FALSE;
@@ -0,0 +1,12 @@
+-- Metadata unfurled from the pages tasks link to, cached across projects.
+CREATE TABLE link_previews (
+    url varchar PRIMARY KEY,
+    -- False when the page couldn't be unfurled, so it's retried later.
+    ok boolean NOT NULL,
+    title varchar,
+    description varchar,
+    site_name varchar,
+    image_url varchar,
+    favicon_url varchar,
+    fetch_time timestamp with time zone NOT NULL
+);
@@ -0,0 +1,5 @@
+DROP INDEX task_attachments_pending_scan_idx;
+
+ALTER TABLE task_attachments
+DROP COLUMN scan_status,
+DROP COLUMN scan_detail;
@@ -0,0 +1,8 @@
+-- One of unscanned, pending, clean or quarantined. Attachments uploaded
+-- before scanning was enabled stay unscanned.
+ALTER TABLE task_attachments
+ADD COLUMN scan_status varchar(16) NOT NULL DEFAULT 'unscanned',
+ADD COLUMN scan_detail varchar;
+
+CREATE INDEX task_attachments_pending_scan_idx ON task_attachments (create_time)
+WHERE scan_status = 'pending';
@@ -0,0 +1 @@
+DROP TABLE plugin_sync_status;
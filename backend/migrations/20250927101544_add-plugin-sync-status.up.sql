@@ -0,0 +1,14 @@
+-- How each plugin config's sync is going, shown to users so stale
+-- integrations can be triaged.
+CREATE TABLE plugin_sync_status (
+    project_id varchar(36) NOT NULL,
+    plugin_id varchar(64) NOT NULL,
+    external_id varchar(64) NOT NULL,
+    last_sync_time timestamp with time zone,
+    -- Webhook events received but not yet applied.
+    pending_events integer NOT NULL DEFAULT 0,
+    -- The most recent failures, newest first, as {"time", "message"} objects.
+    recent_errors jsonb NOT NULL DEFAULT '[]',
+    next_poll_time timestamp with time zone,
+    PRIMARY KEY (project_id, plugin_id, external_id)
+);
@@ -0,0 +1,12 @@
+ALTER TABLE slack_installations
+ADD COLUMN bot_token varchar NOT NULL DEFAULT '';
+
+UPDATE slack_installations s
+SET bot_token = c.access_token
+FROM plugin_credentials c
+WHERE c.plugin_id = 'slack' AND c.account = s.team_id;
+
+ALTER TABLE slack_installations
+ALTER COLUMN bot_token DROP DEFAULT;
+
+DROP TABLE plugin_credentials;
@@ -0,0 +1,26 @@
+-- OAuth credentials plugins act with, refreshed and monitored centrally.
+CREATE TABLE plugin_credentials (
+    plugin_id varchar(64) NOT NULL,
+    -- Whose credential it is, e.g. a user's email or a Slack team id.
+    account varchar(320) NOT NULL,
+    -- The user asked to reconnect when the credential fails.
+    owner varchar(320) NOT NULL,
+    access_token varchar NOT NULL,
+    refresh_token varchar,
+    expires_at timestamp with time zone,
+    refresh_expires_at timestamp with time zone,
+    last_error varchar,
+    -- When the credential started failing, cleared by refreshing or reconnecting.
+    failure_time timestamp with time zone,
+    -- When the owner was asked to reconnect.
+    notify_time timestamp with time zone,
+    update_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (plugin_id, account)
+);
+
+INSERT INTO plugin_credentials (plugin_id, account, owner, access_token)
+SELECT 'slack', team_id, installer, bot_token
+FROM slack_installations;
+
+ALTER TABLE slack_installations
+DROP COLUMN bot_token;
@@ -0,0 +1,18 @@
+-- Koso users' identities in external systems, e.g. GitHub users or Slack
+-- members, consulted by plugins when translating assignees and reporters.
+CREATE TABLE external_identities (
+    -- e.g. github or slack.
+    provider varchar(32) NOT NULL,
+    -- The provider's id for the identity, e.g. a GitHub user id.
+    external_id varchar(256) NOT NULL,
+    email varchar(320) NOT NULL,
+    -- e.g. a GitHub login, shown to users confirming the identity.
+    display_name varchar(256),
+    -- suggested from a matching email, or confirmed or rejected by the user.
+    status varchar(16) NOT NULL,
+    create_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    update_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (provider, external_id)
+);
+
+CREATE INDEX external_identities_email_idx ON external_identities (email);
@@ -0,0 +1,2 @@
+DROP FUNCTION is_premium;
+DROP TABLE license;
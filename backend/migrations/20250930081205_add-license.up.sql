@@ -0,0 +1,21 @@
+-- The self-hosted deployment's verified license, if any. At most one row.
+CREATE TABLE license (
+    id boolean PRIMARY KEY DEFAULT TRUE CHECK (id),
+    licensee varchar NOT NULL,
+    seats integer NOT NULL,
+    features varchar[] NOT NULL,
+    expire_time timestamp with time zone NOT NULL,
+    -- Premium features keep working until this, past expiry.
+    grace_end_time timestamp with time zone NOT NULL,
+    verify_time timestamp with time zone NOT NULL DEFAULT NOW(),
+    -- The state admins were last notified of, reset by renewing.
+    notify_state varchar(16)
+);
+
+-- Whether a user is entitled to premium features, through a Stripe
+-- subscription or the deployment's license.
+CREATE FUNCTION is_premium(subscription_end_time timestamp with time zone) RETURNS boolean
+LANGUAGE sql STABLE AS $$
+    SELECT (subscription_end_time IS NOT NULL AND subscription_end_time > now())
+        OR EXISTS (SELECT 1 FROM license WHERE grace_end_time > now())
+$$;
@@ -0,0 +1,10 @@
+-- Preview images and favicons proxied for link previews, cached so requests
+-- for an image don't each fetch it.
+CREATE TABLE link_preview_images (
+    url varchar PRIMARY KEY,
+    -- False when the image couldn't be fetched, so it's retried later.
+    ok boolean NOT NULL,
+    content_type varchar,
+    body bytea,
+    fetch_time timestamp with time zone NOT NULL
+);
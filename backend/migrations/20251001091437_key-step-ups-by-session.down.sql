@@ -0,0 +1,7 @@
+DROP TABLE step_up_nonces;
+DROP TABLE step_ups;
+CREATE TABLE step_ups (
+    email varchar(320) NOT NULL,
+    expire_time timestamp with time zone NOT NULL,
+    PRIMARY KEY (email)
+);
@@ -0,0 +1,17 @@
+DROP TABLE step_ups;
+-- Sessions that recently re-authenticated and may perform sensitive operations
+-- until expire_time. Sessions present the step-up token whose hash is stored.
+CREATE TABLE step_ups (
+    token_hash varchar PRIMARY KEY,
+    email varchar(320) NOT NULL,
+    expire_time timestamp with time zone NOT NULL
+);
+CREATE INDEX step_ups_email_idx ON step_ups (email);
+
+-- Nonces issued for re-authenticating to step up. Each may be used once.
+CREATE TABLE step_up_nonces (
+    nonce_hash varchar PRIMARY KEY,
+    email varchar(320) NOT NULL,
+    expire_time timestamp with time zone NOT NULL
+);
+CREATE INDEX step_up_nonces_email_idx ON step_up_nonces (email);
@@ -0,0 +1,4 @@
+DROP TABLE org_regions;
+DROP INDEX projects_org_idx;
+ALTER TABLE projects DROP COLUMN org;
+ALTER TABLE projects DROP COLUMN region;
@@ -0,0 +1,13 @@
+-- The data region a project's doc is stored in. NULL for projects stored in
+-- the deployment's own database.
+ALTER TABLE projects ADD COLUMN region varchar(16);
+-- The organization, identified by its subscription's owner, that created the
+-- project, if any. Projects move with their organization between regions.
+ALTER TABLE projects ADD COLUMN org varchar(320);
+CREATE INDEX projects_org_idx ON projects (org);
+
+-- The data region each organization's projects are pinned to.
+CREATE TABLE org_regions (
+    org varchar(320) PRIMARY KEY,
+    region varchar(16) NOT NULL
+);
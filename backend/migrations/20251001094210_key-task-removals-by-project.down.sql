@@ -0,0 +1,2 @@
+ALTER TABLE task_removals DROP CONSTRAINT task_removals_pkey;
+ALTER TABLE task_removals ADD PRIMARY KEY (id);
@@ -0,0 +1,3 @@
+-- Transaction ids are only unique within a project.
+ALTER TABLE task_removals DROP CONSTRAINT task_removals_pkey;
+ALTER TABLE task_removals ADD PRIMARY KEY (project_id, id);
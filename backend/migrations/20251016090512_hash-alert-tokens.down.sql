@@ -0,0 +1,4 @@
+-- Hashed tokens can't be recovered, so integrations must be recreated.
+DELETE FROM alert_integrations;
+ALTER TABLE alert_integrations ALTER COLUMN token_hash TYPE varchar(32);
+ALTER TABLE alert_integrations RENAME COLUMN token_hash TO token;
@@ -0,0 +1,5 @@
+-- Alert tokens are stored hashed, like other inbound tokens. Existing tokens
+-- are hashed in place so their integrations keep working.
+ALTER TABLE alert_integrations RENAME COLUMN token TO token_hash;
+ALTER TABLE alert_integrations ALTER COLUMN token_hash TYPE varchar(64);
+UPDATE alert_integrations SET token_hash = encode(sha256(convert_to(token_hash, 'UTF8')), 'hex');
@@ -0,0 +1,9 @@
+-- Zones with daylight saving time have no single offset, so only Etc/GMT zones are restored.
+UPDATE users
+SET timezone = CASE
+        WHEN timezone = 'UTC' THEN '+00:00'
+        WHEN timezone ~ '^Etc/GMT[+-][0-9]{1,2}$' THEN
+            (CASE WHEN substr(timezone, 8, 1) = '+' THEN '-' ELSE '+' END)
+            || lpad(substr(timezone, 9), 2, '0') || ':00'
+    END
+WHERE timezone IS NOT NULL;
@@ -0,0 +1,10 @@
+-- Timezones are IANA time zones rather than UTC offsets, which ignore daylight
+-- saving time. Whole hour offsets map to the equivalent Etc/GMT zone, whose
+-- sign is inverted. Other offsets are cleared and fall back to UTC.
+UPDATE users
+SET timezone = CASE
+        WHEN timezone ~ '^[+-]00:00$' THEN 'UTC'
+        WHEN timezone ~ '^-(0[0-9]|1[0-2]):00$' THEN 'Etc/GMT+' || substr(timezone, 2, 2)::int
+        WHEN timezone ~ '^\+(0[0-9]|1[0-4]):00$' THEN 'Etc/GMT-' || substr(timezone, 2, 2)::int
+    END
+WHERE timezone ~ '^[+-][0-9]{2}:[0-9]{2}$';
@@ -110,6 +110,14 @@ mkdir -p /root/.secrets/stripe
 touch /root/.secrets/stripe/secret_key
 # The Stripe webhook secret: https://dashboard.stripe.com/webhooks.
 touch /root/.secrets/stripe/webhook_secret
+mkdir -p /root/.secrets/postmark
+# The password Postmark uses to authenticate inbound email webhooks.
+touch /root/.secrets/postmark/inbound_webhook_password
+mkdir -p /root/.secrets/slack
+# The Slack app's credentials: https://api.slack.com/apps.
+touch /root/.secrets/slack/client_id
+touch /root/.secrets/slack/client_secret
+touch /root/.secrets/slack/signing_secret
 # MANUAL - place secrets in those files
 
 # Finally, run the Deploy action to start the backend.
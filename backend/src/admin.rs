@@ -0,0 +1,94 @@
+//! Admin commands, run in place of the server:
+//!   - `koso admin dump-doc <project_id> [<file>]` writes the project's doc and
+//!     comments as human-readable JSON to the file, or stdout.
+//!   - `koso admin load-doc <project_id> <file>` loads a dump into a project
+//!     without a doc, e.g. to seed a fixture.
+//!   - `koso admin verify-license <file>` verifies a license file offline and
+//!     prints its claims, e.g. before installing a renewal.
+//!   - `koso admin migrate-org <org> <region>` pins an organization, identified
+//!     by its subscription's owner, to a data region and moves its projects'
+//!     docs there. Each project is frozen while its doc moves.
+//!   - `koso admin generate-doc <project_id> [<depth> [<branching> [<managed_ratio> [<seed>]]]]`
+//!     generates a project into one without a doc. Requires the `fixtures` feature.
+
+use crate::{
+    api::{doc_dump, regions},
+    license, server,
+    settings::settings,
+};
+use anyhow::{Context as _, Result, bail};
+
+const USAGE: &str = "Usage:
+  koso admin dump-doc <project_id> [<file>]
+  koso admin load-doc <project_id> <file>
+  koso admin verify-license <file>
+  koso admin migrate-org <org> <region>
+  koso admin generate-doc <project_id> [<depth> [<branching> [<managed_ratio> [<seed>]]]]";
+
+pub(crate) async fn run(args: &[String]) -> Result<()> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["dump-doc", project_id, rest @ ..] if rest.len() <= 1 => {
+            let pool = server::connect("primary", &settings().database_url).await?;
+            let dump = doc_dump::dump(pool, &project_id.to_string()).await?;
+            let json = serde_json::to_string_pretty(&dump)?;
+            match rest.first() {
+                Some(file) => std::fs::write(file, json + "\n")
+                    .with_context(|| format!("Failed to write {file}"))?,
+                None => println!("{json}"),
+            }
+        }
+        ["load-doc", project_id, file] => {
+            let json =
+                std::fs::read_to_string(file).with_context(|| format!("Failed to read {file}"))?;
+            let dump = serde_json::from_str(&json).context("Invalid dump")?;
+            let pool = server::connect("primary", &settings().database_url).await?;
+            doc_dump::load(pool, &project_id.to_string(), &dump).await?;
+            tracing::info!("Loaded {file} into project {project_id}");
+        }
+        ["verify-license", file] => {
+            let claims = license::verify_file(file)?;
+            println!(
+                "Licensed to {} for {} seats until {}, with features: {:?}",
+                claims.sub,
+                claims.seats,
+                chrono::DateTime::from_timestamp(claims.exp, 0)
+                    .context("Invalid license expiry")?,
+                claims.features
+            );
+        }
+        ["migrate-org", org, region] => {
+            let pool = server::connect("primary", &settings().database_url).await?;
+            server::connect_regions().await?;
+            let moved = regions::migrate_org(pool, org, region, regions::SETTLE_TIME).await?;
+            println!("Pinned {org} to region {region} and moved {moved} projects");
+        }
+        #[cfg(feature = "fixtures")]
+        ["generate-doc", project_id, rest @ ..] if rest.len() <= 4 => {
+            let doc = crate::api::fixtures::generate(&parse_shape(rest)?)?;
+            let pool = server::connect("primary", &settings().database_url).await?;
+            doc_dump::store(pool, &project_id.to_string(), &doc, &[]).await?;
+            tracing::info!("Generated project {project_id}");
+        }
+        _ => bail!("{USAGE}"),
+    }
+    Ok(())
+}
+
+#[cfg(feature = "fixtures")]
+fn parse_shape(args: &[&str]) -> Result<crate::api::fixtures::Shape> {
+    let mut shape = crate::api::fixtures::Shape::default();
+    if let Some(depth) = args.first() {
+        shape.depth = depth.parse().context("Invalid depth")?;
+    }
+    if let Some(branching) = args.get(1) {
+        shape.branching = branching.parse().context("Invalid branching")?;
+    }
+    if let Some(ratio) = args.get(2) {
+        shape.managed_ratio = ratio.parse().context("Invalid managed_ratio")?;
+    }
+    if let Some(seed) = args.get(3) {
+        shape.seed = seed.parse().context("Invalid seed")?;
+    }
+    Ok(shape)
+}
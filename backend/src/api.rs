@@ -1,29 +1,97 @@
+use crate::{
+    notifiers,
+    postgres::{self, PgPool},
+    settings::settings,
+};
 use anyhow::{Context, Error, Result, anyhow};
 use axum::{
     Router,
-    http::{HeaderName, HeaderValue, StatusCode},
+    http::{HeaderName, HeaderValue, StatusCode, header::CONTENT_TYPE},
     middleware,
     response::{IntoResponse, Response},
 };
 use axum_extra::headers;
+use errors::{CodedError, ErrorCode};
 use google::User;
-use model::{ProjectId, ProjectPermission};
-use sqlx::postgres::PgPool;
+use model::ProjectId;
+use sha2::{Digest as _, Sha256};
 use std::backtrace::{Backtrace, BacktraceStatus};
 
-use crate::notifiers;
-
+pub(crate) mod alerts;
+pub(crate) mod analytics;
+pub(crate) mod attachments;
 pub(crate) mod auth;
+pub(crate) mod auto_archive;
+pub(crate) mod auto_assign;
+pub(crate) mod away;
+pub(crate) mod badges;
 pub(crate) mod billing;
+pub(crate) mod blueprints;
+pub(crate) mod board;
+pub(crate) mod branches;
+pub(crate) mod bulk;
+pub(crate) mod caldav;
 pub(crate) mod collab;
+pub(crate) mod comments;
+pub(crate) mod context;
+pub(crate) mod credentials;
+pub(crate) mod dashboard;
+pub(crate) mod decisions;
+pub(crate) mod demo;
+pub(crate) mod deployments;
 pub(crate) mod dev;
+pub(crate) mod doc_dump;
+pub(crate) mod doc_migrations;
+pub(crate) mod embed;
+pub(crate) mod errors;
+#[cfg(any(test, feature = "fixtures"))]
+pub(crate) mod fixtures;
+pub(crate) mod forecast;
+pub(crate) mod goals;
 pub(crate) mod google;
+pub(crate) mod groups;
+pub(crate) mod identities;
+pub(crate) mod impersonation;
+pub(crate) mod imports;
+pub(crate) mod inbound_email;
+pub(crate) mod inbox;
+pub(crate) mod integrations;
+pub(crate) mod jobs;
+pub(crate) mod license;
+pub(crate) mod maintenance;
+pub(crate) mod management;
+pub(crate) mod mcp;
+pub(crate) mod me;
+pub(crate) mod milestones;
 pub(crate) mod model;
+pub(crate) mod moderation;
+pub(crate) mod offboarding;
+pub(crate) mod oncall;
 pub(crate) mod profile;
+pub(crate) mod project_config;
 pub(crate) mod projects;
+pub(crate) mod proposals;
+pub(crate) mod regions;
+pub(crate) mod release_notes;
+pub(crate) mod reports;
+pub(crate) mod retention;
+pub(crate) mod reverts;
+pub(crate) mod risks;
+pub(crate) mod search;
+pub(crate) mod security;
+pub(crate) mod shadow;
+pub(crate) mod snapshots;
+pub(crate) mod status_pages;
+pub(crate) mod step_up;
+pub(crate) mod transactions;
+pub(crate) mod unfurl;
+pub(crate) mod unread;
+pub(crate) mod usage;
 pub(crate) mod users;
+pub(crate) mod webhooks;
 pub(crate) mod ws;
 pub(crate) mod yproxy;
+pub(crate) mod zapier;
 
 pub(crate) type ApiResult<T> = Result<T, ErrorResponse>;
 
@@ -35,16 +103,56 @@ pub(crate) fn router() -> Result<Router> {
         .nest("/auth", auth::router())
         .nest("/ws", ws::router())
         .nest("/users", users::router())
+        .nest("/me", me::router())
+        .nest("/search", search::router())
+        .nest("/security", security::router())
+        .nest("/inbox", inbox::router())
+        .nest("/unread", unread::summary_router())
+        .nest("/jobs", jobs::router())
+        .nest("/maintenance", maintenance::router())
+        .nest("/moderation", moderation::queue_router())
+        .nest("/credentials", credentials::router())
+        .nest("/identities", identities::router())
+        .nest("/license", license::router())
+        .nest("/retention", retention::router())
+        .nest("/doc-migrations", doc_migrations::router())
+        .nest("/shadow", shadow::router())
+        .nest("/impersonation", impersonation::router())
+        .nest("/dashboard", dashboard::router())
+        .nest("/offboarding", offboarding::router())
+        .nest("/blueprints", blueprints::router())
         .nest("/dev", dev::router())
-        .layer((middleware::from_fn(google::authenticate),))
-        .nest("/billing", billing::router()?))
+        .layer((
+            middleware::from_fn(google::authenticate),
+            middleware::from_fn(usage::record_usage),
+        ))
+        // Invoked by the inbound mail provider and not users.
+        .nest("/inbound-email", inbound_email::webhook_router())
+        // Invoked by CI systems and not users.
+        .nest("/deployments", deployments::webhook_router())
+        // Invoked by Sentry and other alerting systems and not users.
+        .nest("/alerts", alerts::webhook_router())
+        // Invoked by Zapier, Make and other automation platforms with API keys.
+        .nest("/zapier", zapier::api_router())
+        // Invoked by AI agents with API keys.
+        .nest("/mcp", mcp::api_router())
+        // Invoked by task clients, e.g. Apple Reminders, with API keys.
+        .nest("/caldav", caldav::router())
+        .nest("/billing", billing::router()?)
+        // Embedded in READMEs and authorized by embed tokens.
+        .nest("/badges", badges::router())
+        // Invoked by anonymous visitors to demo projects.
+        .nest("/demo", demo::public_router())
+        // Loaded by browsers in img tags, which don't send credentials.
+        .nest("/unfurl", unfurl::image_router())
+        .layer(middleware::from_fn(maintenance::reject_writes)))
 }
 
 /// Verify that the user is premium.
 pub(crate) async fn verify_premium(pool: &PgPool, user: &User) -> Result<(), ErrorResponse> {
     match sqlx::query_as(
         "
-        SELECT subscription_end_time IS NOT NULL AND subscription_end_time > now() AS premium
+        SELECT is_premium(subscription_end_time) AS premium
         FROM users
         WHERE email = $1;
         ",
@@ -80,10 +188,12 @@ pub(crate) async fn verify_project_access(
         .await
         .context("Failed to check user permission")?;
 
-    let permission: Option<ProjectPermission> = sqlx::query_as(
+    // The project's region, if the user is a member.
+    let membership: Option<(Option<String>,)> = sqlx::query_as(
         "
-        SELECT project_id, email
+        SELECT region
         FROM project_permissions
+        JOIN projects USING (project_id)
         WHERE project_id = $1
           AND email = $2;
         ",
@@ -94,12 +204,95 @@ pub(crate) async fn verify_project_access(
     .await
     .context("Failed to check user permission")?;
 
-    match permission {
+    match membership {
+        // Projects pinned to a region whose database isn't connected can't be
+        // served from here.
+        Some((Some(region),)) if !postgres::serves_region(&region) => Err(CodedError::new(
+            ErrorCode::WrongRegion,
+            format!("Project {project_id} is stored in region {region}"),
+        )
+        .into()),
         Some(_) => Ok(()),
         None => Err(unauthorized_error(&format!(
             "User {} is not authorized to access {}",
             user.email, project_id
-        ))),
+        ))
+        .with_code(ErrorCode::NotAMember)),
+    }
+}
+
+/// Verify that the user administers the deployment, as opposed to a project.
+pub(crate) fn verify_admin(user: &User) -> Result<(), ErrorResponse> {
+    if !settings().admins.contains(&user.email) {
+        return Err(unauthorized_error(&format!(
+            "User {} is not an admin",
+            user.email
+        )));
+    }
+    Ok(())
+}
+
+/// Verify that the user is an admin of the project.
+pub(crate) async fn verify_project_admin(
+    pool: &PgPool,
+    user: &User,
+    project_id: &ProjectId,
+) -> Result<(), ErrorResponse> {
+    verify_project_access(pool, user, project_id).await?;
+
+    let (admin,): (bool,) = sqlx::query_as(
+        "
+        SELECT admin
+        FROM project_permissions
+        WHERE project_id = $1
+          AND email = $2;
+        ",
+    )
+    .bind(project_id)
+    .bind(&user.email)
+    .fetch_one(pool)
+    .await
+    .context("Failed to check user admin permission")?;
+    if !admin {
+        return Err(unauthorized_error(&format!(
+            "User {} is not an admin of {}",
+            user.email, project_id
+        )));
+    }
+    Ok(())
+}
+
+const MAX_TITLE_LEN: usize = 200;
+
+/// Checks that the title of a risk, goal, decision, etc. is neither blank nor too long.
+/// `kind` names the thing titled in error messages, e.g. "Risk".
+pub(crate) fn validate_title(kind: &str, title: &str) -> ApiResult<()> {
+    if title.trim().is_empty() {
+        return Err(bad_request_error(
+            "EMPTY_TITLE",
+            &format!("{kind} title is blank"),
+        ));
+    }
+    if title.trim().chars().count() > MAX_TITLE_LEN {
+        return Err(bad_request_error(
+            "LONG_TITLE",
+            &format!("{kind} title cannot be longer than {MAX_TITLE_LEN} characters"),
+        ));
+    }
+    Ok(())
+}
+
+/// Hashes a bearer token, e.g. an embed or deploy token, for storage and lookup.
+/// Surrounding whitespace, e.g. from a pasted token, is ignored.
+pub(crate) fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.trim().as_bytes()))
+}
+
+/// Returns the first `max_chars` characters of `s`, never splitting a character.
+pub(crate) fn truncate(s: &str, max_chars: usize) -> &str {
+    match s.char_indices().nth(max_chars) {
+        Some((i, _)) => &s[..i],
+        None => s,
     }
 }
 
@@ -152,6 +345,7 @@ pub(crate) fn error_response(
     }
     ErrorResponse {
         status,
+        code: ErrorCode::from_status(status),
         details: vec![ErrorDetail {
             reason,
             msg: format!("{err}"),
@@ -257,6 +451,7 @@ impl ErrorRender<'_> {
 #[derive(Debug)]
 pub(crate) struct ErrorResponse {
     status: StatusCode,
+    code: ErrorCode,
     details: Vec<ErrorDetail>,
 }
 
@@ -272,46 +467,109 @@ pub(crate) struct ErrorDetail {
 }
 
 impl ErrorResponse {
-    fn as_err(&self) -> Error {
+    pub(crate) fn as_err(&self) -> Error {
         if self.details.is_empty() {
             anyhow!("({}) <MISSING_ERROR_DETAILS>", self.status)
         } else {
             anyhow!("({}) {:?}", self.status, self.details)
         }
     }
+
+    /// Overrides the code derived from the status.
+    pub(crate) fn with_code(mut self, code: ErrorCode) -> Self {
+        self.code = code;
+        self
+    }
+
+    pub(crate) fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    /// True for failures of the server rather than the request, which may
+    /// succeed if retried.
+    pub(crate) fn is_internal(&self) -> bool {
+        self.status.is_server_error()
+    }
+
+    /// Renders the details for callers that can't see the HTTP response,
+    /// e.g. MCP tool results.
+    pub(crate) fn message(&self) -> String {
+        self.details
+            .iter()
+            .map(|d| format!("{}: {}", d.reason, d.msg))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
 }
 
+/// An RFC 9457 problem details body, extended with the error's code and details.
 #[derive(serde::Serialize)]
 struct ErrorResponseBody {
+    r#type: &'static str,
+    title: &'static str,
     // StatusCode in number form. e.g. 400, 500
     status: u16,
+    // Stable, machine readable error code. e.g. NOT_A_MEMBER
+    code: ErrorCode,
+    detail: Option<String>,
     details: Vec<ErrorDetail>,
+    // Id of the failed request, for correlating with server logs.
+    request_id: Option<String>,
 }
 
 /// Converts from ErrorResponse to Response.
 impl IntoResponse for ErrorResponse {
     fn into_response(self) -> Response {
-        let body = axum::Json(ErrorResponseBody {
+        let body = ErrorResponseBody {
+            r#type: "about:blank",
+            title: self.code.title(),
             status: self.status.as_u16(),
+            code: self.code,
+            detail: self.details.first().map(|d| d.msg.clone()),
             details: self.details,
-        });
-
-        (self.status, body).into_response()
+            request_id: context::current().map(|c| c.request_id().to_string()),
+        };
+        let body = match serde_json::to_vec(&body) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::error!("Failed to serialize error response: {e:?}");
+                return self.status.into_response();
+            }
+        };
+
+        (
+            self.status,
+            [(CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON))],
+            body,
+        )
+            .into_response()
     }
 }
 
-/// Converts from boxed Error to ErrorResponse and logs the error.
+const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Converts from boxed Error to ErrorResponse and logs the error. Errors
+/// carrying a code are reported with it, rather than as internal.
 impl<E> From<E> for ErrorResponse
 where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        internal_error(err.into(), None)
+        let err = err.into();
+        match errors::find_coded(&err) {
+            Some(coded) => {
+                let (code, msg) = (coded.code, coded.msg.clone());
+                error_response(code.status(), code.as_str(), Some(&msg), Some(err)).with_code(code)
+            }
+            None => internal_error(err, None),
+        }
     }
 }
 
+/// The addresses in X-Forwarded-For, in order, starting with the one the
+/// client claims. Each proxy appends the address it received the request from.
 pub struct XForwardedFor {
-    pub client_ip: String,
+    pub hops: Vec<String>,
 }
 
 impl headers::Header for XForwardedFor {
@@ -323,14 +581,31 @@ impl headers::Header for XForwardedFor {
     fn decode<'i, I: Iterator<Item = &'i HeaderValue>>(
         values: &mut I,
     ) -> Result<Self, headers::Error> {
-        let val = values.next().ok_or_else(headers::Error::invalid)?;
-        let val = val.to_str().map_err(|_| headers::Error::invalid())?;
-        let first = val.split(',').next().ok_or_else(headers::Error::invalid)?;
-        let client_ip = first.trim().to_string();
-        Ok(XForwardedFor { client_ip })
+        let mut hops = Vec::new();
+        for val in values {
+            let val = val.to_str().map_err(|_| headers::Error::invalid())?;
+            hops.extend(val.split(',').map(|hop| hop.trim().to_string()));
+        }
+        if hops.is_empty() {
+            return Err(headers::Error::invalid());
+        }
+        Ok(XForwardedFor { hops })
     }
 
     fn encode<E: Extend<HeaderValue>>(&self, values: &mut E) {
-        values.extend([self.client_ip.to_string().try_into().unwrap()])
+        values.extend([self.hops.join(", ").try_into().unwrap()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_title_counts_characters() {
+        assert!(validate_title("Risk", &"界".repeat(MAX_TITLE_LEN)).is_ok());
+        assert!(validate_title("Risk", &"🚀".repeat(MAX_TITLE_LEN)).is_ok());
+        assert!(validate_title("Risk", &"界".repeat(MAX_TITLE_LEN + 1)).is_err());
+        assert!(validate_title("Risk", " ").is_err());
     }
 }
@@ -0,0 +1,524 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{
+            Collab,
+            projects_state::DocBox,
+            txn_origin::{Actor, TxnMetadata, YOrigin},
+        },
+        google::User,
+        hash_token,
+        model::{ProjectId, Task},
+        not_found_error, step_up, truncate, verify_project_admin,
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    body::{Body, Bytes},
+    extract::Path,
+    http::HeaderMap,
+    middleware,
+    routing::{get, post},
+};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest as _, Sha256};
+use sqlx::{
+    FromRow,
+    types::chrono::{DateTime, Utc},
+};
+use tower_http::request_id::RequestId;
+use uuid::Uuid;
+
+/// Routes used by project admins to manage their alert integration.
+pub(super) fn router() -> Router {
+    Router::new()
+        .route(
+            "/{project_id}/alerts/integration",
+            get(get_integration_handler)
+                .patch(update_integration_handler)
+                .delete(delete_integration_handler),
+        )
+        .route(
+            "/{project_id}/alerts/integration",
+            post(create_integration_handler)
+                .route_layer(middleware::from_fn(step_up::require_step_up)),
+        )
+        .route("/{project_id}/alerts", get(list_alerts_handler))
+}
+
+/// Routes invoked by Sentry, or other alerting systems, rather than users.
+pub(super) fn webhook_router() -> Router {
+    Router::new().route("/{token}", post(alert_webhook_handler))
+}
+
+const BODY_LIMIT: usize = 1024 * 1024;
+const MAX_NAME_LEN: usize = 200;
+const MAX_DESC_LEN: usize = 10_000;
+const MAX_FINGERPRINT_LEN: usize = 255;
+/// New alert tasks created per project per hour. Further new alerts are
+/// counted but don't create tasks, so a flood of distinct errors can't bury
+/// the project.
+const MAX_NEW_TASKS_PER_HOUR: i64 = 50;
+/// Prefixes the line of an alert task's description holding its occurrence count.
+const OCCURRENCES_PREFIX: &str = "Occurrences: ";
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AlertIntegration {
+    /// The task new alert tasks are created under, e.g. a Triage task.
+    /// The project's root when unset.
+    parent_task_id: Option<String>,
+    creator: String,
+    create_time: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CreatedAlertIntegration {
+    /// Alerts are posted to /api/alerts/<token>. Only returned when the
+    /// integration is created.
+    token: String,
+    #[serde(flatten)]
+    integration: AlertIntegration,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UpdateAlertIntegration {
+    parent_task_id: Option<String>,
+}
+
+/// An alert deduplicated by its fingerprint.
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AlertSummary {
+    fingerprint: String,
+    title: String,
+    task_id: Option<String>,
+    occurrences: i64,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+}
+
+/// An alert, normalized from the formats of the supported sources.
+#[derive(Debug, PartialEq, Eq)]
+struct Alert {
+    /// Alerts with the same fingerprint are occurrences of the same problem.
+    fingerprint: String,
+    title: String,
+    detail: Option<String>,
+    url: Option<String>,
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn get_integration_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Option<AlertIntegration>>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let integration: Option<AlertIntegration> = sqlx::query_as(
+        "
+        SELECT parent_task_id, creator, create_time
+        FROM alert_integrations
+        WHERE project_id = $1",
+    )
+    .bind(&project_id)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch alert integration")?;
+    Ok(Json(integration))
+}
+
+/// Creates the project's alert integration, replacing any existing one.
+/// Alerts sent to a replaced integration are discarded.
+#[tracing::instrument(skip(user, pool))]
+async fn create_integration_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Json(update): Json<UpdateAlertIntegration>,
+) -> ApiResult<Json<CreatedAlertIntegration>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let token = Uuid::new_v4().simple().to_string();
+    let integration: AlertIntegration = sqlx::query_as(
+        "
+        INSERT INTO alert_integrations (project_id, token_hash, parent_task_id, creator)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (project_id)
+        DO UPDATE SET
+            token_hash = EXCLUDED.token_hash,
+            parent_task_id = EXCLUDED.parent_task_id,
+            creator = EXCLUDED.creator,
+            create_time = NOW()
+        RETURNING parent_task_id, creator, create_time",
+    )
+    .bind(&project_id)
+    .bind(hash_token(&token))
+    .bind(&update.parent_task_id)
+    .bind(&user.email)
+    .fetch_one(pool)
+    .await
+    .context("Failed to upsert alert integration")?;
+    Ok(Json(CreatedAlertIntegration { token, integration }))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn update_integration_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Json(update): Json<UpdateAlertIntegration>,
+) -> ApiResult<Json<AlertIntegration>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let integration: Option<AlertIntegration> = sqlx::query_as(
+        "
+        UPDATE alert_integrations
+        SET parent_task_id = $2
+        WHERE project_id = $1
+        RETURNING parent_task_id, creator, create_time",
+    )
+    .bind(&project_id)
+    .bind(&update.parent_task_id)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to update alert integration")?;
+    match integration {
+        Some(integration) => Ok(Json(integration)),
+        None => Err(not_found_error("NOT_FOUND", "No alert integration")),
+    }
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_integration_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<()>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    sqlx::query("DELETE FROM alert_integrations WHERE project_id = $1")
+        .bind(&project_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete alert integration")?;
+    Ok(Json(()))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn list_alerts_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Vec<AlertSummary>>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let alerts: Vec<AlertSummary> = sqlx::query_as(
+        "
+        SELECT fingerprint, title, task_id, occurrences, first_seen, last_seen
+        FROM alert_fingerprints
+        WHERE project_id = $1
+        ORDER BY last_seen DESC
+        LIMIT 100",
+    )
+    .bind(&project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list alerts")?;
+    Ok(Json(alerts))
+}
+
+/// Turns an alert into a task in the project's triage subtree, or, when a task
+/// already exists for the alert's fingerprint, updates its occurrence count.
+/// Unrecognized payloads are acknowledged so the sender doesn't retry them.
+#[tracing::instrument(skip(pool, collab, request_id, token, headers, body))]
+async fn alert_webhook_handler(
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Extension(request_id): Extension<RequestId>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> ApiResult<String> {
+    let integration: Option<(ProjectId, Option<String>)> = sqlx::query_as(
+        "
+        SELECT project_id, parent_task_id
+        FROM alert_integrations
+        JOIN projects USING (project_id)
+        WHERE token_hash = $1 AND deleted_on IS NULL",
+    )
+    .bind(hash_token(&token))
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up alert integration")?;
+    let Some((project_id, parent_task_id)) = integration else {
+        return Err(not_found_error("NOT_FOUND", "Unknown alert integration"));
+    };
+
+    let body: Bytes = axum::body::to_bytes(body, BODY_LIMIT)
+        .await
+        .map_err(|_| bad_request_error("INVALID_BODY", "Invalid body"))?;
+    let payload: Value = serde_json::from_slice(&body)
+        .map_err(|e| bad_request_error("INVALID_BODY", &format!("Invalid alert: {e}")))?;
+    // See https://docs.sentry.io/organization/integrations/integration-platform/webhooks/
+    let resource = headers
+        .get("Sentry-Hook-Resource")
+        .and_then(|h| h.to_str().ok());
+    let Some(alert) = parse_alert(resource, &payload) else {
+        tracing::debug!("Discarding unrecognized alert: {resource:?}");
+        return Ok("OK".to_string());
+    };
+
+    let (task_id, occurrences, inserted, recent): (Option<String>, i64, bool, i64) =
+        sqlx::query_as(
+            "
+            WITH recent AS (
+                SELECT COUNT(*) AS recent
+                FROM alert_fingerprints
+                WHERE project_id = $1 AND first_seen > NOW() - INTERVAL '1 hour'
+            )
+            INSERT INTO alert_fingerprints (project_id, fingerprint, title)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (project_id, fingerprint)
+            DO UPDATE SET occurrences = alert_fingerprints.occurrences + 1, last_seen = NOW()
+            RETURNING task_id, occurrences, (xmax = 0) AS inserted, (SELECT recent FROM recent)",
+        )
+        .bind(&project_id)
+        .bind(&alert.fingerprint)
+        .bind(&alert.title)
+        .fetch_one(pool)
+        .await
+        .context("Failed to record alert")?;
+
+    let request_id = request_id.header_value().to_str().unwrap_or("INVALID");
+    let origin = YOrigin {
+        who: "alerts".to_string(),
+        id: format!("alert_{request_id}"),
+        actor: Actor::Server,
+        metadata: TxnMetadata {
+            request_id: Some(request_id.to_string()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    match task_id {
+        Some(task_id) => {
+            update_occurrences(&collab, &project_id, &task_id, occurrences, origin).await?;
+        }
+        None if inserted && recent < MAX_NEW_TASKS_PER_HOUR => {
+            let task_id = create_task(
+                &collab,
+                &project_id,
+                parent_task_id.as_deref(),
+                &alert,
+                origin,
+            )
+            .await?;
+            sqlx::query(
+                "
+                UPDATE alert_fingerprints
+                SET task_id = $3
+                WHERE project_id = $1 AND fingerprint = $2",
+            )
+            .bind(&project_id)
+            .bind(&alert.fingerprint)
+            .bind(&task_id)
+            .execute(pool)
+            .await
+            .context("Failed to record alert task")?;
+        }
+        None => {
+            tracing::debug!(
+                "Recorded alert {} without a task, {recent} new alerts in the last hour",
+                alert.fingerprint
+            );
+        }
+    }
+
+    Ok("OK".to_string())
+}
+
+/// Normalizes Sentry issue and issue alert webhooks, and a generic format:
+/// `{"title": ..., "fingerprint": ..., "description": ..., "url": ...}`.
+fn parse_alert(sentry_resource: Option<&str>, payload: &Value) -> Option<Alert> {
+    let str_field = |value: &Value, field: &str| {
+        value
+            .get(field)
+            .and_then(|v| match v {
+                Value::String(s) => Some(s.trim().to_string()),
+                Value::Number(n) => Some(n.to_string()),
+                _ => None,
+            })
+            .filter(|s| !s.is_empty())
+    };
+    let (fingerprint, title, detail, url) = match sentry_resource {
+        Some("issue") => {
+            let issue = payload.get("data")?.get("issue")?;
+            (
+                format!("sentry:{}", str_field(issue, "id")?),
+                str_field(issue, "title")?,
+                str_field(issue, "culprit"),
+                str_field(issue, "permalink").or_else(|| str_field(issue, "web_url")),
+            )
+        }
+        Some("event_alert") => {
+            let event = payload.get("data")?.get("event")?;
+            (
+                format!("sentry:{}", str_field(event, "issue_id")?),
+                str_field(event, "title")?,
+                str_field(event, "culprit"),
+                str_field(event, "web_url"),
+            )
+        }
+        Some(_) => return None,
+        None => {
+            let title = str_field(payload, "title")?;
+            let fingerprint = match str_field(payload, "fingerprint") {
+                Some(fingerprint) => fingerprint,
+                None => hex::encode(Sha256::digest(title.as_bytes())),
+            };
+            (
+                fingerprint,
+                title,
+                str_field(payload, "description"),
+                str_field(payload, "url"),
+            )
+        }
+    };
+    Some(Alert {
+        fingerprint: truncate(&fingerprint, MAX_FINGERPRINT_LEN).to_string(),
+        title: truncate(&title, MAX_NAME_LEN).to_string(),
+        detail: detail.map(|d| truncate(&d, MAX_DESC_LEN).to_string()),
+        url,
+    })
+}
+
+async fn create_task(
+    collab: &Collab,
+    project_id: &ProjectId,
+    parent_task_id: Option<&str>,
+    alert: &Alert,
+    origin: YOrigin,
+) -> Result<String> {
+    let client = collab.register_local_client(project_id).await?;
+    let doc_box = client.project.doc_box.lock().await;
+    let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+    let mut txn = doc.transact_mut_with(origin.as_origin()?);
+    let desc = with_occurrences(alert.detail.as_deref().unwrap_or_default(), 1);
+    let task = Task {
+        id: BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4()),
+        num: doc.next_num(&txn)?.to_string(),
+        name: alert.title.clone(),
+        desc: Some(desc),
+        url: alert.url.clone(),
+        ..Task::default()
+    };
+    doc.set(&mut txn, &task);
+    // Fall back to the root if the triage task has since been deleted.
+    let parent = match parent_task_id.map(|id| doc.get(&txn, id)) {
+        Some(Ok(parent)) => parent,
+        _ => doc.get(&txn, "root")?,
+    };
+    parent.push_child(&mut txn, &task.id)?;
+    Ok(task.id)
+}
+
+async fn update_occurrences(
+    collab: &Collab,
+    project_id: &ProjectId,
+    task_id: &str,
+    occurrences: i64,
+    origin: YOrigin,
+) -> Result<()> {
+    let client = collab.register_local_client(project_id).await?;
+    let doc_box = client.project.doc_box.lock().await;
+    let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+    let mut txn = doc.transact_mut_with(origin.as_origin()?);
+    let Ok(task) = doc.get(&txn, task_id) else {
+        tracing::debug!("Alert task {task_id} no longer exists");
+        return Ok(());
+    };
+    let desc = task.get_desc(&txn)?.unwrap_or_default();
+    task.set_desc(&mut txn, Some(&with_occurrences(&desc, occurrences)));
+    Ok(())
+}
+
+/// Replaces the occurrence count line at the end of the description,
+/// leaving the rest, including any edits, intact.
+fn with_occurrences(desc: &str, occurrences: i64) -> String {
+    let body = match desc.rfind(OCCURRENCES_PREFIX) {
+        Some(i) if !desc[i..].contains('\n') => desc[..i].trim_end(),
+        _ => desc.trim_end(),
+    };
+    if body.is_empty() {
+        format!("{OCCURRENCES_PREFIX}{occurrences}")
+    } else {
+        format!("{body}\n\n{OCCURRENCES_PREFIX}{occurrences}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parse_alert_handles_sentry_and_generic_payloads() {
+        assert_eq!(
+            parse_alert(
+                Some("issue"),
+                &json!({"action": "created", "data": {"issue": {
+                    "id": "1170820242",
+                    "title": "TypeError: undefined is not a function",
+                    "culprit": "app/main.js",
+                    "permalink": "https://sentry.io/issues/1170820242/"
+                }}})
+            ),
+            Some(Alert {
+                fingerprint: "sentry:1170820242".to_string(),
+                title: "TypeError: undefined is not a function".to_string(),
+                detail: Some("app/main.js".to_string()),
+                url: Some("https://sentry.io/issues/1170820242/".to_string()),
+            })
+        );
+        assert_eq!(
+            parse_alert(
+                Some("event_alert"),
+                &json!({"data": {"event": {"issue_id": 42, "title": "Boom"}}})
+            )
+            .map(|a| a.fingerprint),
+            Some("sentry:42".to_string())
+        );
+        assert_eq!(parse_alert(Some("installation"), &json!({})), None);
+
+        let generic = parse_alert(None, &json!({"title": "Disk full"})).unwrap();
+        assert_eq!(
+            generic.fingerprint,
+            parse_alert(None, &json!({"title": "Disk full", "url": "x"}))
+                .unwrap()
+                .fingerprint
+        );
+        assert_eq!(
+            parse_alert(None, &json!({"title": "Disk full", "fingerprint": "disk"}))
+                .unwrap()
+                .fingerprint,
+            "disk"
+        );
+        assert_eq!(parse_alert(None, &json!({"title": " "})), None);
+    }
+
+    #[test]
+    fn with_occurrences_replaces_only_the_count() {
+        assert_eq!(with_occurrences("", 1), "Occurrences: 1");
+        let desc = with_occurrences("app/main.js", 1);
+        assert_eq!(desc, "app/main.js\n\nOccurrences: 1");
+        assert_eq!(with_occurrences(&desc, 7), "app/main.js\n\nOccurrences: 7");
+        // The count is re-appended if it was edited out of the description.
+        assert_eq!(
+            with_occurrences("Occurrences: 3\nnotes", 4),
+            "Occurrences: 3\nnotes\n\nOccurrences: 4"
+        );
+    }
+}
@@ -0,0 +1,634 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{Collab, projects_state::DocBox},
+        google::User,
+        model::{
+            CumulativeFlow, CumulativeFlowSeries, EstimateAccuracy, EstimateAccuracyReport,
+            EstimateSettings, FlowMetrics, FlowMetricsReport, Graph, Percentiles, ProjectId,
+            TaskProgress, WorkflowCategory, WorkflowState,
+        },
+        verify_project_access,
+        yproxy::status_category,
+    },
+    postgres::{PgPool, ReadPool},
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    routing::get,
+};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::Deserialize;
+use sqlx::types::Json as SqlJson;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use tokio::task::JoinHandle;
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route(
+            "/{project_id}/analytics/estimates",
+            get(estimate_accuracy_handler),
+        )
+        .route("/{project_id}/analytics/flow", get(flow_metrics_handler))
+        .route("/{project_id}/analytics/cfd", get(cumulative_flow_handler))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AnalyticsQuery {
+    days: Option<i64>,
+}
+
+const DEFAULT_DAYS: i64 = 90;
+const MAX_DAYS: i64 = 365;
+/// How often to check for projects whose status counts are due.
+const SNAPSHOT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+/// The status counted for tasks without one.
+const NOT_STARTED: &str = "Not Started";
+
+fn validate_days(days: Option<i64>) -> ApiResult<i64> {
+    let days = days.unwrap_or(DEFAULT_DAYS);
+    if !(1..=MAX_DAYS).contains(&days) {
+        return Err(bad_request_error(
+            "INVALID_DAYS",
+            &format!("Days must be between 1 and {MAX_DAYS}"),
+        ));
+    }
+    Ok(days)
+}
+
+#[derive(sqlx::FromRow, Debug)]
+struct Completion {
+    assignee: Option<String>,
+    labels: SqlJson<Vec<String>>,
+    original_estimate: Option<i64>,
+    final_estimate: Option<i64>,
+    create_time: Option<DateTime<Utc>>,
+    start_time: Option<DateTime<Utc>>,
+    done_time: DateTime<Utc>,
+}
+
+/// Compares original estimates of recently completed tasks to their final
+/// estimates and to how long they actually took. Hours per point are in
+/// points converted from the project's estimate unit.
+#[tracing::instrument(skip(user, pool, read_pool, collab))]
+async fn estimate_accuracy_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(read_pool): Extension<ReadPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+    Query(query): Query<AnalyticsQuery>,
+) -> ApiResult<Json<EstimateAccuracyReport>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let days = validate_days(query.days)?;
+    let end = Utc::now();
+    let completions = list_completions(
+        read_pool.get(),
+        &project_id,
+        end - Duration::days(days),
+        end,
+    )
+    .await?;
+    let estimates = {
+        let client = collab.register_local_client(&project_id).await?;
+        let doc_box = client.project.doc_box.lock().await;
+        let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+        let txn = doc.transact();
+        doc.config().get_estimates(&txn)?.unwrap_or_default()
+    };
+    let (overall, by_assignee, by_label) = breakdown(&completions, |key, completions| {
+        estimate_accuracy(key, completions, &estimates)
+    });
+    Ok(Json(EstimateAccuracyReport {
+        days,
+        overall,
+        by_assignee,
+        by_label,
+    }))
+}
+
+/// Reports cycle time, from first starting a task to finishing it, and lead
+/// time, from creating a task to finishing it, of recently completed tasks.
+#[tracing::instrument(skip(user, pool, read_pool))]
+async fn flow_metrics_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(read_pool): Extension<ReadPool>,
+    Path(project_id): Path<ProjectId>,
+    Query(query): Query<AnalyticsQuery>,
+) -> ApiResult<Json<FlowMetricsReport>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let days = validate_days(query.days)?;
+    let end = Utc::now();
+    let completions = list_completions(
+        read_pool.get(),
+        &project_id,
+        end - Duration::days(days),
+        end,
+    )
+    .await?;
+    let (overall, by_assignee, by_label) = breakdown(&completions, flow_metrics);
+    Ok(Json(FlowMetricsReport {
+        days,
+        overall,
+        by_assignee,
+        by_label,
+    }))
+}
+
+/// Returns the project's daily task counts per status over the last `days` days.
+#[tracing::instrument(skip(user, pool, read_pool))]
+async fn cumulative_flow_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(read_pool): Extension<ReadPool>,
+    Path(project_id): Path<ProjectId>,
+    Query(query): Query<AnalyticsQuery>,
+) -> ApiResult<Json<CumulativeFlow>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let days = validate_days(query.days)?;
+    let counts: Vec<(NaiveDate, String, i64)> = sqlx::query_as(
+        "
+        SELECT day, status, count
+        FROM task_status_counts
+        WHERE project_id = $1
+        AND day > CURRENT_DATE - $2::int
+        ORDER BY day",
+    )
+    .bind(&project_id)
+    .bind(days)
+    .fetch_all(read_pool.get())
+    .await
+    .context("Failed to list task status counts")?;
+    Ok(Json(cumulative_flow(counts)))
+}
+
+/// Arranges (day, status, count) rows into one series per status, filling
+/// in zero for days a status had no tasks.
+fn cumulative_flow(counts: Vec<(NaiveDate, String, i64)>) -> CumulativeFlow {
+    let dates: Vec<NaiveDate> = counts
+        .iter()
+        .map(|(day, _, _)| *day)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let index: HashMap<NaiveDate, usize> = dates.iter().enumerate().map(|(i, d)| (*d, i)).collect();
+    let mut series: BTreeMap<String, Vec<i64>> = BTreeMap::new();
+    for (day, status, count) in counts {
+        series.entry(status).or_insert_with(|| vec![0; dates.len()])[index[&day]] = count;
+    }
+    CumulativeFlow {
+        dates,
+        series: series
+            .into_iter()
+            .map(|(status, counts)| CumulativeFlowSeries { status, counts })
+            .collect(),
+    }
+}
+
+/// Records each project's task counts per status once a day. Projects without
+/// any counts yet are first backfilled from their status transition history.
+pub(crate) struct AnalyticsSnapshotter {
+    pool: &'static PgPool,
+    collab: Collab,
+}
+
+impl AnalyticsSnapshotter {
+    pub(crate) fn new(pool: &'static PgPool, collab: Collab) -> Self {
+        AnalyticsSnapshotter { pool, collab }
+    }
+
+    pub(crate) fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.snapshot_due_projects().await {
+                    tracing::warn!("Failed to snapshot status counts: {e:?}");
+                }
+            }
+        })
+    }
+
+    async fn snapshot_due_projects(&self) -> Result<()> {
+        let today = Utc::now().date_naive();
+        let due: Vec<(String, bool)> = sqlx::query_as(
+            "
+            SELECT
+                p.project_id,
+                NOT EXISTS (SELECT 1 FROM task_status_counts c WHERE c.project_id = p.project_id)
+            FROM projects p
+            WHERE p.deleted_on IS NULL
+            AND NOT EXISTS (
+                SELECT 1 FROM task_status_counts c
+                WHERE c.project_id = p.project_id AND c.day = $1
+            )",
+        )
+        .bind(today)
+        .fetch_all(self.pool)
+        .await
+        .context("Failed to list projects due for status counts")?;
+
+        for (project_id, needs_backfill) in due {
+            // Snapshots are best-effort, and any missed are picked up on a later pass.
+            if self.pool.shed_best_effort() {
+                tracing::debug!("Deferring status count snapshots while the pool is saturated");
+                break;
+            }
+            if needs_backfill {
+                if let Err(e) = self.backfill(&project_id, today).await {
+                    tracing::warn!("Failed to backfill status counts for {project_id}: {e:?}");
+                }
+            }
+            if let Err(e) = self.snapshot(&project_id, today).await {
+                tracing::warn!("Failed to snapshot status counts for {project_id}: {e:?}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the project's current task counts per status as of `day`.
+    async fn snapshot(&self, project_id: &ProjectId, day: NaiveDate) -> Result<()> {
+        let mut counts: HashMap<String, i64> = HashMap::new();
+        {
+            let client = self.collab.register_local_client(project_id).await?;
+            let doc_box = client.project.doc_box.lock().await;
+            let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+            for task in doc.to_graph(&doc.transact())?.into_values() {
+                if task.id == "root" || task.archived == Some(true) {
+                    continue;
+                }
+                let status = task.status.unwrap_or_else(|| NOT_STARTED.to_string());
+                *counts.entry(status).or_default() += 1;
+            }
+        }
+        let (statuses, counts): (Vec<String>, Vec<i64>) = counts.into_iter().unzip();
+        sqlx::query(
+            "
+            INSERT INTO task_status_counts (project_id, day, status, count)
+            SELECT $1, $2, status, count
+            FROM UNNEST($3::varchar[], $4::bigint[]) AS c(status, count)
+            ON CONFLICT (project_id, day, status)
+            DO UPDATE SET count = EXCLUDED.count",
+        )
+        .bind(project_id)
+        .bind(day)
+        .bind(statuses)
+        .bind(counts)
+        .execute(self.pool)
+        .await
+        .context("Failed to insert status counts")?;
+        Ok(())
+    }
+
+    /// Computes daily counts before `today` from the status transition history,
+    /// taking each task's latest status as of the end of each day. Deleted
+    /// tasks are absent from the history's point of view and still count.
+    async fn backfill(&self, project_id: &ProjectId, today: NaiveDate) -> Result<()> {
+        sqlx::query(
+            "
+            INSERT INTO task_status_counts (project_id, day, status, count)
+            SELECT $1, d.day::date, COALESCE(t.status, $3), COUNT(*)
+            FROM generate_series(
+                (SELECT MIN(transition_time)::date FROM task_status_transitions WHERE project_id = $1),
+                $2::date - 1,
+                INTERVAL '1 day'
+            ) AS d(day)
+            CROSS JOIN LATERAL (
+                SELECT DISTINCT ON (task_id) task_id, status
+                FROM task_status_transitions
+                WHERE project_id = $1
+                AND transition_time < d.day + INTERVAL '1 day'
+                ORDER BY task_id, transition_time DESC
+            ) t
+            GROUP BY d.day, COALESCE(t.status, $3)
+            ON CONFLICT DO NOTHING",
+        )
+        .bind(project_id)
+        .bind(today)
+        .bind(NOT_STARTED)
+        .execute(self.pool)
+        .await
+        .context("Failed to backfill status counts")?;
+        Ok(())
+    }
+}
+
+/// Computes flow metrics of the tasks completed between `start` and `end`.
+pub(crate) async fn completed_flow_metrics(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<FlowMetrics> {
+    let completions = list_completions(pool, project_id, start, end).await?;
+    Ok(flow_metrics(None, &completions.iter().collect::<Vec<_>>()))
+}
+
+async fn list_completions(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<Completion>> {
+    sqlx::query_as(
+        "
+        SELECT assignee, labels, original_estimate, final_estimate, create_time, start_time, done_time
+        FROM task_completions
+        WHERE project_id = $1
+        AND done_time >= $2
+        AND done_time < $3",
+    )
+    .bind(project_id)
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list task completions")
+}
+
+/// Computes statistics over all the completions, then per assignee and per label.
+fn breakdown<T>(
+    completions: &[Completion],
+    stats: impl Fn(Option<&str>, &[&Completion]) -> T,
+) -> (T, Vec<T>, Vec<T>) {
+    let mut by_assignee: BTreeMap<&str, Vec<&Completion>> = BTreeMap::new();
+    let mut by_label: BTreeMap<&str, Vec<&Completion>> = BTreeMap::new();
+    for completion in completions {
+        if let Some(assignee) = &completion.assignee {
+            by_assignee.entry(assignee).or_default().push(completion);
+        }
+        for label in completion.labels.iter() {
+            by_label.entry(label).or_default().push(completion);
+        }
+    }
+    (
+        stats(None, &completions.iter().collect::<Vec<_>>()),
+        by_assignee
+            .into_iter()
+            .map(|(key, completions)| stats(Some(key), &completions))
+            .collect(),
+        by_label
+            .into_iter()
+            .map(|(key, completions)| stats(Some(key), &completions))
+            .collect(),
+    )
+}
+
+fn estimate_accuracy(
+    key: Option<&str>,
+    completions: &[&Completion],
+    estimates: &EstimateSettings,
+) -> EstimateAccuracy {
+    let estimated: Vec<(f64, f64)> = completions
+        .iter()
+        .filter_map(|c| match (c.original_estimate, c.final_estimate) {
+            (Some(original), Some(last)) if original > 0 => Some((original as f64, last as f64)),
+            _ => None,
+        })
+        .collect();
+    let mut hours_per_point: Vec<f64> = completions
+        .iter()
+        .filter_map(|c| match (c.start_time, c.final_estimate) {
+            (Some(start), Some(estimate)) if estimate > 0 => {
+                Some(hours(c.done_time - start) / estimates.to_points(estimate))
+            }
+            _ => None,
+        })
+        .collect();
+
+    EstimateAccuracy {
+        key: key.map(String::from),
+        tasks: completions.len(),
+        mean_estimate_growth: mean(estimated.iter().map(|(original, last)| last / original)),
+        mean_absolute_percentage_error: mean(
+            estimated
+                .iter()
+                .filter(|(_, last)| *last > 0.0)
+                .map(|(original, last)| (original - last).abs() / last),
+        ),
+        median_hours_per_point: percentile(&mut hours_per_point, 50.0),
+    }
+}
+
+fn flow_metrics(key: Option<&str>, completions: &[&Completion]) -> FlowMetrics {
+    let cycle_times = completions
+        .iter()
+        .filter_map(|c| Some(hours(c.done_time - c.start_time?)))
+        .collect();
+    let lead_times = completions
+        .iter()
+        .filter_map(|c| Some(hours(c.done_time - c.create_time?)))
+        .collect();
+    FlowMetrics {
+        key: key.map(String::from),
+        tasks: completions.len(),
+        cycle_time: percentiles(cycle_times),
+        lead_time: percentiles(lead_times),
+    }
+}
+
+fn percentiles(mut values: Vec<f64>) -> Percentiles {
+    Percentiles {
+        p50: percentile(&mut values, 50.0),
+        p85: percentile(&mut values, 85.0),
+        p95: percentile(&mut values, 95.0),
+    }
+}
+
+fn hours(duration: Duration) -> f64 {
+    duration.num_minutes() as f64 / 60.0
+}
+
+/// Counts the unarchived leaf tasks in the subtrees rooted at `roots`, and
+/// how many of them are done. Rollup tasks are complete when their leaves are.
+pub(crate) fn leaf_progress(
+    graph: &Graph,
+    workflow_states: &[WorkflowState],
+    roots: &[String],
+) -> TaskProgress {
+    let mut progress = TaskProgress::default();
+    let mut visited = HashSet::new();
+    let mut queue: VecDeque<String> = roots.iter().cloned().collect();
+    while let Some(id) = queue.pop_front() {
+        if !visited.insert(id.clone()) {
+            continue;
+        }
+        let Some(task) = graph.get(&id) else {
+            continue;
+        };
+        if task.archived == Some(true) {
+            continue;
+        }
+        if !task.children.is_empty() || task.kind.as_deref() == Some("Rollup") {
+            queue.extend(task.children.iter().cloned());
+            continue;
+        }
+        progress.total += 1;
+        let category = task
+            .status
+            .as_deref()
+            .and_then(|status| status_category(workflow_states, status));
+        if category == Some(WorkflowCategory::Done) {
+            progress.done += 1;
+        }
+    }
+    progress
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    (count > 0).then(|| sum / count as f64)
+}
+
+/// Returns the nearest-rank percentile of the values, sorting them in place.
+pub(crate) fn percentile(values: &mut [f64], percentile: f64) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let rank = ((percentile / 100.0) * values.len() as f64).ceil() as usize;
+    Some(values[rank.clamp(1, values.len()) - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::model::{Task, test_utils};
+
+    fn completion(original: Option<i64>, last: Option<i64>, hours: Option<i64>) -> Completion {
+        let done_time = DateTime::from_timestamp(1_000_000, 0).unwrap();
+        Completion {
+            assignee: None,
+            labels: SqlJson(vec![]),
+            original_estimate: original,
+            final_estimate: last,
+            create_time: None,
+            start_time: hours.map(|h| done_time - Duration::hours(h)),
+            done_time,
+        }
+    }
+
+    #[test]
+    fn percentile_uses_nearest_rank() {
+        let mut values = vec![5.0, 1.0, 4.0, 2.0, 3.0];
+        assert_eq!(percentile(&mut values, 50.0), Some(3.0));
+        assert_eq!(percentile(&mut values, 90.0), Some(5.0));
+        assert_eq!(percentile(&mut values, 0.0), Some(1.0));
+        assert_eq!(percentile(&mut [], 50.0), None);
+    }
+
+    #[test]
+    fn estimate_accuracy_compares_estimates_and_actuals() {
+        let completions = [
+            completion(Some(2), Some(4), Some(8)),
+            completion(Some(4), Some(4), Some(4)),
+            completion(None, Some(1), None),
+        ];
+        let accuracy = estimate_accuracy(
+            Some("a"),
+            &completions.iter().collect::<Vec<_>>(),
+            &EstimateSettings::default(),
+        );
+        assert_eq!(
+            accuracy,
+            EstimateAccuracy {
+                key: Some("a".into()),
+                tasks: 3,
+                mean_estimate_growth: Some(1.5),
+                mean_absolute_percentage_error: Some(0.25),
+                median_hours_per_point: Some(1.0),
+            }
+        );
+    }
+
+    #[test]
+    fn flow_metrics_reports_cycle_and_lead_times() {
+        let done_time = DateTime::from_timestamp(1_000_000, 0).unwrap();
+        let completions: Vec<Completion> = (1..=10)
+            .map(|i| Completion {
+                create_time: (i % 2 == 0).then(|| done_time - Duration::hours(i * 10)),
+                ..completion(None, None, Some(i))
+            })
+            .collect();
+        let metrics = flow_metrics(None, &completions.iter().collect::<Vec<_>>());
+        assert_eq!(
+            metrics,
+            FlowMetrics {
+                key: None,
+                tasks: 10,
+                cycle_time: Percentiles {
+                    p50: Some(5.0),
+                    p85: Some(9.0),
+                    p95: Some(10.0),
+                },
+                lead_time: Percentiles {
+                    p50: Some(60.0),
+                    p85: Some(100.0),
+                    p95: Some(100.0),
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn cumulative_flow_fills_missing_days() {
+        let day = |d: u32| NaiveDate::from_ymd_opt(2025, 8, d).unwrap();
+        let flow = cumulative_flow(vec![
+            (day(1), "Done".into(), 1),
+            (day(1), "Not Started".into(), 4),
+            (day(2), "Done".into(), 3),
+            (day(2), "In Progress".into(), 2),
+        ]);
+        assert_eq!(
+            flow,
+            CumulativeFlow {
+                dates: vec![day(1), day(2)],
+                series: vec![
+                    CumulativeFlowSeries {
+                        status: "Done".into(),
+                        counts: vec![1, 3],
+                    },
+                    CumulativeFlowSeries {
+                        status: "In Progress".into(),
+                        counts: vec![0, 2],
+                    },
+                    CumulativeFlowSeries {
+                        status: "Not Started".into(),
+                        counts: vec![4, 0],
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn leaf_progress_counts_leaves() {
+        let mut graph = test_utils::graph([
+            test_utils::task("epic", "epic", &["1", "2", "3"]),
+            Task {
+                status: Some("Done".to_string()),
+                ..test_utils::task("1", "1", &[])
+            },
+            test_utils::task("2", "2", &["4", "5"]),
+            Task {
+                status: Some("In Progress".to_string()),
+                ..test_utils::task("3", "3", &[])
+            },
+            test_utils::task("4", "4", &[]),
+            test_utils::task("5", "5", &[]),
+        ]);
+        graph.get_mut("5").unwrap().archived = Some(true);
+        assert_eq!(
+            leaf_progress(&graph, &[], &["epic".to_string()]),
+            TaskProgress { done: 1, total: 3 }
+        );
+        assert_eq!(
+            leaf_progress(&graph, &[], &["1".to_string(), "4".to_string()]),
+            TaskProgress { done: 1, total: 2 }
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        google::User,
+        model::{Attachment, ProjectId},
+        not_found_error, verify_project_access,
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS},
+    response::{IntoResponse as _, Response},
+    routing::get,
+};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+pub(crate) mod scanner;
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route(
+            "/{project_id}/tasks/{task_id}/attachments",
+            get(list_attachments_handler),
+        )
+        .route(
+            "/{project_id}/attachments/{attachment_id}",
+            get(download_attachment_handler),
+        )
+}
+
+/// Maximum size of a single attachment in bytes.
+pub(crate) const MAX_ATTACHMENT_SIZE: usize = 10 * 1024 * 1024;
+const MAX_FILENAME_LEN: usize = 255;
+
+#[tracing::instrument(skip(user, pool))]
+async fn list_attachments_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, task_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<Vec<Attachment>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let attachments: Vec<Attachment> = sqlx::query_as(
+        "
+        SELECT id, task_id, filename, content_type, size, uploader, scan_status, create_time
+        FROM task_attachments
+        WHERE project_id = $1 AND task_id = $2
+        ORDER BY create_time",
+    )
+    .bind(&project_id)
+    .bind(&task_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list attachments")?;
+    Ok(Json(attachments))
+}
+
+/// Serves an attachment as a download, never inline, so that uploaded HTML
+/// can't run in our origin. Attachments awaiting a malware scan, or found
+/// infected, aren't served.
+#[tracing::instrument(skip(user, pool))]
+async fn download_attachment_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, attachment_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Response> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let attachment: Option<(String, String, String, Vec<u8>)> = sqlx::query_as(
+        "
+        SELECT filename, content_type, scan_status, content
+        FROM task_attachments
+        WHERE project_id = $1 AND id = $2",
+    )
+    .bind(&project_id)
+    .bind(&attachment_id)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch attachment")?;
+    let Some((filename, content_type, scan_status, content)) = attachment else {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Attachment {attachment_id} not found"),
+        ));
+    };
+    match scan_status.as_str() {
+        "pending" => {
+            return Err(bad_request_error(
+                "ATTACHMENT_PENDING_SCAN",
+                "Attachment is still being scanned for malware",
+            ));
+        }
+        "quarantined" => {
+            return Err(bad_request_error(
+                "ATTACHMENT_QUARANTINED",
+                "Attachment was quarantined as malware",
+            ));
+        }
+        _ => {}
+    }
+    Ok((
+        [
+            (CONTENT_TYPE, content_type),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", sanitize_filename(&filename)),
+            ),
+            (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        content,
+    )
+        .into_response())
+}
+
+/// Stores `content` as an attachment of the given task, returning its id.
+/// When scanning is enabled, the attachment awaits a scan scheduled with
+/// `scanner::enqueue_scan` once the transaction commits.
+pub(crate) async fn insert_attachment<'c, E: PgExecutor<'c>>(
+    executor: E,
+    project_id: &ProjectId,
+    task_id: &str,
+    uploader: &str,
+    filename: &str,
+    content_type: &str,
+    content: &[u8],
+) -> Result<String> {
+    let id = BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4());
+    sqlx::query(
+        "
+        INSERT INTO task_attachments (id, project_id, task_id, filename, content_type, size, content, uploader, scan_status)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+    )
+    .bind(&id)
+    .bind(project_id)
+    .bind(task_id)
+    .bind(filename.chars().take(MAX_FILENAME_LEN).collect::<String>())
+    .bind(content_type.chars().take(MAX_FILENAME_LEN).collect::<String>())
+    .bind(content.len() as i64)
+    .bind(content)
+    .bind(uploader)
+    .bind(if scanner::is_enabled() {
+        "pending"
+    } else {
+        "unscanned"
+    })
+    .execute(executor)
+    .await
+    .context("Failed to insert attachment")?;
+    Ok(id)
+}
+
+/// Keeps the printable ASCII characters that are safe in a quoted header value.
+fn sanitize_filename(filename: &str) -> String {
+    filename
+        .chars()
+        .filter(|c| (c.is_ascii_graphic() || *c == ' ') && *c != '"' && *c != '\\')
+        .collect()
+}
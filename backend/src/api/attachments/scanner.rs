@@ -0,0 +1,281 @@
+//! Malware scanning of uploaded attachments, with clamd or an HTTP scanning
+//! API as configured for the deployment. Attachments are scanned by a job
+//! after upload and can't be downloaded until found clean. Infected ones are
+//! quarantined and their uploaders notified.
+
+use crate::{
+    api::{
+        collab::Collab,
+        inbox::Inbox,
+        jobs::{self, Job, JobHandler, NewJob},
+        model::{InboxKind, ProjectId},
+    },
+    postgres::PgPool,
+    secrets::{self, Secret},
+    settings::settings,
+};
+use anyhow::{Context as _, Result, anyhow};
+use async_trait::async_trait;
+use reqwest::header::CONTENT_TYPE;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _},
+    net::{TcpStream, UnixStream},
+};
+
+pub(crate) const SCAN_JOB: &str = "scan_attachments";
+/// Attachments loaded at once. Each may be up to MAX_ATTACHMENT_SIZE.
+const BATCH_SIZE: i64 = 5;
+const CHUNK_LEN: usize = 64 * 1024;
+const SCAN_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, PartialEq, Eq)]
+enum Verdict {
+    Clean,
+    /// Infected, with the name of the signature that matched.
+    Infected(String),
+}
+
+enum Scanner {
+    Clamd(String),
+    Api { url: String, token: Secret<String> },
+}
+
+pub(super) fn is_enabled() -> bool {
+    let scanning = &settings().attachment_scanning;
+    scanning.clamd_url.is_some() || scanning.api_url.is_some()
+}
+
+/// Schedules a scan of pending attachments, if scanning is enabled.
+pub(crate) async fn enqueue_scan(pool: &PgPool) -> Result<()> {
+    if is_enabled() {
+        jobs::enqueue(pool, NewJob::new(SCAN_JOB, &())?.key(SCAN_JOB)).await?;
+    }
+    Ok(())
+}
+
+/// Scans pending attachments until none are left.
+pub(crate) struct AttachmentScanner {
+    pub(crate) pool: &'static PgPool,
+    pub(crate) collab: Collab,
+}
+
+#[async_trait]
+impl JobHandler for AttachmentScanner {
+    fn kind(&self) -> &'static str {
+        SCAN_JOB
+    }
+
+    fn max_attempts(&self) -> i32 {
+        10
+    }
+
+    async fn run(&self, _job: &Job) -> Result<()> {
+        let Some(scanner) = Scanner::from_settings()? else {
+            return Ok(());
+        };
+        loop {
+            let pending: Vec<(String, ProjectId, String, String, String, Vec<u8>)> =
+                sqlx::query_as(
+                    "
+                    SELECT id, project_id, task_id, filename, uploader, content
+                    FROM task_attachments
+                    WHERE scan_status = 'pending'
+                    ORDER BY create_time
+                    LIMIT $1",
+                )
+                .bind(BATCH_SIZE)
+                .fetch_all(self.pool)
+                .await
+                .context("Failed to list attachments pending scan")?;
+            if pending.is_empty() {
+                return Ok(());
+            }
+            for (id, project_id, task_id, filename, uploader, content) in pending {
+                let verdict = scanner.scan(&content).await?;
+                self.record(&id, &verdict).await?;
+                if let Verdict::Infected(signature) = verdict {
+                    tracing::warn!("Quarantined attachment {id} infected with {signature}");
+                    metrics::counter!("attachments_quarantined_total").increment(1);
+                    self.notify(&project_id, &task_id, &filename, &uploader, &signature)
+                        .await?;
+                }
+            }
+        }
+    }
+}
+
+impl AttachmentScanner {
+    async fn record(&self, id: &str, verdict: &Verdict) -> Result<()> {
+        let (status, detail) = match verdict {
+            Verdict::Clean => ("clean", None),
+            Verdict::Infected(signature) => ("quarantined", Some(signature)),
+        };
+        sqlx::query(
+            "
+            UPDATE task_attachments
+            SET scan_status = $2, scan_detail = $3
+            WHERE id = $1",
+        )
+        .bind(id)
+        .bind(status)
+        .bind(detail)
+        .execute(self.pool)
+        .await
+        .context("Failed to record attachment scan")?;
+        Ok(())
+    }
+
+    /// Lets the uploader know, if they're a user rather than e.g. an
+    /// outside email sender.
+    async fn notify(
+        &self,
+        project_id: &ProjectId,
+        task_id: &str,
+        filename: &str,
+        uploader: &str,
+        signature: &str,
+    ) -> Result<()> {
+        let (is_user,): (bool,) =
+            sqlx::query_as("SELECT EXISTS(SELECT 1 FROM users WHERE email = $1)")
+                .bind(uploader)
+                .fetch_one(self.pool)
+                .await
+                .context("Failed to check uploader")?;
+        if !is_user {
+            return Ok(());
+        }
+        Inbox::new(self.pool, self.collab.messenger())
+            .deliver(
+                uploader,
+                InboxKind::AttachmentQuarantined,
+                Some(project_id),
+                Some(task_id),
+                None,
+                &format!("Your attachment {filename} was quarantined: {signature} detected"),
+            )
+            .await
+    }
+}
+
+impl Scanner {
+    fn from_settings() -> Result<Option<Scanner>> {
+        let scanning = &settings().attachment_scanning;
+        if let Some(url) = &scanning.clamd_url {
+            return Ok(Some(Scanner::Clamd(url.clone())));
+        }
+        if let Some(url) = &scanning.api_url {
+            return Ok(Some(Scanner::Api {
+                url: url.clone(),
+                token: secrets::read_secret("attachment_scanner/token")?,
+            }));
+        }
+        Ok(None)
+    }
+
+    async fn scan(&self, content: &[u8]) -> Result<Verdict> {
+        let scan = async {
+            match self {
+                Scanner::Clamd(url) => scan_clamd(url, content).await,
+                Scanner::Api { url, token } => scan_api(url, token, content).await,
+            }
+        };
+        tokio::time::timeout(SCAN_TIMEOUT, scan)
+            .await
+            .map_err(|_| anyhow!("Timed out scanning attachment"))?
+    }
+}
+
+/// Streams the content to clamd with INSTREAM.
+/// See https://docs.clamav.net/manual/Usage/Scanning.html#clamd
+async fn scan_clamd(url: &str, content: &[u8]) -> Result<Verdict> {
+    let reply = if let Some(path) = url.strip_prefix("unix://") {
+        instream(UnixStream::connect(path).await?, content).await?
+    } else if let Some(addr) = url.strip_prefix("tcp://") {
+        instream(TcpStream::connect(addr).await?, content).await?
+    } else {
+        return Err(anyhow!("Unsupported clamd url {url}"));
+    };
+    parse_clamd_reply(&reply)
+}
+
+async fn instream<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    content: &[u8],
+) -> Result<String> {
+    stream.write_all(b"zINSTREAM\0").await?;
+    for chunk in content.chunks(CHUNK_LEN) {
+        stream
+            .write_all(&(chunk.len() as u32).to_be_bytes())
+            .await?;
+        stream.write_all(chunk).await?;
+    }
+    stream.write_all(&0u32.to_be_bytes()).await?;
+    let mut reply = Vec::new();
+    stream
+        .read_to_end(&mut reply)
+        .await
+        .context("Failed to read clamd reply")?;
+    Ok(String::from_utf8_lossy(&reply)
+        .trim_end_matches('\0')
+        .trim()
+        .to_string())
+}
+
+fn parse_clamd_reply(reply: &str) -> Result<Verdict> {
+    let result = reply.strip_prefix("stream:").unwrap_or(reply).trim();
+    if result == "OK" {
+        return Ok(Verdict::Clean);
+    }
+    if let Some(signature) = result.strip_suffix(" FOUND") {
+        return Ok(Verdict::Infected(signature.trim().to_string()));
+    }
+    Err(anyhow!("clamd failed to scan: {reply}"))
+}
+
+#[derive(serde::Deserialize)]
+struct ApiVerdict {
+    infected: bool,
+    #[serde(default)]
+    signature: Option<String>,
+}
+
+/// The URL is deployment config rather than user input, so unlike fetches
+/// through `outbound` it may well be on the internal network.
+async fn scan_api(url: &str, token: &Secret<String>, content: &[u8]) -> Result<Verdict> {
+    let verdict: ApiVerdict = reqwest::Client::new()
+        .post(url)
+        .bearer_auth(token.data.trim())
+        .header(CONTENT_TYPE, "application/octet-stream")
+        .body(content.to_vec())
+        .send()
+        .await
+        .context("Failed to reach attachment scanner")?
+        .error_for_status()?
+        .json()
+        .await
+        .context("Invalid attachment scanner response")?;
+    Ok(match verdict {
+        ApiVerdict {
+            infected: false, ..
+        } => Verdict::Clean,
+        ApiVerdict { signature, .. } => {
+            Verdict::Infected(signature.unwrap_or_else(|| "malware".to_string()))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_clamd_reply_reads_verdicts() {
+        assert_eq!(parse_clamd_reply("stream: OK").unwrap(), Verdict::Clean);
+        assert_eq!(
+            parse_clamd_reply("stream: Eicar-Test-Signature FOUND").unwrap(),
+            Verdict::Infected("Eicar-Test-Signature".to_string())
+        );
+        assert!(parse_clamd_reply("INSTREAM size limit exceeded. ERROR").is_err());
+    }
+}
@@ -1,12 +1,15 @@
-use crate::api::{ApiResult, billing::update_user_subscription_end_time};
+use crate::{
+    api::google::User,
+    api::{ApiResult, billing::update_user_subscription_end_time, step_up},
+    postgres::PgPool,
+};
 use anyhow::Context as _;
 use axum::{Extension, Router, routing::post};
-use sqlx::PgPool;
-
-use crate::api::google::User;
 
 pub(super) fn router() -> Router {
-    Router::new().route("/login", post(login_handler))
+    Router::new()
+        .route("/login", post(login_handler))
+        .merge(step_up::router())
 }
 #[tracing::instrument(skip(user, pool))]
 async fn login_handler(
@@ -0,0 +1,323 @@
+use crate::{
+    api::{
+        ApiResult,
+        collab::{
+            Collab,
+            projects_state::DocBox,
+            txn_origin::{Actor, YOrigin},
+        },
+        google::User,
+        model::{ProjectId, WorkflowCategory},
+        verify_project_access,
+        yproxy::{YDocProxy, YTaskProxy, status_category},
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{Extension, Json, Router, extract::Path, routing::get};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    FromRow,
+    types::{
+        Json as SqlJson,
+        chrono::{DateTime, NaiveDate, Utc},
+    },
+};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use yrs::ReadTxn;
+
+/// How often projects are checked for a daily auto-archive run.
+const ARCHIVE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Maximum number of tasks archived per transaction, so the doc isn't held
+/// locked, and clients aren't sent one huge update, for large projects.
+const BATCH_SIZE: usize = 100;
+const MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+pub(super) fn router() -> Router {
+    Router::new().route("/{project_id}/auto-archive/runs", get(list_runs_handler))
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ArchivedTask {
+    pub(crate) task_id: String,
+    pub(crate) num: String,
+    pub(crate) name: String,
+}
+
+/// A report of the tasks archived in a project on a given day.
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AutoArchiveRun {
+    pub(crate) day: NaiveDate,
+    #[sqlx(json)]
+    pub(crate) archived: Vec<ArchivedTask>,
+    pub(crate) run_time: DateTime<Utc>,
+}
+
+/// Lists recent runs that archived at least one task.
+#[tracing::instrument(skip(user, pool))]
+async fn list_runs_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Vec<AutoArchiveRun>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let runs: Vec<AutoArchiveRun> = sqlx::query_as(
+        "
+        SELECT day, archived, run_time
+        FROM auto_archive_runs
+        WHERE project_id = $1 AND jsonb_array_length(archived) > 0
+        ORDER BY day DESC
+        LIMIT 90",
+    )
+    .bind(&project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list auto-archive runs")?;
+    Ok(Json(runs))
+}
+
+/// Once a day, archives tasks in projects with auto-archive configured that
+/// have been done for longer than the project's threshold.
+pub(crate) struct AutoArchiver {
+    pool: &'static PgPool,
+    collab: Collab,
+}
+
+impl AutoArchiver {
+    pub(crate) fn new(pool: &'static PgPool, collab: Collab) -> Self {
+        AutoArchiver { pool, collab }
+    }
+
+    pub(crate) fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ARCHIVE_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.archive_due_projects().await {
+                    tracing::warn!("Failed to auto-archive tasks: {e:?}");
+                }
+            }
+        })
+    }
+
+    async fn archive_due_projects(&self) -> Result<()> {
+        let today = Utc::now().date_naive();
+        let due: Vec<(String,)> = sqlx::query_as(
+            "
+            SELECT p.project_id
+            FROM projects p
+            WHERE p.deleted_on IS NULL
+            AND NOT EXISTS (
+                SELECT 1 FROM auto_archive_runs r
+                WHERE r.project_id = p.project_id AND r.day = $1
+            )",
+        )
+        .bind(today)
+        .fetch_all(self.pool)
+        .await
+        .context("Failed to list projects due for auto-archive")?;
+
+        for (project_id,) in due {
+            match self.archive_project(&project_id).await {
+                Ok(archived) => {
+                    if !archived.is_empty() {
+                        tracing::info!("Auto-archived {} tasks in {project_id}", archived.len());
+                    }
+                    self.record_run(&project_id, today, &archived).await?;
+                }
+                Err(e) => tracing::warn!("Failed to auto-archive tasks in {project_id}: {e:?}"),
+            }
+        }
+
+        // Runs that archived nothing only mark the project as checked for the day.
+        sqlx::query(
+            "
+            DELETE FROM auto_archive_runs
+            WHERE day < $1 AND jsonb_array_length(archived) = 0",
+        )
+        .bind(today)
+        .execute(self.pool)
+        .await
+        .context("Failed to prune empty auto-archive runs")?;
+        Ok(())
+    }
+
+    async fn record_run(
+        &self,
+        project_id: &ProjectId,
+        day: NaiveDate,
+        archived: &[ArchivedTask],
+    ) -> Result<()> {
+        sqlx::query(
+            "
+            INSERT INTO auto_archive_runs (project_id, day, archived)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (project_id, day)
+            DO UPDATE SET archived = auto_archive_runs.archived || EXCLUDED.archived",
+        )
+        .bind(project_id)
+        .bind(day)
+        .bind(SqlJson(archived))
+        .execute(self.pool)
+        .await
+        .context("Failed to record auto-archive run")?;
+        Ok(())
+    }
+
+    /// Archives due tasks in batches, releasing the doc between batches.
+    async fn archive_project(&self, project_id: &ProjectId) -> Result<Vec<ArchivedTask>> {
+        let now = Utc::now().timestamp_millis();
+        let client = self.collab.register_local_client(project_id).await?;
+        let mut archived = Vec::new();
+        loop {
+            let doc_box = client.project.doc_box.lock().await;
+            let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+            let batch = select_due(doc, &doc.transact(), now)?;
+            if batch.is_empty() {
+                break;
+            }
+
+            let full = batch.len() >= BATCH_SIZE;
+            let mut txn = doc.transact_mut_with(
+                YOrigin {
+                    who: "auto_archive".to_string(),
+                    id: format!("auto_archive_{project_id}"),
+                    actor: Actor::Server,
+                    ..Default::default()
+                }
+                .as_origin()?,
+            );
+            for (task, archived_task) in batch {
+                task.set_archived(&mut txn, Some(true));
+                archived.push(archived_task);
+            }
+            drop(txn);
+            drop(doc_box);
+            if !full {
+                break;
+            }
+        }
+        Ok(archived)
+    }
+}
+
+/// Returns up to `BATCH_SIZE` tasks due to be archived: unarchived tasks whose
+/// status has been in a done state for longer than the project's threshold
+/// and that don't have the excluded label.
+fn select_due<T: ReadTxn>(
+    doc: &YDocProxy,
+    txn: &T,
+    now: i64,
+) -> Result<Vec<(YTaskProxy, ArchivedTask)>> {
+    let Some(settings) = doc.config().get_auto_archive(txn)? else {
+        return Ok(Vec::new());
+    };
+    let workflow_states = doc.config().get_workflow_states(txn)?;
+    let cutoff = now - i64::from(settings.after_days) * MILLIS_PER_DAY;
+    let mut due = Vec::new();
+    for task in doc.tasks(txn)? {
+        if due.len() >= BATCH_SIZE {
+            break;
+        }
+        let id = task.get_id(txn)?;
+        if id == "root" || task.get_archived(txn)? == Some(true) {
+            continue;
+        }
+        let Some(status) = task.get_status(txn)? else {
+            continue;
+        };
+        if status_category(&workflow_states, &status) != Some(WorkflowCategory::Done) {
+            continue;
+        }
+        if task.get_status_time(txn)?.is_none_or(|t| t > cutoff) {
+            continue;
+        }
+        if let Some(label) = &settings.exclude_label {
+            if task.get_labels(txn)?.contains(label) {
+                continue;
+            }
+        }
+        let archived_task = ArchivedTask {
+            task_id: id,
+            num: task.get_num(txn)?,
+            name: task.get_name(txn)?,
+        };
+        due.push((task, archived_task));
+    }
+    Ok(due)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::model::{AutoArchiveSettings, Label, ProjectConfig, Task};
+
+    #[test]
+    fn select_due_skips_recent_excluded_and_unfinished_tasks() {
+        let doc = YDocProxy::new();
+        let mut txn = doc.transact_mut_with(
+            YOrigin {
+                who: "select_due_skips_recent_excluded_and_unfinished_tasks".to_string(),
+                id: "test".to_string(),
+                actor: Actor::Server,
+                ..Default::default()
+            }
+            .as_origin()
+            .unwrap(),
+        );
+        let now = 100 * MILLIS_PER_DAY;
+        for (id, status, days_ago, archived) in [
+            ("old", Some("Done"), 40, None),
+            ("recent", Some("Done"), 10, None),
+            ("open", Some("In Progress"), 40, None),
+            ("none", None, 40, None),
+            ("archived", Some("Done"), 40, Some(true)),
+            ("keep", Some("Done"), 40, None),
+        ] {
+            doc.set(
+                &mut txn,
+                &Task {
+                    id: id.to_string(),
+                    num: id.to_string(),
+                    name: id.to_string(),
+                    status: status.map(String::from),
+                    status_time: Some(now - days_ago * MILLIS_PER_DAY),
+                    archived,
+                    ..Default::default()
+                },
+            );
+        }
+        assert!(select_due(&doc, &txn, now).unwrap().is_empty());
+
+        doc.config()
+            .set_labels(
+                &mut txn,
+                &[Label {
+                    name: "keep".to_string(),
+                    color: "#1f883d".to_string(),
+                }],
+            )
+            .unwrap();
+        let config = ProjectConfig {
+            auto_archive: Some(AutoArchiveSettings {
+                after_days: 30,
+                exclude_label: Some("keep".to_string()),
+            }),
+            ..doc.config().get(&txn).unwrap()
+        };
+        doc.config().set(&mut txn, &config).unwrap();
+        doc.get(&txn, "keep")
+            .unwrap()
+            .set_labels(&mut txn, &["keep".to_string()])
+            .unwrap();
+
+        let due = select_due(&doc, &txn, now).unwrap();
+        assert_eq!(
+            due.into_iter().map(|(_, t)| t.task_id).collect::<Vec<_>>(),
+            vec!["old".to_string()]
+        );
+    }
+}
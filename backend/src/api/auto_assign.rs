@@ -0,0 +1,279 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{
+            Collab,
+            txn_origin::{Actor, YOrigin},
+        },
+        google::User,
+        model::{AutoAssign, AutoAssignMode, ProjectId},
+        not_found_error,
+        proposals::transact_or_propose,
+        verify_project_access,
+        yproxy::{YDocProxy, YTaskProxy},
+    },
+    postgres::{PgPool, list_project_users},
+};
+use anyhow::Result;
+use axum::{Extension, Json, Router, extract::Path, routing::put};
+use std::collections::HashMap;
+use yrs::TransactionMut;
+
+pub(super) fn router() -> Router {
+    Router::new().route(
+        "/{project_id}/tasks/{task_id}/autoAssign",
+        put(set_auto_assign_handler).delete(delete_auto_assign_handler),
+    )
+}
+
+#[tracing::instrument(skip(user, pool, collab))]
+async fn set_auto_assign_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path((project_id, task_id)): Path<(ProjectId, String)>,
+    Json(auto_assign): Json<AutoAssign>,
+) -> ApiResult<Json<AutoAssign>> {
+    verify_project_access(pool, &user, &project_id).await?;
+
+    let mut members = Vec::with_capacity(auto_assign.members.len());
+    for member in auto_assign.members {
+        let member = member.to_lowercase();
+        if !members.contains(&member) {
+            members.push(member);
+        }
+    }
+    if members.is_empty() {
+        return Err(bad_request_error(
+            "EMPTY_MEMBERS",
+            "Auto assignment requires at least one member",
+        ));
+    }
+    let project_users = list_project_users(pool, &project_id).await?;
+    if let Some(non_member) = members
+        .iter()
+        .find(|m| !project_users.iter().any(|u| &u.email == *m))
+    {
+        return Err(bad_request_error(
+            "NOT_A_MEMBER",
+            &format!("{non_member} is not a member of the project"),
+        ));
+    }
+
+    let auto_assign = AutoAssign {
+        mode: auto_assign.mode,
+        members,
+        next: 0,
+    };
+    update_auto_assign(
+        pool,
+        &collab,
+        &user,
+        &project_id,
+        &task_id,
+        Some(&auto_assign),
+    )
+    .await?;
+    Ok(Json(auto_assign))
+}
+
+#[tracing::instrument(skip(user, pool, collab))]
+async fn delete_auto_assign_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path((project_id, task_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<()>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    update_auto_assign(pool, &collab, &user, &project_id, &task_id, None).await?;
+    Ok(Json(()))
+}
+
+async fn update_auto_assign(
+    pool: &'static PgPool,
+    collab: &Collab,
+    user: &User,
+    project_id: &ProjectId,
+    task_id: &str,
+    auto_assign: Option<&AutoAssign>,
+) -> ApiResult<()> {
+    let origin = YOrigin {
+        who: "auto_assign".to_string(),
+        id: format!("auto_assign_{task_id}"),
+        actor: Actor::User(user.clone()),
+        ..Default::default()
+    };
+    transact_or_propose(pool, collab, project_id, &origin, |doc, txn| {
+        let Ok(task) = doc.get(txn, task_id) else {
+            return Err(not_found_error(
+                "NOT_FOUND",
+                &format!("Task {task_id} not found"),
+            ));
+        };
+        task.set_auto_assign(txn, auto_assign)?;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+/// Assigns the given, newly added children of `parent` according to the parent's
+/// auto assignment configuration. Children that already have an assignee are left alone.
+/// Returns the assignments made as (task id, assignee) pairs.
+pub(crate) fn auto_assign_children(
+    txn: &mut TransactionMut,
+    doc: &YDocProxy,
+    parent: &YTaskProxy,
+    children: &[String],
+) -> Result<Vec<(String, String)>> {
+    let Some(mut auto_assign) = parent.get_auto_assign(txn)? else {
+        return Ok(vec![]);
+    };
+    if auto_assign.members.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut workloads = match auto_assign.mode {
+        AutoAssignMode::RoundRobin => HashMap::new(),
+        AutoAssignMode::LeastLoaded => doc.workloads(txn)?,
+    };
+    let mut assignments = vec![];
+    for child_id in children {
+        let Ok(child) = doc.get(txn, child_id) else {
+            continue;
+        };
+        if child.get_assignee(txn)?.is_some() {
+            continue;
+        }
+        let assignee = select_member(&mut auto_assign, &workloads);
+        child.set_assignee(txn, Some(&assignee));
+        *workloads.entry(assignee.clone()).or_insert(0) += child.get_estimate(txn)?.unwrap_or(1);
+        assignments.push((child_id.clone(), assignee));
+    }
+
+    if !assignments.is_empty() && auto_assign.mode == AutoAssignMode::RoundRobin {
+        parent.set_auto_assign(txn, Some(&auto_assign))?;
+    }
+    Ok(assignments)
+}
+
+/// Picks the next member to assign. Round robin advances `next` while
+/// least loaded picks the member with the least work, breaking ties by member order.
+fn select_member(auto_assign: &mut AutoAssign, workloads: &HashMap<String, i64>) -> String {
+    match auto_assign.mode {
+        AutoAssignMode::RoundRobin => {
+            let index = (auto_assign.next % auto_assign.members.len() as u64) as usize;
+            auto_assign.next = auto_assign.next.wrapping_add(1);
+            auto_assign.members[index].clone()
+        }
+        AutoAssignMode::LeastLoaded => auto_assign
+            .members
+            .iter()
+            .enumerate()
+            .min_by_key(|(i, m)| (workloads.get(*m).copied().unwrap_or(0), *i))
+            .map(|(_, m)| m.clone())
+            .expect("members is not empty"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auto_assign(mode: AutoAssignMode) -> AutoAssign {
+        AutoAssign {
+            mode,
+            members: vec![
+                "a@koso.app".into(),
+                "b@koso.app".into(),
+                "c@koso.app".into(),
+            ],
+            next: 0,
+        }
+    }
+
+    #[test]
+    fn select_member_round_robin_cycles() {
+        let mut config = auto_assign(AutoAssignMode::RoundRobin);
+        let selected: Vec<String> = (0..4)
+            .map(|_| select_member(&mut config, &HashMap::new()))
+            .collect();
+        assert_eq!(
+            selected,
+            vec!["a@koso.app", "b@koso.app", "c@koso.app", "a@koso.app"]
+        );
+        assert_eq!(config.next, 4);
+    }
+
+    #[test]
+    fn select_member_least_loaded_picks_minimum() {
+        let mut config = auto_assign(AutoAssignMode::LeastLoaded);
+        let workloads = HashMap::from([
+            ("a@koso.app".to_string(), 5),
+            ("b@koso.app".to_string(), 2),
+            ("c@koso.app".to_string(), 2),
+        ]);
+        assert_eq!(select_member(&mut config, &workloads), "b@koso.app");
+
+        let workloads = HashMap::from([("a@koso.app".to_string(), 1)]);
+        assert_eq!(select_member(&mut config, &workloads), "b@koso.app");
+    }
+
+    #[test]
+    fn auto_assign_children_skips_assigned() {
+        let doc = YDocProxy::new();
+        let mut txn = doc.transact_mut_with(
+            YOrigin {
+                who: "auto_assign_children_skips_assigned".to_string(),
+                id: "test".to_string(),
+                actor: Actor::Server,
+                ..Default::default()
+            }
+            .as_origin()
+            .unwrap(),
+        );
+        let parent = doc.set(
+            &mut txn,
+            &crate::api::model::Task {
+                id: "p".into(),
+                num: "1".into(),
+                children: vec!["c1".into(), "c2".into(), "c3".into()],
+                ..Default::default()
+            },
+        );
+        parent
+            .set_auto_assign(&mut txn, Some(&auto_assign(AutoAssignMode::RoundRobin)))
+            .unwrap();
+        for (id, assignee) in [("c1", None), ("c2", Some("z@koso.app")), ("c3", None)] {
+            doc.set(
+                &mut txn,
+                &crate::api::model::Task {
+                    id: id.into(),
+                    num: id.into(),
+                    assignee: assignee.map(String::from),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let assignments = auto_assign_children(
+            &mut txn,
+            &doc,
+            &parent,
+            &["c1".into(), "c2".into(), "c3".into()],
+        )
+        .unwrap();
+        assert_eq!(
+            assignments,
+            vec![
+                ("c1".to_string(), "a@koso.app".to_string()),
+                ("c3".to_string(), "b@koso.app".to_string())
+            ]
+        );
+        assert_eq!(
+            doc.get(&txn, "c2").unwrap().get_assignee(&txn).unwrap(),
+            Some("z@koso.app".to_string())
+        );
+        assert_eq!(parent.get_auto_assign(&txn).unwrap().unwrap().next, 2);
+    }
+}
@@ -0,0 +1,269 @@
+//! Away statuses, e.g. vacations. While a user is away, assignments to them
+//! notify their delegate and the project workload shows them as unavailable.
+
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{Collab, projects_state::DocBox},
+        google::User,
+        model::{EstimateUnit, ProjectId},
+        verify_project_access,
+    },
+    postgres::{PgPool, list_project_users},
+};
+use anyhow::{Context as _, Result};
+use axum::{Extension, Json, Router, extract::Path, routing::get};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    FromRow,
+    types::chrono::{DateTime, Utc},
+};
+use std::collections::HashMap;
+
+/// Away windows may be set at most this far ahead, and last at most this long.
+const MAX_DAYS: i64 = 366;
+
+/// Routes for the user's own away status, under /profile.
+pub(super) fn router() -> Router {
+    Router::new().route(
+        "/away",
+        get(get_away_handler)
+            .put(set_away_handler)
+            .delete(delete_away_handler),
+    )
+}
+
+/// Routes for project workloads, under /projects.
+pub(super) fn workload_router() -> Router {
+    Router::new().route("/{project_id}/workload", get(get_workload_handler))
+}
+
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AwayStatus {
+    #[serde(skip_deserializing)]
+    pub(crate) email: String,
+    pub(crate) start_time: DateTime<Utc>,
+    pub(crate) end_time: DateTime<Utc>,
+    pub(crate) delegate: Option<String>,
+    pub(crate) message: Option<String>,
+}
+
+impl AwayStatus {
+    fn is_away(&self, now: DateTime<Utc>) -> bool {
+        self.start_time <= now && now < self.end_time
+    }
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn get_away_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<Option<AwayStatus>>> {
+    let status: Option<AwayStatus> = sqlx::query_as(
+        "
+        SELECT email, start_time, end_time, delegate, message
+        FROM away_statuses
+        WHERE email = $1 AND end_time > NOW()",
+    )
+    .bind(&user.email)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to get away status")?;
+    Ok(Json(status))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn set_away_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Json(status): Json<AwayStatus>,
+) -> ApiResult<Json<AwayStatus>> {
+    let now = Utc::now();
+    if status.end_time <= status.start_time || status.end_time <= now {
+        return Err(bad_request_error(
+            "INVALID_WINDOW",
+            "The away window must end after it starts, and in the future",
+        ));
+    }
+    let max = chrono::Duration::days(MAX_DAYS);
+    if status.start_time - now > max || status.end_time - status.start_time > max {
+        return Err(bad_request_error(
+            "INVALID_WINDOW",
+            &format!("Away windows are limited to {MAX_DAYS} days"),
+        ));
+    }
+    let delegate = status.delegate.map(|d| d.trim().to_lowercase());
+    let delegate = delegate.filter(|d| !d.is_empty());
+    if let Some(delegate) = &delegate {
+        if *delegate == user.email {
+            return Err(bad_request_error(
+                "INVALID_DELEGATE",
+                "Users can't delegate to themselves",
+            ));
+        }
+        let exists: Option<(String,)> = sqlx::query_as("SELECT email FROM users WHERE email = $1")
+            .bind(delegate)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to look up delegate")?;
+        if exists.is_none() {
+            return Err(bad_request_error(
+                "INVALID_DELEGATE",
+                &format!("No user {delegate}"),
+            ));
+        }
+    }
+
+    let status: AwayStatus = sqlx::query_as(
+        "
+        INSERT INTO away_statuses (email, start_time, end_time, delegate, message)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (email)
+        DO UPDATE SET
+          start_time = EXCLUDED.start_time,
+          end_time = EXCLUDED.end_time,
+          delegate = EXCLUDED.delegate,
+          message = EXCLUDED.message
+        RETURNING email, start_time, end_time, delegate, message",
+    )
+    .bind(&user.email)
+    .bind(status.start_time)
+    .bind(status.end_time)
+    .bind(&delegate)
+    .bind(&status.message)
+    .fetch_one(pool)
+    .await
+    .context("Failed to set away status")?;
+    Ok(Json(status))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_away_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<()>> {
+    sqlx::query("DELETE FROM away_statuses WHERE email = $1")
+        .bind(&user.email)
+        .execute(pool)
+        .await
+        .context("Failed to delete away status")?;
+    Ok(Json(()))
+}
+
+/// Returns the user's away status if they're away now.
+pub(crate) async fn away_now(pool: &PgPool, email: &str) -> Result<Option<AwayStatus>> {
+    let status: Option<AwayStatus> = sqlx::query_as(
+        "
+        SELECT email, start_time, end_time, delegate, message
+        FROM away_statuses
+        WHERE email = $1",
+    )
+    .bind(email)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to get away status")?;
+    Ok(status.filter(|s| s.is_away(Utc::now())))
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Workload {
+    /// The unit workloads and capacity are in.
+    unit: EstimateUnit,
+    /// How much work each member can take on per iteration, if configured.
+    capacity: Option<i64>,
+    members: Vec<MemberWorkload>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct MemberWorkload {
+    email: String,
+    /// The sum of estimates of the member's outstanding tasks.
+    workload: i64,
+    /// The workload in points, comparable across projects.
+    points: f64,
+    /// False while the member is away.
+    available: bool,
+    /// The member's current or upcoming away window, if any.
+    away: Option<AwayStatus>,
+}
+
+/// Returns each member's outstanding work and availability.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn get_workload_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Workload>> {
+    verify_project_access(pool, &user, &project_id).await?;
+
+    let (workloads, estimates) = {
+        let client = collab.register_local_client(&project_id).await?;
+        let doc_box = client.project.doc_box.lock().await;
+        let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+        let txn = doc.transact();
+        (
+            doc.workloads(&txn)?,
+            doc.config().get_estimates(&txn)?.unwrap_or_default(),
+        )
+    };
+    let members = list_project_users(pool, &project_id).await?;
+    let emails: Vec<String> = members.iter().map(|m| m.email.clone()).collect();
+    let statuses: Vec<AwayStatus> = sqlx::query_as(
+        "
+        SELECT email, start_time, end_time, delegate, message
+        FROM away_statuses
+        WHERE email = ANY($1) AND end_time > NOW()",
+    )
+    .bind(&emails)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list away statuses")?;
+    let mut statuses: HashMap<String, AwayStatus> =
+        statuses.into_iter().map(|s| (s.email.clone(), s)).collect();
+
+    let now = Utc::now();
+    let mut members: Vec<MemberWorkload> = emails
+        .into_iter()
+        .map(|email| {
+            let away = statuses.remove(&email);
+            let workload = workloads.get(&email).copied().unwrap_or(0);
+            MemberWorkload {
+                workload,
+                points: estimates.to_points(workload),
+                available: !away.as_ref().is_some_and(|a| a.is_away(now)),
+                away,
+                email,
+            }
+        })
+        .collect();
+    members.sort_by(|a, b| a.email.cmp(&b.email));
+    Ok(Json(Workload {
+        unit: estimates.unit,
+        capacity: estimates.capacity,
+        members,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn is_away_within_window() {
+        let now = Utc::now();
+        let status = AwayStatus {
+            email: "a@koso.app".to_string(),
+            start_time: now - chrono::Duration::hours(1),
+            end_time: now + chrono::Duration::hours(1),
+            delegate: None,
+            message: None,
+        };
+        assert!(status.is_away(now));
+        assert!(!status.is_away(now + chrono::Duration::hours(1)));
+        assert!(!status.is_away(now - chrono::Duration::hours(2)));
+    }
+}
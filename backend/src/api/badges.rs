@@ -0,0 +1,192 @@
+//! SVG status badges for READMEs, e.g.
+//! `/api/badges/projects/{project_id}.svg?token=..`. READMEs can't
+//! authenticate, so badges are authorized by an embed token of the project.
+//! They're generated from `task_projections` rather than the doc.
+
+use crate::{
+    api::{
+        ApiResult, bad_request_error, embed, model::WorkflowCategory, not_found_error,
+        reports::escape_html, yproxy::status_category,
+    },
+    postgres::PgPool,
+};
+use anyhow::Context as _;
+use axum::{
+    Extension, Router,
+    extract::{Path, Query},
+    http::header::{CACHE_CONTROL, CONTENT_TYPE},
+    response::{IntoResponse as _, Response},
+    routing::get,
+};
+use serde::Deserialize;
+
+const BADGE_CACHE_CONTROL: &str = "public, max-age=120";
+const LABEL_COLOR: &str = "#555";
+const GREEN: &str = "#4c1";
+const BLUE: &str = "#007ec6";
+const RED: &str = "#e05d44";
+const GREY: &str = "#9f9f9f";
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/projects/{file}", get(project_badge_handler))
+        .route("/tasks/{file}", get(task_badge_handler))
+}
+
+#[derive(Deserialize, Debug)]
+struct BadgeQuery {
+    token: String,
+}
+
+/// Shows the number of open and done tasks in the project.
+#[tracing::instrument(skip(pool, query))]
+async fn project_badge_handler(
+    Extension(pool): Extension<&'static PgPool>,
+    Path(file): Path<String>,
+    Query(query): Query<BadgeQuery>,
+) -> ApiResult<Response> {
+    let project_id = svg_stem(&file)?;
+    // Tokens limited to a subtree can't reveal the whole project.
+    match embed::lookup_token(pool, &query.token).await? {
+        Some((token_project_id, None)) if token_project_id == project_id => {}
+        _ => return Err(not_found_error("NOT_FOUND", "No such badge")),
+    }
+    let statuses: Vec<(Option<String>, i64)> = sqlx::query_as(
+        "
+        SELECT status, COUNT(*)
+        FROM task_projections
+        WHERE project_id = $1 AND NOT archived AND status IS NOT NULL
+        GROUP BY status",
+    )
+    .bind(project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to count task statuses")?;
+    let (open, done) = count(&statuses);
+    let color = if open == 0 && done > 0 { GREEN } else { BLUE };
+    Ok(svg_response(render_svg(
+        "tasks",
+        &format!("{open} open, {done} done"),
+        color,
+    )))
+}
+
+/// Shows the task's status.
+#[tracing::instrument(skip(pool, query))]
+async fn task_badge_handler(
+    Extension(pool): Extension<&'static PgPool>,
+    Path(file): Path<String>,
+    Query(query): Query<BadgeQuery>,
+) -> ApiResult<Response> {
+    let task_id = svg_stem(&file)?;
+    let Some((project_id, root_task_id)) = embed::lookup_token(pool, &query.token).await? else {
+        return Err(not_found_error("NOT_FOUND", "No such badge"));
+    };
+    // Projections don't record parents, so subtree tokens only cover their root.
+    if root_task_id.is_some_and(|root| root != task_id) {
+        return Err(not_found_error("NOT_FOUND", "No such badge"));
+    }
+    let task: Option<(String, Option<String>, bool)> = sqlx::query_as(
+        "
+        SELECT num, status, archived
+        FROM task_projections
+        WHERE project_id = $1 AND task_id = $2",
+    )
+    .bind(&project_id)
+    .bind(task_id)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch task projection")?;
+    let Some((num, status, archived)) = task else {
+        return Err(not_found_error("NOT_FOUND", "No such badge"));
+    };
+    let (message, color) = match (archived, status.as_deref()) {
+        (true, _) => ("Archived", GREY),
+        (false, None) => ("No status", GREY),
+        (false, Some(status)) => (status, status_color(status)),
+    };
+    Ok(svg_response(render_svg(&format!("#{num}"), message, color)))
+}
+
+fn svg_stem(file: &str) -> ApiResult<&str> {
+    match file.strip_suffix(".svg") {
+        Some(stem) if !stem.is_empty() => Ok(stem),
+        _ => Err(bad_request_error("NOT_SVG", "Badges end in .svg")),
+    }
+}
+
+/// Counts open and done tasks. Statuses are categorized with the built-in
+/// statuses, as custom workflow states live in the doc, and statuses outside
+/// them count as open.
+fn count(statuses: &[(Option<String>, i64)]) -> (i64, i64) {
+    statuses.iter().fold((0, 0), |(open, done), (status, n)| {
+        match status.as_deref().and_then(|s| status_category(&[], s)) {
+            Some(WorkflowCategory::Done) => (open, done + n),
+            _ => (open + n, done),
+        }
+    })
+}
+
+fn status_color(status: &str) -> &'static str {
+    match status_category(&[], status) {
+        Some(WorkflowCategory::Done) => GREEN,
+        Some(WorkflowCategory::InProgress) => BLUE,
+        Some(WorkflowCategory::Blocked) => RED,
+        Some(WorkflowCategory::NotStarted) | None => GREY,
+    }
+}
+
+fn svg_response(svg: String) -> Response {
+    (
+        [
+            (CONTENT_TYPE, "image/svg+xml; charset=utf-8"),
+            (CACHE_CONTROL, BADGE_CACHE_CONTROL),
+        ],
+        svg,
+    )
+        .into_response()
+}
+
+/// Renders a flat, shields.io style badge. Text widths are estimated, as
+/// fonts aren't available to measure them.
+fn render_svg(label: &str, message: &str, color: &str) -> String {
+    let width = |text: &str| 10 + 7 * text.chars().count();
+    let (label_width, message_width) = (width(label), width(message));
+    let total = label_width + message_width;
+    let (label, message) = (escape_html(label), escape_html(message));
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{total}\" height=\"20\" role=\"img\" aria-label=\"{label}: {message}\">\
+<title>{label}: {message}</title>\
+<rect width=\"{label_width}\" height=\"20\" fill=\"{LABEL_COLOR}\"/>\
+<rect x=\"{label_width}\" width=\"{message_width}\" height=\"20\" fill=\"{color}\"/>\
+<g fill=\"#fff\" text-anchor=\"middle\" font-family=\"Verdana,Geneva,sans-serif\" font-size=\"11\">\
+<text x=\"{}\" y=\"14\">{label}</text>\
+<text x=\"{}\" y=\"14\">{message}</text>\
+</g></svg>",
+        label_width / 2,
+        label_width + message_width / 2,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn renders_badges() {
+        let statuses = [
+            (Some("Done".to_string()), 3),
+            (Some("In Progress".to_string()), 2),
+            (Some("Custom".to_string()), 1),
+        ];
+        assert_eq!(count(&statuses), (3, 3));
+
+        let svg = render_svg("#12", "<Done>", status_color("Done"));
+        assert!(svg.contains("width=\"83\""));
+        assert!(svg.contains(">&lt;Done&gt;</text>"));
+        assert!(svg.contains(GREEN));
+
+        assert_eq!(svg_stem("abc.svg").unwrap(), "abc");
+        assert!(svg_stem("abc.png").is_err());
+    }
+}
@@ -13,6 +13,7 @@ use crate::{
         google::{self, User},
         not_found_error,
     },
+    postgres::PgPool,
     secrets::{self},
     settings::settings,
 };
@@ -21,7 +22,7 @@ use axum::middleware;
 use axum::routing::put;
 use axum::{Extension, Json, Router, routing::post};
 use chrono::{DateTime, Utc};
-use sqlx::{PgConnection, PgPool, Postgres};
+use sqlx::{PgConnection, Postgres};
 use std::collections::HashMap;
 
 pub(super) fn router() -> Result<Router> {
@@ -435,6 +436,7 @@ mod webhook {
             billing::stripe::{KosoMetadata, StripeClient, Subscription},
             unauthorized_error,
         },
+        postgres::PgPool,
         secrets::Secret,
         settings::settings,
     };
@@ -449,7 +451,6 @@ mod webhook {
     use serde::{Deserialize, Serialize};
     use serde_json::value::RawValue;
     use sha2::Sha256;
-    use sqlx::PgPool;
     use std::collections::HashMap;
 
     #[derive(Clone)]
@@ -767,18 +768,22 @@ pub(crate) mod model {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::postgres::PgPool;
     use crate::{api::billing::webhook::handle_webhook, secrets::Secret};
     use axum::{body::Body, http::HeaderMap};
     use chrono::{DateTime, Utc};
-    use sqlx::PgPool;
 
     #[test_log::test(sqlx::test)]
-    async fn create_checkout_session(pool: PgPool) {
+    async fn create_checkout_session(pool: sqlx::PgPool) {
+        let pool = PgPool::from(pool);
         let user = User {
             email: "stripe-test@test.koso.app".to_string(),
             name: "IntegTesting DoNotDelete".to_string(),
             picture: "".to_string(),
             exp: 5,
+            iat: None,
+            auth_time: None,
+            nonce: None,
         };
         let client = StripeClient {
             client: reqwest::Client::new(),
@@ -800,7 +805,8 @@ mod tests {
     }
 
     #[test_log::test(sqlx::test)]
-    async fn create_portal_session(pool: PgPool) {
+    async fn create_portal_session(pool: sqlx::PgPool) {
+        let pool = PgPool::from(pool);
         sqlx::query(
             "
             INSERT INTO users (email, name, picture, subscription_end_time, github_user_id)
@@ -823,6 +829,9 @@ mod tests {
             name: "IntegTesting DoNotDelete".to_string(),
             picture: "".to_string(),
             exp: 5,
+            iat: None,
+            auth_time: None,
+            nonce: None,
         };
         let client = StripeClient {
             client: reqwest::Client::new(),
@@ -843,7 +852,8 @@ mod tests {
     }
 
     #[test_log::test(sqlx::test)]
-    async fn update_subscription(pool: PgPool) {
+    async fn update_subscription(pool: sqlx::PgPool) {
+        let pool = PgPool::from(pool);
         sqlx::query(
             "
             INSERT INTO users (email, name, picture, subscription_end_time, github_user_id)
@@ -872,6 +882,9 @@ mod tests {
             name: "IntegTesting DoNotDelete".to_string(),
             picture: "".to_string(),
             exp: 5,
+            iat: None,
+            auth_time: None,
+            nonce: None,
         };
 
         // Remove user-1@test.koso.app
@@ -937,7 +950,8 @@ mod tests {
     }
 
     #[test_log::test(sqlx::test)]
-    async fn test_handle_webhook(pool: PgPool) {
+    async fn test_handle_webhook(pool: sqlx::PgPool) {
+        let pool = PgPool::from(pool);
         sqlx::query(
             "
             INSERT INTO users (email, name, picture, subscription_end_time, github_user_id)
@@ -961,6 +975,9 @@ mod tests {
             name: "IntegTesting DoNotDelete".to_string(),
             picture: "".to_string(),
             exp: 5,
+            iat: None,
+            auth_time: None,
+            nonce: None,
         };
         let webhook_secret = WebhookSecret(Secret {
             data: "something".as_bytes().to_vec(),
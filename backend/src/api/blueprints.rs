@@ -0,0 +1,696 @@
+//! Blueprints: starter structures, a task hierarchy and project configuration,
+//! that projects can be created from or have applied. Besides the built-in
+//! blueprints, organizations keep their own. An organization is a
+//! subscription: its owner and members share the owner's blueprints.
+//!
+//! Every update to a blueprint creates a new version. Projects remember the
+//! version they applied, so updates can be re-applied as diffs.
+
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{
+            Collab,
+            projects_state::DocBox,
+            txn_origin::{Actor, YOrigin},
+        },
+        google::User,
+        model::{ProjectConfig, ProjectId, Task},
+        not_found_error, unauthorized_error, verify_project_access, verify_project_admin,
+        yproxy::{YDocProxy, validate_config},
+    },
+    postgres::{PgPool, PgTransaction},
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    FromRow,
+    types::{
+        Json as SqlJson,
+        chrono::{DateTime, Utc},
+    },
+};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+use yrs::TransactionMut;
+
+pub(crate) mod apply;
+
+/// Blueprints may nest tasks at most this deep.
+const MAX_DEPTH: usize = 8;
+/// Blueprints may contain at most this many tasks.
+const MAX_TASKS: usize = 500;
+const MAX_NAME_LEN: usize = 100;
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route(
+            "/",
+            get(list_blueprints_handler).post(create_blueprint_handler),
+        )
+        .route(
+            "/{blueprint_id}",
+            get(get_blueprint_handler)
+                .put(update_blueprint_handler)
+                .delete(delete_blueprint_handler),
+        )
+        .route("/{blueprint_id}/versions", get(list_versions_handler))
+}
+
+/// Routes for applying blueprints to projects, under /projects.
+pub(super) fn projects_router() -> Router {
+    Router::new()
+        .route("/{project_id}/blueprints", get(list_applications_handler))
+        .route(
+            "/{project_id}/blueprints/{blueprint_id}/apply",
+            post(apply_blueprint_handler),
+        )
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BlueprintContent {
+    /// Tasks added under the root, in order.
+    #[serde(default)]
+    pub(crate) tasks: Vec<BlueprintTask>,
+    /// Replaces the project's configuration, e.g. workflow states, labels and
+    /// automation rules.
+    #[serde(default)]
+    pub(crate) config: Option<ProjectConfig>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BlueprintTask {
+    /// Identifies the task across versions of the blueprint. Assigned by the
+    /// server when absent; clients must preserve it when editing.
+    #[serde(default)]
+    pub(crate) key: String,
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) desc: Option<String>,
+    #[serde(default)]
+    pub(crate) kind: Option<String>,
+    /// In points, converted to the project's estimate unit when applied.
+    #[serde(default)]
+    pub(crate) estimate: Option<i64>,
+    #[serde(default)]
+    pub(crate) labels: Vec<String>,
+    #[serde(default)]
+    pub(crate) children: Vec<BlueprintTask>,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Blueprint {
+    pub(crate) id: String,
+    /// The owner of the subscription the blueprint belongs to, or None for
+    /// built-in blueprints.
+    pub(crate) org: Option<String>,
+    pub(crate) name: String,
+    pub(crate) description: String,
+    pub(crate) version: i32,
+    pub(crate) content: SqlJson<BlueprintContent>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UpsertBlueprint {
+    name: String,
+    #[serde(default)]
+    description: String,
+    content: BlueprintContent,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct BlueprintVersion {
+    version: i32,
+    author: String,
+    create_time: DateTime<Utc>,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Application {
+    blueprint_id: String,
+    version: i32,
+    /// The blueprint's current version, if it still exists.
+    latest_version: Option<i32>,
+    apply_time: DateTime<Utc>,
+}
+
+/// The version of a blueprint last applied to a project, and what it created.
+#[derive(FromRow, Debug)]
+struct PreviousApplication {
+    version: i32,
+    content: SqlJson<BlueprintContent>,
+    /// Ids of the tasks created, by blueprint task key.
+    task_ids: SqlJson<HashMap<String, String>>,
+}
+
+/// The built-in onboarding checklist, walking new teams through setting up.
+fn onboarding_checklist() -> Blueprint {
+    let task = |key: &str, name: &str, desc: &str| BlueprintTask {
+        key: key.to_string(),
+        name: name.to_string(),
+        desc: Some(desc.to_string()),
+        ..BlueprintTask::default()
+    };
+    Blueprint {
+        id: "builtin-onboarding".to_string(),
+        org: None,
+        name: "Onboarding checklist".to_string(),
+        description: "A checklist for getting your team started".to_string(),
+        version: 1,
+        content: SqlJson(BlueprintContent {
+            tasks: vec![BlueprintTask {
+                key: "get-started".to_string(),
+                name: "Get started with Koso".to_string(),
+                children: vec![
+                    task(
+                        "invite",
+                        "Invite your team",
+                        "Share the project with your teammates.",
+                    ),
+                    task(
+                        "first-goal",
+                        "Break down your first goal",
+                        "Add a task and indent subtasks beneath it.",
+                    ),
+                    task(
+                        "notifications",
+                        "Set up notifications",
+                        "Connect a notifier on your profile to hear about assignments.",
+                    ),
+                    task(
+                        "github",
+                        "Connect GitHub",
+                        "Link pull requests to tasks to track them automatically.",
+                    ),
+                ],
+                ..BlueprintTask::default()
+            }],
+            config: None,
+        }),
+    }
+}
+
+fn builtin() -> Vec<Blueprint> {
+    vec![onboarding_checklist()]
+}
+
+/// Returns the owner of the subscription the user belongs to, if any.
+pub(crate) async fn org_of(pool: &PgPool, email: &str) -> Result<Option<String>> {
+    let org: Option<(String,)> = sqlx::query_as(
+        "
+        SELECT email
+        FROM subscriptions
+        WHERE email = $1 OR $1 = ANY(member_emails)
+        ORDER BY email = $1 DESC, end_time DESC
+        LIMIT 1",
+    )
+    .bind(email)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up organization")?;
+    Ok(org.map(|(org,)| org))
+}
+
+/// Returns the user's organization, failing if they have none.
+async fn require_org(pool: &PgPool, user: &User) -> ApiResult<String> {
+    match org_of(pool, &user.email).await? {
+        Some(org) => Ok(org),
+        None => Err(unauthorized_error(
+            "Blueprints are shared within a subscription. Subscribe to create your own.",
+        )),
+    }
+}
+
+/// Lists the built-in blueprints and those of the user's organization.
+#[tracing::instrument(skip(user, pool))]
+async fn list_blueprints_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<Vec<Blueprint>>> {
+    let mut blueprints = builtin();
+    if let Some(org) = org_of(pool, &user.email).await? {
+        let org_blueprints: Vec<Blueprint> = sqlx::query_as(
+            "
+            SELECT id, org, name, description, version, content
+            FROM blueprints
+            WHERE org = $1
+            ORDER BY name",
+        )
+        .bind(&org)
+        .fetch_all(pool)
+        .await
+        .context("Failed to list blueprints")?;
+        blueprints.extend(org_blueprints);
+    }
+    Ok(Json(blueprints))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn get_blueprint_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(blueprint_id): Path<String>,
+) -> ApiResult<Json<Blueprint>> {
+    Ok(Json(find(pool, &user, &blueprint_id).await?))
+}
+
+/// Finds a blueprint available to the user.
+pub(crate) async fn find(pool: &PgPool, user: &User, id: &str) -> ApiResult<Blueprint> {
+    if let Some(blueprint) = builtin().into_iter().find(|b| b.id == id) {
+        return Ok(blueprint);
+    }
+    if let Some(org) = org_of(pool, &user.email).await? {
+        if let Some(blueprint) = find_in_org(pool, &org, id).await? {
+            return Ok(blueprint);
+        }
+    }
+    Err(not_found_error(
+        "NOT_FOUND",
+        &format!("Blueprint {id} not found"),
+    ))
+}
+
+async fn find_in_org(pool: &PgPool, org: &str, id: &str) -> Result<Option<Blueprint>> {
+    sqlx::query_as(
+        "
+        SELECT id, org, name, description, version, content
+        FROM blueprints
+        WHERE id = $1 AND org = $2",
+    )
+    .bind(id)
+    .bind(org)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to get blueprint")
+}
+
+/// Validates the blueprint, assigning keys to new tasks.
+fn prepare(blueprint: &mut UpsertBlueprint) -> ApiResult<()> {
+    let name = blueprint.name.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(bad_request_error(
+            "INVALID_NAME",
+            &format!("Blueprint names must be 1 to {MAX_NAME_LEN} characters"),
+        ));
+    }
+    blueprint.name = name.to_string();
+    assign_keys(&mut blueprint.content.tasks);
+    if let Err(e) = validate(&blueprint.content) {
+        return Err(bad_request_error("INVALID_BLUEPRINT", &e.to_string()));
+    }
+    Ok(())
+}
+
+fn assign_keys(tasks: &mut [BlueprintTask]) {
+    for task in tasks {
+        if task.key.is_empty() {
+            task.key = Uuid::new_v4().to_string();
+        }
+        assign_keys(&mut task.children);
+    }
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn create_blueprint_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Json(mut blueprint): Json<UpsertBlueprint>,
+) -> ApiResult<Json<Blueprint>> {
+    let org = require_org(pool, &user).await?;
+    prepare(&mut blueprint)?;
+
+    let id = Uuid::new_v4().to_string();
+    let mut txn = pool.begin().await?;
+    let created: Blueprint = sqlx::query_as(
+        "
+        INSERT INTO blueprints (id, org, name, description, version, content)
+        VALUES ($1, $2, $3, $4, 1, $5)
+        RETURNING id, org, name, description, version, content",
+    )
+    .bind(&id)
+    .bind(&org)
+    .bind(&blueprint.name)
+    .bind(&blueprint.description)
+    .bind(SqlJson(&blueprint.content))
+    .fetch_one(&mut *txn)
+    .await
+    .context("Failed to create blueprint")?;
+    insert_version(&mut txn, &created, &user).await?;
+    txn.commit().await?;
+    Ok(Json(created))
+}
+
+/// Replaces the blueprint's content, creating a new version.
+#[tracing::instrument(skip(user, pool))]
+async fn update_blueprint_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(blueprint_id): Path<String>,
+    Json(mut blueprint): Json<UpsertBlueprint>,
+) -> ApiResult<Json<Blueprint>> {
+    let org = require_org(pool, &user).await?;
+    prepare(&mut blueprint)?;
+
+    let mut txn = pool.begin().await?;
+    let updated: Option<Blueprint> = sqlx::query_as(
+        "
+        UPDATE blueprints
+        SET name = $3, description = $4, content = $5, version = version + 1, update_time = NOW()
+        WHERE id = $1 AND org = $2
+        RETURNING id, org, name, description, version, content",
+    )
+    .bind(&blueprint_id)
+    .bind(&org)
+    .bind(&blueprint.name)
+    .bind(&blueprint.description)
+    .bind(SqlJson(&blueprint.content))
+    .fetch_optional(&mut *txn)
+    .await
+    .context("Failed to update blueprint")?;
+    let Some(updated) = updated else {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Blueprint {blueprint_id} not found"),
+        ));
+    };
+    insert_version(&mut txn, &updated, &user).await?;
+    txn.commit().await?;
+    Ok(Json(updated))
+}
+
+async fn insert_version(
+    txn: &mut PgTransaction,
+    blueprint: &Blueprint,
+    author: &User,
+) -> Result<()> {
+    sqlx::query(
+        "
+        INSERT INTO blueprint_versions (blueprint_id, version, content, author)
+        VALUES ($1, $2, $3, $4)",
+    )
+    .bind(&blueprint.id)
+    .bind(blueprint.version)
+    .bind(&blueprint.content)
+    .bind(&author.email)
+    .execute(&mut **txn)
+    .await
+    .context("Failed to insert blueprint version")?;
+    Ok(())
+}
+
+/// Deletes the blueprint and its versions. Projects it was applied to keep
+/// their tasks and config.
+#[tracing::instrument(skip(user, pool))]
+async fn delete_blueprint_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(blueprint_id): Path<String>,
+) -> ApiResult<Json<()>> {
+    let org = require_org(pool, &user).await?;
+    let mut txn = pool.begin().await?;
+    let deleted = sqlx::query("DELETE FROM blueprints WHERE id = $1 AND org = $2")
+        .bind(&blueprint_id)
+        .bind(&org)
+        .execute(&mut *txn)
+        .await
+        .context("Failed to delete blueprint")?;
+    if deleted.rows_affected() == 0 {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Blueprint {blueprint_id} not found"),
+        ));
+    }
+    sqlx::query("DELETE FROM blueprint_versions WHERE blueprint_id = $1")
+        .bind(&blueprint_id)
+        .execute(&mut *txn)
+        .await
+        .context("Failed to delete blueprint versions")?;
+    sqlx::query("DELETE FROM blueprint_applications WHERE blueprint_id = $1")
+        .bind(&blueprint_id)
+        .execute(&mut *txn)
+        .await
+        .context("Failed to delete blueprint applications")?;
+    txn.commit().await?;
+    Ok(Json(()))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn list_versions_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(blueprint_id): Path<String>,
+) -> ApiResult<Json<Vec<BlueprintVersion>>> {
+    let org = require_org(pool, &user).await?;
+    if find_in_org(pool, &org, &blueprint_id).await?.is_none() {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Blueprint {blueprint_id} not found"),
+        ));
+    }
+    let versions: Vec<BlueprintVersion> = sqlx::query_as(
+        "
+        SELECT version, author, create_time
+        FROM blueprint_versions
+        WHERE blueprint_id = $1
+        ORDER BY version DESC",
+    )
+    .bind(&blueprint_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list blueprint versions")?;
+    Ok(Json(versions))
+}
+
+/// Lists the blueprints applied to the project, flagging those with newer
+/// versions to apply.
+#[tracing::instrument(skip(user, pool))]
+async fn list_applications_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Vec<Application>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let mut applications: Vec<Application> = sqlx::query_as(
+        "
+        SELECT a.blueprint_id, a.version, b.version AS latest_version, a.apply_time
+        FROM blueprint_applications a
+        LEFT JOIN blueprints b ON b.id = a.blueprint_id
+        WHERE a.project_id = $1
+        ORDER BY a.apply_time DESC",
+    )
+    .bind(&project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list blueprint applications")?;
+    for application in &mut applications {
+        if let Some(blueprint) = builtin()
+            .into_iter()
+            .find(|b| b.id == application.blueprint_id)
+        {
+            application.latest_version = Some(blueprint.version);
+        }
+    }
+    Ok(Json(applications))
+}
+
+/// Applies the blueprint's current version to the project. Blueprints
+/// applied before are updated with the changes since.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn apply_blueprint_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path((project_id, blueprint_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<apply::ApplyResult>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let blueprint = find(pool, &user, &blueprint_id).await?;
+    let previous: Option<PreviousApplication> = sqlx::query_as(
+        "
+            SELECT version, content, task_ids
+            FROM blueprint_applications
+            WHERE project_id = $1 AND blueprint_id = $2",
+    )
+    .bind(&project_id)
+    .bind(&blueprint_id)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to get blueprint application")?;
+
+    let result = {
+        let client = collab.register_local_client(&project_id).await?;
+        let doc_box = client.project.doc_box.lock().await;
+        let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+        let mut txn = doc.transact_mut_with(
+            YOrigin {
+                who: "blueprint".to_string(),
+                id: format!("blueprint_{blueprint_id}"),
+                actor: Actor::User(user.clone()),
+                ..Default::default()
+            }
+            .as_origin()?,
+        );
+        let (previous_version, previous_content, task_ids) = match previous {
+            Some(previous) => (
+                Some(previous.version),
+                Some(previous.content.0),
+                previous.task_ids.0,
+            ),
+            None => (None, None, HashMap::new()),
+        };
+        let applied = apply::apply(
+            doc,
+            &mut txn,
+            previous_content.as_ref(),
+            task_ids,
+            &blueprint.content,
+            &user.email,
+        );
+        match applied {
+            Ok(result) => apply::ApplyResult {
+                previous_version,
+                version: blueprint.version,
+                ..result
+            },
+            Err(e) => return Err(bad_request_error("INVALID_BLUEPRINT", &e.to_string())),
+        }
+    };
+
+    let mut txn = pool.begin().await?;
+    apply::record(
+        &mut txn,
+        &project_id,
+        &blueprint.id,
+        blueprint.version,
+        &blueprint.content,
+        &result.task_ids,
+    )
+    .await?;
+    txn.commit().await?;
+    tracing::info!(
+        "Applied blueprint {blueprint_id} v{} to {project_id}: {result:?}",
+        blueprint.version
+    );
+    Ok(Json(result))
+}
+
+/// Checks that the blueprint's tasks are within limits, their keys unique,
+/// and its configuration valid.
+pub(crate) fn validate(content: &BlueprintContent) -> Result<()> {
+    fn count<'a>(
+        tasks: &'a [BlueprintTask],
+        depth: usize,
+        keys: &mut HashSet<&'a str>,
+    ) -> Result<usize> {
+        if depth > MAX_DEPTH {
+            return Err(anyhow::anyhow!(
+                "Tasks may be nested at most {MAX_DEPTH} deep"
+            ));
+        }
+        let mut total = 0;
+        for task in tasks {
+            if task.name.trim().is_empty() {
+                return Err(anyhow::anyhow!("Task names must not be empty"));
+            }
+            if task.key.is_empty() || !keys.insert(&task.key) {
+                return Err(anyhow::anyhow!(
+                    "Task keys must be present and unique: {:?}",
+                    task.key
+                ));
+            }
+            total += 1 + count(&task.children, depth + 1, keys)?;
+        }
+        Ok(total)
+    }
+    if count(&content.tasks, 1, &mut HashSet::new())? > MAX_TASKS {
+        return Err(anyhow::anyhow!(
+            "Blueprints may have at most {MAX_TASKS} tasks"
+        ));
+    }
+    if let Some(config) = &content.config {
+        validate_config(config)?;
+    }
+    Ok(())
+}
+
+/// Adds the blueprint's tasks under the root of a new project's doc, creating
+/// the root, and applies its configuration. Returns the ids of the tasks
+/// added, by blueprint task key.
+pub(crate) fn instantiate(
+    doc: &YDocProxy,
+    txn: &mut TransactionMut,
+    content: &BlueprintContent,
+    reporter: &str,
+) -> Result<HashMap<String, String>> {
+    validate(content)?;
+    if doc.get(txn, "root").is_err() {
+        doc.set(
+            txn,
+            &Task {
+                id: "root".to_string(),
+                num: "0".to_string(),
+                name: "Root".to_string(),
+                ..Task::default()
+            },
+        );
+    }
+    let result = apply::apply(doc, txn, None, HashMap::new(), content, reporter)?;
+    Ok(result.task_ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn instantiate_creates_root_and_hierarchy() {
+        let doc = YDocProxy::new();
+        let mut txn = doc.transact_mut_with(
+            YOrigin {
+                who: "test".to_string(),
+                id: "test".to_string(),
+                actor: Actor::Server,
+                ..Default::default()
+            }
+            .as_origin()
+            .unwrap(),
+        );
+        let blueprint = onboarding_checklist();
+        let task_ids = instantiate(&doc, &mut txn, &blueprint.content, "a@koso.app").unwrap();
+        assert_eq!(task_ids.len(), 5);
+
+        let root = doc.get(&txn, "root").unwrap();
+        let children = root.get_children(&txn).unwrap();
+        assert_eq!(children, vec![task_ids["get-started"].clone()]);
+        let parent = doc.get(&txn, &task_ids["get-started"]).unwrap();
+        assert_eq!(parent.get_num(&txn).unwrap(), "1");
+        assert_eq!(parent.get_children(&txn).unwrap().len(), 4);
+    }
+
+    #[test_log::test]
+    fn validate_rejects_empty_names_and_duplicate_keys() {
+        let task = |key: &str, name: &str| BlueprintTask {
+            key: key.to_string(),
+            name: name.to_string(),
+            ..BlueprintTask::default()
+        };
+        let content = |tasks| BlueprintContent {
+            tasks,
+            config: None,
+        };
+        assert!(validate(&content(vec![task("a", "")])).is_err());
+        assert!(validate(&content(vec![task("a", "A"), task("a", "B")])).is_err());
+        assert!(validate(&content(vec![task("a", "A"), task("b", "B")])).is_ok());
+        assert!(validate(&onboarding_checklist().content).is_ok());
+    }
+}
@@ -0,0 +1,404 @@
+//! Applies blueprints to projects. Projects remember the content of the
+//! version they last applied and which task each blueprint task became, so
+//! applying a later version only makes the changes between the two. Tasks and
+//! config sections edited in the project since are left alone and reported as
+//! conflicts.
+
+use super::{BlueprintContent, BlueprintTask};
+use crate::{
+    api::{
+        model::{ProjectConfig, ProjectId, Task},
+        yproxy::YDocProxy,
+    },
+    postgres::PgTransaction,
+};
+use anyhow::{Context as _, Result};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use serde::Serialize;
+use sqlx::types::Json as SqlJson;
+use std::collections::HashMap;
+use uuid::Uuid;
+use yrs::TransactionMut;
+
+/// The outcome of applying a blueprint to a project.
+#[derive(Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ApplyResult {
+    /// The version applied before, if any.
+    pub(crate) previous_version: Option<i32>,
+    pub(crate) version: i32,
+    pub(crate) created: usize,
+    pub(crate) updated: usize,
+    /// Tasks removed from the blueprint are archived rather than deleted.
+    pub(crate) archived: usize,
+    pub(crate) config_sections: Vec<&'static str>,
+    /// Tasks and config sections left alone because they were changed in the
+    /// project as well as in the blueprint.
+    pub(crate) conflicts: Vec<String>,
+    /// Maps blueprint task keys to the project's task ids.
+    #[serde(skip)]
+    pub(crate) task_ids: HashMap<String, String>,
+}
+
+/// Applies `content`, given the previously applied content, if any, and the
+/// task ids it mapped to.
+pub(crate) fn apply(
+    doc: &YDocProxy,
+    txn: &mut TransactionMut,
+    previous: Option<&BlueprintContent>,
+    mut task_ids: HashMap<String, String>,
+    content: &BlueprintContent,
+    reporter: &str,
+) -> Result<ApplyResult> {
+    let mut result = ApplyResult::default();
+    // Config first, so an invalid result fails before any task is touched.
+    if let Some(config) = &content.config {
+        let current = doc.config().get(txn)?;
+        let old = previous.and_then(|p| p.config.as_ref());
+        let merged = merge_config(&current, old, config, &mut result);
+        if !result.config_sections.is_empty() {
+            doc.config()
+                .set(txn, &merged)
+                .context("The merged config is invalid")?;
+        }
+        if merged.estimates != current.estimates {
+            doc.convert_estimates(
+                txn,
+                &current.estimates.unwrap_or_default(),
+                &merged.estimates.unwrap_or_default(),
+            )?;
+        }
+    }
+    // Blueprint estimates are in points, converted to the project's unit.
+    let estimates = doc.config().get_estimates(txn)?.unwrap_or_default();
+    let estimate = |task: &BlueprintTask| {
+        task.estimate
+            .map(|e| estimates.points_to_estimate(e as f64))
+    };
+
+    let old = flatten(previous.map_or(&[][..], |p| &p.tasks));
+    let new = flatten(&content.tasks);
+    let mut next_num = doc.next_num(txn)?;
+    for (key, parent_key, task) in &new {
+        let existing = task_ids.get(*key).and_then(|id| doc.get(txn, id).ok());
+        match existing {
+            None => {
+                let parent_id = parent_key
+                    .and_then(|k| task_ids.get(k))
+                    .map_or("root", String::as_str)
+                    .to_string();
+                let id = BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4());
+                let y_task = doc.set(
+                    txn,
+                    &Task {
+                        id: id.clone(),
+                        num: next_num.to_string(),
+                        name: task.name.clone(),
+                        desc: task.desc.clone(),
+                        reporter: Some(reporter.to_string()),
+                        kind: task.kind.clone(),
+                        estimate: estimate(task),
+                        ..Task::default()
+                    },
+                );
+                next_num += 1;
+                if !task.labels.is_empty() {
+                    y_task.set_labels(txn, &task.labels)?;
+                }
+                doc.get(txn, &parent_id)?.push_child(txn, &id)?;
+                task_ids.insert(key.to_string(), id);
+                result.created += 1;
+            }
+            Some(y_task) => {
+                let Some((_, _, old_task)) = old.iter().find(|(k, _, _)| k == key) else {
+                    continue;
+                };
+                let mut conflict = false;
+                let mut updated = false;
+                if merge(
+                    &old_task.name,
+                    &task.name,
+                    &y_task.get_name(txn)?,
+                    &mut conflict,
+                ) {
+                    y_task.set_name(txn, &task.name);
+                    updated = true;
+                }
+                if merge(
+                    &old_task.desc,
+                    &task.desc,
+                    &y_task.get_desc(txn)?,
+                    &mut conflict,
+                ) {
+                    y_task.set_desc(txn, task.desc.as_deref());
+                    updated = true;
+                }
+                if merge(
+                    &old_task.kind,
+                    &task.kind,
+                    &y_task.get_kind(txn)?,
+                    &mut conflict,
+                ) {
+                    y_task.set_kind(txn, task.kind.as_deref());
+                    updated = true;
+                }
+                if merge(
+                    &estimate(old_task),
+                    &estimate(task),
+                    &y_task.get_estimate(txn)?,
+                    &mut conflict,
+                ) {
+                    y_task.set_estimate(txn, estimate(task));
+                    updated = true;
+                }
+                if merge(
+                    &old_task.labels,
+                    &task.labels,
+                    &y_task.get_labels(txn)?,
+                    &mut conflict,
+                ) {
+                    y_task.set_labels(txn, &task.labels)?;
+                    updated = true;
+                }
+                if updated {
+                    result.updated += 1;
+                }
+                if conflict {
+                    result.conflicts.push(format!("task {}", task.name));
+                }
+            }
+        }
+    }
+
+    for (key, _, _) in &old {
+        if new.iter().any(|(k, _, _)| k == key) {
+            continue;
+        }
+        let Some(id) = task_ids.remove(*key) else {
+            continue;
+        };
+        if let Ok(y_task) = doc.get(txn, &id) {
+            if !y_task.get_archived(txn)?.unwrap_or(false) {
+                y_task.set_archived(txn, Some(true));
+                result.archived += 1;
+            }
+        }
+    }
+    result.task_ids = task_ids;
+    Ok(result)
+}
+
+/// Lists tasks in pre-order, parents before children, as (key, parent key, task).
+fn flatten(tasks: &[BlueprintTask]) -> Vec<(&str, Option<&str>, &BlueprintTask)> {
+    fn visit<'a>(
+        tasks: &'a [BlueprintTask],
+        parent: Option<&'a str>,
+        out: &mut Vec<(&'a str, Option<&'a str>, &'a BlueprintTask)>,
+    ) {
+        for task in tasks {
+            out.push((&task.key, parent, task));
+            visit(&task.children, Some(&task.key), out);
+        }
+    }
+    let mut out = Vec::new();
+    visit(tasks, None, &mut out);
+    out
+}
+
+/// Whether a value the blueprint changed from `old` to `new` should be
+/// updated: only if the project still has the old value. Flags a conflict if
+/// the project has neither.
+fn merge<V: PartialEq>(old: &V, new: &V, current: &V, conflict: &mut bool) -> bool {
+    if old == new || current == new {
+        return false;
+    }
+    if current != old {
+        *conflict = true;
+        return false;
+    }
+    true
+}
+
+fn merge_config(
+    current: &ProjectConfig,
+    old: Option<&ProjectConfig>,
+    new: &ProjectConfig,
+    result: &mut ApplyResult,
+) -> ProjectConfig {
+    let mut merged = current.clone();
+    merge_section(
+        "workflowStates",
+        &mut merged.workflow_states,
+        old.map(|o| &o.workflow_states),
+        &new.workflow_states,
+        result,
+    );
+    merge_section(
+        "labels",
+        &mut merged.labels,
+        old.map(|o| &o.labels),
+        &new.labels,
+        result,
+    );
+    merge_section(
+        "iteration",
+        &mut merged.iteration,
+        old.map(|o| &o.iteration),
+        &new.iteration,
+        result,
+    );
+    merge_section(
+        "automationRules",
+        &mut merged.automation_rules,
+        old.map(|o| &o.automation_rules),
+        &new.automation_rules,
+        result,
+    );
+    merge_section(
+        "autoArchive",
+        &mut merged.auto_archive,
+        old.map(|o| &o.auto_archive),
+        &new.auto_archive,
+        result,
+    );
+    merge_section(
+        "estimates",
+        &mut merged.estimates,
+        old.map(|o| &o.estimates),
+        &new.estimates,
+        result,
+    );
+    merged
+}
+
+/// Applies a config section. The first time a blueprint is applied, every
+/// section it sets replaces the project's.
+fn merge_section<V: PartialEq + Clone + Default>(
+    name: &'static str,
+    current: &mut V,
+    old: Option<&V>,
+    new: &V,
+    result: &mut ApplyResult,
+) {
+    let apply = match old {
+        None => *new != V::default() && current != new,
+        Some(old) => {
+            let mut conflict = false;
+            let apply = merge(old, new, current, &mut conflict);
+            if conflict {
+                result.conflicts.push(format!("config {name}"));
+            }
+            apply
+        }
+    };
+    if apply {
+        *current = new.clone();
+        result.config_sections.push(name);
+    }
+}
+
+/// Records the version applied to the project, for later diffs.
+pub(crate) async fn record(
+    txn: &mut PgTransaction,
+    project_id: &ProjectId,
+    blueprint_id: &str,
+    version: i32,
+    content: &BlueprintContent,
+    task_ids: &HashMap<String, String>,
+) -> Result<()> {
+    sqlx::query(
+        "
+        INSERT INTO blueprint_applications (project_id, blueprint_id, version, content, task_ids)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (project_id, blueprint_id)
+        DO UPDATE SET
+          version = EXCLUDED.version,
+          content = EXCLUDED.content,
+          task_ids = EXCLUDED.task_ids,
+          apply_time = NOW()",
+    )
+    .bind(project_id)
+    .bind(blueprint_id)
+    .bind(version)
+    .bind(SqlJson(content))
+    .bind(SqlJson(task_ids))
+    .execute(&mut **txn)
+    .await
+    .context("Failed to record blueprint application")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::collab::txn_origin::{Actor, YOrigin};
+
+    fn task(key: &str, name: &str, children: Vec<BlueprintTask>) -> BlueprintTask {
+        BlueprintTask {
+            key: key.to_string(),
+            name: name.to_string(),
+            children,
+            ..BlueprintTask::default()
+        }
+    }
+
+    #[test_log::test]
+    fn reapplying_applies_the_diff() {
+        let doc = YDocProxy::new();
+        let mut txn = doc.transact_mut_with(
+            YOrigin {
+                who: "test".to_string(),
+                id: "test".to_string(),
+                actor: Actor::Server,
+                ..Default::default()
+            }
+            .as_origin()
+            .unwrap(),
+        );
+        doc.set(
+            &mut txn,
+            &Task {
+                id: "root".to_string(),
+                num: "0".to_string(),
+                name: "Root".to_string(),
+                ..Task::default()
+            },
+        );
+        let v1 = BlueprintContent {
+            tasks: vec![task(
+                "a",
+                "A",
+                vec![task("b", "B", vec![]), task("c", "C", vec![])],
+            )],
+            config: None,
+        };
+        let applied = apply(&doc, &mut txn, None, HashMap::new(), &v1, "a@koso.app").unwrap();
+        assert_eq!(applied.created, 3);
+
+        // Edited in the project, so the blueprint's rename conflicts.
+        let c = doc.get(&txn, &applied.task_ids["c"]).unwrap();
+        c.set_name(&mut txn, "Local C");
+
+        let v2 = BlueprintContent {
+            tasks: vec![task(
+                "a",
+                "A2",
+                vec![task("c", "C2", vec![]), task("d", "D", vec![])],
+            )],
+            config: None,
+        };
+        let task_ids = applied.task_ids.clone();
+        let reapplied = apply(&doc, &mut txn, Some(&v1), task_ids, &v2, "a@koso.app").unwrap();
+        assert_eq!(reapplied.created, 1);
+        assert_eq!(reapplied.updated, 1);
+        assert_eq!(reapplied.archived, 1);
+        assert_eq!(reapplied.conflicts, vec!["task C2"]);
+
+        let a = doc.get(&txn, &applied.task_ids["a"]).unwrap();
+        assert_eq!(a.get_name(&txn).unwrap(), "A2");
+        assert_eq!(a.get_children(&txn).unwrap().len(), 3);
+        assert_eq!(c.get_name(&txn).unwrap(), "Local C");
+        let b = doc.get(&txn, &applied.task_ids["b"]).unwrap();
+        assert_eq!(b.get_archived(&txn).unwrap(), Some(true));
+    }
+}
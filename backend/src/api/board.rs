@@ -0,0 +1,113 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{
+            Collab,
+            projects_state::DocBox,
+            txn_origin::{Actor, YOrigin},
+        },
+        google::User,
+        model::{BoardColumn, ProjectId},
+        proposals::transact_or_propose,
+        verify_project_access,
+        yproxy::BOARD_STATUSES,
+    },
+    postgres::PgPool,
+};
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    routing::{get, put},
+};
+use std::collections::HashSet;
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/{project_id}/board", get(get_board_handler))
+        .route(
+            "/{project_id}/board/{status}",
+            put(set_board_column_handler),
+        )
+}
+
+#[tracing::instrument(skip(user, pool, collab))]
+async fn get_board_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Vec<BoardColumn>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+
+    let client = collab.register_local_client(&project_id).await?;
+    let doc_box = client.project.doc_box.lock().await;
+    let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+    let txn = doc.transact();
+    let mut columns = Vec::with_capacity(BOARD_STATUSES.len());
+    for status in BOARD_STATUSES {
+        columns.push(BoardColumn {
+            status: status.to_string(),
+            task_ids: doc.board_column(&txn, status)?,
+        });
+    }
+    Ok(Json(columns))
+}
+
+/// Sets the manual ordering of a column. The given tasks must all be in the column,
+/// though tasks may be omitted, in which case they're ordered after those given.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn set_board_column_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path((project_id, status)): Path<(ProjectId, String)>,
+    Json(column): Json<BoardColumn>,
+) -> ApiResult<Json<BoardColumn>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    if status != column.status {
+        return Err(bad_request_error(
+            "STATUS_MISMATCH",
+            &format!(
+                "Path status ({status}) is different than body status ({})",
+                column.status
+            ),
+        ));
+    }
+    if !BOARD_STATUSES.contains(&status.as_str()) {
+        return Err(bad_request_error(
+            "INVALID_STATUS",
+            &format!("Invalid status: {status}"),
+        ));
+    }
+    let mut seen = HashSet::new();
+    if let Some(duplicate) = column.task_ids.iter().find(|id| !seen.insert(*id)) {
+        return Err(bad_request_error(
+            "DUPLICATE_TASK",
+            &format!("Task {duplicate} appears more than once"),
+        ));
+    }
+
+    let origin = YOrigin {
+        who: "board".to_string(),
+        id: format!("board_{status}"),
+        actor: Actor::User(user.clone()),
+        ..Default::default()
+    };
+    let transacted = transact_or_propose(pool, &collab, &project_id, &origin, |doc, txn| {
+        let current = doc.board_column(txn, &status)?;
+        if let Some(misplaced) = column.task_ids.iter().find(|id| !current.contains(*id)) {
+            return Err(bad_request_error(
+                "NOT_IN_COLUMN",
+                &format!("Task {misplaced} is not in column {status}"),
+            ));
+        }
+        doc.set_board_order(txn, &status, &column.task_ids);
+        Ok(doc.board_column(txn, &status)?)
+    })
+    .await?;
+
+    Ok(Json(BoardColumn {
+        task_ids: transacted.result,
+        status,
+    }))
+}
@@ -0,0 +1,253 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{
+            Collab,
+            projects_state::DocBox,
+            txn_origin::{Actor, YOrigin},
+        },
+        google::User,
+        model::{CreateBranch, ProjectBranch, ProjectId},
+        not_found_error,
+        projects::validate_project_name,
+        proposals::transact_or_propose,
+        verify_project_access,
+    },
+    postgres::{self, PgPool},
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    routing::{delete, get, post},
+};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use uuid::Uuid;
+use yrs::{
+    ReadTxn as _, StateVector, Update,
+    updates::{decoder::Decode as _, encoder::Encode as _},
+};
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route(
+            "/{project_id}/branches",
+            get(list_branches_handler).post(create_branch_handler),
+        )
+        .route(
+            "/{project_id}/branches/{branch_id}",
+            delete(discard_branch_handler),
+        )
+        .route(
+            "/{project_id}/branches/{branch_id}/merge",
+            post(merge_branch_handler),
+        )
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn list_branches_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Vec<ProjectBranch>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let branches: Vec<ProjectBranch> = sqlx::query_as(
+        "
+        SELECT b.branch_project_id, b.project_id, b.name, b.creator, b.create_time
+        FROM project_branches b
+        JOIN projects p ON p.project_id = b.branch_project_id
+        WHERE b.project_id = $1
+        AND p.deleted_on IS NULL
+        ORDER BY b.create_time DESC",
+    )
+    .bind(&project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list branches")?;
+    Ok(Json(branches))
+}
+
+/// Forks the project's current doc into a new draft project, visible only to its creator.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn create_branch_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+    Json(branch): Json<CreateBranch>,
+) -> ApiResult<Json<ProjectBranch>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    validate_project_name(&branch.name)?;
+    if is_branch(pool, &project_id).await? {
+        return Err(bad_request_error(
+            "NESTED_BRANCH",
+            "Cannot branch a draft branch",
+        ));
+    }
+
+    let (update, state_vector) = {
+        let client = collab.register_local_client(&project_id).await?;
+        let doc_box = client.project.doc_box.lock().await;
+        let txn = DocBox::doc_or_error(doc_box.as_ref())?.ydoc.transact();
+        (
+            txn.encode_state_as_update_v2(&StateVector::default()),
+            txn.state_vector(),
+        )
+    };
+
+    let branch_project_id = BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4());
+    // Branches stay in their project's region, and their doc goes first. One
+    // left behind by a failed create is never loaded.
+    sqlx::query("INSERT INTO yupdates (project_id, seq, update_v2) VALUES ($1, DEFAULT, $2)")
+        .bind(&branch_project_id)
+        .bind(update)
+        .execute(postgres::doc_pool(pool, &project_id).await?)
+        .await?;
+    let mut txn = pool.begin().await?;
+    sqlx::query(
+        "
+        INSERT INTO projects (project_id, name, region, org)
+        SELECT $1, $2, region, org FROM projects WHERE project_id = $3",
+    )
+    .bind(&branch_project_id)
+    .bind(&branch.name)
+    .bind(&project_id)
+    .execute(&mut *txn)
+    .await?;
+    sqlx::query("INSERT INTO project_permissions (project_id, email, admin) VALUES ($1, $2, TRUE)")
+        .bind(&branch_project_id)
+        .bind(&user.email)
+        .execute(&mut *txn)
+        .await?;
+    let created: ProjectBranch = sqlx::query_as(
+        "
+        INSERT INTO project_branches (branch_project_id, project_id, name, creator, base_state_vector)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING branch_project_id, project_id, name, creator, create_time",
+    )
+    .bind(&branch_project_id)
+    .bind(&project_id)
+    .bind(&branch.name)
+    .bind(&user.email)
+    .bind(state_vector.encode_v1())
+    .fetch_one(&mut *txn)
+    .await?;
+    txn.commit().await?;
+
+    tracing::debug!("Created branch {branch_project_id} of project {project_id}");
+    Ok(Json(created))
+}
+
+/// Applies the branch's changes since it was created to the live doc and closes the branch.
+/// Concurrent changes to the live doc are preserved by the CRDT merge.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn merge_branch_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path((project_id, branch_id)): Path<(ProjectId, ProjectId)>,
+) -> ApiResult<Json<()>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    verify_project_access(pool, &user, &branch_id).await?;
+    let base_state_vector = fetch_base_state_vector(pool, &project_id, &branch_id).await?;
+
+    let update = {
+        let client = collab.register_local_client(&branch_id).await?;
+        let doc_box = client.project.doc_box.lock().await;
+        DocBox::doc_or_error(doc_box.as_ref())?
+            .ydoc
+            .transact()
+            .encode_state_as_update_v2(&base_state_vector)
+    };
+    let update = Update::decode_v2(&update).context("Failed to decode branch update")?;
+    let origin = YOrigin {
+        who: "merge_branch".to_string(),
+        id: format!("merge_{branch_id}"),
+        actor: Actor::User(user.clone()),
+        ..Default::default()
+    };
+    // Merges are held for review like any other change the user makes.
+    let transacted = transact_or_propose(pool, &collab, &project_id, &origin, |doc, txn| {
+        if let Err(e) = doc.snapshot_deleted(txn, update.delete_set()) {
+            tracing::warn!("Failed to snapshot removed tasks: {e:?}");
+        }
+        txn.apply_update(update)
+            .context("Failed to apply branch update")?;
+        Ok(())
+    })
+    .await?;
+
+    close_branch(pool, &branch_id).await?;
+    tracing::debug!(
+        "Merged branch {branch_id} into project {project_id}, proposed: {}",
+        transacted.proposed
+    );
+    Ok(Json(()))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn discard_branch_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, branch_id)): Path<(ProjectId, ProjectId)>,
+) -> ApiResult<Json<()>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    verify_project_access(pool, &user, &branch_id).await?;
+    fetch_base_state_vector(pool, &project_id, &branch_id).await?;
+    close_branch(pool, &branch_id).await?;
+    Ok(Json(()))
+}
+
+async fn fetch_base_state_vector(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    branch_id: &ProjectId,
+) -> ApiResult<StateVector> {
+    let base: Option<(Vec<u8>,)> = sqlx::query_as(
+        "
+        SELECT b.base_state_vector
+        FROM project_branches b
+        JOIN projects p ON p.project_id = b.branch_project_id
+        WHERE b.branch_project_id = $1
+        AND b.project_id = $2
+        AND p.deleted_on IS NULL",
+    )
+    .bind(branch_id)
+    .bind(project_id)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch branch")?;
+    let Some((base,)) = base else {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Branch {branch_id} not found"),
+        ));
+    };
+    Ok(StateVector::decode_v1(&base).context("Failed to decode base state vector")?)
+}
+
+/// Closed branches are soft deleted like any other project.
+async fn close_branch(pool: &PgPool, branch_id: &ProjectId) -> Result<()> {
+    sqlx::query(
+        "
+        UPDATE projects
+        SET deleted_on = CURRENT_TIMESTAMP
+        WHERE project_id = $1",
+    )
+    .bind(branch_id)
+    .execute(pool)
+    .await
+    .context("Failed to close branch")?;
+    Ok(())
+}
+
+pub(crate) async fn is_branch(pool: &PgPool, project_id: &ProjectId) -> Result<bool> {
+    let (is_branch,): (bool,) = sqlx::query_as(
+        "SELECT EXISTS (SELECT 1 FROM project_branches WHERE branch_project_id = $1)",
+    )
+    .bind(project_id)
+    .fetch_one(pool)
+    .await
+    .context("Failed to check for branch")?;
+    Ok(is_branch)
+}
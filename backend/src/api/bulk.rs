@@ -0,0 +1,302 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{
+            Collab,
+            projects_state::DocBox,
+            txn_origin::{Actor, YOrigin},
+        },
+        google::User,
+        model::{BulkAction, BulkChange, BulkOperation, BulkResult, ProjectId},
+        not_found_error, proposals,
+        step_up::verify_step_up,
+        unauthorized_error, verify_project_access,
+        yproxy::{YDocProxy, YTaskProxy},
+    },
+    postgres::{PgPool, list_project_users},
+};
+use anyhow::Result;
+use axum::{Extension, Json, Router, extract::Path, http::HeaderMap, routing::post};
+use chrono::{TimeDelta, Utc};
+use std::collections::{HashSet, VecDeque};
+use yrs::{ReadTxn, TransactionMut};
+
+pub(super) fn router() -> Router {
+    Router::new().route("/{project_id}/bulk", post(bulk_handler))
+}
+
+/// Applies the operation to every matching task in a single transaction or,
+/// for dry runs, only reports the tasks that would change.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn bulk_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+    headers: HeaderMap,
+    Json(operation): Json<BulkOperation>,
+) -> ApiResult<Json<BulkResult>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    if !operation.dry_run {
+        verify_step_up(pool, &user, &headers).await?;
+    }
+    if !operation.dry_run && collab.is_held(&project_id, &user.email) {
+        return Err(unauthorized_error(&format!(
+            "Changes by {} to project {project_id} are held for review",
+            user.email
+        )));
+    }
+    if !operation.dry_run && proposals::requires_review(pool, &project_id, &user.email).await? {
+        return Err(unauthorized_error(&format!(
+            "User {} cannot apply bulk operations to reviewed project {project_id}",
+            user.email
+        )));
+    }
+    match &operation.action {
+        BulkAction::SetStatus { .. } => {}
+        BulkAction::Reassign { from, to } => {
+            if let Some(to) = to {
+                if to == from {
+                    return Err(bad_request_error(
+                        "SAME_ASSIGNEE",
+                        "Tasks are already assigned to the new assignee",
+                    ));
+                }
+                let project_users = list_project_users(pool, &project_id).await?;
+                if !project_users.iter().any(|u| &u.email == to) {
+                    return Err(bad_request_error(
+                        "NOT_A_MEMBER",
+                        &format!("{to} is not a member of the project"),
+                    ));
+                }
+            }
+        }
+        BulkAction::ArchiveDone { older_than_days } => {
+            if *older_than_days == 0 {
+                return Err(bad_request_error(
+                    "INVALID_AGE",
+                    "olderThanDays must be positive",
+                ));
+            }
+        }
+    }
+
+    let client = collab.register_local_client(&project_id).await?;
+    let doc_box = client.project.doc_box.lock().await;
+    let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+    let now = Utc::now().timestamp_millis();
+    if let BulkAction::SetStatus { root, status } = &operation.action {
+        let txn = doc.transact();
+        if doc.get(&txn, root).is_err() {
+            return Err(not_found_error(
+                "NOT_FOUND",
+                &format!("Task {root} not found"),
+            ));
+        }
+        if !doc.config().statuses(&txn)?.contains(status) {
+            return Err(bad_request_error(
+                "INVALID_STATUS",
+                &format!("Invalid status: {status}"),
+            ));
+        }
+    }
+
+    let changes = if operation.dry_run {
+        plan(doc, &doc.transact(), &operation.action, now)?
+    } else {
+        let mut txn = doc.transact_mut_with(
+            YOrigin {
+                who: "bulk_handler".to_string(),
+                id: "bulk".to_string(),
+                actor: Actor::User(user),
+                ..Default::default()
+            }
+            .as_origin()?,
+        );
+        let changes = plan(doc, &txn, &operation.action, now)?;
+        apply(doc, &mut txn, &operation.action, &changes, now)?;
+        changes
+    };
+    Ok(Json(BulkResult {
+        dry_run: operation.dry_run,
+        changes,
+    }))
+}
+
+/// Returns the tasks the action would change, ordered by number.
+fn plan<T: ReadTxn>(
+    doc: &YDocProxy,
+    txn: &T,
+    action: &BulkAction,
+    now: i64,
+) -> Result<Vec<BulkChange>> {
+    let mut changes = vec![];
+    match action {
+        BulkAction::SetStatus { root, status } => {
+            let mut visited = HashSet::new();
+            let mut queue = VecDeque::from(doc.get(txn, root)?.get_children(txn)?);
+            while let Some(id) = queue.pop_front() {
+                if !visited.insert(id.clone()) {
+                    continue;
+                }
+                let Ok(task) = doc.get(txn, &id) else {
+                    continue;
+                };
+                queue.extend(task.get_children(txn)?);
+                // Rollup statuses are derived from their children and managed
+                // tasks are owned by their plugin.
+                if task.is_rollup(txn)? || task.is_managed(txn)? {
+                    continue;
+                }
+                if task.get_status(txn)?.as_deref().unwrap_or("Not Started") != status {
+                    changes.push(to_change(txn, &task)?);
+                }
+            }
+        }
+        BulkAction::Reassign { from, .. } => {
+            for task in doc.tasks(txn)? {
+                if task.get_assignee(txn)?.as_ref() == Some(from) {
+                    changes.push(to_change(txn, &task)?);
+                }
+            }
+        }
+        BulkAction::ArchiveDone { older_than_days } => {
+            let cutoff = now - TimeDelta::days(*older_than_days as i64).num_milliseconds();
+            for task in doc.tasks(txn)? {
+                if task.get_archived(txn)?.unwrap_or(false)
+                    || task.is_rollup(txn)?
+                    || task.get_status(txn)?.as_deref() != Some("Done")
+                {
+                    continue;
+                }
+                if task.get_status_time(txn)?.is_some_and(|t| t < cutoff) {
+                    changes.push(to_change(txn, &task)?);
+                }
+            }
+        }
+    }
+    changes.sort_by_key(|c| (c.num.parse::<u64>().unwrap_or(u64::MAX), c.num.clone()));
+    Ok(changes)
+}
+
+fn to_change<T: ReadTxn>(txn: &T, task: &YTaskProxy) -> Result<BulkChange> {
+    Ok(BulkChange {
+        task_id: task.get_id(txn)?,
+        num: task.get_num(txn)?,
+        name: task.get_name(txn)?,
+    })
+}
+
+fn apply(
+    doc: &YDocProxy,
+    txn: &mut TransactionMut,
+    action: &BulkAction,
+    changes: &[BulkChange],
+    now: i64,
+) -> Result<()> {
+    for change in changes {
+        let task = doc.get(txn, &change.task_id)?;
+        match action {
+            BulkAction::SetStatus { status, .. } => {
+                task.set_status(txn, Some(status));
+                task.set_status_time(txn, Some(now));
+            }
+            BulkAction::Reassign { to, .. } => task.set_assignee(txn, to.as_deref()),
+            BulkAction::ArchiveDone { .. } => task.set_archived(txn, Some(true)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::model::{Task, test_utils};
+    use yrs::Origin;
+
+    fn origin() -> Origin {
+        YOrigin {
+            who: "bulk_test".to_string(),
+            id: "test".to_string(),
+            actor: Actor::Server,
+            ..Default::default()
+        }
+        .as_origin()
+        .unwrap()
+    }
+
+    fn ids(changes: &[BulkChange]) -> Vec<&str> {
+        changes.iter().map(|c| c.task_id.as_str()).collect()
+    }
+
+    #[test]
+    fn plan_set_status_skips_rollups_and_matching_tasks() {
+        let doc = YDocProxy::new();
+        let mut txn = doc.transact_mut_with(origin());
+        doc.set(&mut txn, &test_utils::task("1", "1", &["2", "3"]));
+        doc.set(&mut txn, &test_utils::task("2", "2", &["4"]));
+        doc.set(
+            &mut txn,
+            &Task {
+                status: Some("Done".to_string()),
+                ..test_utils::task("3", "3", &[])
+            },
+        );
+        doc.set(
+            &mut txn,
+            &Task {
+                status: Some("In Progress".to_string()),
+                ..test_utils::task("4", "4", &[])
+            },
+        );
+        doc.set(&mut txn, &test_utils::task("5", "5", &[]));
+
+        let action = BulkAction::SetStatus {
+            root: "1".to_string(),
+            status: "Done".to_string(),
+        };
+        let changes = plan(&doc, &txn, &action, 100).unwrap();
+        assert_eq!(ids(&changes), vec!["4"]);
+
+        apply(&doc, &mut txn, &action, &changes, 100).unwrap();
+        let task = doc.get(&txn, "4").unwrap();
+        assert_eq!(task.get_status(&txn).unwrap().as_deref(), Some("Done"));
+        assert_eq!(task.get_status_time(&txn).unwrap(), Some(100));
+    }
+
+    #[test]
+    fn plan_archive_done_respects_age() {
+        let day = TimeDelta::days(1).num_milliseconds();
+        let now = 100 * day;
+        let doc = YDocProxy::new();
+        let mut txn = doc.transact_mut_with(origin());
+        doc.set(
+            &mut txn,
+            &Task {
+                status: Some("Done".to_string()),
+                status_time: Some(now - 91 * day),
+                ..test_utils::task("1", "1", &[])
+            },
+        );
+        doc.set(
+            &mut txn,
+            &Task {
+                status: Some("Done".to_string()),
+                status_time: Some(now - 10 * day),
+                ..test_utils::task("2", "2", &[])
+            },
+        );
+        doc.set(
+            &mut txn,
+            &Task {
+                status: Some("In Progress".to_string()),
+                ..test_utils::task("3", "3", &[])
+            },
+        );
+
+        let action = BulkAction::ArchiveDone {
+            older_than_days: 90,
+        };
+        assert_eq!(ids(&plan(&doc, &txn, &action, now).unwrap()), vec!["1"]);
+    }
+}
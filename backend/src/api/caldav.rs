@@ -0,0 +1,546 @@
+//! A CalDAV VTODO collection of the tasks assigned to a user, so they can
+//! tick off tasks from Apple Reminders, Thunderbird and other task clients.
+//!
+//! Clients authenticate with Basic auth, using the user's API key as the
+//! password. The collection lives at `/api/caldav/tasks/`, with one resource
+//! per task named `{project_id}~{task_id}.ics`, and is read from
+//! `task_projections`. Only status changes are written back, applied to the
+//! doc in a server transaction; other edits are ignored.
+
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{
+            Collab,
+            txn_origin::{Actor, TxnMetadata, YOrigin},
+        },
+        google::User,
+        model::{ProjectId, WorkflowCategory},
+        not_found_error,
+        proposals::transact_or_propose,
+        reports::escape_html,
+        verify_project_access,
+        yproxy::status_category,
+        zapier,
+    },
+    postgres::PgPool,
+};
+use anyhow::Context as _;
+use axum::{
+    Extension, Router,
+    body::Bytes,
+    extract::Path,
+    http::{
+        HeaderMap, HeaderName, Method, StatusCode,
+        header::{ALLOW, AUTHORIZATION, CONTENT_TYPE, ETAG, WWW_AUTHENTICATE},
+    },
+    response::{IntoResponse as _, Response},
+    routing::any,
+};
+use base64::{Engine as _, prelude::BASE64_STANDARD};
+use sqlx::{
+    FromRow,
+    types::chrono::{DateTime, Utc},
+};
+use tower_http::request_id::RequestId;
+
+const ROOT_HREF: &str = "/api/caldav/";
+const COLLECTION_HREF: &str = "/api/caldav/tasks/";
+const PRODID: &str = "-//Koso//CalDAV Tasks//EN";
+const DAV_HEADER: HeaderName = HeaderName::from_static("dav");
+/// Advertised on multistatus responses too, as clients probe with OPTIONS and
+/// PROPFIND alike.
+const DAV: &str = "1, calendar-access";
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/", any(root_handler))
+        .route("/tasks", any(collection_handler))
+        .route("/tasks/", any(collection_handler))
+        .route("/tasks/{file}", any(task_handler))
+}
+
+#[derive(FromRow, Debug)]
+struct CalTask {
+    project_id: ProjectId,
+    task_id: String,
+    name: String,
+    status: Option<String>,
+    deadline_millis: Option<i64>,
+    update_time: DateTime<Utc>,
+}
+
+impl CalTask {
+    fn href(&self) -> String {
+        format!("{COLLECTION_HREF}{}~{}.ics", self.project_id, self.task_id)
+    }
+
+    fn etag(&self) -> String {
+        format!("\"{}\"", self.update_time.timestamp_millis())
+    }
+}
+
+/// Serves principal discovery. The user's principal, and calendar home, is
+/// the root itself.
+#[tracing::instrument(skip(pool, headers, method))]
+async fn root_handler(
+    Extension(pool): Extension<&'static PgPool>,
+    method: Method,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    if method == Method::OPTIONS {
+        return Ok(options_response());
+    }
+    let Some(_user) = authenticate(pool, &headers).await? else {
+        return Ok(challenge_response());
+    };
+    if method.as_str() != "PROPFIND" {
+        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+    }
+    let mut responses = vec![prop_response(
+        ROOT_HREF,
+        "<d:resourcetype><d:collection/></d:resourcetype>\
+         <d:displayname>Koso</d:displayname>",
+    )];
+    if depth(&headers) > 0 {
+        responses.push(collection_response(&[]));
+    }
+    Ok(multistatus_response(&responses))
+}
+
+/// Serves the collection itself: PROPFIND lists the tasks and REPORT returns
+/// their data, either all of them for a calendar-query or those requested by
+/// a calendar-multiget.
+#[tracing::instrument(skip(pool, headers, method, body))]
+async fn collection_handler(
+    Extension(pool): Extension<&'static PgPool>,
+    method: Method,
+    headers: HeaderMap,
+    body: Bytes,
+) -> ApiResult<Response> {
+    if method == Method::OPTIONS {
+        return Ok(options_response());
+    }
+    let Some(user) = authenticate(pool, &headers).await? else {
+        return Ok(challenge_response());
+    };
+    let tasks = list_tasks(pool, &user).await?;
+    match method.as_str() {
+        "PROPFIND" => {
+            let mut responses = vec![collection_response(&tasks)];
+            if depth(&headers) > 0 {
+                responses.extend(tasks.iter().map(|task| {
+                    prop_response(
+                        &task.href(),
+                        &format!(
+                            "<d:resourcetype/><d:getetag>{}</d:getetag>\
+                             <d:getcontenttype>text/calendar; charset=utf-8</d:getcontenttype>",
+                            escape_html(&task.etag())
+                        ),
+                    )
+                }));
+            }
+            Ok(multistatus_response(&responses))
+        }
+        "REPORT" => {
+            let body = String::from_utf8_lossy(&body);
+            let hrefs = multiget_hrefs(&body);
+            let now = Utc::now();
+            let responses: Vec<String> = tasks
+                .iter()
+                .filter(|task| hrefs.is_empty() || hrefs.contains(&task.href()))
+                .map(|task| {
+                    prop_response(
+                        &task.href(),
+                        &format!(
+                            "<d:getetag>{}</d:getetag><c:calendar-data>{}</c:calendar-data>",
+                            escape_html(&task.etag()),
+                            escape_html(&render_vtodo(task, now))
+                        ),
+                    )
+                })
+                .collect();
+            Ok(multistatus_response(&responses))
+        }
+        _ => Ok(StatusCode::METHOD_NOT_ALLOWED.into_response()),
+    }
+}
+
+/// Serves a single task: GET returns it and PUT writes back its status.
+#[tracing::instrument(skip(pool, collab, headers, method, body))]
+async fn task_handler(
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Extension(request_id): Extension<RequestId>,
+    method: Method,
+    headers: HeaderMap,
+    Path(file): Path<String>,
+    body: Bytes,
+) -> ApiResult<Response> {
+    if method == Method::OPTIONS {
+        return Ok(options_response());
+    }
+    let Some(user) = authenticate(pool, &headers).await? else {
+        return Ok(challenge_response());
+    };
+    let Some((project_id, task_id)) = file
+        .strip_suffix(".ics")
+        .and_then(|stem| stem.split_once('~'))
+    else {
+        return Err(not_found_error("NOT_FOUND", "No such task"));
+    };
+    let Some(task) = list_tasks(pool, &user)
+        .await?
+        .into_iter()
+        .find(|t| t.project_id == project_id && t.task_id == task_id)
+    else {
+        // Clients creating tasks would end up here too, as they pick new names.
+        return Err(not_found_error("NOT_FOUND", "No such task"));
+    };
+
+    match method {
+        Method::GET | Method::HEAD => Ok((
+            [
+                (CONTENT_TYPE, "text/calendar; charset=utf-8".to_string()),
+                (ETAG, task.etag()),
+            ],
+            render_vtodo(&task, Utc::now()),
+        )
+            .into_response()),
+        Method::PUT => {
+            let body = String::from_utf8_lossy(&body);
+            let Some(category) = parse_status(&body) else {
+                return Err(bad_request_error(
+                    "UNSUPPORTED_STATUS",
+                    "Only NEEDS-ACTION, IN-PROCESS and COMPLETED are supported",
+                ));
+            };
+            update_status(pool, &collab, &user, &task, category, &request_id).await?;
+            // Omit the ETag, as the task now differs from what the client sent.
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        _ => Ok(StatusCode::METHOD_NOT_ALLOWED.into_response()),
+    }
+}
+
+/// Returns the owner of the API key sent as the Basic auth password, or None
+/// if it's missing so the client can be challenged for it.
+async fn authenticate(pool: &PgPool, headers: &HeaderMap) -> ApiResult<Option<User>> {
+    let key = headers
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Basic "))
+        .and_then(|h| BASE64_STANDARD.decode(h.trim()).ok())
+        .and_then(|h| String::from_utf8(h).ok())
+        .and_then(|h| h.split_once(':').map(|(_, key)| key.to_string()));
+    match key {
+        Some(key) => Ok(Some(zapier::authenticate_key(pool, &key).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Lists the unarchived tasks assigned to the user in their projects.
+async fn list_tasks(pool: &PgPool, user: &User) -> ApiResult<Vec<CalTask>> {
+    let tasks: Vec<CalTask> = sqlx::query_as(
+        "
+        SELECT t.project_id, t.task_id, t.name, t.status, t.deadline_millis, t.update_time
+        FROM task_projections t
+        JOIN project_permissions pp ON pp.project_id = t.project_id AND pp.email = t.assignee
+        JOIN projects p ON p.project_id = t.project_id
+        WHERE t.assignee = $1 AND NOT t.archived AND p.deleted_on IS NULL
+        ORDER BY t.update_time DESC",
+    )
+    .bind(&user.email)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list assigned tasks")?;
+    Ok(tasks)
+}
+
+/// Moves the task to the project's first status in the given category, unless
+/// it's in that category already.
+async fn update_status(
+    pool: &'static PgPool,
+    collab: &Collab,
+    user: &User,
+    task: &CalTask,
+    category: WorkflowCategory,
+    request_id: &RequestId,
+) -> ApiResult<()> {
+    verify_project_access(pool, user, &task.project_id).await?;
+    let request_id = request_id.header_value().to_str().unwrap_or("INVALID");
+    let origin = YOrigin {
+        who: "caldav".to_string(),
+        id: format!("caldav_status_{request_id}"),
+        actor: Actor::User(user.clone()),
+        metadata: TxnMetadata {
+            request_id: Some(request_id.to_string()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    transact_or_propose(pool, collab, &task.project_id, &origin, |doc, txn| {
+        let found = doc.get(txn, &task.task_id)?;
+        if found.is_managed(txn)? {
+            return Err(bad_request_error(
+                "MANAGED_TASK",
+                "Tasks managed by a plugin can't be updated",
+            ));
+        }
+        let config = doc.config();
+        let current = match found.get_status(txn)? {
+            Some(status) => config.status_category(txn, &status)?,
+            None => Some(WorkflowCategory::NotStarted),
+        };
+        if current == Some(category) {
+            return Ok(());
+        }
+        let mut status = None;
+        for s in config.statuses(txn)? {
+            if config.status_category(txn, &s)? == Some(category) {
+                status = Some(s);
+                break;
+            }
+        }
+        let Some(status) = status else {
+            return Err(bad_request_error(
+                "INVALID_STATUS",
+                "The project has no status for the change",
+            ));
+        };
+        found.set_status(txn, Some(&status));
+        found.set_status_time(txn, Some(Utc::now().timestamp_millis()));
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+fn depth(headers: &HeaderMap) -> u32 {
+    match headers.get("Depth").and_then(|h| h.to_str().ok()) {
+        Some("0") => 0,
+        _ => 1,
+    }
+}
+
+/// Extracts the hrefs of a calendar-multiget REPORT. Returns none for other
+/// reports, which are answered with every task.
+fn multiget_hrefs(body: &str) -> Vec<String> {
+    if !body.contains("calendar-multiget") {
+        return Vec::new();
+    }
+    let mut hrefs = Vec::new();
+    let mut rest = body;
+    while let Some(start) = rest.find("href>") {
+        rest = &rest[start + "href>".len()..];
+        let Some(end) = rest.find('<') else {
+            break;
+        };
+        let href = rest[..end].trim();
+        if !href.is_empty() {
+            hrefs.push(href.replace("%7E", "~").replace("%7e", "~"));
+        }
+        rest = &rest[end..];
+    }
+    hrefs
+}
+
+/// Maps the VTODO's STATUS to a category. COMPLETED without a STATUS is
+/// treated as completed too, as some clients only set that.
+fn parse_status(ics: &str) -> Option<WorkflowCategory> {
+    let unfolded = ics.replace("\r\n ", "").replace("\n ", "");
+    let mut completed = false;
+    for line in unfolded.lines() {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let name = name.split(';').next().unwrap_or(name);
+        match (name.to_ascii_uppercase().as_str(), value.trim()) {
+            ("STATUS", "NEEDS-ACTION") => return Some(WorkflowCategory::NotStarted),
+            ("STATUS", "IN-PROCESS") => return Some(WorkflowCategory::InProgress),
+            ("STATUS", "COMPLETED") => return Some(WorkflowCategory::Done),
+            ("STATUS", _) => return None,
+            ("COMPLETED", _) => completed = true,
+            _ => {}
+        }
+    }
+    Some(if completed {
+        WorkflowCategory::Done
+    } else {
+        WorkflowCategory::NotStarted
+    })
+}
+
+/// Renders the task as a VTODO. Statuses are categorized with the built-in
+/// statuses, as custom workflow states live in the doc, and others are
+/// rendered as needing action.
+fn render_vtodo(task: &CalTask, now: DateTime<Utc>) -> String {
+    let category = task.status.as_deref().and_then(|s| status_category(&[], s));
+    let status = match category {
+        Some(WorkflowCategory::Done) => "COMPLETED",
+        Some(WorkflowCategory::InProgress) => "IN-PROCESS",
+        _ => "NEEDS-ACTION",
+    };
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:{PRODID}"),
+        "BEGIN:VTODO".to_string(),
+        format!("UID:{}~{}@koso.app", task.project_id, task.task_id),
+        format!("DTSTAMP:{}", format_time(now)),
+        format!("LAST-MODIFIED:{}", format_time(task.update_time)),
+        format!("SUMMARY:{}", escape_text(&task.name)),
+        format!("STATUS:{status}"),
+        format!("URL:{}", zapier::task_url(&task.project_id, &task.task_id)),
+    ];
+    if category == Some(WorkflowCategory::Done) {
+        lines.push(format!("COMPLETED:{}", format_time(task.update_time)));
+    }
+    if let Some(due) = task
+        .deadline_millis
+        .and_then(DateTime::<Utc>::from_timestamp_millis)
+    {
+        lines.push(format!("DUE:{}", format_time(due)));
+    }
+    lines.push("END:VTODO".to_string());
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|line| fold(line) + "\r\n").collect()
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+        .replace('\r', "")
+}
+
+/// Folds lines longer than 75 octets, without splitting characters.
+fn fold(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            len = 1;
+        }
+        out.push(c);
+        len += c.len_utf8();
+    }
+    out
+}
+
+fn collection_response(tasks: &[CalTask]) -> String {
+    let ctag = tasks
+        .iter()
+        .map(|t| t.update_time.timestamp_millis())
+        .max()
+        .unwrap_or(0);
+    prop_response(
+        COLLECTION_HREF,
+        &format!(
+            "<d:resourcetype><d:collection/><c:calendar/></d:resourcetype>\
+             <d:displayname>Koso tasks</d:displayname>\
+             <c:supported-calendar-component-set><c:comp name=\"VTODO\"/></c:supported-calendar-component-set>\
+             <cs:getctag>{ctag}-{}</cs:getctag>",
+            tasks.len()
+        ),
+    )
+}
+
+fn prop_response(href: &str, props: &str) -> String {
+    format!(
+        "<d:response><d:href>{}</d:href><d:propstat><d:prop>\
+         <d:current-user-principal><d:href>{ROOT_HREF}</d:href></d:current-user-principal>\
+         <c:calendar-home-set><d:href>{ROOT_HREF}</d:href></c:calendar-home-set>\
+         {props}</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>",
+        escape_html(href)
+    )
+}
+
+fn multistatus_response(responses: &[String]) -> Response {
+    (
+        StatusCode::MULTI_STATUS,
+        [
+            (CONTENT_TYPE, "application/xml; charset=utf-8"),
+            (DAV_HEADER, DAV),
+        ],
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+             <d:multistatus xmlns:d=\"DAV:\" xmlns:c=\"urn:ietf:params:xml:ns:caldav\" \
+             xmlns:cs=\"http://calendarserver.org/ns/\">{}</d:multistatus>",
+            responses.concat()
+        ),
+    )
+        .into_response()
+}
+
+fn options_response() -> Response {
+    (
+        StatusCode::OK,
+        [
+            (DAV_HEADER, DAV),
+            (ALLOW, "OPTIONS, GET, HEAD, PUT, PROPFIND, REPORT"),
+        ],
+    )
+        .into_response()
+}
+
+fn challenge_response() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(WWW_AUTHENTICATE, "Basic realm=\"Koso\", charset=\"UTF-8\"")],
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::types::chrono::TimeZone as _;
+
+    #[test_log::test]
+    fn renders_and_parses_vtodos() {
+        let time = Utc.with_ymd_and_hms(2025, 9, 1, 12, 30, 0).unwrap();
+        let task = CalTask {
+            project_id: "p1".to_string(),
+            task_id: "t1".to_string(),
+            name: "Ship it, then; celebrate".to_string(),
+            status: Some("Done".to_string()),
+            deadline_millis: Some(time.timestamp_millis()),
+            update_time: time,
+        };
+        let ics = render_vtodo(&task, time);
+        assert!(ics.contains("UID:p1~t1@koso.app\r\n"));
+        assert!(ics.contains("SUMMARY:Ship it\\, then\\; celebrate\r\n"));
+        assert!(ics.contains("STATUS:COMPLETED\r\n"));
+        assert!(ics.contains("COMPLETED:20250901T123000Z\r\n"));
+        assert!(ics.contains("DUE:20250901T123000Z\r\n"));
+        assert_eq!(parse_status(&ics), Some(WorkflowCategory::Done));
+        assert_eq!(
+            parse_status("BEGIN:VTODO\r\nSTATUS:IN-PROCESS\r\nEND:VTODO\r\n"),
+            Some(WorkflowCategory::InProgress)
+        );
+        assert_eq!(
+            parse_status("BEGIN:VTODO\r\nSUMMARY:x\r\nEND:VTODO\r\n"),
+            Some(WorkflowCategory::NotStarted)
+        );
+        assert_eq!(parse_status("STATUS:CANCELLED\r\n"), None);
+
+        let folded = fold(&"é".repeat(50));
+        assert!(folded.split("\r\n").all(|line| line.len() <= 75));
+        assert_eq!(folded.replace("\r\n ", ""), "é".repeat(50));
+
+        assert_eq!(
+            multiget_hrefs(
+                "<c:calendar-multiget><d:href>/api/caldav/tasks/p1%7Et1.ics</d:href></c:calendar-multiget>"
+            ),
+            vec!["/api/caldav/tasks/p1~t1.ics"]
+        );
+        assert!(multiget_hrefs("<c:calendar-query/>").is_empty());
+    }
+}
@@ -5,37 +5,52 @@
 //!   - SYNC_RESPONSE
 //!   - SYNC_UPDATE -
 
-use crate::api::{
-    self,
-    collab::{
-        client::{CLOSE_UNAUTHORIZED, from_socket},
-        client_messages::{ClientMessage, ClientMessageProcessor},
-        doc_updates::{DocUpdate, DocUpdateProcessor},
-        projects_state::ProjectsState,
+use crate::{
+    api::{
+        self,
+        collab::{
+            anomalies::AnomalyDetector,
+            client::{CLOSE_UNAUTHORIZED, ConnectionInfo, from_socket},
+            doc_updates::{DocUpdate, DocUpdateProcessor, FollowUpProcessor},
+            projects_state::{ProjectsState, ResidentDoc, UserMessenger},
+            txn_origin::YOrigin,
+        },
+        demo,
+        google::User,
+        inbox::Inbox,
+        maintenance::Maintenance,
+        model::{Graph, ProjectId},
+        yproxy::YDocProxy,
     },
-    google::User,
-    model::{Graph, ProjectId},
-    yproxy::YDocProxy,
+    postgres::PgPool,
 };
 use anyhow::Error;
 use anyhow::Result;
 use axum::extract::ws::WebSocket;
 use notifications::{EventProcessor, KosoEvent};
 use projects_state::ProjectState;
-use sqlx::PgPool;
 use std::{sync::Arc, time::Duration};
 use tokio::sync::mpsc::{self};
 use tokio::time::sleep;
 use tokio_util::task::TaskTracker;
+use yrs::Update;
 
+pub(crate) mod anomalies;
+pub(crate) mod attribution;
 pub(crate) mod awareness;
+pub(crate) mod capabilities;
 pub(crate) mod client;
 pub(crate) mod client_messages;
+pub(crate) mod doc_migrations;
 pub(crate) mod doc_updates;
 pub(crate) mod msg_sync;
 pub(crate) mod notifications;
+pub(crate) mod progress;
+pub(crate) mod projections;
 pub(crate) mod projects_state;
 pub(crate) mod storage;
+pub(crate) mod task_history;
+pub(crate) mod tiering;
 pub(crate) mod txn_origin;
 
 #[derive(Clone)]
@@ -46,61 +61,83 @@ pub(crate) struct Collab {
 struct Inner {
     state: ProjectsState,
     pool: &'static PgPool,
+    maintenance: Maintenance,
+    anomalies: Arc<AnomalyDetector>,
     tracker: tokio_util::task::TaskTracker,
 }
 
 impl Collab {
     pub(crate) fn new(pool: &'static PgPool) -> Result<Collab> {
-        let (process_msg_tx, process_msg_rx) = mpsc::channel::<ClientMessage>(1);
         let (doc_update_tx, doc_update_rx) = mpsc::channel::<DocUpdate>(50);
+        let (follow_up_tx, follow_up_rx) = mpsc::channel::<DocUpdate>(50);
         let (event_tx, event_rx) = mpsc::channel::<KosoEvent>(50);
         let tracker = tokio_util::task::TaskTracker::new();
+        let maintenance = Maintenance::new(pool);
+        let anomalies = Arc::new(AnomalyDetector::new(pool));
         let collab = Collab {
             inner: Arc::new(Inner {
                 state: ProjectsState::new(
-                    process_msg_tx,
                     doc_update_tx,
                     event_tx,
                     pool,
+                    maintenance.clone(),
+                    Arc::clone(&anomalies),
                     tracker.clone(),
                 ),
                 pool,
+                maintenance,
+                anomalies: Arc::clone(&anomalies),
                 tracker,
             }),
         };
 
-        collab
-            .inner
-            .tracker
-            .spawn(DocUpdateProcessor::new(pool, doc_update_rx).process_doc_updates());
+        collab.inner.tracker.spawn(
+            DocUpdateProcessor::new(
+                pool,
+                doc_update_rx,
+                follow_up_tx,
+                collab.inner.tracker.clone(),
+            )
+            .process_doc_updates(),
+        );
 
-        collab
-            .inner
-            .tracker
-            .spawn(ClientMessageProcessor::new(process_msg_rx).process_messages());
+        collab.inner.tracker.spawn(
+            FollowUpProcessor::new(
+                pool,
+                follow_up_rx,
+                anomalies,
+                Inbox::new(pool, collab.inner.state.messenger()),
+            )
+            .process_follow_ups(),
+        );
 
-        collab
-            .inner
-            .tracker
-            .spawn(EventProcessor::new(pool, event_rx)?.process_events());
+        collab.inner.tracker.spawn(
+            EventProcessor::new(pool, event_rx, collab.inner.state.messenger())?.process_events(),
+        );
 
         Ok(collab)
     }
 
-    #[tracing::instrument(skip(self, socket, who, project_id, user))]
+    #[tracing::instrument(skip(self, socket, who, project_id, user, connection))]
     pub(super) async fn register_client(
         self,
         socket: WebSocket,
         who: String,
         project_id: ProjectId,
         user: User,
+        connection: ConnectionInfo,
     ) -> Result<()> {
         tracing::debug!("Registering client");
 
-        let (mut sender, receiver) = from_socket(socket, &who, &user, &project_id);
+        connection.capabilities.record_metrics();
+        let (mut sender, receiver) = from_socket(socket, &who, &user, &project_id, &connection);
 
         // Before doing anything else, make sure the user has access to the project.
-        if let Err(e) = api::verify_project_access(self.inner.pool, &user, &project_id).await {
+        let access = match &connection.quota {
+            Some(_) => demo::verify_guest_access(self.inner.pool, &project_id).await,
+            None => api::verify_project_access(self.inner.pool, &user, &project_id).await,
+        };
+        if let Err(e) = access {
             sender.close(CLOSE_UNAUTHORIZED, "Unauthorized.").await;
             return Err(e.as_err());
         }
@@ -126,6 +163,36 @@ impl Collab {
         Ok(LocalClient { project })
     }
 
+    /// Applies an update, e.g. one computed from another doc, to the project's live doc.
+    pub(crate) async fn apply_update(
+        &self,
+        project_id: &ProjectId,
+        origin: YOrigin,
+        update: Update,
+    ) -> Result<()> {
+        let client = self.register_local_client(project_id).await?;
+        client.project.apply_doc_update(origin, update).await
+    }
+
+    pub(crate) fn messenger(&self) -> UserMessenger {
+        self.inner.state.messenger()
+    }
+
+    /// Lists the projects whose docs are loaded in memory on this server.
+    pub(crate) async fn resident_docs(&self) -> Vec<ResidentDoc> {
+        self.inner.state.resident().await
+    }
+
+    pub(crate) fn maintenance(&self) -> &Maintenance {
+        &self.inner.maintenance
+    }
+
+    /// Whether the user's changes to the project are held for review after
+    /// they made unusually destructive changes.
+    pub(crate) fn is_held(&self, project_id: &ProjectId, email: &str) -> bool {
+        self.inner.anomalies.is_held(project_id, email)
+    }
+
     #[tracing::instrument(skip(self))]
     pub(crate) async fn stop(self) {
         tracing::debug!("Closing all clients...");
@@ -160,8 +227,14 @@ impl Collab {
         }
     }
 
-    pub(super) async fn get_graph(&self, project_id: &ProjectId) -> Result<Graph, Error> {
-        let (ydoc, _) = storage::load_doc(project_id, self.inner.pool).await?;
+    /// Loads the project's graph from storage, rather than the live doc, through
+    /// the given pool, e.g. a read replica.
+    pub(super) async fn get_graph(
+        &self,
+        pool: &PgPool,
+        project_id: &ProjectId,
+    ) -> Result<Graph, Error> {
+        let (ydoc, _) = storage::load_doc(project_id, pool).await?;
         let txn = ydoc.transact();
         ydoc.to_graph(&txn)
     }
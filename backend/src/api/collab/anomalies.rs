@@ -0,0 +1,280 @@
+use crate::{
+    api::{
+        google::User,
+        inbox::Inbox,
+        model::{InboxKind, ProjectId, TaskChange, TaskChangeKind, WorkingHours, parse_time_zone},
+        proposals,
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use chrono::{DateTime, Datelike as _, Timelike as _, Utc};
+use sqlx::types::Json;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Window over which a user's activity in a project is accumulated, and for
+/// which their changes are held once it's anomalous.
+const WINDOW: Duration = Duration::from_secs(10 * 60);
+const MAX_DELETED_TASKS: usize = 50;
+const MAX_OFF_HOURS_STATUS_CHANGES: usize = 50;
+
+/// The effect of an update on a project's tasks.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Impact {
+    deleted: usize,
+    status_changes: usize,
+}
+
+struct Activity {
+    time: Instant,
+    deleted: usize,
+    off_hours_status_changes: usize,
+}
+
+/// Flags users whose recent changes to a project delete or change unusually many
+/// tasks, e.g. an account deleting hundreds of tasks or mass changing statuses
+/// outside of the user's working hours. Activity is measured from the task changes
+/// of applied updates, so the update crossing the line is applied, and revertible
+/// if it removed many tasks, while the user's further changes are held for review.
+pub(super) struct AnomalyDetector {
+    pool: &'static PgPool,
+    windows: Mutex<HashMap<(ProjectId, String), Vec<Activity>>>,
+    /// When each user's changes to a project started being held.
+    held: Mutex<HashMap<(ProjectId, String), Instant>>,
+}
+
+impl AnomalyDetector {
+    pub(super) fn new(pool: &'static PgPool) -> Self {
+        AnomalyDetector {
+            pool,
+            windows: Mutex::new(HashMap::new()),
+            held: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether the user's changes to the project should be held rather than applied.
+    pub(super) fn is_held(&self, project_id: &ProjectId, email: &str) -> bool {
+        let now = Instant::now();
+        let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        held.retain(|_, since| now.duration_since(*since) < WINDOW);
+        held.contains_key(&(project_id.clone(), email.to_string()))
+    }
+
+    /// Records the impact of the user's applied changes. Returns a description of
+    /// the anomaly, and holds the user's further changes, if together with the
+    /// user's recent activity in the project they're anomalous.
+    pub(super) async fn check(
+        &self,
+        project_id: &ProjectId,
+        user: &User,
+        changes: &[TaskChange],
+    ) -> Result<Option<String>> {
+        let impact = impact(changes);
+        if impact == Impact::default() {
+            return Ok(None);
+        }
+        let off_hours = impact.status_changes > 0 && self.is_off_hours(&user.email).await?;
+        let anomaly = self.record(project_id, user, impact, off_hours);
+        if anomaly.is_some() {
+            self.held
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert((project_id.clone(), user.email.clone()), Instant::now());
+        }
+        Ok(anomaly)
+    }
+
+    fn record(
+        &self,
+        project_id: &ProjectId,
+        user: &User,
+        impact: Impact,
+        off_hours: bool,
+    ) -> Option<String> {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap_or_else(PoisonError::into_inner);
+        windows.retain(|_, activities| {
+            activities.retain(|a| now.duration_since(a.time) < WINDOW);
+            !activities.is_empty()
+        });
+        let activities = windows
+            .entry((project_id.clone(), user.email.clone()))
+            .or_default();
+        activities.push(Activity {
+            time: now,
+            deleted: impact.deleted,
+            off_hours_status_changes: if off_hours { impact.status_changes } else { 0 },
+        });
+
+        let deleted: usize = activities.iter().map(|a| a.deleted).sum();
+        if deleted > MAX_DELETED_TASKS {
+            activities.clear();
+            return Some(format!(
+                "{} deleted {deleted} tasks in under {} minutes",
+                user.email,
+                WINDOW.as_secs() / 60
+            ));
+        }
+        let off_hours_status_changes: usize =
+            activities.iter().map(|a| a.off_hours_status_changes).sum();
+        if off_hours_status_changes > MAX_OFF_HOURS_STATUS_CHANGES {
+            activities.clear();
+            return Some(format!(
+                "{} changed the status of {off_hours_status_changes} tasks outside of their working hours",
+                user.email
+            ));
+        }
+        None
+    }
+
+    async fn is_off_hours(&self, email: &str) -> Result<bool> {
+        let profile: Option<(Option<String>, Option<Json<WorkingHours>>)> =
+            sqlx::query_as("SELECT timezone, working_hours FROM users WHERE email = $1")
+                .bind(email)
+                .fetch_optional(self.pool)
+                .await
+                .context("Failed to fetch working hours")?;
+        Ok(match profile {
+            Some((timezone, Some(Json(working_hours)))) => {
+                is_outside_working_hours(&working_hours, timezone.as_deref(), Utc::now())
+            }
+            _ => false,
+        })
+    }
+
+    /// Alerts the project's admins that the user's changes are being held.
+    pub(super) async fn alert(
+        &self,
+        inbox: &Inbox,
+        project_id: &ProjectId,
+        user: &User,
+        anomaly: &str,
+    ) -> Result<()> {
+        tracing::warn!("Holding further changes to {project_id}: {anomaly}");
+        for admin in proposals::list_admins(self.pool, project_id).await? {
+            inbox
+                .deliver(
+                    &admin,
+                    InboxKind::Anomaly,
+                    Some(project_id),
+                    None,
+                    Some(&user.email),
+                    &format!("Holding further changes for review after unusual changes: {anomaly}"),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+fn impact(changes: &[TaskChange]) -> Impact {
+    let mut impact = Impact::default();
+    for change in changes {
+        match change.kind {
+            TaskChangeKind::Deleted => impact.deleted += 1,
+            TaskChangeKind::Updated if change.fields.iter().any(|f| f == "status") => {
+                impact.status_changes += 1
+            }
+            TaskChangeKind::Created | TaskChangeKind::Updated => {}
+        }
+    }
+    impact
+}
+
+fn is_outside_working_hours(
+    working_hours: &WorkingHours,
+    timezone: Option<&str>,
+    now: DateTime<Utc>,
+) -> bool {
+    let now = now.with_timezone(&parse_time_zone(timezone));
+    !working_hours.days.contains(&now.weekday())
+        || now.hour() < working_hours.start_hour
+        || now.hour() >= working_hours.end_hour
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone as _, Weekday};
+
+    fn change(kind: TaskChangeKind, fields: &[&str]) -> TaskChange {
+        TaskChange {
+            task_id: "1".to_string(),
+            kind,
+            fields: fields.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn impact_counts_deletes_and_status_changes() {
+        let changes = [
+            change(TaskChangeKind::Deleted, &[]),
+            change(TaskChangeKind::Updated, &["name", "status"]),
+            change(TaskChangeKind::Updated, &["name"]),
+            change(TaskChangeKind::Created, &["status"]),
+        ];
+        assert_eq!(
+            impact(&changes),
+            Impact {
+                deleted: 1,
+                status_changes: 1
+            }
+        );
+    }
+
+    #[test_log::test(sqlx::test)]
+    async fn check_holds_users_deleting_many_tasks(pool: sqlx::PgPool) {
+        let pool: &'static PgPool = Box::leak(Box::new(PgPool::from(pool)));
+        let detector = AnomalyDetector::new(pool);
+        let project_id = "project".to_string();
+        let user = User {
+            email: "a@koso.app".to_string(),
+            name: "A".to_string(),
+            picture: String::new(),
+            exp: 0,
+            iat: None,
+            auth_time: None,
+            nonce: None,
+        };
+        let deletes = vec![change(TaskChangeKind::Deleted, &[]); MAX_DELETED_TASKS];
+
+        assert_eq!(
+            detector.check(&project_id, &user, &deletes).await.unwrap(),
+            None
+        );
+        assert!(!detector.is_held(&project_id, &user.email));
+        assert!(
+            detector
+                .check(&project_id, &user, &deletes[..1])
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert!(detector.is_held(&project_id, &user.email));
+        assert!(!detector.is_held(&"other".to_string(), &user.email));
+    }
+
+    #[test]
+    fn is_outside_working_hours_respects_timezone() {
+        let working_hours = WorkingHours {
+            start_hour: 9,
+            end_hour: 17,
+            days: vec![Weekday::Mon, Weekday::Tue],
+        };
+        // Monday, 2025-07-28 at 15:00 UTC.
+        let now = Utc.with_ymd_and_hms(2025, 7, 28, 15, 0, 0).unwrap();
+        assert!(!is_outside_working_hours(&working_hours, None, now));
+        assert!(is_outside_working_hours(
+            &working_hours,
+            Some("Asia/Karachi"),
+            now
+        ));
+        // Sunday.
+        let now = Utc.with_ymd_and_hms(2025, 7, 27, 10, 0, 0).unwrap();
+        assert!(is_outside_working_hours(&working_hours, None, now));
+    }
+}
@@ -0,0 +1,163 @@
+//! Records who created and last modified each task, taken from the origins of
+//! the transactions that changed them. Reporters are set by hand and often
+//! wrong, so these are the reliable record.
+
+use super::txn_origin::Actor;
+use crate::{
+    api::model::{Graph, ProjectId, Task, TaskChange, TaskChangeKind},
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use sqlx::{
+    FromRow,
+    types::chrono::{DateTime, Utc},
+};
+
+#[derive(FromRow)]
+struct AttributionRow {
+    task_id: String,
+    created_by: Option<String>,
+    created_at: Option<DateTime<Utc>>,
+    last_modified_by: String,
+    last_modified_at: DateTime<Utc>,
+}
+
+/// Records the update's changes against the tasks they touched.
+pub(super) async fn record(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    actor: &Actor,
+    time: DateTime<Utc>,
+    changes: &[TaskChange],
+) -> Result<()> {
+    let Some(by) = actor_name(actor) else {
+        return Ok(());
+    };
+    let mut created = Vec::new();
+    let mut updated = Vec::new();
+    let mut deleted = Vec::new();
+    for change in changes {
+        match change.kind {
+            TaskChangeKind::Created => created.push(change.task_id.clone()),
+            TaskChangeKind::Updated => updated.push(change.task_id.clone()),
+            TaskChangeKind::Deleted => deleted.push(change.task_id.clone()),
+        }
+    }
+
+    let mut txn = pool.begin().await?;
+    if !deleted.is_empty() {
+        sqlx::query(
+            "
+            DELETE FROM task_attribution
+            WHERE project_id = $1 AND task_id IN (SELECT * FROM unnest($2))",
+        )
+        .bind(project_id)
+        .bind(&deleted)
+        .execute(&mut *txn)
+        .await
+        .context("Failed to delete task attribution")?;
+    }
+    // Tasks are sometimes re-added, e.g. when a removal is reverted, so keep the original creator.
+    if !created.is_empty() {
+        sqlx::query(
+            "
+            INSERT INTO task_attribution (project_id, task_id, created_by, created_at, last_modified_by, last_modified_at)
+            SELECT $1, task_id, $3, $4, $3, $4 FROM unnest($2) AS task_id
+            ON CONFLICT (project_id, task_id)
+            DO UPDATE SET
+                created_by = COALESCE(task_attribution.created_by, EXCLUDED.created_by),
+                created_at = COALESCE(task_attribution.created_at, EXCLUDED.created_at),
+                last_modified_by = EXCLUDED.last_modified_by,
+                last_modified_at = EXCLUDED.last_modified_at",
+        )
+        .bind(project_id)
+        .bind(&created)
+        .bind(&by)
+        .bind(time)
+        .execute(&mut *txn)
+        .await
+        .context("Failed to record task creation")?;
+    }
+    // Tasks created before attribution was recorded have no known creator.
+    if !updated.is_empty() {
+        sqlx::query(
+            "
+            INSERT INTO task_attribution (project_id, task_id, last_modified_by, last_modified_at)
+            SELECT $1, task_id, $3, $4 FROM unnest($2) AS task_id
+            ON CONFLICT (project_id, task_id)
+            DO UPDATE SET
+                last_modified_by = EXCLUDED.last_modified_by,
+                last_modified_at = EXCLUDED.last_modified_at",
+        )
+        .bind(project_id)
+        .bind(&updated)
+        .bind(&by)
+        .bind(time)
+        .execute(&mut *txn)
+        .await
+        .context("Failed to record task modification")?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+/// Sets the attribution fields of the graph's tasks.
+pub(crate) async fn annotate(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    graph: &mut Graph,
+) -> Result<()> {
+    for row in fetch(pool, project_id, None).await? {
+        if let Some(task) = graph.get_mut(&row.task_id) {
+            apply(task, row);
+        }
+    }
+    Ok(())
+}
+
+/// Sets the attribution fields of a single task.
+pub(crate) async fn annotate_task(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    task: &mut Task,
+) -> Result<()> {
+    if let Some(row) = fetch(pool, project_id, Some(&task.id)).await?.pop() {
+        apply(task, row);
+    }
+    Ok(())
+}
+
+async fn fetch(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    task_id: Option<&str>,
+) -> Result<Vec<AttributionRow>> {
+    sqlx::query_as(
+        "
+        SELECT task_id, created_by, created_at, last_modified_by, last_modified_at
+        FROM task_attribution
+        WHERE project_id = $1 AND ($2::varchar IS NULL OR task_id = $2)",
+    )
+    .bind(project_id)
+    .bind(task_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch task attribution")
+}
+
+fn apply(task: &mut Task, row: AttributionRow) {
+    task.created_by = row.created_by;
+    task.created_at = row.created_at;
+    task.last_modified_by = Some(row.last_modified_by);
+    task.last_modified_at = Some(row.last_modified_at);
+}
+
+/// Users, including agents acting on their behalf, are recorded by email.
+fn actor_name(actor: &Actor) -> Option<String> {
+    match actor {
+        Actor::User(user) | Actor::Agent(user) => Some(user.email.clone()),
+        Actor::GitHub => Some("github".to_string()),
+        Actor::Server => Some("koso".to_string()),
+        Actor::None => None,
+    }
+}
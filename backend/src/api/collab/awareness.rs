@@ -8,6 +8,26 @@ pub(crate) struct AwarenessUpdate {
     client_id: i64,
     sequence: i64,
     selected: Vec<String>,
+    /// The client's selection in a task's description, if it's editing one.
+    #[serde(default)]
+    pub(crate) desc_cursor: Option<DescCursor>,
+}
+
+/// A selection within a task description's YText. The anchor and head are
+/// base64 encoded relative positions (yrs sticky indexes) so they remain valid
+/// as the description is concurrently edited.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DescCursor {
+    pub(crate) task_id: String,
+    pub(crate) anchor: String,
+    pub(crate) head: String,
+    /// The anchor's absolute offset, as translated by the server.
+    #[serde(default)]
+    pub(crate) anchor_index: Option<u32>,
+    /// The head's absolute offset, as translated by the server.
+    #[serde(default)]
+    pub(crate) head_index: Option<u32>,
 }
 
 impl AwarenessUpdate {
@@ -16,6 +36,7 @@ impl AwarenessUpdate {
             client_id: self.client_id,
             sequence: self.sequence,
             selected: self.selected,
+            desc_cursor: self.desc_cursor,
             user: AwarenessUser {
                 email: user.email.clone(),
                 name: user.name.clone(),
@@ -25,16 +46,28 @@ impl AwarenessUpdate {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct AwarenessState {
     client_id: i64,
     sequence: i64,
     selected: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    desc_cursor: Option<DescCursor>,
     user: AwarenessUser,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl AwarenessState {
+    /// The state as clients that predate description cursors expect it.
+    pub(crate) fn without_desc_cursor(&self) -> AwarenessState {
+        AwarenessState {
+            desc_cursor: None,
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct AwarenessUser {
     pub(crate) email: String,
@@ -0,0 +1,185 @@
+//! Capabilities clients announce when connecting, in the `capabilities`
+//! query parameter, so the server can omit or adapt messages older clients
+//! don't understand rather than break them.
+
+use super::msg_sync::{
+    MSG_KOSO_AWARENESS, MSG_KOSO_BACKPRESSURE, MSG_KOSO_ERROR, MSG_KOSO_JOB_PROGRESS,
+    MSG_KOSO_MAINTENANCE, MSG_KOSO_NOTIFICATION, MSG_KOSO_PROGRESS, MSG_KOSO_UNREAD,
+};
+use std::collections::HashSet;
+
+/// Messages, and message fields, clients may understand beyond the sync
+/// protocol every client speaks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Capability {
+    Awareness,
+    /// Description cursors in awareness states.
+    DescCursors,
+    Notifications,
+    JobProgress,
+    Maintenance,
+    Backpressure,
+    Errors,
+    /// Subtask progress rollups.
+    Progress,
+    /// Unread and flagged task markers.
+    Unread,
+}
+
+impl Capability {
+    const ALL: [Capability; 9] = [
+        Capability::Awareness,
+        Capability::DescCursors,
+        Capability::Notifications,
+        Capability::JobProgress,
+        Capability::Maintenance,
+        Capability::Backpressure,
+        Capability::Errors,
+        Capability::Progress,
+        Capability::Unread,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Capability::Awareness => "awareness",
+            Capability::DescCursors => "descCursors",
+            Capability::Notifications => "notifications",
+            Capability::JobProgress => "jobProgress",
+            Capability::Maintenance => "maintenance",
+            Capability::Backpressure => "backpressure",
+            Capability::Errors => "errors",
+            Capability::Progress => "progress",
+            Capability::Unread => "unread",
+        }
+    }
+
+    /// The capability needed to understand the encoded message, if any.
+    fn required_by(msg: &[u8]) -> Option<Capability> {
+        // Message types are below 128, so their var encoding is one byte.
+        match *msg.first()? {
+            MSG_KOSO_AWARENESS => Some(Capability::Awareness),
+            MSG_KOSO_NOTIFICATION => Some(Capability::Notifications),
+            MSG_KOSO_JOB_PROGRESS => Some(Capability::JobProgress),
+            MSG_KOSO_MAINTENANCE => Some(Capability::Maintenance),
+            MSG_KOSO_BACKPRESSURE => Some(Capability::Backpressure),
+            MSG_KOSO_ERROR => Some(Capability::Errors),
+            MSG_KOSO_PROGRESS => Some(Capability::Progress),
+            MSG_KOSO_UNREAD => Some(Capability::Unread),
+            _ => None,
+        }
+    }
+}
+
+/// What a client announced about itself.
+#[derive(Clone, Debug)]
+pub(crate) struct Capabilities {
+    supported: HashSet<Capability>,
+    /// The client's version, for metrics, or "unknown".
+    pub(crate) version: String,
+}
+
+impl Default for Capabilities {
+    /// Clients that predate the handshake only understand sync and awareness
+    /// messages, and fail on any other.
+    fn default() -> Self {
+        Capabilities {
+            supported: HashSet::from([Capability::Awareness]),
+            version: "unknown".to_string(),
+        }
+    }
+}
+
+/// Versions longer than this, or with unexpected characters, are reported as
+/// "invalid" to bound the cardinality of version metrics.
+const MAX_VERSION_CHARS: usize = 32;
+
+impl Capabilities {
+    /// Parses the announced, comma-separated capabilities, ignoring unknown
+    /// ones from newer clients.
+    pub(crate) fn parse(capabilities: Option<&str>, version: Option<&str>) -> Self {
+        let mut parsed = Capabilities::default();
+        if let Some(capabilities) = capabilities {
+            parsed.supported = capabilities
+                .split(',')
+                .filter_map(|name| {
+                    Capability::ALL
+                        .into_iter()
+                        .find(|c| c.name() == name.trim())
+                })
+                .collect();
+        }
+        if let Some(version) = version {
+            let valid = version.len() <= MAX_VERSION_CHARS
+                && version
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+'));
+            parsed.version = if valid { version } else { "invalid" }.to_string();
+        }
+        parsed
+    }
+
+    pub(crate) fn supports(&self, capability: Capability) -> bool {
+        self.supported.contains(&capability)
+    }
+
+    /// Whether the client understands the encoded message.
+    pub(crate) fn understands(&self, msg: &[u8]) -> bool {
+        Capability::required_by(msg).is_none_or(|c| self.supports(c))
+    }
+
+    /// Counts the connection in metrics by client version and capability.
+    pub(crate) fn record_metrics(&self) {
+        metrics::counter!("collab_client_connections_total", "version" => self.version.clone())
+            .increment(1);
+        for capability in &self.supported {
+            metrics::counter!("collab_client_capabilities_total", "capability" => capability.name())
+                .increment(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::collab::msg_sync::{
+        MSG_KOSO_BACKPRESSURE_PROJECT, koso_awareness_state, koso_backpressure, koso_error,
+        koso_job_progress, koso_maintenance, koso_notification, koso_progress, koso_unread,
+        sync_update,
+    };
+
+    #[test_log::test]
+    fn omits_unsupported_messages() {
+        let legacy = Capabilities::parse(None, None);
+        assert!(legacy.understands(&sync_update(&[])));
+        assert!(legacy.supports(Capability::Awareness));
+        assert!(!legacy.understands(&koso_progress("{}")));
+
+        let announced = Capabilities::parse(Some("progress, unknown"), Some("1.2.3"));
+        assert!(announced.understands(&koso_progress("{}")));
+        assert!(!announced.supports(Capability::Awareness));
+        assert_eq!(announced.version, "1.2.3");
+
+        assert_eq!(
+            Capabilities::parse(None, Some("<script>")).version,
+            "invalid"
+        );
+    }
+
+    #[test_log::test]
+    fn legacy_clients_only_get_sync_and_awareness() {
+        let legacy = Capabilities::parse(None, Some("0.1.0"));
+        assert!(legacy.understands(&sync_update(&[])));
+        assert!(legacy.understands(&koso_awareness_state("{}")));
+        for msg in [
+            koso_notification("{}"),
+            koso_job_progress("{}"),
+            koso_maintenance("{}"),
+            koso_backpressure(MSG_KOSO_BACKPRESSURE_PROJECT, true),
+            koso_error("{}"),
+            koso_progress("{}"),
+            koso_unread("{}"),
+        ] {
+            assert!(!legacy.understands(&msg), "Legacy client got {msg:?}");
+        }
+    }
+}
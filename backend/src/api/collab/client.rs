@@ -1,7 +1,11 @@
-use crate::api::{google::User, model::ProjectId};
-use axum::extract::ws::{CloseCode, CloseFrame, Message, WebSocket};
+use super::capabilities::Capabilities;
+use crate::api::{demo::SessionQuota, google::User, model::ProjectId};
+use axum::{
+    body::Bytes,
+    extract::ws::{CloseCode, CloseFrame, Message, WebSocket},
+};
 use futures::SinkExt as _;
-use std::fmt;
+use std::{fmt, sync::Arc};
 
 /// Splits a socket into a read, ClientReceiver, and write, ClientSender, side.
 pub(super) fn from_socket(
@@ -9,6 +13,7 @@ pub(super) fn from_socket(
     who: &str,
     user: &User,
     project_id: &ProjectId,
+    connection: &ConnectionInfo,
 ) -> (ClientSender, ClientReceiver) {
     use futures::stream::StreamExt;
     let (ws_sender, ws_receiver) = socket.split();
@@ -16,17 +21,38 @@ pub(super) fn from_socket(
         ClientSender {
             ws_sender,
             who: who.to_owned(),
+            email: user.email.clone(),
             project_id: project_id.clone(),
+            capabilities: connection.capabilities.clone(),
         },
         ClientReceiver {
             ws_receiver,
             who: who.to_owned(),
             user: user.clone(),
+            device: connection.device.clone(),
+            request_id: connection.request_id.clone(),
+            read_only: connection.read_only,
+            quota: connection.quota.clone(),
             project_id: project_id.clone(),
         },
     )
 }
 
+/// Details of the HTTP request that opened a client's connection.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ConnectionInfo {
+    /// The client's user agent.
+    pub(crate) device: Option<String>,
+    pub(crate) request_id: Option<String>,
+    /// Set for connections of impersonating admins, whose changes are refused.
+    pub(crate) read_only: bool,
+    /// What the client announced it understands.
+    pub(crate) capabilities: Capabilities,
+    /// Set for anonymous demo sessions, which are limited by the quota
+    /// rather than project membership.
+    pub(crate) quota: Option<Arc<SessionQuota>>,
+}
+
 // https://www.rfc-editor.org/rfc/rfc6455.html#section-7.4.1
 // https://www.iana.org/assignments/websocket/websocket.xhtml#close-code-number
 pub(super) const CLOSE_NORMAL: u16 = 1000;
@@ -48,12 +74,20 @@ pub(super) struct ClientClosure {
 pub(super) struct ClientSender {
     ws_sender: futures::stream::SplitSink<WebSocket, Message>,
     pub(super) who: String,
+    pub(super) email: String,
     pub(super) project_id: ProjectId,
+    pub(super) capabilities: Capabilities,
 }
 
 impl ClientSender {
-    pub(super) async fn send(&mut self, data: Vec<u8>) -> Result<(), axum::Error> {
-        self.ws_sender.send(Message::Binary(data.into())).await
+    /// Sends the message, or silently drops it if the client wouldn't
+    /// understand it.
+    pub(super) async fn send(&mut self, data: impl Into<Bytes>) -> Result<(), axum::Error> {
+        let data = data.into();
+        if !self.capabilities.understands(&data) {
+            return Ok(());
+        }
+        self.ws_sender.send(Message::Binary(data)).await
     }
 
     /// Send the close frame and close the socket.
@@ -95,6 +129,10 @@ pub(super) struct ClientReceiver {
     ws_receiver: futures::stream::SplitStream<WebSocket>,
     pub(super) who: String,
     pub(super) user: User,
+    pub(super) device: Option<String>,
+    pub(super) request_id: Option<String>,
+    pub(super) read_only: bool,
+    pub(super) quota: Option<Arc<SessionQuota>>,
     pub(super) project_id: ProjectId,
 }
 
@@ -1,51 +1,97 @@
-use crate::api::{
-    collab::{
-        awareness::AwarenessUpdate,
-        client::{CLOSE_ERROR, CLOSE_NORMAL, ClientClosure, ClientReceiver},
-        msg_sync::{
-            MSG_KOSO_AWARENESS, MSG_KOSO_AWARENESS_UPDATE, MSG_SYNC, MSG_SYNC_REQUEST,
-            MSG_SYNC_RESPONSE, MSG_SYNC_UPDATE, sync_response,
+use crate::{
+    api::{
+        collab::{
+            anomalies::AnomalyDetector,
+            awareness::AwarenessUpdate,
+            client::{CLOSE_ERROR, CLOSE_NORMAL, ClientClosure, ClientReceiver, OVERLOADED},
+            msg_sync::{
+                ClientFrame, MSG_SYNC_UPDATE, decode_client_frame, koso_error, koso_maintenance,
+                sync_response, sync_update,
+            },
+            projects_state::{ProjectState, UserMessenger},
+            txn_origin::{Actor, TxnMetadata, YOrigin},
         },
-        projects_state::ProjectState,
-        txn_origin::{Actor, YOrigin},
+        context::RequestContext,
+        demo,
+        errors::{CodedError, ErrorCode, ErrorFrame},
+        google::User,
+        inbox::Inbox,
+        maintenance::Maintenance,
+        proposals,
     },
-    google::User,
+    postgres::PgPool,
 };
-use anyhow::{Result, anyhow};
-use axum::extract::ws::Message;
+use anyhow::Result;
+use axum::{body::Bytes, extract::ws::Message};
 use rand::random;
-use std::{fmt, ops::ControlFlow, sync::Arc, time::Duration};
-use tokio::sync::mpsc::Receiver;
-use tokio::{sync::mpsc::Sender, time::timeout};
-use uuid::Uuid;
-use yrs::{
-    StateVector, Update,
-    encoding::read::Read as _,
-    updates::decoder::{Decode as _, DecoderV1},
+use std::{
+    fmt,
+    ops::ControlFlow,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed},
+    },
+    time::Duration,
 };
+use tokio::sync::{Semaphore, mpsc::Receiver};
+use tokio::time::timeout;
+use uuid::Uuid;
+
+/// How many projects' messages are processed at once, across all projects.
+const WORKERS: usize = 4;
+/// Bytes a connection may have queued but unprocessed before it's asked to hold back.
+const SEND_WINDOW: usize = 1024 * 1024;
+/// Bytes a connection may have queued but unprocessed before it's disconnected.
+/// Also the largest message accepted.
+pub(crate) const MAX_PENDING_BYTES: usize = 16 * 1024 * 1024;
+
+/// Accounts for the bytes a connection has queued but not yet processed, so
+/// clients that flood updates are throttled and, if they don't back off,
+/// disconnected rather than buffered without bound.
+#[derive(Default)]
+pub(super) struct SendWindow {
+    pending: AtomicUsize,
+    throttled: AtomicBool,
+}
+
+impl SendWindow {
+    /// Reserves room for a message of `len` bytes. Returns whether the
+    /// connection needs to be throttled, or an error if it's over its cap.
+    fn reserve(&self, len: usize) -> Result<bool, usize> {
+        let pending = self.pending.fetch_add(len, Relaxed) + len;
+        if pending > MAX_PENDING_BYTES {
+            self.pending.fetch_sub(len, Relaxed);
+            return Err(pending);
+        }
+        Ok(pending > SEND_WINDOW && !self.throttled.swap(true, Relaxed))
+    }
+
+    /// Releases a processed message of `len` bytes. Returns whether the
+    /// connection caught up and can be unthrottled.
+    fn release(&self, len: usize) -> bool {
+        let pending = self.pending.fetch_sub(len, Relaxed) - len;
+        pending <= SEND_WINDOW / 2 && self.throttled.swap(false, Relaxed)
+    }
+}
 
 /// ClientMessageReceiver receives messages from clients
-/// about a particular project and forwards the binary ones to
-/// process_msg_tx for handling by `ClientMessageProcessor`.
+/// about a particular project and queues the binary ones on the
+/// project for handling by `ClientMessageProcessor`.
 ///
 /// When clients disconnect, perhaps by closing their browser tab,
 /// we'll recieve a Close message and remove the client.
 pub(super) struct ClientMessageReceiver {
     project: Arc<ProjectState>,
-    process_msg_tx: Sender<ClientMessage>,
     receiver: ClientReceiver,
+    window: Arc<SendWindow>,
 }
 
 impl ClientMessageReceiver {
-    pub(super) fn new(
-        project: Arc<ProjectState>,
-        process_msg_tx: Sender<ClientMessage>,
-        receiver: ClientReceiver,
-    ) -> Self {
+    pub(super) fn new(project: Arc<ProjectState>, receiver: ClientReceiver) -> Self {
         ClientMessageReceiver {
             project,
-            process_msg_tx,
             receiver,
+            window: Arc::default(),
         }
     }
 
@@ -105,18 +151,67 @@ impl ClientMessageReceiver {
     ) -> ControlFlow<ClientClosure> {
         match msg {
             Ok(Message::Binary(data)) => {
+                if let Some(quota) = &self.receiver.quota {
+                    if let Err(reason) = quota.charge(data.len()) {
+                        return ControlFlow::Break(ClientClosure {
+                            code: OVERLOADED,
+                            reason,
+                            details: format!("Closing guest session: {reason}"),
+                            client_initiated: false,
+                        });
+                    }
+                }
+                match self.window.reserve(data.len()) {
+                    Ok(false) => {}
+                    Ok(true) => {
+                        tracing::debug!("Send window full, throttling client");
+                        if let Err(e) = self
+                            .project
+                            .send_backpressure(&self.receiver.who, true)
+                            .await
+                        {
+                            tracing::debug!("Failed to throttle client: {e:?}");
+                        }
+                    }
+                    Err(pending) => {
+                        tracing::warn!(
+                            "Disconnecting client with {pending} bytes pending, over the {MAX_PENDING_BYTES} byte cap"
+                        );
+                        return ControlFlow::Break(ClientClosure {
+                            code: OVERLOADED,
+                            reason: "Too many pending updates.",
+                            details: format!("Too many pending updates: {pending} bytes"),
+                            client_initiated: false,
+                        });
+                    }
+                }
+                let len = data.len();
                 if let Err(e) = self
-                    .process_msg_tx
-                    .send(ClientMessage {
+                    .project
+                    .enqueue(ClientMessage {
                         who: self.receiver.who.clone(),
                         user: self.receiver.user.clone(),
+                        device: self.receiver.device.clone(),
+                        request_id: self.receiver.request_id.clone(),
+                        read_only: self.receiver.read_only,
                         project: Arc::clone(&self.project),
                         id: Uuid::new_v4().to_string(),
-                        data: data.into(),
+                        window: Arc::clone(&self.window),
+                        data,
                     })
                     .await
                 {
-                    tracing::error!("Error sending message to process_msg channel: {e:?}");
+                    tracing::error!("Error queueing message for processing: {e:?}");
+                    // The message will never be processed, so give back its room.
+                    if self.window.release(len) {
+                        if let Err(e) = self
+                            .project
+                            .send_backpressure(&self.receiver.who, false)
+                            .await
+                        {
+                            tracing::debug!("Failed to unthrottle client: {e:?}");
+                        }
+                    }
                 };
                 ControlFlow::Continue(())
             }
@@ -158,82 +253,219 @@ impl ClientMessageReceiver {
 
 /// ClientMessageProcessor processes messages sent by ClientMessageReceiver.
 /// See the `api::collab::Collab` documentation for details on the protocol.
+///
+/// Each project has its own queue and worker, so a project's messages are
+/// applied in order while a busy project can't hold up the others.
 pub(super) struct ClientMessageProcessor {
-    process_msg_rx: Receiver<ClientMessage>,
+    workers: Semaphore,
+    pool: &'static PgPool,
+    inbox: Inbox,
+    anomalies: Arc<AnomalyDetector>,
+    maintenance: Maintenance,
 }
 
 impl ClientMessageProcessor {
-    pub(super) fn new(process_msg_rx: Receiver<ClientMessage>) -> Self {
-        ClientMessageProcessor { process_msg_rx }
+    pub(super) fn new(
+        pool: &'static PgPool,
+        messenger: UserMessenger,
+        maintenance: Maintenance,
+        anomalies: Arc<AnomalyDetector>,
+    ) -> Self {
+        ClientMessageProcessor {
+            workers: Semaphore::new(WORKERS),
+            pool,
+            inbox: Inbox::new(pool, messenger),
+            anomalies,
+            maintenance,
+        }
     }
 
-    #[tracing::instrument(skip(self))]
-    pub(super) async fn process_messages(mut self) {
+    /// Processes one project's queued messages until the project is dropped.
+    #[tracing::instrument(skip(self, process_msg_rx))]
+    pub(super) async fn process_messages(
+        self: Arc<Self>,
+        project_id: String,
+        mut process_msg_rx: Receiver<ClientMessage>,
+    ) {
         loop {
-            let Some(msg) = self.process_msg_rx.recv().await else {
+            let Some(msg) = process_msg_rx.recv().await else {
                 break;
             };
-            self.process_message(msg).await;
+            let project = Arc::clone(&msg.project);
+            let window = Arc::clone(&msg.window);
+            let who = msg.who.clone();
+            let len = msg.data.len();
+            {
+                // Each message waits its turn for a worker. The semaphore is fair,
+                // so busy projects take turns with quiet ones.
+                let _permit = self.workers.acquire().await;
+                self.process_message(msg).await;
+            }
+            if window.release(len) {
+                tracing::debug!("Client caught up, unthrottling it");
+                if let Err(e) = project.send_backpressure(&who, false).await {
+                    tracing::debug!("Failed to unthrottle client: {e:?}");
+                }
+            }
+            if process_msg_rx.is_empty() && project.throttled.swap(false, Relaxed) {
+                tracing::debug!("Caught up on queued messages, unthrottling clients");
+                project.broadcast_backpressure(false).await;
+            }
         }
-        tracing::info!("Stopped processing messages");
+        tracing::debug!("Stopped processing messages");
     }
 
     #[tracing::instrument(skip(self))]
     async fn process_message(&self, msg: ClientMessage) {
+        // Messages are processed by the project's worker rather than the
+        // connection's task, so re-establish the connection's context.
+        let context = RequestContext::new(
+            msg.request_id
+                .clone()
+                .unwrap_or_else(|| "MISSING".to_string()),
+            Some(&msg.project.project_id),
+        );
+        context.set_user(&msg.user);
+        context.scope(self.process_message_in_context(msg)).await
+    }
+
+    async fn process_message_in_context(&self, msg: ClientMessage) {
+        let (project, who) = (Arc::clone(&msg.project), msg.who.clone());
         if let Err(e) = self.process_message_internal(msg).await {
             tracing::warn!("Failed to process message: {e:?}");
+            // Tell the client what went wrong, so it can react to the code.
+            let frame = ErrorFrame::from_error(&e);
+            match serde_json::to_string(&frame) {
+                Ok(frame) => {
+                    if let Err(e) = project.send_msg(&who, koso_error(&frame)).await {
+                        tracing::debug!("Failed to send error frame: {e:?}");
+                    }
+                }
+                Err(e) => tracing::warn!("Failed to serialize error frame: {e:?}"),
+            }
         }
     }
 
     async fn process_message_internal(&self, msg: ClientMessage) -> Result<()> {
-        let mut decoder = DecoderV1::from(msg.data.as_slice());
-        match decoder.read_var()? {
-            MSG_SYNC => match decoder.read_var()? {
-                MSG_SYNC_REQUEST => {
-                    tracing::debug!("Handling sync_request message");
-                    let update = msg
-                        .project
-                        .encode_state_as_update(&StateVector::decode_v1(decoder.read_buf()?)?)
-                        .await?;
+        // Decode everything, updates included, up front so malformed messages
+        // never reach the project's doc, peers or pending proposals.
+        let frame = decode_client_frame(&msg.data)
+            .map_err(|e| CodedError::new(ErrorCode::MalformedMessage, format!("{e:#}")))?;
+        // Impersonating admins view the project without changing it or
+        // appearing present to its members.
+        if msg.read_only && !matches!(frame, ClientFrame::SyncRequest(_)) {
+            return Err(
+                CodedError::new(ErrorCode::Forbidden, "The connection is read-only").into(),
+            );
+        }
+        match frame {
+            ClientFrame::SyncRequest(sv) => {
+                tracing::debug!("Handling sync_request message");
+                let update = msg.project.encode_state_as_update(&sv).await?;
 
-                    // Respond to the client with a sync_response message containing
-                    // changes known to the server but not the client.
-                    // There's no need to broadcast such updates to others or perist them.
-                    tracing::debug!("Sending synce_response message to client.");
-                    msg.project
-                        .send_msg(&msg.who, sync_response(&update))
-                        .await?;
-                    Ok(())
+                // Respond to the client with a sync_response message containing
+                // changes known to the server but not the client.
+                // There's no need to broadcast such updates to others or perist them.
+                tracing::debug!("Sending synce_response message to client.");
+                msg.project
+                    .send_msg(&msg.who, sync_response(&update))
+                    .await?;
+                msg.project.send_progress(&msg.who).await?;
+                // Impersonators and demo guests would only litter read state.
+                if !msg.read_only && !demo::is_guest(&msg.user) {
+                    msg.project.send_unread(&msg.who, &msg.user.email).await?;
                 }
-                MSG_SYNC_RESPONSE | MSG_SYNC_UPDATE => {
-                    tracing::debug!("Handling sync_update|sync_response message");
-                    let update = Update::decode_v2(decoder.read_buf()?)?;
+                Ok(())
+            }
+            ClientFrame::Sync {
+                sync_type,
+                data,
+                update,
+            } => {
+                tracing::debug!("Handling sync_update|sync_response message");
+                // Drop changes to frozen projects, telling the client why so it
+                // can go read-only until the freeze lifts.
+                if let Some(freeze) = self.maintenance.frozen(Some(&msg.project.project_id)) {
+                    tracing::debug!("Rejecting update during maintenance");
                     msg.project
-                        .apply_doc_update(
-                            YOrigin {
-                                who: msg.who,
-                                id: msg.id,
-                                actor: Actor::User(msg.user),
-                            },
-                            update,
-                        )
+                        .send_msg(&msg.who, koso_maintenance(&serde_json::to_string(&freeze)?))
                         .await?;
-                    Ok(())
+                    return Ok(());
                 }
-                invalid_type => Err(anyhow!("Invalid sync type: {invalid_type}")),
-            },
-            MSG_KOSO_AWARENESS => match decoder.read_var()? {
-                MSG_KOSO_AWARENESS_UPDATE => {
-                    let update: AwarenessUpdate = serde_json::from_str(decoder.read_string()?)?;
-                    tracing::debug!("{update:?}");
-                    msg.project
-                        .update_awareness(&msg.who, &msg.user, update)
-                        .await?;
-                    Ok(())
+                // Hold changes to review protected projects for review rather than
+                // applying them to the live doc.
+                if proposals::requires_review(self.pool, &msg.project.project_id, &msg.user.email)
+                    .await?
+                {
+                    tracing::debug!("Buffering update for review");
+                    return proposals::propose_update(
+                        self.pool,
+                        &self.inbox,
+                        &msg.project.project_id,
+                        &msg.user,
+                        None,
+                        data,
+                    )
+                    .await;
                 }
-                invalid_type => Err(anyhow!("Invalid Koso awareness type: {invalid_type}")),
-            },
-            invalid_type => Err(anyhow!("Invalid message protocol type: {invalid_type}")),
+                // Likewise hold the changes of users who just made unusually
+                // destructive changes until an admin reviews them.
+                if self
+                    .anomalies
+                    .is_held(&msg.project.project_id, &msg.user.email)
+                {
+                    tracing::debug!("Holding update after anomalous changes");
+                    return proposals::propose_update(
+                        self.pool,
+                        &self.inbox,
+                        &msg.project.project_id,
+                        &msg.user,
+                        None,
+                        data,
+                    )
+                    .await;
+                }
+                msg.project
+                    .apply_doc_update(
+                        YOrigin {
+                            who: msg.who.clone(),
+                            id: msg.id.clone(),
+                            actor: Actor::User(msg.user.clone()),
+                            metadata: TxnMetadata {
+                                device: msg.device.clone(),
+                                request_id: msg.request_id.clone(),
+                                feature: Some("sync".to_string()),
+                            },
+                            relayed: true,
+                        },
+                        update,
+                    )
+                    .await?;
+                // Relay the applied update to peers as the client framed it, rather
+                // than broadcasting the server's re-encoding. Sync responses must be
+                // re-framed, as clients treat them as the end of their initial sync.
+                let frame = match sync_type {
+                    MSG_SYNC_UPDATE => msg.data.clone(),
+                    _ => Bytes::from(sync_update(data)),
+                };
+                msg.project
+                    .broadcast_update(msg.who.clone(), msg.data.slice_ref(data), frame)
+                    .await;
+                Ok(())
+            }
+            ClientFrame::AwarenessUpdate(update) => {
+                let update: AwarenessUpdate = serde_json::from_str(&update).map_err(|e| {
+                    CodedError::new(
+                        ErrorCode::MalformedMessage,
+                        format!("Invalid awareness: {e}"),
+                    )
+                })?;
+                tracing::debug!("{update:?}");
+                msg.project
+                    .update_awareness(&msg.who, &msg.user, update)
+                    .await?;
+                Ok(())
+            }
         }
     }
 }
@@ -241,11 +473,19 @@ impl ClientMessageProcessor {
 pub(super) struct ClientMessage {
     pub(super) who: String,
     pub(super) user: User,
+    /// User agent of the client's connection.
+    pub(super) device: Option<String>,
+    /// Id of the request that opened the client's connection.
+    pub(super) request_id: Option<String>,
+    /// Whether the client's connection is read-only.
+    pub(super) read_only: bool,
     pub(super) project: Arc<ProjectState>,
     /// Unique ID associated with this update.
     pub(super) id: String,
+    /// The send window of the client's connection.
+    pub(super) window: Arc<SendWindow>,
     /// Binary contents of the client message.
-    pub(super) data: Vec<u8>,
+    pub(super) data: Bytes,
 }
 
 impl fmt::Debug for ClientMessage {
@@ -258,3 +498,25 @@ impl fmt::Debug for ClientMessage {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn send_window_throttles_once_and_caps() {
+        let window = SendWindow::default();
+        assert_eq!(window.reserve(SEND_WINDOW), Ok(false));
+        assert_eq!(window.reserve(1), Ok(true));
+        // Already throttled.
+        assert_eq!(window.reserve(1), Ok(false));
+        assert_eq!(
+            window.reserve(MAX_PENDING_BYTES),
+            Err(SEND_WINDOW + 2 + MAX_PENDING_BYTES)
+        );
+        // The rejected message isn't counted.
+        assert!(!window.release(1));
+        assert!(window.release(SEND_WINDOW));
+        assert!(!window.release(1));
+    }
+}
@@ -0,0 +1,298 @@
+//! Versioned migrations of the doc schema, e.g. rewriting legacy fields. Each
+//! doc records the latest migration applied to it in its config and pending
+//! migrations are applied lazily, in one server transaction, when the doc is
+//! first loaded. Before a migration changes anything, the doc is snapshotted
+//! so the migration can be rolled back.
+
+use crate::{
+    api::{
+        collab::{
+            storage,
+            txn_origin::{Actor, YOrigin},
+        },
+        model::ProjectId,
+        yproxy::YDocProxy,
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use serde::Serialize;
+use yrs::{ReadTxn as _, StateVector, TransactionMut, Update, updates::decoder::Decode as _};
+
+pub(crate) struct DocMigration {
+    pub(crate) version: u32,
+    pub(crate) name: &'static str,
+    /// Rewrites the doc, returning the number of changes made.
+    migrate: fn(&YDocProxy, &mut TransactionMut) -> Result<usize>,
+}
+
+/// All migrations, in version order. Never reorder or remove released migrations.
+pub(crate) const MIGRATIONS: &[DocMigration] = &[
+    DocMigration {
+        version: 1,
+        name: "legacy_deadlines",
+        migrate: migrate_legacy_deadlines,
+    },
+    DocMigration {
+        version: 2,
+        name: "estimate_units",
+        migrate: migrate_estimates,
+    },
+    DocMigration {
+        version: 3,
+        name: "deadline_time_zones",
+        migrate: migrate_deadline_offsets,
+    },
+];
+
+pub(crate) fn current_version() -> u32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+fn migrate_legacy_deadlines(doc: &YDocProxy, txn: &mut TransactionMut) -> Result<usize> {
+    doc.migrate_legacy_deadlines(txn)
+}
+
+fn migrate_deadline_offsets(doc: &YDocProxy, txn: &mut TransactionMut) -> Result<usize> {
+    doc.migrate_deadline_offsets(txn)
+}
+
+fn migrate_estimates(doc: &YDocProxy, txn: &mut TransactionMut) -> Result<usize> {
+    doc.migrate_estimates(txn)
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AppliedMigration {
+    pub(crate) version: u32,
+    pub(crate) name: &'static str,
+    pub(crate) changes: usize,
+}
+
+/// The outcome of applying a doc's pending migrations to a copy of it.
+#[derive(Debug)]
+pub(crate) struct MigrationPlan {
+    pub(crate) from_version: u32,
+    pub(crate) to_version: u32,
+    pub(crate) applied: Vec<AppliedMigration>,
+    /// The doc's state before migrating.
+    before: Vec<u8>,
+    /// The update taking the doc from `before` to migrated.
+    update: Vec<u8>,
+}
+
+impl MigrationPlan {
+    fn changes(&self) -> usize {
+        self.applied.iter().map(|m| m.changes).sum()
+    }
+}
+
+fn origin(project_id: &ProjectId) -> YOrigin {
+    YOrigin {
+        who: "doc_migrations".to_string(),
+        id: project_id.to_string(),
+        actor: Actor::Server,
+        ..Default::default()
+    }
+}
+
+/// Applies the doc's pending migrations to a copy of it, leaving the doc
+/// untouched. Returns None if the doc is up to date.
+pub(crate) fn plan(project_id: &ProjectId, doc: &YDocProxy) -> Result<Option<MigrationPlan>> {
+    let from_version = doc.config().get_schema_version(&doc.transact())?;
+    let pending: Vec<&DocMigration> = MIGRATIONS
+        .iter()
+        .filter(|m| m.version > from_version)
+        .collect();
+    let Some(to_version) = pending.last().map(|m| m.version) else {
+        return Ok(None);
+    };
+    let (before, sv) = {
+        let txn = doc.transact();
+        (
+            txn.encode_state_as_update_v2(&StateVector::default()),
+            txn.state_vector(),
+        )
+    };
+
+    let copy = YDocProxy::new();
+    let mut applied = Vec::with_capacity(pending.len());
+    {
+        let mut txn = copy.transact_mut_with(origin(project_id).as_origin()?);
+        txn.apply_update(Update::decode_v2(&before)?)?;
+        for migration in pending {
+            let changes = (migration.migrate)(&copy, &mut txn)
+                .with_context(|| format!("Doc migration {} failed", migration.name))?;
+            applied.push(AppliedMigration {
+                version: migration.version,
+                name: migration.name,
+                changes,
+            });
+        }
+        copy.config().set_schema_version(&mut txn, to_version)?;
+    }
+    let update = copy.transact().encode_state_as_update_v2(&sv);
+    Ok(Some(MigrationPlan {
+        from_version,
+        to_version,
+        applied,
+        before,
+        update,
+    }))
+}
+
+/// Applies the doc's pending migrations, unless a migration of it was rolled
+/// back. Migrations that change anything are snapshotted first.
+#[tracing::instrument(skip(pool, doc))]
+pub(super) async fn migrate(pool: &PgPool, project_id: &ProjectId, doc: &YDocProxy) -> Result<()> {
+    // A new project's doc has nothing to migrate, and stamping its version
+    // would hand its first clients a server change before they've synced.
+    // Migrations leave current docs as they are, so it's migrated once loaded
+    // with content instead.
+    if doc.transact().state_vector() == StateVector::default() {
+        return Ok(());
+    }
+    let Some(plan) = plan(project_id, doc)? else {
+        return Ok(());
+    };
+    if rolled_back(pool, project_id).await? {
+        tracing::debug!(
+            "Skipping doc migrations to version {} since a migration was rolled back",
+            plan.to_version
+        );
+        return Ok(());
+    }
+
+    if plan.changes() > 0 {
+        sqlx::query(
+            "
+            INSERT INTO doc_migration_snapshots (id, project_id, from_version, to_version, update_v2)
+            VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(project_id)
+        .bind(plan.from_version as i32)
+        .bind(plan.to_version as i32)
+        .bind(&plan.before)
+        .execute(pool)
+        .await
+        .context("Failed to snapshot doc before migrating")?;
+    }
+
+    {
+        let mut txn = doc.transact_mut_with(origin(project_id).as_origin()?);
+        txn.apply_update(Update::decode_v2(&plan.update)?)
+            .context("Failed to apply doc migrations")?;
+    }
+    for applied in &plan.applied {
+        metrics::counter!("doc_migrations_applied_total", "migration" => applied.name).increment(1);
+    }
+    tracing::info!(
+        "Migrated doc from version {} to {}: {:?}",
+        plan.from_version,
+        plan.to_version,
+        plan.applied
+    );
+    Ok(())
+}
+
+async fn rolled_back(pool: &PgPool, project_id: &ProjectId) -> Result<bool> {
+    let (rolled_back,): (bool,) = sqlx::query_as(
+        "
+        SELECT EXISTS (
+            SELECT 1 FROM doc_migration_snapshots
+            WHERE project_id = $1 AND rollback_time IS NOT NULL
+        )",
+    )
+    .bind(project_id)
+    .fetch_one(pool)
+    .await
+    .context("Failed to check for rolled back doc migrations")?;
+    Ok(rolled_back)
+}
+
+/// Plans migrations of the project's stored doc without loading it for editing.
+pub(crate) async fn dry_run(
+    pool: &PgPool,
+    project_id: &ProjectId,
+) -> Result<Option<MigrationPlan>> {
+    let (doc, _) = storage::load_doc(project_id, pool).await?;
+    plan(project_id, &doc)
+}
+
+/// Reverts what the migrations from the snapshot's version changed: tasks and
+/// config the migrations modified are restored to their state in the snapshot,
+/// discarding any later edits to them, and the schema version is reset.
+/// Returns the number of tasks restored.
+pub(crate) fn rollback(
+    doc: &YDocProxy,
+    txn: &mut TransactionMut,
+    project_id: &ProjectId,
+    snapshot: &[u8],
+) -> Result<usize> {
+    let before = YDocProxy::new();
+    {
+        let mut before_txn = before.transact_mut_with(origin(project_id).as_origin()?);
+        before_txn.apply_update(Update::decode_v2(snapshot)?)?;
+    }
+    let Some(plan) = plan(project_id, &before)? else {
+        return Ok(0);
+    };
+    let after = YDocProxy::new();
+    {
+        let mut after_txn = after.transact_mut_with(origin(project_id).as_origin()?);
+        after_txn.apply_update(Update::decode_v2(&plan.before)?)?;
+        after_txn.apply_update(Update::decode_v2(&plan.update)?)?;
+    }
+
+    let (before_graph, before_config) = {
+        let txn = before.transact();
+        (before.to_graph(&txn)?, before.config().get(&txn)?)
+    };
+    let (after_graph, after_config) = {
+        let txn = after.transact();
+        (after.to_graph(&txn)?, after.config().get(&txn)?)
+    };
+
+    let mut restored = 0;
+    for (id, task) in &before_graph {
+        if after_graph.get(id) == Some(task) || doc.get(txn, id).is_err() {
+            continue;
+        }
+        doc.set(txn, task);
+        restored += 1;
+    }
+    if before_config != after_config {
+        doc.config().set(txn, &before_config)?;
+    }
+    doc.config().set_schema_version(txn, plan.from_version)?;
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn plan_brings_doc_to_current_version() {
+        let project_id = "project".to_string();
+        let doc = YDocProxy::new();
+        let plan = plan(&project_id, &doc).unwrap().unwrap();
+        assert_eq!(plan.from_version, 0);
+        assert_eq!(plan.to_version, current_version());
+        assert_eq!(plan.changes(), 0);
+        // Planning doesn't touch the doc.
+        assert_eq!(doc.config().get_schema_version(&doc.transact()).unwrap(), 0);
+
+        {
+            let mut txn = doc.transact_mut_with(origin(&project_id).as_origin().unwrap());
+            txn.apply_update(Update::decode_v2(&plan.update).unwrap())
+                .unwrap();
+        }
+        assert_eq!(
+            doc.config().get_schema_version(&doc.transact()).unwrap(),
+            current_version()
+        );
+        assert!(super::plan(&project_id, &doc).unwrap().is_none());
+    }
+}
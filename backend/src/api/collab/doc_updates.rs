@@ -1,31 +1,66 @@
-use crate::api::collab::{msg_sync::sync_update, storage};
-use crate::api::collab::{projects_state::ProjectState, txn_origin::from_origin};
-use crate::api::yproxy::YTaskProxy;
+use crate::{
+    api::collab::{anomalies::AnomalyDetector, attribution, progress, storage},
+    api::collab::{projects_state::ProjectState, txn_origin::from_origin},
+    api::{
+        google::User,
+        inbox::Inbox,
+        model::{TaskChange, TaskChangeKind},
+        reverts::{self, Removal},
+        webhooks::{self, ChangeEvent},
+        yproxy::{RemovedTasks, YTaskProxy},
+    },
+    postgres::PgPool,
+};
 use anyhow::{Context, Result};
-use sqlx::PgPool;
-use std::{fmt, sync::Arc};
+use axum::body::Bytes;
+use sqlx::types::chrono::{DateTime, Utc};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, PoisonError},
+};
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::mpsc::Sender;
 use tokio_util::task::TaskTracker;
 use tracing::Instrument;
 use yrs::Map as _;
-use yrs::types::map::MapEvent;
+use yrs::types::{EntryChange, Event, Events, PathSegment, map::MapEvent};
 
+use super::msg_sync::sync_update;
 use super::projects_state::{DocBox, DocBoxProvider};
-use super::txn_origin::YOrigin;
+use super::txn_origin::{self, Actor, TxnMetadata, YOrigin};
 
 // Handles updates applied to a project doc and forward them to the doc_update_tx
 // for handling by the `DocUpdateProcessor`.
 pub(super) struct DocObserver {
     doc_update_tx: Sender<DocUpdate>,
     tracker: TaskTracker,
+    /// Task changes collected from the current transaction's deep graph events.
+    changes: std::sync::Mutex<CollectedChanges>,
+    /// Tasks snapshotted by the doc before the current transaction removed them.
+    removed: RemovedTasks,
 }
 
 impl DocObserver {
-    pub(super) fn new(doc_update_tx: Sender<DocUpdate>, tracker: TaskTracker) -> Self {
+    pub(super) fn new(
+        doc_update_tx: Sender<DocUpdate>,
+        tracker: TaskTracker,
+        removed: RemovedTasks,
+    ) -> Self {
         DocObserver {
             doc_update_tx,
             tracker,
+            changes: std::sync::Mutex::new(CollectedChanges::default()),
+            removed,
+        }
+    }
+
+    /// Callback invoked on deep graph events. yrs emits these before the transaction's
+    /// update event, so the collected changes are attached to that update.
+    pub(super) fn collect_changes(&self, txn: &yrs::TransactionMut, events: &Events) {
+        let mut changes = self.changes.lock().unwrap_or_else(PoisonError::into_inner);
+        for event in events.iter() {
+            collect_event_changes(txn, event, &mut changes);
         }
     }
 
@@ -38,6 +73,9 @@ impl DocObserver {
         txn: &yrs::TransactionMut,
         event: &yrs::UpdateEvent,
     ) {
+        let changes =
+            std::mem::take(&mut *self.changes.lock().unwrap_or_else(PoisonError::into_inner))
+                .changes;
         let origin = match from_origin(txn.origin()) {
             Ok(o) => o,
             Err(e) => {
@@ -45,11 +83,22 @@ impl DocObserver {
                 return;
             }
         };
+        let feature = origin.feature().to_string();
+        let removal = reverts::removal(self.removed.take());
         let update = DocUpdate {
             who: origin.who,
             project,
             id: origin.id,
-            data: event.update.clone(),
+            data: event.update.clone().into(),
+            actor: origin.actor,
+            metadata: TxnMetadata {
+                feature: Some(feature),
+                ..origin.metadata
+            },
+            time: Utc::now(),
+            changes,
+            removal,
+            relayed: origin.relayed,
         };
 
         let doc_update_tx = self.doc_update_tx.clone();
@@ -67,16 +116,27 @@ impl DocObserver {
 /// DocUpdateProcessor receives doc updates from a channel
 /// and 1) persists them to the DB, and 2) broadcasts them
 /// to other clients connected for the given project.
+/// Everything else, e.g. attribution, is left to the `FollowUpProcessor`
+/// so it doesn't delay broadcasts.
 pub(super) struct DocUpdateProcessor {
     pool: &'static PgPool,
     doc_update_rx: Receiver<DocUpdate>,
+    follow_up_tx: Sender<DocUpdate>,
+    tracker: TaskTracker,
 }
 
 impl DocUpdateProcessor {
-    pub(super) fn new(pool: &'static PgPool, doc_update_rx: Receiver<DocUpdate>) -> Self {
+    pub(super) fn new(
+        pool: &'static PgPool,
+        doc_update_rx: Receiver<DocUpdate>,
+        follow_up_tx: Sender<DocUpdate>,
+        tracker: TaskTracker,
+    ) -> Self {
         DocUpdateProcessor {
             pool,
             doc_update_rx,
+            follow_up_tx,
+            tracker,
         }
     }
 
@@ -99,13 +159,132 @@ impl DocUpdateProcessor {
     }
 
     async fn process_doc_update_internal(&self, update: DocUpdate) -> Result<()> {
-        storage::persist_update(&update.project.project_id, &update.data, self.pool)
+        storage::persist_update(&update, self.pool)
             .await
             .context("Failed to persist update")?;
-        update
-            .project
-            .broadcast_msg(sync_update(&update.data), Some(&update.who))
-            .await;
+        if progress::affected_by(&update.changes) {
+            update.project.schedule_progress_refresh();
+        }
+        if !update.relayed {
+            let frame = sync_update(&update.data).into();
+            update
+                .project
+                .broadcast_update(update.who.clone(), update.data.clone(), frame)
+                .await;
+        }
+
+        // Like the observer, hand off without waiting on a full queue.
+        let follow_up_tx = self.follow_up_tx.clone();
+        self.tracker.spawn(
+            async move {
+                if let Err(e) = follow_up_tx.send(update).await {
+                    tracing::error!("Failed to send to follow_up channel: {e:?}");
+                }
+            }
+            .in_current_span(),
+        );
+        Ok(())
+    }
+}
+
+/// FollowUpProcessor receives persisted doc updates from a channel and does
+/// the work that can wait until after they're broadcast: recording removals
+/// and attribution, delivering webhooks and checking for anomalies.
+pub(super) struct FollowUpProcessor {
+    pool: &'static PgPool,
+    follow_up_rx: Receiver<DocUpdate>,
+    anomalies: Arc<AnomalyDetector>,
+    inbox: Inbox,
+}
+
+impl FollowUpProcessor {
+    pub(super) fn new(
+        pool: &'static PgPool,
+        follow_up_rx: Receiver<DocUpdate>,
+        anomalies: Arc<AnomalyDetector>,
+        inbox: Inbox,
+    ) -> Self {
+        FollowUpProcessor {
+            pool,
+            follow_up_rx,
+            anomalies,
+            inbox,
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub(super) async fn process_follow_ups(mut self) {
+        loop {
+            let Some(update) = self.follow_up_rx.recv().await else {
+                break;
+            };
+            self.process_follow_up(update).await;
+        }
+        tracing::info!("Stopped processing doc update follow ups");
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn process_follow_up(&self, update: DocUpdate) {
+        if let Some(removal) = &update.removal {
+            if let Err(e) = reverts::record_removal(
+                self.pool,
+                &update.project.project_id,
+                &update.id,
+                update.actor.email().unwrap_or(&update.who),
+                removal,
+            )
+            .await
+            {
+                tracing::warn!("Failed to record removal: {e:?}");
+            }
+        }
+        if let Err(e) = attribution::record(
+            self.pool,
+            &update.project.project_id,
+            &update.actor,
+            update.time,
+            &update.changes,
+        )
+        .await
+        {
+            tracing::warn!("Failed to record attribution: {e:?}");
+        }
+        webhooks::deliver(
+            self.pool,
+            ChangeEvent {
+                project_id: update.project.project_id.clone(),
+                txn_id: update.id.clone(),
+                actor: update.actor.kind(),
+                email: update.actor.email().map(String::from),
+                plugin: txn_origin::plugin_of(&update.who),
+                feature: update
+                    .metadata
+                    .feature
+                    .clone()
+                    .unwrap_or_else(|| update.who.clone()),
+                create_time: update.time,
+                changes: update.changes.clone(),
+            },
+        );
+        if let Actor::User(user) = &update.actor {
+            if let Err(e) = self.check_anomalies(&update, user).await {
+                tracing::warn!("Failed to check for anomalies: {e:?}");
+            }
+        }
+    }
+
+    /// Alerts the project's admins if the user's update looks anomalous.
+    async fn check_anomalies(&self, update: &DocUpdate, user: &User) -> Result<()> {
+        let project_id = &update.project.project_id;
+        if let Some(anomaly) = self
+            .anomalies
+            .check(project_id, user, &update.changes)
+            .await?
+        {
+            self.anomalies
+                .alert(&self.inbox, project_id, user, &anomaly)
+                .await?;
+        }
         Ok(())
     }
 }
@@ -119,7 +298,107 @@ pub(super) struct DocUpdate {
     pub(super) id: String,
     /// A yrs Update in the v2 encoding.
     /// Can be decoded via Update::decode_v2.
-    pub(super) data: Vec<u8>,
+    pub(super) data: Bytes,
+    pub(super) actor: Actor,
+    /// Metadata from the transaction's origin, with the feature always set.
+    pub(super) metadata: TxnMetadata,
+    /// When the update was applied.
+    pub(super) time: DateTime<Utc>,
+    /// The tasks changed by the update.
+    pub(super) changes: Vec<TaskChange>,
+    /// The tasks the update removed, if enough to record for revert.
+    pub(super) removal: Option<Removal>,
+    /// Whether the update was relayed to peers as the client sent it, so it
+    /// isn't broadcast again. Follow-up changes the server makes aren't.
+    pub(super) relayed: bool,
+}
+
+/// Task changes in the order they were first seen, indexed by task id.
+#[derive(Default)]
+struct CollectedChanges {
+    changes: Vec<TaskChange>,
+    index: HashMap<String, usize>,
+}
+
+fn collect_event_changes(txn: &yrs::TransactionMut, event: &Event, changes: &mut CollectedChanges) {
+    let path = event.path();
+    let mut keys = path.iter().filter_map(|segment| match segment {
+        PathSegment::Key(key) => Some(key.to_string()),
+        PathSegment::Index(_) => None,
+    });
+    match (keys.next(), keys.next()) {
+        // Tasks added to or removed from the graph.
+        (None, _) => {
+            let Event::Map(event) = event else {
+                return;
+            };
+            for (task_id, change) in event.keys(txn).iter() {
+                let kind = match change {
+                    EntryChange::Removed(_) => TaskChangeKind::Deleted,
+                    EntryChange::Inserted(_) | EntryChange::Updated(_, _) => {
+                        TaskChangeKind::Created
+                    }
+                };
+                record_change(changes, task_id, kind, None);
+            }
+        }
+        // Fields set on a task.
+        (Some(task_id), None) => {
+            let Event::Map(event) = event else {
+                return;
+            };
+            for field in event.keys(txn).keys() {
+                record_change(
+                    changes,
+                    &task_id,
+                    TaskChangeKind::Updated,
+                    Some(field.as_ref()),
+                );
+            }
+        }
+        // Changes nested within a field, e.g. children.
+        (Some(task_id), Some(field)) => {
+            record_change(changes, &task_id, TaskChangeKind::Updated, Some(&field));
+        }
+    }
+}
+
+/// Merges the change into any existing change to the same task.
+fn record_change(
+    changes: &mut CollectedChanges,
+    task_id: &str,
+    kind: TaskChangeKind,
+    field: Option<&str>,
+) {
+    let i = match changes.index.get(task_id) {
+        Some(&i) => i,
+        None => {
+            changes.changes.push(TaskChange {
+                task_id: task_id.to_string(),
+                kind,
+                fields: vec![],
+            });
+            let i = changes.changes.len() - 1;
+            changes.index.insert(task_id.to_string(), i);
+            i
+        }
+    };
+    let change = &mut changes.changes[i];
+    match (change.kind, kind) {
+        (_, TaskChangeKind::Deleted) => {
+            change.kind = TaskChangeKind::Deleted;
+            change.fields.clear();
+        }
+        (TaskChangeKind::Updated, TaskChangeKind::Created) => change.kind = TaskChangeKind::Created,
+        _ => (),
+    }
+    if let Some(field) = field {
+        if change.kind != TaskChangeKind::Deleted {
+            if let Err(i) = change.fields.binary_search_by(|f| f.as_str().cmp(field)) {
+                change.fields.insert(i, field.to_string());
+            }
+        }
+    }
 }
 
 impl fmt::Debug for DocUpdate {
@@ -129,6 +408,7 @@ impl fmt::Debug for DocUpdate {
             .field("who", &self.who)
             .field("id", &self.id)
             .field("data.len()", &self.data.len())
+            .field("metadata", &self.metadata)
             .finish()
     }
 }
@@ -248,6 +528,7 @@ mod tests {
             who: "graph_observer_test".into(),
             id: "test1".into(),
             actor: Actor::None,
+            ..Default::default()
         }
         .as_origin()
         .unwrap();
@@ -311,4 +592,39 @@ mod tests {
             self.db.lock().await
         }
     }
+
+    #[test]
+    fn record_change_merges_changes_to_a_task() {
+        use super::{CollectedChanges, record_change};
+        use crate::api::model::{TaskChange, TaskChangeKind};
+
+        let mut changes = CollectedChanges::default();
+        record_change(&mut changes, "1", TaskChangeKind::Updated, Some("name"));
+        record_change(&mut changes, "2", TaskChangeKind::Created, None);
+        record_change(&mut changes, "1", TaskChangeKind::Updated, Some("assignee"));
+        record_change(&mut changes, "1", TaskChangeKind::Updated, Some("name"));
+        record_change(&mut changes, "2", TaskChangeKind::Updated, Some("num"));
+        record_change(&mut changes, "3", TaskChangeKind::Updated, Some("name"));
+        record_change(&mut changes, "3", TaskChangeKind::Deleted, None);
+        assert_eq!(
+            changes.changes,
+            vec![
+                TaskChange {
+                    task_id: "1".into(),
+                    kind: TaskChangeKind::Updated,
+                    fields: vec!["assignee".into(), "name".into()],
+                },
+                TaskChange {
+                    task_id: "2".into(),
+                    kind: TaskChangeKind::Created,
+                    fields: vec!["num".into()],
+                },
+                TaskChange {
+                    task_id: "3".into(),
+                    kind: TaskChangeKind::Deleted,
+                    fields: vec![],
+                },
+            ]
+        );
+    }
 }
@@ -1,7 +1,18 @@
+//! Encoding and decoding of the websocket protocol's messages. Kept free of
+//! project state, and of dependencies beyond yrs, so decoding can be tested
+//! and fuzzed in isolation. See backend/fuzz.
+
+use anyhow::{Result, anyhow};
 use yrs::{
-    StateVector,
-    encoding::write::Write as _,
-    updates::encoder::{Encode as _, Encoder as _, EncoderV1},
+    StateVector, Update,
+    encoding::{
+        read::{Cursor, Read as _},
+        write::Write as _,
+    },
+    updates::{
+        decoder::Decode as _,
+        encoder::{Encode as _, Encoder as _, EncoderV1},
+    },
 };
 
 pub(crate) const MSG_SYNC: u8 = 0;
@@ -15,6 +26,25 @@ pub(crate) const MSG_KOSO_AWARENESS: u8 = 8;
 pub(crate) const MSG_KOSO_AWARENESS_UPDATE: u8 = 0;
 pub(crate) const MSG_KOSO_AWARENESS_STATE: u8 = 1;
 
+pub(crate) const MSG_KOSO_NOTIFICATION: u8 = 9;
+
+pub(crate) const MSG_KOSO_JOB_PROGRESS: u8 = 10;
+
+pub(crate) const MSG_KOSO_MAINTENANCE: u8 = 11;
+
+pub(crate) const MSG_KOSO_BACKPRESSURE: u8 = 12;
+
+pub(crate) const MSG_KOSO_ERROR: u8 = 13;
+
+pub(crate) const MSG_KOSO_PROGRESS: u8 = 14;
+
+pub(crate) const MSG_KOSO_UNREAD: u8 = 15;
+
+/// The project's queue is full, affecting all of its clients.
+pub(crate) const MSG_KOSO_BACKPRESSURE_PROJECT: u8 = 0;
+/// The connection has too many unprocessed bytes in flight.
+pub(crate) const MSG_KOSO_BACKPRESSURE_CONNECTION: u8 = 1;
+
 pub(crate) fn sync_request(sv: &StateVector) -> Vec<u8> {
     let mut encoder = EncoderV1::new();
     encoder.write_var(MSG_SYNC);
@@ -46,3 +76,151 @@ pub(crate) fn koso_awareness_state(state: &str) -> Vec<u8> {
     encoder.write_string(state);
     encoder.to_vec()
 }
+
+pub(crate) fn koso_notification(notification: &str) -> Vec<u8> {
+    let mut encoder = EncoderV1::new();
+    encoder.write_var(MSG_KOSO_NOTIFICATION);
+    encoder.write_string(notification);
+    encoder.to_vec()
+}
+
+pub(crate) fn koso_job_progress(progress: &str) -> Vec<u8> {
+    let mut encoder = EncoderV1::new();
+    encoder.write_var(MSG_KOSO_JOB_PROGRESS);
+    encoder.write_string(progress);
+    encoder.to_vec()
+}
+
+pub(crate) fn koso_maintenance(freeze: &str) -> Vec<u8> {
+    let mut encoder = EncoderV1::new();
+    encoder.write_var(MSG_KOSO_MAINTENANCE);
+    encoder.write_string(freeze);
+    encoder.to_vec()
+}
+
+/// Tells clients to hold back updates until the project's queue, or their
+/// connection's send window, drains.
+pub(crate) fn koso_backpressure(scope: u8, throttled: bool) -> Vec<u8> {
+    let mut encoder = EncoderV1::new();
+    encoder.write_var(MSG_KOSO_BACKPRESSURE);
+    encoder.write_var(scope);
+    encoder.write_var(throttled as u8);
+    encoder.to_vec()
+}
+
+/// Tells the client one of its messages failed. `error` is a JSON object with
+/// the error's code and detail.
+pub(crate) fn koso_error(error: &str) -> Vec<u8> {
+    let mut encoder = EncoderV1::new();
+    encoder.write_var(MSG_KOSO_ERROR);
+    encoder.write_string(error);
+    encoder.to_vec()
+}
+
+/// Tells clients the subtask progress of tasks. `progress` is a JSON object
+/// flagging whether it's a full snapshot, and mapping task ids to their
+/// progress, or to null once they have none.
+pub(crate) fn koso_progress(progress: &str) -> Vec<u8> {
+    let mut encoder = EncoderV1::new();
+    encoder.write_var(MSG_KOSO_PROGRESS);
+    encoder.write_string(progress);
+    encoder.to_vec()
+}
+
+/// Tells a client which tasks its user hasn't read since others changed them,
+/// and which they flagged. `markers` is a JSON object with both lists of task ids.
+pub(crate) fn koso_unread(markers: &str) -> Vec<u8> {
+    let mut encoder = EncoderV1::new();
+    encoder.write_var(MSG_KOSO_UNREAD);
+    encoder.write_string(markers);
+    encoder.to_vec()
+}
+
+/// A message from a client, fully decoded, including any update, so malformed
+/// messages are rejected before anything acts on them.
+pub(crate) enum ClientFrame<'a> {
+    SyncRequest(StateVector),
+    /// A sync response or update. `data` is the update as the client encoded
+    /// it, borrowed from the message.
+    Sync {
+        sync_type: u8,
+        data: &'a [u8],
+        update: Update,
+    },
+    /// JSON encoded awareness.
+    AwarenessUpdate(String),
+}
+
+pub(crate) fn decode_client_frame(msg: &[u8]) -> Result<ClientFrame<'_>> {
+    let mut decoder = Cursor::new(msg);
+    match decoder.read_var()? {
+        MSG_SYNC => match decoder.read_var()? {
+            MSG_SYNC_REQUEST => Ok(ClientFrame::SyncRequest(StateVector::decode_v1(
+                decoder.read_buf()?,
+            )?)),
+            sync_type @ (MSG_SYNC_RESPONSE | MSG_SYNC_UPDATE) => {
+                let data = read_borrowed_buf(&mut decoder)?;
+                Ok(ClientFrame::Sync {
+                    sync_type,
+                    data,
+                    update: Update::decode_v2(data)?,
+                })
+            }
+            invalid_type => Err(anyhow!("Invalid sync type: {invalid_type}")),
+        },
+        MSG_KOSO_AWARENESS => match decoder.read_var()? {
+            MSG_KOSO_AWARENESS_UPDATE => Ok(ClientFrame::AwarenessUpdate(
+                decoder.read_string()?.to_string(),
+            )),
+            invalid_type => Err(anyhow!("Invalid Koso awareness type: {invalid_type}")),
+        },
+        invalid_type => Err(anyhow!("Invalid message protocol type: {invalid_type}")),
+    }
+}
+
+/// Reads a length-prefixed buffer, borrowed from the message rather than the
+/// cursor so it outlives the cursor.
+fn read_borrowed_buf<'a>(cursor: &mut Cursor<'a>) -> Result<&'a [u8]> {
+    let len: u32 = cursor.read_var()?;
+    let buf = cursor
+        .buf
+        .get(cursor.next..)
+        .and_then(|rest| rest.get(..len as usize))
+        .ok_or_else(|| anyhow!("Buffer of {len} bytes overruns the message"))?;
+    cursor.next += buf.len();
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yrs::{Doc, Map as _, ReadTxn as _, Transact as _};
+
+    #[test]
+    fn decode_client_frame_decodes_updates() {
+        let doc = Doc::new();
+        let map = doc.get_or_insert_map("graph");
+        map.insert(&mut doc.transact_mut(), "id1", "task");
+        let update = doc
+            .transact()
+            .encode_state_as_update_v2(&StateVector::default());
+
+        let msg = sync_update(&update);
+        let Ok(ClientFrame::Sync {
+            sync_type, data, ..
+        }) = decode_client_frame(&msg)
+        else {
+            panic!("Expected a sync frame");
+        };
+        assert_eq!(sync_type, MSG_SYNC_UPDATE);
+        assert_eq!(data, update.as_slice());
+    }
+
+    #[test]
+    fn decode_client_frame_rejects_malformed_messages() {
+        assert!(decode_client_frame(&[]).is_err());
+        assert!(decode_client_frame(&[MSG_SYNC, 7]).is_err());
+        // A truncated update.
+        assert!(decode_client_frame(&[MSG_SYNC, MSG_SYNC_UPDATE, 10, 0]).is_err());
+    }
+}
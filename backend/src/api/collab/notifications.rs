@@ -1,23 +1,32 @@
 use super::{
-    projects_state::ProjectState,
+    projections,
+    projects_state::{ProjectState, UserMessenger},
+    task_history,
     txn_origin::{YOrigin, from_origin},
 };
 use crate::{
     api::{
+        auto_assign, away,
         collab::txn_origin::Actor,
         google::User,
-        model::Task,
+        groups::{self, GroupAssignment},
+        inbox::Inbox,
+        model::{InboxKind, Task, parse_time_zone},
+        oncall,
         yproxy::{YDocProxy, YTaskProxy},
     },
-    notifiers::Notifier,
+    i18n::Locale,
+    notifiers::Notifiers,
+    postgres::{PgPool, list_project_users},
 };
 use anyhow::{Context, Result, anyhow};
-use sqlx::PgPool;
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use sqlx::types::chrono::Utc;
 use std::{collections::HashMap, fmt, sync::Arc, time::SystemTime};
 use tokio::sync::mpsc::Receiver;
 use yrs::{
-    ReadTxn, TransactionMut,
-    types::{EntryChange, Event, Events, PathSegment},
+    Any, Out, ReadTxn, TransactionMut,
+    types::{Change, EntryChange, Event, Events, PathSegment},
 };
 
 #[derive(Debug)]
@@ -32,6 +41,10 @@ pub(super) struct KosoEvent {
 pub(super) enum KosoEventChanges {
     Task(HashMap<String, KosoEntryChange>),
     Children(),
+    /// A task was added to the graph.
+    Created(),
+    /// Children added to a task configured for auto assignment.
+    ChildrenAdded(Vec<String>),
 }
 
 pub(super) struct KosoEntryChange(EntryChange);
@@ -77,6 +90,27 @@ fn handle_deep_graph_update_event_internal(
 
     match event {
         yrs::types::Event::Map(map_event) => {
+            if map_event.path().is_empty() {
+                let origin = from_origin(txn.origin())?;
+                for (task_id, change) in map_event.keys(txn).iter() {
+                    let EntryChange::Inserted(Out::YMap(y_task)) = change else {
+                        continue;
+                    };
+                    let task = YTaskProxy::new(y_task.clone())
+                        .to_task(txn)
+                        .with_context(|| format!("Failed to convert new task {task_id}"))?;
+                    project
+                        .event_tx
+                        .try_send(KosoEvent {
+                            project: project.clone(),
+                            changes: KosoEventChanges::Created(),
+                            task,
+                            origin: origin.clone(),
+                        })
+                        .context("Failed to send event to deep graph observer")?;
+                }
+                return Ok(());
+            }
             if map_event.path().len() != 1 {
                 return Ok(());
             }
@@ -104,9 +138,6 @@ fn handle_deep_graph_update_event_internal(
             if array_event.path().len() != 2 {
                 return Ok(());
             }
-            if array_event.removes(txn).is_empty() {
-                return Ok(());
-            }
 
             let path = array_event.path();
             let PathSegment::Key(task_id) = path.front().context("missing task path segment")?
@@ -120,23 +151,61 @@ fn handle_deep_graph_update_event_internal(
                 return Ok(());
             }
 
+            let removed = !array_event.removes(txn).is_empty();
+            let added: Vec<String> = array_event
+                .delta(txn)
+                .iter()
+                .filter_map(|change| match change {
+                    Change::Added(values) => Some(values),
+                    _ => None,
+                })
+                .flatten()
+                .filter_map(|value| match value {
+                    Out::Any(Any::String(child)) => Some(child.to_string()),
+                    _ => None,
+                })
+                .collect();
+            if !removed && added.is_empty() {
+                return Ok(());
+            }
+
             let origin = from_origin(txn.origin())?;
 
             let doc = YDocProxy::new_from_existing_doc(txn.doc().clone(), txn)?;
-            let task = doc
-                .get(txn, task_id)?
+            let y_task = doc.get(txn, task_id)?;
+            let task = y_task
                 .to_task(txn)
                 .context("Failed to convert ArrayEvent to Koso Task")?;
-            let event = KosoEvent {
-                project: project.clone(),
-                changes: KosoEventChanges::Children(),
-                task,
-                origin,
+            // Only tasks configured for auto assignment care about added children.
+            let added = if !added.is_empty() && y_task.get_auto_assign(txn)?.is_some() {
+                Some(added)
+            } else {
+                None
             };
-            return project
-                .event_tx
-                .try_send(event)
-                .context("Failed to send array event to deep graph observer");
+
+            if removed {
+                project
+                    .event_tx
+                    .try_send(KosoEvent {
+                        project: project.clone(),
+                        changes: KosoEventChanges::Children(),
+                        task: task.clone(),
+                        origin: origin.clone(),
+                    })
+                    .context("Failed to send array event to deep graph observer")?;
+            }
+            if let Some(added) = added {
+                project
+                    .event_tx
+                    .try_send(KosoEvent {
+                        project: project.clone(),
+                        changes: KosoEventChanges::ChildrenAdded(added),
+                        task,
+                        origin,
+                    })
+                    .context("Failed to send array event to deep graph observer")?;
+            }
+            return Ok(());
         }
         _ => (),
     }
@@ -145,14 +214,22 @@ fn handle_deep_graph_update_event_internal(
 
 pub(super) struct EventProcessor {
     event_rx: Receiver<KosoEvent>,
-    notifier: Notifier,
+    notifier: Notifiers,
+    inbox: Inbox,
+    pool: &'static PgPool,
 }
 
 impl EventProcessor {
-    pub(super) fn new(pool: &'static PgPool, event_rx: Receiver<KosoEvent>) -> Result<Self> {
+    pub(super) fn new(
+        pool: &'static PgPool,
+        event_rx: Receiver<KosoEvent>,
+        messenger: UserMessenger,
+    ) -> Result<Self> {
         Ok(EventProcessor {
             event_rx,
-            notifier: Notifier::new(pool)?,
+            notifier: Notifiers::new(pool)?,
+            inbox: Inbox::new(pool, messenger),
+            pool,
         })
     }
 
@@ -178,6 +255,22 @@ impl EventProcessor {
     async fn process_event_internal(&self, event: KosoEvent) -> Result<()> {
         match &event.changes {
             KosoEventChanges::Task(changes) => {
+                projections::upsert_task(self.pool, &event.project.project_id, &event.task).await?;
+                if changes.contains_key("estimate") {
+                    task_history::record_estimate(
+                        self.pool,
+                        &event.project.project_id,
+                        &event.task,
+                        Utc::now(),
+                    )
+                    .await?;
+                }
+                if changes.contains_key("status") {
+                    self.record_status(&event).await?;
+                }
+                if changes.contains_key("labels") {
+                    self.assign_oncall(&event).await?;
+                }
                 for (field, change) in changes {
                     match (field.as_str(), change) {
                         (
@@ -192,9 +285,10 @@ impl EventProcessor {
                             KosoEntryChange(EntryChange::Inserted(yrs::Out::Any(
                                 yrs::Any::String(assignee),
                             ))),
-                        ) => {
-                            self.notify_assignee(&event, assignee).await?;
-                        }
+                        ) => match groups::parse_group_assignee(assignee) {
+                            Some(group) => self.assign_group(&event, assignee, group).await?,
+                            None => self.notify_assignee(&event, assignee).await?,
+                        },
                         (
                             "status",
                             KosoEntryChange(EntryChange::Updated(
@@ -206,17 +300,74 @@ impl EventProcessor {
                                 self.unblock_and_notify_actionable_tasks(&event).await?;
                             }
                         }
+                        (
+                            "deadline",
+                            KosoEntryChange(EntryChange::Updated(
+                                _,
+                                yrs::Out::Any(yrs::Any::Number(_)),
+                            )),
+                        )
+                        | (
+                            "deadline",
+                            KosoEntryChange(EntryChange::Inserted(yrs::Out::Any(
+                                yrs::Any::Number(_),
+                            ))),
+                        ) => {
+                            self.notify_deadline(&event).await?;
+                        }
                         _ => continue,
                     }
                 }
             }
             KosoEventChanges::Children() => {
+                self.prune_projections(&event).await?;
                 self.unblock_and_notify_actionable_tasks(&event).await?;
             }
+            KosoEventChanges::Created() => {
+                projections::upsert_task(self.pool, &event.project.project_id, &event.task).await?;
+                task_history::record_estimate(
+                    self.pool,
+                    &event.project.project_id,
+                    &event.task,
+                    Utc::now(),
+                )
+                .await?;
+                self.record_status(&event).await?;
+                self.assign_oncall(&event).await?;
+            }
+            KosoEventChanges::ChildrenAdded(children) => {
+                self.auto_assign_children(&event, children).await?;
+            }
         }
         Ok(())
     }
 
+    async fn record_status(&self, event: &KosoEvent) -> Result<()> {
+        let (category, labels) = {
+            let doc = event.project.doc_box.lock().await;
+            let doc = &doc.as_ref().context("No doc initialized.")?.ydoc;
+            let txn = doc.transact();
+            let category = match &event.task.status {
+                Some(status) => doc.config().status_category(&txn, status)?,
+                None => None,
+            };
+            let labels = match doc.get(&txn, &event.task.id) {
+                Ok(task) => task.get_labels(&txn)?,
+                Err(_) => vec![],
+            };
+            (category, labels)
+        };
+        task_history::record_status(
+            self.pool,
+            &event.project.project_id,
+            &event.task,
+            category,
+            &labels,
+            Utc::now(),
+        )
+        .await
+    }
+
     async fn notify_assignee(&self, event: &KosoEvent, assignee: &str) -> Result<()> {
         // Don't notify a user if they assigned the task to themself.
         if let Actor::User(user) = &event.origin.actor {
@@ -225,13 +376,244 @@ impl EventProcessor {
             }
         };
 
-        let msg = format!(
-            "🎁 <i>{}</i> assigned to you:\n<a href=\"https://koso.app/projects/{}?taskId={}\"><b>{}</b></a>",
-            Sender::from_actor(&event.origin.actor).format(),
-            event.project.project_id,
+        let locale = Locale::of_user(self.pool, assignee).await?;
+        let sender = Sender::from_actor(&event.origin.actor).format();
+        let url = task_url(&event.project.project_id, &event.task.id);
+        let task = task_display_name(&event.task, &locale);
+        let msg = locale.message(
+            "notify-assigned",
+            &[("sender", &sender), ("url", &url), ("task", &task)],
+        );
+        let inbox_msg = locale.message("inbox-assigned", &[("task", &task)]);
+        self.inbox
+            .deliver(
+                assignee,
+                InboxKind::Assigned,
+                Some(&event.project.project_id),
+                Some(&event.task.id),
+                actor_email(&event.origin.actor),
+                &inbox_msg,
+            )
+            .await?;
+        // Users who are away learn of the assignment from their inbox on return,
+        // while their delegate, if any, is notified in their stead.
+        let Some(away) = away::away_now(self.pool, assignee).await? else {
+            return self
+                .notifier
+                .notify_assignment(assignee, &msg, &event.project.project_id, &event.task.id)
+                .await;
+        };
+        let Some(delegate) = away.delegate else {
+            return Ok(());
+        };
+        if actor_email(&event.origin.actor) == Some(delegate.as_str()) {
+            return Ok(());
+        }
+        let locale = Locale::of_user(self.pool, &delegate).await?;
+        let task = task_display_name(&event.task, &locale);
+        let msg = locale.message(
+            "notify-delegated",
+            &[
+                ("sender", &sender),
+                ("assignee", &assignee),
+                ("url", &url),
+                ("task", &task),
+            ],
+        );
+        let inbox_msg = locale.message(
+            "inbox-delegated",
+            &[("assignee", &assignee), ("task", &task)],
+        );
+        self.inbox
+            .deliver(
+                &delegate,
+                InboxKind::DelegatedAssignment,
+                Some(&event.project.project_id),
+                Some(&event.task.id),
+                actor_email(&event.origin.actor),
+                &inbox_msg,
+            )
+            .await?;
+        self.notifier
+            .notify_assignment(&delegate, &msg, &event.project.project_id, &event.task.id)
+            .await
+    }
+
+    /// Replaces a group placeholder assignee with the group's members.
+    async fn assign_group(&self, event: &KosoEvent, placeholder: &str, group: &str) -> Result<()> {
+        let assignment = groups::assign_group(self.pool, &event.project.project_id, group).await?;
+
+        let doc = event.project.doc_box.lock().await;
+        let doc = &doc.as_ref().context("No doc initialized.")?.ydoc;
+        let mut txn = doc.transact_mut_with(event.origin.delegated("group").as_origin()?);
+        let task = doc.get(&txn, &event.task.id)?;
+        // The task may have been reassigned in the meantime.
+        if task.get_assignee(&txn)?.is_none_or(|a| a != placeholder) {
+            return Ok(());
+        }
+
+        match assignment {
+            None => {
+                tracing::warn!("Task {} assigned to unknown group {group}", event.task.id);
+                task.set_assignee(&mut txn, None);
+            }
+            Some(GroupAssignment::Member(member)) => {
+                tracing::debug!("Assigning task {} to {member}", event.task.id);
+                task.set_assignee(&mut txn, Some(&member));
+            }
+            Some(GroupAssignment::Expand(members)) => {
+                tracing::debug!("Expanding task {} for {members:?}", event.task.id);
+                task.set_assignee(&mut txn, None);
+                let name = ytask_display_name(&task, &txn)?;
+                let reporter = task.get_reporter(&txn)?;
+                let mut next_num = doc.next_num(&txn)?;
+                let mut children = task.get_children(&txn)?;
+                for member in members {
+                    let child = Task {
+                        id: BASE64_URL_SAFE_NO_PAD.encode(uuid::Uuid::new_v4()),
+                        num: next_num.to_string(),
+                        name: format!("{name} ({member})"),
+                        assignee: Some(member),
+                        reporter: reporter.clone(),
+                        ..Task::default()
+                    };
+                    next_num += 1;
+                    doc.set(&mut txn, &child);
+                    children.push(child.id);
+                }
+                task.set_children(&mut txn, &children);
+            }
+        }
+        Ok(())
+    }
+
+    async fn auto_assign_children(&self, event: &KosoEvent, children: &[String]) -> Result<()> {
+        let assignments = {
+            let doc = event.project.doc_box.lock().await;
+            let doc = &doc.as_ref().context("No doc initialized.")?.ydoc;
+            let mut txn = doc.transact_mut_with(event.origin.delegated("autoassign").as_origin()?);
+            let parent = doc.get(&txn, &event.task.id)?;
+            auto_assign::auto_assign_children(&mut txn, doc, &parent, children)?
+        };
+        for (task_id, assignee) in assignments {
+            tracing::debug!("Auto assigned task {task_id} to {assignee}");
+        }
+        Ok(())
+    }
+
+    /// Assigns tasks with the project's incident label to the current on-call,
+    /// unless someone other than the reporter was already assigned.
+    async fn assign_oncall(&self, event: &KosoEvent) -> Result<()> {
+        let project_id = &event.project.project_id;
+        let Some(integration) = oncall::fetch_integration(self.pool, project_id).await? else {
+            return Ok(());
+        };
+        if !self.needs_oncall(event, &integration.label).await? {
+            return Ok(());
+        }
+        let Some(oncall) = oncall::current_oncall(self.pool, &integration).await? else {
+            return Ok(());
+        };
+        if !list_project_users(self.pool, project_id)
+            .await?
+            .iter()
+            .any(|u| u.email == oncall.email)
+        {
+            tracing::warn!("On-call {} is not a member of the project", oncall.email);
+            return Ok(());
+        }
+
+        let doc = event.project.doc_box.lock().await;
+        let doc = &doc.as_ref().context("No doc initialized.")?.ydoc;
+        let mut txn = doc.transact_mut_with(event.origin.delegated("oncall").as_origin()?);
+        let Ok(task) = doc.get(&txn, &event.task.id) else {
+            return Ok(());
+        };
+        // The task may have been relabeled or assigned while looking up the on-call.
+        if !needs_oncall(&task, &txn, &integration.label)? {
+            return Ok(());
+        }
+        tracing::debug!(
+            "Assigning task {} to on-call {}",
             event.task.id,
-            task_display_name(&event.task)
+            oncall.email
         );
+        task.set_assignee(&mut txn, Some(&oncall.email));
+        Ok(())
+    }
+
+    async fn needs_oncall(&self, event: &KosoEvent, label: &str) -> Result<bool> {
+        let doc = event.project.doc_box.lock().await;
+        let doc = &doc.as_ref().context("No doc initialized.")?.ydoc;
+        let txn = doc.transact();
+        match doc.get(&txn, &event.task.id) {
+            Ok(task) => needs_oncall(&task, &txn, label),
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Removing a child may have deleted it, so drop projections of any tasks no longer in the doc.
+    async fn prune_projections(&self, event: &KosoEvent) -> Result<()> {
+        let task_ids = {
+            let doc = event.project.doc_box.lock().await;
+            let doc = &doc.as_ref().context("No doc initialized.")?.ydoc;
+            let txn = doc.transact();
+            doc.tasks(&txn)?
+                .iter()
+                .map(|task| task.get_id(&txn))
+                .collect::<Result<Vec<String>>>()?
+        };
+        projections::prune(self.pool, &event.project.project_id, &task_ids).await
+    }
+
+    async fn notify_deadline(&self, event: &KosoEvent) -> Result<()> {
+        let (Some(assignee), Some(deadline)) = (&event.task.assignee, &event.task.deadline) else {
+            return Ok(());
+        };
+        // Don't notify a user if they set the deadline themself.
+        if let Actor::User(user) = &event.origin.actor {
+            if &user.email == assignee {
+                return Ok(());
+            }
+        };
+
+        // Render the deadline in the assignee's timezone rather than the setter's.
+        let timezone: Option<(Option<String>,)> =
+            sqlx::query_as("SELECT timezone FROM users WHERE email = $1")
+                .bind(assignee)
+                .fetch_optional(self.pool)
+                .await
+                .context("Failed to query user timezone")?;
+        let tz = parse_time_zone(timezone.and_then(|(tz,)| tz).as_deref());
+
+        let locale = Locale::of_user(self.pool, assignee).await?;
+        let sender = Sender::from_actor(&event.origin.actor).format();
+        let deadline = deadline.format(&tz);
+        let url = task_url(&event.project.project_id, &event.task.id);
+        let task = task_display_name(&event.task, &locale);
+        let msg = locale.message(
+            "notify-deadline",
+            &[
+                ("sender", &sender),
+                ("deadline", &deadline),
+                ("url", &url),
+                ("task", &task),
+            ],
+        );
+        let inbox_msg = locale.message(
+            "inbox-deadline",
+            &[("deadline", &deadline), ("task", &task)],
+        );
+        self.inbox
+            .deliver(
+                assignee,
+                InboxKind::Deadline,
+                Some(&event.project.project_id),
+                Some(&event.task.id),
+                actor_email(&event.origin.actor),
+                &inbox_msg,
+            )
+            .await?;
         self.notifier.notify(assignee, &msg).await
     }
 
@@ -270,10 +652,20 @@ impl EventProcessor {
                 }
             }
 
-            let msg = format!(
-                "🎁 <i>Koso</i> assigned to you:\n<a href=\"https://koso.app/projects/{}?taskId={}\"><b>{}</b></a>",
-                event.project.project_id, task_id, name
-            );
+            let locale = Locale::of_user(self.pool, &assignee).await?;
+            let url = task_url(&event.project.project_id, &task_id);
+            let msg = locale.message("notify-unblocked", &[("url", &url), ("task", &name)]);
+            let inbox_msg = locale.message("inbox-unblocked", &[("task", &name)]);
+            self.inbox
+                .deliver(
+                    &assignee,
+                    InboxKind::Unblocked,
+                    Some(&event.project.project_id),
+                    Some(&task_id),
+                    None,
+                    &inbox_msg,
+                )
+                .await?;
             self.notifier.notify(&assignee, &msg).await?;
         }
         Ok(())
@@ -345,11 +737,32 @@ fn ytask_display_name<T: ReadTxn>(task: &YTaskProxy, txn: &T) -> Result<String>
     Ok(format!("Task #{}", task.get_num(txn)?))
 }
 
-fn task_display_name(task: &Task) -> String {
+/// Whether the task carries the incident label and is unassigned, or assigned
+/// to whoever filed it.
+fn needs_oncall<T: ReadTxn>(task: &YTaskProxy, txn: &T, label: &str) -> Result<bool> {
+    if task.is_managed(txn)? || !task.get_labels(txn)?.iter().any(|l| l == label) {
+        return Ok(false);
+    }
+    let assignee = task.get_assignee(txn)?;
+    Ok(assignee.is_none() || assignee == task.get_reporter(txn)?)
+}
+
+fn task_display_name(task: &Task, locale: &Locale) -> String {
     if !task.name.is_empty() {
         return task.name.clone();
     }
-    format!("Task #{}", task.num)
+    locale.message("task-untitled", &[("num", &task.num)])
+}
+
+fn task_url(project_id: &str, task_id: &str) -> String {
+    format!("https://koso.app/projects/{project_id}?taskId={task_id}")
+}
+
+fn actor_email(actor: &Actor) -> Option<&str> {
+    match actor {
+        Actor::User(user) | Actor::Agent(user) => Some(&user.email),
+        _ => None,
+    }
 }
 
 fn now() -> Result<i64> {
@@ -374,7 +787,7 @@ impl Sender<'_> {
 
     fn from_actor(actor: &Actor) -> Sender {
         match actor {
-            Actor::User(user) => Sender::User(user),
+            Actor::User(user) | Actor::Agent(user) => Sender::User(user),
             _ => Sender::Koso,
         }
     }
@@ -0,0 +1,175 @@
+//! Maintains each task's subtask progress, the done and total unarchived
+//! leaves beneath it, and sends it to clients in a sidecar message next to
+//! sync messages, so list views can render progress bars without walking the
+//! graph themselves.
+
+use super::msg_sync::koso_progress;
+use crate::api::{
+    analytics::leaf_progress,
+    model::{TaskChange, TaskChangeKind, TaskProgress},
+    yproxy::YDocProxy,
+};
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{PoisonError, atomic::AtomicBool},
+};
+
+/// Task fields that can change a rollup.
+const FIELDS: &[&str] = &["archived", "children", "kind", "status"];
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ProgressMessage<'a> {
+    /// Whether the message replaces all progress the client has, rather than
+    /// updating some of it.
+    full: bool,
+    /// Progress by task id. Tasks mapped to null no longer have subtasks.
+    tasks: BTreeMap<&'a str, Option<TaskProgress>>,
+}
+
+/// The progress last sent to the project's clients.
+#[derive(Default)]
+pub(crate) struct ProgressRollup {
+    sent: std::sync::Mutex<HashMap<String, TaskProgress>>,
+    /// Whether a refresh is scheduled, so bursts of updates cause one.
+    pub(super) pending: AtomicBool,
+}
+
+impl ProgressRollup {
+    /// Replaces the progress without telling clients, e.g. when the doc loads.
+    pub(super) fn reset(&self, progress: HashMap<String, TaskProgress>) {
+        *self.sent.lock().unwrap_or_else(PoisonError::into_inner) = progress;
+    }
+
+    /// Encodes all of the progress, for clients that just synced.
+    pub(super) fn full_message(&self) -> Result<Vec<u8>> {
+        let sent = self.sent.lock().unwrap_or_else(PoisonError::into_inner);
+        let tasks = sent.iter().map(|(id, p)| (id.as_str(), Some(*p))).collect();
+        let message = ProgressMessage { full: true, tasks };
+        Ok(koso_progress(&serde_json::to_string(&message)?))
+    }
+
+    /// Replaces the progress and encodes the differences, if any.
+    pub(super) fn update(
+        &self,
+        progress: HashMap<String, TaskProgress>,
+    ) -> Result<Option<Vec<u8>>> {
+        let mut sent = self.sent.lock().unwrap_or_else(PoisonError::into_inner);
+        let message = {
+            let mut tasks: BTreeMap<&str, Option<TaskProgress>> = progress
+                .iter()
+                .filter(|(id, p)| sent.get(*id) != Some(*p))
+                .map(|(id, p)| (id.as_str(), Some(*p)))
+                .collect();
+            for id in sent.keys() {
+                if !progress.contains_key(id) {
+                    tasks.insert(id.as_str(), None);
+                }
+            }
+            if tasks.is_empty() {
+                None
+            } else {
+                Some(koso_progress(&serde_json::to_string(&ProgressMessage {
+                    full: false,
+                    tasks,
+                })?))
+            }
+        };
+        *sent = progress;
+        Ok(message)
+    }
+}
+
+/// Whether the changes may change anyone's progress. Updates without task
+/// changes, e.g. to workflow states, may recategorize statuses.
+pub(super) fn affected_by(changes: &[TaskChange]) -> bool {
+    changes.is_empty()
+        || changes.iter().any(|change| {
+            change.kind != TaskChangeKind::Updated
+                || change.fields.iter().any(|f| FIELDS.contains(&f.as_str()))
+        })
+}
+
+/// Computes the progress of every task with unarchived leaves beneath it.
+pub(super) fn compute(doc: &YDocProxy) -> Result<HashMap<String, TaskProgress>> {
+    let txn = doc.transact();
+    let graph = doc.to_graph(&txn)?;
+    let workflow_states = doc.config().get_workflow_states(&txn)?;
+    Ok(graph
+        .values()
+        .filter(|task| task.id != "root" && !task.children.is_empty())
+        .map(|task| {
+            let progress = leaf_progress(&graph, &workflow_states, &[task.id.clone()]);
+            (task.id.clone(), progress)
+        })
+        .filter(|(_, progress)| progress.total > 0)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{
+        collab::txn_origin::{Actor, YOrigin},
+        model::{Task, test_utils},
+    };
+
+    #[test_log::test]
+    fn update_sends_only_changes() {
+        let doc = YDocProxy::new();
+        {
+            let mut txn = doc.transact_mut_with(
+                YOrigin {
+                    who: "test".to_string(),
+                    id: "test".to_string(),
+                    actor: Actor::Server,
+                    ..Default::default()
+                }
+                .as_origin()
+                .unwrap(),
+            );
+            doc.set(&mut txn, &test_utils::task("root", "root", &["a"]));
+            doc.set(&mut txn, &test_utils::task("a", "a", &["b", "c"]));
+            doc.set(
+                &mut txn,
+                &Task {
+                    status: Some("Done".to_string()),
+                    ..test_utils::task("b", "b", &["d"])
+                },
+            );
+            doc.set(
+                &mut txn,
+                &Task {
+                    status: Some("Done".to_string()),
+                    ..test_utils::task("c", "c", &[])
+                },
+            );
+            doc.set(
+                &mut txn,
+                &Task {
+                    status: Some("In Progress".to_string()),
+                    ..test_utils::task("d", "d", &[])
+                },
+            );
+        }
+        let progress = compute(&doc).unwrap();
+        assert_eq!(progress.len(), 2);
+        assert_eq!(progress["a"], TaskProgress { done: 1, total: 2 });
+        assert_eq!(progress["b"], TaskProgress { done: 0, total: 1 });
+
+        let rollup = ProgressRollup::default();
+        rollup.reset(progress.clone());
+        assert_eq!(rollup.update(progress.clone()).unwrap(), None);
+
+        let mut changed = progress;
+        changed.remove("b");
+        changed.insert("a".to_string(), TaskProgress { done: 2, total: 2 });
+        let message = rollup.update(changed).unwrap().unwrap();
+        assert!(
+            String::from_utf8_lossy(&message)
+                .ends_with(r#"{"full":false,"tasks":{"a":{"done":2,"total":2},"b":null}}"#)
+        );
+    }
+}
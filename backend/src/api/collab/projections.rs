@@ -0,0 +1,109 @@
+//! Maintains `task_projections`, a queryable copy of select task fields,
+//! so that cross-project views don't need to load every project's doc.
+
+use crate::{
+    api::model::{ProjectId, Task},
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use sqlx::types::{
+    Json,
+    chrono::{DateTime, Utc},
+};
+
+const UPSERT_TASK: &str = "
+    INSERT INTO task_projections
+        (project_id, task_id, num, name, assignee, status, kind, deadline, deadline_millis, archived, update_time)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+    ON CONFLICT (project_id, task_id)
+    DO UPDATE SET
+        num = EXCLUDED.num,
+        name = EXCLUDED.name,
+        assignee = EXCLUDED.assignee,
+        status = EXCLUDED.status,
+        kind = EXCLUDED.kind,
+        deadline = EXCLUDED.deadline,
+        deadline_millis = EXCLUDED.deadline_millis,
+        archived = EXCLUDED.archived,
+        update_time = EXCLUDED.update_time
+    WHERE task_projections.update_time <= EXCLUDED.update_time";
+
+pub(crate) async fn upsert_task(pool: &PgPool, project_id: &ProjectId, task: &Task) -> Result<()> {
+    bind_task(sqlx::query(UPSERT_TASK), project_id, task, Utc::now())
+        .execute(pool)
+        .await
+        .context("Failed to upsert task projection")?;
+    Ok(())
+}
+
+/// Replaces all of a project's projections with the given snapshot of its tasks,
+/// taken at `as_of`. Projections updated after the snapshot are left alone.
+pub(crate) async fn rebuild(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    tasks: &[Task],
+    as_of: DateTime<Utc>,
+) -> Result<()> {
+    let mut txn = pool.begin().await?;
+    sqlx::query(
+        "
+        DELETE FROM task_projections
+        WHERE project_id = $1
+        AND update_time <= $2
+        AND task_id NOT IN (SELECT * FROM unnest($3))",
+    )
+    .bind(project_id)
+    .bind(as_of)
+    .bind(tasks.iter().map(|t| t.id.clone()).collect::<Vec<_>>())
+    .execute(&mut *txn)
+    .await
+    .context("Failed to delete task projections")?;
+    for task in tasks {
+        bind_task(sqlx::query(UPSERT_TASK), project_id, task, as_of)
+            .execute(&mut *txn)
+            .await
+            .context("Failed to upsert task projection")?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+/// Deletes the projections of tasks that no longer exist.
+pub(crate) async fn prune(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    task_ids: &[String],
+) -> Result<()> {
+    sqlx::query(
+        "
+        DELETE FROM task_projections
+        WHERE project_id = $1
+        AND task_id NOT IN (SELECT * FROM unnest($2))",
+    )
+    .bind(project_id)
+    .bind(task_ids)
+    .execute(pool)
+    .await
+    .context("Failed to prune task projections")?;
+    Ok(())
+}
+
+fn bind_task<'q>(
+    query: sqlx::query::Query<'q, sqlx::Postgres, sqlx::postgres::PgArguments>,
+    project_id: &'q ProjectId,
+    task: &'q Task,
+    update_time: DateTime<Utc>,
+) -> sqlx::query::Query<'q, sqlx::Postgres, sqlx::postgres::PgArguments> {
+    query
+        .bind(project_id)
+        .bind(&task.id)
+        .bind(&task.num)
+        .bind(&task.name)
+        .bind(&task.assignee)
+        .bind(&task.status)
+        .bind(&task.kind)
+        .bind(task.deadline.as_ref().map(Json))
+        .bind(task.deadline.as_ref().map(|d| d.millis()))
+        .bind(task.archived.unwrap_or(false))
+        .bind(update_time)
+}
@@ -1,42 +1,63 @@
 use super::{
     YDocProxy,
-    awareness::{AwarenessState, AwarenessUpdate},
-    msg_sync::koso_awareness_state,
+    awareness::{AwarenessState, AwarenessUpdate, DescCursor},
+    capabilities::Capability,
+    msg_sync::{
+        MSG_KOSO_BACKPRESSURE_CONNECTION, MSG_KOSO_BACKPRESSURE_PROJECT, koso_awareness_state,
+        koso_backpressure, koso_unread, sync_update,
+    },
     notifications,
+    progress::{self, ProgressRollup},
 };
 use crate::{
     api::{
+        branches,
         collab::{
+            anomalies::AnomalyDetector,
             client::{
                 CLOSE_ERROR, CLOSE_RESTART, ClientClosure, ClientReceiver, ClientSender, OVERLOADED,
             },
-            client_messages::{ClientMessage, ClientMessageReceiver},
+            client_messages::{ClientMessage, ClientMessageProcessor, ClientMessageReceiver},
+            doc_migrations,
             doc_updates::{DocObserver, DocUpdate, GraphObserver},
             msg_sync::sync_request,
             notifications::KosoEvent,
-            storage,
+            projections, storage, tiering,
             txn_origin::YOrigin,
         },
+        errors::{CodedError, ErrorCode},
         google::User,
+        maintenance::Maintenance,
         model::ProjectId,
+        unread,
     },
-    postgres::compact,
+    postgres::{PgPool, queue_compaction},
+    settings::settings,
 };
 use anyhow::{Context as _, Result, anyhow};
 use async_trait::async_trait;
-use sqlx::PgPool;
+use axum::body::Bytes;
+use base64::{Engine as _, prelude::BASE64_STANDARD};
+use sqlx::types::chrono::Utc;
 use std::{
-    collections::{HashMap, hash_map::Entry},
+    collections::{HashMap, HashSet, hash_map::Entry},
     fmt,
     sync::{
-        Arc, Weak,
-        atomic::{self, Ordering::Relaxed},
+        Arc, PoisonError, Weak,
+        atomic::{self, AtomicBool, Ordering::Relaxed},
     },
+    time::Duration,
+};
+use tokio::sync::{
+    Mutex, MutexGuard,
+    mpsc::{self, Sender, error::TrySendError},
 };
-use tokio::sync::{Mutex, MutexGuard, mpsc::Sender};
 use tokio_util::sync::CancellationToken;
 use tracing::Instrument;
-use yrs::{ReadTxn as _, StateVector, Subscription, Update};
+use yrs::{
+    ReadTxn as _, StateVector, Subscription, Update,
+    updates::{decoder::Decode as _, encoder::Encode as _},
+};
 
 #[derive(Debug)]
 enum ProjectInsertionError {
@@ -49,9 +70,36 @@ struct ProjectsMap {
     stopped: bool,
 }
 
+/// Sends messages to all of a user's connected clients, across projects.
+#[derive(Clone)]
+pub(crate) struct UserMessenger {
+    projects: Arc<Mutex<ProjectsMap>>,
+}
+
+impl UserMessenger {
+    pub(crate) async fn send_to_user(&self, email: &str, data: Vec<u8>) {
+        let data = Bytes::from(data);
+        let projects: Vec<Arc<ProjectState>> = {
+            let projects = self.projects.lock().await;
+            projects.map.values().filter_map(Weak::upgrade).collect()
+        };
+        for project in projects {
+            project.send_to_user(email, &data).await;
+        }
+    }
+}
+
+/// How many of a project's client messages may wait to be processed before
+/// its clients are throttled.
+const PROJECT_QUEUE_LEN: usize = 32;
+
+/// The least time to wait after an update before recomputing subtask progress,
+/// so bursts of changes, e.g. moving many tasks, recompute it once.
+const PROGRESS_DELAY_MS: u64 = 250;
+
 pub(super) struct ProjectsState {
-    projects: Mutex<ProjectsMap>,
-    process_msg_tx: Sender<ClientMessage>,
+    projects: Arc<Mutex<ProjectsMap>>,
+    processor: Arc<ClientMessageProcessor>,
     doc_update_tx: Sender<DocUpdate>,
     event_tx: Sender<KosoEvent>,
     pool: &'static PgPool,
@@ -60,18 +108,28 @@ pub(super) struct ProjectsState {
 
 impl ProjectsState {
     pub(super) fn new(
-        process_msg_tx: Sender<ClientMessage>,
         doc_update_tx: Sender<DocUpdate>,
         event_tx: Sender<KosoEvent>,
         pool: &'static PgPool,
+        maintenance: Maintenance,
+        anomalies: Arc<AnomalyDetector>,
         tracker: tokio_util::task::TaskTracker,
     ) -> Self {
+        let projects = Arc::new(Mutex::new(ProjectsMap {
+            map: HashMap::new(),
+            stopped: false,
+        }));
+        let messenger = UserMessenger {
+            projects: Arc::clone(&projects),
+        };
         ProjectsState {
-            projects: Mutex::new(ProjectsMap {
-                map: HashMap::new(),
-                stopped: false,
-            }),
-            process_msg_tx,
+            projects,
+            processor: Arc::new(ClientMessageProcessor::new(
+                pool,
+                messenger,
+                maintenance,
+                anomalies,
+            )),
             doc_update_tx,
             event_tx,
             pool,
@@ -79,6 +137,12 @@ impl ProjectsState {
         }
     }
 
+    pub(super) fn messenger(&self) -> UserMessenger {
+        UserMessenger {
+            projects: Arc::clone(&self.projects),
+        }
+    }
+
     pub(super) async fn add_and_init_local_client(
         &self,
         project_id: &ProjectId,
@@ -157,8 +221,7 @@ impl ProjectsState {
         }
 
         // Listen for messages on the read side of the socket.
-        let handler =
-            ClientMessageReceiver::new(Arc::clone(&project), self.process_msg_tx.clone(), receiver);
+        let handler = ClientMessageReceiver::new(Arc::clone(&project), receiver);
         self.tracker.spawn(handler.receive_messages_from_client());
 
         Ok(())
@@ -199,8 +262,17 @@ impl ProjectsState {
     }
 
     fn new_project(&self, project_id: &String) -> Arc<ProjectState> {
+        // The worker stops once the project, which holds the sending side, is dropped.
+        let (process_msg_tx, process_msg_rx) = mpsc::channel::<ClientMessage>(PROJECT_QUEUE_LEN);
+        self.tracker.spawn(
+            Arc::clone(&self.processor).process_messages(project_id.to_string(), process_msg_rx),
+        );
         Arc::new(ProjectState {
             project_id: project_id.to_string(),
+            process_msg_tx,
+            throttled: AtomicBool::new(false),
+            pending_broadcasts: std::sync::Mutex::new(Vec::new()),
+            progress: ProgressRollup::default(),
             clients: Mutex::new(ClientsMap {
                 map: HashMap::new(),
                 stopped: false,
@@ -216,6 +288,24 @@ impl ProjectsState {
         })
     }
 
+    /// Lists the projects whose docs are loaded in memory.
+    pub(super) async fn resident(&self) -> Vec<ResidentDoc> {
+        let projects: Vec<Arc<ProjectState>> = {
+            let projects = self.projects.lock().await;
+            projects.map.values().filter_map(Weak::upgrade).collect()
+        };
+        let mut resident = Vec::with_capacity(projects.len());
+        for project in projects {
+            let clients = project.clients.lock().await.map.len();
+            resident.push(ResidentDoc {
+                project_id: project.project_id.clone(),
+                clients,
+                stored_updates: project.updates.load(Relaxed),
+            });
+        }
+        resident
+    }
+
     pub(super) async fn stop(&self) {
         let mut projects = self.projects.lock().await;
         projects.stopped = true;
@@ -232,6 +322,16 @@ impl ProjectsState {
     }
 }
 
+/// A project doc loaded in memory.
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ResidentDoc {
+    pub(crate) project_id: ProjectId,
+    pub(crate) clients: usize,
+    /// Stored updates, which compaction merges once the doc is unloaded.
+    pub(crate) stored_updates: usize,
+}
+
 enum ClientInsertionError {
     TooManyClients(String),
     DuplicateClient(String),
@@ -245,6 +345,13 @@ pub(crate) struct ClientsMap {
 
 pub(crate) struct ProjectState {
     pub(crate) project_id: ProjectId,
+    process_msg_tx: Sender<ClientMessage>,
+    /// Whether clients were told to hold back because the queue filled up.
+    pub(super) throttled: AtomicBool,
+    /// Updates waiting to be coalesced and broadcast.
+    pending_broadcasts: std::sync::Mutex<Vec<PendingBroadcast>>,
+    /// Subtask progress last sent to clients.
+    progress: ProgressRollup,
     clients: Mutex<ClientsMap>,
     awarenesses: Mutex<HashMap<String, AwarenessState>>,
     pub(crate) doc_box: Mutex<Option<DocBox>>,
@@ -299,16 +406,42 @@ impl ProjectState {
 
         // Load the doc if it wasn't already loaded by another client.
         tracing::debug!("Initializing new YDoc");
+        tiering::rehydrate(&project.project_id, project.pool).await?;
         let (ydoc, update_count) = storage::load_doc(&project.project_id, project.pool).await?;
         tracing::debug!("Initialized new YDoc with {update_count} updates");
         project.updates.store(update_count, Relaxed);
 
+        // Draft branches are private until merged, so they don't trigger
+        // notifications, automations or projections.
+        let draft = branches::is_branch(project.pool, &project.project_id).await?;
+
         // Attach observers to the doc.
-        let subs = vec![
-            Self::create_doc_observer(project, &ydoc)?,
-            Self::create_graph_observer(project, &ydoc),
-            Self::create_deep_graph_observer(project, &ydoc),
-        ];
+        let mut subs = Self::create_doc_observers(project, &ydoc)?;
+        subs.push(Self::create_graph_observer(project, &ydoc));
+        if !draft {
+            subs.push(Self::create_deep_graph_observer(project, &ydoc));
+        }
+
+        // Migrate the doc now that observers will persist and broadcast the changes.
+        // A failed migration leaves the doc as is rather than locking users out.
+        if let Err(e) = doc_migrations::migrate(project.pool, &project.project_id, &ydoc).await {
+            tracing::error!("Failed to migrate doc: {e:?}");
+        }
+
+        // Clients can write malformed tasks or config, which mustn't keep the
+        // doc from loading. Stored projections are kept and progress starts
+        // empty instead.
+        if !draft {
+            if let Err(e) = Self::rebuild_projections(project, &ydoc) {
+                tracing::error!("Failed to rebuild task projections: {e:?}");
+            }
+        }
+        project
+            .progress
+            .reset(progress::compute(&ydoc).unwrap_or_else(|e| {
+                tracing::error!("Failed to compute progress: {e:?}");
+                HashMap::new()
+            }));
 
         let db = DocBox { ydoc, subs };
         let sv = db.ydoc.transact().state_vector();
@@ -316,24 +449,57 @@ impl ProjectState {
         Ok(sv)
     }
 
+    /// Refresh the task projections in the background. They may be stale if
+    /// the server stopped before processing every event.
+    fn rebuild_projections(project: &Arc<ProjectState>, ydoc: &YDocProxy) -> Result<()> {
+        let as_of = Utc::now();
+        let tasks = {
+            let txn = ydoc.transact();
+            ydoc.tasks(&txn)?
+                .iter()
+                .map(|task| task.to_task(&txn))
+                .collect::<Result<Vec<_>>>()?
+        };
+        let pool = project.pool;
+        let project_id = project.project_id.clone();
+        project.tracker.spawn(async move {
+            if let Err(e) = projections::rebuild(pool, &project_id, &tasks, as_of).await {
+                tracing::warn!("Failed to rebuild task projections: {e:?}");
+            }
+        });
+        Ok(())
+    }
+
     /// Persist and broadcast update events by subscribing to the callback.
-    fn create_doc_observer(project: &Arc<ProjectState>, doc: &YDocProxy) -> Result<Subscription> {
-        let observer = DocObserver::new(project.doc_update_tx.clone(), project.tracker.clone());
+    fn create_doc_observers(
+        project: &Arc<ProjectState>,
+        doc: &YDocProxy,
+    ) -> Result<Vec<Subscription>> {
+        let observer = Arc::new(DocObserver::new(
+            project.doc_update_tx.clone(),
+            project.tracker.clone(),
+            doc.removed().clone(),
+        ));
+        let collector = Arc::clone(&observer);
+        let changes_sub =
+            doc.observe_deep_graph(move |txn, events| collector.collect_changes(txn, events));
         let project = Arc::downgrade(project);
-        doc.observe_update_v2(move |txn, update| {
-            let Some(project) = project.upgrade() else {
-                // This will never happen because the observer is invoked syncronously in
-                // ProjectState.apply_update while holding a strong reference to the project.
-                tracing::error!(
-                    "handle_doc_update_v2_event but weak project reference was destroyed"
-                );
-                return;
-            };
+        let update_sub = doc
+            .observe_update_v2(move |txn, update| {
+                let Some(project) = project.upgrade() else {
+                    // This will never happen because the observer is invoked syncronously in
+                    // ProjectState.apply_update while holding a strong reference to the project.
+                    tracing::error!(
+                        "handle_doc_update_v2_event but weak project reference was destroyed"
+                    );
+                    return;
+                };
 
-            project.updates.fetch_add(1, Relaxed);
-            observer.handle_doc_update_v2_event(project, txn, update);
-        })
-        .context("Failed to create observer")
+                project.updates.fetch_add(1, Relaxed);
+                observer.handle_doc_update_v2_event(project, txn, update);
+            })
+            .context("Failed to create observer")?;
+        Ok(vec![changes_sub, update_sub])
     }
 
     fn create_graph_observer(project: &Arc<ProjectState>, doc: &YDocProxy) -> Subscription {
@@ -377,16 +543,97 @@ impl ProjectState {
             .encode_state_as_update_v2(sv);
         Ok(update)
     }
+    /// Applies the update to the doc. Updates with a `relayed` origin are
+    /// relayed to peers by the caller, so aren't broadcast once applied.
     pub(super) async fn apply_doc_update(&self, origin: YOrigin, update: Update) -> Result<()> {
         let doc_box = self.doc_box.lock().await;
-        DocBox::doc_or_error(doc_box.as_ref())?
-            .ydoc
-            .transact_mut_with(origin.as_origin()?)
+        let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+        let mut txn = doc.transact_mut_with(origin.as_origin()?);
+        // Tasks can only be read before they're removed, so snapshot the ones
+        // the update deletes.
+        if !update.delete_set().is_empty() {
+            if let Err(e) = doc.snapshot_deleted(&txn, update.delete_set()) {
+                tracing::warn!("Failed to snapshot removed tasks: {e:?}");
+            }
+        }
+        let config = doc.config().get(&txn);
+        let applied = txn
             .apply_update(update)
-            .context("Failed to apply doc update")
+            .context("Failed to apply doc update");
+        // The doc observer takes the removed tasks when the transaction
+        // commits, unless the update changed nothing.
+        drop(txn);
+        doc.removed().take();
+        // Clients write the config map directly, so changes leaving it invalid
+        // are undone, in a transaction of its own so the sender gets it too.
+        if let (Ok(()), Ok(config)) = (&applied, config) {
+            let mut txn = doc.transact_mut_with(origin.delegated("config_validation").as_origin()?);
+            if let Err(e) = doc.config().restore_if_invalid(&mut txn, &config) {
+                tracing::warn!("Failed to restore project config: {e:?}");
+            }
+        }
+        applied
+    }
+
+    /// Queues a client's message for the project's worker. When the queue is
+    /// full, tells the project's clients to slow down and waits for room,
+    /// holding up only this project's clients.
+    pub(super) async fn enqueue(&self, msg: ClientMessage) -> Result<()> {
+        let msg = match self.process_msg_tx.try_send(msg) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Full(msg)) => msg,
+            Err(TrySendError::Closed(_)) => return Err(anyhow!("Message queue closed")),
+        };
+        if !self.throttled.swap(true, Relaxed) {
+            tracing::debug!("Message queue full, throttling clients");
+            self.broadcast_backpressure(true).await;
+        }
+        self.process_msg_tx
+            .send(msg)
+            .await
+            .map_err(|_| anyhow!("Message queue closed"))
+    }
+
+    /// Tells the project's clients that negotiated backpressure to pause or
+    /// resume sending. Other clients would fail on the message.
+    pub(super) async fn broadcast_backpressure(&self, throttled: bool) {
+        let data = Bytes::from(koso_backpressure(MSG_KOSO_BACKPRESSURE_PROJECT, throttled));
+        let mut clients = self.clients.lock().await;
+        if clients.stopped {
+            return;
+        }
+        let mut results = Vec::new();
+        for client in clients.map.values_mut() {
+            if client.capabilities.supports(Capability::Backpressure) {
+                results.push(client.send(data.clone()));
+            }
+        }
+        futures::future::join_all(results).await;
+    }
+
+    /// Tells the client, if it negotiated backpressure, to pause or resume
+    /// sending on its connection.
+    pub(super) async fn send_backpressure(&self, to_who: &String, throttled: bool) -> Result<()> {
+        let mut clients = self.clients.lock().await;
+        let Some(client) = clients.map.get_mut(to_who) else {
+            return Err(anyhow!("Unexpectedly found no client to send to"));
+        };
+        if !client.capabilities.supports(Capability::Backpressure) {
+            return Ok(());
+        }
+        client
+            .send(koso_backpressure(
+                MSG_KOSO_BACKPRESSURE_CONNECTION,
+                throttled,
+            ))
+            .await
+            .context("Failed to send to client")
     }
 
-    pub(super) async fn broadcast_msg(&self, data: Vec<u8>, exclude_who: Option<&String>) {
+    /// Sends the message to every client but `exclude_who`. Clients share
+    /// the one buffer rather than each getting a copy.
+    pub(super) async fn broadcast_msg(&self, data: impl Into<Bytes>, exclude_who: Option<&String>) {
+        let data = data.into();
         let mut clients = self.clients.lock().await;
         if clients.stopped {
             return;
@@ -397,14 +644,149 @@ impl ProjectState {
         for client in clients.map.values_mut() {
             match exclude_who {
                 Some(exclude_who) if client.who == *exclude_who => {}
-                _ => results.push(client.send(data.to_owned())),
+                _ => results.push(client.send(data.clone())),
             }
         }
         let res = futures::future::join_all(results).await;
         tracing::debug!("Finished broadcasting: {res:?}");
     }
 
-    pub(super) async fn send_msg(&self, to_who: &String, data: Vec<u8>) -> Result<()> {
+    /// Broadcasts an update to every client but the one that made it. Updates
+    /// arriving within the coalescing window are merged, so each client gets
+    /// one message per window rather than one per keystroke. `frame` is sent
+    /// as is when the update goes out alone, e.g. a client's update relayed
+    /// as the client framed it.
+    pub(super) async fn broadcast_update(self: &Arc<Self>, who: String, data: Bytes, frame: Bytes) {
+        metrics::counter!("collab_broadcast_updates_total").increment(1);
+        let window = Duration::from_millis(settings().coalesce_window_ms);
+        if window.is_zero() {
+            self.broadcast_msg(frame, Some(&who)).await;
+            return;
+        }
+
+        let first = {
+            let mut pending = self
+                .pending_broadcasts
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            pending.push(PendingBroadcast { who, data, frame });
+            pending.len() == 1
+        };
+        // The first update of a window schedules the flush for the rest.
+        if first {
+            let project = Arc::clone(self);
+            self.tracker.spawn(
+                async move {
+                    tokio::time::sleep(window).await;
+                    if let Err(e) = project.flush_broadcasts().await {
+                        tracing::warn!("Failed to broadcast coalesced updates: {e:?}");
+                    }
+                }
+                .in_current_span(),
+            );
+        }
+    }
+
+    async fn flush_broadcasts(&self) -> Result<()> {
+        let pending = std::mem::take(
+            &mut *self
+                .pending_broadcasts
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        metrics::histogram!("collab_coalesced_updates").record(pending.len() as f64);
+        if let [PendingBroadcast { who, frame, .. }] = pending.as_slice() {
+            self.broadcast_msg(frame.clone(), Some(who)).await;
+            return Ok(());
+        }
+
+        // Clients get every update but their own, so authors get a merge without theirs.
+        let authors: HashSet<&str> = pending.iter().map(|p| p.who.as_str()).collect();
+        let mut merged: HashMap<Option<&str>, Option<Bytes>> = HashMap::new();
+        merged.insert(None, merge_updates(&pending, None)?);
+        for author in authors {
+            merged.insert(Some(author), merge_updates(&pending, Some(author))?);
+        }
+
+        let mut clients = self.clients.lock().await;
+        if clients.stopped {
+            return Ok(());
+        }
+        let mut results = Vec::new();
+        for client in clients.map.values_mut() {
+            let key = merged
+                .contains_key(&Some(client.who.as_str()))
+                .then_some(client.who.as_str());
+            if let Some(Some(update)) = merged.get(&key) {
+                results.push(client.send(update.clone()));
+            }
+        }
+        metrics::counter!("collab_broadcast_messages_total").increment(results.len() as u64);
+        let res = futures::future::join_all(results).await;
+        tracing::debug!("Finished broadcasting coalesced updates: {res:?}");
+        Ok(())
+    }
+
+    /// Sends the client the progress of every task with subtasks.
+    pub(super) async fn send_progress(&self, to_who: &String) -> Result<()> {
+        let msg = self.progress.full_message()?;
+        self.send_msg(to_who, msg).await
+    }
+
+    /// Sends the client its user's unread and flagged tasks.
+    pub(super) async fn send_unread(&self, to_who: &String, email: &str) -> Result<()> {
+        let markers = unread::markers(self.pool, &self.project_id, email).await?;
+        let msg = koso_unread(&serde_json::to_string(&markers)?);
+        self.send_msg(to_who, msg).await
+    }
+
+    /// Recomputes subtask progress shortly, once a burst of updates settles,
+    /// and broadcasts whatever changed.
+    pub(super) fn schedule_progress_refresh(self: &Arc<Self>) {
+        if self.progress.pending.swap(true, Relaxed) {
+            return;
+        }
+        let window = Duration::from_millis(settings().coalesce_window_ms.max(PROGRESS_DELAY_MS));
+        let project = Arc::clone(self);
+        self.tracker.spawn(
+            async move {
+                tokio::time::sleep(window).await;
+                project.progress.pending.store(false, Relaxed);
+                if let Err(e) = project.refresh_progress().await {
+                    tracing::warn!("Failed to refresh progress: {e:?}");
+                }
+            }
+            .in_current_span(),
+        );
+    }
+
+    async fn refresh_progress(&self) -> Result<()> {
+        let progress = {
+            let doc_box = self.doc_box.lock().await;
+            progress::compute(&DocBox::doc_or_error(doc_box.as_ref())?.ydoc)?
+        };
+        if let Some(msg) = self.progress.update(progress)? {
+            self.broadcast_msg(msg, None).await;
+        }
+        Ok(())
+    }
+
+    /// Sends the message to each of the user's clients, ignoring failures.
+    pub(super) async fn send_to_user(&self, email: &str, data: &Bytes) {
+        let mut clients = self.clients.lock().await;
+        if clients.stopped {
+            return;
+        }
+        for client in clients.map.values_mut() {
+            if client.email == email {
+                if let Err(e) = client.send(data.clone()).await {
+                    tracing::debug!("Failed to send message to {}: {e:?}", client.who);
+                }
+            }
+        }
+    }
+
+    pub(super) async fn send_msg(&self, to_who: &String, data: impl Into<Bytes>) -> Result<()> {
         let mut clients = self.clients.lock().await;
         let Some(client) = clients.map.get_mut(to_who) else {
             return Err(anyhow!("Unexpectedly found no client to send to"));
@@ -471,25 +853,96 @@ impl ProjectState {
         &self,
         who: &str,
         user: &User,
-        update: AwarenessUpdate,
+        mut update: AwarenessUpdate,
     ) -> Result<()> {
+        if let Some(cursor) = update.desc_cursor.take() {
+            update.desc_cursor = self.translate_desc_cursor(cursor).await;
+        }
         let state = update.into_state(user);
         self.awarenesses.lock().await.insert(who.into(), state);
         self.broadcast_awarenesses().await?;
         Ok(())
     }
 
+    /// Resolves the cursor's relative positions into offsets for clients that don't decode them.
+    /// Cursors that can't be resolved, e.g. because the task is gone, are dropped.
+    async fn translate_desc_cursor(&self, mut cursor: DescCursor) -> Option<DescCursor> {
+        let doc_box = self.doc_box.lock().await;
+        let doc = &doc_box.as_ref()?.ydoc;
+        let txn = doc.transact();
+        let task = doc.get(&txn, &cursor.task_id).ok()?;
+        let resolve = |encoded: &str| -> Result<u32> {
+            task.desc_index(&txn, &BASE64_STANDARD.decode(encoded)?)?
+                .context("Position not found in description")
+        };
+        match (resolve(&cursor.anchor), resolve(&cursor.head)) {
+            (Ok(anchor), Ok(head)) => {
+                cursor.anchor_index = Some(anchor);
+                cursor.head_index = Some(head);
+                Some(cursor)
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                tracing::debug!("Dropping unresolvable description cursor: {e:?}");
+                None
+            }
+        }
+    }
+
     async fn broadcast_awarenesses(&self) -> Result<()> {
-        let state = {
+        let (state, legacy_state) = {
             let awarenesses = self.awarenesses.lock().await;
-            serde_json::to_string(&awarenesses.values().collect::<Vec<_>>())?
+            let legacy: Vec<AwarenessState> = awarenesses
+                .values()
+                .map(AwarenessState::without_desc_cursor)
+                .collect();
+            (
+                serde_json::to_string(&awarenesses.values().collect::<Vec<_>>())?,
+                serde_json::to_string(&legacy)?,
+            )
         };
-        let msg = koso_awareness_state(&state);
-        self.broadcast_msg(msg, None).await;
+        let msg = Bytes::from(koso_awareness_state(&state));
+        let legacy_msg = Bytes::from(koso_awareness_state(&legacy_state));
+
+        let mut clients = self.clients.lock().await;
+        if clients.stopped {
+            return Ok(());
+        }
+        let mut results = Vec::new();
+        for client in clients.map.values_mut() {
+            let msg = if client.capabilities.supports(Capability::DescCursors) {
+                &msg
+            } else {
+                &legacy_msg
+            };
+            results.push(client.send(msg.clone()));
+        }
+        futures::future::join_all(results).await;
         Ok(())
     }
 }
 
+/// An update waiting to be broadcast.
+struct PendingBroadcast {
+    /// Who made the update.
+    who: String,
+    data: Bytes,
+    /// The message to send if the update goes out alone.
+    frame: Bytes,
+}
+
+/// Merges the updates, except those made by `exclude`, into one sync_update message.
+fn merge_updates(pending: &[PendingBroadcast], exclude: Option<&str>) -> Result<Option<Bytes>> {
+    let updates = pending
+        .iter()
+        .filter(|p| Some(p.who.as_str()) != exclude)
+        .map(|p| Update::decode_v2(&p.data))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(match updates.len() {
+        0 => None,
+        _ => Some(sync_update(&Update::merge_updates(updates).encode_v2()).into()),
+    })
+}
+
 impl fmt::Debug for ProjectState {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_str(&self.project_id)
@@ -531,7 +984,7 @@ impl Drop for ProjectState {
         let updates: usize = self.updates.load(Relaxed);
         if updates > 10 {
             self.tracker
-                .spawn(compact(self.pool, self.project_id.clone()).in_current_span());
+                .spawn(queue_compaction(self.pool, self.project_id.clone()).in_current_span());
         } else {
             tracing::debug!("Skipping compacting, only {updates} updates exist")
         }
@@ -549,7 +1002,7 @@ impl DocBox {
     pub(crate) fn doc_or_error(doc_box: Option<&DocBox>) -> Result<&DocBox> {
         match doc_box {
             Some(db) => Ok(db),
-            None => Err(anyhow!("DocBox is absent")),
+            None => Err(CodedError::new(ErrorCode::DocNotLoaded, "DocBox is absent").into()),
         }
     }
 }
@@ -1,27 +1,70 @@
-use crate::api::model::ProjectId;
+use crate::{
+    api::{model::ProjectId, shadow},
+    postgres::{self, PgPool},
+};
 use anyhow::{Context as _, Result};
-use sqlx::PgPool;
-use yrs::{Update, updates::decoder::Decode as _};
+use sqlx::types::Json as SqlJson;
+use yrs::{Origin, Update, updates::decoder::Decode as _};
 
 use super::{
     YDocProxy,
+    doc_updates::DocUpdate,
+    tiering,
     txn_origin::{self, YOrigin},
 };
 
-pub(super) async fn persist_update(
-    project_id: &ProjectId,
-    data: &Vec<u8>,
-    pool: &PgPool,
-) -> Result<()> {
-    sqlx::query(
+/// Stores the update in the project's region, along with its metadata. Metadata
+/// is kept in a separate table so it survives compaction of the updates
+/// themselves, and in the deployment's own database since it holds no content.
+pub(super) async fn persist_update(update: &DocUpdate, pool: &PgPool) -> Result<()> {
+    let project_id = &update.project.project_id;
+    let doc_pool = postgres::doc_pool(pool, project_id).await?;
+    let mut txn = pool.begin().await?;
+    let mut doc_txn = if std::ptr::eq(doc_pool, pool) {
+        None
+    } else {
+        Some(doc_pool.begin().await?)
+    };
+    let (seq,): (i32,) = sqlx::query_as(
         "
             INSERT INTO yupdates (project_id, seq, update_v2)
-            VALUES ($1, DEFAULT, $2)",
+            VALUES ($1, DEFAULT, $2)
+            RETURNING seq",
     )
     .bind(project_id)
-    .bind(data)
-    .execute(pool)
+    .bind(update.data.as_ref())
+    .fetch_one(match &mut doc_txn {
+        Some(doc_txn) => &mut **doc_txn,
+        None => &mut *txn,
+    })
     .await?;
+
+    sqlx::query(
+        "
+            INSERT INTO yupdate_metadata (project_id, seq, txn_id, who, actor, email, device, request_id, feature, update_len, create_time, changes)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
+    )
+    .bind(project_id)
+    .bind(seq)
+    .bind(&update.id)
+    .bind(&update.who)
+    .bind(update.actor.kind())
+    .bind(update.actor.email())
+    .bind(&update.metadata.device)
+    .bind(&update.metadata.request_id)
+    .bind(update.metadata.feature.as_deref().unwrap_or(&update.who))
+    .bind(update.data.len() as i32)
+    .bind(update.time)
+    .bind(SqlJson(&update.changes))
+    .execute(&mut *txn)
+    .await
+    .context("Failed to insert update metadata")?;
+    // An update whose metadata failed to commit still loads, but not the
+    // reverse, so the update commits first.
+    if let Some(doc_txn) = doc_txn {
+        doc_txn.commit().await?;
+    }
+    txn.commit().await?;
     Ok(())
 }
 
@@ -31,22 +74,64 @@ pub(super) async fn load_doc(project_id: &ProjectId, pool: &PgPool) -> Result<(Y
 
     let ydoc = YDocProxy::new();
     {
-        let mut txn = ydoc.transact_mut_with(
-            YOrigin {
-                who: "load_doc".to_string(),
-                id: project_id.to_string(),
-                actor: txn_origin::Actor::Server,
-            }
-            .as_origin()?,
-        );
-        for (update,) in updates {
-            txn.apply_update(Update::decode_v2(&update)?)
+        let mut txn = ydoc.transact_mut_with(load_origin(project_id)?);
+        for (update,) in &updates {
+            txn.apply_update(Update::decode_v2(update)?)
                 .context("Failed to apply loaded update")?
         }
     }
+
+    shadow::compare(
+        "merged_load",
+        project_id,
+        || ydoc.to_graph(&ydoc.transact()),
+        || {
+            let project_id = project_id.clone();
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    let ydoc = load_doc_merged(&project_id, updates)?;
+                    ydoc.to_graph(&ydoc.transact())
+                })
+                .await?
+            }
+        },
+    );
     Result::Ok((ydoc, update_count))
 }
 
+/// Loads the doc outside of the live collab state, e.g. from admin commands,
+/// rehydrating it first if it was archived.
+pub(crate) async fn load_doc_offline(project_id: &ProjectId, pool: &PgPool) -> Result<YDocProxy> {
+    tiering::rehydrate(project_id, pool).await?;
+    Ok(load_doc(project_id, pool).await?.0)
+}
+
+/// Loads the doc by merging its updates and applying the result once, rather
+/// than applying each update in turn. Verified against load_doc in shadow.
+fn load_doc_merged(project_id: &ProjectId, updates: Vec<(Vec<u8>,)>) -> Result<YDocProxy> {
+    let merged = Update::merge_updates(
+        updates
+            .iter()
+            .map(|(update,)| Update::decode_v2(update))
+            .collect::<Result<Vec<_>, _>>()?,
+    );
+    let ydoc = YDocProxy::new();
+    ydoc.transact_mut_with(load_origin(project_id)?)
+        .apply_update(merged)
+        .context("Failed to apply merged update")?;
+    Ok(ydoc)
+}
+
+fn load_origin(project_id: &ProjectId) -> Result<Origin> {
+    YOrigin {
+        who: "load_doc".to_string(),
+        id: project_id.to_string(),
+        actor: txn_origin::Actor::Server,
+        ..Default::default()
+    }
+    .as_origin()
+}
+
 pub async fn load_updates(project_id: &ProjectId, pool: &PgPool) -> Result<Vec<Update>> {
     let updates = load_raw_updates(project_id, pool).await?;
     let updates = updates
@@ -57,11 +142,66 @@ pub async fn load_updates(project_id: &ProjectId, pool: &PgPool) -> Result<Vec<U
     Result::Ok(updates)
 }
 
+/// Loads the project's stored updates, including its cold snapshot if the doc is in cold storage.
 async fn load_raw_updates(project_id: &ProjectId, pool: &PgPool) -> Result<Vec<(Vec<u8>,)>> {
-    let updates: Vec<(Vec<u8>,)> =
+    let mut updates: Vec<(Vec<u8>,)> =
         sqlx::query_as("SELECT update_v2 FROM yupdates WHERE project_id=$1")
             .bind(project_id)
-            .fetch_all(pool)
+            .fetch_all(postgres::doc_pool(pool, project_id).await?)
             .await?;
+    if let Some(snapshot) = tiering::load_snapshot(project_id, pool).await? {
+        updates.push((snapshot,));
+    }
     Ok(updates)
 }
+
+/// Copies the project's stored updates to another database, e.g. one in the
+/// region the project is moving to, replacing any left there by an earlier,
+/// interrupted copy. Cold snapshots aren't copied, so rehydrate first.
+pub(crate) async fn copy_updates(project_id: &ProjectId, from: &PgPool, to: &PgPool) -> Result<()> {
+    let updates: Vec<(i32, Vec<u8>)> =
+        sqlx::query_as("SELECT seq, update_v2 FROM yupdates WHERE project_id = $1")
+            .bind(project_id)
+            .fetch_all(from)
+            .await
+            .context("Failed to read updates")?;
+    let mut txn = to.begin().await?;
+    sqlx::query("DELETE FROM yupdates WHERE project_id = $1")
+        .bind(project_id)
+        .execute(&mut *txn)
+        .await?;
+    for (seq, update) in &updates {
+        sqlx::query("INSERT INTO yupdates (project_id, seq, update_v2) VALUES ($1, $2, $3)")
+            .bind(project_id)
+            .bind(seq)
+            .bind(update)
+            .execute(&mut *txn)
+            .await
+            .context("Failed to copy update")?;
+    }
+    // Metadata is keyed by seq, so updates stored after the copy must be
+    // numbered after the copied ones.
+    if let Some(max_seq) = updates.iter().map(|(seq, _)| *seq).max() {
+        sqlx::query(
+            "
+            SELECT setval(pg_get_serial_sequence('yupdates', 'seq'), GREATEST($1, last_value))
+            FROM yupdates_seq_seq",
+        )
+        .bind(i64::from(max_seq))
+        .execute(&mut *txn)
+        .await
+        .context("Failed to advance update sequence")?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+/// Deletes the project's stored updates, e.g. from the region it moved out of.
+pub(crate) async fn delete_updates(project_id: &ProjectId, pool: &PgPool) -> Result<()> {
+    sqlx::query("DELETE FROM yupdates WHERE project_id = $1")
+        .bind(project_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete updates")?;
+    Ok(())
+}
@@ -0,0 +1,111 @@
+//! Records the history of task statuses and estimates, which the doc only
+//! holds the current values of, for analytics.
+
+use crate::{
+    api::model::{ProjectId, Task, WorkflowCategory},
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use sqlx::types::{
+    Json,
+    chrono::{DateTime, Utc},
+};
+
+/// Records the task's move into its current status and, if the task is now done,
+/// its estimates and actuals. A task's first transition is recorded when it's
+/// created, even without a status, and marks the start of its lead time.
+pub(crate) async fn record_status(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    task: &Task,
+    category: Option<WorkflowCategory>,
+    labels: &[String],
+    time: DateTime<Utc>,
+) -> Result<()> {
+    sqlx::query(
+        "
+        INSERT INTO task_status_transitions (project_id, task_id, status, category, transition_time)
+        VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(project_id)
+    .bind(&task.id)
+    .bind(&task.status)
+    .bind(category.map(category_str))
+    .bind(time)
+    .execute(pool)
+    .await
+    .context("Failed to insert status transition")?;
+
+    if category != Some(WorkflowCategory::Done) {
+        return Ok(());
+    }
+    sqlx::query(
+        "
+        INSERT INTO task_completions
+            (project_id, task_id, assignee, labels, original_estimate, final_estimate, create_time, start_time, done_time)
+        VALUES (
+            $1, $2, $3, $4,
+            (SELECT original_estimate FROM task_estimates WHERE project_id = $1 AND task_id = $2),
+            $5,
+            (SELECT MIN(transition_time) FROM task_status_transitions
+             WHERE project_id = $1 AND task_id = $2),
+            (SELECT MIN(transition_time) FROM task_status_transitions
+             WHERE project_id = $1 AND task_id = $2 AND category = 'inProgress'),
+            $6)
+        ON CONFLICT (project_id, task_id)
+        DO UPDATE SET
+            assignee = EXCLUDED.assignee,
+            labels = EXCLUDED.labels,
+            original_estimate = EXCLUDED.original_estimate,
+            final_estimate = EXCLUDED.final_estimate,
+            create_time = EXCLUDED.create_time,
+            start_time = EXCLUDED.start_time,
+            done_time = EXCLUDED.done_time",
+    )
+    .bind(project_id)
+    .bind(&task.id)
+    .bind(&task.assignee)
+    .bind(Json(labels))
+    .bind(task.estimate)
+    .bind(time)
+    .execute(pool)
+    .await
+    .context("Failed to upsert task completion")?;
+    Ok(())
+}
+
+/// Records the task's estimate if it's the first one given.
+pub(crate) async fn record_estimate(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    task: &Task,
+    time: DateTime<Utc>,
+) -> Result<()> {
+    let Some(estimate) = task.estimate else {
+        return Ok(());
+    };
+    sqlx::query(
+        "
+        INSERT INTO task_estimates (project_id, task_id, original_estimate, estimate_time)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT DO NOTHING",
+    )
+    .bind(project_id)
+    .bind(&task.id)
+    .bind(estimate)
+    .bind(time)
+    .execute(pool)
+    .await
+    .context("Failed to insert task estimate")?;
+    Ok(())
+}
+
+/// The category as stored in the database.
+pub(crate) fn category_str(category: WorkflowCategory) -> &'static str {
+    match category {
+        WorkflowCategory::NotStarted => "notStarted",
+        WorkflowCategory::InProgress => "inProgress",
+        WorkflowCategory::Blocked => "blocked",
+        WorkflowCategory::Done => "done",
+    }
+}
@@ -0,0 +1,337 @@
+//! Tiering of project docs between Postgres and cold object storage. Docs of
+//! projects without updates for a while are compacted to a single snapshot
+//! and moved out of yupdates, then moved back the next time they're opened.
+//! Reads that don't open the doc, e.g. exports, merge the snapshot in place.
+
+use crate::{
+    api::model::ProjectId, object_store::object_store, postgres::PgPool, settings::settings,
+};
+use anyhow::{Context as _, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+use yrs::{
+    Update,
+    updates::{decoder::Decode as _, encoder::Encode as _},
+};
+
+/// How often inactive docs are moved to cold storage.
+const MOVE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Maximum docs moved per run.
+const BATCH_SIZE: i64 = 100;
+
+/// Returns the project's cold snapshot, if its doc is in cold storage.
+pub(super) async fn load_snapshot(
+    project_id: &ProjectId,
+    pool: &PgPool,
+) -> Result<Option<Vec<u8>>> {
+    let Some(object_key) = cold_object_key(project_id, pool).await? else {
+        return Ok(None);
+    };
+    let store = object_store().context("Doc is in cold storage, but none is configured")?;
+    Ok(Some(store.get(&object_key).await?))
+}
+
+async fn cold_object_key(project_id: &ProjectId, pool: &PgPool) -> Result<Option<String>> {
+    let key: Option<(String,)> =
+        sqlx::query_as("SELECT object_key FROM doc_tiers WHERE project_id = $1 AND tier = 'cold'")
+            .bind(project_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to query doc tier")?;
+    Ok(key.map(|(key,)| key))
+}
+
+/// Moves the project's doc back into Postgres if it's in cold storage. Called
+/// before opening the doc for editing.
+#[tracing::instrument(skip(pool))]
+pub(crate) async fn rehydrate(project_id: &ProjectId, pool: &PgPool) -> Result<()> {
+    // Most docs are hot, so check without taking a lock first.
+    if cold_object_key(project_id, pool).await?.is_none() {
+        return Ok(());
+    }
+
+    let mut txn = pool.begin().await?;
+    // Concurrent rehydrations wait here and then find the doc already hot.
+    let key: Option<(String,)> = sqlx::query_as(
+        "
+        SELECT object_key FROM doc_tiers
+        WHERE project_id = $1 AND tier = 'cold'
+        FOR UPDATE",
+    )
+    .bind(project_id)
+    .fetch_optional(&mut *txn)
+    .await?;
+    let Some((object_key,)) = key else {
+        return Ok(());
+    };
+    let store = object_store().context("Doc is in cold storage, but none is configured")?;
+    let snapshot = store.get(&object_key).await?;
+    sqlx::query(
+        "
+        INSERT INTO yupdates (project_id, seq, update_v2)
+        VALUES ($1, DEFAULT, $2)",
+    )
+    .bind(project_id)
+    .bind(&snapshot)
+    .execute(&mut *txn)
+    .await
+    .context("Failed to restore cold snapshot")?;
+    sqlx::query(
+        "
+        UPDATE doc_tiers
+        SET tier = 'hot', object_key = NULL, size = NULL, move_time = NOW()
+        WHERE project_id = $1",
+    )
+    .bind(project_id)
+    .execute(&mut *txn)
+    .await?;
+    txn.commit().await?;
+
+    metrics::counter!("doc_tier_moves_total", "direction" => "rehydrate").increment(1);
+    tracing::info!(
+        "Rehydrated doc from cold storage ({} bytes)",
+        snapshot.len()
+    );
+    if let Err(e) = store.delete(&object_key).await {
+        tracing::warn!("Failed to delete rehydrated snapshot {object_key}: {e:?}");
+    }
+    Ok(())
+}
+
+/// Compacts the project's updates, along with any existing cold snapshot, into a
+/// new snapshot in cold storage and removes them from Postgres.
+#[tracing::instrument(skip(pool))]
+async fn archive(project_id: &ProjectId, pool: &PgPool) -> Result<()> {
+    let store = object_store().context("No cold storage is configured")?;
+    let mut txn = pool.begin().await?;
+    let previous: Option<(String,)> = sqlx::query_as(
+        "
+        SELECT object_key FROM doc_tiers
+        WHERE project_id = $1 AND tier = 'cold'
+        FOR UPDATE",
+    )
+    .bind(project_id)
+    .fetch_optional(&mut *txn)
+    .await?;
+    // Updates inserted meanwhile aren't locked, or deleted, and stay hot.
+    let updates: Vec<(i32, Vec<u8>)> = sqlx::query_as(
+        "
+        SELECT seq, update_v2
+        FROM yupdates
+        WHERE project_id = $1
+        ORDER BY seq
+        FOR UPDATE",
+    )
+    .bind(project_id)
+    .fetch_all(&mut *txn)
+    .await?;
+    if updates.is_empty() {
+        return Ok(());
+    }
+
+    let mut decoded = Vec::with_capacity(updates.len() + 1);
+    if let Some((previous,)) = &previous {
+        decoded.push(Update::decode_v2(&store.get(previous).await?)?);
+    }
+    for (_, update) in &updates {
+        decoded.push(Update::decode_v2(update)?);
+    }
+    let snapshot = Update::merge_updates(decoded).encode_v2();
+    let size = snapshot.len() as i64;
+    let object_key = format!("docs/{project_id}/{}", Uuid::new_v4());
+    store.put(&object_key, snapshot).await?;
+
+    let seqs = updates.iter().map(|(seq, _)| *seq).collect::<Vec<_>>();
+    let result: Result<()> = async {
+        sqlx::query(
+            "
+            DELETE FROM yupdates
+            WHERE project_id = $1
+            AND seq IN (SELECT unnest($2::integer[]))",
+        )
+        .bind(project_id)
+        .bind(&seqs)
+        .execute(&mut *txn)
+        .await?;
+        sqlx::query(
+            "
+            INSERT INTO doc_tiers (project_id, tier, object_key, size)
+            VALUES ($1, 'cold', $2, $3)
+            ON CONFLICT (project_id)
+            DO UPDATE SET
+              tier = EXCLUDED.tier,
+              object_key = EXCLUDED.object_key,
+              size = EXCLUDED.size,
+              move_time = NOW()",
+        )
+        .bind(project_id)
+        .bind(&object_key)
+        .bind(size)
+        .execute(&mut *txn)
+        .await?;
+        txn.commit().await?;
+        Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        if let Err(e) = store.delete(&object_key).await {
+            tracing::warn!("Failed to delete orphaned snapshot {object_key}: {e:?}");
+        }
+        return Err(e.context("Failed to move doc to cold storage"));
+    }
+
+    metrics::counter!("doc_tier_moves_total", "direction" => "archive").increment(1);
+    tracing::info!(
+        "Moved {} updates to cold storage ({size} bytes)",
+        seqs.len()
+    );
+    if let Some((previous,)) = previous {
+        if let Err(e) = store.delete(&previous).await {
+            tracing::warn!("Failed to delete replaced snapshot {previous}: {e:?}");
+        }
+    }
+    Ok(())
+}
+
+/// Lists up to a batch of projects whose last update was stored before the
+/// cutoff. Docs rehydrated since are left hot even if nobody has edited them.
+/// Projects with updates but no metadata, e.g. from before metadata was
+/// recorded, have no known last update and are never listed.
+async fn inactive_projects(pool: &PgPool, cutoff: DateTime<Utc>) -> Result<Vec<ProjectId>> {
+    let inactive: Vec<(ProjectId,)> = sqlx::query_as(
+        "
+        SELECT y.project_id
+        FROM (SELECT DISTINCT project_id FROM yupdates) y
+        JOIN (
+            SELECT project_id, MAX(create_time) AS last_update_time
+            FROM yupdate_metadata
+            GROUP BY project_id
+        ) m ON m.project_id = y.project_id
+        LEFT JOIN doc_tiers t ON t.project_id = y.project_id
+        WHERE m.last_update_time < $1
+        AND (t.move_time IS NULL OR t.move_time < $1)
+        LIMIT $2",
+    )
+    .bind(cutoff)
+    .bind(BATCH_SIZE)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list inactive projects")?;
+    Ok(inactive
+        .into_iter()
+        .map(|(project_id,)| project_id)
+        .collect())
+}
+
+/// Periodically moves docs of inactive projects to cold storage and reports
+/// how many docs, and bytes, reside in each tier.
+pub(crate) struct ColdStorageMover {
+    pool: &'static PgPool,
+}
+
+impl ColdStorageMover {
+    pub(crate) fn new(pool: &'static PgPool) -> Self {
+        ColdStorageMover { pool }
+    }
+
+    pub(crate) fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(MOVE_INTERVAL);
+            loop {
+                interval.tick().await;
+                if self.pool.shed_best_effort() {
+                    continue;
+                }
+                if object_store().is_some() {
+                    if let Err(e) = self.archive_inactive().await {
+                        tracing::warn!("Failed to move docs to cold storage: {e:?}");
+                    }
+                }
+                if let Err(e) = self.record_residency().await {
+                    tracing::warn!("Failed to record doc tier residency: {e:?}");
+                }
+            }
+        })
+    }
+
+    async fn archive_inactive(&self) -> Result<()> {
+        let cutoff = Utc::now() - TimeDelta::days(settings().cold_storage.inactive_days.into());
+        for project_id in inactive_projects(self.pool, cutoff).await? {
+            if let Err(e) = archive(&project_id, self.pool).await {
+                tracing::warn!("Failed to move {project_id} to cold storage: {e:?}");
+            }
+        }
+        Ok(())
+    }
+
+    async fn record_residency(&self) -> Result<()> {
+        let (hot_projects, hot_bytes, cold_projects, cold_bytes): (i64, i64, i64, i64) =
+            sqlx::query_as(
+                "
+                SELECT
+                  (SELECT COUNT(DISTINCT project_id) FROM yupdates),
+                  pg_total_relation_size('yupdates'),
+                  (SELECT COUNT(*) FROM doc_tiers WHERE tier = 'cold'),
+                  (SELECT COALESCE(SUM(size), 0)::bigint FROM doc_tiers WHERE tier = 'cold')",
+            )
+            .fetch_one(self.pool)
+            .await
+            .context("Failed to query doc tier residency")?;
+        metrics::gauge!("doc_tier_projects", "tier" => "hot").set(hot_projects as f64);
+        metrics::gauge!("doc_tier_projects", "tier" => "cold").set(cold_projects as f64);
+        metrics::gauge!("doc_tier_bytes", "tier" => "hot").set(hot_bytes as f64);
+        metrics::gauge!("doc_tier_bytes", "tier" => "cold").set(cold_bytes as f64);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn insert_update(pool: &PgPool, project_id: &str, create_time: Option<DateTime<Utc>>) {
+        let (seq,): (i32,) = sqlx::query_as(
+            "
+            INSERT INTO yupdates (project_id, seq, update_v2)
+            VALUES ($1, DEFAULT, $2)
+            RETURNING seq",
+        )
+        .bind(project_id)
+        .bind(Update::default().encode_v2())
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        let Some(create_time) = create_time else {
+            return;
+        };
+        sqlx::query(
+            "
+            INSERT INTO yupdate_metadata (project_id, seq, txn_id, who, actor, feature, update_len, create_time)
+            VALUES ($1, $2, 'txn', 'who', 'server', 'test', 0, $3)",
+        )
+        .bind(project_id)
+        .bind(seq)
+        .bind(create_time)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    #[test_log::test(sqlx::test)]
+    async fn inactive_projects_require_a_known_last_update(pool: sqlx::PgPool) {
+        let pool = PgPool::from(pool);
+        let cutoff = Utc::now() - TimeDelta::days(30);
+        let old = cutoff - TimeDelta::days(1);
+        insert_update(&pool, "inactive", Some(old)).await;
+        insert_update(&pool, "active", Some(old)).await;
+        insert_update(&pool, "active", Some(Utc::now())).await;
+        insert_update(&pool, "unknown", None).await;
+
+        assert_eq!(
+            inactive_projects(&pool, cutoff).await.unwrap(),
+            vec!["inactive".to_string()]
+        );
+    }
+}
@@ -4,21 +4,78 @@ use yrs::Origin;
 
 use crate::api::google::User;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) enum Actor {
+    #[default]
     None,
     User(User),
+    /// An automation, e.g. an AI agent connected over MCP, acting on behalf of the user.
+    Agent(User),
     GitHub,
     Server,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+impl Actor {
+    /// The kind of actor, as stored in `yupdate_metadata.actor`.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Actor::None => "none",
+            Actor::User(_) => "user",
+            Actor::Agent(_) => "agent",
+            Actor::GitHub => "github",
+            Actor::Server => "server",
+        }
+    }
+
+    /// The email of the user the actor is or acts for, if any.
+    pub(crate) fn email(&self) -> Option<&str> {
+        match self {
+            Actor::User(user) | Actor::Agent(user) => Some(&user.email),
+            Actor::None | Actor::GitHub | Actor::Server => None,
+        }
+    }
+}
+
+/// Ids of the plugins that make changes, which prefix the `who` of their
+/// transactions, e.g. "github_webhook".
+pub(crate) const PLUGINS: &[&str] = &["github", "slack"];
+
+/// The plugin that made a transaction, given its `who`.
+pub(crate) fn plugin_of(who: &str) -> Option<&'static str> {
+    PLUGINS.iter().copied().find(|plugin| {
+        who.strip_prefix(plugin)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('_'))
+    })
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct YOrigin {
     pub(crate) who: String,
     pub(crate) id: String,
     pub(crate) actor: Actor,
+    #[serde(default)]
+    pub(crate) metadata: TxnMetadata,
+    /// Whether the update is relayed to peers as its sender framed it, so
+    /// isn't broadcast once applied.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) relayed: bool,
+}
+
+/// Details about where a transaction came from, stored alongside the resulting update.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TxnMetadata {
+    /// The client device, e.g. the user agent of the websocket connection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) device: Option<String>,
+    /// Id of the HTTP request that triggered the transaction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) request_id: Option<String>,
+    /// The feature that generated the transaction. Defaults to `who` when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) feature: Option<String>,
 }
 
 pub(crate) fn from_origin(origin: Option<&Origin>) -> Result<YOrigin> {
@@ -38,6 +95,13 @@ impl YOrigin {
             who: format!("{}-{}", prefix, self.who),
             id: format!("{}-{}", prefix, self.id),
             actor: Actor::Server,
+            metadata: self.metadata.clone(),
+            relayed: false,
         }
     }
+
+    /// The feature recorded for the transaction.
+    pub(crate) fn feature(&self) -> &str {
+        self.metadata.feature.as_deref().unwrap_or(&self.who)
+    }
 }
@@ -0,0 +1,291 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::Collab,
+        google::User,
+        inbox::Inbox,
+        model::{Comment, CreateComment, InboxKind, ProjectId, UpdateComment},
+        moderation, not_found_error, unauthorized_error, verify_project_access,
+    },
+    postgres::PgPool,
+};
+use anyhow::Context as _;
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    routing::{get, patch},
+};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use serde::Deserialize;
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/{project_id}/comments", get(list_project_comments_handler))
+        .route(
+            "/{project_id}/tasks/{task_id}/comments",
+            get(list_task_comments_handler).post(create_comment_handler),
+        )
+        .route(
+            "/{project_id}/comments/{comment_id}",
+            patch(update_comment_handler).delete(delete_comment_handler),
+        )
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ListCommentsQuery {
+    /// Only include threads that are not resolved.
+    #[serde(default)]
+    unresolved: bool,
+}
+
+const MAX_BODY_LEN: usize = 10_000;
+
+/// Lists a task's comments, oldest first. Replies follow in the same list
+/// and reference their thread via `parentId`. Quarantined comments are only
+/// listed for their author.
+#[tracing::instrument(skip(user, pool))]
+async fn list_task_comments_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, task_id)): Path<(ProjectId, String)>,
+    Query(query): Query<ListCommentsQuery>,
+) -> ApiResult<Json<Vec<Comment>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let comments: Vec<Comment> = sqlx::query_as(
+        "
+        SELECT c.id, c.task_id, c.parent_id, c.author, c.body, c.resolved, c.create_time, c.update_time
+        FROM task_comments c
+        LEFT JOIN task_comments root ON root.id = c.parent_id
+        WHERE c.project_id = $1
+        AND c.task_id = $2
+        AND (NOT $3 OR NOT COALESCE(root.resolved, c.resolved))
+        AND (NOT c.quarantined OR c.author = $4)
+        ORDER BY c.create_time, c.id",
+    )
+    .bind(&project_id)
+    .bind(&task_id)
+    .bind(query.unresolved)
+    .bind(&user.email)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list comments")?;
+    Ok(Json(comments))
+}
+
+/// Lists the root comments of threads across the project, newest first.
+#[tracing::instrument(skip(user, pool))]
+async fn list_project_comments_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Query(query): Query<ListCommentsQuery>,
+) -> ApiResult<Json<Vec<Comment>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let comments: Vec<Comment> = sqlx::query_as(
+        "
+        SELECT id, task_id, parent_id, author, body, resolved, create_time, update_time
+        FROM task_comments
+        WHERE project_id = $1
+        AND parent_id IS NULL
+        AND (NOT $2 OR NOT resolved)
+        AND (NOT quarantined OR author = $3)
+        ORDER BY create_time DESC, id
+        LIMIT 500",
+    )
+    .bind(&project_id)
+    .bind(query.unresolved)
+    .bind(&user.email)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list threads")?;
+    Ok(Json(comments))
+}
+
+#[tracing::instrument(skip(user, pool, collab))]
+async fn create_comment_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path((project_id, task_id)): Path<(ProjectId, String)>,
+    Json(comment): Json<CreateComment>,
+) -> ApiResult<Json<Comment>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    validate_body(&comment.body)?;
+
+    // Replies always attach to the thread's root comment.
+    let parent = match &comment.parent_id {
+        Some(parent_id) => {
+            let parent = fetch_comment(pool, &project_id, parent_id).await?;
+            if parent.task_id != task_id {
+                return Err(bad_request_error(
+                    "PARENT_MISMATCH",
+                    "Parent comment belongs to a different task",
+                ));
+            }
+            match parent.parent_id {
+                Some(root_id) => Some(fetch_comment(pool, &project_id, &root_id).await?),
+                None => Some(parent),
+            }
+        }
+        None => None,
+    };
+
+    // Comments from quarantined users are hidden from others until reviewed.
+    let quarantined = moderation::is_quarantined(pool, &project_id, &user.email).await?;
+    let created: Comment = sqlx::query_as(
+        "
+        INSERT INTO task_comments (id, project_id, task_id, parent_id, author, body, quarantined)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, task_id, parent_id, author, body, resolved, create_time, update_time",
+    )
+    .bind(BASE64_URL_SAFE_NO_PAD.encode(uuid::Uuid::new_v4()))
+    .bind(&project_id)
+    .bind(&task_id)
+    .bind(parent.as_ref().map(|p| &p.id))
+    .bind(&user.email)
+    .bind(&comment.body)
+    .bind(quarantined)
+    .fetch_one(pool)
+    .await
+    .context("Failed to insert comment")?;
+
+    if let Some(parent) = parent {
+        if parent.author != user.email && !quarantined {
+            Inbox::new(pool, collab.messenger())
+                .deliver(
+                    &parent.author,
+                    InboxKind::CommentReply,
+                    Some(&project_id),
+                    Some(&task_id),
+                    Some(&user.email),
+                    &format!("{} replied: {}", user.name, excerpt(&created.body)),
+                )
+                .await?;
+        }
+    }
+
+    Ok(Json(created))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn update_comment_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, comment_id)): Path<(ProjectId, String)>,
+    Json(update): Json<UpdateComment>,
+) -> ApiResult<Json<Comment>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let comment = fetch_comment(pool, &project_id, &comment_id).await?;
+    if let Some(body) = &update.body {
+        if comment.author != user.email {
+            return Err(unauthorized_error(&format!(
+                "User {} cannot edit comment {comment_id}",
+                user.email
+            )));
+        }
+        validate_body(body)?;
+    }
+    if update.resolved.is_some() && comment.parent_id.is_some() {
+        return Err(bad_request_error(
+            "NOT_A_THREAD",
+            "Only the root comment of a thread can be resolved",
+        ));
+    }
+
+    let updated: Comment = sqlx::query_as(
+        "
+        UPDATE task_comments
+        SET body = COALESCE($3, body),
+            resolved = COALESCE($4, resolved),
+            update_time = NOW()
+        WHERE project_id = $1 AND id = $2
+        RETURNING id, task_id, parent_id, author, body, resolved, create_time, update_time",
+    )
+    .bind(&project_id)
+    .bind(&comment_id)
+    .bind(&update.body)
+    .bind(update.resolved)
+    .fetch_one(pool)
+    .await
+    .context("Failed to update comment")?;
+    Ok(Json(updated))
+}
+
+/// Deletes a comment and, if it's the root of a thread, its replies.
+#[tracing::instrument(skip(user, pool))]
+async fn delete_comment_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, comment_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<()>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let comment = fetch_comment(pool, &project_id, &comment_id).await?;
+    if comment.author != user.email {
+        return Err(unauthorized_error(&format!(
+            "User {} cannot delete comment {comment_id}",
+            user.email
+        )));
+    }
+    sqlx::query(
+        "
+        DELETE FROM task_comments
+        WHERE project_id = $1 AND (id = $2 OR parent_id = $2)",
+    )
+    .bind(&project_id)
+    .bind(&comment_id)
+    .execute(pool)
+    .await
+    .context("Failed to delete comment")?;
+    Ok(Json(()))
+}
+
+async fn fetch_comment(pool: &PgPool, project_id: &ProjectId, id: &str) -> ApiResult<Comment> {
+    let comment: Option<Comment> = sqlx::query_as(
+        "
+        SELECT id, task_id, parent_id, author, body, resolved, create_time, update_time
+        FROM task_comments
+        WHERE project_id = $1 AND id = $2",
+    )
+    .bind(project_id)
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch comment")?;
+    comment.ok_or_else(|| not_found_error("NOT_FOUND", &format!("Comment {id} not found")))
+}
+
+fn validate_body(body: &str) -> ApiResult<()> {
+    if body.trim().is_empty() {
+        return Err(bad_request_error("EMPTY_BODY", "Comment is blank"));
+    }
+    if body.len() > MAX_BODY_LEN {
+        return Err(bad_request_error(
+            "LONG_BODY",
+            &format!("Comment cannot be longer than {MAX_BODY_LEN} characters"),
+        ));
+    }
+    Ok(())
+}
+
+/// Truncates the comment for use in notifications.
+fn excerpt(body: &str) -> String {
+    const MAX_EXCERPT_CHARS: usize = 80;
+    let body = body.trim();
+    match body.char_indices().nth(MAX_EXCERPT_CHARS) {
+        Some((i, _)) => format!("{}…", &body[..i]),
+        None => body.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excerpt_truncates_long_bodies() {
+        assert_eq!(excerpt("  short  "), "short");
+        let long = "é".repeat(100);
+        assert_eq!(excerpt(&long), format!("{}…", "é".repeat(80)));
+    }
+}
@@ -0,0 +1,144 @@
+//! The context of the request, or background work, being handled: its request
+//! id, user and project. The context lives in a task-local and its span, so
+//! every log line emitted while handling the request, including from collab
+//! operations and plugins, carries it without it being passed around.
+
+use crate::api::{google::User, model::ProjectId};
+use axum::{extract::Request, middleware::Next, response::Response};
+use std::{
+    future::Future,
+    sync::{Arc, OnceLock},
+};
+use tower_http::request_id::RequestId;
+use tracing::{Instrument as _, Span, field::Empty};
+
+tokio::task_local! {
+    static CONTEXT: Arc<RequestContext>;
+}
+
+#[derive(Debug)]
+pub(crate) struct RequestContext {
+    request_id: String,
+    /// Known only once the request is authenticated.
+    user: OnceLock<String>,
+    span: Span,
+}
+
+impl RequestContext {
+    pub(crate) fn new(request_id: String, project_id: Option<&str>) -> Arc<Self> {
+        let span = tracing::info_span!(
+            "context",
+            request_id = %request_id,
+            project_id = project_id,
+            user = Empty,
+        );
+        Arc::new(RequestContext {
+            request_id,
+            user: OnceLock::new(),
+            span,
+        })
+    }
+
+    pub(crate) fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    /// Records who the request is from. Only the first user recorded sticks.
+    pub(crate) fn set_user(&self, user: &User) {
+        if self.user.set(user.email.clone()).is_ok() {
+            self.span.record("user", &user.email);
+        }
+    }
+
+    /// Runs `f` in this context.
+    pub(crate) async fn scope<F: Future>(self: Arc<Self>, f: F) -> F::Output {
+        let span = self.span.clone();
+        CONTEXT.scope(self, f.instrument(span)).await
+    }
+}
+
+/// The current context, if any.
+pub(crate) fn current() -> Option<Arc<RequestContext>> {
+    CONTEXT.try_with(Arc::clone).ok()
+}
+
+/// Records the user of the current context, if any.
+pub(crate) fn set_user(user: &User) {
+    if let Some(context) = current() {
+        context.set_user(user);
+    }
+}
+
+/// Runs `f` in the given context, if any.
+pub(crate) async fn within<F: Future>(context: Option<Arc<RequestContext>>, f: F) -> F::Output {
+    match context {
+        Some(context) => context.scope(f).await,
+        None => f.await,
+    }
+}
+
+/// Spawns `f`, carrying over the current context, if any.
+pub(crate) fn spawn<F>(f: F) -> tokio::task::JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(within(current(), f))
+}
+
+/// Middleware establishing the context of each request.
+pub(crate) async fn propagate(request: Request, next: Next) -> Response {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .unwrap_or("MISSING")
+        .to_string();
+    let project_id: Option<ProjectId> = project_id_from_path(request.uri().path());
+    let context = RequestContext::new(request_id, project_id.as_deref());
+    context.scope(next.run(request)).await
+}
+
+/// Extracts the project id from paths like /api/projects/{project_id}/...
+fn project_id_from_path(path: &str) -> Option<ProjectId> {
+    let mut segments = path.split('/');
+    segments.find(|s| *s == "projects")?;
+    segments
+        .next()
+        .filter(|s| !s.is_empty())
+        .map(ToString::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn project_id_from_path_finds_project() {
+        assert_eq!(
+            project_id_from_path("/api/projects/abc/tasks").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(
+            project_id_from_path("/api/ws/projects/abc").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(project_id_from_path("/api/projects"), None);
+        assert_eq!(project_id_from_path("/api/projects/"), None);
+        assert_eq!(project_id_from_path("/api/me"), None);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn spawn_carries_context() {
+        assert!(current().is_none());
+        let context = RequestContext::new("req".to_string(), None);
+        let request_id = context
+            .scope(async {
+                spawn(async { current().map(|c| c.request_id().to_string()) })
+                    .await
+                    .unwrap()
+            })
+            .await;
+        assert_eq!(request_id.as_deref(), Some("req"));
+    }
+}
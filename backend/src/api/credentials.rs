@@ -0,0 +1,23 @@
+//! Lets deployment admins spot integration credentials that are failing or
+//! about to expire, e.g. revoked Slack tokens, before users report stale syncs.
+
+use crate::{
+    api::{ApiResult, google::User, verify_admin},
+    plugins::credentials::{self, CredentialHealth},
+    postgres::PgPool,
+};
+use axum::{Extension, Json, Router, routing::get};
+
+pub(super) fn router() -> Router {
+    Router::new().route("/", get(list_unhealthy_handler))
+}
+
+/// Lists failing credentials, then those that must be reconnected soon.
+#[tracing::instrument(skip(user, pool))]
+async fn list_unhealthy_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<Vec<CredentialHealth>>> {
+    verify_admin(&user)?;
+    Ok(Json(credentials::list_unhealthy(pool).await?))
+}
@@ -0,0 +1,438 @@
+//! Instance-wide health summaries for the ops dashboard: the largest projects,
+//! docs resident in memory, the heaviest API users, failed jobs, delivery
+//! backlogs and storage growth. Admin only.
+
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{Collab, projects_state::ResidentDoc},
+        google::User,
+        verify_admin,
+    },
+    postgres::ReadPool,
+};
+use anyhow::Context as _;
+use axum::{Extension, Json, Router, extract::Query, routing::get};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    FromRow,
+    types::chrono::{DateTime, Utc},
+};
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/projects", get(list_largest_projects_handler))
+        .route("/resident", get(list_resident_docs_handler))
+        .route("/top-talkers", get(list_top_talkers_handler))
+        .route("/failed-jobs", get(list_failed_jobs_handler))
+        .route("/backlogs", get(get_backlogs_handler))
+        .route("/storage", get(get_storage_handler))
+}
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 500;
+const DEFAULT_HOURS: i32 = 24;
+const MAX_HOURS: i32 = 30 * 24;
+const DEFAULT_DAYS: i32 = 30;
+const MAX_DAYS: i32 = 365;
+/// Tables whose size is reported, the largest and fastest growing ones.
+const TABLES: &[&str] = &[
+    "yupdates",
+    "yupdate_metadata",
+    "task_status_transitions",
+    "inbox_notifications",
+    "notification_deliveries",
+    "api_usage",
+    "task_attachments",
+    "project_snapshots",
+    "jobs",
+];
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct DashboardQuery {
+    limit: Option<i64>,
+    /// How far back activity is summarized, in hours.
+    hours: Option<i32>,
+    /// How far back growth is summarized, in days.
+    days: Option<i32>,
+}
+
+impl DashboardQuery {
+    fn limit(&self) -> ApiResult<i64> {
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(bad_request_error(
+                "INVALID_LIMIT",
+                &format!("Limit must be between 1 and {MAX_LIMIT}"),
+            ));
+        }
+        Ok(limit)
+    }
+
+    fn hours(&self) -> ApiResult<i32> {
+        let hours = self.hours.unwrap_or(DEFAULT_HOURS);
+        if !(1..=MAX_HOURS).contains(&hours) {
+            return Err(bad_request_error(
+                "INVALID_HOURS",
+                &format!("Hours must be between 1 and {MAX_HOURS}"),
+            ));
+        }
+        Ok(hours)
+    }
+
+    fn days(&self) -> ApiResult<i32> {
+        let days = self.days.unwrap_or(DEFAULT_DAYS);
+        if !(1..=MAX_DAYS).contains(&days) {
+            return Err(bad_request_error(
+                "INVALID_DAYS",
+                &format!("Days must be between 1 and {MAX_DAYS}"),
+            ));
+        }
+        Ok(days)
+    }
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ProjectSize {
+    project_id: String,
+    name: Option<String>,
+    /// Bytes of stored updates in Postgres.
+    hot_bytes: i64,
+    /// Bytes of the snapshot in cold storage, if any.
+    cold_bytes: i64,
+    stored_updates: i64,
+    members: i64,
+}
+
+/// Lists projects by the size of their stored doc, largest first.
+#[tracing::instrument(skip(user, read_pool))]
+async fn list_largest_projects_handler(
+    Extension(user): Extension<User>,
+    Extension(read_pool): Extension<ReadPool>,
+    Query(query): Query<DashboardQuery>,
+) -> ApiResult<Json<Vec<ProjectSize>>> {
+    verify_admin(&user)?;
+    let sizes: Vec<ProjectSize> = sqlx::query_as(
+        "
+        WITH sizes AS (
+            SELECT project_id, SUM(octet_length(update_v2))::bigint AS bytes, COUNT(*) AS updates
+            FROM yupdates
+            GROUP BY project_id
+        )
+        SELECT
+          p.project_id,
+          p.name,
+          COALESCE(s.bytes, 0)::bigint AS hot_bytes,
+          COALESCE(t.size, 0)::bigint AS cold_bytes,
+          COALESCE(s.updates, 0) AS stored_updates,
+          (SELECT COUNT(*) FROM project_permissions pp WHERE pp.project_id = p.project_id) AS members
+        FROM projects p
+        LEFT JOIN sizes s ON s.project_id = p.project_id
+        LEFT JOIN doc_tiers t ON t.project_id = p.project_id AND t.tier = 'cold'
+        WHERE p.deleted_on IS NULL
+        ORDER BY COALESCE(s.bytes, 0) + COALESCE(t.size, 0) DESC
+        LIMIT $1",
+    )
+    .bind(query.limit()?)
+    .fetch_all(read_pool.get())
+    .await
+    .context("Failed to list project sizes")?;
+    Ok(Json(sizes))
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ResidentDocs {
+    docs: usize,
+    clients: usize,
+    /// Resident docs with the most clients first.
+    projects: Vec<ResidentDoc>,
+}
+
+/// Summarizes the docs loaded in memory on the server handling the request.
+#[tracing::instrument(skip(user, collab))]
+async fn list_resident_docs_handler(
+    Extension(user): Extension<User>,
+    Extension(collab): Extension<Collab>,
+    Query(query): Query<DashboardQuery>,
+) -> ApiResult<Json<ResidentDocs>> {
+    verify_admin(&user)?;
+    let limit = usize::try_from(query.limit()?)?;
+    let mut projects = collab.resident_docs().await;
+    projects.sort_by(|a, b| b.clients.cmp(&a.clients));
+    let docs = projects.len();
+    let clients = projects.iter().map(|p| p.clients).sum();
+    projects.truncate(limit);
+    Ok(Json(ResidentDocs {
+        docs,
+        clients,
+        projects,
+    }))
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Talker {
+    email: String,
+    channel: String,
+    requests: i64,
+    errors: i64,
+    projects: i64,
+}
+
+/// Lists the users making the most API requests recently.
+#[tracing::instrument(skip(user, read_pool))]
+async fn list_top_talkers_handler(
+    Extension(user): Extension<User>,
+    Extension(read_pool): Extension<ReadPool>,
+    Query(query): Query<DashboardQuery>,
+) -> ApiResult<Json<Vec<Talker>>> {
+    verify_admin(&user)?;
+    let talkers: Vec<Talker> = sqlx::query_as(
+        "
+        SELECT
+          email,
+          channel,
+          SUM(requests)::bigint AS requests,
+          SUM(errors)::bigint AS errors,
+          COUNT(DISTINCT project_id) AS projects
+        FROM api_usage
+        WHERE bucket_start >= NOW() - make_interval(hours => $1)
+        GROUP BY email, channel
+        ORDER BY requests DESC
+        LIMIT $2",
+    )
+    .bind(query.hours()?)
+    .bind(query.limit()?)
+    .fetch_all(read_pool.get())
+    .await
+    .context("Failed to list top talkers")?;
+    Ok(Json(talkers))
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FailedJob {
+    job_id: String,
+    kind: String,
+    /// dead if out of attempts, otherwise queued for a retry.
+    status: String,
+    attempts: i32,
+    last_error: Option<String>,
+    run_at: DateTime<Utc>,
+    create_time: DateTime<Utc>,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FailedJobCount {
+    kind: String,
+    dead: i64,
+    retrying: i64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FailedJobs {
+    counts: Vec<FailedJobCount>,
+    /// The most recently created failed jobs.
+    jobs: Vec<FailedJob>,
+}
+
+/// Lists jobs that failed, whether dead or awaiting a retry.
+#[tracing::instrument(skip(user, read_pool))]
+async fn list_failed_jobs_handler(
+    Extension(user): Extension<User>,
+    Extension(read_pool): Extension<ReadPool>,
+    Query(query): Query<DashboardQuery>,
+) -> ApiResult<Json<FailedJobs>> {
+    verify_admin(&user)?;
+    let pool = read_pool.get();
+    let counts: Vec<FailedJobCount> = sqlx::query_as(
+        "
+        SELECT
+          kind,
+          COUNT(*) FILTER (WHERE status = 'dead') AS dead,
+          COUNT(*) FILTER (WHERE status = 'queued') AS retrying
+        FROM jobs
+        WHERE last_error IS NOT NULL AND status IN ('dead', 'queued')
+        GROUP BY kind
+        ORDER BY kind",
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to count failed jobs")?;
+    let jobs: Vec<FailedJob> = sqlx::query_as(
+        "
+        SELECT job_id, kind, status, attempts, last_error, run_at, create_time
+        FROM jobs
+        WHERE last_error IS NOT NULL AND status IN ('dead', 'queued')
+        ORDER BY create_time DESC
+        LIMIT $1",
+    )
+    .bind(query.limit()?)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list failed jobs")?;
+    Ok(Json(FailedJobs { counts, jobs }))
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Backlog {
+    /// What's backed up, e.g. a notifier or job kind.
+    name: String,
+    pending: i64,
+    oldest: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Backlogs {
+    /// Outbound notifications, including webhooks, awaiting delivery or a retry.
+    deliveries: Vec<Backlog>,
+    /// Notifications held back by users' quiet hours or batching.
+    held_notifications: Vec<Backlog>,
+    /// Jobs waiting to run.
+    jobs: Vec<Backlog>,
+}
+
+#[tracing::instrument(skip(user, read_pool))]
+async fn get_backlogs_handler(
+    Extension(user): Extension<User>,
+    Extension(read_pool): Extension<ReadPool>,
+) -> ApiResult<Json<Backlogs>> {
+    verify_admin(&user)?;
+    let pool = read_pool.get();
+    let deliveries: Vec<Backlog> = sqlx::query_as(
+        "
+        SELECT notifier AS name, COUNT(*) AS pending, MIN(create_time) AS oldest
+        FROM notification_deliveries
+        WHERE status IN ('pending', 'failed')
+        GROUP BY notifier
+        ORDER BY pending DESC",
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to summarize delivery backlog")?;
+    let held_notifications: Vec<Backlog> = sqlx::query_as(
+        "
+        SELECT reason AS name, COUNT(*) AS pending, MIN(create_time) AS oldest
+        FROM held_notifications
+        GROUP BY reason
+        ORDER BY pending DESC",
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to summarize held notifications")?;
+    let jobs: Vec<Backlog> = sqlx::query_as(
+        "
+        SELECT kind AS name, COUNT(*) AS pending, MIN(run_at) AS oldest
+        FROM jobs
+        WHERE status = 'queued' AND run_at <= NOW()
+        GROUP BY kind
+        ORDER BY pending DESC",
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to summarize job backlog")?;
+    Ok(Json(Backlogs {
+        deliveries,
+        held_notifications,
+        jobs,
+    }))
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct TableSize {
+    table_name: String,
+    bytes: i64,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct DailyGrowth {
+    day: DateTime<Utc>,
+    /// Doc updates stored that day, before compaction.
+    updates: i64,
+    update_bytes: i64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Storage {
+    database_bytes: i64,
+    table_sizes: Vec<TableSize>,
+    cold_projects: i64,
+    cold_bytes: i64,
+    /// Growth per day, oldest first.
+    growth: Vec<DailyGrowth>,
+}
+
+/// Reports current storage use and how quickly it's growing.
+#[tracing::instrument(skip(user, read_pool))]
+async fn get_storage_handler(
+    Extension(user): Extension<User>,
+    Extension(read_pool): Extension<ReadPool>,
+    Query(query): Query<DashboardQuery>,
+) -> ApiResult<Json<Storage>> {
+    verify_admin(&user)?;
+    let pool = read_pool.get();
+    let (database_bytes, cold_projects, cold_bytes): (i64, i64, i64) = sqlx::query_as(
+        "
+        SELECT
+          pg_database_size(current_database()),
+          (SELECT COUNT(*) FROM doc_tiers WHERE tier = 'cold'),
+          (SELECT COALESCE(SUM(size), 0)::bigint FROM doc_tiers WHERE tier = 'cold')",
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to get database size")?;
+    let table_sizes: Vec<TableSize> = sqlx::query_as(
+        "
+        SELECT t AS table_name, pg_total_relation_size(t::regclass) AS bytes
+        FROM unnest($1::text[]) AS t
+        ORDER BY bytes DESC",
+    )
+    .bind(TABLES)
+    .fetch_all(pool)
+    .await
+    .context("Failed to get table sizes")?;
+    let growth: Vec<DailyGrowth> = sqlx::query_as(
+        "
+        WITH days AS (
+            SELECT generate_series(
+                date_trunc('day', NOW()) - make_interval(days => $1 - 1),
+                date_trunc('day', NOW()),
+                interval '1 day'
+            ) AS day
+        ),
+        updates AS (
+            SELECT date_trunc('day', create_time) AS day, COUNT(*) AS updates, SUM(update_len)::bigint AS bytes
+            FROM yupdate_metadata
+            WHERE create_time >= date_trunc('day', NOW()) - make_interval(days => $1 - 1)
+            GROUP BY 1
+        )
+        SELECT
+          d.day,
+          COALESCE(u.updates, 0) AS updates,
+          COALESCE(u.bytes, 0)::bigint AS update_bytes
+        FROM days d
+        LEFT JOIN updates u ON u.day = d.day
+        ORDER BY d.day",
+    )
+    .bind(query.days()?)
+    .fetch_all(pool)
+    .await
+    .context("Failed to summarize storage growth")?;
+    Ok(Json(Storage {
+        database_bytes,
+        table_sizes,
+        cold_projects,
+        cold_bytes,
+        growth,
+    }))
+}
@@ -0,0 +1,295 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        google::User,
+        model::{CreateDecision, Decision, ProjectId, UpdateDecision},
+        not_found_error, unauthorized_error, validate_title, verify_project_access,
+    },
+    postgres::PgPool,
+};
+use anyhow::Context as _;
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    routing::{get, patch},
+};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use serde::Deserialize;
+use sqlx::types::{Json as SqlJson, chrono::Utc};
+use uuid::Uuid;
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route(
+            "/{project_id}/decisions",
+            get(list_project_decisions_handler),
+        )
+        .route(
+            "/{project_id}/tasks/{task_id}/decisions",
+            get(list_task_decisions_handler).post(create_decision_handler),
+        )
+        .route(
+            "/{project_id}/decisions/{decision_id}",
+            patch(update_decision_handler).delete(delete_decision_handler),
+        )
+}
+
+const MAX_TEXT_LEN: usize = 20_000;
+const MAX_ENTRIES: usize = 20;
+const DECISION_COLUMNS: &str = "id, task_id, title, context, decision, alternatives, deciders, decision_date, author, create_time, update_time";
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ListDecisionsQuery {
+    /// Only include decisions made by this user.
+    decider: Option<String>,
+}
+
+/// Lists a task's decisions, most recent first.
+#[tracing::instrument(skip(user, pool))]
+async fn list_task_decisions_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, task_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<Vec<Decision>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let decisions: Vec<Decision> = sqlx::query_as(&format!(
+        "
+        SELECT {DECISION_COLUMNS}
+        FROM decisions
+        WHERE project_id = $1 AND task_id = $2
+        ORDER BY decision_date DESC, create_time DESC"
+    ))
+    .bind(&project_id)
+    .bind(&task_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list decisions")?;
+    Ok(Json(decisions))
+}
+
+/// Indexes decisions across the project, most recent first.
+#[tracing::instrument(skip(user, pool))]
+async fn list_project_decisions_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Query(query): Query<ListDecisionsQuery>,
+) -> ApiResult<Json<Vec<Decision>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let decisions: Vec<Decision> = sqlx::query_as(&format!(
+        "
+        SELECT {DECISION_COLUMNS}
+        FROM decisions
+        WHERE project_id = $1
+        AND ($2::varchar IS NULL OR deciders ? $2)
+        ORDER BY decision_date DESC, create_time DESC
+        LIMIT 500"
+    ))
+    .bind(&project_id)
+    .bind(query.decider.map(|d| d.to_lowercase()))
+    .fetch_all(pool)
+    .await
+    .context("Failed to list project decisions")?;
+    Ok(Json(decisions))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn create_decision_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, task_id)): Path<(ProjectId, String)>,
+    Json(decision): Json<CreateDecision>,
+) -> ApiResult<Json<Decision>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    validate_title("Decision", &decision.title)?;
+    validate_text("Context", &decision.context)?;
+    validate_text("Decision", &decision.decision)?;
+    validate_entries(&decision.alternatives)?;
+    let deciders = normalize_deciders(&decision.deciders)?;
+
+    let created: Decision = sqlx::query_as(&format!(
+        "
+        INSERT INTO decisions (id, project_id, task_id, title, context, decision, alternatives, deciders, decision_date, author)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        RETURNING {DECISION_COLUMNS}"
+    ))
+    .bind(BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4()))
+    .bind(&project_id)
+    .bind(&task_id)
+    .bind(decision.title.trim())
+    .bind(&decision.context)
+    .bind(&decision.decision)
+    .bind(SqlJson(&decision.alternatives))
+    .bind(SqlJson(&deciders))
+    .bind(
+        decision
+            .decision_date
+            .unwrap_or_else(|| Utc::now().date_naive()),
+    )
+    .bind(&user.email)
+    .fetch_one(pool)
+    .await
+    .context("Failed to insert decision")?;
+    Ok(Json(created))
+}
+
+/// Any project member may amend a decision record.
+#[tracing::instrument(skip(user, pool))]
+async fn update_decision_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, decision_id)): Path<(ProjectId, String)>,
+    Json(update): Json<UpdateDecision>,
+) -> ApiResult<Json<Decision>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    if let Some(title) = &update.title {
+        validate_title("Decision", title)?;
+    }
+    if let Some(context) = &update.context {
+        validate_text("Context", context)?;
+    }
+    if let Some(decision) = &update.decision {
+        validate_text("Decision", decision)?;
+    }
+    if let Some(alternatives) = &update.alternatives {
+        validate_entries(alternatives)?;
+    }
+    let deciders = update
+        .deciders
+        .as_deref()
+        .map(normalize_deciders)
+        .transpose()?;
+
+    let updated: Option<Decision> = sqlx::query_as(&format!(
+        "
+        UPDATE decisions
+        SET title = COALESCE($3, title),
+            context = COALESCE($4, context),
+            decision = COALESCE($5, decision),
+            alternatives = COALESCE($6, alternatives),
+            deciders = COALESCE($7, deciders),
+            decision_date = COALESCE($8, decision_date),
+            update_time = NOW()
+        WHERE project_id = $1 AND id = $2
+        RETURNING {DECISION_COLUMNS}"
+    ))
+    .bind(&project_id)
+    .bind(&decision_id)
+    .bind(update.title.as_deref().map(str::trim))
+    .bind(&update.context)
+    .bind(&update.decision)
+    .bind(update.alternatives.as_ref().map(SqlJson))
+    .bind(deciders.as_ref().map(SqlJson))
+    .bind(update.decision_date)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to update decision")?;
+    updated
+        .map(Json)
+        .ok_or_else(|| not_found_error("NOT_FOUND", &format!("Decision {decision_id} not found")))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_decision_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, decision_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<()>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let author: Option<(String,)> =
+        sqlx::query_as("SELECT author FROM decisions WHERE project_id = $1 AND id = $2")
+            .bind(&project_id)
+            .bind(&decision_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to fetch decision")?;
+    let Some((author,)) = author else {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Decision {decision_id} not found"),
+        ));
+    };
+    if author != user.email {
+        return Err(unauthorized_error(&format!(
+            "User {} cannot delete decision {decision_id}",
+            user.email
+        )));
+    }
+    sqlx::query("DELETE FROM decisions WHERE id = $1")
+        .bind(&decision_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete decision")?;
+    Ok(Json(()))
+}
+
+fn validate_text(field: &str, text: &str) -> ApiResult<()> {
+    if text.trim().is_empty() {
+        return Err(bad_request_error(
+            "EMPTY_TEXT",
+            &format!("{field} is blank"),
+        ));
+    }
+    if text.len() > MAX_TEXT_LEN {
+        return Err(bad_request_error(
+            "LONG_TEXT",
+            &format!("{field} cannot be longer than {MAX_TEXT_LEN} characters"),
+        ));
+    }
+    Ok(())
+}
+
+fn validate_entries(entries: &[String]) -> ApiResult<()> {
+    if entries.len() > MAX_ENTRIES {
+        return Err(bad_request_error(
+            "TOO_MANY_ENTRIES",
+            &format!("Cannot list more than {MAX_ENTRIES} entries"),
+        ));
+    }
+    if entries.iter().any(|e| e.len() > MAX_TEXT_LEN) {
+        return Err(bad_request_error(
+            "LONG_TEXT",
+            &format!("Entries cannot be longer than {MAX_TEXT_LEN} characters"),
+        ));
+    }
+    Ok(())
+}
+
+/// Lowercases and dedupes decider emails, preserving their order.
+fn normalize_deciders(deciders: &[String]) -> ApiResult<Vec<String>> {
+    validate_entries(deciders)?;
+    let mut normalized: Vec<String> = vec![];
+    for decider in deciders {
+        let decider = decider.trim().to_lowercase();
+        if !decider.contains('@') {
+            return Err(bad_request_error(
+                "INVALID_DECIDER",
+                &format!("Decider {decider} is not an email"),
+            ));
+        }
+        if !normalized.contains(&decider) {
+            normalized.push(decider);
+        }
+    }
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_deciders_lowercases_and_dedupes() {
+        assert_eq!(
+            normalize_deciders(&[
+                "Ann@Example.com".into(),
+                " bob@example.com".into(),
+                "ann@example.com".into(),
+            ])
+            .unwrap(),
+            vec!["ann@example.com", "bob@example.com"]
+        );
+        assert!(normalize_deciders(&["ann".into()]).is_err());
+    }
+}
@@ -0,0 +1,510 @@
+//! Demo sandboxes: projects, e.g. embedded as a live playground on the
+//! marketing site, that anyone can edit without signing in. Anonymous sessions
+//! are held to tight quotas and the project is reset to a stored snapshot on
+//! a schedule, undoing whatever visitors did.
+
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{
+            Collab,
+            capabilities::Capabilities,
+            client::ConnectionInfo,
+            projects_state::DocBox,
+            txn_origin::{Actor, YOrigin},
+        },
+        google::User,
+        model::ProjectId,
+        not_found_error, unauthorized_error, verify_admin,
+        ws::ConnectQuery,
+        yproxy::YDocProxy,
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    body::Body,
+    extract::{Path, Query, WebSocketUpgrade},
+    response::Response,
+    routing::{get, post, put},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    FromRow,
+    types::chrono::{DateTime, Utc},
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+use yrs::{TransactionMut, Update, updates::decoder::Decode as _};
+
+/// How often demo projects are checked for a due reset.
+const RESET_INTERVAL: Duration = Duration::from_secs(60);
+const MIN_RESET_INTERVAL_MINS: i32 = 5;
+const MAX_RESET_INTERVAL_MINS: i32 = 7 * 24 * 60;
+
+/// Anonymous users are given emails in this domain, which no real user has.
+const GUEST_DOMAIN: &str = "guest.koso.app";
+/// Concurrent anonymous sessions allowed per demo project.
+const MAX_SESSIONS: usize = 50;
+/// How long an anonymous session may last.
+const MAX_SESSION_DURATION: Duration = Duration::from_secs(30 * 60);
+/// Total bytes an anonymous session may send.
+const MAX_SESSION_BYTES: usize = 2 * 1024 * 1024;
+/// Messages an anonymous session may send each minute, awareness included.
+const MAX_MESSAGES_PER_MINUTE: usize = 240;
+
+/// Routes for deployment admins to manage demo projects.
+pub(super) fn router() -> Router {
+    Router::new()
+        .route(
+            "/{project_id}/demo",
+            put(set_demo_handler).delete(delete_demo_handler),
+        )
+        .route("/{project_id}/demo/reset", post(reset_demo_handler))
+}
+
+/// Unauthenticated routes for anonymous visitors.
+pub(super) fn public_router() -> Router {
+    Router::new().route("/{project_id}/ws", get(ws_handler))
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct DemoProject {
+    project_id: String,
+    /// The project snapshot the project is reset to.
+    snapshot_id: String,
+    reset_interval_mins: i32,
+    last_reset_time: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SetDemo {
+    snapshot_id: String,
+    reset_interval_mins: i32,
+}
+
+/// Flags the project as a demo sandbox. Anyone can then edit it anonymously,
+/// so only deployment admins can.
+#[tracing::instrument(skip(user, pool))]
+async fn set_demo_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Json(req): Json<SetDemo>,
+) -> ApiResult<Json<DemoProject>> {
+    verify_admin(&user)?;
+    if !(MIN_RESET_INTERVAL_MINS..=MAX_RESET_INTERVAL_MINS).contains(&req.reset_interval_mins) {
+        return Err(bad_request_error(
+            "INVALID_RESET_INTERVAL",
+            &format!(
+                "Reset interval must be between {MIN_RESET_INTERVAL_MINS} and {MAX_RESET_INTERVAL_MINS} minutes"
+            ),
+        ));
+    }
+    let snapshot: Option<(String,)> =
+        sqlx::query_as("SELECT id FROM project_snapshots WHERE project_id = $1 AND id = $2")
+            .bind(&project_id)
+            .bind(&req.snapshot_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to check snapshot")?;
+    if snapshot.is_none() {
+        return Err(not_found_error("NOT_FOUND", "Snapshot not found"));
+    }
+
+    let demo: DemoProject = sqlx::query_as(
+        "
+        INSERT INTO demo_projects (project_id, snapshot_id, reset_interval_mins, creator)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (project_id)
+        DO UPDATE SET snapshot_id = EXCLUDED.snapshot_id, reset_interval_mins = EXCLUDED.reset_interval_mins
+        RETURNING project_id, snapshot_id, reset_interval_mins, last_reset_time",
+    )
+    .bind(&project_id)
+    .bind(&req.snapshot_id)
+    .bind(req.reset_interval_mins)
+    .bind(&user.email)
+    .fetch_one(pool)
+    .await
+    .context("Failed to set demo project")?;
+    Ok(Json(demo))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_demo_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<()>> {
+    verify_admin(&user)?;
+    let res = sqlx::query("DELETE FROM demo_projects WHERE project_id = $1")
+        .bind(&project_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete demo project")?;
+    if res.rows_affected() == 0 {
+        return Err(not_found_error("NOT_FOUND", "Project isn't a demo"));
+    }
+    Ok(Json(()))
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ResetResponse {
+    changed_tasks: usize,
+}
+
+/// Resets the demo now rather than waiting for its schedule.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn reset_demo_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<ResetResponse>> {
+    verify_admin(&user)?;
+    let Some(demo) = fetch_demo(pool, &project_id).await? else {
+        return Err(not_found_error("NOT_FOUND", "Project isn't a demo"));
+    };
+    let changed_tasks = reset_project(pool, &collab, &demo).await?;
+    Ok(Json(ResetResponse { changed_tasks }))
+}
+
+async fn fetch_demo(pool: &PgPool, project_id: &ProjectId) -> Result<Option<DemoProject>> {
+    sqlx::query_as(
+        "
+        SELECT d.project_id, d.snapshot_id, d.reset_interval_mins, d.last_reset_time
+        FROM demo_projects d
+        JOIN projects p ON p.project_id = d.project_id
+        WHERE d.project_id = $1 AND p.deleted_on IS NULL",
+    )
+    .bind(project_id)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch demo project")
+}
+
+/// Opens an anonymous edit session on a demo project.
+#[tracing::instrument(skip(ws, pool, collab))]
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+    Query(query): Query<ConnectQuery>,
+) -> ApiResult<Response<Body>> {
+    if fetch_demo(pool, &project_id).await?.is_none() {
+        return Err(not_found_error("NOT_FOUND", "Project isn't a demo"));
+    }
+    let Some(quota) = SessionQuota::acquire(&project_id) else {
+        metrics::counter!("demo_sessions_rejected_total").increment(1);
+        return Err(unauthorized_error(
+            "Too many visitors in the demo, try again later",
+        ));
+    };
+
+    let who = Uuid::new_v4().to_string();
+    let user = User {
+        email: format!("{}@{GUEST_DOMAIN}", &who[..8]),
+        name: "Guest".to_string(),
+        picture: String::new(),
+        exp: 0,
+        iat: None,
+        auth_time: None,
+        nonce: None,
+    };
+    let connection = ConnectionInfo {
+        capabilities: Capabilities::parse(query.capabilities.as_deref(), query.version.as_deref()),
+        quota: Some(Arc::new(quota)),
+        ..Default::default()
+    };
+    Ok(ws
+        .max_message_size(MAX_SESSION_BYTES)
+        .on_failed_upgrade(|e| tracing::warn!("Failed to upgrade socket: {e:?}"))
+        .on_upgrade(move |socket| async move {
+            if let Err(e) = collab
+                .register_client(socket, who, project_id, user, connection)
+                .await
+            {
+                tracing::warn!("Failed to register guest client: {e:?}");
+            }
+        }))
+}
+
+/// Whether the user is an anonymous demo guest.
+pub(crate) fn is_guest(user: &User) -> bool {
+    user.email.ends_with(&format!("@{GUEST_DOMAIN}"))
+}
+
+/// Verifies that guests may edit the project.
+pub(crate) async fn verify_guest_access(pool: &PgPool, project_id: &ProjectId) -> ApiResult<()> {
+    match fetch_demo(pool, project_id).await? {
+        Some(_) => Ok(()),
+        None => Err(unauthorized_error(&format!(
+            "Project {project_id} isn't a demo"
+        ))),
+    }
+}
+
+static SESSIONS: LazyLock<Mutex<HashMap<ProjectId, usize>>> = LazyLock::new(Mutex::default);
+
+/// Limits what an anonymous session can send. Also counts towards the
+/// project's concurrent sessions until dropped.
+#[derive(Debug)]
+pub(crate) struct SessionQuota {
+    project_id: ProjectId,
+    start: Instant,
+    bytes: AtomicUsize,
+    /// The start of the current minute, and the messages sent in it.
+    minute: Mutex<(Instant, usize)>,
+}
+
+impl SessionQuota {
+    fn acquire(project_id: &ProjectId) -> Option<SessionQuota> {
+        let mut sessions = SESSIONS.lock().unwrap();
+        let count = sessions.entry(project_id.clone()).or_default();
+        if *count >= MAX_SESSIONS {
+            return None;
+        }
+        *count += 1;
+        Some(SessionQuota {
+            project_id: project_id.clone(),
+            start: Instant::now(),
+            bytes: AtomicUsize::new(0),
+            minute: Mutex::new((Instant::now(), 0)),
+        })
+    }
+
+    /// Charges a message of the given length, returning why the session must
+    /// end if it's over quota.
+    pub(crate) fn charge(&self, len: usize) -> Result<(), &'static str> {
+        if self.start.elapsed() > MAX_SESSION_DURATION {
+            return Err("Demo session expired.");
+        }
+        if self.bytes.fetch_add(len, Ordering::Relaxed) + len > MAX_SESSION_BYTES {
+            return Err("Demo session quota exhausted.");
+        }
+        let mut minute = self.minute.lock().unwrap();
+        if minute.0.elapsed() >= Duration::from_secs(60) {
+            *minute = (Instant::now(), 0);
+        }
+        minute.1 += 1;
+        if minute.1 > MAX_MESSAGES_PER_MINUTE {
+            return Err("Demo session rate limit exceeded.");
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SessionQuota {
+    fn drop(&mut self) {
+        let mut sessions = SESSIONS.lock().unwrap();
+        if let Some(count) = sessions.get_mut(&self.project_id) {
+            *count -= 1;
+            if *count == 0 {
+                sessions.remove(&self.project_id);
+            }
+        }
+    }
+}
+
+/// Resets demo projects to their snapshots on schedule.
+pub(crate) struct DemoResetter {
+    pool: &'static PgPool,
+    collab: Collab,
+}
+
+impl DemoResetter {
+    pub(crate) fn new(pool: &'static PgPool, collab: Collab) -> Self {
+        DemoResetter { pool, collab }
+    }
+
+    pub(crate) fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RESET_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.reset_due_projects().await {
+                    tracing::warn!("Failed to reset demo projects: {e:?}");
+                }
+            }
+        })
+    }
+
+    async fn reset_due_projects(&self) -> Result<()> {
+        let due: Vec<DemoProject> = sqlx::query_as(
+            "
+            SELECT d.project_id, d.snapshot_id, d.reset_interval_mins, d.last_reset_time
+            FROM demo_projects d
+            JOIN projects p ON p.project_id = d.project_id
+            WHERE p.deleted_on IS NULL
+            AND d.last_reset_time + d.reset_interval_mins * INTERVAL '1 minute' <= NOW()",
+        )
+        .fetch_all(self.pool)
+        .await
+        .context("Failed to list demo projects due for reset")?;
+        for demo in due {
+            if let Err(e) = reset_project(self.pool, &self.collab, &demo).await {
+                tracing::warn!("Failed to reset demo project {}: {e:?}", demo.project_id);
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn reset_project(pool: &PgPool, collab: &Collab, demo: &DemoProject) -> Result<usize> {
+    let (update,): (Vec<u8>,) =
+        sqlx::query_as("SELECT update_v2 FROM project_snapshots WHERE project_id = $1 AND id = $2")
+            .bind(&demo.project_id)
+            .bind(&demo.snapshot_id)
+            .fetch_one(pool)
+            .await
+            .context("Failed to fetch demo snapshot")?;
+    let origin = YOrigin {
+        who: "demo_reset".to_string(),
+        id: format!("demo_reset_{}", demo.project_id),
+        actor: Actor::Server,
+        ..Default::default()
+    };
+    let snapshot = YDocProxy::new();
+    snapshot
+        .transact_mut_with(origin.as_origin()?)
+        .apply_update(Update::decode_v2(&update)?)?;
+
+    let changed = {
+        let client = collab.register_local_client(&demo.project_id).await?;
+        let doc_box = client.project.doc_box.lock().await;
+        let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+        let mut txn = doc.transact_mut_with(origin.as_origin()?);
+        reset(doc, &mut txn, &snapshot)?
+    };
+    sqlx::query("UPDATE demo_projects SET last_reset_time = NOW() WHERE project_id = $1")
+        .bind(&demo.project_id)
+        .execute(pool)
+        .await
+        .context("Failed to record demo reset")?;
+    metrics::counter!("demo_resets_total").increment(1);
+    tracing::info!(
+        "Reset demo project {}, changing {changed} tasks",
+        demo.project_id
+    );
+    Ok(changed)
+}
+
+/// Makes the doc's tasks and config match the snapshot's, returning the
+/// number of tasks changed.
+fn reset(doc: &YDocProxy, txn: &mut TransactionMut, snapshot: &YDocProxy) -> Result<usize> {
+    let (graph, config) = {
+        let txn = snapshot.transact();
+        (snapshot.to_graph(&txn)?, snapshot.config().get(&txn)?)
+    };
+    let live = doc.to_graph(txn)?;
+    let mut changed = 0;
+    for id in live.keys().filter(|id| !graph.contains_key(*id)) {
+        doc.delete(txn, id);
+        changed += 1;
+    }
+    for (id, task) in &graph {
+        if live.get(id) != Some(task) {
+            doc.set(txn, task);
+            changed += 1;
+        }
+    }
+    if doc.config().get(txn)? != config {
+        doc.config().set(txn, &config)?;
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::model::{Task, test_utils};
+
+    #[test_log::test]
+    fn reset_restores_snapshot() {
+        let origin = || {
+            YOrigin {
+                who: "reset_restores_snapshot".to_string(),
+                id: "test".to_string(),
+                actor: Actor::Server,
+                ..Default::default()
+            }
+            .as_origin()
+            .unwrap()
+        };
+        let snapshot = YDocProxy::new();
+        {
+            let mut txn = snapshot.transact_mut_with(origin());
+            snapshot.set(
+                &mut txn,
+                &Task {
+                    name: "Root".to_string(),
+                    ..test_utils::task("root", "root", &["a"])
+                },
+            );
+            snapshot.set(
+                &mut txn,
+                &Task {
+                    name: "A".to_string(),
+                    ..test_utils::task("a", "a", &[])
+                },
+            );
+        }
+        let doc = YDocProxy::new();
+        let mut txn = doc.transact_mut_with(origin());
+        doc.set(
+            &mut txn,
+            &Task {
+                name: "Root".to_string(),
+                ..test_utils::task("root", "root", &["a", "b"])
+            },
+        );
+        doc.set(
+            &mut txn,
+            &Task {
+                name: "Renamed".to_string(),
+                ..test_utils::task("a", "a", &[])
+            },
+        );
+        doc.set(
+            &mut txn,
+            &Task {
+                name: "Added".to_string(),
+                ..test_utils::task("b", "b", &[])
+            },
+        );
+
+        assert_eq!(reset(&doc, &mut txn, &snapshot).unwrap(), 3);
+        assert_eq!(
+            doc.to_graph(&txn).unwrap(),
+            snapshot.to_graph(&snapshot.transact()).unwrap()
+        );
+        assert_eq!(reset(&doc, &mut txn, &snapshot).unwrap(), 0);
+    }
+
+    #[test_log::test]
+    fn quota_limits_sessions_and_bytes() {
+        let project_id = "quota_limits_sessions_and_bytes".to_string();
+        let sessions: Vec<SessionQuota> = (0..MAX_SESSIONS)
+            .map(|_| SessionQuota::acquire(&project_id).unwrap())
+            .collect();
+        assert!(SessionQuota::acquire(&project_id).is_none());
+        drop(sessions);
+
+        let quota = SessionQuota::acquire(&project_id).unwrap();
+        assert!(quota.charge(MAX_SESSION_BYTES).is_ok());
+        assert!(quota.charge(1).is_err());
+    }
+}
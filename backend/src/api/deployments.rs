@@ -0,0 +1,537 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{Collab, projects_state::DocBox},
+        google::User,
+        hash_token,
+        model::ProjectId,
+        projects::fetch_task_key_prefix,
+        step_up, unauthorized_error, verify_project_access, verify_project_admin,
+        yproxy::{YDocProxy, parse_task_key},
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    http::{HeaderMap, header::AUTHORIZATION},
+    middleware,
+    routing::{delete, get, post},
+};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    FromRow,
+    types::{
+        Json as SqlJson,
+        chrono::{DateTime, Utc},
+    },
+};
+use std::collections::{BTreeMap, HashSet};
+use uuid::Uuid;
+use yrs::ReadTxn;
+
+/// Routes used by project members to inspect deployments.
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/{project_id}/deployments", get(list_deployments_handler))
+        .route(
+            "/{project_id}/deployments/token",
+            post(create_token_handler).route_layer(middleware::from_fn(step_up::require_step_up)),
+        )
+        .route(
+            "/{project_id}/deployments/token",
+            delete(delete_token_handler),
+        )
+        .route(
+            "/{project_id}/deployments/shipped",
+            get(shipped_tasks_handler),
+        )
+        .route(
+            "/{project_id}/tasks/{task_id}/deployments",
+            get(list_task_deployments_handler),
+        )
+}
+
+/// Routes invoked by CI systems, authenticated with a project's deploy token.
+pub(super) fn webhook_router() -> Router {
+    Router::new().route("/", post(report_deployment_handler))
+}
+
+const MAX_ENV_LEN: usize = 64;
+const MAX_VERSION_LEN: usize = 128;
+const MAX_TASKS_PER_DEPLOYMENT: usize = 1000;
+/// Shipped tasks are grouped under this heading when they have no labels.
+pub(super) const UNLABELED: &str = "Other";
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ReportDeployment {
+    /// e.g. prod or staging.
+    env: String,
+    /// e.g. v1.4.0 or a commit SHA.
+    version: String,
+    /// Keys of the tasks shipped, e.g. KOSO-12 or #12.
+    #[serde(default)]
+    tasks: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ReportDeploymentResponse {
+    deployment_id: String,
+    /// Keys that didn't match a task in the project.
+    unknown_tasks: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct DeployToken {
+    /// Only returned when the token is created.
+    token: String,
+}
+
+/// A task as it was when it was deployed.
+#[derive(Serialize, Deserialize, FromRow, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ShippedTask {
+    pub(crate) task_id: String,
+    pub(crate) num: String,
+    pub(crate) name: String,
+    #[sqlx(json)]
+    pub(crate) labels: Vec<String>,
+    pub(crate) assignee: Option<String>,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Deployment {
+    pub(crate) id: String,
+    pub(crate) env: String,
+    pub(crate) version: String,
+    pub(crate) deploy_time: DateTime<Utc>,
+    #[sqlx(json)]
+    pub(crate) tasks: Vec<ShippedTask>,
+}
+
+/// Annotates a task with where and when it shipped, e.g. "shipped to prod on <date>".
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct TaskDeployment {
+    env: String,
+    version: String,
+    deploy_time: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct ShippedQuery {
+    pub(super) env: Option<String>,
+    /// Only tasks shipped in deployments of this version, e.g. a tag like v1.4.0.
+    pub(super) version: Option<String>,
+    pub(super) since: Option<DateTime<Utc>>,
+    pub(super) until: Option<DateTime<Utc>>,
+}
+
+/// Tasks shipped in a range of deployments, grouped by their first label.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ShippedGroup {
+    pub(crate) label: String,
+    pub(crate) tasks: Vec<ShippedTask>,
+}
+
+#[tracing::instrument(skip(pool, collab, headers))]
+async fn report_deployment_handler(
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    headers: HeaderMap,
+    Json(report): Json<ReportDeployment>,
+) -> ApiResult<Json<ReportDeploymentResponse>> {
+    let project_id = authenticate(pool, &headers).await?;
+    let env = report.env.trim().to_lowercase();
+    if env.is_empty()
+        || env.len() > MAX_ENV_LEN
+        || !env
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(bad_request_error(
+            "INVALID_ENV",
+            "Env must be 1 to 64 letters, digits, dashes or underscores",
+        ));
+    }
+    let version = report.version.trim();
+    if version.is_empty() || version.len() > MAX_VERSION_LEN {
+        return Err(bad_request_error(
+            "INVALID_VERSION",
+            "Version must be 1 to 128 characters",
+        ));
+    }
+    if report.tasks.len() > MAX_TASKS_PER_DEPLOYMENT {
+        return Err(bad_request_error(
+            "TOO_MANY_TASKS",
+            &format!("At most {MAX_TASKS_PER_DEPLOYMENT} tasks may be reported"),
+        ));
+    }
+
+    let task_key_prefix = fetch_task_key_prefix(pool, &project_id).await?;
+    let (nums, mut unknown_tasks) = resolve_keys(&report.tasks, task_key_prefix.as_deref());
+    let shipped = {
+        let client = collab.register_local_client(&project_id).await?;
+        let doc_box = client.project.doc_box.lock().await;
+        let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+        snapshot_tasks(doc, &doc.transact(), &nums)?
+    };
+    let found: HashSet<&str> = shipped.iter().map(|t| t.num.as_str()).collect();
+    let missing: Vec<String> = report
+        .tasks
+        .iter()
+        .filter(|key| !unknown_tasks.contains(key))
+        .filter(|key| parse_task_key(key).is_some_and(|(_, num)| !found.contains(num)))
+        .cloned()
+        .collect();
+    unknown_tasks.extend(missing);
+
+    let deployment_id = BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4());
+    let mut txn = pool.begin().await?;
+    sqlx::query(
+        "
+        INSERT INTO deployments (id, project_id, env, version)
+        VALUES ($1, $2, $3, $4)",
+    )
+    .bind(&deployment_id)
+    .bind(&project_id)
+    .bind(&env)
+    .bind(version)
+    .execute(&mut *txn)
+    .await
+    .context("Failed to insert deployment")?;
+    for task in &shipped {
+        sqlx::query(
+            "
+            INSERT INTO deployed_tasks (deployment_id, project_id, task_id, num, name, labels, assignee)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(&deployment_id)
+        .bind(&project_id)
+        .bind(&task.task_id)
+        .bind(&task.num)
+        .bind(&task.name)
+        .bind(SqlJson(&task.labels))
+        .bind(&task.assignee)
+        .execute(&mut *txn)
+        .await
+        .context("Failed to insert deployed task")?;
+    }
+    txn.commit().await?;
+    tracing::info!(
+        "Recorded deployment of {version} to {env} in {project_id} with {} tasks",
+        shipped.len()
+    );
+
+    Ok(Json(ReportDeploymentResponse {
+        deployment_id,
+        unknown_tasks,
+    }))
+}
+
+/// Returns the project the bearer deploy token belongs to.
+async fn authenticate(pool: &PgPool, headers: &HeaderMap) -> ApiResult<ProjectId> {
+    let Some(token) = headers
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+    else {
+        return Err(unauthorized_error("Missing deploy token"));
+    };
+    let project: Option<(ProjectId,)> = sqlx::query_as(
+        "
+        SELECT project_id
+        FROM deploy_tokens
+        JOIN projects USING (project_id)
+        WHERE token_hash = $1 AND deleted_on IS NULL",
+    )
+    .bind(hash_token(token))
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up deploy token")?;
+    match project {
+        Some((project_id,)) => Ok(project_id),
+        None => Err(unauthorized_error("Invalid deploy token")),
+    }
+}
+
+/// Splits task keys into the nums of keys belonging to the project and
+/// keys that can't, e.g. those with another project's prefix.
+fn resolve_keys(keys: &[String], task_key_prefix: Option<&str>) -> (HashSet<String>, Vec<String>) {
+    let mut nums = HashSet::new();
+    let mut unknown = Vec::new();
+    for key in keys {
+        match parse_task_key(key) {
+            Some((None, num)) => {
+                nums.insert(num.to_string());
+            }
+            Some((Some(prefix), num))
+                if task_key_prefix.is_some_and(|p| p.eq_ignore_ascii_case(prefix)) =>
+            {
+                nums.insert(num.to_string());
+            }
+            _ => unknown.push(key.clone()),
+        }
+    }
+    (nums, unknown)
+}
+
+fn snapshot_tasks<T: ReadTxn>(
+    doc: &YDocProxy,
+    txn: &T,
+    nums: &HashSet<String>,
+) -> Result<Vec<ShippedTask>> {
+    let mut shipped = Vec::with_capacity(nums.len());
+    for task in doc.get_by_nums(txn, nums)? {
+        shipped.push(ShippedTask {
+            task_id: task.get_id(txn)?,
+            num: task.get_num(txn)?,
+            name: task.get_name(txn)?,
+            labels: task.get_labels(txn)?,
+            assignee: task.get_assignee(txn)?,
+        });
+    }
+    Ok(shipped)
+}
+
+/// Creates the project's deploy token, replacing, and so revoking, any existing one.
+#[tracing::instrument(skip(user, pool))]
+async fn create_token_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<DeployToken>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let token = format!("kdt_{}", Uuid::new_v4().simple());
+    sqlx::query(
+        "
+        INSERT INTO deploy_tokens (project_id, token_hash, creator)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (project_id)
+        DO UPDATE SET
+            token_hash = EXCLUDED.token_hash,
+            creator = EXCLUDED.creator,
+            create_time = NOW()",
+    )
+    .bind(&project_id)
+    .bind(hash_token(&token))
+    .bind(&user.email)
+    .execute(pool)
+    .await
+    .context("Failed to upsert deploy token")?;
+    Ok(Json(DeployToken { token }))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_token_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<()>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    sqlx::query("DELETE FROM deploy_tokens WHERE project_id = $1")
+        .bind(&project_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete deploy token")?;
+    Ok(Json(()))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn list_deployments_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Vec<Deployment>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let deployments: Vec<Deployment> = sqlx::query_as(
+        "
+        SELECT d.id, d.env, d.version, d.deploy_time,
+            COALESCE(
+                (SELECT jsonb_agg(jsonb_build_object(
+                    'taskId', t.task_id,
+                    'num', t.num,
+                    'name', t.name,
+                    'labels', t.labels,
+                    'assignee', t.assignee))
+                FROM deployed_tasks t
+                WHERE t.deployment_id = d.id),
+                '[]'::jsonb) AS tasks
+        FROM deployments d
+        WHERE d.project_id = $1
+        ORDER BY d.deploy_time DESC
+        LIMIT 100",
+    )
+    .bind(&project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list deployments")?;
+    Ok(Json(deployments))
+}
+
+/// Lists where and when the task was shipped.
+#[tracing::instrument(skip(user, pool))]
+async fn list_task_deployments_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, task_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<Vec<TaskDeployment>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let deployments: Vec<TaskDeployment> = sqlx::query_as(
+        "
+        SELECT d.env, d.version, d.deploy_time
+        FROM deployed_tasks t
+        JOIN deployments d ON d.id = t.deployment_id
+        WHERE t.project_id = $1 AND t.task_id = $2
+        ORDER BY d.deploy_time DESC",
+    )
+    .bind(&project_id)
+    .bind(&task_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list task deployments")?;
+    Ok(Json(deployments))
+}
+
+/// Lists the tasks shipped in a range of deployments, grouped by label, for
+/// use in release notes.
+#[tracing::instrument(skip(user, pool))]
+async fn shipped_tasks_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Query(query): Query<ShippedQuery>,
+) -> ApiResult<Json<Vec<ShippedGroup>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let tasks = fetch_shipped(pool, &project_id, &query).await?;
+    Ok(Json(group_by_label(tasks)))
+}
+
+/// Returns the tasks shipped to the env within the range or in the version.
+pub(super) async fn fetch_shipped(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    query: &ShippedQuery,
+) -> Result<Vec<ShippedTask>> {
+    sqlx::query_as(
+        "
+        SELECT DISTINCT ON (t.task_id) t.task_id, t.num, t.name, t.labels, t.assignee
+        FROM deployed_tasks t
+        JOIN deployments d ON d.id = t.deployment_id
+        WHERE t.project_id = $1
+        AND ($2::varchar IS NULL OR d.env = $2)
+        AND ($3::timestamptz IS NULL OR d.deploy_time >= $3)
+        AND ($4::timestamptz IS NULL OR d.deploy_time < $4)
+        AND ($5::varchar IS NULL OR d.version = $5)
+        ORDER BY t.task_id, d.deploy_time",
+    )
+    .bind(project_id)
+    .bind(query.env.as_deref().map(str::to_lowercase))
+    .bind(query.since)
+    .bind(query.until)
+    .bind(&query.version)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list shipped tasks")
+}
+
+/// Groups tasks by their first label, alphabetically, with unlabeled tasks last.
+pub(super) fn group_by_label(tasks: Vec<ShippedTask>) -> Vec<ShippedGroup> {
+    let mut by_label: BTreeMap<String, Vec<ShippedTask>> = BTreeMap::new();
+    let mut unlabeled = Vec::new();
+    for task in tasks {
+        match task.labels.first() {
+            Some(label) => by_label.entry(label.clone()).or_default().push(task),
+            None => unlabeled.push(task),
+        }
+    }
+    let mut groups: Vec<ShippedGroup> = by_label
+        .into_iter()
+        .map(|(label, tasks)| ShippedGroup { label, tasks })
+        .collect();
+    if !unlabeled.is_empty() {
+        groups.push(ShippedGroup {
+            label: UNLABELED.to_string(),
+            tasks: unlabeled,
+        });
+    }
+    for group in groups.iter_mut() {
+        group
+            .tasks
+            .sort_by_key(|t| t.num.parse::<u64>().unwrap_or(u64::MAX));
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(num: &str, labels: &[&str]) -> ShippedTask {
+        ShippedTask {
+            task_id: format!("id{num}"),
+            num: num.to_string(),
+            name: format!("Task {num}"),
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+            assignee: None,
+        }
+    }
+
+    #[test]
+    fn resolve_keys_accepts_own_prefix_and_plain_nums() {
+        let (nums, unknown) = resolve_keys(
+            &[
+                "KOSO-1".to_string(),
+                "#2".to_string(),
+                "3".to_string(),
+                "WEB-4".to_string(),
+                "nope".to_string(),
+            ],
+            Some("KOSO"),
+        );
+        assert_eq!(
+            nums,
+            HashSet::from(["1".to_string(), "2".to_string(), "3".to_string()])
+        );
+        assert_eq!(unknown, vec!["WEB-4".to_string(), "nope".to_string()]);
+
+        let (nums, unknown) = resolve_keys(&["KOSO-1".to_string()], None);
+        assert!(nums.is_empty());
+        assert_eq!(unknown, vec!["KOSO-1".to_string()]);
+    }
+
+    #[test]
+    fn group_by_label_orders_groups_and_tasks() {
+        let groups = group_by_label(vec![
+            task("10", &["fix"]),
+            task("3", &[]),
+            task("2", &["feature", "fix"]),
+            task("9", &["fix"]),
+        ]);
+        assert_eq!(
+            groups,
+            vec![
+                ShippedGroup {
+                    label: "feature".to_string(),
+                    tasks: vec![task("2", &["feature", "fix"])],
+                },
+                ShippedGroup {
+                    label: "fix".to_string(),
+                    tasks: vec![task("9", &["fix"]), task("10", &["fix"])],
+                },
+                ShippedGroup {
+                    label: UNLABELED.to_string(),
+                    tasks: vec![task("3", &[])],
+                },
+            ]
+        );
+    }
+}
@@ -1,11 +1,10 @@
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use super::{User, bad_request_error, google};
-use crate::{api::ApiResult, settings::settings};
+use crate::{api::ApiResult, postgres::PgPool, settings::settings};
 use anyhow::Context as _;
 use axum::{Extension, Router, routing::post};
 use chrono::{DateTime, Utc};
-use sqlx::PgPool;
 
 fn integ_test_user_suffix() -> String {
     format!("-test{}", google::TEST_USER_SUFFIX)
@@ -105,6 +104,17 @@ async fn cleanup_test_data_handler(Extension(pool): Extension<&'static PgPool>)
     .execute(pool)
     .await
     .context("Failed to delete test yupdates")?;
+    // Delete any orphaned yupdate metadata.
+    sqlx::query(
+        "
+        DELETE FROM yupdate_metadata
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test yupdate_metadata")?;
     // Delete any orphaned plugin configs.
     sqlx::query(
         "
@@ -116,6 +126,437 @@ async fn cleanup_test_data_handler(Extension(pool): Extension<&'static PgPool>)
     .execute(pool)
     .await
     .context("Failed to delete test yupdates")?;
+    // Delete any orphaned task projections.
+    sqlx::query(
+        "
+        DELETE FROM task_projections
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test task_projections")?;
+    // Delete any orphaned comments.
+    sqlx::query(
+        "
+        DELETE FROM task_comments
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test task_comments")?;
+    // Delete any orphaned branches.
+    sqlx::query(
+        "
+        DELETE FROM project_branches
+        WHERE branch_project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test project_branches")?;
+    // Delete any orphaned report subscriptions.
+    sqlx::query(
+        "
+        DELETE FROM report_subscriptions
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test report_subscriptions")?;
+    // Delete any orphaned snapshots.
+    sqlx::query(
+        "
+        DELETE FROM project_snapshots
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test project_snapshots")?;
+    // Delete any orphaned task completions.
+    sqlx::query(
+        "
+        DELETE FROM task_completions
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test task_completions")?;
+    // Delete any orphaned task estimates.
+    sqlx::query(
+        "
+        DELETE FROM task_estimates
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test task_estimates")?;
+    // Delete any orphaned task status transitions.
+    sqlx::query(
+        "
+        DELETE FROM task_status_transitions
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test task_status_transitions")?;
+    // Delete any orphaned task status counts.
+    sqlx::query(
+        "
+        DELETE FROM task_status_counts
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test task_status_counts")?;
+    // Delete any orphaned goals.
+    sqlx::query(
+        "
+        DELETE FROM goals
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test goals")?;
+    // Delete any orphaned milestones.
+    sqlx::query(
+        "
+        DELETE FROM milestones
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test milestones")?;
+    // Delete any orphaned risks.
+    sqlx::query(
+        "
+        DELETE FROM risks
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test risks")?;
+    // Delete any orphaned decisions.
+    sqlx::query(
+        "
+        DELETE FROM decisions
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test decisions")?;
+    // Delete any orphaned inbound email addresses.
+    sqlx::query(
+        "
+        DELETE FROM inbound_email_addresses
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test inbound email addresses")?;
+    // Delete any orphaned inbound emails.
+    sqlx::query(
+        "
+        DELETE FROM inbound_emails
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test inbound emails")?;
+    // Delete any orphaned attachments.
+    sqlx::query(
+        "
+        DELETE FROM task_attachments
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test attachments")?;
+    // Delete any orphaned telegram reminders.
+    sqlx::query(
+        "
+        DELETE FROM telegram_reminders
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test telegram reminders")?;
+    // Delete any orphaned auto-archive runs.
+    sqlx::query(
+        "
+        DELETE FROM auto_archive_runs
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test auto-archive runs")?;
+    sqlx::query(
+        "
+        DELETE FROM deploy_tokens
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test deploy tokens")?;
+    sqlx::query(
+        "
+        DELETE FROM deployments
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test deployments")?;
+    sqlx::query(
+        "
+        DELETE FROM deployed_tasks
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test deployed tasks")?;
+    sqlx::query(
+        "
+        DELETE FROM alert_integrations
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test alert integrations")?;
+    sqlx::query(
+        "
+        DELETE FROM alert_fingerprints
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test alert fingerprints")?;
+    sqlx::query(
+        "
+        DELETE FROM oncall_integrations
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test on-call integrations")?;
+    sqlx::query(
+        "
+        DELETE FROM task_attribution
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test task attribution")?;
+    sqlx::query(
+        "
+        DELETE FROM import_jobs
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test import jobs")?;
+    sqlx::query(
+        "
+        DELETE FROM import_sources
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test import sources")?;
+    sqlx::query(
+        "
+        DELETE FROM maintenance_freezes
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test maintenance freezes")?;
+    sqlx::query(
+        "
+        DELETE FROM retention_policies
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test retention policies")?;
+    sqlx::query(
+        "
+        DELETE FROM doc_tiers
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test doc tiers")?;
+    sqlx::query(
+        "
+        DELETE FROM doc_migration_snapshots
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test doc migration snapshots")?;
+    sqlx::query(
+        "
+        DELETE FROM blueprint_applications
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test blueprint applications")?;
+    sqlx::query(
+        "
+        DELETE FROM project_webhooks
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test project webhooks")?;
+    sqlx::query(
+        "
+        DELETE FROM demo_projects
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test demo projects")?;
+    sqlx::query(
+        "
+        DELETE FROM embed_tokens
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test embed tokens")?;
+    sqlx::query(
+        "
+        DELETE FROM status_pages
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test status pages")?;
+    sqlx::query(
+        "
+        DELETE FROM project_reads
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test project reads")?;
+    sqlx::query(
+        "
+        DELETE FROM task_reads
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test task reads")?;
+    // Delete any orphaned abuse reports.
+    sqlx::query(
+        "
+        DELETE FROM abuse_reports
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test abuse reports")?;
+    // Delete any orphaned task removals.
+    sqlx::query(
+        "
+        DELETE FROM task_removals
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test task_removals")?;
+    // Delete any orphaned API usage.
+    sqlx::query(
+        "
+        DELETE FROM api_usage
+        WHERE project_id NOT IN (
+            SELECT project_id FROM projects
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test api_usage")?;
     // Delete any orphaned project permissions.
     sqlx::query(
         "
@@ -149,6 +590,146 @@ async fn cleanup_test_data_handler(Extension(pool): Extension<&'static PgPool>)
     .execute(pool)
     .await
     .context("Failed to delete test users")?;
+    // Delete any orphaned notification deliveries.
+    sqlx::query(
+        "
+        DELETE FROM notification_deliveries
+        WHERE email NOT IN (
+            SELECT email FROM users
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test notification deliveries")?;
+    // Delete any orphaned API keys.
+    sqlx::query(
+        "
+        DELETE FROM zapier_keys
+        WHERE email NOT IN (
+            SELECT email FROM users
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test API keys")?;
+    // Delete any orphaned notification preferences.
+    sqlx::query(
+        "
+        DELETE FROM notification_preferences
+        WHERE email NOT IN (
+            SELECT email FROM users
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test notification preferences")?;
+    // Delete any orphaned held notifications.
+    sqlx::query(
+        "
+        DELETE FROM held_notifications
+        WHERE email NOT IN (
+            SELECT email FROM users
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test held notifications")?;
+    // Delete any orphaned avatars.
+    sqlx::query(
+        "
+        DELETE FROM user_avatars
+        WHERE email NOT IN (
+            SELECT email FROM users
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test avatars")?;
+    // Delete any orphaned inbox notifications.
+    sqlx::query(
+        "
+        DELETE FROM inbox_notifications
+        WHERE email NOT IN (
+            SELECT email FROM users
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test inbox notifications")?;
+    // Delete any orphaned security policies and audit logs.
+    sqlx::query(
+        "
+        DELETE FROM security_policies
+        WHERE owner_email NOT IN (
+            SELECT email FROM users
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test security_policies")?;
+    sqlx::query(
+        "
+        DELETE FROM security_audit_log
+        WHERE owner_email NOT IN (
+            SELECT email FROM users
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test security_audit_log")?;
+    // Delete any orphaned impersonation sessions and their audit logs.
+    sqlx::query(
+        "
+        DELETE FROM impersonation_sessions
+        WHERE user_email NOT IN (
+            SELECT email FROM users
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test impersonation_sessions")?;
+    sqlx::query(
+        "
+        DELETE FROM impersonation_audit_log
+        WHERE session_id NOT IN (
+            SELECT id FROM impersonation_sessions
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test impersonation_audit_log")?;
+    // Delete any orphaned away statuses.
+    sqlx::query(
+        "
+        DELETE FROM away_statuses
+        WHERE email NOT IN (
+            SELECT email FROM users
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test away_statuses")?;
+    // Delete any orphaned blueprints and their versions.
+    sqlx::query(
+        "
+        DELETE FROM blueprints
+        WHERE org NOT IN (
+            SELECT email FROM users
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test blueprints")?;
+    sqlx::query(
+        "
+        DELETE FROM blueprint_versions
+        WHERE blueprint_id NOT IN (
+            SELECT id FROM blueprints
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete test blueprint_versions")?;
     // Delete any orphaned subscriptions.
     sqlx::query(
         "
@@ -0,0 +1,371 @@
+//! A human-readable JSON representation of a project's doc, and its comments,
+//! for diffing in code review and seeding test fixtures.
+//!
+//! Each of the doc's root maps, e.g. `graph` and `config`, is written as an
+//! object with sorted keys. Primitive values are plain JSON. Shared types, and
+//! primitives JSON can't represent, are tagged objects with a single key:
+//! `{"$map": {..}}`, `{"$array": [..]}`, `{"$text": ".."}`, `{"$object": {..}}`
+//! for primitive maps, `{"$bigint": n}`, `{"$bytes": "<base64>"}` and
+//! `{"$undefined": true}`. Text formatting and embeds aren't represented.
+
+use crate::{
+    api::{
+        collab::{
+            storage,
+            txn_origin::{Actor, YOrigin},
+        },
+        model::{Comment, ProjectId},
+        yproxy::YDocProxy,
+    },
+    postgres::{self, PgPool},
+};
+use anyhow::{Context as _, Result, anyhow, bail};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map as JsonMap, Value, json};
+use std::{collections::HashMap, sync::Arc};
+use yrs::{
+    Any, Array, ArrayPrelim, ArrayRef, GetString, Map, MapPrelim, MapRef, Out, ReadTxn,
+    StateVector, TextPrelim, TransactionMut, WriteTxn as _,
+};
+
+/// Identifies the dump format, so incompatible changes can be detected.
+const FORMAT: &str = "koso-doc/1";
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DocDump {
+    format: String,
+    /// The doc's root maps, by name.
+    doc: JsonMap<String, Value>,
+    comments: Vec<Comment>,
+}
+
+/// Dumps the project's doc and comments.
+pub(crate) async fn dump(pool: &PgPool, project_id: &ProjectId) -> Result<DocDump> {
+    let doc = storage::load_doc_offline(project_id, pool).await?;
+    let comments: Vec<Comment> = sqlx::query_as(
+        "
+        SELECT id, task_id, parent_id, author, body, resolved, create_time, update_time
+        FROM task_comments
+        WHERE project_id = $1
+        ORDER BY create_time, id",
+    )
+    .bind(project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list comments")?;
+    Ok(DocDump {
+        format: FORMAT.to_string(),
+        doc: dump_doc(&doc)?,
+        comments,
+    })
+}
+
+/// Loads a dump into a project that doesn't have a doc yet, e.g. one just
+/// created for a fixture.
+pub(crate) async fn load(pool: &PgPool, project_id: &ProjectId, dump: &DocDump) -> Result<()> {
+    if dump.format != FORMAT {
+        bail!("Unsupported format {}, expected {FORMAT}", dump.format);
+    }
+    store(pool, project_id, &load_doc(&dump.doc)?, &dump.comments).await
+}
+
+/// Stores the doc, and comments, as the first state of a project without a doc.
+pub(crate) async fn store(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    doc: &YDocProxy,
+    comments: &[Comment],
+) -> Result<()> {
+    let update = doc
+        .transact()
+        .encode_state_as_update_v2(&StateVector::default());
+
+    let doc_pool = postgres::doc_pool(pool, project_id).await?;
+    let mut doc_txn = doc_pool.begin().await?;
+    let (updates,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM yupdates WHERE project_id = $1")
+        .bind(project_id)
+        .fetch_one(&mut *doc_txn)
+        .await?;
+    if updates > 0 {
+        bail!("Project {project_id} already has a doc");
+    }
+    sqlx::query("INSERT INTO yupdates (project_id, seq, update_v2) VALUES ($1, DEFAULT, $2)")
+        .bind(project_id)
+        .bind(update)
+        .execute(&mut *doc_txn)
+        .await
+        .context("Failed to insert doc")?;
+    doc_txn.commit().await?;
+
+    let mut txn = pool.begin().await?;
+    for comment in comments {
+        sqlx::query(
+            "
+            INSERT INTO task_comments (id, project_id, task_id, parent_id, author, body, resolved, create_time, update_time)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        )
+        .bind(&comment.id)
+        .bind(project_id)
+        .bind(&comment.task_id)
+        .bind(&comment.parent_id)
+        .bind(&comment.author)
+        .bind(&comment.body)
+        .bind(comment.resolved)
+        .bind(comment.create_time)
+        .bind(comment.update_time)
+        .execute(&mut *txn)
+        .await
+        .context("Failed to insert comment")?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+fn dump_doc(doc: &YDocProxy) -> Result<JsonMap<String, Value>> {
+    let txn = doc.transact();
+    let mut roots = JsonMap::new();
+    let names: Vec<String> = txn.root_refs().map(|(name, _)| name.to_string()).collect();
+    for name in names {
+        let map = txn
+            .get_map(name.as_str())
+            .with_context(|| format!("Root {name} isn't a map"))?;
+        roots.insert(name, Value::Object(dump_map(&txn, &map)?));
+    }
+    Ok(roots)
+}
+
+fn dump_map<T: ReadTxn>(txn: &T, map: &MapRef) -> Result<JsonMap<String, Value>> {
+    map.iter(txn)
+        .map(|(key, value)| Ok((key.to_string(), dump_out(txn, value)?)))
+        .collect()
+}
+
+fn dump_out<T: ReadTxn>(txn: &T, out: Out) -> Result<Value> {
+    Ok(match out {
+        Out::Any(any) => dump_any(&any)?,
+        Out::YText(text) => json!({ "$text": text.get_string(txn) }),
+        Out::YArray(array) => json!({
+            "$array": array
+                .iter(txn)
+                .map(|item| dump_out(txn, item))
+                .collect::<Result<Vec<_>>>()?
+        }),
+        Out::YMap(map) => json!({ "$map": dump_map(txn, &map)? }),
+        other => bail!("Unsupported shared type: {other}"),
+    })
+}
+
+fn dump_any(any: &Any) -> Result<Value> {
+    Ok(match any {
+        Any::Null => Value::Null,
+        Any::Undefined => json!({ "$undefined": true }),
+        Any::Bool(b) => Value::Bool(*b),
+        Any::Number(n) => json!(n),
+        Any::BigInt(n) => json!({ "$bigint": n }),
+        Any::String(s) => Value::String(s.to_string()),
+        Any::Buffer(bytes) => json!({ "$bytes": BASE64_URL_SAFE_NO_PAD.encode(bytes) }),
+        Any::Array(items) => Value::Array(items.iter().map(dump_any).collect::<Result<_>>()?),
+        Any::Map(entries) => json!({
+            "$object": entries
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), dump_any(v)?)))
+                .collect::<Result<JsonMap<_, _>>>()?
+        }),
+    })
+}
+
+/// A dumped value, parsed back into what to insert.
+enum Node<'a> {
+    Map(&'a JsonMap<String, Value>),
+    Array(&'a [Value]),
+    Text(&'a str),
+    Any(Any),
+}
+
+impl<'a> Node<'a> {
+    fn parse(value: &'a Value) -> Result<Self> {
+        let tagged = match value {
+            Value::Object(object) if object.len() == 1 => object.iter().next(),
+            _ => None,
+        };
+        Ok(match tagged {
+            Some((tag, Value::Object(entries))) if tag == "$map" => Node::Map(entries),
+            Some((tag, Value::Array(items))) if tag == "$array" => Node::Array(items),
+            Some((tag, Value::String(text))) if tag == "$text" => Node::Text(text),
+            _ => Node::Any(load_any(value)?),
+        })
+    }
+}
+
+fn load_doc(roots: &JsonMap<String, Value>) -> Result<YDocProxy> {
+    let doc = YDocProxy::new();
+    {
+        let mut txn = doc.transact_mut_with(
+            YOrigin {
+                who: "load_doc_dump".to_string(),
+                id: "load_doc_dump".to_string(),
+                actor: Actor::Server,
+                ..Default::default()
+            }
+            .as_origin()?,
+        );
+        for (name, entries) in roots {
+            let Value::Object(entries) = entries else {
+                bail!("Root {name} isn't an object");
+            };
+            let map = txn.get_or_insert_map(name.as_str());
+            load_map(&mut txn, &map, entries)?;
+        }
+    }
+    Ok(doc)
+}
+
+fn load_map(
+    txn: &mut TransactionMut,
+    map: &MapRef,
+    entries: &JsonMap<String, Value>,
+) -> Result<()> {
+    for (key, value) in entries {
+        let key = key.as_str();
+        match Node::parse(value)? {
+            Node::Map(entries) => {
+                let child = map.insert(txn, key, MapPrelim::default());
+                load_map(txn, &child, entries)?;
+            }
+            Node::Array(items) => {
+                let child = map.insert(txn, key, ArrayPrelim::default());
+                load_array(txn, &child, items)?;
+            }
+            Node::Text(text) => {
+                map.insert(txn, key, TextPrelim::new(text));
+            }
+            Node::Any(any) => {
+                map.insert(txn, key, any);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn load_array(txn: &mut TransactionMut, array: &ArrayRef, items: &[Value]) -> Result<()> {
+    for item in items {
+        match Node::parse(item)? {
+            Node::Map(entries) => {
+                let child = array.push_back(txn, MapPrelim::default());
+                load_map(txn, &child, entries)?;
+            }
+            Node::Array(items) => {
+                let child = array.push_back(txn, ArrayPrelim::default());
+                load_array(txn, &child, items)?;
+            }
+            Node::Text(text) => {
+                array.push_back(txn, TextPrelim::new(text));
+            }
+            Node::Any(any) => {
+                array.push_back(txn, any);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn load_any(value: &Value) -> Result<Any> {
+    Ok(match value {
+        Value::Null => Any::Null,
+        Value::Bool(b) => Any::Bool(*b),
+        Value::Number(n) => Any::Number(n.as_f64().context("Invalid number")?),
+        Value::String(s) => Any::String(s.as_str().into()),
+        Value::Array(items) => Any::Array(
+            items
+                .iter()
+                .map(load_any)
+                .collect::<Result<Vec<_>>>()?
+                .into(),
+        ),
+        Value::Object(object) => {
+            let Some((tag, value)) = object.iter().next().filter(|_| object.len() == 1) else {
+                bail!("Untagged object: {value}");
+            };
+            match (tag.as_str(), value) {
+                ("$object", Value::Object(entries)) => Any::Map(Arc::new(
+                    entries
+                        .iter()
+                        .map(|(k, v)| Ok((k.clone(), load_any(v)?)))
+                        .collect::<Result<HashMap<_, _>>>()?,
+                )),
+                ("$bigint", value) => Any::BigInt(value.as_i64().context("Invalid bigint")?),
+                ("$bytes", Value::String(bytes)) => {
+                    Any::Buffer(BASE64_URL_SAFE_NO_PAD.decode(bytes)?.into())
+                }
+                ("$undefined", _) => Any::Undefined,
+                _ => return Err(anyhow!("Unknown tag {tag}")),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::model::{ProjectConfig, Task};
+
+    #[test_log::test]
+    fn dump_and_load_round_trips() {
+        let doc = YDocProxy::new();
+        {
+            let mut txn = doc.transact_mut_with(
+                YOrigin {
+                    who: "test".to_string(),
+                    id: "test".to_string(),
+                    actor: Actor::Server,
+                    ..Default::default()
+                }
+                .as_origin()
+                .unwrap(),
+            );
+            doc.set(
+                &mut txn,
+                &Task {
+                    id: "root".to_string(),
+                    num: "0".to_string(),
+                    name: "Root".to_string(),
+                    children: vec!["a".to_string()],
+                    ..Task::default()
+                },
+            );
+            let a = doc.set(
+                &mut txn,
+                &Task {
+                    id: "a".to_string(),
+                    num: "1".to_string(),
+                    name: "A".to_string(),
+                    estimate: Some(3),
+                    ..Task::default()
+                },
+            );
+            a.set_desc(&mut txn, Some("Some *markdown*"));
+            a.set_labels(&mut txn, &["bug".to_string()]).unwrap();
+            doc.set_board_order(&mut txn, "Done", &["a".to_string()]);
+        }
+
+        let dumped = dump_doc(&doc).unwrap();
+        let loaded = load_doc(&dumped).unwrap();
+        assert_eq!(dump_doc(&loaded).unwrap(), dumped);
+
+        let txn = loaded.transact();
+        assert_eq!(
+            loaded.to_graph(&txn).unwrap(),
+            doc.to_graph(&doc.transact()).unwrap()
+        );
+        let a = loaded.get(&txn, "a").unwrap();
+        assert_eq!(
+            a.get_desc(&txn).unwrap().as_deref(),
+            Some("Some *markdown*")
+        );
+        assert_eq!(a.get_labels(&txn).unwrap(), vec!["bug"]);
+        assert_eq!(loaded.get_board_order(&txn, "Done").unwrap(), vec!["a"]);
+        assert_eq!(loaded.config().get(&txn).unwrap(), ProjectConfig::default());
+    }
+}
@@ -0,0 +1,244 @@
+//! Admin tooling for doc migrations: listing them, dry-running pending
+//! migrations against stored docs and rolling back applied ones.
+
+use crate::{
+    api::{
+        ApiResult,
+        collab::{
+            Collab,
+            doc_migrations::{self, AppliedMigration, MIGRATIONS},
+            projects_state::DocBox,
+            txn_origin::{Actor, YOrigin},
+        },
+        google::User,
+        model::ProjectId,
+        not_found_error, verify_admin,
+    },
+    postgres::{PgPool, ReadPool},
+};
+use anyhow::Context as _;
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    routing::{delete, get, post},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    FromRow,
+    types::chrono::{DateTime, Utc},
+};
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/", get(list_migrations_handler))
+        .route("/dry-run", get(dry_run_handler))
+        .route("/snapshots", get(list_snapshots_handler))
+        .route("/snapshots/{snapshot_id}", delete(delete_snapshot_handler))
+        .route("/snapshots/{snapshot_id}/rollback", post(rollback_handler))
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Migration {
+    version: u32,
+    name: &'static str,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Migrations {
+    current_version: u32,
+    migrations: Vec<Migration>,
+}
+
+#[tracing::instrument(skip(user))]
+async fn list_migrations_handler(Extension(user): Extension<User>) -> ApiResult<Json<Migrations>> {
+    verify_admin(&user)?;
+    Ok(Json(Migrations {
+        current_version: doc_migrations::current_version(),
+        migrations: MIGRATIONS
+            .iter()
+            .map(|m| Migration {
+                version: m.version,
+                name: m.name,
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ProjectQuery {
+    /// Limits the request to one project rather than all of them.
+    project_id: Option<ProjectId>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct DryRun {
+    project_id: ProjectId,
+    from_version: Option<u32>,
+    to_version: Option<u32>,
+    migrations: Vec<AppliedMigration>,
+    error: Option<String>,
+}
+
+/// Reports what pending migrations would change in each stored doc, without
+/// changing anything. Docs that are up to date are omitted.
+#[tracing::instrument(skip(user, read_pool))]
+async fn dry_run_handler(
+    Extension(user): Extension<User>,
+    Extension(read_pool): Extension<ReadPool>,
+    Query(query): Query<ProjectQuery>,
+) -> ApiResult<Json<Vec<DryRun>>> {
+    verify_admin(&user)?;
+    let pool = read_pool.get();
+    let project_ids: Vec<ProjectId> = match query.project_id {
+        Some(project_id) => vec![project_id],
+        None => sqlx::query_as("SELECT project_id FROM projects WHERE deleted_on IS NULL")
+            .fetch_all(pool)
+            .await
+            .context("Failed to list projects")?
+            .into_iter()
+            .map(|(project_id,)| project_id)
+            .collect(),
+    };
+
+    let mut dry_runs = Vec::new();
+    for project_id in project_ids {
+        match doc_migrations::dry_run(pool, &project_id).await {
+            Ok(None) => {}
+            Ok(Some(plan)) => dry_runs.push(DryRun {
+                project_id,
+                from_version: Some(plan.from_version),
+                to_version: Some(plan.to_version),
+                migrations: plan.applied,
+                error: None,
+            }),
+            Err(e) => dry_runs.push(DryRun {
+                project_id,
+                from_version: None,
+                to_version: None,
+                migrations: Vec::new(),
+                error: Some(format!("{e:#}")),
+            }),
+        }
+    }
+    Ok(Json(dry_runs))
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct MigrationSnapshot {
+    id: String,
+    project_id: String,
+    from_version: i32,
+    to_version: i32,
+    create_time: DateTime<Utc>,
+    rollback_time: Option<DateTime<Utc>>,
+    rolled_back_by: Option<String>,
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn list_snapshots_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Query(query): Query<ProjectQuery>,
+) -> ApiResult<Json<Vec<MigrationSnapshot>>> {
+    verify_admin(&user)?;
+    let snapshots: Vec<MigrationSnapshot> = sqlx::query_as(
+        "
+        SELECT id, project_id, from_version, to_version, create_time, rollback_time, rolled_back_by
+        FROM doc_migration_snapshots
+        WHERE $1::varchar IS NULL OR project_id = $1
+        ORDER BY create_time DESC
+        LIMIT 500",
+    )
+    .bind(&query.project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list doc migration snapshots")?;
+    Ok(Json(snapshots))
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RollbackResponse {
+    restored_tasks: usize,
+}
+
+/// Rolls back the migrations applied after the snapshot was taken. The project
+/// isn't migrated again until the snapshot is deleted.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn rollback_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(snapshot_id): Path<String>,
+) -> ApiResult<Json<RollbackResponse>> {
+    verify_admin(&user)?;
+    let snapshot: Option<(String, Vec<u8>)> = sqlx::query_as(
+        "
+        SELECT project_id, update_v2
+        FROM doc_migration_snapshots
+        WHERE id = $1 AND rollback_time IS NULL",
+    )
+    .bind(&snapshot_id)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch doc migration snapshot")?;
+    let Some((project_id, update)) = snapshot else {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("No doc migration snapshot {snapshot_id} to roll back"),
+        ));
+    };
+
+    // Pin the project first so it isn't migrated again if it's reloaded meanwhile.
+    sqlx::query(
+        "
+        UPDATE doc_migration_snapshots
+        SET rollback_time = NOW(), rolled_back_by = $2
+        WHERE id = $1",
+    )
+    .bind(&snapshot_id)
+    .bind(&user.email)
+    .execute(pool)
+    .await
+    .context("Failed to mark doc migration rolled back")?;
+
+    let restored_tasks = {
+        let client = collab.register_local_client(&project_id).await?;
+        let doc_box = client.project.doc_box.lock().await;
+        let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+        let mut txn = doc.transact_mut_with(
+            YOrigin {
+                who: "rollback_handler".to_string(),
+                id: snapshot_id.clone(),
+                actor: Actor::User(user.clone()),
+                ..Default::default()
+            }
+            .as_origin()?,
+        );
+        doc_migrations::rollback(doc, &mut txn, &project_id, &update)?
+    };
+    tracing::info!("Rolled back doc migrations of {project_id}, restoring {restored_tasks} tasks");
+    Ok(Json(RollbackResponse { restored_tasks }))
+}
+
+/// Deletes the snapshot. Deleting a rolled back snapshot lets the project be
+/// migrated again.
+#[tracing::instrument(skip(user, pool))]
+async fn delete_snapshot_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(snapshot_id): Path<String>,
+) -> ApiResult<()> {
+    verify_admin(&user)?;
+    sqlx::query("DELETE FROM doc_migration_snapshots WHERE id = $1")
+        .bind(&snapshot_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete doc migration snapshot")?;
+    Ok(())
+}
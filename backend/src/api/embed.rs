@@ -0,0 +1,429 @@
+//! Read-only views of a subtree's status for embedding in wikis and
+//! dashboards, e.g. in an iframe. Views are addressed by an embed token,
+//! rather than authenticated, so they work without signing in. Project admins
+//! mint tokens, optionally limited to one subtree, and can revoke them.
+
+use crate::{
+    api::{
+        ApiResult,
+        analytics::leaf_progress,
+        bad_request_error,
+        collab::{Collab, projects_state::DocBox},
+        google::User,
+        hash_token,
+        model::{Graph, ProjectId, Task, TaskProgress, WorkflowState},
+        not_found_error,
+        reports::escape_html,
+        step_up, verify_project_admin,
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    http::header::{
+        ACCESS_CONTROL_ALLOW_ORIGIN, CACHE_CONTROL, CONTENT_SECURITY_POLICY, CONTENT_TYPE,
+    },
+    middleware,
+    response::{IntoResponse as _, Response},
+    routing::{delete, get, post},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    FromRow,
+    types::chrono::{DateTime, Utc},
+};
+use uuid::Uuid;
+
+/// Levels of subtasks shown below the embedded task.
+const MAX_DEPTH: usize = 2;
+/// Tasks shown in total, so huge subtrees stay lightweight.
+const MAX_TASKS: usize = 200;
+const MAX_TOKENS: i64 = 25;
+/// Views are briefly cached, by browsers and CDNs alike, to absorb the load
+/// of popular pages.
+const EMBED_CACHE_CONTROL: &str = "public, max-age=60, stale-while-revalidate=300";
+/// Embeds may be framed anywhere but load nothing else.
+const EMBED_CSP: &str = "default-src 'none'; style-src 'unsafe-inline'; frame-ancestors *";
+
+/// Routes for project admins to manage embed tokens.
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/{project_id}/embed-tokens", get(list_tokens_handler))
+        .route(
+            "/{project_id}/embed-tokens",
+            post(create_token_handler).route_layer(middleware::from_fn(step_up::require_step_up)),
+        )
+        .route(
+            "/{project_id}/embed-tokens/{token_id}",
+            delete(delete_token_handler),
+        )
+}
+
+/// Unauthenticated routes serving the embeds themselves.
+pub(crate) fn embed_router() -> Router {
+    Router::new().route(
+        "/projects/{token}/subtree/{task_id}",
+        get(embed_subtree_handler),
+    )
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct EmbedToken {
+    id: String,
+    /// Only returned when the token is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[sqlx(skip)]
+    token: Option<String>,
+    /// If set, only this task and its subtasks can be embedded.
+    root_task_id: Option<String>,
+    creator: String,
+    create_time: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CreateEmbedToken {
+    root_task_id: Option<String>,
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn list_tokens_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Vec<EmbedToken>>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let tokens: Vec<EmbedToken> = sqlx::query_as(
+        "
+        SELECT id, root_task_id, creator, create_time
+        FROM embed_tokens
+        WHERE project_id = $1
+        ORDER BY create_time",
+    )
+    .bind(&project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list embed tokens")?;
+    Ok(Json(tokens))
+}
+
+/// Creates a token. The token itself is only returned now.
+#[tracing::instrument(skip(user, pool))]
+async fn create_token_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Json(req): Json<CreateEmbedToken>,
+) -> ApiResult<Json<EmbedToken>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let (count,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM embed_tokens WHERE project_id = $1")
+            .bind(&project_id)
+            .fetch_one(pool)
+            .await
+            .context("Failed to count embed tokens")?;
+    if count >= MAX_TOKENS {
+        return Err(bad_request_error(
+            "TOO_MANY_EMBED_TOKENS",
+            &format!("Projects may have at most {MAX_TOKENS} embed tokens"),
+        ));
+    }
+
+    let token = format!("kem_{}", Uuid::new_v4().simple());
+    let mut embed_token: EmbedToken = sqlx::query_as(
+        "
+        INSERT INTO embed_tokens (id, project_id, token_hash, root_task_id, creator)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, root_task_id, creator, create_time",
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(&project_id)
+    .bind(hash_token(&token))
+    .bind(&req.root_task_id)
+    .bind(&user.email)
+    .fetch_one(pool)
+    .await
+    .context("Failed to create embed token")?;
+    embed_token.token = Some(token);
+    Ok(Json(embed_token))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_token_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, token_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<()>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let res = sqlx::query("DELETE FROM embed_tokens WHERE project_id = $1 AND id = $2")
+        .bind(&project_id)
+        .bind(&token_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete embed token")?;
+    if res.rows_affected() == 0 {
+        return Err(not_found_error("NOT_FOUND", "Embed token not found"));
+    }
+    Ok(Json(()))
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum EmbedFormat {
+    #[default]
+    Html,
+    Json,
+}
+
+#[derive(Deserialize, Debug)]
+struct EmbedQuery {
+    #[serde(default)]
+    format: EmbedFormat,
+}
+
+/// A task in an embedded subtree.
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct EmbedTask {
+    num: String,
+    name: String,
+    status: Option<String>,
+    progress: TaskProgress,
+    /// Unarchived subtasks, down to MAX_DEPTH.
+    children: Vec<EmbedTask>,
+}
+
+#[tracing::instrument(skip(pool, collab, token))]
+async fn embed_subtree_handler(
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path((token, task_id)): Path<(String, String)>,
+    Query(query): Query<EmbedQuery>,
+) -> ApiResult<Response> {
+    // Unknown tokens and tasks are indistinguishable, so tokens can't be probed.
+    let not_found = || not_found_error("NOT_FOUND", "Nothing to embed here");
+    let Some((project_id, root_task_id)) = lookup_token(pool, &token).await? else {
+        return Err(not_found());
+    };
+
+    let (graph, workflow_states) = {
+        let client = collab.register_local_client(&project_id).await?;
+        let doc_box = client.project.doc_box.lock().await;
+        let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+        let txn = doc.transact();
+        (doc.to_graph(&txn)?, doc.config().get_workflow_states(&txn)?)
+    };
+    if let Some(root_task_id) = &root_task_id {
+        if !in_subtree(&graph, root_task_id, &task_id) {
+            return Err(not_found());
+        }
+    }
+    let Some(task) = graph.get(&task_id).filter(|t| t.archived != Some(true)) else {
+        return Err(not_found());
+    };
+    let mut budget = MAX_TASKS;
+    let view = embed_task(&graph, &workflow_states, task, 0, &mut budget);
+
+    let response = match query.format {
+        EmbedFormat::Json => (
+            [
+                (CACHE_CONTROL, EMBED_CACHE_CONTROL),
+                (ACCESS_CONTROL_ALLOW_ORIGIN, "*"),
+            ],
+            Json(view),
+        )
+            .into_response(),
+        EmbedFormat::Html => (
+            [
+                (CONTENT_TYPE, "text/html; charset=utf-8"),
+                (CACHE_CONTROL, EMBED_CACHE_CONTROL),
+                (CONTENT_SECURITY_POLICY, EMBED_CSP),
+            ],
+            render_html(&view),
+        )
+            .into_response(),
+    };
+    Ok(response)
+}
+
+/// Returns the project, and root task if any, the token may embed.
+pub(crate) async fn lookup_token(
+    pool: &PgPool,
+    token: &str,
+) -> Result<Option<(ProjectId, Option<String>)>> {
+    sqlx::query_as(
+        "
+        SELECT t.project_id, t.root_task_id
+        FROM embed_tokens t
+        JOIN projects p ON p.project_id = t.project_id
+        WHERE t.token_hash = $1 AND p.deleted_on IS NULL",
+    )
+    .bind(hash_token(token))
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up embed token")
+}
+
+/// Whether the task is the root or one of its descendants.
+fn in_subtree(graph: &Graph, root_id: &str, task_id: &str) -> bool {
+    let mut stack = vec![root_id];
+    let mut visited = std::collections::HashSet::new();
+    while let Some(id) = stack.pop() {
+        if id == task_id {
+            return true;
+        }
+        if !visited.insert(id) {
+            continue;
+        }
+        if let Some(task) = graph.get(id) {
+            stack.extend(task.children.iter().map(String::as_str));
+        }
+    }
+    false
+}
+
+fn embed_task(
+    graph: &Graph,
+    workflow_states: &[WorkflowState],
+    task: &Task,
+    depth: usize,
+    budget: &mut usize,
+) -> EmbedTask {
+    *budget = budget.saturating_sub(1);
+    let mut children = Vec::new();
+    if depth < MAX_DEPTH {
+        for child in &task.children {
+            if *budget == 0 {
+                break;
+            }
+            if let Some(child) = graph.get(child).filter(|t| t.archived != Some(true)) {
+                children.push(embed_task(graph, workflow_states, child, depth + 1, budget));
+            }
+        }
+    }
+    EmbedTask {
+        num: task.num.clone(),
+        name: task.name.clone(),
+        status: task.status.clone(),
+        progress: leaf_progress(graph, workflow_states, &[task.id.clone()]),
+        children,
+    }
+}
+
+fn render_html(view: &EmbedTask) -> String {
+    let mut out = String::from(
+        "<!doctype html><html><head><meta charset=\"utf-8\"><style>\
+         body{font:14px system-ui,sans-serif;margin:8px;color:#222}\
+         ul{list-style:none;padding-left:16px;margin:0}li{margin:2px 0}\
+         .status{color:#666;font-size:12px}\
+         progress{width:120px;vertical-align:middle}\
+         </style></head><body>",
+    );
+    out.push_str(&format!(
+        "<h3>#{} {}</h3>",
+        escape_html(&view.num),
+        escape_html(&view.name)
+    ));
+    push_progress(&mut out, &view.progress);
+    push_children(&mut out, &view.children);
+    out.push_str("</body></html>");
+    out
+}
+
+fn push_children(out: &mut String, children: &[EmbedTask]) {
+    if children.is_empty() {
+        return;
+    }
+    out.push_str("<ul>");
+    for child in children {
+        out.push_str(&format!(
+            "<li>#{} {}",
+            escape_html(&child.num),
+            escape_html(&child.name)
+        ));
+        if child.children.is_empty() {
+            if let Some(status) = &child.status {
+                out.push_str(&format!(
+                    " <span class=\"status\">{}</span>",
+                    escape_html(status)
+                ));
+            }
+        } else {
+            out.push(' ');
+            push_progress(out, &child.progress);
+        }
+        push_children(out, &child.children);
+        out.push_str("</li>");
+    }
+    out.push_str("</ul>");
+}
+
+fn push_progress(out: &mut String, progress: &TaskProgress) {
+    out.push_str(&format!(
+        "<progress value=\"{}\" max=\"{}\"></progress> <span class=\"status\">{}/{} done</span>",
+        progress.done,
+        progress.total.max(1),
+        progress.done,
+        progress.total
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::model::test_utils;
+
+    #[test_log::test]
+    fn embeds_subtree() {
+        let mut graph = test_utils::graph([
+            Task {
+                name: "<root>".to_string(),
+                ..test_utils::task("root", "root", &["a", "b"])
+            },
+            Task {
+                name: "<a>".to_string(),
+                ..test_utils::task("a", "a", &["a1", "a2"])
+            },
+            Task {
+                name: "<a1>".to_string(),
+                status: Some("Done".to_string()),
+                ..test_utils::task("a1", "a1", &["deep"])
+            },
+            Task {
+                name: "<deep>".to_string(),
+                status: Some("Done".to_string()),
+                ..test_utils::task("deep", "deep", &[])
+            },
+            Task {
+                name: "<a2>".to_string(),
+                status: Some("In Progress".to_string()),
+                ..test_utils::task("a2", "a2", &[])
+            },
+            Task {
+                name: "<b>".to_string(),
+                status: Some("Not Started".to_string()),
+                ..test_utils::task("b", "b", &[])
+            },
+        ]);
+        graph.get_mut("b").unwrap().archived = Some(true);
+
+        assert!(in_subtree(&graph, "a", "deep"));
+        assert!(!in_subtree(&graph, "a", "b"));
+
+        let mut budget = MAX_TASKS;
+        let view = embed_task(&graph, &[], &graph["root"], 0, &mut budget);
+        assert_eq!(view.progress, TaskProgress { done: 1, total: 2 });
+        assert_eq!(view.children.len(), 1);
+        let a1 = &view.children[0].children[0];
+        assert_eq!(a1.num, "a1");
+        assert!(a1.children.is_empty());
+
+        let html = render_html(&view);
+        assert!(html.contains("<h3>#root &lt;root&gt;</h3>"));
+        assert!(html.contains("#a2 &lt;a2&gt; <span class=\"status\">In Progress</span>"));
+        assert!(!html.contains("&lt;b&gt;"));
+    }
+}
@@ -0,0 +1,204 @@
+//! The taxonomy of errors surfaced to clients. Every error carries one of a
+//! small, stable set of codes, both in HTTP problem+json responses and in
+//! websocket error frames, so clients can tell, say, a missing membership from
+//! an unloaded doc without parsing messages. Finer grained reasons remain in
+//! the response's details.
+
+use axum::http::StatusCode;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub(crate) enum ErrorCode {
+    /// The request isn't signed in, or its credentials are invalid.
+    Unauthenticated,
+    /// The user isn't a member of the project.
+    NotAMember,
+    /// The user may not perform the action, e.g. isn't an admin.
+    Forbidden,
+    NotFound,
+    /// The request, or a field of it, is invalid.
+    ValidationFailed,
+    Conflict,
+    /// The project's doc isn't loaded, e.g. because the project is closing.
+    DocNotLoaded,
+    /// A websocket message couldn't be decoded.
+    MalformedMessage,
+    /// The project is frozen for maintenance.
+    Maintenance,
+    /// The server is shedding load. Retry later.
+    Overloaded,
+    /// The project is stored in another data region, served by another deployment.
+    WrongRegion,
+    Internal,
+}
+
+impl ErrorCode {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::Unauthenticated => "UNAUTHENTICATED",
+            ErrorCode::NotAMember => "NOT_A_MEMBER",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::ValidationFailed => "VALIDATION_FAILED",
+            ErrorCode::Conflict => "CONFLICT",
+            ErrorCode::DocNotLoaded => "DOC_NOT_LOADED",
+            ErrorCode::MalformedMessage => "MALFORMED_MESSAGE",
+            ErrorCode::Maintenance => "MAINTENANCE",
+            ErrorCode::Overloaded => "OVERLOADED",
+            ErrorCode::WrongRegion => "WRONG_REGION",
+            ErrorCode::Internal => "INTERNAL",
+        }
+    }
+
+    pub(crate) fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::Unauthenticated => StatusCode::UNAUTHORIZED,
+            ErrorCode::NotAMember | ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::ValidationFailed | ErrorCode::MalformedMessage => StatusCode::BAD_REQUEST,
+            ErrorCode::Conflict => StatusCode::CONFLICT,
+            ErrorCode::DocNotLoaded | ErrorCode::Maintenance => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::Overloaded => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::WrongRegion => StatusCode::MISDIRECTED_REQUEST,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The code of errors that don't set one explicitly.
+    pub(crate) fn from_status(status: StatusCode) -> ErrorCode {
+        match status {
+            StatusCode::UNAUTHORIZED => ErrorCode::Unauthenticated,
+            StatusCode::FORBIDDEN => ErrorCode::Forbidden,
+            StatusCode::NOT_FOUND => ErrorCode::NotFound,
+            StatusCode::CONFLICT => ErrorCode::Conflict,
+            StatusCode::TOO_MANY_REQUESTS => ErrorCode::Overloaded,
+            StatusCode::SERVICE_UNAVAILABLE => ErrorCode::Maintenance,
+            StatusCode::MISDIRECTED_REQUEST => ErrorCode::WrongRegion,
+            s if s.is_client_error() => ErrorCode::ValidationFailed,
+            _ => ErrorCode::Internal,
+        }
+    }
+
+    /// A short, human readable summary, the problem's title.
+    pub(crate) fn title(self) -> &'static str {
+        match self {
+            ErrorCode::Unauthenticated => "Not signed in",
+            ErrorCode::NotAMember => "Not a member of the project",
+            ErrorCode::Forbidden => "Not allowed",
+            ErrorCode::NotFound => "Not found",
+            ErrorCode::ValidationFailed => "Validation failed",
+            ErrorCode::Conflict => "Conflict",
+            ErrorCode::DocNotLoaded => "Project doc not loaded",
+            ErrorCode::MalformedMessage => "Malformed message",
+            ErrorCode::Maintenance => "Down for maintenance",
+            ErrorCode::Overloaded => "Overloaded",
+            ErrorCode::WrongRegion => "Stored in another region",
+            ErrorCode::Internal => "Internal error",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An error with a code, for code below the API boundary that returns anyhow
+/// errors. Coded errors found in an error's chain determine the code clients
+/// see, rather than the error being reported as internal.
+#[derive(Debug)]
+pub(crate) struct CodedError {
+    pub(crate) code: ErrorCode,
+    pub(crate) msg: String,
+}
+
+impl CodedError {
+    pub(crate) fn new(code: ErrorCode, msg: impl Into<String>) -> Self {
+        CodedError {
+            code,
+            msg: msg.into(),
+        }
+    }
+}
+
+impl fmt::Display for CodedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.msg)
+    }
+}
+
+impl std::error::Error for CodedError {}
+
+/// Finds the coded error in the error's chain, if any, whether it's the root
+/// cause or was attached as context.
+pub(crate) fn find_coded(err: &anyhow::Error) -> Option<&CodedError> {
+    err.downcast_ref::<CodedError>()
+}
+
+/// An error frame sent to websocket clients when a message fails.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ErrorFrame {
+    pub(crate) code: ErrorCode,
+    pub(crate) detail: String,
+}
+
+impl ErrorFrame {
+    /// Internal errors aren't detailed to clients.
+    pub(crate) fn from_error(err: &anyhow::Error) -> ErrorFrame {
+        match find_coded(err) {
+            Some(coded) => ErrorFrame {
+                code: coded.code,
+                detail: coded.msg.clone(),
+            },
+            None => ErrorFrame {
+                code: ErrorCode::Internal,
+                detail: "Something went wrong processing the message".to_string(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context as _;
+
+    #[test_log::test]
+    fn coded_errors_are_found_through_context() {
+        let err = Err::<(), _>(CodedError::new(ErrorCode::DocNotLoaded, "absent"))
+            .context("Failed to apply doc update")
+            .context("Failed to process message")
+            .unwrap_err();
+        let frame = ErrorFrame::from_error(&err);
+        assert_eq!(frame.code, ErrorCode::DocNotLoaded);
+        assert_eq!(frame.detail, "absent");
+
+        let frame = ErrorFrame::from_error(&anyhow::anyhow!("boom"));
+        assert_eq!(frame.code, ErrorCode::Internal);
+        assert!(!frame.detail.contains("boom"));
+    }
+
+    #[test_log::test]
+    fn codes_round_trip_through_status() {
+        for code in [
+            ErrorCode::Unauthenticated,
+            ErrorCode::Forbidden,
+            ErrorCode::NotFound,
+            ErrorCode::ValidationFailed,
+            ErrorCode::Conflict,
+            ErrorCode::Overloaded,
+            ErrorCode::WrongRegion,
+            ErrorCode::Internal,
+        ] {
+            assert_eq!(ErrorCode::from_status(code.status()), code);
+        }
+        assert_eq!(
+            serde_json::to_string(&ErrorCode::NotAMember).unwrap(),
+            "\"NOT_A_MEMBER\""
+        );
+    }
+}
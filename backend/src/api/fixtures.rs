@@ -0,0 +1,238 @@
+//! Generates realistic projects of a configurable size and shape, for
+//! benchmarks, load tests and the demo environment. Built with the `fixtures`
+//! feature, e.g. `cargo run --features fixtures -- admin generate-doc ..`.
+
+use crate::api::{
+    collab::txn_origin::{Actor, YOrigin},
+    model::Task,
+    yproxy::YDocProxy,
+};
+use anyhow::{Result, bail};
+use rand::{Rng, SeedableRng as _, rngs::StdRng, seq::IndexedRandom as _};
+use std::collections::VecDeque;
+
+const VERBS: &[&str] = &[
+    "Add", "Fix", "Refactor", "Document", "Migrate", "Test", "Design", "Remove",
+];
+const NOUNS: &[&str] = &[
+    "login flow",
+    "billing page",
+    "search index",
+    "sync protocol",
+    "settings panel",
+    "onboarding",
+    "export job",
+    "dashboard",
+];
+const STATUSES: &[&str] = &["Not Started", "In Progress", "Done"];
+const ESTIMATES: &[i64] = &[1, 2, 3, 5, 8, 13];
+/// Status times are spread over the 90 days before this instant, so
+/// generated docs are identical across runs.
+const NOW_MILLIS: i64 = 1_750_000_000_000;
+const DAY_MILLIS: i64 = 24 * 60 * 60 * 1000;
+
+/// The size and shape of a generated project.
+#[derive(Debug, Clone)]
+pub(crate) struct Shape {
+    /// Levels of tasks below the root.
+    pub(crate) depth: usize,
+    /// The average number of children of each parent.
+    pub(crate) branching: usize,
+    /// Managed GitHub PR tasks to add, as a fraction of the other tasks.
+    pub(crate) managed_ratio: f64,
+    /// Distinct assignees to spread tasks across.
+    pub(crate) assignees: usize,
+    pub(crate) seed: u64,
+}
+
+impl Default for Shape {
+    fn default() -> Self {
+        Shape {
+            depth: 3,
+            branching: 4,
+            managed_ratio: 0.1,
+            assignees: 5,
+            seed: 0,
+        }
+    }
+}
+
+/// Generates a project, deterministically for a given shape.
+pub(crate) fn generate(shape: &Shape) -> Result<YDocProxy> {
+    if shape.branching == 0 || !(0.0..=1.0).contains(&shape.managed_ratio) {
+        bail!("Invalid shape: {shape:?}");
+    }
+    let mut rng = StdRng::seed_from_u64(shape.seed);
+    let mut tasks: Vec<Task> = Vec::new();
+    let mut next_num = 1;
+
+    // Breadth first, so nums increase level by level like a project grown over time.
+    let mut parents = VecDeque::from([(0, 0)]);
+    tasks.push(Task {
+        id: "root".to_string(),
+        num: "0".to_string(),
+        name: "Root".to_string(),
+        ..Task::default()
+    });
+    while let Some((parent, level)) = parents.pop_front() {
+        if level == shape.depth {
+            continue;
+        }
+        let count = rng.random_range(1..=shape.branching * 2 - 1);
+        for _ in 0..count {
+            let task = human_task(&mut rng, shape, next_num, level + 1 == shape.depth);
+            next_num += 1;
+            tasks[parent].children.push(task.id.clone());
+            parents.push_back((tasks.len(), level + 1));
+            tasks.push(task);
+        }
+    }
+
+    let managed = ((tasks.len() - 1) as f64 * shape.managed_ratio).round() as usize;
+    if managed > 0 {
+        let mut plugin = container("github", "GitHub");
+        let mut prs = container("github_pr", "GitHub PRs");
+        for _ in 0..managed {
+            let task = pr_task(&mut rng, shape, next_num);
+            next_num += 1;
+            prs.children.push(task.id.clone());
+            tasks.push(task);
+        }
+        plugin.children.push(prs.id.clone());
+        tasks[0].children.push(plugin.id.clone());
+        tasks.push(plugin);
+        tasks.push(prs);
+    }
+
+    let doc = YDocProxy::new();
+    {
+        let mut txn = doc.transact_mut_with(
+            YOrigin {
+                who: "fixtures".to_string(),
+                id: "fixtures".to_string(),
+                actor: Actor::Server,
+                ..Default::default()
+            }
+            .as_origin()?,
+        );
+        for task in &tasks {
+            doc.set(&mut txn, task);
+        }
+    }
+    Ok(doc)
+}
+
+fn human_task(rng: &mut impl Rng, shape: &Shape, num: usize, leaf: bool) -> Task {
+    let mut task = Task {
+        id: format!("task-{num}"),
+        num: num.to_string(),
+        name: format!(
+            "{} {}",
+            VERBS.choose(rng).unwrap_or(&"Do"),
+            NOUNS.choose(rng).unwrap_or(&"things")
+        ),
+        reporter: Some(assignee(rng, shape)),
+        ..Task::default()
+    };
+    // Parents' statuses and estimates roll up from their children.
+    if leaf || rng.random_bool(0.3) {
+        task.status = STATUSES.choose(rng).map(|s| s.to_string());
+        task.status_time = Some(NOW_MILLIS - rng.random_range(0..90) * DAY_MILLIS);
+        task.estimate = ESTIMATES
+            .choose(rng)
+            .copied()
+            .filter(|_| rng.random_bool(0.7));
+        if task.status.as_deref() != Some("Not Started") || rng.random_bool(0.5) {
+            task.assignee = Some(assignee(rng, shape));
+        }
+        task.archived = Some(task.status.as_deref() == Some("Done") && rng.random_bool(0.2));
+    }
+    task
+}
+
+fn pr_task(rng: &mut impl Rng, shape: &Shape, num: usize) -> Task {
+    Task {
+        id: format!("pr-{num}"),
+        num: num.to_string(),
+        name: format!("PR #{num}"),
+        url: Some(format!("https://github.com/koso-fixtures/app/pull/{num}")),
+        kind: Some("github_pr".to_string()),
+        status: Some(
+            if rng.random_bool(0.7) {
+                "In Progress"
+            } else {
+                "Done"
+            }
+            .to_string(),
+        ),
+        status_time: Some(NOW_MILLIS - rng.random_range(0..30) * DAY_MILLIS),
+        assignee: Some(assignee(rng, shape)),
+        ..Task::default()
+    }
+}
+
+fn container(kind: &str, name: &str) -> Task {
+    Task {
+        id: kind.to_string(),
+        num: kind.to_string(),
+        name: name.to_string(),
+        kind: Some(kind.to_string()),
+        ..Task::default()
+    }
+}
+
+fn assignee(rng: &mut impl Rng, shape: &Shape) -> String {
+    format!(
+        "user{}@koso.test",
+        rng.random_range(0..shape.assignees.max(1))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn generates_project_of_shape() {
+        let shape = Shape {
+            depth: 2,
+            branching: 3,
+            managed_ratio: 0.5,
+            ..Shape::default()
+        };
+        let doc = generate(&shape).unwrap();
+        let graph = doc.to_graph(&doc.transact()).unwrap();
+
+        let root = &graph["root"];
+        let human: Vec<&Task> = graph
+            .values()
+            .filter(|t| t.id.starts_with("task-"))
+            .collect();
+        let prs: Vec<&Task> = graph.values().filter(|t| t.id.starts_with("pr-")).collect();
+        assert!(root.children.contains(&"github".to_string()));
+        assert_eq!(graph["github"].children, vec!["github_pr"]);
+        assert_eq!(graph["github_pr"].children.len(), prs.len());
+        assert_eq!(prs.len(), (human.len() as f64 * 0.5).round() as usize);
+        assert!(prs.iter().all(|t| t.kind.as_deref() == Some("github_pr")));
+
+        // Every task is reachable and leaves sit at the requested depth.
+        for child in root.children.iter().filter(|c| c.starts_with("task-")) {
+            let child = &graph[child];
+            assert!(!child.children.is_empty());
+            for leaf in &child.children {
+                assert!(graph[leaf].children.is_empty());
+                assert!(graph[leaf].status.is_some());
+            }
+        }
+        let reachable: usize = root
+            .children
+            .iter()
+            .filter(|c| c.starts_with("task-"))
+            .map(|c| 1 + graph[c].children.len())
+            .sum();
+        assert_eq!(reachable, human.len());
+
+        let again = generate(&shape).unwrap();
+        assert_eq!(again.to_graph(&again.transact()).unwrap(), graph);
+    }
+}
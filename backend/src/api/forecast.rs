@@ -0,0 +1,212 @@
+use crate::{
+    api::{
+        ApiResult, analytics, bad_request_error,
+        collab::{Collab, projects_state::DocBox},
+        google::User,
+        model::{Forecast, ProjectId},
+        not_found_error, verify_project_access,
+    },
+    postgres::{PgPool, ReadPool},
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    routing::get,
+};
+use chrono::{Days, NaiveDate, Utc};
+use rand::Rng;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+pub(super) fn router() -> Router {
+    Router::new().route("/{project_id}/analytics/forecast", get(forecast_handler))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ForecastQuery {
+    /// The root of the subtree to forecast.
+    task_id: String,
+    /// How many days of throughput history to sample from.
+    days: Option<i64>,
+}
+
+/// Days of throughput history sampled by default.
+pub(crate) const DEFAULT_DAYS: i64 = 90;
+const MAX_DAYS: i64 = 365;
+const TRIALS: usize = 10_000;
+/// Trials that take longer than this are considered to never finish.
+const MAX_FORECAST_DAYS: u64 = 5 * 365;
+
+/// Forecasts when the remaining tasks under `task_id` will be done by
+/// simulating future days with throughput sampled from the project's history.
+#[tracing::instrument(skip(user, pool, read_pool, collab))]
+async fn forecast_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(read_pool): Extension<ReadPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+    Query(query): Query<ForecastQuery>,
+) -> ApiResult<Json<Forecast>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let days = query.days.unwrap_or(DEFAULT_DAYS);
+    if !(1..=MAX_DAYS).contains(&days) {
+        return Err(bad_request_error(
+            "INVALID_DAYS",
+            &format!("Days must be between 1 and {MAX_DAYS}"),
+        ));
+    }
+
+    let (graph, workflow_states) = {
+        let client = collab.register_local_client(&project_id).await?;
+        let doc_box = client.project.doc_box.lock().await;
+        let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+        let txn = doc.transact();
+        (doc.to_graph(&txn)?, doc.config().get_workflow_states(&txn)?)
+    };
+    if !graph.contains_key(&query.task_id) {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Task {} not found", query.task_id),
+        ));
+    }
+    let progress = analytics::leaf_progress(
+        &graph,
+        &workflow_states,
+        std::slice::from_ref(&query.task_id),
+    );
+    let remaining = progress.total - progress.done;
+
+    let today = Utc::now().date_naive();
+    let throughput = load_throughput(read_pool.get(), &project_id, today, days).await?;
+    if remaining > 0 && throughput.iter().all(|&t| t == 0) {
+        return Err(bad_request_error(
+            "NO_THROUGHPUT",
+            &format!("No tasks were completed in the last {days} days"),
+        ));
+    }
+
+    let dates = completion_dates(&throughput, remaining, today);
+    Ok(Json(Forecast {
+        task_id: query.task_id,
+        remaining,
+        history_days: days,
+        trials: TRIALS,
+        p50: dates.p50,
+        p85: dates.p85,
+        p95: dates.p95,
+    }))
+}
+
+/// Dates by which 50%, 85% and 95% of simulated trials finished, absent if
+/// those trials never finished.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct CompletionDates {
+    pub(crate) p50: Option<NaiveDate>,
+    pub(crate) p85: Option<NaiveDate>,
+    pub(crate) p95: Option<NaiveDate>,
+}
+
+/// Simulates completing `remaining` tasks starting `today` at the given
+/// historical daily throughput.
+pub(crate) fn completion_dates(
+    throughput: &[u64],
+    remaining: u64,
+    today: NaiveDate,
+) -> CompletionDates {
+    let mut trials = simulate(&mut rand::rng(), throughput, remaining, TRIALS);
+    let mut date = |percentile: f64| {
+        analytics::percentile(&mut trials, percentile)
+            .filter(|&days| days <= MAX_FORECAST_DAYS as f64)
+            .and_then(|days| today.checked_add_days(Days::new(days as u64)))
+    };
+    CompletionDates {
+        p50: date(50.0),
+        p85: date(85.0),
+        p95: date(95.0),
+    }
+}
+
+/// Loads the number of tasks completed on each of the `days` days before `today`.
+pub(crate) async fn load_throughput(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    today: NaiveDate,
+    days: i64,
+) -> Result<Vec<u64>> {
+    let completed: Vec<(NaiveDate, i64)> = sqlx::query_as(
+        "
+        SELECT done_time::date, COUNT(*)
+        FROM task_completions
+        WHERE project_id = $1
+        AND done_time >= $2::date - $3::int
+        AND done_time < $2::date
+        GROUP BY 1",
+    )
+    .bind(project_id)
+    .bind(today)
+    .bind(days)
+    .fetch_all(pool)
+    .await
+    .context("Failed to count completed tasks")?;
+    Ok(daily_throughput(&completed, today, days))
+}
+
+/// Returns the number of tasks completed on each of the `days` days before `today`.
+fn daily_throughput(completed: &[(NaiveDate, i64)], today: NaiveDate, days: i64) -> Vec<u64> {
+    let completed: HashMap<NaiveDate, i64> = completed.iter().cloned().collect();
+    (1..=days as u64)
+        .filter_map(|ago| today.checked_sub_days(Days::new(ago)))
+        .map(|day| completed.get(&day).copied().unwrap_or(0) as u64)
+        .collect()
+}
+
+/// Returns how many days each trial took to complete `remaining` tasks,
+/// drawing each day's throughput from the historical samples.
+fn simulate(rng: &mut impl Rng, throughput: &[u64], remaining: u64, trials: usize) -> Vec<f64> {
+    if throughput.is_empty() {
+        return vec![];
+    }
+    (0..trials)
+        .map(|_| {
+            let mut done = 0;
+            let mut days = 0;
+            while done < remaining && days <= MAX_FORECAST_DAYS {
+                done += throughput[rng.random_range(..throughput.len())];
+                days += 1;
+            }
+            days as f64
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng as _, rngs::StdRng};
+
+    #[test]
+    fn daily_throughput_fills_missing_days() {
+        let today = NaiveDate::from_ymd_opt(2025, 8, 10).unwrap();
+        let throughput = daily_throughput(
+            &[(NaiveDate::from_ymd_opt(2025, 8, 8).unwrap(), 3)],
+            today,
+            3,
+        );
+        assert_eq!(throughput, vec![0, 3, 0]);
+    }
+
+    #[test]
+    fn simulate_samples_throughput() {
+        let mut rng = StdRng::seed_from_u64(7);
+        assert_eq!(simulate(&mut rng, &[2], 5, 3), vec![3.0, 3.0, 3.0]);
+        assert_eq!(simulate(&mut rng, &[2], 0, 1), vec![0.0]);
+
+        let mut trials = simulate(&mut rng, &[0, 1], 10, 1000);
+        assert!(trials.iter().all(|&days| days >= 10.0));
+        let median = analytics::percentile(&mut trials, 50.0).unwrap();
+        assert!((15.0..=25.0).contains(&median), "{median}");
+    }
+}
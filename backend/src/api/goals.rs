@@ -0,0 +1,276 @@
+use crate::{
+    api::{
+        ApiResult, analytics, bad_request_error,
+        collab::{Collab, projects_state::DocBox},
+        google::User,
+        model::{CreateGoal, Goal, Graph, ProjectId, UpdateGoal, WorkflowState},
+        not_found_error, validate_title, verify_project_access,
+    },
+    postgres::{PgPool, list_project_users},
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    routing::{get, patch},
+};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use sqlx::types::{
+    Json as SqlJson,
+    chrono::{DateTime, NaiveDate, Utc},
+};
+use uuid::Uuid;
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route(
+            "/{project_id}/goals",
+            get(list_goals_handler).post(create_goal_handler),
+        )
+        .route(
+            "/{project_id}/goals/{goal_id}",
+            patch(update_goal_handler).delete(delete_goal_handler),
+        )
+}
+
+const MAX_DESCRIPTION_LEN: usize = 10_000;
+const MAX_TASKS: usize = 100;
+
+#[derive(sqlx::FromRow)]
+struct GoalRow {
+    id: String,
+    title: String,
+    description: Option<String>,
+    owner: Option<String>,
+    due_date: Option<NaiveDate>,
+    task_ids: SqlJson<Vec<String>>,
+    creator: String,
+    create_time: DateTime<Utc>,
+    update_time: DateTime<Utc>,
+}
+
+impl GoalRow {
+    fn into_goal(self, graph: &Graph, workflow_states: &[WorkflowState]) -> Goal {
+        Goal {
+            progress: analytics::leaf_progress(graph, workflow_states, &self.task_ids),
+            id: self.id,
+            title: self.title,
+            description: self.description,
+            owner: self.owner,
+            due_date: self.due_date,
+            task_ids: self.task_ids.0,
+            creator: self.creator,
+            create_time: self.create_time,
+            update_time: self.update_time,
+        }
+    }
+}
+
+/// Lists the project's goals, soonest due first, with progress derived from
+/// the completion of their linked tasks.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn list_goals_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Vec<Goal>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let (graph, workflow_states) = load_graph(&collab, &project_id).await?;
+    Ok(Json(
+        list_goals_with_progress(pool, &project_id, &graph, &workflow_states).await?,
+    ))
+}
+
+#[tracing::instrument(skip(user, pool, collab))]
+async fn create_goal_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+    Json(goal): Json<CreateGoal>,
+) -> ApiResult<Json<Goal>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    validate_title("Goal", &goal.title)?;
+    validate_description(goal.description.as_deref())?;
+    validate_owner(pool, &project_id, goal.owner.as_deref()).await?;
+    let (graph, workflow_states) = load_graph(&collab, &project_id).await?;
+    validate_task_ids(&graph, &goal.task_ids)?;
+
+    let created: GoalRow = sqlx::query_as(
+        "
+        INSERT INTO goals (id, project_id, title, description, owner, due_date, task_ids, creator)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING id, title, description, owner, due_date, task_ids, creator, create_time, update_time",
+    )
+    .bind(BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4()))
+    .bind(&project_id)
+    .bind(goal.title.trim())
+    .bind(&goal.description)
+    .bind(&goal.owner)
+    .bind(goal.due_date)
+    .bind(SqlJson(&goal.task_ids))
+    .bind(&user.email)
+    .fetch_one(pool)
+    .await
+    .context("Failed to insert goal")?;
+    Ok(Json(created.into_goal(&graph, &workflow_states)))
+}
+
+#[tracing::instrument(skip(user, pool, collab))]
+async fn update_goal_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path((project_id, goal_id)): Path<(ProjectId, String)>,
+    Json(update): Json<UpdateGoal>,
+) -> ApiResult<Json<Goal>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    if let Some(title) = &update.title {
+        validate_title("Goal", title)?;
+    }
+    validate_description(update.description.as_deref())?;
+    validate_owner(pool, &project_id, update.owner.as_deref()).await?;
+    let (graph, workflow_states) = load_graph(&collab, &project_id).await?;
+    if let Some(task_ids) = &update.task_ids {
+        validate_task_ids(&graph, task_ids)?;
+    }
+
+    let updated: Option<GoalRow> = sqlx::query_as(
+        "
+        UPDATE goals
+        SET title = COALESCE($3, title),
+            description = COALESCE($4, description),
+            owner = COALESCE($5, owner),
+            due_date = COALESCE($6, due_date),
+            task_ids = COALESCE($7, task_ids),
+            update_time = NOW()
+        WHERE project_id = $1 AND id = $2
+        RETURNING id, title, description, owner, due_date, task_ids, creator, create_time, update_time",
+    )
+    .bind(&project_id)
+    .bind(&goal_id)
+    .bind(update.title.as_deref().map(str::trim))
+    .bind(&update.description)
+    .bind(&update.owner)
+    .bind(update.due_date)
+    .bind(update.task_ids.as_ref().map(SqlJson))
+    .fetch_optional(pool)
+    .await
+    .context("Failed to update goal")?;
+    let Some(updated) = updated else {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Goal {goal_id} not found"),
+        ));
+    };
+    Ok(Json(updated.into_goal(&graph, &workflow_states)))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_goal_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, goal_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<()>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let res = sqlx::query("DELETE FROM goals WHERE project_id = $1 AND id = $2")
+        .bind(&project_id)
+        .bind(&goal_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete goal")?;
+    if res.rows_affected() == 0 {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Goal {goal_id} not found"),
+        ));
+    }
+    Ok(Json(()))
+}
+
+/// Lists the project's goals with progress derived from `graph`.
+pub(crate) async fn list_goals_with_progress(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    graph: &Graph,
+    workflow_states: &[WorkflowState],
+) -> Result<Vec<Goal>> {
+    Ok(list_goals(pool, project_id)
+        .await?
+        .into_iter()
+        .map(|row| row.into_goal(graph, workflow_states))
+        .collect())
+}
+
+async fn list_goals(pool: &PgPool, project_id: &ProjectId) -> Result<Vec<GoalRow>> {
+    sqlx::query_as(
+        "
+        SELECT id, title, description, owner, due_date, task_ids, creator, create_time, update_time
+        FROM goals
+        WHERE project_id = $1
+        ORDER BY due_date NULLS LAST, create_time",
+    )
+    .bind(project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list goals")
+}
+
+pub(crate) async fn load_graph(
+    collab: &Collab,
+    project_id: &ProjectId,
+) -> Result<(Graph, Vec<WorkflowState>)> {
+    let client = collab.register_local_client(project_id).await?;
+    let doc_box = client.project.doc_box.lock().await;
+    let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+    let txn = doc.transact();
+    Ok((doc.to_graph(&txn)?, doc.config().get_workflow_states(&txn)?))
+}
+
+fn validate_description(description: Option<&str>) -> ApiResult<()> {
+    if description.is_some_and(|d| d.len() > MAX_DESCRIPTION_LEN) {
+        return Err(bad_request_error(
+            "LONG_DESCRIPTION",
+            &format!("Goal description cannot be longer than {MAX_DESCRIPTION_LEN} characters"),
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) async fn validate_owner(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    owner: Option<&str>,
+) -> ApiResult<()> {
+    let Some(owner) = owner else {
+        return Ok(());
+    };
+    if !list_project_users(pool, project_id)
+        .await?
+        .iter()
+        .any(|u| u.email == owner)
+    {
+        return Err(bad_request_error(
+            "INVALID_OWNER",
+            &format!("{owner} is not a member of the project"),
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_task_ids(graph: &Graph, task_ids: &[String]) -> ApiResult<()> {
+    if task_ids.len() > MAX_TASKS {
+        return Err(bad_request_error(
+            "TOO_MANY_TASKS",
+            &format!("Cannot link more than {MAX_TASKS} tasks"),
+        ));
+    }
+    if let Some(missing) = task_ids.iter().find(|id| !graph.contains_key(*id)) {
+        return Err(bad_request_error(
+            "INVALID_TASK",
+            &format!("Task {missing} does not exist"),
+        ));
+    }
+    Ok(())
+}
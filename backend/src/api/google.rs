@@ -1,5 +1,10 @@
 use crate::{
-    api::{ApiResult, unauthenticated_error},
+    api::{
+        ApiResult, context, impersonation,
+        security::{self, client_ip},
+        unauthenticated_error,
+    },
+    postgres::PgPool,
     settings::settings,
 };
 use anyhow::{Result, anyhow};
@@ -239,6 +244,17 @@ pub(crate) async fn authenticate(mut request: Request, next: Next) -> ApiResult<
     user.email = user.email.to_lowercase();
 
     tracing::Span::current().record("email", user.email.clone());
+    let pool = *request.extensions().get::<&'static PgPool>().unwrap();
+    security::enforce_policies(
+        pool,
+        &user,
+        client_ip(request.headers(), request.extensions()),
+    )
+    .await?;
+    if let Some(token) = impersonation::token(request.headers()) {
+        return impersonation::impersonate(pool, user, &token, request, next).await;
+    }
+    context::set_user(&user);
     assert!(request.extensions_mut().insert(user).is_none());
 
     Ok(next.run(request).await)
@@ -297,6 +313,17 @@ pub(crate) struct User {
     pub(crate) name: String,
     pub(crate) picture: String,
     pub(crate) exp: usize,
+    /// When the token was issued. Refreshing the token advances it.
+    #[serde(default)]
+    pub(crate) iat: Option<usize>,
+    /// When the user last actually authenticated with the identity provider.
+    /// Unlike `iat`, refreshing the token doesn't advance it.
+    #[serde(default)]
+    pub(crate) auth_time: Option<usize>,
+    /// The nonce the client asked the identity provider to include, e.g. one
+    /// issued for a step up.
+    #[serde(default)]
+    pub(crate) nonce: Option<String>,
 }
 
 #[cfg(test)]
@@ -529,6 +556,11 @@ J4Q2mbNL+TM1cM1BbzXn6SBWWTKlyEx7OLgQ2+VlZ1CRLQI/iI1tbHIt
         pub(crate) name: String,
         pub(crate) picture: String,
         pub(crate) exp: u32,
+        pub(crate) iat: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) auth_time: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) nonce: Option<String>,
     }
 
     impl Default for Claims {
@@ -541,6 +573,12 @@ J4Q2mbNL+TM1cM1BbzXn6SBWWTKlyEx7OLgQ2+VlZ1CRLQI/iI1tbHIt
                 name: "Valid User".to_string(),
                 picture: "koso.app/valid-user/pic".to_string(),
                 exp: 2024788014,
+                iat: std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+                auth_time: None,
+                nonce: None,
             }
         }
     }
@@ -0,0 +1,273 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        google::User,
+        model::{GroupPolicy, ProjectGroup, ProjectId},
+        not_found_error, verify_project_access,
+    },
+    postgres::{PgPool, list_project_users},
+};
+use anyhow::{Context as _, Result, anyhow};
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    routing::{get, put},
+};
+
+/// Tasks are assigned to a group by setting their assignee to this prefix
+/// followed by the group name. e.g. "group:backend"
+pub(crate) const GROUP_ASSIGNEE_PREFIX: &str = "group:";
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/{project_id}/groups", get(list_groups_handler))
+        .route(
+            "/{project_id}/groups/{name}",
+            put(upsert_group_handler).delete(delete_group_handler),
+        )
+}
+
+/// Returns the group name if the assignee is a group placeholder.
+pub(crate) fn parse_group_assignee(assignee: &str) -> Option<&str> {
+    assignee.strip_prefix(GROUP_ASSIGNEE_PREFIX)
+}
+
+/// The outcome of assigning a task to a group.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum GroupAssignment {
+    Member(String),
+    Expand(Vec<String>),
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn list_groups_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Vec<ProjectGroup>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    Ok(Json(list_groups(pool, &project_id).await?))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn upsert_group_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, name)): Path<(ProjectId, String)>,
+    Json(group): Json<ProjectGroup>,
+) -> ApiResult<Json<ProjectGroup>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    if name != group.name {
+        return Err(bad_request_error(
+            "NAME_MISMATCH",
+            &format!(
+                "Path group name ({name}) is different than body group name ({})",
+                group.name
+            ),
+        ));
+    }
+    validate_group_name(&group.name)?;
+
+    let mut members = group
+        .members
+        .into_iter()
+        .map(|m| m.to_lowercase())
+        .collect::<Vec<String>>();
+    members.sort();
+    members.dedup();
+    if members.is_empty() {
+        return Err(bad_request_error(
+            "EMPTY_GROUP",
+            "Group must have at least one member",
+        ));
+    }
+    let project_users = list_project_users(pool, &project_id).await?;
+    if let Some(non_member) = members
+        .iter()
+        .find(|m| !project_users.iter().any(|u| &u.email == *m))
+    {
+        return Err(bad_request_error(
+            "NOT_A_MEMBER",
+            &format!("{non_member} is not a member of the project"),
+        ));
+    }
+
+    sqlx::query(
+        "
+        INSERT INTO project_groups (project_id, name, members, policy)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (project_id, name)
+        DO UPDATE SET members = EXCLUDED.members, policy = EXCLUDED.policy",
+    )
+    .bind(&project_id)
+    .bind(&group.name)
+    .bind(&members)
+    .bind(policy_to_str(group.policy))
+    .execute(pool)
+    .await
+    .context("Failed to upsert group")?;
+
+    Ok(Json(ProjectGroup {
+        name: group.name,
+        members,
+        policy: group.policy,
+    }))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_group_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, name)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<()>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let res = sqlx::query("DELETE FROM project_groups WHERE project_id = $1 AND name = $2")
+        .bind(&project_id)
+        .bind(&name)
+        .execute(pool)
+        .await
+        .context("Failed to delete group")?;
+    if res.rows_affected() == 0 {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Group {name} not found"),
+        ));
+    }
+    Ok(Json(()))
+}
+
+pub(crate) async fn list_groups(
+    pool: &PgPool,
+    project_id: &ProjectId,
+) -> Result<Vec<ProjectGroup>> {
+    let rows: Vec<(String, Vec<String>, String)> = sqlx::query_as(
+        "
+        SELECT name, members, policy
+        FROM project_groups
+        WHERE project_id = $1
+        ORDER BY name",
+    )
+    .bind(project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list groups")?;
+
+    rows.into_iter()
+        .map(|(name, members, policy)| {
+            Ok(ProjectGroup {
+                name,
+                members,
+                policy: policy_from_str(&policy)?,
+            })
+        })
+        .collect()
+}
+
+/// Resolves the members a task assigned to the given group should be assigned to,
+/// advancing the group's round robin position.
+/// Returns None if the group does not exist.
+pub(crate) async fn assign_group(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    name: &str,
+) -> Result<Option<GroupAssignment>> {
+    let row: Option<(Vec<String>, String, i32)> = sqlx::query_as(
+        "
+        UPDATE project_groups
+        SET next_index = next_index + 1
+        WHERE project_id = $1 AND name = $2
+        RETURNING members, policy, next_index - 1",
+    )
+    .bind(project_id)
+    .bind(name)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to advance group")?;
+    let Some((members, policy, index)) = row else {
+        return Ok(None);
+    };
+    select_members(members, policy_from_str(&policy)?, index)
+}
+
+fn select_members(
+    members: Vec<String>,
+    policy: GroupPolicy,
+    index: i32,
+) -> Result<Option<GroupAssignment>> {
+    if members.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(match policy {
+        GroupPolicy::RoundRobin => {
+            let index = usize::try_from(index)? % members.len();
+            GroupAssignment::Member(members[index].clone())
+        }
+        GroupPolicy::Expand => GroupAssignment::Expand(members),
+    }))
+}
+
+fn validate_group_name(name: &str) -> ApiResult<()> {
+    if name.is_empty() {
+        return Err(bad_request_error("EMPTY_NAME", "Group name is blank"));
+    }
+    const MAX_NAME_LEN: usize = 64;
+    if name.len() > MAX_NAME_LEN {
+        return Err(bad_request_error(
+            "LONG_NAME",
+            &format!("Group name cannot be longer than {MAX_NAME_LEN} characters"),
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(bad_request_error(
+            "INVALID_NAME",
+            "Group name may only contain letters, numbers, '-' and '_'",
+        ));
+    }
+    Ok(())
+}
+
+fn policy_to_str(policy: GroupPolicy) -> &'static str {
+    match policy {
+        GroupPolicy::RoundRobin => "roundRobin",
+        GroupPolicy::Expand => "expand",
+    }
+}
+
+fn policy_from_str(policy: &str) -> Result<GroupPolicy> {
+    match policy {
+        "roundRobin" => Ok(GroupPolicy::RoundRobin),
+        "expand" => Ok(GroupPolicy::Expand),
+        policy => Err(anyhow!("Invalid group policy: {policy}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_group_assignee_strips_prefix() {
+        assert_eq!(parse_group_assignee("group:backend"), Some("backend"));
+        assert_eq!(parse_group_assignee("a@koso.app"), None);
+    }
+
+    #[test]
+    fn select_members_round_robins() {
+        let members = vec!["a@koso.app".to_string(), "b@koso.app".to_string()];
+        assert_eq!(
+            select_members(members.clone(), GroupPolicy::RoundRobin, 3).unwrap(),
+            Some(GroupAssignment::Member("b@koso.app".to_string()))
+        );
+        assert_eq!(
+            select_members(members.clone(), GroupPolicy::Expand, 3).unwrap(),
+            Some(GroupAssignment::Expand(members))
+        );
+        assert_eq!(
+            select_members(vec![], GroupPolicy::RoundRobin, 0).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,62 @@
+//! Lets users review the external identities, e.g. Slack members, suggested
+//! for them from matching emails, confirming or rejecting each.
+
+use crate::{
+    api::{ApiResult, bad_request_error, google::User, not_found_error},
+    plugins::identities::{Identity, IdentityStatus, IdentityStore, Provider},
+    postgres::PgPool,
+};
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    routing::{get, put},
+};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UpdateIdentityRequest {
+    /// Either confirmed or rejected.
+    status: IdentityStatus,
+}
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/", get(list_identities_handler))
+        .route("/{provider}/{external_id}", put(update_identity_handler))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn list_identities_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<Vec<Identity>>> {
+    Ok(Json(
+        IdentityStore::new(pool).list_for_user(&user.email).await?,
+    ))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn update_identity_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((provider, external_id)): Path<(Provider, String)>,
+    Json(request): Json<UpdateIdentityRequest>,
+) -> ApiResult<Json<()>> {
+    if request.status == IdentityStatus::Suggested {
+        return Err(bad_request_error(
+            "INVALID_STATUS",
+            "Identities can only be confirmed or rejected",
+        ));
+    }
+    let updated = IdentityStore::new(pool)
+        .set_status(provider, &external_id, &user.email, request.status)
+        .await?;
+    if !updated {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("No {provider:?} identity {external_id} for user"),
+        ));
+    }
+    Ok(Json(()))
+}
@@ -0,0 +1,458 @@
+//! Admin impersonation, i.e. "view as user", for debugging support requests.
+//! An admin requests a session with a reason, the user consents, and for a
+//! limited time the admin's requests carrying the session's token act as the
+//! user. Impersonated requests are read-only, flagged in responses so clients
+//! can show a banner, and every one of them is audited.
+
+use crate::{
+    api::{
+        ApiResult, bad_request_error, collab::Collab, context, error_response, google::User,
+        hash_token, inbox::Inbox, model::InboxKind, not_found_error, step_up, unauthorized_error,
+        verify_admin,
+    },
+    postgres::PgPool,
+};
+use anyhow::Context as _;
+use axum::{
+    Extension, Json, Router,
+    body::Body,
+    extract::{Path, Request},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::{self, Next},
+    response::Response,
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    FromRow,
+    types::chrono::{DateTime, Utc},
+};
+use uuid::Uuid;
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/", get(list_sessions_handler))
+        .route(
+            "/",
+            post(request_session_handler)
+                .route_layer(middleware::from_fn(step_up::require_step_up)),
+        )
+        .route("/{session_id}/consent", post(consent_handler))
+        .route("/{session_id}/end", post(end_handler))
+        .route("/{session_id}/audit", get(list_audit_log_handler))
+}
+
+/// Header carrying the session's token on impersonated requests.
+const TOKEN_HEADER: &str = "koso-impersonation";
+/// Header flagging responses to impersonated requests with the user's email.
+const IMPERSONATING_HEADER: HeaderName = HeaderName::from_static("koso-impersonating");
+const DEFAULT_DURATION_MINS: i32 = 30;
+const MAX_DURATION_MINS: i32 = 120;
+const MAX_REASON_LEN: usize = 1000;
+/// Requests the user hasn't consented to within this long lapse.
+const CONSENT_WINDOW_HOURS: i32 = 24;
+/// Read-only routes that are nonetheless off limits while impersonating,
+/// because they expose credentials or manage access.
+const EXCLUDED_PATHS: &[&str] = &[
+    "/impersonation",
+    "/auth",
+    "/billing",
+    "/security",
+    "/dev",
+    "/profile/zapier-key",
+];
+
+/// Present in the extensions of impersonated requests.
+#[derive(Clone, Debug)]
+pub(crate) struct Impersonation {
+    pub(crate) session_id: String,
+    pub(crate) admin_email: String,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Session {
+    id: String,
+    admin_email: String,
+    user_email: String,
+    reason: String,
+    duration_secs: i32,
+    create_time: DateTime<Utc>,
+    consent_time: Option<DateTime<Utc>>,
+    expire_time: Option<DateTime<Utc>>,
+    end_time: Option<DateTime<Utc>>,
+    ended_by: Option<String>,
+}
+
+const SESSION_COLUMNS: &str = "id, admin_email, user_email, reason, duration_secs, create_time, consent_time, expire_time, end_time, ended_by";
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RequestSession {
+    email: String,
+    reason: String,
+    duration_mins: Option<i32>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SessionToken {
+    id: String,
+    /// Usable once the user consents. Not retrievable later.
+    token: String,
+}
+
+/// Asks the user to consent to being impersonated by the admin.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn request_session_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Json(request): Json<RequestSession>,
+) -> ApiResult<Json<SessionToken>> {
+    verify_admin(&user)?;
+    let email = request.email.trim().to_lowercase();
+    if email == user.email {
+        return Err(bad_request_error(
+            "SELF_IMPERSONATION",
+            "Admins can't impersonate themselves",
+        ));
+    }
+    let reason = request.reason.trim();
+    if reason.is_empty() {
+        return Err(bad_request_error("EMPTY_REASON", "A reason is required"));
+    }
+    if reason.len() > MAX_REASON_LEN {
+        return Err(bad_request_error(
+            "LONG_REASON",
+            &format!("Reasons must be at most {MAX_REASON_LEN} characters"),
+        ));
+    }
+    let duration_mins = request.duration_mins.unwrap_or(DEFAULT_DURATION_MINS);
+    if !(1..=MAX_DURATION_MINS).contains(&duration_mins) {
+        return Err(bad_request_error(
+            "INVALID_DURATION",
+            &format!("Durations must be between 1 and {MAX_DURATION_MINS} minutes"),
+        ));
+    }
+    let exists: Option<(String,)> = sqlx::query_as("SELECT email FROM users WHERE email = $1")
+        .bind(&email)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to look up user")?;
+    if exists.is_none() {
+        return Err(not_found_error("NOT_FOUND", &format!("No user {email}")));
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let token = format!("kim_{}", Uuid::new_v4().simple());
+    sqlx::query(
+        "
+        INSERT INTO impersonation_sessions (id, admin_email, user_email, reason, token_hash, duration_secs)
+        VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(&id)
+    .bind(&user.email)
+    .bind(&email)
+    .bind(reason)
+    .bind(hash_token(&token))
+    .bind(duration_mins * 60)
+    .execute(pool)
+    .await
+    .context("Failed to create impersonation session")?;
+
+    Inbox::new(pool, collab.messenger())
+        .deliver(
+            &email,
+            InboxKind::ImpersonationRequest,
+            None,
+            None,
+            Some(&user.email),
+            &format!(
+                "{} asks to view Koso as you for {duration_mins} minutes: {reason}",
+                user.email
+            ),
+        )
+        .await?;
+    tracing::info!("{} requested to impersonate {email}", user.email);
+    Ok(Json(SessionToken { id, token }))
+}
+
+/// Lists recent sessions in which the user is the admin or the impersonated user.
+#[tracing::instrument(skip(user, pool))]
+async fn list_sessions_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<Vec<Session>>> {
+    let sessions: Vec<Session> = sqlx::query_as(&format!(
+        "
+        SELECT {SESSION_COLUMNS}
+        FROM impersonation_sessions
+        WHERE admin_email = $1 OR user_email = $1
+        ORDER BY create_time DESC
+        LIMIT 100"
+    ))
+    .bind(&user.email)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list impersonation sessions")?;
+    Ok(Json(sessions))
+}
+
+async fn fetch_session(pool: &PgPool, user: &User, session_id: &str) -> ApiResult<Session> {
+    let session: Option<Session> = sqlx::query_as(&format!(
+        "
+        SELECT {SESSION_COLUMNS}
+        FROM impersonation_sessions
+        WHERE id = $1 AND (admin_email = $2 OR user_email = $2)"
+    ))
+    .bind(session_id)
+    .bind(&user.email)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch impersonation session")?;
+    session.ok_or_else(|| {
+        not_found_error(
+            "NOT_FOUND",
+            &format!("No impersonation session {session_id}"),
+        )
+    })
+}
+
+/// Consents to the session, starting it.
+#[tracing::instrument(skip(user, pool))]
+async fn consent_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(session_id): Path<String>,
+) -> ApiResult<Json<()>> {
+    let session = fetch_session(pool, &user, &session_id).await?;
+    if session.user_email != user.email {
+        return Err(unauthorized_error("Only the impersonated user may consent"));
+    }
+    let consented = sqlx::query(
+        "
+        UPDATE impersonation_sessions
+        SET consent_time = NOW(), expire_time = NOW() + make_interval(secs => duration_secs)
+        WHERE id = $1
+          AND consent_time IS NULL
+          AND end_time IS NULL
+          AND create_time > NOW() - make_interval(hours => $2)",
+    )
+    .bind(&session_id)
+    .bind(CONSENT_WINDOW_HOURS)
+    .execute(pool)
+    .await
+    .context("Failed to consent to impersonation session")?
+    .rows_affected();
+    if consented == 0 {
+        return Err(bad_request_error(
+            "NOT_PENDING",
+            "The session was already consented to, ended or lapsed",
+        ));
+    }
+    tracing::info!(
+        "{} consented to impersonation by {}",
+        user.email,
+        session.admin_email
+    );
+    Ok(Json(()))
+}
+
+/// Ends the session. Either the admin or the user may end it.
+#[tracing::instrument(skip(user, pool))]
+async fn end_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(session_id): Path<String>,
+) -> ApiResult<Json<()>> {
+    fetch_session(pool, &user, &session_id).await?;
+    sqlx::query(
+        "
+        UPDATE impersonation_sessions
+        SET end_time = NOW(), ended_by = $2
+        WHERE id = $1 AND end_time IS NULL",
+    )
+    .bind(&session_id)
+    .bind(&user.email)
+    .execute(pool)
+    .await
+    .context("Failed to end impersonation session")?;
+    Ok(Json(()))
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AuditEntry {
+    method: String,
+    path: String,
+    status: i32,
+    request_id: Option<String>,
+    create_time: DateTime<Utc>,
+}
+
+/// Lists the requests made in the session, for the admin and the user alike.
+#[tracing::instrument(skip(user, pool))]
+async fn list_audit_log_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(session_id): Path<String>,
+) -> ApiResult<Json<Vec<AuditEntry>>> {
+    fetch_session(pool, &user, &session_id).await?;
+    let entries: Vec<AuditEntry> = sqlx::query_as(
+        "
+        SELECT method, path, status, request_id, create_time
+        FROM impersonation_audit_log
+        WHERE session_id = $1
+        ORDER BY create_time DESC
+        LIMIT 1000",
+    )
+    .bind(&session_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list impersonation audit log")?;
+    Ok(Json(entries))
+}
+
+/// Returns the impersonation token presented with the request, if any.
+/// Websockets, which can't set headers, present it as a subprotocol
+/// following "impersonation", like the bearer token.
+pub(crate) fn token(headers: &HeaderMap) -> Option<String> {
+    if let Some(token) = headers.get(TOKEN_HEADER) {
+        return token.to_str().ok().map(ToString::to_string);
+    }
+    let swp = headers.get("sec-websocket-protocol")?.to_str().ok()?;
+    let mut parts = swp.split(", ");
+    parts.find(|p| *p == "impersonation")?;
+    parts.next().map(ToString::to_string)
+}
+
+/// Runs the request as the user of the admin's active session. Called by the
+/// auth middleware in place of running the request as the admin.
+pub(crate) async fn impersonate(
+    pool: &PgPool,
+    admin: User,
+    token: &str,
+    mut request: Request,
+    next: Next,
+) -> ApiResult<Response<Body>> {
+    verify_admin(&admin)?;
+    let session: Option<(String, String, String, String)> = sqlx::query_as(
+        "
+        SELECT s.id, s.user_email, u.name, u.picture
+        FROM impersonation_sessions s
+        JOIN users u ON u.email = s.user_email
+        WHERE s.token_hash = $1
+          AND s.admin_email = $2
+          AND s.consent_time IS NOT NULL
+          AND s.end_time IS NULL
+          AND s.expire_time > NOW()",
+    )
+    .bind(hash_token(token))
+    .bind(&admin.email)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up impersonation session")?;
+    let Some((session_id, email, name, picture)) = session else {
+        return Err(unauthorized_error(
+            "The impersonation session is not active",
+        ));
+    };
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    if !permitted(&method, &path) {
+        audit(pool, &session_id, &method, &path, StatusCode::FORBIDDEN).await;
+        return Err(error_response(
+            StatusCode::FORBIDDEN,
+            "IMPERSONATION_READ_ONLY",
+            Some(&format!(
+                "{method} {path} is not permitted while impersonating"
+            )),
+            None,
+        ));
+    }
+
+    let user = User {
+        email: email.clone(),
+        name,
+        picture,
+        exp: admin.exp,
+        iat: admin.iat,
+        auth_time: admin.auth_time,
+        nonce: None,
+    };
+    tracing::info!("{} is impersonating {email}", admin.email);
+    context::set_user(&user);
+    request.extensions_mut().insert(user);
+    request.extensions_mut().insert(Impersonation {
+        session_id: session_id.clone(),
+        admin_email: admin.email,
+    });
+
+    let mut response = next.run(request).await;
+    audit(pool, &session_id, &method, &path, response.status()).await;
+    if let Ok(email) = HeaderValue::from_str(&email) {
+        response.headers_mut().insert(IMPERSONATING_HEADER, email);
+    }
+    Ok(response)
+}
+
+/// Impersonation is read-only: only reads outside the excluded routes are
+/// permitted. Websocket connections are permitted but their edits dropped.
+fn permitted(method: &Method, path: &str) -> bool {
+    (method == Method::GET || method == Method::HEAD)
+        && !EXCLUDED_PATHS
+            .iter()
+            .any(|p| path == *p || path.starts_with(&format!("{p}/")))
+}
+
+/// Records the request. Failing to do so is logged rather than failing the
+/// request, whose response may already have been produced.
+async fn audit(pool: &PgPool, session_id: &str, method: &Method, path: &str, status: StatusCode) {
+    let request_id = context::current().map(|c| c.request_id().to_string());
+    if let Err(e) = sqlx::query(
+        "
+        INSERT INTO impersonation_audit_log (session_id, method, path, status, request_id)
+        VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(session_id)
+    .bind(method.as_str())
+    .bind(path)
+    .bind(i32::from(status.as_u16()))
+    .bind(request_id)
+    .execute(pool)
+    .await
+    {
+        tracing::error!("Failed to audit impersonated request {method} {path}: {e:?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn permits_only_reads_outside_excluded_paths() {
+        assert!(permitted(&Method::GET, "/projects/abc/export"));
+        assert!(permitted(&Method::GET, "/ws/projects/abc"));
+        assert!(permitted(&Method::GET, "/authors"));
+        assert!(!permitted(&Method::POST, "/projects/abc/tasks"));
+        assert!(!permitted(&Method::DELETE, "/projects/abc"));
+        assert!(!permitted(&Method::GET, "/auth/github"));
+        assert!(!permitted(&Method::GET, "/impersonation"));
+    }
+
+    #[test_log::test]
+    fn token_from_header_or_subprotocol() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(token(&headers), None);
+        headers.insert(
+            "sec-websocket-protocol",
+            HeaderValue::from_static("bearer, jwt, impersonation, kim_123"),
+        );
+        assert_eq!(token(&headers).as_deref(), Some("kim_123"));
+        headers.insert(TOKEN_HEADER, HeaderValue::from_static("kim_456"));
+        assert_eq!(token(&headers).as_deref(), Some("kim_456"));
+    }
+}
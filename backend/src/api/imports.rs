@@ -0,0 +1,691 @@
+//! Imports tasks from other trackers. Each source implements [Importer],
+//! streaming rows that a queued job adds to the project under a single
+//! task named after the source, so an import is easy to find, move or undo.
+//! Imported tasks are remembered by their ids in the source, so re-running an
+//! import updates the tasks it created rather than duplicating them.
+
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{Collab, projects_state::DocBox},
+        google::User,
+        jobs::{self, Job, JobHandler, JobProgress, NewJob},
+        model::{Deadline, Label, ProjectId},
+        not_found_error, verify_project_access,
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result, anyhow};
+use async_trait::async_trait;
+use axum::{
+    Extension, Json, Router,
+    extract::{DefaultBodyLimit, Path},
+    routing::{get, post},
+};
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use futures::{StreamExt as _, stream::BoxStream};
+use sqlx::{
+    FromRow,
+    types::chrono::{DateTime, Utc},
+};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+pub(crate) mod asana;
+pub(crate) mod outline;
+pub(crate) mod todoist;
+pub(crate) mod trello;
+mod writer;
+
+/// Exports of large boards run to tens of megabytes.
+const MAX_IMPORT_BYTES: usize = 50 * 1024 * 1024;
+const MAX_IMPORTED_TASKS: usize = 10_000;
+/// Row errors beyond this many are counted but not kept.
+const MAX_ROW_ERRORS: usize = 100;
+/// Progress is saved after every this many rows.
+const PROGRESS_INTERVAL: usize = 100;
+/// For labels whose source has no color.
+const DEFAULT_LABEL_COLOR: &str = "#b3b9c4";
+const IMPORT_JOB: &str = "import";
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route(
+            "/{project_id}/import/asana",
+            post(asana::import_asana_handler),
+        )
+        .route(
+            "/{project_id}/import/outline",
+            post(outline::import_outline_handler),
+        )
+        .route(
+            "/{project_id}/import/todoist",
+            post(todoist::import_todoist_handler),
+        )
+        .route(
+            "/{project_id}/import/trello",
+            post(trello::import_trello_handler),
+        )
+        .layer(DefaultBodyLimit::max(MAX_IMPORT_BYTES))
+        .route("/{project_id}/imports", get(list_jobs_handler))
+        .route("/{project_id}/imports/{job_id}", get(get_job_handler))
+}
+
+/// A source of imported tasks, e.g. a Trello board.
+pub(crate) trait Importer: Send {
+    /// Names the kind of source, e.g. trello.
+    fn source(&self) -> &'static str;
+
+    /// Identifies what's imported within the source, e.g. the board's id.
+    /// Source ids are only unique within it.
+    fn source_key(&self) -> String;
+
+    /// Reads the source's rows, parents before their children. Rows that can't
+    /// be read are reported without failing the import.
+    fn rows(&mut self) -> BoxStream<'_, Result<ImportRow, RowError>>;
+
+    /// Labels used by the rows, added to the project if missing.
+    /// Called once every row has been read.
+    fn labels(&self) -> Vec<Label>;
+}
+
+/// A task read from another tracker.
+#[derive(serde::Serialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ImportedTask {
+    /// Identifies the task in the source, so re-runs update it.
+    pub(crate) source_id: String,
+    pub(crate) name: String,
+    pub(crate) desc: Option<String>,
+    pub(crate) url: Option<String>,
+    pub(crate) status: Option<String>,
+    /// Completed in the source. Mapped to the project's first done status
+    /// unless a status is also given.
+    pub(crate) done: bool,
+    /// Email of the assignee. Dropped if they aren't a member of the project.
+    pub(crate) assignee: Option<String>,
+    pub(crate) deadline: Option<Deadline>,
+    pub(crate) labels: Vec<String>,
+    /// Subtasks, for sources read into a tree. See [TreeImporter].
+    pub(crate) children: Vec<ImportedTask>,
+}
+
+/// A task along with the source id of its parent.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct ImportRow {
+    /// Unset for the task containing the import.
+    pub(crate) parent: Option<String>,
+    pub(crate) task: ImportedTask,
+}
+
+/// A row that couldn't be read or written.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RowError {
+    /// The row's source id or, for rows without one, its position.
+    pub(crate) row: String,
+    pub(crate) message: String,
+}
+
+/// Imports sources read into a tree up front, e.g. from a single JSON document.
+pub(crate) struct TreeImporter {
+    source: &'static str,
+    source_key: String,
+    tree: Option<ImportedTask>,
+    labels: Vec<Label>,
+}
+
+impl TreeImporter {
+    pub(crate) fn new(
+        source: &'static str,
+        source_key: String,
+        tree: ImportedTask,
+        labels: Vec<Label>,
+    ) -> Self {
+        TreeImporter {
+            source,
+            source_key,
+            tree: Some(tree),
+            labels,
+        }
+    }
+}
+
+impl Importer for TreeImporter {
+    fn source(&self) -> &'static str {
+        self.source
+    }
+
+    fn source_key(&self) -> String {
+        self.source_key.clone()
+    }
+
+    fn rows(&mut self) -> BoxStream<'_, Result<ImportRow, RowError>> {
+        let rows = self.tree.take().map(flatten).unwrap_or_default();
+        futures::stream::iter(rows.into_iter().map(Ok)).boxed()
+    }
+
+    fn labels(&self) -> Vec<Label> {
+        self.labels.clone()
+    }
+}
+
+/// Lists the tree's tasks, parents before their children.
+fn flatten(tree: ImportedTask) -> Vec<ImportRow> {
+    fn visit(mut task: ImportedTask, parent: Option<String>, rows: &mut Vec<ImportRow>) {
+        let children = std::mem::take(&mut task.children);
+        let source_id = task.source_id.clone();
+        rows.push(ImportRow { parent, task });
+        for child in children {
+            visit(child, Some(source_id.clone()), rows);
+        }
+    }
+    let mut rows = Vec::new();
+    visit(tree, None, &mut rows);
+    rows
+}
+
+/// Source id for tasks without ids in their source, e.g. in text outlines,
+/// derived from their names. Siblings with the same name are told apart by
+/// their position among them.
+pub(crate) fn path_id(parent: Option<&str>, name: &str, occurrence: usize) -> String {
+    let name = match occurrence {
+        0 => name.to_string(),
+        n => format!("{name}#{n}"),
+    };
+    match parent {
+        Some(parent) => format!("{parent}/{name}"),
+        None => name,
+    }
+}
+
+/// Sets the source ids of the tree's tasks from their paths. See [path_id].
+pub(crate) fn assign_path_ids(task: &mut ImportedTask, parent: Option<&str>, occurrence: usize) {
+    task.source_id = path_id(parent, &task.name, occurrence);
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for child in &mut task.children {
+        let occurrence = seen.entry(child.name.clone()).or_default();
+        assign_path_ids(child, Some(&task.source_id), *occurrence);
+        *occurrence += 1;
+    }
+}
+
+/// Builds a tree from tasks listed in order along with their depth, as
+/// in an outline. Each task contains the deeper tasks that follow it.
+fn nest(tasks: Vec<(i32, ImportedTask)>) -> Vec<ImportedTask> {
+    fn nest_under(
+        tasks: &mut std::iter::Peekable<std::vec::IntoIter<(i32, ImportedTask)>>,
+        depth: Option<i32>,
+    ) -> Vec<ImportedTask> {
+        let mut nested = Vec::new();
+        while let Some((next_depth, _)) = tasks.peek() {
+            if depth.is_some_and(|depth| *next_depth <= depth) {
+                break;
+            }
+            let Some((next_depth, mut task)) = tasks.next() else {
+                break;
+            };
+            task.children.extend(nest_under(tasks, Some(next_depth)));
+            nested.push(task);
+        }
+        nested
+    }
+    nest_under(&mut tasks.into_iter().peekable(), None)
+}
+
+/// Options common to every source.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ImportOptions {
+    /// Task to import under. Defaults to the root.
+    pub(crate) parent_id: Option<String>,
+    /// Report what would be imported without changing the project.
+    #[serde(default)]
+    pub(crate) dry_run: bool,
+}
+
+/// The outcome of an import.
+#[derive(serde::Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ImportResult {
+    /// The task containing everything imported. Unset for dry runs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) task_id: Option<String>,
+    pub(crate) created: usize,
+    pub(crate) updated: usize,
+    pub(crate) unchanged: usize,
+    pub(crate) labels_added: Vec<String>,
+    /// Assignees left unset because they aren't members of the project.
+    pub(crate) skipped_assignees: Vec<String>,
+    /// True if the import was held for review rather than applied.
+    pub(crate) proposed: bool,
+    /// For dry runs, the changes the import would make.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) changes: Option<Vec<ImportChange>>,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ImportChange {
+    pub(crate) source_id: String,
+    pub(crate) task_id: String,
+    pub(crate) name: String,
+    pub(crate) action: ImportAction,
+    /// For updates, the fields that change.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) fields: Vec<&'static str>,
+}
+
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum ImportAction {
+    Create,
+    Update,
+    Unchanged,
+}
+
+/// An import running, or run, in the background.
+#[derive(serde::Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ImportJob {
+    pub(crate) job_id: String,
+    pub(crate) source: String,
+    pub(crate) created_by: String,
+    pub(crate) dry_run: bool,
+    /// queued, running, succeeded or failed.
+    pub(crate) status: String,
+    pub(crate) rows_read: i32,
+    pub(crate) rows_failed: i32,
+    #[sqlx(json)]
+    pub(crate) errors: Vec<RowError>,
+    #[sqlx(json)]
+    pub(crate) result: Option<serde_json::Value>,
+    /// Why the job failed.
+    pub(crate) failure: Option<String>,
+    pub(crate) create_time: DateTime<Utc>,
+    pub(crate) finish_time: Option<DateTime<Utc>>,
+}
+
+const JOB_COLUMNS: &str = "job_id, source, created_by, dry_run, status, rows_read, rows_failed, errors, result, failure, create_time, finish_time";
+
+#[tracing::instrument(skip(user, pool))]
+async fn list_jobs_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Vec<ImportJob>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let jobs: Vec<ImportJob> = sqlx::query_as(&format!(
+        "
+        SELECT {JOB_COLUMNS}
+        FROM import_jobs
+        WHERE project_id = $1
+        ORDER BY create_time DESC
+        LIMIT 50"
+    ))
+    .bind(&project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list import jobs")?;
+    Ok(Json(jobs))
+}
+
+/// Reports the job's progress, for polling until it finishes.
+#[tracing::instrument(skip(user, pool))]
+async fn get_job_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, job_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<ImportJob>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    Ok(Json(fetch_job(pool, &project_id, &job_id).await?))
+}
+
+async fn fetch_job(pool: &PgPool, project_id: &ProjectId, job_id: &str) -> ApiResult<ImportJob> {
+    let job: Option<ImportJob> = sqlx::query_as(&format!(
+        "
+        SELECT {JOB_COLUMNS}
+        FROM import_jobs
+        WHERE project_id = $1 AND job_id = $2"
+    ))
+    .bind(project_id)
+    .bind(job_id)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch import job")?;
+    job.ok_or_else(|| not_found_error("NOT_FOUND", &format!("Import {job_id} not found")))
+}
+
+/// The project's statuses, for sources that map their own to them.
+pub(crate) async fn project_statuses(
+    collab: &Collab,
+    project_id: &ProjectId,
+) -> ApiResult<Vec<String>> {
+    let client = collab.register_local_client(project_id).await?;
+    let doc_box = client.project.doc_box.lock().await;
+    let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+    Ok(doc.config().statuses(&doc.transact())?)
+}
+
+/// An import request, for any source.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase", tag = "source", content = "request")]
+pub(crate) enum ImportRequest {
+    Asana(asana::AsanaImport),
+    Outline(outline::OutlineImport),
+    Todoist(todoist::TodoistImport),
+    Trello(Box<trello::TrelloImport>),
+}
+
+impl ImportRequest {
+    fn options(&self) -> &ImportOptions {
+        match self {
+            ImportRequest::Asana(req) => &req.options,
+            ImportRequest::Outline(req) => &req.options,
+            ImportRequest::Todoist(req) => &req.options,
+            ImportRequest::Trello(req) => &req.options,
+        }
+    }
+
+    /// Reads the request's source. Called before queuing the import, to
+    /// reject invalid requests, and again when the import runs.
+    async fn importer(
+        &self,
+        collab: &Collab,
+        project_id: &ProjectId,
+    ) -> ApiResult<Box<dyn Importer>> {
+        Ok(match self {
+            ImportRequest::Asana(req) => Box::new(req.importer().await?),
+            ImportRequest::Outline(req) => Box::new(req.importer()?),
+            ImportRequest::Todoist(req) => Box::new(req.importer()?),
+            ImportRequest::Trello(req) => {
+                Box::new(req.importer(&project_statuses(collab, project_id).await?)?)
+            }
+        })
+    }
+}
+
+/// The payload of import jobs.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct QueuedImport {
+    job_id: String,
+    project_id: ProjectId,
+    user: User,
+    request: ImportRequest,
+}
+
+/// Queues the import, returning the job to poll for progress.
+pub(crate) async fn start(
+    pool: &'static PgPool,
+    collab: &Collab,
+    user: &User,
+    project_id: &ProjectId,
+    request: ImportRequest,
+) -> ApiResult<Json<ImportJob>> {
+    let source = request.importer(collab, project_id).await?.source();
+    let job_id = BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4());
+    sqlx::query(
+        "
+        INSERT INTO import_jobs (job_id, project_id, source, created_by, dry_run, status)
+        VALUES ($1, $2, $3, $4, $5, 'queued')",
+    )
+    .bind(&job_id)
+    .bind(project_id)
+    .bind(source)
+    .bind(&user.email)
+    .bind(request.options().dry_run)
+    .execute(pool)
+    .await
+    .context("Failed to create import job")?;
+
+    let queued = QueuedImport {
+        job_id: job_id.clone(),
+        project_id: project_id.clone(),
+        user: user.clone(),
+        request,
+    };
+    jobs::enqueue(pool, NewJob::new(IMPORT_JOB, &queued)?).await?;
+    Ok(Json(fetch_job(pool, project_id, &job_id).await?))
+}
+
+/// Runs queued imports.
+pub(crate) struct ImportRunner {
+    pub(crate) pool: &'static PgPool,
+    pub(crate) collab: Collab,
+}
+
+#[async_trait]
+impl JobHandler for ImportRunner {
+    fn kind(&self) -> &'static str {
+        IMPORT_JOB
+    }
+
+    fn concurrency(&self) -> usize {
+        2
+    }
+
+    fn max_attempts(&self) -> i32 {
+        3
+    }
+
+    async fn run(&self, job: &Job) -> Result<()> {
+        let queued: QueuedImport = job.payload()?;
+        let run = ImportRun {
+            pool: self.pool,
+            collab: self.collab.clone(),
+            user: queued.user,
+            project_id: queued.project_id,
+            job_id: queued.job_id,
+            options: queued.request.options().clone(),
+        };
+        run.set_status("running").await?;
+        let result = match queued.request.importer(&run.collab, &run.project_id).await {
+            Ok(mut importer) => run.import(importer.as_mut()).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            // Re-runs update the tasks imported before, so failures of the
+            // server rather than the request are retried.
+            Err(e) if e.is_internal() && job.attempts < self.max_attempts() => {
+                Err(anyhow!("Import failed: {}", e.message()))
+            }
+            result => run.finish(result).await,
+        }
+    }
+}
+
+struct ImportRun {
+    pool: &'static PgPool,
+    collab: Collab,
+    user: User,
+    project_id: ProjectId,
+    job_id: String,
+    options: ImportOptions,
+}
+
+impl ImportRun {
+    async fn set_status(&self, status: &str) -> Result<()> {
+        sqlx::query(
+            "
+            UPDATE import_jobs
+            SET status = $3
+            WHERE project_id = $1 AND job_id = $2",
+        )
+        .bind(&self.project_id)
+        .bind(&self.job_id)
+        .bind(status)
+        .execute(self.pool)
+        .await
+        .context("Failed to update import job")?;
+        self.report(status, 0, 0).await;
+        Ok(())
+    }
+
+    async fn finish(&self, result: ApiResult<ImportResult>) -> Result<()> {
+        let (status, result, failure) = match result {
+            Ok(result) => ("succeeded", serde_json::to_value(&result).ok(), None),
+            Err(e) => {
+                tracing::warn!("Import {} failed: {e:?}", self.job_id);
+                ("failed", None, Some(e.message()))
+            }
+        };
+        let (rows_read, rows_failed): (i32, i32) = sqlx::query_as(
+            "
+            UPDATE import_jobs
+            SET status = $3, result = $4, failure = $5, finish_time = NOW()
+            WHERE project_id = $1 AND job_id = $2
+            RETURNING rows_read, rows_failed",
+        )
+        .bind(&self.project_id)
+        .bind(&self.job_id)
+        .bind(status)
+        .bind(result.map(sqlx::types::Json))
+        .bind(failure)
+        .fetch_one(self.pool)
+        .await
+        .context("Failed to finish import job")?;
+        self.report(status, rows_read as usize, rows_failed as usize)
+            .await;
+        Ok(())
+    }
+
+    async fn import(&self, importer: &mut dyn Importer) -> ApiResult<ImportResult> {
+        let mut rows = Vec::new();
+        let mut errors = Errors::default();
+        {
+            let mut stream = importer.rows();
+            while let Some(row) = stream.next().await {
+                match row {
+                    Ok(row) => rows.push(row),
+                    Err(e) => errors.push(e),
+                }
+                if rows.len() > MAX_IMPORTED_TASKS {
+                    return Err(bad_request_error(
+                        "IMPORT_TOO_LARGE",
+                        &format!("Imports are limited to {MAX_IMPORTED_TASKS} tasks"),
+                    ));
+                }
+                if (rows.len() + errors.count) % PROGRESS_INTERVAL == 0 {
+                    self.save_progress(rows.len(), &errors).await?;
+                }
+            }
+        }
+        let labels = importer.labels();
+        let rows_read = rows.len();
+
+        let written = writer::write(
+            self,
+            &writer::Source {
+                source: importer.source(),
+                source_key: importer.source_key(),
+            },
+            rows,
+            labels,
+        )
+        .await?;
+        for e in written.errors {
+            errors.push(e);
+        }
+        self.save_progress(rows_read, &errors).await?;
+        if !self.options.dry_run {
+            tracing::info!(
+                "Imported {} new and {} updated tasks from {}",
+                written.result.created,
+                written.result.updated,
+                importer.source()
+            );
+        }
+        Ok(written.result)
+    }
+
+    async fn save_progress(&self, rows_read: usize, errors: &Errors) -> Result<()> {
+        sqlx::query(
+            "
+            UPDATE import_jobs
+            SET rows_read = $3, rows_failed = $4, errors = $5
+            WHERE project_id = $1 AND job_id = $2",
+        )
+        .bind(&self.project_id)
+        .bind(&self.job_id)
+        .bind(rows_read as i32)
+        .bind(errors.count as i32)
+        .bind(sqlx::types::Json(&errors.kept))
+        .execute(self.pool)
+        .await
+        .context("Failed to save import progress")?;
+        self.report("running", rows_read, errors.count).await;
+        Ok(())
+    }
+
+    /// Pushes the job's progress to the user who started the import.
+    async fn report(&self, status: &str, rows_read: usize, rows_failed: usize) {
+        JobProgress {
+            job_id: &self.job_id,
+            kind: IMPORT_JOB,
+            project_id: Some(&self.project_id),
+            status,
+            processed: rows_read,
+            failed: rows_failed,
+            total: None,
+        }
+        .send(&self.collab.messenger(), &self.user.email)
+        .await;
+    }
+}
+
+#[derive(Default)]
+struct Errors {
+    kept: Vec<RowError>,
+    count: usize,
+}
+
+impl Errors {
+    fn push(&mut self, error: RowError) {
+        self.count += 1;
+        if self.kept.len() < MAX_ROW_ERRORS {
+            self.kept.push(error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(name: &str, children: Vec<ImportedTask>) -> ImportedTask {
+        ImportedTask {
+            name: name.to_string(),
+            children,
+            ..ImportedTask::default()
+        }
+    }
+
+    #[test]
+    fn assign_path_ids_and_flatten() {
+        let mut tree = task(
+            "Plan",
+            vec![
+                task("A", vec![task("x", vec![])]),
+                task("B", vec![]),
+                task("A", vec![]),
+            ],
+        );
+        assign_path_ids(&mut tree, None, 0);
+        let rows: Vec<(Option<String>, String)> = flatten(tree)
+            .into_iter()
+            .map(|row| (row.parent, row.task.source_id))
+            .collect();
+        let some = |s: &str| Some(s.to_string());
+        assert_eq!(
+            rows,
+            vec![
+                (None, "Plan".to_string()),
+                (some("Plan"), "Plan/A".to_string()),
+                (some("Plan/A"), "Plan/A/x".to_string()),
+                (some("Plan"), "Plan/B".to_string()),
+                (some("Plan"), "Plan/A#1".to_string()),
+            ]
+        );
+    }
+}
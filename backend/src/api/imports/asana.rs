@@ -0,0 +1,464 @@
+//! Imports an Asana project, with its sections, tasks and subtasks, using the
+//! importing user's personal access token.
+
+use super::{
+    ImportJob, ImportOptions, ImportRequest, ImportRow, ImportedTask, Importer, RowError, start,
+};
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::Collab,
+        google::User,
+        model::{Deadline, Label, ProjectId},
+        verify_project_access,
+    },
+    postgres::PgPool,
+};
+use anyhow::Context as _;
+use axum::{Extension, Json, extract::Path};
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::{StreamExt as _, stream::BoxStream};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use std::{collections::VecDeque, sync::LazyLock};
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(reqwest::Client::new);
+
+const ASANA_API: &str = "https://app.asana.com/api/1.0";
+const PAGE_SIZE: &str = "100";
+const TASK_FIELDS: &str = "name,notes,completed,due_on,due_at,assignee.email,permalink_url,memberships.project.gid,memberships.section.gid,num_subtasks";
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AsanaImport {
+    /// A personal access token. It's only used for this import, and is only
+    /// stored until the import job succeeds.
+    token: String,
+    /// Id of the Asana project, e.g. 1204 in https://app.asana.com/0/1204/list.
+    project: String,
+    #[serde(flatten)]
+    pub(super) options: ImportOptions,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Page<T> {
+    data: T,
+    next_page: Option<NextPage>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct NextPage {
+    offset: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct AsanaProject {
+    name: String,
+    #[serde(default)]
+    notes: String,
+    permalink_url: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Section {
+    gid: String,
+    name: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct AsanaTask {
+    gid: String,
+    name: String,
+    #[serde(default)]
+    notes: String,
+    #[serde(default)]
+    completed: bool,
+    due_on: Option<NaiveDate>,
+    due_at: Option<DateTime<Utc>>,
+    assignee: Option<Assignee>,
+    permalink_url: Option<String>,
+    #[serde(default)]
+    memberships: Vec<Membership>,
+    #[serde(default)]
+    num_subtasks: usize,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Assignee {
+    email: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Membership {
+    project: Option<Gid>,
+    section: Option<Gid>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Gid {
+    gid: String,
+}
+
+/// Imports the Asana project under a task named after it. Re-importing the
+/// project updates the tasks imported before.
+#[tracing::instrument(skip(user, pool, collab, req))]
+pub(super) async fn import_asana_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+    Json(req): Json<AsanaImport>,
+) -> ApiResult<Json<ImportJob>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    start(pool, &collab, &user, &project_id, ImportRequest::Asana(req)).await
+}
+
+impl AsanaImport {
+    /// Fetches the project up front, so a bad token or project is rejected
+    /// before the import is queued.
+    pub(super) async fn importer(&self) -> ApiResult<AsanaImporter> {
+        if self.project.is_empty() || !self.project.chars().all(|c| c.is_ascii_digit()) {
+            return Err(bad_request_error(
+                "INVALID_PROJECT",
+                &format!("Invalid Asana project id: {}", self.project),
+            ));
+        }
+        let project: AsanaProject = get(
+            &self.token,
+            &format!("/projects/{}", self.project),
+            &[("opt_fields", "name,notes,permalink_url")],
+        )
+        .await?
+        .data;
+        let sections: Vec<Section> = get_all(
+            &self.token,
+            &format!("/projects/{}/sections", self.project),
+            "name",
+        )
+        .await?;
+        Ok(AsanaImporter {
+            token: self.token.clone(),
+            project_gid: self.project.clone(),
+            project,
+            sections,
+        })
+    }
+}
+
+/// Fetches the project's tasks page by page as they're imported, followed by
+/// their subtasks.
+pub(super) struct AsanaImporter {
+    token: String,
+    project_gid: String,
+    project: AsanaProject,
+    sections: Vec<Section>,
+}
+
+/// A page of tasks to fetch: the project's tasks, or a task's subtasks.
+struct Fetch {
+    parent: Option<String>,
+    offset: Option<String>,
+}
+
+struct Reader<'a> {
+    importer: &'a AsanaImporter,
+    rows: VecDeque<Result<ImportRow, RowError>>,
+    fetches: VecDeque<Fetch>,
+}
+
+impl Importer for AsanaImporter {
+    fn source(&self) -> &'static str {
+        "asana"
+    }
+
+    fn source_key(&self) -> String {
+        self.project_gid.clone()
+    }
+
+    fn rows(&mut self) -> BoxStream<'_, Result<ImportRow, RowError>> {
+        let reader = Reader {
+            importer: self,
+            rows: self.initial_rows().into_iter().map(Ok).collect(),
+            fetches: VecDeque::from([Fetch {
+                parent: None,
+                offset: None,
+            }]),
+        };
+        futures::stream::unfold(reader, |mut reader| async move {
+            loop {
+                if let Some(row) = reader.rows.pop_front() {
+                    return Some((row, reader));
+                }
+                let fetch = reader.fetches.pop_front()?;
+                reader.fetch(fetch).await;
+            }
+        })
+        .boxed()
+    }
+
+    fn labels(&self) -> Vec<Label> {
+        Vec::new()
+    }
+}
+
+impl AsanaImporter {
+    /// The project and, if nesting tasks under them, its sections.
+    fn initial_rows(&self) -> Vec<ImportRow> {
+        let mut rows = vec![ImportRow {
+            parent: None,
+            task: ImportedTask {
+                source_id: self.project_gid.clone(),
+                name: self.project.name.clone(),
+                desc: Some(self.project.notes.clone()),
+                url: self.project.permalink_url.clone(),
+                ..ImportedTask::default()
+            },
+        }];
+        if self.nest_sections() {
+            rows.extend(self.sections.iter().map(|section| ImportRow {
+                parent: Some(self.project_gid.clone()),
+                task: ImportedTask {
+                    source_id: section.gid.clone(),
+                    name: section.name.clone(),
+                    ..ImportedTask::default()
+                },
+            }));
+        }
+        rows
+    }
+
+    /// Projects without sections of their own have a single, default
+    /// section, which is skipped.
+    fn nest_sections(&self) -> bool {
+        self.sections.len() > 1
+    }
+
+    /// The source id of the task's section or, if not nesting tasks under
+    /// sections, of the project.
+    fn task_parent(&self, task: &AsanaTask) -> String {
+        let section = task
+            .memberships
+            .iter()
+            .find(|m| {
+                m.project
+                    .as_ref()
+                    .is_some_and(|p| p.gid == self.project_gid)
+            })
+            .and_then(|m| m.section.as_ref());
+        match section {
+            Some(section) if self.nest_sections() => section.gid.clone(),
+            _ => self.project_gid.clone(),
+        }
+    }
+}
+
+impl Reader<'_> {
+    /// Fetches a page of tasks, queueing their rows along with the next page and their subtasks.
+    /// Pages that can't be fetched are reported as row errors.
+    async fn fetch(&mut self, fetch: Fetch) {
+        let path = match &fetch.parent {
+            Some(parent) => format!("/tasks/{parent}/subtasks"),
+            None => format!("/projects/{}/tasks", self.importer.project_gid),
+        };
+        let page: Page<Vec<AsanaTask>> = match get_page(
+            &self.importer.token,
+            &path,
+            TASK_FIELDS,
+            fetch.offset.as_deref(),
+        )
+        .await
+        {
+            Ok(page) => page,
+            Err(e) => {
+                self.rows.push_back(Err(RowError {
+                    row: path,
+                    message: e.message(),
+                }));
+                return;
+            }
+        };
+        if let Some(next) = page.next_page {
+            self.fetches.push_front(Fetch {
+                parent: fetch.parent.clone(),
+                offset: Some(next.offset),
+            });
+        }
+        for task in page.data {
+            if task.num_subtasks > 0 {
+                self.fetches.push_back(Fetch {
+                    parent: Some(task.gid.clone()),
+                    offset: None,
+                });
+            }
+            let parent = match &fetch.parent {
+                Some(parent) => parent.clone(),
+                None => self.importer.task_parent(&task),
+            };
+            self.rows.push_back(Ok(ImportRow {
+                parent: Some(parent),
+                task: map_task(task),
+            }));
+        }
+    }
+}
+
+fn map_task(task: AsanaTask) -> ImportedTask {
+    ImportedTask {
+        source_id: task.gid,
+        name: task.name,
+        desc: Some(task.notes),
+        url: task.permalink_url,
+        done: task.completed,
+        assignee: task
+            .assignee
+            .and_then(|a| a.email)
+            .map(|email| email.to_lowercase()),
+        deadline: match (task.due_at, task.due_on) {
+            (Some(due_at), _) => Some(Deadline::DateTime {
+                millis: due_at.timestamp_millis(),
+                time_zone: "UTC".to_string(),
+            }),
+            (None, Some(date)) => Some(Deadline::Date { date }),
+            (None, None) => None,
+        },
+        ..ImportedTask::default()
+    }
+}
+
+async fn get_all<T: DeserializeOwned>(token: &str, path: &str, fields: &str) -> ApiResult<Vec<T>> {
+    let mut items = Vec::new();
+    let mut offset = None;
+    loop {
+        let page: Page<Vec<T>> = get_page(token, path, fields, offset.as_deref()).await?;
+        items.extend(page.data);
+        match page.next_page {
+            Some(next) => offset = Some(next.offset),
+            None => return Ok(items),
+        }
+    }
+}
+
+async fn get_page<T: DeserializeOwned>(
+    token: &str,
+    path: &str,
+    fields: &str,
+    offset: Option<&str>,
+) -> ApiResult<Page<T>> {
+    let mut query = vec![("opt_fields", fields), ("limit", PAGE_SIZE)];
+    if let Some(offset) = offset {
+        query.push(("offset", offset));
+    }
+    get(token, path, &query).await
+}
+
+async fn get<T: DeserializeOwned>(
+    token: &str,
+    path: &str,
+    query: &[(&str, &str)],
+) -> ApiResult<Page<T>> {
+    let res = CLIENT
+        .get(format!("{ASANA_API}{path}"))
+        .bearer_auth(token)
+        .query(query)
+        .send()
+        .await
+        .context("Failed to reach Asana")?;
+    match res.status() {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(bad_request_error(
+            "ASANA_UNAUTHORIZED",
+            "Asana rejected the access token",
+        )),
+        StatusCode::NOT_FOUND => Err(bad_request_error(
+            "ASANA_NOT_FOUND",
+            &format!("Asana couldn't find {path}"),
+        )),
+        _ => Ok(res
+            .error_for_status()
+            .context("Asana request failed")?
+            .json()
+            .await
+            .context("Failed to decode Asana response")?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(gid: &str, section: Option<&str>) -> AsanaTask {
+        serde_json::from_value(serde_json::json!({
+            "gid": gid,
+            "name": format!("Task {gid}"),
+            "notes": "",
+            "completed": gid == "2",
+            "due_on": "2025-10-01",
+            "assignee": {"email": "Alice@Koso.app"},
+            "memberships": [
+                {"project": {"gid": "other"}, "section": {"gid": "x"}},
+                {"project": {"gid": "p"}, "section": section.map(|s| serde_json::json!({"gid": s}))},
+            ],
+        }))
+        .unwrap()
+    }
+
+    fn importer(sections: &[&str]) -> AsanaImporter {
+        AsanaImporter {
+            token: "token".to_string(),
+            project_gid: "p".to_string(),
+            project: AsanaProject {
+                name: "Roadmap".to_string(),
+                notes: "Q4".to_string(),
+                permalink_url: Some("https://app.asana.com/0/p".to_string()),
+            },
+            sections: sections
+                .iter()
+                .map(|gid| Section {
+                    gid: gid.to_string(),
+                    name: format!("Section {gid}"),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn nests_tasks_under_sections() {
+        let importer = importer(&["a", "b", "c"]);
+        assert_eq!(
+            importer
+                .initial_rows()
+                .iter()
+                .map(|r| (r.parent.as_deref(), r.task.name.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (None, "Roadmap"),
+                (Some("p"), "Section a"),
+                (Some("p"), "Section b"),
+                (Some("p"), "Section c"),
+            ]
+        );
+        assert_eq!(importer.task_parent(&task("1", Some("b"))), "b");
+        assert_eq!(importer.task_parent(&task("5", None)), "p");
+    }
+
+    #[test]
+    fn skips_default_section() {
+        let importer = importer(&["a"]);
+        assert_eq!(importer.initial_rows().len(), 1);
+        assert_eq!(importer.task_parent(&task("1", Some("a"))), "p");
+    }
+
+    #[test]
+    fn map_task_fields() {
+        let imported = map_task(task("2", None));
+        assert_eq!(imported.source_id, "2");
+        assert_eq!(imported.name, "Task 2");
+        assert!(imported.done);
+        assert_eq!(imported.assignee.as_deref(), Some("alice@koso.app"));
+        assert_eq!(
+            imported.deadline,
+            Some(Deadline::Date {
+                date: NaiveDate::from_ymd_opt(2025, 10, 1).unwrap()
+            })
+        );
+    }
+}
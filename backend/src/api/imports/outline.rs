@@ -0,0 +1,346 @@
+//! Imports plain-text outlines written in Markdown or Org mode. Headings and
+//! indented list items give the hierarchy, while checkboxes and TODO/DONE
+//! keywords give the status.
+
+use super::{
+    DEFAULT_LABEL_COLOR, ImportJob, ImportOptions, ImportRequest, ImportedTask, TreeImporter,
+    assign_path_ids, nest, start,
+};
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::Collab,
+        google::User,
+        model::{Deadline, Label, ProjectId},
+        verify_project_access,
+    },
+    postgres::PgPool,
+};
+use axum::{Extension, Json, extract::Path};
+use chrono::NaiveDate;
+use regex::Regex;
+use std::sync::LazyLock;
+
+static MARKDOWN_HEADING: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(#{1,6})\s+(.*)$").unwrap());
+static ORG_HEADING: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(\*+)\s+(.*)$").unwrap());
+static LIST_ITEM: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\s*)(?:[-*+]|\d+[.)])\s+(?:\[([ xX-])\]\s*)?(.*)$").unwrap());
+static KEYWORD: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(TODO|DONE|NEXT|WAITING|CANCELLED|CANCELED)(?:\s+(.*))?$").unwrap()
+});
+static ORG_PRIORITY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\[#[A-Za-z0-9]\]\s*").unwrap());
+static ORG_TAGS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s+:((?:[\w@#%]+:)+)$").unwrap());
+static ORG_DEADLINE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"DEADLINE:\s*<(\d{4}-\d{2}-\d{2})").unwrap());
+static ORG_TITLE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^#\+TITLE:\s*(.*)$").unwrap());
+
+/// Headings rank above list items, however deeply the items are indented.
+const HEADING_DEPTH: i32 = -100;
+const TAB_WIDTH: usize = 4;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OutlineImport {
+    content: String,
+    #[serde(default)]
+    format: Format,
+    /// Name of the task containing the outline. Defaults to the outline's
+    /// title or, if it has a single top-level item, to that item.
+    name: Option<String>,
+    #[serde(flatten)]
+    pub(super) options: ImportOptions,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+enum Format {
+    #[default]
+    Markdown,
+    Org,
+}
+
+/// Imports the outline under a task named after it. Outline items have no
+/// ids, so re-importing an outline of the same name matches tasks by their
+/// path of names.
+#[tracing::instrument(skip(user, pool, collab, req))]
+pub(super) async fn import_outline_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+    Json(req): Json<OutlineImport>,
+) -> ApiResult<Json<ImportJob>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    start(
+        pool,
+        &collab,
+        &user,
+        &project_id,
+        ImportRequest::Outline(req),
+    )
+    .await
+}
+
+impl OutlineImport {
+    pub(super) fn importer(&self) -> ApiResult<TreeImporter> {
+        let (mut tree, labels) = parse_outline(&self.content, self.format, self.name.as_deref())?;
+        assign_path_ids(&mut tree, None, 0);
+        Ok(TreeImporter::new(
+            "outline",
+            tree.name.clone(),
+            tree,
+            labels,
+        ))
+    }
+}
+
+fn parse_outline(
+    content: &str,
+    format: Format,
+    name: Option<&str>,
+) -> ApiResult<(ImportedTask, Vec<Label>)> {
+    let mut title = None;
+    let mut desc = Vec::new();
+    let mut items: Vec<(i32, ImportedTask)> = Vec::new();
+    let mut in_drawer = false;
+    for line in content.lines() {
+        let line = line.replace('\t', &" ".repeat(TAB_WIDTH));
+        if format == Format::Org {
+            // Skip property and logbook drawers.
+            let trimmed = line.trim();
+            if in_drawer {
+                in_drawer = !trimmed.eq_ignore_ascii_case(":END:");
+                continue;
+            }
+            if trimmed.starts_with(':') && trimmed.ends_with(':') && trimmed.len() > 1 {
+                in_drawer = true;
+                continue;
+            }
+            if let Some(captures) = ORG_TITLE.captures(&line) {
+                title = Some(captures[1].trim().to_string());
+                continue;
+            }
+            if let Some(captures) = ORG_DEADLINE.captures(&line) {
+                if let Some((_, item)) = items.last_mut() {
+                    item.deadline = NaiveDate::parse_from_str(&captures[1], "%Y-%m-%d")
+                        .ok()
+                        .map(|date| Deadline::Date { date });
+                }
+                continue;
+            }
+            if line.trim_start().starts_with("#+") {
+                continue;
+            }
+        }
+
+        let heading = match format {
+            Format::Markdown => MARKDOWN_HEADING.captures(&line),
+            Format::Org => ORG_HEADING.captures(&line),
+        };
+        if let Some(captures) = heading {
+            let depth = HEADING_DEPTH + captures[1].len() as i32;
+            items.push((depth, parse_item(&captures[2], None, format)));
+        } else if let Some(captures) = LIST_ITEM.captures(&line) {
+            let depth = captures[1].len() as i32;
+            let checkbox = captures.get(2).map(|c| c.as_str());
+            items.push((depth, parse_item(&captures[3], checkbox, format)));
+        } else if !line.trim().is_empty() {
+            match items.last_mut() {
+                Some((_, item)) => {
+                    let item_desc = item.desc.get_or_insert_with(String::new);
+                    if !item_desc.is_empty() {
+                        item_desc.push('\n');
+                    }
+                    item_desc.push_str(line.trim());
+                }
+                None => desc.push(line.trim().to_string()),
+            }
+        }
+    }
+    items.retain(|(_, item)| !item.name.is_empty());
+    if items.is_empty() {
+        return Err(bad_request_error(
+            "EMPTY_OUTLINE",
+            "The outline has no headings or list items",
+        ));
+    }
+
+    let mut labels: Vec<Label> = Vec::new();
+    for (_, item) in &items {
+        for label in &item.labels {
+            if !labels.iter().any(|l| &l.name == label) {
+                labels.push(Label {
+                    name: label.clone(),
+                    color: DEFAULT_LABEL_COLOR.to_string(),
+                });
+            }
+        }
+    }
+
+    let mut children = nest(items);
+    let root = match (name.or(title.as_deref()), children.len()) {
+        (Some(name), _) => ImportedTask {
+            name: name.to_string(),
+            children,
+            ..ImportedTask::default()
+        },
+        (None, 1) => children.remove(0),
+        (None, _) => ImportedTask {
+            name: "Outline".to_string(),
+            children,
+            ..ImportedTask::default()
+        },
+    };
+    Ok((
+        ImportedTask {
+            desc: root.desc.or(Some(desc.join("\n"))),
+            ..root
+        },
+        labels,
+    ))
+}
+
+fn parse_item(text: &str, checkbox: Option<&str>, format: Format) -> ImportedTask {
+    let mut text = text.trim().to_string();
+    let mut done = matches!(checkbox, Some("x" | "X"));
+    if let Some(captures) = KEYWORD.captures(&text) {
+        done |= matches!(&captures[1], "DONE" | "CANCELLED" | "CANCELED");
+        text = captures.get(2).map_or("", |rest| rest.as_str()).to_string();
+    }
+    let mut labels = Vec::new();
+    if format == Format::Org {
+        text = ORG_PRIORITY.replace(&text, "").to_string();
+        if let Some(captures) = ORG_TAGS.captures(&text) {
+            labels = captures[1]
+                .split(':')
+                .filter(|tag| !tag.is_empty())
+                .map(String::from)
+                .collect();
+            let start = captures.get(0).map_or(text.len(), |tags| tags.start());
+            text = text[..start].to_string();
+        }
+    }
+    ImportedTask {
+        name: text.trim().to_string(),
+        done,
+        labels,
+        ..ImportedTask::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summarize(task: &ImportedTask, depth: usize, out: &mut Vec<String>) {
+        out.push(format!(
+            "{}{}{}",
+            "  ".repeat(depth),
+            task.name,
+            if task.done { " (done)" } else { "" }
+        ));
+        for child in &task.children {
+            summarize(child, depth + 1, out);
+        }
+    }
+
+    fn outline(task: &ImportedTask) -> Vec<String> {
+        let mut out = Vec::new();
+        summarize(task, 0, &mut out);
+        out
+    }
+
+    #[test]
+    fn parse_markdown_outline() {
+        let content = "\
+Notes about the plan.
+
+# Launch
+## Website
+- [x] Draft copy
+  Needs legal review.
+- [ ] Publish
+    1. DONE Build
+    2. Deploy
+## Blog
+* TODO Write post
+";
+        let (root, labels) = parse_outline(content, Format::Markdown, None).unwrap();
+        assert_eq!(
+            outline(&root),
+            vec![
+                "Launch",
+                "  Website",
+                "    Draft copy (done)",
+                "    Publish",
+                "      Build (done)",
+                "      Deploy",
+                "  Blog",
+                "    Write post",
+            ]
+        );
+        assert_eq!(root.desc.as_deref(), Some("Notes about the plan."));
+        assert_eq!(
+            root.children[0].children[0].desc.as_deref(),
+            Some("Needs legal review.")
+        );
+        assert!(labels.is_empty());
+    }
+
+    #[test]
+    fn parse_org_outline() {
+        let content = "\
+#+TITLE: Roadmap
+* TODO [#A] Search :backend:perf:
+  DEADLINE: <2025-11-03 Mon>
+  :PROPERTIES:
+  :ID: 123
+  :END:
+** DONE Index tasks :backend:
+** CANCELLED Fuzzy matching
+* Mobile
+  - [X] Prototype
+  - [ ] Beta
+";
+        let (root, labels) = parse_outline(content, Format::Org, None).unwrap();
+        assert_eq!(
+            outline(&root),
+            vec![
+                "Roadmap",
+                "  Search",
+                "    Index tasks (done)",
+                "    Fuzzy matching (done)",
+                "  Mobile",
+                "    Prototype (done)",
+                "    Beta",
+            ]
+        );
+        let search = &root.children[0];
+        assert_eq!(search.labels, vec!["backend", "perf"]);
+        assert_eq!(
+            search.deadline,
+            Some(Deadline::Date {
+                date: NaiveDate::from_ymd_opt(2025, 11, 3).unwrap()
+            })
+        );
+        assert_eq!(search.desc, None);
+        assert_eq!(
+            labels.iter().map(|l| l.name.as_str()).collect::<Vec<_>>(),
+            vec!["backend", "perf"]
+        );
+    }
+
+    #[test]
+    fn parse_outline_names_container() {
+        let (root, _) = parse_outline("- a\n- b\n", Format::Markdown, None).unwrap();
+        assert_eq!(outline(&root), vec!["Outline", "  a", "  b"]);
+
+        let (root, _) = parse_outline("- a\n- b\n", Format::Markdown, Some("Plan")).unwrap();
+        assert_eq!(outline(&root), vec!["Plan", "  a", "  b"]);
+
+        assert!(parse_outline("Just prose.\n", Format::Markdown, None).is_err());
+    }
+}
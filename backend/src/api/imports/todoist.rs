@@ -0,0 +1,446 @@
+//! Imports a Todoist project from its CSV export (project menu > Export as a template > Download as CSV).
+//! Sections become tasks containing the section's tasks, indentation gives
+//! subtasks and comments are added to their task's description.
+
+use super::{
+    DEFAULT_LABEL_COLOR, ImportJob, ImportOptions, ImportRequest, ImportRow, ImportedTask,
+    Importer, RowError, path_id, start,
+};
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::Collab,
+        google::User,
+        model::{Deadline, Label, ProjectId},
+        verify_project_access,
+    },
+    postgres::PgPool,
+};
+use axum::{Extension, Json, extract::Path};
+use chrono::NaiveDate;
+use futures::{StreamExt as _, stream::BoxStream};
+use std::collections::HashMap;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TodoistImport {
+    csv: String,
+    /// Name of the task containing the project, usually the project's name.
+    name: String,
+    /// Emails of collaborators, by the name shown in the export's RESPONSIBLE column.
+    /// Exports don't include emails, so collaborators without one are left unassigned.
+    #[serde(default)]
+    member_emails: HashMap<String, String>,
+    #[serde(flatten)]
+    pub(super) options: ImportOptions,
+}
+
+/// Imports the Todoist project under a task with the given name. Exports have
+/// no task ids, so re-importing under the same name matches tasks by their
+/// path of names.
+#[tracing::instrument(skip(user, pool, collab, req))]
+pub(super) async fn import_todoist_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+    Json(req): Json<TodoistImport>,
+) -> ApiResult<Json<ImportJob>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    start(
+        pool,
+        &collab,
+        &user,
+        &project_id,
+        ImportRequest::Todoist(req),
+    )
+    .await
+}
+
+impl TodoistImport {
+    pub(super) fn importer(&self) -> ApiResult<TodoistImporter> {
+        TodoistImporter::new(&self.csv, self.name.clone(), self.member_emails.clone())
+    }
+}
+
+/// Positions of the export's columns.
+struct Columns {
+    kind: usize,
+    content: usize,
+    indent: usize,
+    description: Option<usize>,
+    responsible: Option<usize>,
+    date: Option<usize>,
+    deadline: Option<usize>,
+}
+
+/// Reads the export's records as they're imported. Sections are at depth 0
+/// and tasks at their INDENT, with each containing the deeper records that follow.
+pub(super) struct TodoistImporter {
+    name: String,
+    member_emails: HashMap<String, String>,
+    columns: Columns,
+    records: Vec<Vec<String>>,
+    /// Labels seen in the records read so far.
+    labels: Vec<Label>,
+}
+
+impl TodoistImporter {
+    fn new(
+        csv: &str,
+        name: String,
+        member_emails: HashMap<String, String>,
+    ) -> ApiResult<TodoistImporter> {
+        let mut records = parse_csv(csv).map_err(|e| bad_request_error("INVALID_CSV", &e))?;
+        if records.is_empty() {
+            return Err(bad_request_error("INVALID_CSV", "The export is empty"));
+        }
+        let header = records.remove(0);
+        let column = |name: &str| header.iter().position(|h| h.trim() == name);
+        let (Some(kind), Some(content), Some(indent)) =
+            (column("TYPE"), column("CONTENT"), column("INDENT"))
+        else {
+            return Err(bad_request_error(
+                "INVALID_CSV",
+                "Expected TYPE, CONTENT and INDENT columns",
+            ));
+        };
+        let columns = Columns {
+            kind,
+            content,
+            indent,
+            description: column("DESCRIPTION"),
+            responsible: column("RESPONSIBLE"),
+            date: column("DATE"),
+            deadline: column("DEADLINE"),
+        };
+        Ok(TodoistImporter {
+            name,
+            member_emails,
+            columns,
+            records,
+            labels: Vec::new(),
+        })
+    }
+
+    fn read(&mut self) -> impl Iterator<Item = Result<ImportRow, RowError>> + Send + '_ {
+        let root = ImportedTask {
+            source_id: path_id(None, &self.name, 0),
+            name: self.name.clone(),
+            ..ImportedTask::default()
+        };
+        let root_id = root.source_id.clone();
+        let columns = &self.columns;
+        let member_emails = &self.member_emails;
+        let labels = &mut self.labels;
+        let mut records = std::mem::take(&mut self.records)
+            .into_iter()
+            .enumerate()
+            .peekable();
+        // The depth and source id of each task containing the next.
+        let mut parents: Vec<(i32, String)> = Vec::new();
+        let mut occurrences: HashMap<(String, String), usize> = HashMap::new();
+
+        let rows = std::iter::from_fn(move || {
+            loop {
+                let (index, record) = records.next()?;
+                // Records are numbered from the header, as in a spreadsheet.
+                let row = format!("row {}", index + 2);
+                let (depth, mut task) = match field(&record, Some(columns.kind)).as_str() {
+                    "section" => (
+                        0,
+                        ImportedTask {
+                            name: field(&record, Some(columns.content)),
+                            ..ImportedTask::default()
+                        },
+                    ),
+                    "task" => {
+                        let indent = field(&record, Some(columns.indent));
+                        let depth = match indent.parse::<i32>() {
+                            Ok(depth) if depth >= 1 => depth,
+                            _ => {
+                                return Some(Err(RowError {
+                                    row,
+                                    message: format!("Invalid indent: {indent}"),
+                                }));
+                            }
+                        };
+                        (depth, map_task(&record, columns, member_emails, labels))
+                    }
+                    // Blank separators, and notes following a skipped record.
+                    _ => continue,
+                };
+                while let Some((_, note)) = records.peek() {
+                    if field(note, Some(columns.kind)) != "note" {
+                        break;
+                    }
+                    let desc = task.desc.get_or_insert_with(String::new);
+                    if !desc.is_empty() {
+                        desc.push_str("\n\n");
+                    }
+                    desc.push_str(&field(note, Some(columns.content)));
+                    records.next();
+                }
+                if task.name.is_empty() {
+                    return Some(Err(RowError {
+                        row,
+                        message: "Missing content".to_string(),
+                    }));
+                }
+
+                while parents.last().is_some_and(|(d, _)| *d >= depth) {
+                    parents.pop();
+                }
+                let parent = parents.last().map_or(&root_id, |(_, id)| id).clone();
+                let occurrence = occurrences
+                    .entry((parent.clone(), task.name.clone()))
+                    .or_default();
+                task.source_id = path_id(Some(&parent), &task.name, *occurrence);
+                *occurrence += 1;
+                parents.push((depth, task.source_id.clone()));
+                return Some(Ok(ImportRow {
+                    parent: Some(parent),
+                    task,
+                }));
+            }
+        });
+        std::iter::once(Ok(ImportRow {
+            parent: None,
+            task: root,
+        }))
+        .chain(rows)
+    }
+}
+
+impl Importer for TodoistImporter {
+    fn source(&self) -> &'static str {
+        "todoist"
+    }
+
+    fn source_key(&self) -> String {
+        self.name.clone()
+    }
+
+    fn rows(&mut self) -> BoxStream<'_, Result<ImportRow, RowError>> {
+        futures::stream::iter(self.read()).boxed()
+    }
+
+    fn labels(&self) -> Vec<Label> {
+        self.labels.clone()
+    }
+}
+
+fn field(record: &[String], index: Option<usize>) -> String {
+    index
+        .and_then(|i| record.get(i))
+        .map(|f| f.trim().to_string())
+        .unwrap_or_default()
+}
+
+fn map_task(
+    record: &[String],
+    columns: &Columns,
+    member_emails: &HashMap<String, String>,
+    labels: &mut Vec<Label>,
+) -> ImportedTask {
+    let (name, task_labels) = parse_content(&field(record, Some(columns.content)));
+    for label in &task_labels {
+        if !labels.iter().any(|l| &l.name == label) {
+            labels.push(Label {
+                name: label.clone(),
+                color: DEFAULT_LABEL_COLOR.to_string(),
+            });
+        }
+    }
+    let due = match field(record, columns.deadline) {
+        d if d.is_empty() => field(record, columns.date),
+        d => d,
+    };
+    ImportedTask {
+        name,
+        desc: Some(field(record, columns.description)),
+        assignee: responsible_email(&field(record, columns.responsible), member_emails),
+        deadline: parse_date(&due).map(|date| Deadline::Date { date }),
+        labels: task_labels,
+        ..ImportedTask::default()
+    }
+}
+
+/// Separates the task's name from its labels, written inline as @label.
+/// Uncompletable tasks are prefixed with "* ", which is dropped.
+fn parse_content(content: &str) -> (String, Vec<String>) {
+    let content = content.strip_prefix("* ").unwrap_or(content);
+    let mut labels = Vec::new();
+    let mut words = Vec::new();
+    for word in content.split_whitespace() {
+        match word.strip_prefix('@') {
+            Some(label) if !label.is_empty() => {
+                if !labels.iter().any(|l| l == label) {
+                    labels.push(label.to_string());
+                }
+            }
+            _ => words.push(word),
+        }
+    }
+    (words.join(" "), labels)
+}
+
+/// Responsible collaborators are written as "Name (id)".
+fn responsible_email(responsible: &str, member_emails: &HashMap<String, String>) -> Option<String> {
+    if responsible.is_empty() {
+        return None;
+    }
+    let name = responsible
+        .rsplit_once(" (")
+        .map_or(responsible, |(name, _)| name);
+    member_emails
+        .get(responsible)
+        .or_else(|| member_emails.get(name))
+        .map(|email| email.trim().to_lowercase())
+}
+
+/// Dates are free text, e.g. "every monday", so only those starting with a
+/// calendar date are imported.
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date.get(..10)?, "%Y-%m-%d").ok()
+}
+
+/// Splits CSV into records, allowing quoted fields to contain commas,
+/// escaped quotes and newlines.
+fn parse_csv(csv: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = csv.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => record.push(std::mem::take(&mut field)),
+            (false, '\r') => {}
+            (false, '\n') => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("Unterminated quoted field".to_string());
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT: &str = "\u{feff}TYPE,CONTENT,DESCRIPTION,PRIORITY,INDENT,AUTHOR,RESPONSIBLE,DATE,DATE_LANG,TIMEZONE
+task,Inbox zero @admin,,4,1,Alice (1),,,en,UTC
+,,,,,,,,,
+section,Launch,,,,,,,,
+task,\"Plan, then ship @work @urgent\",\"Line one
+line two\",1,1,Alice (1),Bob (2),2025-10-01,en,UTC
+task,Write post,,1,2,Alice (1),Carol (3),every monday,en,UTC
+note,\"Use the \"\"new\"\" template\",,,,Alice (1),,,,
+task,* Checklist,,1,1,Alice (1),,,en,UTC
+";
+
+    fn read(csv: &str) -> (Vec<ImportRow>, Vec<RowError>, Vec<Label>) {
+        let emails = HashMap::from([("Bob".to_string(), "Bob@koso.app".to_string())]);
+        let mut importer = TodoistImporter::new(csv, "Marketing".to_string(), emails).unwrap();
+        let (rows, errors): (Vec<_>, Vec<_>) = importer.read().partition(Result::is_ok);
+        (
+            rows.into_iter().map(Result::unwrap).collect(),
+            errors.into_iter().map(Result::unwrap_err).collect(),
+            importer.labels(),
+        )
+    }
+
+    #[test]
+    fn read_nests_sections_and_indents() {
+        let (rows, errors, labels) = read(EXPORT);
+        assert!(errors.is_empty());
+        assert_eq!(
+            rows.iter()
+                .map(|r| (r.parent.as_deref(), r.task.source_id.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (None, "Marketing"),
+                (Some("Marketing"), "Marketing/Inbox zero"),
+                (Some("Marketing"), "Marketing/Launch"),
+                (Some("Marketing/Launch"), "Marketing/Launch/Plan, then ship"),
+                (
+                    Some("Marketing/Launch/Plan, then ship"),
+                    "Marketing/Launch/Plan, then ship/Write post"
+                ),
+                (Some("Marketing/Launch"), "Marketing/Launch/Checklist"),
+            ]
+        );
+        assert_eq!(rows[1].task.labels, vec!["admin"]);
+
+        let plan = &rows[3].task;
+        assert_eq!(plan.name, "Plan, then ship");
+        assert_eq!(plan.desc.as_deref(), Some("Line one\nline two"));
+        assert_eq!(plan.assignee.as_deref(), Some("bob@koso.app"));
+        assert_eq!(plan.labels, vec!["work", "urgent"]);
+        assert_eq!(
+            plan.deadline,
+            Some(Deadline::Date {
+                date: NaiveDate::from_ymd_opt(2025, 10, 1).unwrap()
+            })
+        );
+
+        let post = &rows[4].task;
+        assert_eq!(post.assignee, None);
+        assert_eq!(post.deadline, None);
+        assert_eq!(post.desc.as_deref(), Some("Use the \"new\" template"));
+
+        assert_eq!(
+            labels.iter().map(|l| l.name.as_str()).collect::<Vec<_>>(),
+            vec!["admin", "work", "urgent"]
+        );
+    }
+
+    #[test]
+    fn read_reports_invalid_rows() {
+        let (rows, errors, _) =
+            read("TYPE,CONTENT,INDENT\ntask,a,1\ntask,,1\ntask,b,x\ntask,a,1\n");
+        assert_eq!(
+            rows.iter()
+                .map(|r| r.task.source_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Marketing", "Marketing/a", "Marketing/a#1"]
+        );
+        assert_eq!(
+            errors,
+            vec![
+                RowError {
+                    row: "row 3".to_string(),
+                    message: "Missing content".to_string()
+                },
+                RowError {
+                    row: "row 4".to_string(),
+                    message: "Invalid indent: x".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn new_rejects_invalid_csv() {
+        let new = |csv| TodoistImporter::new(csv, "x".to_string(), HashMap::new());
+        assert!(new("TYPE,CONTENT\ntask,a\n").is_err());
+        assert!(new("TYPE,CONTENT,INDENT\ntask,\"a,1\n").is_err());
+    }
+}
@@ -0,0 +1,567 @@
+//! Imports a Trello board from its JSON export (Menu > Print, export and share > Export as JSON).
+
+use super::{
+    DEFAULT_LABEL_COLOR, ImportJob, ImportOptions, ImportRequest, ImportedTask, TreeImporter, start,
+};
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::Collab,
+        google::User,
+        model::{Deadline, Label, ProjectId},
+        verify_project_access,
+    },
+    postgres::PgPool,
+};
+use axum::{Extension, Json, extract::Path};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TrelloImport {
+    board: Board,
+    #[serde(default)]
+    list_mode: ListMode,
+    /// Statuses for lists, by list name, when lists are mapped to statuses.
+    /// Other lists use the status of the same name, ignoring case.
+    #[serde(default)]
+    list_statuses: HashMap<String, String>,
+    /// Emails of board members, by Trello username or member id.
+    /// Trello exports don't include emails, so members without one are left unassigned.
+    #[serde(default)]
+    member_emails: HashMap<String, String>,
+    /// Import archived lists and cards too.
+    #[serde(default)]
+    include_archived: bool,
+    #[serde(flatten)]
+    pub(super) options: ImportOptions,
+}
+
+/// How the board's lists are represented.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+enum ListMode {
+    /// Each list becomes a task containing its cards.
+    #[default]
+    Parents,
+    /// Each list maps to a status, set on its cards.
+    Statuses,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Board {
+    id: String,
+    name: String,
+    #[serde(default)]
+    desc: String,
+    url: Option<String>,
+    #[serde(default)]
+    lists: Vec<List>,
+    #[serde(default)]
+    cards: Vec<Card>,
+    #[serde(default)]
+    labels: Vec<BoardLabel>,
+    #[serde(default)]
+    checklists: Vec<Checklist>,
+    #[serde(default)]
+    members: Vec<Member>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct List {
+    id: String,
+    name: String,
+    #[serde(default)]
+    closed: bool,
+    #[serde(default)]
+    pos: f64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Card {
+    id: String,
+    name: String,
+    #[serde(default)]
+    desc: String,
+    id_list: String,
+    #[serde(default)]
+    closed: bool,
+    #[serde(default)]
+    pos: f64,
+    due: Option<DateTime<Utc>>,
+    #[serde(default)]
+    due_complete: bool,
+    short_url: Option<String>,
+    #[serde(default)]
+    id_members: Vec<String>,
+    #[serde(default)]
+    id_labels: Vec<String>,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Attachment {
+    name: String,
+    url: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct BoardLabel {
+    id: String,
+    #[serde(default)]
+    name: String,
+    color: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Checklist {
+    id: String,
+    id_card: String,
+    name: String,
+    #[serde(default)]
+    pos: f64,
+    #[serde(default)]
+    check_items: Vec<CheckItem>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CheckItem {
+    id: String,
+    name: String,
+    state: String,
+    #[serde(default)]
+    pos: f64,
+    id_member: Option<String>,
+    due: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Member {
+    id: String,
+    username: String,
+}
+
+/// Imports the board under a task named after it. Re-importing the board
+/// updates the tasks imported before.
+#[tracing::instrument(skip(user, pool, collab, req))]
+pub(super) async fn import_trello_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+    Json(req): Json<TrelloImport>,
+) -> ApiResult<Json<ImportJob>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    start(
+        pool,
+        &collab,
+        &user,
+        &project_id,
+        ImportRequest::Trello(Box::new(req)),
+    )
+    .await
+}
+
+impl TrelloImport {
+    /// Maps the board using the project's statuses.
+    pub(super) fn importer(&self, statuses: &[String]) -> ApiResult<TreeImporter> {
+        let (tree, labels) = map_board(self, statuses)?;
+        Ok(TreeImporter::new(
+            "trello",
+            self.board.id.clone(),
+            tree,
+            labels,
+        ))
+    }
+}
+
+fn map_board(req: &TrelloImport, statuses: &[String]) -> ApiResult<(ImportedTask, Vec<Label>)> {
+    let board = &req.board;
+    let labels: Vec<(&str, Label)> = board
+        .labels
+        .iter()
+        .filter_map(|l| to_label(l).map(|label| (l.id.as_str(), label)))
+        .collect();
+    let label_names: HashMap<&str, &str> = labels
+        .iter()
+        .map(|(id, label)| (*id, label.name.as_str()))
+        .collect();
+    let emails: HashMap<&str, &str> = board
+        .members
+        .iter()
+        .filter_map(|m| {
+            req.member_emails
+                .get(&m.username)
+                .or_else(|| req.member_emails.get(&m.id))
+                .map(|email| (m.id.as_str(), email.as_str()))
+        })
+        .collect();
+
+    let mut lists: Vec<&List> = board
+        .lists
+        .iter()
+        .filter(|l| req.include_archived || !l.closed)
+        .collect();
+    lists.sort_by(|a, b| a.pos.total_cmp(&b.pos));
+    let mut cards: Vec<&Card> = board
+        .cards
+        .iter()
+        .filter(|c| req.include_archived || !c.closed)
+        .collect();
+    cards.sort_by(|a, b| a.pos.total_cmp(&b.pos));
+
+    let mut children = Vec::new();
+    for list in lists {
+        let status = match req.list_mode {
+            ListMode::Parents => None,
+            ListMode::Statuses => Some(list_status(req, list, statuses)?),
+        };
+        let tasks = cards.iter().filter(|c| c.id_list == list.id).map(|card| {
+            let mut task = map_card(board, card, &label_names, &emails);
+            task.status = status.clone();
+            task
+        });
+        match req.list_mode {
+            ListMode::Parents => children.push(ImportedTask {
+                source_id: list.id.clone(),
+                name: list.name.clone(),
+                children: tasks.collect(),
+                ..ImportedTask::default()
+            }),
+            ListMode::Statuses => children.extend(tasks),
+        }
+    }
+
+    Ok((
+        ImportedTask {
+            source_id: board.id.clone(),
+            name: board.name.clone(),
+            desc: Some(board.desc.clone()),
+            url: board.url.clone(),
+            children,
+            ..ImportedTask::default()
+        },
+        labels.into_iter().map(|(_, label)| label).collect(),
+    ))
+}
+
+fn list_status(req: &TrelloImport, list: &List, statuses: &[String]) -> ApiResult<String> {
+    if let Some(status) = req.list_statuses.get(&list.name) {
+        if !statuses.contains(status) {
+            return Err(bad_request_error(
+                "INVALID_STATUS",
+                &format!("Invalid status for list {}: {status}", list.name),
+            ));
+        }
+        return Ok(status.clone());
+    }
+    statuses
+        .iter()
+        .find(|s| s.eq_ignore_ascii_case(list.name.trim()))
+        .cloned()
+        .ok_or_else(|| {
+            bad_request_error(
+                "UNMAPPED_LIST",
+                &format!(
+                    "List {} doesn't match a status. Map it with listStatuses",
+                    list.name
+                ),
+            )
+        })
+}
+
+fn map_card(
+    board: &Board,
+    card: &Card,
+    label_names: &HashMap<&str, &str>,
+    emails: &HashMap<&str, &str>,
+) -> ImportedTask {
+    let mut desc = card.desc.clone();
+    if !card.attachments.is_empty() {
+        if !desc.is_empty() {
+            desc.push_str("\n\n");
+        }
+        desc.push_str("Attachments:");
+        for attachment in &card.attachments {
+            desc.push_str(&format!("\n- [{}]({})", attachment.name, attachment.url));
+        }
+    }
+
+    let mut checklists: Vec<&Checklist> = board
+        .checklists
+        .iter()
+        .filter(|c| c.id_card == card.id)
+        .collect();
+    checklists.sort_by(|a, b| a.pos.total_cmp(&b.pos));
+    // A single checklist's items are the card's subtasks. Multiple checklists each get a task.
+    let children = match checklists.as_slice() {
+        [checklist] => map_check_items(checklist, emails),
+        checklists => checklists
+            .iter()
+            .map(|checklist| ImportedTask {
+                source_id: checklist.id.clone(),
+                name: checklist.name.clone(),
+                children: map_check_items(checklist, emails),
+                ..ImportedTask::default()
+            })
+            .collect(),
+    };
+
+    ImportedTask {
+        source_id: card.id.clone(),
+        name: card.name.clone(),
+        desc: Some(desc),
+        url: card.short_url.clone(),
+        done: card.due_complete,
+        // Tasks have a single assignee, so take the first member with an email.
+        assignee: card
+            .id_members
+            .iter()
+            .find_map(|id| emails.get(id.as_str()))
+            .map(|email| email.to_string()),
+        deadline: card.due.map(deadline),
+        labels: card
+            .id_labels
+            .iter()
+            .filter_map(|id| label_names.get(id.as_str()))
+            .map(|name| name.to_string())
+            .collect(),
+        children,
+        ..ImportedTask::default()
+    }
+}
+
+fn map_check_items(checklist: &Checklist, emails: &HashMap<&str, &str>) -> Vec<ImportedTask> {
+    let mut items: Vec<&CheckItem> = checklist.check_items.iter().collect();
+    items.sort_by(|a, b| a.pos.total_cmp(&b.pos));
+    items
+        .into_iter()
+        .map(|item| ImportedTask {
+            source_id: item.id.clone(),
+            name: item.name.clone(),
+            done: item.state == "complete",
+            assignee: item
+                .id_member
+                .as_deref()
+                .and_then(|id| emails.get(id))
+                .map(|email| email.to_string()),
+            deadline: item.due.map(deadline),
+            ..ImportedTask::default()
+        })
+        .collect()
+}
+
+fn deadline(due: DateTime<Utc>) -> Deadline {
+    Deadline::DateTime {
+        millis: due.timestamp_millis(),
+        time_zone: "UTC".to_string(),
+    }
+}
+
+/// Unnamed Trello labels are identified by their color, so name them after it.
+fn to_label(label: &BoardLabel) -> Option<Label> {
+    let color = label.color.as_deref();
+    let name = match label.name.trim() {
+        "" => {
+            let mut chars = color?.split('_').next()?.chars();
+            let first = chars.next()?;
+            first.to_uppercase().chain(chars).collect()
+        }
+        name => name.to_string(),
+    };
+    Some(Label {
+        name,
+        color: trello_color(color).to_string(),
+    })
+}
+
+/// Trello's palette. Newer variants, e.g. green_dark, use their base color.
+fn trello_color(color: Option<&str>) -> &'static str {
+    match color.map(|c| c.split('_').next().unwrap_or(c)) {
+        Some("green") => "#4bce97",
+        Some("yellow") => "#f5cd47",
+        Some("orange") => "#fea362",
+        Some("red") => "#f87168",
+        Some("purple") => "#9f8fef",
+        Some("blue") => "#579dff",
+        Some("sky") => "#6cc3e0",
+        Some("lime") => "#94c748",
+        Some("pink") => "#e774bb",
+        Some("black") => "#8590a2",
+        _ => DEFAULT_LABEL_COLOR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export() -> serde_json::Value {
+        serde_json::json!({
+            "id": "b1",
+            "name": "Launch",
+            "desc": "Launch plan",
+            "url": "https://trello.com/b/abc/launch",
+            "lists": [
+                {"id": "l2", "name": "Done", "pos": 2.0},
+                {"id": "l1", "name": "todo", "pos": 1.0},
+                {"id": "l3", "name": "Old", "pos": 3.0, "closed": true},
+            ],
+            "cards": [
+                {
+                    "id": "c1",
+                    "name": "Write post",
+                    "desc": "Draft it",
+                    "idList": "l1",
+                    "pos": 1.0,
+                    "due": "2025-09-01T17:00:00.000Z",
+                    "shortUrl": "https://trello.com/c/c1",
+                    "idMembers": ["m2", "m1"],
+                    "idLabels": ["lb1", "lb2"],
+                    "attachments": [{"name": "spec.pdf", "url": "https://example.com/spec.pdf"}],
+                },
+                {"id": "c2", "name": "Ship", "idList": "l2", "pos": 1.0, "dueComplete": true},
+                {"id": "c3", "name": "Archived", "idList": "l1", "pos": 0.5, "closed": true},
+            ],
+            "labels": [
+                {"id": "lb1", "name": "Blog", "color": "blue"},
+                {"id": "lb2", "name": "", "color": "green_dark"},
+                {"id": "lb3", "name": "", "color": null},
+            ],
+            "checklists": [
+                {
+                    "id": "cl1",
+                    "idCard": "c1",
+                    "name": "Steps",
+                    "checkItems": [
+                        {"id": "i2", "name": "Review", "state": "incomplete", "pos": 2.0, "idMember": "m1"},
+                        {"id": "i1", "name": "Outline", "state": "complete", "pos": 1.0},
+                    ],
+                },
+            ],
+            "members": [
+                {"id": "m1", "username": "alice"},
+                {"id": "m2", "username": "bob"},
+            ],
+        })
+    }
+
+    fn request(list_mode: &str, extra: serde_json::Value) -> TrelloImport {
+        let mut req = serde_json::json!({
+            "board": export(),
+            "listMode": list_mode,
+            "memberEmails": {"alice": "alice@koso.app"},
+        });
+        req.as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(req).unwrap()
+    }
+
+    fn statuses() -> Vec<String> {
+        vec![
+            "Not Started".into(),
+            "In Progress".into(),
+            "Todo".into(),
+            "Done".into(),
+        ]
+    }
+
+    #[test]
+    fn map_board_nests_cards_under_lists() {
+        let (board, labels) =
+            map_board(&request("parents", serde_json::json!({})), &statuses()).unwrap();
+        assert_eq!(board.name, "Launch");
+        assert_eq!(board.source_id, "b1");
+        assert_eq!(
+            board
+                .children
+                .iter()
+                .map(|l| l.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["todo", "Done"]
+        );
+        assert_eq!(
+            labels
+                .iter()
+                .map(|l| (l.name.as_str(), l.color.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("Blog", "#579dff"), ("Green", "#4bce97")]
+        );
+
+        let card = &board.children[0].children[0];
+        assert_eq!(board.children[0].children.len(), 1);
+        assert_eq!(
+            (card.source_id.as_str(), card.name.as_str()),
+            ("c1", "Write post")
+        );
+        assert_eq!(
+            card.desc.as_deref(),
+            Some("Draft it\n\nAttachments:\n- [spec.pdf](https://example.com/spec.pdf)")
+        );
+        assert_eq!(card.url.as_deref(), Some("https://trello.com/c/c1"));
+        assert_eq!(card.assignee.as_deref(), Some("alice@koso.app"));
+        assert_eq!(card.labels, vec!["Blog", "Green"]);
+        assert_eq!(
+            card.deadline,
+            Some(Deadline::DateTime {
+                millis: 1756746000000,
+                time_zone: "UTC".to_string()
+            })
+        );
+        assert_eq!(card.status, None);
+        assert_eq!(
+            card.children
+                .iter()
+                .map(|i| (i.source_id.as_str(), i.done, i.assignee.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![("i1", true, None), ("i2", false, Some("alice@koso.app"))]
+        );
+        assert!(board.children[1].children[0].done);
+    }
+
+    #[test]
+    fn map_board_maps_lists_to_statuses() {
+        let req = request(
+            "statuses",
+            serde_json::json!({"includeArchived": true, "listStatuses": {"Old": "Done"}}),
+        );
+        let (board, _) = map_board(&req, &statuses()).unwrap();
+        assert_eq!(
+            board
+                .children
+                .iter()
+                .map(|c| (c.name.as_str(), c.status.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("Archived", Some("Todo")),
+                ("Write post", Some("Todo")),
+                ("Ship", Some("Done"))
+            ]
+        );
+    }
+
+    #[test]
+    fn map_board_rejects_unmapped_lists() {
+        let req = request("statuses", serde_json::json!({"includeArchived": true}));
+        let err = map_board(&req, &statuses()).unwrap_err();
+        assert!(err.message().starts_with("UNMAPPED_LIST: List Old"));
+
+        let req = request(
+            "statuses",
+            serde_json::json!({"listStatuses": {"todo": "Nope"}}),
+        );
+        let err = map_board(&req, &statuses()).unwrap_err();
+        assert!(err.message().starts_with("INVALID_STATUS"));
+    }
+}
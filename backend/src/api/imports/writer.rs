@@ -0,0 +1,596 @@
+//! Writes imported rows to the project in a single transaction, creating
+//! tasks for new rows and updating those imported by earlier runs.
+
+use super::{ImportAction, ImportChange, ImportResult, ImportRow, ImportRun, RowError};
+use crate::{
+    api::{
+        ApiResult,
+        collab::txn_origin::{Actor, YOrigin},
+        model::{Deadline, Label, ProjectId, Task, WorkflowCategory},
+        projects::fetch_task_key_prefix,
+        proposals::{transact_dry_run, transact_or_propose},
+        yproxy::{MAX_CONFIG_NAME_LEN, YDocProxy, YTaskProxy, status_for_category},
+        zapier::find_task,
+    },
+    postgres::{PgPool, list_project_users},
+};
+use anyhow::{Context as _, Result};
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use sqlx::types::chrono::Utc;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+use yrs::TransactionMut;
+
+/// Scopes the source ids of an import.
+pub(super) struct Source {
+    pub(super) source: &'static str,
+    pub(super) source_key: String,
+}
+
+pub(super) struct Written {
+    pub(super) result: ImportResult,
+    pub(super) errors: Vec<RowError>,
+}
+
+/// The project-specific inputs to [apply].
+struct Context<'a> {
+    reporter: &'a str,
+    members: &'a HashSet<String>,
+    parent_id: Option<&'a str>,
+    task_key_prefix: Option<&'a str>,
+    dry_run: bool,
+}
+
+pub(super) async fn write(
+    run: &ImportRun,
+    source: &Source,
+    rows: Vec<ImportRow>,
+    labels: Vec<Label>,
+) -> ApiResult<Written> {
+    let members: HashSet<String> = list_project_users(run.pool, &run.project_id)
+        .await?
+        .into_iter()
+        .map(|u| u.email)
+        .collect();
+    let task_key_prefix = fetch_task_key_prefix(run.pool, &run.project_id).await?;
+    let tasks = fetch_sources(run.pool, &run.project_id, source).await?;
+    let context = Context {
+        reporter: &run.user.email,
+        members: &members,
+        parent_id: run.options.parent_id.as_deref(),
+        task_key_prefix: task_key_prefix.as_deref(),
+        dry_run: run.options.dry_run,
+    };
+    let origin = YOrigin {
+        who: format!("import_{}", source.source),
+        id: format!("import_{}", run.job_id),
+        actor: Actor::User(run.user.clone()),
+        ..Default::default()
+    };
+    let apply_rows =
+        |doc: &YDocProxy, txn: &mut TransactionMut| apply(doc, txn, &context, tasks, rows, labels);
+
+    if run.options.dry_run {
+        let (written, _) =
+            transact_dry_run(&run.collab, &run.project_id, &origin, apply_rows).await?;
+        return Ok(written);
+    }
+    let transacted =
+        transact_or_propose(run.pool, &run.collab, &run.project_id, &origin, apply_rows).await?;
+    let (mut written, created) = transacted.result;
+    written.result.proposed = transacted.proposed;
+    // Tasks held for review are remembered too. If the proposal is
+    // rejected, the next run finds them missing and creates them again.
+    save_sources(run.pool, &run.project_id, source, &created).await?;
+    Ok(written)
+}
+
+/// Writes the rows, returning the outcome along with the source and task ids
+/// of the tasks created.
+fn apply(
+    doc: &YDocProxy,
+    txn: &mut TransactionMut,
+    context: &Context,
+    tasks: HashMap<String, String>,
+    rows: Vec<ImportRow>,
+    labels: Vec<Label>,
+) -> ApiResult<(Written, Vec<(String, String)>)> {
+    let parent_id = match context.parent_id {
+        Some(parent_id) => find_task(doc, txn, parent_id, context.task_key_prefix)?,
+        None => doc.get(txn, "root")?,
+    }
+    .get_id(txn)?;
+    let (labels_added, label_names) = add_labels(doc, txn, labels)?;
+    let mut writer = Writer {
+        doc,
+        reporter: context.reporter,
+        members: context.members,
+        label_names,
+        statuses: doc.config().statuses(txn)?,
+        done_status: status_for_category(
+            &doc.config().get_workflow_states(txn)?,
+            WorkflowCategory::Done,
+        ),
+        next_num: doc.next_num(txn)?,
+        now: Utc::now().timestamp_millis(),
+        parent_id,
+        tasks,
+        root_id: None,
+        created: Vec::new(),
+        changes: Vec::new(),
+        errors: Vec::new(),
+        skipped_assignees: Vec::new(),
+    };
+    for row in rows {
+        writer.write(txn, row)?;
+    }
+
+    let count = |action| writer.changes.iter().filter(|c| c.action == action).count();
+    let result = ImportResult {
+        task_id: if context.dry_run {
+            None
+        } else {
+            writer.root_id.clone()
+        },
+        created: count(ImportAction::Create),
+        updated: count(ImportAction::Update),
+        unchanged: count(ImportAction::Unchanged),
+        labels_added,
+        skipped_assignees: writer.skipped_assignees,
+        proposed: false,
+        changes: context.dry_run.then_some(writer.changes),
+    };
+    Ok((
+        Written {
+            result,
+            errors: writer.errors,
+        },
+        writer.created,
+    ))
+}
+
+/// Adds labels not already configured, returning the names added along with
+/// the configured name of each imported label. Label names are unique
+/// regardless of case, so imported labels reuse existing ones that differ only in case.
+fn add_labels(
+    doc: &YDocProxy,
+    txn: &mut TransactionMut,
+    labels: Vec<Label>,
+) -> Result<(Vec<String>, HashMap<String, String>)> {
+    let config = doc.config();
+    let mut configured = config.get_labels(txn)?;
+    let mut added = Vec::new();
+    let mut names = HashMap::new();
+    for label in labels {
+        let name: String = label
+            .name
+            .trim()
+            .chars()
+            .take(MAX_CONFIG_NAME_LEN)
+            .collect();
+        if name.is_empty() {
+            continue;
+        }
+        match configured
+            .iter()
+            .find(|l| l.name.eq_ignore_ascii_case(&name))
+        {
+            Some(existing) => {
+                names.insert(label.name, existing.name.clone());
+            }
+            None => {
+                names.insert(label.name, name.clone());
+                added.push(name.clone());
+                configured.push(Label {
+                    name,
+                    color: label.color,
+                });
+            }
+        }
+    }
+    if !added.is_empty() {
+        config.set_labels(txn, &configured)?;
+    }
+    Ok((added, names))
+}
+
+struct Writer<'a> {
+    doc: &'a YDocProxy,
+    reporter: &'a str,
+    members: &'a HashSet<String>,
+    label_names: HashMap<String, String>,
+    statuses: Vec<String>,
+    done_status: Option<String>,
+    /// Computing the next num scans the whole graph, so allocate them here instead.
+    next_num: u64,
+    now: i64,
+    /// Where rows without a parent go.
+    parent_id: String,
+    /// Task ids by source id, including those imported by earlier runs.
+    tasks: HashMap<String, String>,
+    /// The task containing the import.
+    root_id: Option<String>,
+    created: Vec<(String, String)>,
+    changes: Vec<ImportChange>,
+    errors: Vec<RowError>,
+    skipped_assignees: Vec<String>,
+}
+
+/// A row's fields, mapped to the project.
+struct Fields {
+    name: String,
+    desc: Option<String>,
+    url: Option<String>,
+    status: Option<String>,
+    assignee: Option<String>,
+    deadline: Option<Deadline>,
+    labels: Vec<String>,
+}
+
+impl Writer<'_> {
+    fn write(&mut self, txn: &mut TransactionMut, row: ImportRow) -> Result<()> {
+        let ImportRow {
+            parent,
+            task: imported,
+        } = row;
+        let source_id = imported.source_id;
+        let status = match imported.status {
+            Some(status) if !self.statuses.contains(&status) => {
+                self.errors.push(RowError {
+                    row: source_id.clone(),
+                    message: format!("Invalid status: {status}"),
+                });
+                None
+            }
+            Some(status) => Some(status),
+            None if imported.done => self.done_status.clone(),
+            None => None,
+        };
+        let fields = Fields {
+            name: imported.name,
+            desc: imported.desc.filter(|d| !d.trim().is_empty()),
+            url: imported.url,
+            status,
+            assignee: self.assignee(imported.assignee),
+            deadline: imported.deadline,
+            labels: imported
+                .labels
+                .iter()
+                .filter_map(|label| self.label_names.get(label).cloned())
+                .collect(),
+        };
+
+        let existing = self
+            .tasks
+            .get(&source_id)
+            .and_then(|task_id| self.doc.get(txn, task_id).ok());
+        let change = match existing {
+            Some(task) => self.update(txn, &task, fields)?,
+            None => {
+                let parent_id = match &parent {
+                    Some(parent) => match self.tasks.get(parent) {
+                        Some(parent_id) if self.doc.get(txn, parent_id).is_ok() => {
+                            parent_id.clone()
+                        }
+                        _ => {
+                            self.errors.push(RowError {
+                                row: source_id,
+                                message: format!("Parent {parent} wasn't imported"),
+                            });
+                            return Ok(());
+                        }
+                    },
+                    None => self.parent_id.clone(),
+                };
+                let change = self.create(txn, &parent_id, fields)?;
+                self.tasks.insert(source_id.clone(), change.task_id.clone());
+                self.created
+                    .push((source_id.clone(), change.task_id.clone()));
+                change
+            }
+        };
+        if parent.is_none() {
+            self.root_id = Some(change.task_id.clone());
+        }
+        self.changes.push(ImportChange {
+            source_id,
+            ..change
+        });
+        Ok(())
+    }
+
+    fn create(
+        &mut self,
+        txn: &mut TransactionMut,
+        parent_id: &str,
+        fields: Fields,
+    ) -> Result<ImportChange> {
+        let task = Task {
+            id: BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4()),
+            num: self.next_num.to_string(),
+            name: fields.name,
+            desc: fields.desc,
+            assignee: fields.assignee,
+            reporter: Some(self.reporter.to_string()),
+            status_time: fields.status.as_ref().map(|_| self.now),
+            status: fields.status,
+            url: fields.url,
+            deadline: fields.deadline,
+            ..Task::default()
+        };
+        self.next_num += 1;
+        let y_task = self.doc.set(txn, &task);
+        if !fields.labels.is_empty() {
+            y_task.set_labels(txn, &fields.labels)?;
+        }
+        self.doc.get(txn, parent_id)?.push_child(txn, &task.id)?;
+        Ok(ImportChange {
+            source_id: String::new(),
+            task_id: task.id,
+            name: task.name,
+            action: ImportAction::Create,
+            fields: Vec::new(),
+        })
+    }
+
+    /// Updates the fields the source sets. Fields the source leaves unset,
+    /// and labels added since, are left alone.
+    fn update(
+        &mut self,
+        txn: &mut TransactionMut,
+        task: &YTaskProxy,
+        fields: Fields,
+    ) -> Result<ImportChange> {
+        let mut changed = Vec::new();
+        if task.get_name(txn)? != fields.name {
+            task.set_name(txn, &fields.name);
+            changed.push("name");
+        }
+        if fields.desc.is_some() && task.get_desc(txn)? != fields.desc {
+            task.set_desc(txn, fields.desc.as_deref());
+            changed.push("desc");
+        }
+        if fields.url.is_some() && task.get_url(txn)? != fields.url {
+            task.set_url(txn, fields.url.as_deref());
+            changed.push("url");
+        }
+        if fields.assignee.is_some() && task.get_assignee(txn)? != fields.assignee {
+            task.set_assignee(txn, fields.assignee.as_deref());
+            changed.push("assignee");
+        }
+        if fields.deadline.is_some() && task.get_deadline(txn)? != fields.deadline {
+            task.set_deadline(txn, fields.deadline.as_ref());
+            changed.push("deadline");
+        }
+        if fields.status.is_some() && task.get_status(txn)? != fields.status {
+            task.set_status(txn, fields.status.as_deref());
+            task.set_status_time(txn, Some(self.now));
+            changed.push("status");
+        }
+        let mut labels = task.get_labels(txn)?;
+        let missing: Vec<String> = fields
+            .labels
+            .into_iter()
+            .filter(|label| !labels.contains(label))
+            .collect();
+        if !missing.is_empty() {
+            labels.extend(missing);
+            task.set_labels(txn, &labels)?;
+            changed.push("labels");
+        }
+        Ok(ImportChange {
+            source_id: String::new(),
+            task_id: task.get_id(txn)?,
+            name: fields.name,
+            action: if changed.is_empty() {
+                ImportAction::Unchanged
+            } else {
+                ImportAction::Update
+            },
+            fields: changed,
+        })
+    }
+
+    fn assignee(&mut self, assignee: Option<String>) -> Option<String> {
+        match assignee {
+            Some(assignee) if self.members.contains(&assignee) => Some(assignee),
+            Some(assignee) => {
+                if !self.skipped_assignees.contains(&assignee) {
+                    self.skipped_assignees.push(assignee);
+                }
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+async fn fetch_sources(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    source: &Source,
+) -> Result<HashMap<String, String>> {
+    let sources: Vec<(String, String)> = sqlx::query_as(
+        "
+        SELECT source_id, task_id
+        FROM import_sources
+        WHERE project_id = $1 AND source = $2 AND source_key = $3",
+    )
+    .bind(project_id)
+    .bind(source.source)
+    .bind(&source.source_key)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch import sources")?;
+    Ok(sources.into_iter().collect())
+}
+
+async fn save_sources(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    source: &Source,
+    created: &[(String, String)],
+) -> Result<()> {
+    if created.is_empty() {
+        return Ok(());
+    }
+    let (source_ids, task_ids): (Vec<&str>, Vec<&str>) = created
+        .iter()
+        .map(|(source_id, task_id)| (source_id.as_str(), task_id.as_str()))
+        .unzip();
+    sqlx::query(
+        "
+        INSERT INTO import_sources (project_id, source, source_key, source_id, task_id)
+        SELECT $1, $2, $3, source_id, task_id FROM unnest($4::text[], $5::text[]) AS t(source_id, task_id)
+        ON CONFLICT (project_id, source, source_key, source_id)
+        DO UPDATE SET task_id = EXCLUDED.task_id",
+    )
+    .bind(project_id)
+    .bind(source.source)
+    .bind(&source.source_key)
+    .bind(&source_ids)
+    .bind(&task_ids)
+    .execute(pool)
+    .await
+    .context("Failed to save import sources")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::imports::ImportedTask;
+    use yrs::Origin;
+
+    fn origin() -> Origin {
+        YOrigin {
+            who: "imports_test".to_string(),
+            id: "test".to_string(),
+            actor: Actor::Server,
+            ..Default::default()
+        }
+        .as_origin()
+        .unwrap()
+    }
+
+    fn row(parent: Option<&str>, source_id: &str, name: &str, done: bool) -> ImportRow {
+        ImportRow {
+            parent: parent.map(String::from),
+            task: ImportedTask {
+                source_id: source_id.to_string(),
+                name: name.to_string(),
+                done,
+                assignee: Some("outsider@koso.app".to_string()),
+                ..ImportedTask::default()
+            },
+        }
+    }
+
+    fn rows(second_name: &str) -> Vec<ImportRow> {
+        vec![
+            row(None, "board", "Board", false),
+            row(Some("board"), "a", "A", true),
+            row(Some("board"), "b", second_name, false),
+            row(Some("missing"), "c", "C", false),
+        ]
+    }
+
+    #[test]
+    fn apply_creates_then_updates_by_source_id() {
+        let doc = YDocProxy::new();
+        let mut txn = doc.transact_mut_with(origin());
+        doc.set(
+            &mut txn,
+            &Task {
+                id: "root".to_string(),
+                num: "0".to_string(),
+                name: "Root".to_string(),
+                ..Task::default()
+            },
+        );
+        let members = HashSet::from(["a@koso.app".to_string()]);
+        let context = Context {
+            reporter: "a@koso.app",
+            members: &members,
+            parent_id: None,
+            task_key_prefix: None,
+            dry_run: false,
+        };
+
+        let (written, created) = apply(
+            &doc,
+            &mut txn,
+            &context,
+            HashMap::new(),
+            rows("B"),
+            Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(written.result.created, 3);
+        assert_eq!(written.result.skipped_assignees, vec!["outsider@koso.app"]);
+        assert_eq!(
+            written.errors,
+            vec![RowError {
+                row: "c".to_string(),
+                message: "Parent missing wasn't imported".to_string()
+            }]
+        );
+        let tasks: HashMap<String, String> = created.into_iter().collect();
+        let board = doc.get(&txn, &tasks["board"]).unwrap();
+        assert_eq!(
+            doc.get(&txn, "root").unwrap().get_children(&txn).unwrap(),
+            vec![tasks["board"].clone()]
+        );
+        assert_eq!(
+            board.get_children(&txn).unwrap(),
+            vec![tasks["a"].clone(), tasks["b"].clone()]
+        );
+        assert_eq!(
+            doc.get(&txn, &tasks["a"])
+                .unwrap()
+                .get_status(&txn)
+                .unwrap()
+                .as_deref(),
+            Some("Done")
+        );
+        assert_eq!(written.result.task_id, Some(tasks["board"].clone()));
+
+        let (written, created) = apply(
+            &doc,
+            &mut txn,
+            &Context {
+                dry_run: true,
+                ..context
+            },
+            tasks.clone(),
+            rows("B2"),
+            Vec::new(),
+        )
+        .unwrap();
+        assert!(created.is_empty());
+        assert_eq!(
+            (
+                written.result.created,
+                written.result.updated,
+                written.result.unchanged
+            ),
+            (0, 1, 2)
+        );
+        let changes = written.result.changes.unwrap();
+        assert_eq!(changes[2].source_id, "b");
+        assert_eq!(changes[2].action, ImportAction::Update);
+        assert_eq!(changes[2].fields, vec!["name"]);
+        assert_eq!(
+            doc.get(&txn, &tasks["b"]).unwrap().get_name(&txn).unwrap(),
+            "B2"
+        );
+        assert_eq!(
+            doc.get(&txn, "root")
+                .unwrap()
+                .get_children(&txn)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,589 @@
+use crate::{
+    api::{
+        ApiResult, attachments, bad_request_error,
+        collab::{
+            Collab,
+            projects_state::DocBox,
+            txn_origin::{Actor, TxnMetadata, YOrigin},
+        },
+        google::User,
+        model::{InboundEmail, InboundEmailAddress, ProjectId, Task, UpdateInboundEmailAddress},
+        not_found_error, truncate, unauthorized_error, verify_project_admin,
+    },
+    postgres::{PgPool, list_project_users},
+    secrets::{Secret, read_secret},
+    settings::settings,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    body::{Body, Bytes},
+    extract::Path,
+    http::{HeaderMap, header::AUTHORIZATION},
+    routing::{get, post},
+};
+use base64::{
+    Engine as _,
+    prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD},
+};
+use serde::Deserialize;
+use sha2::{Digest as _, Sha256};
+use sqlx::types::chrono::{DateTime, Utc};
+use tower_http::request_id::RequestId;
+use uuid::Uuid;
+
+/// Routes used by project admins to manage their inbound address.
+pub(super) fn router() -> Router {
+    Router::new()
+        .route(
+            "/{project_id}/inbound-email",
+            get(get_address_handler)
+                .post(create_address_handler)
+                .patch(update_address_handler)
+                .delete(delete_address_handler),
+        )
+        .route(
+            "/{project_id}/inbound-email/emails",
+            get(list_emails_handler),
+        )
+}
+
+/// Routes invoked by the inbound mail provider rather than users.
+pub(super) fn webhook_router() -> Router {
+    Router::new().route("/postmark", post(postmark_webhook_handler))
+}
+
+/// Maximum size of an inbound email, including attachments. Matches Postmark's limit.
+const BODY_LIMIT: usize = 35 * 1024 * 1024;
+const MAX_ATTACHMENTS: usize = 10;
+const MAX_NAME_LEN: usize = 200;
+const MAX_DESC_LEN: usize = 20_000;
+/// Emails accepted per project per hour.
+const MAX_EMAILS_PER_HOUR: i64 = 100;
+/// Emails accepted from a single sender per hour.
+const MAX_SENDER_EMAILS_PER_HOUR: i64 = 20;
+
+#[derive(sqlx::FromRow)]
+struct AddressRow {
+    token: String,
+    allow_non_members: bool,
+    creator: String,
+    create_time: DateTime<Utc>,
+}
+
+impl AddressRow {
+    fn into_address(self) -> InboundEmailAddress {
+        InboundEmailAddress {
+            address: format!("{}@{}", self.token, settings().inbound_email.domain),
+            allow_non_members: self.allow_non_members,
+            creator: self.creator,
+            create_time: self.create_time,
+        }
+    }
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn get_address_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Option<InboundEmailAddress>>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let address: Option<AddressRow> = sqlx::query_as(
+        "
+        SELECT token, allow_non_members, creator, create_time
+        FROM inbound_email_addresses
+        WHERE project_id = $1",
+    )
+    .bind(&project_id)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch inbound email address")?;
+    Ok(Json(address.map(AddressRow::into_address)))
+}
+
+/// Creates the project's inbound address, replacing any existing one.
+/// Emails sent to a replaced address are discarded.
+#[tracing::instrument(skip(user, pool))]
+async fn create_address_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Json(update): Json<UpdateInboundEmailAddress>,
+) -> ApiResult<Json<InboundEmailAddress>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let address: AddressRow = sqlx::query_as(
+        "
+        INSERT INTO inbound_email_addresses (project_id, token, allow_non_members, creator)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (project_id)
+        DO UPDATE SET
+            token = EXCLUDED.token,
+            allow_non_members = EXCLUDED.allow_non_members,
+            creator = EXCLUDED.creator,
+            create_time = NOW()
+        RETURNING token, allow_non_members, creator, create_time",
+    )
+    .bind(&project_id)
+    .bind(Uuid::new_v4().simple().to_string())
+    .bind(update.allow_non_members)
+    .bind(&user.email)
+    .fetch_one(pool)
+    .await
+    .context("Failed to upsert inbound email address")?;
+    Ok(Json(address.into_address()))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn update_address_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Json(update): Json<UpdateInboundEmailAddress>,
+) -> ApiResult<Json<InboundEmailAddress>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let address: Option<AddressRow> = sqlx::query_as(
+        "
+        UPDATE inbound_email_addresses
+        SET allow_non_members = $2
+        WHERE project_id = $1
+        RETURNING token, allow_non_members, creator, create_time",
+    )
+    .bind(&project_id)
+    .bind(update.allow_non_members)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to update inbound email address")?;
+    address.map(|a| Json(a.into_address())).ok_or_else(|| {
+        not_found_error(
+            "NOT_FOUND",
+            &format!("Project {project_id} has no inbound email address"),
+        )
+    })
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_address_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<()>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    sqlx::query("DELETE FROM inbound_email_addresses WHERE project_id = $1")
+        .bind(&project_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete inbound email address")?;
+    Ok(Json(()))
+}
+
+/// Lists recently received emails, including rejected ones, so admins can
+/// see why an email didn't become a task.
+#[tracing::instrument(skip(user, pool))]
+async fn list_emails_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Vec<InboundEmail>>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let emails: Vec<InboundEmail> = sqlx::query_as(
+        "
+        SELECT id, sender, subject, task_id, rejection, receive_time
+        FROM inbound_emails
+        WHERE project_id = $1
+        ORDER BY receive_time DESC
+        LIMIT 100",
+    )
+    .bind(&project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list inbound emails")?;
+    Ok(Json(emails))
+}
+
+/// An inbound email as posted by Postmark.
+/// See https://postmarkapp.com/developer/webhooks/inbound-webhook
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkEmail {
+    from_full: PostmarkAddress,
+    #[serde(default)]
+    to_full: Vec<PostmarkAddress>,
+    #[serde(default)]
+    cc_full: Vec<PostmarkAddress>,
+    #[serde(default)]
+    original_recipient: String,
+    #[serde(default)]
+    subject: String,
+    #[serde(rename = "MessageID")]
+    message_id: String,
+    #[serde(default)]
+    text_body: String,
+    #[serde(default)]
+    headers: Vec<PostmarkHeader>,
+    #[serde(default)]
+    attachments: Vec<PostmarkAttachment>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkAddress {
+    email: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkHeader {
+    name: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkAttachment {
+    name: String,
+    /// Base64 encoded content.
+    content: String,
+    content_type: String,
+}
+
+impl std::fmt::Debug for PostmarkAttachment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PostmarkAttachment")
+            .field("name", &self.name)
+            .field("content_type", &self.content_type)
+            .finish()
+    }
+}
+
+impl PostmarkEmail {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| h.value.as_str())
+    }
+
+    /// Returns the tokens of any of our addresses the email was sent to.
+    fn recipient_tokens(&self, domain: &str) -> Vec<String> {
+        std::iter::once(self.original_recipient.as_str())
+            .chain(self.to_full.iter().map(|a| a.email.as_str()))
+            .chain(self.cc_full.iter().map(|a| a.email.as_str()))
+            .filter_map(|address| {
+                let (local, address_domain) = address.trim().rsplit_once('@')?;
+                address_domain
+                    .eq_ignore_ascii_case(domain)
+                    .then(|| local.to_lowercase())
+            })
+            .collect()
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct InboundProject {
+    project_id: ProjectId,
+    allow_non_members: bool,
+}
+
+/// Turns an email received by Postmark into a task in the project it was addressed to.
+/// Rejected emails are acknowledged, and recorded, so that Postmark doesn't retry them.
+#[tracing::instrument(skip(pool, collab, request_id, headers, body))]
+async fn postmark_webhook_handler(
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Extension(request_id): Extension<RequestId>,
+    headers: HeaderMap,
+    body: Body,
+) -> ApiResult<String> {
+    verify_basic_auth(&headers, &read_secret("postmark/inbound_webhook_password")?)?;
+    let body: Bytes = axum::body::to_bytes(body, BODY_LIMIT)
+        .await
+        .map_err(|_| bad_request_error("INVALID_BODY", "Invalid body"))?;
+    let email: PostmarkEmail = serde_json::from_slice(&body)
+        .map_err(|e| bad_request_error("INVALID_BODY", &format!("Invalid email: {e}")))?;
+
+    let tokens = email.recipient_tokens(&settings().inbound_email.domain);
+    let project: Option<InboundProject> = sqlx::query_as(
+        "
+        SELECT project_id, allow_non_members
+        FROM inbound_email_addresses
+        WHERE token = ANY($1)
+        LIMIT 1",
+    )
+    .bind(&tokens)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up inbound email address")?;
+    let Some(project) = project else {
+        tracing::debug!("Discarding email sent to unknown address: {tokens:?}");
+        return Ok("OK".to_string());
+    };
+
+    let sender = email.from_full.email.trim().to_lowercase();
+    let is_member = list_project_users(pool, &project.project_id)
+        .await?
+        .iter()
+        .any(|u| u.email.to_lowercase() == sender);
+    let (recent, recent_from_sender): (i64, i64) = sqlx::query_as(
+        "
+        SELECT COUNT(*), COUNT(*) FILTER (WHERE sender = $2)
+        FROM inbound_emails
+        WHERE project_id = $1
+        AND receive_time > NOW() - INTERVAL '1 hour'",
+    )
+    .bind(&project.project_id)
+    .bind(&sender)
+    .fetch_one(pool)
+    .await
+    .context("Failed to count recent inbound emails")?;
+    let rejection = screen(
+        &email,
+        is_member || project.allow_non_members,
+        recent,
+        recent_from_sender,
+    );
+
+    let email_id = BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4());
+    let inserted = sqlx::query(
+        "
+        INSERT INTO inbound_emails (id, project_id, message_id, sender, subject, rejection)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (project_id, message_id) DO NOTHING",
+    )
+    .bind(&email_id)
+    .bind(&project.project_id)
+    .bind(&email.message_id)
+    .bind(&sender)
+    .bind(truncate(&email.subject, MAX_NAME_LEN))
+    .bind(rejection)
+    .execute(pool)
+    .await
+    .context("Failed to record inbound email")?;
+    if inserted.rows_affected() == 0 {
+        tracing::debug!("Discarding duplicate email {}", email.message_id);
+        return Ok("OK".to_string());
+    }
+    if let Some(rejection) = rejection {
+        tracing::info!("Rejected email from {sender}: {rejection}");
+        return Ok("OK".to_string());
+    }
+
+    let reporter = is_member.then_some(sender.as_str());
+    let task_id = create_task(
+        &collab,
+        &project.project_id,
+        &email,
+        &sender,
+        reporter,
+        &email_id,
+        request_id.header_value().to_str().unwrap_or("INVALID"),
+    )
+    .await?;
+
+    let mut txn = pool.begin().await?;
+    for attachment in email.attachments.iter().take(MAX_ATTACHMENTS) {
+        let Ok(content) = BASE64_STANDARD.decode(&attachment.content) else {
+            tracing::warn!("Skipping undecodable attachment {}", attachment.name);
+            continue;
+        };
+        if content.len() > attachments::MAX_ATTACHMENT_SIZE {
+            tracing::info!("Skipping oversized attachment {}", attachment.name);
+            continue;
+        }
+        attachments::insert_attachment(
+            &mut *txn,
+            &project.project_id,
+            &task_id,
+            &sender,
+            &attachment.name,
+            &attachment.content_type,
+            &content,
+        )
+        .await?;
+    }
+    sqlx::query("UPDATE inbound_emails SET task_id = $2 WHERE id = $1")
+        .bind(&email_id)
+        .bind(&task_id)
+        .execute(&mut *txn)
+        .await
+        .context("Failed to record inbound email task")?;
+    txn.commit().await?;
+    attachments::scanner::enqueue_scan(pool).await?;
+
+    Ok("OK".to_string())
+}
+
+/// Postmark authenticates using credentials embedded in the webhook URL.
+/// See https://postmarkapp.com/developer/webhooks/webhooks-overview#protecting-your-webhook
+fn verify_basic_auth(headers: &HeaderMap, password: &Secret<String>) -> ApiResult<()> {
+    let provided = headers
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Basic "))
+        .and_then(|h| BASE64_STANDARD.decode(h).ok())
+        .and_then(|h| String::from_utf8(h).ok())
+        .and_then(|h| h.split_once(':').map(|(_, password)| password.to_string()));
+    // Compare digests to avoid leaking the password through timing.
+    match provided {
+        Some(provided)
+            if Sha256::digest(provided.as_bytes()) == Sha256::digest(password.data.as_bytes()) =>
+        {
+            Ok(())
+        }
+        _ => Err(unauthorized_error("Invalid inbound email credentials")),
+    }
+}
+
+/// Returns why the email should be rejected, if it should be.
+fn screen(
+    email: &PostmarkEmail,
+    sender_allowed: bool,
+    recent: i64,
+    recent_from_sender: i64,
+) -> Option<&'static str> {
+    if email
+        .header("X-Spam-Status")
+        .is_some_and(|v| v.trim_start().starts_with("Yes"))
+    {
+        return Some("spam");
+    }
+    // Avoid loops with out of office replies and other automated mail.
+    if email
+        .header("Auto-Submitted")
+        .is_some_and(|v| !v.trim().eq_ignore_ascii_case("no"))
+        || email
+            .header("Precedence")
+            .is_some_and(|v| ["bulk", "junk"].contains(&v.trim().to_lowercase().as_str()))
+    {
+        return Some("automated");
+    }
+    if !sender_allowed {
+        return Some("non_member");
+    }
+    if recent >= MAX_EMAILS_PER_HOUR || recent_from_sender >= MAX_SENDER_EMAILS_PER_HOUR {
+        return Some("rate_limited");
+    }
+    None
+}
+
+/// Creates a task, under the root, from the email. Returns the id of the new task.
+async fn create_task(
+    collab: &Collab,
+    project_id: &ProjectId,
+    email: &PostmarkEmail,
+    sender: &str,
+    reporter: Option<&str>,
+    email_id: &str,
+    request_id: &str,
+) -> Result<String> {
+    let name = match email.subject.trim() {
+        "" => "(no subject)".to_string(),
+        subject => truncate(subject, MAX_NAME_LEN).to_string(),
+    };
+    let desc = match reporter {
+        Some(_) => truncate(email.text_body.trim(), MAX_DESC_LEN).to_string(),
+        // Attribute emails from non-members in the description since they can't be the reporter.
+        None => truncate(
+            &format!("From: {sender}\n\n{}", email.text_body.trim()),
+            MAX_DESC_LEN,
+        )
+        .to_string(),
+    };
+
+    let client = collab.register_local_client(project_id).await?;
+    let doc_box = client.project.doc_box.lock().await;
+    let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+    let mut txn = doc.transact_mut_with(
+        YOrigin {
+            who: "inbound_email".to_string(),
+            id: format!("inbound_email_{email_id}"),
+            actor: Actor::Server,
+            metadata: TxnMetadata {
+                request_id: Some(request_id.to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+        .as_origin()?,
+    );
+    let task = Task {
+        id: BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4()),
+        num: doc.next_num(&txn)?.to_string(),
+        name,
+        desc: (!desc.is_empty()).then_some(desc),
+        reporter: reporter.map(String::from),
+        ..Task::default()
+    };
+    doc.set(&mut txn, &task);
+    doc.get(&txn, "root")?.push_child(&mut txn, &task.id)?;
+    Ok(task.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email(headers: &[(&str, &str)]) -> PostmarkEmail {
+        PostmarkEmail {
+            from_full: PostmarkAddress {
+                email: "a@koso.app".to_string(),
+            },
+            to_full: vec![PostmarkAddress {
+                email: "ABC123@Inbound.koso.app".to_string(),
+            }],
+            cc_full: vec![PostmarkAddress {
+                email: "def456@elsewhere.com".to_string(),
+            }],
+            original_recipient: "".to_string(),
+            subject: "Subject".to_string(),
+            message_id: "id".to_string(),
+            text_body: "Body".to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| PostmarkHeader {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+            attachments: vec![],
+        }
+    }
+
+    #[test]
+    fn recipient_tokens_matches_domain() {
+        assert_eq!(
+            email(&[]).recipient_tokens("inbound.koso.app"),
+            vec!["abc123"]
+        );
+    }
+
+    #[test]
+    fn screen_rejects_abuse() {
+        assert_eq!(screen(&email(&[]), true, 0, 0), None);
+        assert_eq!(
+            screen(&email(&[("x-spam-status", "Yes, score=7.1")]), true, 0, 0),
+            Some("spam")
+        );
+        assert_eq!(
+            screen(&email(&[("X-Spam-Status", "No, score=0.1")]), true, 0, 0),
+            None
+        );
+        assert_eq!(
+            screen(&email(&[("Auto-Submitted", "auto-replied")]), true, 0, 0),
+            Some("automated")
+        );
+        assert_eq!(
+            screen(&email(&[("Auto-Submitted", "no")]), true, 0, 0),
+            None
+        );
+        assert_eq!(screen(&email(&[]), false, 0, 0), Some("non_member"));
+        assert_eq!(
+            screen(&email(&[]), true, MAX_EMAILS_PER_HOUR, 0),
+            Some("rate_limited")
+        );
+        assert_eq!(
+            screen(&email(&[]), true, 0, MAX_SENDER_EMAILS_PER_HOUR),
+            Some("rate_limited")
+        );
+    }
+}
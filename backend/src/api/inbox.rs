@@ -0,0 +1,209 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{msg_sync::koso_notification, projects_state::UserMessenger},
+        google::User,
+        model::{InboxKind, InboxNotification, MarkInboxRead, ProjectId},
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result, anyhow};
+use axum::{
+    Extension, Json, Router,
+    extract::Query,
+    routing::{get, post},
+};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use serde::Deserialize;
+use sqlx::types::chrono::{DateTime, Utc};
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/", get(list_notifications_handler))
+        .route("/read", post(mark_read_handler))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ListNotificationsQuery {
+    #[serde(default)]
+    unread_only: bool,
+    limit: Option<i64>,
+}
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 200;
+
+#[tracing::instrument(skip(user, pool))]
+async fn list_notifications_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Query(query): Query<ListNotificationsQuery>,
+) -> ApiResult<Json<Vec<InboxNotification>>> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(bad_request_error(
+            "INVALID_LIMIT",
+            &format!("Limit must be between 1 and {MAX_LIMIT}"),
+        ));
+    }
+
+    let rows: Vec<NotificationRow> = sqlx::query_as(
+        "
+        SELECT id, kind, project_id, task_id, actor, text, create_time, read_time
+        FROM inbox_notifications
+        WHERE email = $1
+        AND (NOT $2 OR read_time IS NULL)
+        ORDER BY create_time DESC
+        LIMIT $3",
+    )
+    .bind(&user.email)
+    .bind(query.unread_only)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list notifications")?;
+
+    Ok(Json(
+        rows.into_iter()
+            .map(NotificationRow::into_notification)
+            .collect::<Result<Vec<_>>>()?,
+    ))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn mark_read_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Json(request): Json<MarkInboxRead>,
+) -> ApiResult<Json<()>> {
+    sqlx::query(
+        "
+        UPDATE inbox_notifications
+        SET read_time = NOW()
+        WHERE email = $1
+        AND read_time IS NULL
+        AND ($2::varchar[] IS NULL OR id = ANY($2))",
+    )
+    .bind(&user.email)
+    .bind(&request.ids)
+    .execute(pool)
+    .await
+    .context("Failed to mark notifications read")?;
+    Ok(Json(()))
+}
+
+/// Stores notifications in users' inboxes and pushes them to any connected clients.
+pub(crate) struct Inbox {
+    pool: &'static PgPool,
+    messenger: UserMessenger,
+}
+
+impl Inbox {
+    pub(crate) fn new(pool: &'static PgPool, messenger: UserMessenger) -> Self {
+        Inbox { pool, messenger }
+    }
+
+    pub(crate) async fn deliver(
+        &self,
+        recipient: &str,
+        kind: InboxKind,
+        project_id: Option<&ProjectId>,
+        task_id: Option<&str>,
+        actor: Option<&str>,
+        text: &str,
+    ) -> Result<()> {
+        let notification = InboxNotification {
+            id: BASE64_URL_SAFE_NO_PAD.encode(uuid::Uuid::new_v4()),
+            kind,
+            project_id: project_id.cloned(),
+            task_id: task_id.map(String::from),
+            actor: actor.map(String::from),
+            text: text.to_string(),
+            create_time: Utc::now(),
+            read_time: None,
+        };
+        sqlx::query(
+            "
+            INSERT INTO inbox_notifications (id, email, kind, project_id, task_id, actor, text, create_time)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        )
+        .bind(&notification.id)
+        .bind(recipient)
+        .bind(kind_to_str(kind))
+        .bind(&notification.project_id)
+        .bind(&notification.task_id)
+        .bind(&notification.actor)
+        .bind(&notification.text)
+        .bind(notification.create_time)
+        .execute(self.pool)
+        .await
+        .context("Failed to insert notification")?;
+
+        self.messenger
+            .send_to_user(
+                recipient,
+                koso_notification(&serde_json::to_string(&notification)?),
+            )
+            .await;
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct NotificationRow {
+    id: String,
+    kind: String,
+    project_id: Option<String>,
+    task_id: Option<String>,
+    actor: Option<String>,
+    text: String,
+    create_time: DateTime<Utc>,
+    read_time: Option<DateTime<Utc>>,
+}
+
+impl NotificationRow {
+    fn into_notification(self) -> Result<InboxNotification> {
+        Ok(InboxNotification {
+            id: self.id,
+            kind: kind_from_str(&self.kind)?,
+            project_id: self.project_id,
+            task_id: self.task_id,
+            actor: self.actor,
+            text: self.text,
+            create_time: self.create_time,
+            read_time: self.read_time,
+        })
+    }
+}
+
+fn kind_to_str(kind: InboxKind) -> &'static str {
+    match kind {
+        InboxKind::Assigned => "assigned",
+        InboxKind::Deadline => "deadline",
+        InboxKind::Unblocked => "unblocked",
+        InboxKind::CommentReply => "commentReply",
+        InboxKind::ChangeProposal => "changeProposal",
+        InboxKind::Anomaly => "anomaly",
+        InboxKind::ImpersonationRequest => "impersonationRequest",
+        InboxKind::DelegatedAssignment => "delegatedAssignment",
+        InboxKind::AbuseReport => "abuseReport",
+        InboxKind::AttachmentQuarantined => "attachmentQuarantined",
+    }
+}
+
+fn kind_from_str(kind: &str) -> Result<InboxKind> {
+    match kind {
+        "assigned" => Ok(InboxKind::Assigned),
+        "deadline" => Ok(InboxKind::Deadline),
+        "unblocked" => Ok(InboxKind::Unblocked),
+        "commentReply" => Ok(InboxKind::CommentReply),
+        "changeProposal" => Ok(InboxKind::ChangeProposal),
+        "anomaly" => Ok(InboxKind::Anomaly),
+        "impersonationRequest" => Ok(InboxKind::ImpersonationRequest),
+        "delegatedAssignment" => Ok(InboxKind::DelegatedAssignment),
+        "abuseReport" => Ok(InboxKind::AbuseReport),
+        "attachmentQuarantined" => Ok(InboxKind::AttachmentQuarantined),
+        kind => Err(anyhow!("Invalid notification kind: {kind}")),
+    }
+}
@@ -0,0 +1,25 @@
+//! The health of a project's integrations, e.g. GitHub, so users can tell
+//! why synced tasks look stale.
+
+use crate::{
+    api::{ApiResult, google::User, model::ProjectId, verify_project_access},
+    plugins::status::{self, SyncStatus},
+    postgres::PgPool,
+};
+use axum::{Extension, Json, Router, extract::Path, routing::get};
+
+pub(super) fn router() -> Router {
+    Router::new().route("/{project_id}/integrations/status", get(status_handler))
+}
+
+/// Reports each connected integration's last successful sync, webhook
+/// events still being applied, recent errors and next scheduled poll.
+#[tracing::instrument(skip(user, pool))]
+async fn status_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Vec<SyncStatus>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    Ok(Json(status::list_for_project(pool, &project_id).await?))
+}
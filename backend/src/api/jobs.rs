@@ -0,0 +1,455 @@
+//! A durable queue of background work, e.g. imports and compaction, stored in
+//! Postgres so queued jobs survive restarts. Each kind of job has a
+//! [JobHandler], registered with the [JobQueue] that claims and runs due jobs.
+//! Failed jobs are retried with exponential backoff until they run out of
+//! attempts, when they're dead-lettered for admins to inspect and retry.
+
+use crate::{
+    api::{
+        ApiResult,
+        collab::{msg_sync::koso_job_progress, projects_state::UserMessenger},
+        context::RequestContext,
+        google::User,
+        model::ProjectId,
+        not_found_error, verify_admin,
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use async_trait::async_trait;
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    routing::{get, post},
+};
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use serde::{Serialize, de::DeserializeOwned};
+use sqlx::{
+    FromRow,
+    types::chrono::{DateTime, Utc},
+};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::{sync::Semaphore, task::JoinHandle};
+use uuid::Uuid;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Running jobs not finished within this long were abandoned, e.g. by a
+/// server that crashed, and are run again.
+const LEASE: Duration = Duration::from_secs(30 * 60);
+const BASE_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+/// Succeeded jobs are kept this long, for introspection.
+const RETENTION_DAYS: i32 = 7;
+
+const JOB_COLUMNS: &str =
+    "job_id, kind, key, status, attempts, run_at, last_error, create_time, finish_time, payload";
+/// For introspection, which omits payloads.
+const SUMMARY_COLUMNS: &str = "job_id, kind, key, status, attempts, run_at, last_error, create_time, finish_time, 'null'::jsonb AS payload";
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/", get(list_jobs_handler))
+        .route("/stats", get(stats_handler))
+        .route("/{job_id}/retry", post(retry_job_handler))
+}
+
+/// Runs jobs of one kind.
+#[async_trait]
+pub(crate) trait JobHandler: Send + Sync {
+    /// The kind of job handled, as stored in `jobs.kind`.
+    fn kind(&self) -> &'static str;
+
+    /// How many jobs of this kind each server runs at once.
+    fn concurrency(&self) -> usize {
+        1
+    }
+
+    /// How many times a job is attempted before it's dead-lettered.
+    fn max_attempts(&self) -> i32 {
+        5
+    }
+
+    /// Runs the job. Errors are retried, so jobs should be safe to re-run.
+    async fn run(&self, job: &Job) -> Result<()>;
+}
+
+#[derive(serde::Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Job {
+    pub(crate) job_id: String,
+    pub(crate) kind: String,
+    pub(crate) key: Option<String>,
+    /// queued, running, succeeded or dead.
+    pub(crate) status: String,
+    /// Attempts started, including the running one.
+    pub(crate) attempts: i32,
+    pub(crate) run_at: DateTime<Utc>,
+    pub(crate) last_error: Option<String>,
+    pub(crate) create_time: DateTime<Utc>,
+    pub(crate) finish_time: Option<DateTime<Utc>>,
+    /// Omitted from introspection, as payloads may be large or sensitive.
+    #[serde(skip)]
+    #[sqlx(json)]
+    pub(crate) payload: serde_json::Value,
+}
+
+impl Job {
+    pub(crate) fn payload<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_value(self.payload.clone())
+            .with_context(|| format!("Invalid payload for {} job {}", self.kind, self.job_id))
+    }
+}
+
+/// A job to enqueue.
+pub(crate) struct NewJob {
+    kind: &'static str,
+    key: Option<String>,
+    payload: serde_json::Value,
+    run_at: Option<DateTime<Utc>>,
+}
+
+impl NewJob {
+    pub(crate) fn new<T: Serialize>(kind: &'static str, payload: &T) -> Result<Self> {
+        Ok(NewJob {
+            kind,
+            key: None,
+            payload: serde_json::to_value(payload).context("Failed to serialize job payload")?,
+            run_at: None,
+        })
+    }
+
+    /// Skips enqueuing if a job of the same kind and key is already waiting
+    /// to run, e.g. to compact each project once.
+    pub(crate) fn key(mut self, key: impl Into<String>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+
+    /// Delays the job until the given time.
+    pub(crate) fn run_at(mut self, run_at: DateTime<Utc>) -> Self {
+        self.run_at = Some(run_at);
+        self
+    }
+}
+
+/// Progress of a long-running job, pushed to the connected clients of the
+/// user who started it so they can follow along without polling.
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct JobProgress<'a> {
+    /// The id the job was started with, e.g. of the import.
+    pub(crate) job_id: &'a str,
+    pub(crate) kind: &'static str,
+    pub(crate) project_id: Option<&'a ProjectId>,
+    pub(crate) status: &'a str,
+    /// Items processed so far, e.g. rows read.
+    pub(crate) processed: usize,
+    pub(crate) failed: usize,
+    /// Items to process, if known up front.
+    pub(crate) total: Option<usize>,
+}
+
+impl JobProgress<'_> {
+    pub(crate) async fn send(&self, messenger: &UserMessenger, email: &str) {
+        match serde_json::to_string(self) {
+            Ok(progress) => {
+                messenger
+                    .send_to_user(email, koso_job_progress(&progress))
+                    .await
+            }
+            Err(e) => tracing::warn!("Failed to serialize job progress: {e:?}"),
+        }
+    }
+}
+
+/// Enqueues the job, returning its id or, if deduplicated by its key, None.
+pub(crate) async fn enqueue(pool: &PgPool, job: NewJob) -> Result<Option<String>> {
+    let job_id: Option<(String,)> = sqlx::query_as(
+        "
+        INSERT INTO jobs (job_id, kind, key, payload, run_at)
+        VALUES ($1, $2, $3, $4, COALESCE($5, NOW()))
+        ON CONFLICT (kind, key) WHERE status = 'queued' AND last_error IS NULL
+        DO NOTHING
+        RETURNING job_id",
+    )
+    .bind(BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4()))
+    .bind(job.kind)
+    .bind(&job.key)
+    .bind(sqlx::types::Json(&job.payload))
+    .bind(job.run_at)
+    .fetch_optional(pool)
+    .await
+    .with_context(|| format!("Failed to enqueue {} job", job.kind))?;
+    Ok(job_id.map(|(job_id,)| job_id))
+}
+
+/// Claims due jobs and runs them with their kind's handler.
+pub(crate) struct JobQueue {
+    pool: &'static PgPool,
+    handlers: HashMap<&'static str, Registered>,
+}
+
+struct Registered {
+    handler: Arc<dyn JobHandler>,
+    /// Bounds the jobs of the kind running at once.
+    slots: Arc<Semaphore>,
+}
+
+impl JobQueue {
+    pub(crate) fn new(pool: &'static PgPool) -> Self {
+        JobQueue {
+            pool,
+            handlers: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn register(mut self, handler: impl JobHandler + 'static) -> Self {
+        let slots = Arc::new(Semaphore::new(handler.concurrency()));
+        self.handlers.insert(
+            handler.kind(),
+            Registered {
+                handler: Arc::new(handler),
+                slots,
+            },
+        );
+        self
+    }
+
+    pub(crate) fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.poll().await {
+                    tracing::warn!("Failed to poll jobs: {e:?}");
+                }
+            }
+        })
+    }
+
+    async fn poll(&self) -> Result<()> {
+        self.requeue_abandoned().await?;
+        for registered in self.handlers.values() {
+            let free = registered.slots.available_permits();
+            if free == 0 {
+                continue;
+            }
+            for job in self.claim(registered.handler.kind(), free).await? {
+                let permit = registered.slots.clone().acquire_owned().await?;
+                let handler = registered.handler.clone();
+                let pool = self.pool;
+                // Jobs run in a context of their own, identified by the job's id.
+                let context = RequestContext::new(job.job_id.clone(), None);
+                tokio::spawn(context.scope(async move {
+                    execute(pool, handler.as_ref(), job).await;
+                    drop(permit);
+                }));
+            }
+        }
+        sqlx::query(
+            "
+            DELETE FROM jobs
+            WHERE status = 'succeeded'
+            AND finish_time < NOW() - make_interval(days => $1)",
+        )
+        .bind(RETENTION_DAYS)
+        .execute(self.pool)
+        .await
+        .context("Failed to prune jobs")?;
+        Ok(())
+    }
+
+    /// Claims up to limit due jobs of the kind. Other servers skip jobs
+    /// being claimed, so each job is claimed once.
+    async fn claim(&self, kind: &str, limit: usize) -> Result<Vec<Job>> {
+        sqlx::query_as(&format!(
+            "
+            UPDATE jobs
+            SET status = 'running', attempts = attempts + 1, start_time = NOW()
+            WHERE job_id IN (
+                SELECT job_id
+                FROM jobs
+                WHERE kind = $1 AND status = 'queued' AND run_at <= NOW()
+                ORDER BY run_at
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING {JOB_COLUMNS}"
+        ))
+        .bind(kind)
+        .bind(limit as i64)
+        .fetch_all(self.pool)
+        .await
+        .with_context(|| format!("Failed to claim {kind} jobs"))
+    }
+
+    async fn requeue_abandoned(&self) -> Result<()> {
+        let requeued = sqlx::query(
+            "
+            UPDATE jobs
+            SET status = 'queued', run_at = NOW(), start_time = NULL, last_error = 'Abandoned'
+            WHERE status = 'running'
+            AND start_time < NOW() - make_interval(secs => $1)",
+        )
+        .bind(LEASE.as_secs_f64())
+        .execute(self.pool)
+        .await
+        .context("Failed to requeue abandoned jobs")?;
+        if requeued.rows_affected() > 0 {
+            tracing::warn!("Requeued {} abandoned jobs", requeued.rows_affected());
+        }
+        Ok(())
+    }
+}
+
+#[tracing::instrument(skip(pool, handler, job), fields(kind = %job.kind, job_id = %job.job_id, attempt = job.attempts))]
+async fn execute(pool: &PgPool, handler: &dyn JobHandler, job: Job) {
+    let outcome = match handler.run(&job).await {
+        // Payloads can be large, e.g. imported exports, so drop them once they're done with.
+        Ok(()) => {
+            sqlx::query(
+                "
+            UPDATE jobs
+            SET status = 'succeeded', payload = 'null', start_time = NULL, finish_time = NOW()
+            WHERE job_id = $1",
+            )
+            .bind(&job.job_id)
+            .execute(pool)
+            .await
+        }
+        Err(e) => {
+            let dead = job.attempts >= handler.max_attempts();
+            if dead {
+                tracing::error!("Job failed {} times, giving up: {e:?}", job.attempts);
+            } else {
+                tracing::warn!("Job failed, will retry: {e:?}");
+            }
+            sqlx::query(
+                "
+                UPDATE jobs
+                SET status = $2,
+                    run_at = NOW() + make_interval(secs => $3),
+                    last_error = $4,
+                    start_time = NULL,
+                    finish_time = CASE WHEN $2 = 'dead' THEN NOW() END
+                WHERE job_id = $1",
+            )
+            .bind(&job.job_id)
+            .bind(if dead { "dead" } else { "queued" })
+            .bind(backoff(job.attempts).as_secs_f64())
+            .bind(format!("{e:#}"))
+            .execute(pool)
+            .await
+        }
+    };
+    if let Err(e) = outcome {
+        tracing::warn!("Failed to record job outcome: {e:?}");
+    }
+}
+
+/// Doubles the delay after each failed attempt.
+fn backoff(attempts: i32) -> Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    BASE_BACKOFF
+        .saturating_mul(2_u32.pow(exponent))
+        .min(MAX_BACKOFF)
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct JobFilter {
+    kind: Option<String>,
+    status: Option<String>,
+}
+
+/// Lists the latest jobs, e.g. the dead-lettered ones with ?status=dead.
+#[tracing::instrument(skip(user, pool))]
+async fn list_jobs_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Query(filter): Query<JobFilter>,
+) -> ApiResult<Json<Vec<Job>>> {
+    verify_admin(&user)?;
+    let jobs: Vec<Job> = sqlx::query_as(&format!(
+        "
+        SELECT {SUMMARY_COLUMNS}
+        FROM jobs
+        WHERE ($1::varchar IS NULL OR kind = $1)
+        AND ($2::varchar IS NULL OR status = $2)
+        ORDER BY create_time DESC
+        LIMIT 100"
+    ))
+    .bind(&filter.kind)
+    .bind(&filter.status)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list jobs")?;
+    Ok(Json(jobs))
+}
+
+#[derive(serde::Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct JobStats {
+    kind: String,
+    status: String,
+    count: i64,
+    /// When the longest waiting job was due.
+    oldest_run_at: DateTime<Utc>,
+}
+
+/// Counts jobs by kind and status, to spot backlogs and failures.
+#[tracing::instrument(skip(user, pool))]
+async fn stats_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<Vec<JobStats>>> {
+    verify_admin(&user)?;
+    let stats: Vec<JobStats> = sqlx::query_as(
+        "
+        SELECT kind, status, COUNT(*) AS count, MIN(run_at) AS oldest_run_at
+        FROM jobs
+        GROUP BY kind, status
+        ORDER BY kind, status",
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to count jobs")?;
+    Ok(Json(stats))
+}
+
+/// Requeues a dead-lettered job with a fresh set of attempts.
+#[tracing::instrument(skip(user, pool))]
+async fn retry_job_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(job_id): Path<String>,
+) -> ApiResult<Json<Job>> {
+    verify_admin(&user)?;
+    let job: Option<Job> = sqlx::query_as(&format!(
+        "
+        UPDATE jobs
+        SET status = 'queued', attempts = 0, run_at = NOW(), finish_time = NULL
+        WHERE job_id = $1 AND status = 'dead'
+        RETURNING {SUMMARY_COLUMNS}"
+    ))
+    .bind(&job_id)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retry job")?;
+    job.map(Json).ok_or_else(|| {
+        not_found_error("NOT_FOUND", &format!("No dead-lettered job {job_id} found"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        assert_eq!(backoff(1), Duration::from_secs(30));
+        assert_eq!(backoff(2), Duration::from_secs(60));
+        assert_eq!(backoff(4), Duration::from_secs(240));
+        assert_eq!(backoff(8), MAX_BACKOFF);
+        assert_eq!(backoff(1000), MAX_BACKOFF);
+    }
+}
@@ -0,0 +1,23 @@
+//! Lets deployment admins of a self-hosted deployment check its license, e.g.
+//! how many seats are used and when it must be renewed by.
+
+use crate::{
+    api::{ApiResult, google::User, verify_admin},
+    license::{self, LicenseStatus},
+    postgres::PgPool,
+};
+use axum::{Extension, Json, Router, routing::get};
+
+pub(super) fn router() -> Router {
+    Router::new().route("/", get(get_license_handler))
+}
+
+/// Returns the deployment's license, or null if it's unlicensed.
+#[tracing::instrument(skip(user, pool))]
+async fn get_license_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<Option<LicenseStatus>>> {
+    verify_admin(&user)?;
+    Ok(Json(license::status(pool).await?))
+}
@@ -0,0 +1,289 @@
+//! Maintenance mode, which freezes projects, or the whole instance, read-only
+//! during migrations and incidents. Clients stay connected and can still read,
+//! but REST writes fail with 503 and websocket updates are dropped with a
+//! message explaining why.
+
+use crate::{
+    api::{
+        ApiResult, bad_request_error, collab::Collab, error_response, google::User, verify_admin,
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::{Query, Request},
+    http::{HeaderValue, Method, StatusCode, header::RETRY_AFTER},
+    middleware::Next,
+    response::{IntoResponse as _, Response},
+    routing::get,
+};
+use serde::Deserialize;
+use sqlx::{
+    FromRow,
+    types::chrono::{DateTime, Utc},
+};
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+
+pub(super) fn router() -> Router {
+    Router::new().route(
+        "/",
+        get(list_freezes_handler)
+            .put(freeze_handler)
+            .delete(unfreeze_handler),
+    )
+}
+
+/// How often each server picks up freezes set on other servers.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+/// Advised to clients when a freeze has no expected end.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, FromRow, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Freeze {
+    /// The frozen project, or None when the whole instance is frozen.
+    pub(crate) project_id: Option<String>,
+    pub(crate) reason: String,
+    pub(crate) end_time: Option<DateTime<Utc>>,
+    pub(crate) created_by: String,
+    pub(crate) create_time: DateTime<Utc>,
+}
+
+impl Freeze {
+    /// How long clients should wait before retrying a rejected write.
+    pub(crate) fn retry_after(&self) -> Duration {
+        self.end_time
+            .and_then(|end_time| (end_time - Utc::now()).to_std().ok())
+            .filter(|d| !d.is_zero())
+            .unwrap_or(DEFAULT_RETRY_AFTER)
+    }
+}
+
+/// The active freezes, cached in memory so checking writes doesn't cost a query.
+#[derive(Clone)]
+pub(crate) struct Maintenance {
+    pool: &'static PgPool,
+    freezes: Arc<RwLock<Vec<Freeze>>>,
+}
+
+impl Maintenance {
+    pub(crate) fn new(pool: &'static PgPool) -> Self {
+        Maintenance {
+            pool,
+            freezes: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+
+    /// Returns the freeze preventing writes to the project, if any. Passing
+    /// None only checks whether the whole instance is frozen.
+    pub(crate) fn frozen(&self, project_id: Option<&str>) -> Option<Freeze> {
+        let freezes = self.freezes.read().unwrap();
+        freezes
+            .iter()
+            .find(|f| f.project_id.is_none())
+            .or_else(|| {
+                let project_id = project_id?;
+                freezes
+                    .iter()
+                    .find(|f| f.project_id.as_deref() == Some(project_id))
+            })
+            .cloned()
+    }
+
+    /// Reloads freezes every few seconds.
+    pub(crate) fn start_refreshing(&self) -> JoinHandle<()> {
+        let maintenance = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = maintenance.refresh().await {
+                    tracing::warn!("Failed to refresh maintenance freezes: {e:?}");
+                }
+            }
+        })
+    }
+
+    async fn refresh(&self) -> Result<()> {
+        let freezes = list_freezes(self.pool).await?;
+        *self.freezes.write().unwrap() = freezes;
+        Ok(())
+    }
+}
+
+async fn list_freezes(pool: &PgPool) -> Result<Vec<Freeze>> {
+    sqlx::query_as(
+        "
+        SELECT project_id, reason, end_time, created_by, create_time
+        FROM maintenance_freezes
+        ORDER BY project_id NULLS FIRST",
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list maintenance freezes")
+}
+
+/// Rejects REST writes to frozen projects, or to anything when the instance is frozen,
+/// with a 503 and a Retry-After header. Reads and the maintenance API itself pass through.
+pub(crate) async fn reject_writes(request: Request, next: Next) -> Response {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return next.run(request).await;
+    }
+    let path = request.uri().path();
+    let path = path.strip_prefix("/api").unwrap_or(path);
+    if path.starts_with("/maintenance") {
+        return next.run(request).await;
+    }
+    let Some(collab) = request.extensions().get::<Collab>() else {
+        return next.run(request).await;
+    };
+    let project_id = path
+        .strip_prefix("/projects/")
+        .and_then(|rest| rest.split('/').next())
+        .filter(|project_id| !project_id.is_empty());
+    let Some(freeze) = collab.maintenance().frozen(project_id) else {
+        return next.run(request).await;
+    };
+
+    let mut response = error_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "MAINTENANCE",
+        Some(&freeze.reason),
+        None,
+    )
+    .into_response();
+    response.headers_mut().insert(
+        RETRY_AFTER,
+        HeaderValue::from(freeze.retry_after().as_secs()),
+    );
+    response
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn list_freezes_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<Vec<Freeze>>> {
+    verify_admin(&user)?;
+    Ok(Json(list_freezes(pool).await?))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FreezeRequest {
+    /// The project to freeze, or None to freeze the whole instance.
+    project_id: Option<String>,
+    reason: String,
+    end_time: Option<DateTime<Utc>>,
+}
+
+/// Freezes a project or the instance, replacing any existing freeze of it.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn freeze_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Json(req): Json<FreezeRequest>,
+) -> ApiResult<Json<Freeze>> {
+    verify_admin(&user)?;
+    let reason = req.reason.trim();
+    if reason.is_empty() {
+        return Err(bad_request_error(
+            "EMPTY_REASON",
+            "Freezes must give users a reason",
+        ));
+    }
+
+    let freeze: Freeze = sqlx::query_as(
+        "
+        INSERT INTO maintenance_freezes (project_id, reason, end_time, created_by)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT ((COALESCE(project_id, '')))
+        DO UPDATE SET
+          reason = EXCLUDED.reason,
+          end_time = EXCLUDED.end_time,
+          created_by = EXCLUDED.created_by,
+          create_time = NOW()
+        RETURNING project_id, reason, end_time, created_by, create_time",
+    )
+    .bind(&req.project_id)
+    .bind(reason)
+    .bind(req.end_time)
+    .bind(&user.email)
+    .fetch_one(pool)
+    .await
+    .context("Failed to freeze")?;
+    tracing::info!(
+        "Froze {} for maintenance: {}",
+        freeze.project_id.as_deref().unwrap_or("the instance"),
+        freeze.reason
+    );
+    // Apply it here right away rather than on the next refresh.
+    collab.maintenance().refresh().await?;
+    Ok(Json(freeze))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UnfreezeQuery {
+    project_id: Option<String>,
+}
+
+#[tracing::instrument(skip(user, pool, collab))]
+async fn unfreeze_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Query(query): Query<UnfreezeQuery>,
+) -> ApiResult<()> {
+    verify_admin(&user)?;
+    sqlx::query(
+        "DELETE FROM maintenance_freezes WHERE COALESCE(project_id, '') = COALESCE($1, '')",
+    )
+    .bind(&query.project_id)
+    .execute(pool)
+    .await
+    .context("Failed to unfreeze")?;
+    tracing::info!(
+        "Unfroze {}",
+        query.project_id.as_deref().unwrap_or("the instance")
+    );
+    collab.maintenance().refresh().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    fn freeze(project_id: Option<&str>, end_time: Option<DateTime<Utc>>) -> Freeze {
+        Freeze {
+            project_id: project_id.map(str::to_string),
+            reason: "Migrating".to_string(),
+            end_time,
+            created_by: "admin@koso.app".to_string(),
+            create_time: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn retry_after_defaults_without_a_future_end_time() {
+        assert_eq!(freeze(None, None).retry_after(), DEFAULT_RETRY_AFTER);
+        assert_eq!(
+            freeze(None, Some(Utc::now() - TimeDelta::minutes(5))).retry_after(),
+            DEFAULT_RETRY_AFTER
+        );
+        let retry_after = freeze(None, Some(Utc::now() + TimeDelta::minutes(10))).retry_after();
+        assert!(retry_after > Duration::from_secs(9 * 60));
+        assert!(retry_after <= Duration::from_secs(10 * 60));
+    }
+}
@@ -0,0 +1,87 @@
+//! Pausing plugin management of individual tasks, e.g. a GitHub PR task, so
+//! users can annotate or re-parent it without the next sync undoing their
+//! changes. Paused tasks are skipped by syncs and editable like any other.
+
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{
+            Collab,
+            projects_state::DocBox,
+            txn_origin::{Actor, YOrigin},
+        },
+        google::User,
+        model::ProjectId,
+        not_found_error, verify_project_access,
+    },
+    postgres::PgPool,
+};
+use axum::{Extension, Json, Router, extract::Path, routing::put};
+
+pub(super) fn router() -> Router {
+    Router::new().route(
+        "/{project_id}/tasks/{task_id}/managementPaused",
+        put(pause_handler).delete(unpause_handler),
+    )
+}
+
+#[tracing::instrument(skip(user, pool, collab))]
+async fn pause_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path((project_id, task_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<()>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    set_management_paused(&collab, &user, &project_id, &task_id, true).await?;
+    Ok(Json(()))
+}
+
+/// Hands the task back to its plugin. The next sync overwrites local changes
+/// to the fields it manages.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn unpause_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path((project_id, task_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<()>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    set_management_paused(&collab, &user, &project_id, &task_id, false).await?;
+    Ok(Json(()))
+}
+
+async fn set_management_paused(
+    collab: &Collab,
+    user: &User,
+    project_id: &ProjectId,
+    task_id: &str,
+    paused: bool,
+) -> ApiResult<()> {
+    let client = collab.register_local_client(project_id).await?;
+    let doc_box = client.project.doc_box.lock().await;
+    let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+    let mut txn = doc.transact_mut_with(
+        YOrigin {
+            who: "management".to_string(),
+            id: format!("management_{task_id}"),
+            actor: Actor::User(user.clone()),
+            ..Default::default()
+        }
+        .as_origin()?,
+    );
+    let Ok(task) = doc.get(&txn, task_id) else {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Task {task_id} not found"),
+        ));
+    };
+    if !task.is_managed_kind(&txn)? {
+        return Err(bad_request_error(
+            "NOT_MANAGED",
+            "Only tasks managed by a plugin can be paused",
+        ));
+    }
+    task.set_management_paused(&mut txn, paused);
+    Ok(())
+}
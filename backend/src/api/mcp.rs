@@ -0,0 +1,502 @@
+//! A Model Context Protocol (MCP) server exposing Koso tools to AI agents.
+//!
+//! Implements the Streamable HTTP transport: clients POST JSON-RPC messages
+//! and receive JSON responses. The server never initiates messages, so there's
+//! no SSE stream to GET. Clients limited to stdio can connect through a
+//! stdio-to-HTTP bridge such as `mcp-remote`.
+//!
+//! Requests authenticate with the same personal API keys as the Zapier
+//! integration and act on behalf of the key's owner.
+//! See https://modelcontextprotocol.io/specification/2025-06-18
+
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{
+            Collab,
+            projects_state::DocBox,
+            txn_origin::{Actor, TxnMetadata, YOrigin},
+        },
+        google::User,
+        model::ProjectId,
+        projects::fetch_task_key_prefix,
+        search, verify_project_access,
+        yproxy::{YDocProxy, YTaskProxy, task_key},
+        zapier::{self, CreateTask},
+    },
+    postgres::PgPool,
+};
+use anyhow::Result;
+use axum::{
+    Extension, Json, Router,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tower_http::request_id::RequestId;
+use yrs::ReadTxn;
+
+const PROTOCOL_VERSIONS: [&str; 3] = ["2025-06-18", "2025-03-26", "2024-11-05"];
+const DEFAULT_DEPTH: usize = 2;
+const MAX_DEPTH: usize = 5;
+/// Bounds the size of subtrees returned to agents.
+const MAX_SUBTREE_TASKS: usize = 500;
+
+// JSON-RPC error codes.
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Invoked by MCP clients and authenticated with API keys.
+pub(super) fn api_router() -> Router {
+    Router::new().route("/", post(mcp_handler).get(sse_handler))
+}
+
+#[derive(Deserialize, Debug)]
+struct JsonRpcRequest {
+    /// Absent on notifications.
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonRpcResponse {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<JsonRpcError>,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonRpcError {
+    code: i64,
+    message: String,
+}
+
+impl JsonRpcResponse {
+    fn result(id: Value, result: Value) -> Self {
+        JsonRpcResponse {
+            jsonrpc: "2.0",
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    fn error(id: Value, code: i64, message: String) -> Self {
+        JsonRpcResponse {
+            jsonrpc: "2.0",
+            id,
+            result: None,
+            error: Some(JsonRpcError { code, message }),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ToolCall {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SearchTasksArgs {
+    query: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CreateTaskArgs {
+    project_id: ProjectId,
+    name: String,
+    parent: Option<String>,
+    description: Option<String>,
+    assignee: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UpdateStatusArgs {
+    project_id: ProjectId,
+    task: String,
+    status: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GetSubtreeArgs {
+    project_id: ProjectId,
+    task: Option<String>,
+    depth: Option<usize>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SubtreeTask {
+    id: String,
+    key: String,
+    name: String,
+    status: Option<String>,
+    assignee: Option<String>,
+    /// Omitted below the requested depth.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    children: Option<Vec<SubtreeTask>>,
+}
+
+#[tracing::instrument(skip(pool, collab, headers, body))]
+async fn mcp_handler(
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Extension(request_id): Extension<RequestId>,
+    headers: HeaderMap,
+    body: String,
+) -> ApiResult<Response> {
+    let user = zapier::authenticate(pool, &headers).await?;
+    let request: JsonRpcRequest = match serde_json::from_str(&body) {
+        Ok(request) => request,
+        Err(e) => {
+            return Ok(Json(JsonRpcResponse::error(
+                Value::Null,
+                PARSE_ERROR,
+                format!("Invalid JSON-RPC message: {e}"),
+            ))
+            .into_response());
+        }
+    };
+    // Notifications, e.g. notifications/initialized, need no response.
+    let Some(id) = request.id else {
+        tracing::debug!("Received MCP notification {}", request.method);
+        return Ok(StatusCode::ACCEPTED.into_response());
+    };
+
+    let response = match request.method.as_str() {
+        "initialize" => JsonRpcResponse::result(id, initialize(&request.params)),
+        "ping" => JsonRpcResponse::result(id, json!({})),
+        "tools/list" => JsonRpcResponse::result(id, json!({ "tools": tools() })),
+        "tools/call" => match serde_json::from_value::<ToolCall>(request.params) {
+            Ok(call) => {
+                let origin = origin(&user, &request_id, &call.name);
+                let result = call_tool(pool, &collab, &user, call, origin).await;
+                JsonRpcResponse::result(id, tool_result(result))
+            }
+            Err(e) => JsonRpcResponse::error(id, INVALID_PARAMS, format!("Invalid params: {e}")),
+        },
+        method => {
+            JsonRpcResponse::error(id, METHOD_NOT_FOUND, format!("Method not found: {method}"))
+        }
+    };
+    Ok(Json(response).into_response())
+}
+
+/// The server never sends unsolicited messages, so it doesn't offer an SSE stream.
+async fn sse_handler() -> StatusCode {
+    StatusCode::METHOD_NOT_ALLOWED
+}
+
+fn initialize(params: &Value) -> Value {
+    // Use the client's version if supported, otherwise propose the latest.
+    let requested = params.get("protocolVersion").and_then(Value::as_str);
+    let version = PROTOCOL_VERSIONS
+        .into_iter()
+        .find(|v| Some(*v) == requested)
+        .unwrap_or(PROTOCOL_VERSIONS[0]);
+    json!({
+        "protocolVersion": version,
+        "capabilities": { "tools": {} },
+        "serverInfo": { "name": "koso", "version": env!("CARGO_PKG_VERSION") },
+        "instructions": "Koso is a task planner. Tasks form a tree in each project. \
+            Use search_tasks to find tasks and their project ids, get_subtree to explore \
+            a project's tree, and create_task or update_status to make changes. \
+            Tasks can be referred to by id or key, e.g. KOSO-12 or #12."
+    })
+}
+
+fn tools() -> Value {
+    json!([
+        {
+            "name": "search_tasks",
+            "description": "Searches the names and keys of tasks in every project the user can access.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "Text or a task key, e.g. KOSO-12." }
+                },
+                "required": ["query"]
+            },
+            "annotations": { "readOnlyHint": true }
+        },
+        {
+            "name": "get_subtree",
+            "description": "Returns a task and its descendants, excluding archived tasks.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "projectId": { "type": "string" },
+                    "task": { "type": "string", "description": "A task id or key. Defaults to the project's root." },
+                    "depth": { "type": "integer", "minimum": 1, "maximum": MAX_DEPTH, "default": DEFAULT_DEPTH }
+                },
+                "required": ["projectId"]
+            },
+            "annotations": { "readOnlyHint": true }
+        },
+        {
+            "name": "create_task",
+            "description": "Creates a task, reported by the user.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "projectId": { "type": "string" },
+                    "name": { "type": "string" },
+                    "parent": { "type": "string", "description": "A task id or key. Defaults to the project's root." },
+                    "description": { "type": "string", "description": "Markdown." },
+                    "assignee": { "type": "string", "description": "Email of a project member." }
+                },
+                "required": ["projectId", "name"]
+            }
+        },
+        {
+            "name": "update_status",
+            "description": "Moves a task to a status, e.g. Not Started, In Progress or Done.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "projectId": { "type": "string" },
+                    "task": { "type": "string", "description": "A task id or key." },
+                    "status": { "type": "string" }
+                },
+                "required": ["projectId", "task", "status"]
+            },
+            "annotations": { "idempotentHint": true }
+        }
+    ])
+}
+
+async fn call_tool(
+    pool: &'static PgPool,
+    collab: &Collab,
+    user: &User,
+    call: ToolCall,
+    origin: YOrigin,
+) -> ApiResult<Value> {
+    match call.name.as_str() {
+        "search_tasks" => {
+            let args: SearchTasksArgs = parse_args(call.arguments)?;
+            Ok(serde_json::to_value(
+                search::search_tasks(pool, &user.email, &args.query).await?,
+            )?)
+        }
+        "get_subtree" => {
+            let args: GetSubtreeArgs = parse_args(call.arguments)?;
+            Ok(serde_json::to_value(
+                get_subtree(pool, collab, user, &args).await?,
+            )?)
+        }
+        "create_task" => {
+            let args: CreateTaskArgs = parse_args(call.arguments)?;
+            let req = CreateTask {
+                name: args.name,
+                parent_id: args.parent,
+                description: args.description,
+                assignee: args.assignee,
+                status: None,
+            };
+            Ok(serde_json::to_value(
+                zapier::create_task(pool, collab, user, &args.project_id, req, origin).await?,
+            )?)
+        }
+        "update_status" => {
+            let args: UpdateStatusArgs = parse_args(call.arguments)?;
+            Ok(serde_json::to_value(
+                zapier::update_status(
+                    pool,
+                    collab,
+                    user,
+                    &args.project_id,
+                    &args.task,
+                    &args.status,
+                    origin,
+                )
+                .await?,
+            )?)
+        }
+        name => Err(bad_request_error(
+            "UNKNOWN_TOOL",
+            &format!("Unknown tool: {name}"),
+        )),
+    }
+}
+
+fn parse_args<T: serde::de::DeserializeOwned>(arguments: Value) -> ApiResult<T> {
+    serde_json::from_value(arguments)
+        .map_err(|e| bad_request_error("INVALID_ARGUMENTS", &format!("Invalid arguments: {e}")))
+}
+
+/// Errors are reported in the result, rather than as JSON-RPC errors, so the
+/// agent can see them and correct itself.
+fn tool_result(result: ApiResult<Value>) -> Value {
+    match result {
+        Ok(value) => json!({
+            "content": [{ "type": "text", "text": value.to_string() }],
+            "structuredContent": { "result": value },
+            "isError": false
+        }),
+        Err(e) => json!({
+            "content": [{ "type": "text", "text": e.message() }],
+            "structuredContent": { "code": e.code() },
+            "isError": true
+        }),
+    }
+}
+
+fn origin(user: &User, request_id: &RequestId, tool: &str) -> YOrigin {
+    let request_id = request_id.header_value().to_str().unwrap_or("INVALID");
+    YOrigin {
+        who: "mcp".to_string(),
+        id: format!("mcp_{tool}_{request_id}"),
+        actor: Actor::Agent(user.clone()),
+        metadata: TxnMetadata {
+            request_id: Some(request_id.to_string()),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+async fn get_subtree(
+    pool: &PgPool,
+    collab: &Collab,
+    user: &User,
+    args: &GetSubtreeArgs,
+) -> ApiResult<SubtreeTask> {
+    verify_project_access(pool, user, &args.project_id).await?;
+    let depth = args.depth.unwrap_or(DEFAULT_DEPTH);
+    if !(1..=MAX_DEPTH).contains(&depth) {
+        return Err(bad_request_error(
+            "INVALID_DEPTH",
+            &format!("Depth must be between 1 and {MAX_DEPTH}"),
+        ));
+    }
+    let task_key_prefix = fetch_task_key_prefix(pool, &args.project_id).await?;
+
+    let client = collab.register_local_client(&args.project_id).await?;
+    let doc_box = client.project.doc_box.lock().await;
+    let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+    let txn = doc.transact();
+    let task = match &args.task {
+        Some(task) => zapier::find_task(doc, &txn, task, task_key_prefix.as_deref())?,
+        None => doc.get(&txn, "root")?,
+    };
+    let mut remaining = MAX_SUBTREE_TASKS;
+    Ok(subtree(
+        doc,
+        &txn,
+        &task,
+        depth,
+        task_key_prefix.as_deref(),
+        &mut remaining,
+    )?)
+}
+
+fn subtree<T: ReadTxn>(
+    doc: &YDocProxy,
+    txn: &T,
+    task: &YTaskProxy,
+    depth: usize,
+    task_key_prefix: Option<&str>,
+    remaining: &mut usize,
+) -> Result<SubtreeTask> {
+    *remaining = remaining.saturating_sub(1);
+    let children = if depth == 0 {
+        None
+    } else {
+        let mut children = Vec::new();
+        for child_id in task.get_children(txn)? {
+            if *remaining == 0 {
+                break;
+            }
+            let child = doc.get(txn, &child_id)?;
+            if child.get_archived(txn)?.unwrap_or(false) {
+                continue;
+            }
+            children.push(subtree(
+                doc,
+                txn,
+                &child,
+                depth - 1,
+                task_key_prefix,
+                remaining,
+            )?);
+        }
+        Some(children)
+    };
+    Ok(SubtreeTask {
+        id: task.get_id(txn)?,
+        key: task_key(task_key_prefix, &task.get_num(txn)?),
+        name: task.get_name(txn)?,
+        status: task.get_status(txn)?,
+        assignee: task.get_assignee(txn)?,
+        children,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initialize_negotiates_protocol_version() {
+        let result = initialize(&json!({ "protocolVersion": "2025-03-26" }));
+        assert_eq!(result["protocolVersion"], "2025-03-26");
+        let result = initialize(&json!({ "protocolVersion": "1999-01-01" }));
+        assert_eq!(result["protocolVersion"], PROTOCOL_VERSIONS[0]);
+        assert!(result["capabilities"]["tools"].is_object());
+    }
+
+    #[test]
+    fn tools_have_schemas() {
+        let tools = tools();
+        let names: Vec<&str> = tools
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| {
+                assert_eq!(t["inputSchema"]["type"], "object");
+                t["name"].as_str().unwrap()
+            })
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "search_tasks",
+                "get_subtree",
+                "create_task",
+                "update_status"
+            ]
+        );
+    }
+
+    #[test]
+    fn tool_result_reports_errors_to_the_agent() {
+        let result = tool_result(Err(bad_request_error(
+            "INVALID_STATUS",
+            "Invalid status: X",
+        )));
+        assert_eq!(result["isError"], true);
+        assert_eq!(
+            result["content"][0]["text"],
+            "INVALID_STATUS: Invalid status: X"
+        );
+
+        let result = tool_result(Ok(json!({ "id": "a" })));
+        assert_eq!(result["isError"], false);
+        assert_eq!(result["content"][0]["text"], r#"{"id":"a"}"#);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        google::User,
+        model::{AssignedTask, Deadline},
+    },
+    postgres::PgPool,
+};
+use anyhow::Context as _;
+use axum::{Extension, Json, Router, extract::Query, routing::get};
+use serde::Deserialize;
+use sqlx::types::Json as SqlJson;
+
+pub(super) fn router() -> Router {
+    Router::new().route("/tasks", get(list_my_tasks_handler))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct MyTasksQuery {
+    /// Comma separated list of statuses to include. Tasks without a status are "Not Started".
+    status: Option<String>,
+    /// Only include tasks due before this time, in millis since the epoch.
+    deadline_before: Option<i64>,
+    /// Only include tasks due at or after this time, in millis since the epoch.
+    deadline_after: Option<i64>,
+    #[serde(default)]
+    include_archived: bool,
+}
+
+/// Lists the tasks assigned to the user across all of their projects.
+#[tracing::instrument(skip(user, pool))]
+async fn list_my_tasks_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Query(query): Query<MyTasksQuery>,
+) -> ApiResult<Json<Vec<AssignedTask>>> {
+    let statuses: Option<Vec<String>> = query.status.as_ref().map(|s| {
+        s.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect()
+    });
+    if statuses.as_ref().is_some_and(Vec::is_empty) {
+        return Err(bad_request_error("EMPTY_STATUS", "No statuses specified"));
+    }
+
+    let tasks: Vec<AssignedTaskRow> = sqlx::query_as(
+        "
+        SELECT
+            t.project_id,
+            p.name AS project_name,
+            t.task_id,
+            t.num,
+            t.name,
+            t.status,
+            t.kind,
+            t.deadline
+        FROM task_projections t
+        JOIN project_permissions pp ON pp.project_id = t.project_id AND pp.email = t.assignee
+        JOIN projects p ON p.project_id = t.project_id
+        WHERE t.assignee = $1
+        AND p.deleted_on IS NULL
+        AND ($2 OR NOT t.archived)
+        AND ($3::varchar[] IS NULL OR COALESCE(t.status, 'Not Started') = ANY($3))
+        AND ($4::bigint IS NULL OR t.deadline_millis < $4)
+        AND ($5::bigint IS NULL OR t.deadline_millis >= $5)
+        ORDER BY t.deadline_millis ASC NULLS LAST, p.name, t.project_id, t.num
+        LIMIT 1000",
+    )
+    .bind(&user.email)
+    .bind(query.include_archived)
+    .bind(&statuses)
+    .bind(query.deadline_before)
+    .bind(query.deadline_after)
+    .fetch_all(pool)
+    .await
+    .context("Failed to query assigned tasks")?;
+
+    Ok(Json(
+        tasks
+            .into_iter()
+            .map(|t| AssignedTask {
+                project_id: t.project_id,
+                project_name: t.project_name,
+                task_id: t.task_id,
+                num: t.num,
+                name: t.name,
+                status: t.status,
+                kind: t.kind,
+                deadline: t.deadline.map(|SqlJson(d)| d),
+            })
+            .collect(),
+    ))
+}
+
+#[derive(sqlx::FromRow)]
+struct AssignedTaskRow {
+    project_id: String,
+    project_name: String,
+    task_id: String,
+    num: String,
+    name: String,
+    status: Option<String>,
+    kind: Option<String>,
+    deadline: Option<SqlJson<Deadline>>,
+}
@@ -0,0 +1,462 @@
+use crate::{
+    api::{
+        ApiResult, analytics, bad_request_error,
+        collab::Collab,
+        forecast::{self, CompletionDates},
+        goals::{load_graph, validate_owner, validate_task_ids},
+        google::User,
+        model::{
+            CreateMilestone, Graph, Milestone, MilestoneHealth, ProjectId, TaskProgress,
+            UpdateMilestone, WorkflowState,
+        },
+        not_found_error,
+        reports::escape_html,
+        verify_project_access,
+    },
+    notifiers::Notifiers,
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    routing::{get, patch},
+};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use sqlx::types::{
+    Json as SqlJson,
+    chrono::{DateTime, NaiveDate, Utc},
+};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route(
+            "/{project_id}/milestones",
+            get(list_milestones_handler).post(create_milestone_handler),
+        )
+        .route(
+            "/{project_id}/milestones/{milestone_id}",
+            patch(update_milestone_handler).delete(delete_milestone_handler),
+        )
+}
+
+const MAX_NAME_LEN: usize = 200;
+/// How often to recompute health and alert on milestones that degraded.
+const MONITOR_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+#[derive(sqlx::FromRow)]
+struct MilestoneRow {
+    id: String,
+    name: String,
+    target_date: NaiveDate,
+    owner: Option<String>,
+    task_ids: SqlJson<Vec<String>>,
+    creator: String,
+    health: Option<String>,
+    create_time: DateTime<Utc>,
+    update_time: DateTime<Utc>,
+}
+
+/// Everything needed to judge the health of a project's milestones.
+struct Schedule {
+    graph: Graph,
+    workflow_states: Vec<WorkflowState>,
+    throughput: Vec<u64>,
+    today: NaiveDate,
+}
+
+impl Schedule {
+    async fn load(pool: &PgPool, collab: &Collab, project_id: &ProjectId) -> Result<Schedule> {
+        let (graph, workflow_states) = load_graph(collab, project_id).await?;
+        let today = Utc::now().date_naive();
+        let throughput =
+            forecast::load_throughput(pool, project_id, today, forecast::DEFAULT_DAYS).await?;
+        Ok(Schedule {
+            graph,
+            workflow_states,
+            throughput,
+            today,
+        })
+    }
+
+    fn to_milestone(&self, row: MilestoneRow) -> Milestone {
+        let progress = analytics::leaf_progress(&self.graph, &self.workflow_states, &row.task_ids);
+        let dates = (progress.done < progress.total && self.throughput.iter().any(|&t| t > 0))
+            .then(|| {
+                forecast::completion_dates(
+                    &self.throughput,
+                    progress.total - progress.done,
+                    self.today,
+                )
+            });
+        Milestone {
+            health: health(progress, dates.as_ref(), row.target_date, self.today),
+            forecast_date: dates.and_then(|d| d.p85),
+            progress,
+            id: row.id,
+            name: row.name,
+            target_date: row.target_date,
+            owner: row.owner,
+            task_ids: row.task_ids.0,
+            creator: row.creator,
+            create_time: row.create_time,
+            update_time: row.update_time,
+        }
+    }
+}
+
+/// Judges whether the remaining tasks will be done by the target date.
+/// `dates` is absent when no tasks remain or there's no throughput history.
+fn health(
+    progress: TaskProgress,
+    dates: Option<&CompletionDates>,
+    target_date: NaiveDate,
+    today: NaiveDate,
+) -> MilestoneHealth {
+    if progress.total == 0 {
+        return MilestoneHealth::Unknown;
+    }
+    if progress.done == progress.total {
+        return MilestoneHealth::Complete;
+    }
+    if target_date < today {
+        return MilestoneHealth::OffTrack;
+    }
+    let Some(dates) = dates else {
+        return MilestoneHealth::Unknown;
+    };
+    match (dates.p50, dates.p85) {
+        (_, Some(p85)) if p85 <= target_date => MilestoneHealth::OnTrack,
+        (Some(p50), _) if p50 <= target_date => MilestoneHealth::AtRisk,
+        _ => MilestoneHealth::OffTrack,
+    }
+}
+
+/// Orders health from best to worst, ignoring health that isn't a forecast.
+fn severity(health: MilestoneHealth) -> u8 {
+    match health {
+        MilestoneHealth::Complete | MilestoneHealth::Unknown | MilestoneHealth::OnTrack => 0,
+        MilestoneHealth::AtRisk => 1,
+        MilestoneHealth::OffTrack => 2,
+    }
+}
+
+fn health_to_str(health: MilestoneHealth) -> &'static str {
+    match health {
+        MilestoneHealth::Complete => "complete",
+        MilestoneHealth::OnTrack => "onTrack",
+        MilestoneHealth::AtRisk => "atRisk",
+        MilestoneHealth::OffTrack => "offTrack",
+        MilestoneHealth::Unknown => "unknown",
+    }
+}
+
+pub(crate) fn health_from_str(health: &str) -> Option<MilestoneHealth> {
+    Some(match health {
+        "complete" => MilestoneHealth::Complete,
+        "onTrack" => MilestoneHealth::OnTrack,
+        "atRisk" => MilestoneHealth::AtRisk,
+        "offTrack" => MilestoneHealth::OffTrack,
+        "unknown" => MilestoneHealth::Unknown,
+        _ => return None,
+    })
+}
+
+/// Lists the project's milestones, soonest first, with their current health.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn list_milestones_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Vec<Milestone>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let schedule = Schedule::load(pool, &collab, &project_id).await?;
+    Ok(Json(
+        list_milestones(pool, &project_id)
+            .await?
+            .into_iter()
+            .map(|row| schedule.to_milestone(row))
+            .collect(),
+    ))
+}
+
+#[tracing::instrument(skip(user, pool, collab))]
+async fn create_milestone_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+    Json(milestone): Json<CreateMilestone>,
+) -> ApiResult<Json<Milestone>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    validate_name(&milestone.name)?;
+    validate_owner(pool, &project_id, milestone.owner.as_deref()).await?;
+    let schedule = Schedule::load(pool, &collab, &project_id).await?;
+    validate_task_ids(&schedule.graph, &milestone.task_ids)?;
+
+    let created: MilestoneRow = sqlx::query_as(
+        "
+        INSERT INTO milestones (id, project_id, name, target_date, owner, task_ids, creator)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, name, target_date, owner, task_ids, creator, health, create_time, update_time",
+    )
+    .bind(BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4()))
+    .bind(&project_id)
+    .bind(milestone.name.trim())
+    .bind(milestone.target_date)
+    .bind(&milestone.owner)
+    .bind(SqlJson(&milestone.task_ids))
+    .bind(&user.email)
+    .fetch_one(pool)
+    .await
+    .context("Failed to insert milestone")?;
+    Ok(Json(schedule.to_milestone(created)))
+}
+
+#[tracing::instrument(skip(user, pool, collab))]
+async fn update_milestone_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path((project_id, milestone_id)): Path<(ProjectId, String)>,
+    Json(update): Json<UpdateMilestone>,
+) -> ApiResult<Json<Milestone>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    if let Some(name) = &update.name {
+        validate_name(name)?;
+    }
+    validate_owner(pool, &project_id, update.owner.as_deref()).await?;
+    let schedule = Schedule::load(pool, &collab, &project_id).await?;
+    if let Some(task_ids) = &update.task_ids {
+        validate_task_ids(&schedule.graph, task_ids)?;
+    }
+
+    let updated: Option<MilestoneRow> = sqlx::query_as(
+        "
+        UPDATE milestones
+        SET name = COALESCE($3, name),
+            target_date = COALESCE($4, target_date),
+            owner = COALESCE($5, owner),
+            task_ids = COALESCE($6, task_ids),
+            update_time = NOW()
+        WHERE project_id = $1 AND id = $2
+        RETURNING id, name, target_date, owner, task_ids, creator, health, create_time, update_time",
+    )
+    .bind(&project_id)
+    .bind(&milestone_id)
+    .bind(update.name.as_deref().map(str::trim))
+    .bind(update.target_date)
+    .bind(&update.owner)
+    .bind(update.task_ids.as_ref().map(SqlJson))
+    .fetch_optional(pool)
+    .await
+    .context("Failed to update milestone")?;
+    let Some(updated) = updated else {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Milestone {milestone_id} not found"),
+        ));
+    };
+    Ok(Json(schedule.to_milestone(updated)))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_milestone_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, milestone_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<()>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let res = sqlx::query("DELETE FROM milestones WHERE project_id = $1 AND id = $2")
+        .bind(&project_id)
+        .bind(&milestone_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete milestone")?;
+    if res.rows_affected() == 0 {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Milestone {milestone_id} not found"),
+        ));
+    }
+    Ok(Json(()))
+}
+
+async fn list_milestones(pool: &PgPool, project_id: &ProjectId) -> Result<Vec<MilestoneRow>> {
+    sqlx::query_as(
+        "
+        SELECT id, name, target_date, owner, task_ids, creator, health, create_time, update_time
+        FROM milestones
+        WHERE project_id = $1
+        ORDER BY target_date, create_time",
+    )
+    .bind(project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list milestones")
+}
+
+fn validate_name(name: &str) -> ApiResult<()> {
+    if name.trim().is_empty() {
+        return Err(bad_request_error("EMPTY_NAME", "Milestone name is blank"));
+    }
+    if name.trim().len() > MAX_NAME_LEN {
+        return Err(bad_request_error(
+            "LONG_NAME",
+            &format!("Milestone name cannot be longer than {MAX_NAME_LEN} characters"),
+        ));
+    }
+    Ok(())
+}
+
+/// Periodically recomputes the health of every milestone and alerts its
+/// owner, or its creator if unowned, through their notifiers when it degrades.
+pub(crate) struct MilestoneMonitor {
+    pool: &'static PgPool,
+    collab: Collab,
+    notifier: Notifiers,
+}
+
+impl MilestoneMonitor {
+    pub(crate) fn new(pool: &'static PgPool, collab: Collab) -> Result<Self> {
+        Ok(MilestoneMonitor {
+            pool,
+            collab,
+            notifier: Notifiers::new(pool)?,
+        })
+    }
+
+    pub(crate) fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(MONITOR_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.check_projects().await {
+                    tracing::warn!("Failed to check milestones: {e:?}");
+                }
+            }
+        })
+    }
+
+    async fn check_projects(&self) -> Result<()> {
+        let projects: Vec<(String,)> = sqlx::query_as(
+            "
+            SELECT DISTINCT m.project_id
+            FROM milestones m
+            JOIN projects p ON p.project_id = m.project_id
+            WHERE p.deleted_on IS NULL",
+        )
+        .fetch_all(self.pool)
+        .await
+        .context("Failed to list projects with milestones")?;
+
+        for (project_id,) in projects {
+            if let Err(e) = self.check_project(&project_id).await {
+                tracing::warn!("Failed to check milestones of {project_id}: {e:?}");
+            }
+        }
+        Ok(())
+    }
+
+    async fn check_project(&self, project_id: &ProjectId) -> Result<()> {
+        let schedule = Schedule::load(self.pool, &self.collab, project_id).await?;
+        for row in list_milestones(self.pool, project_id).await? {
+            let previous = row.health.as_deref().and_then(health_from_str);
+            let milestone = schedule.to_milestone(row);
+            if previous == Some(milestone.health) {
+                continue;
+            }
+            sqlx::query("UPDATE milestones SET health = $2 WHERE id = $1")
+                .bind(&milestone.id)
+                .bind(health_to_str(milestone.health))
+                .execute(self.pool)
+                .await
+                .context("Failed to update milestone health")?;
+            if severity(milestone.health) > previous.map_or(0, severity) {
+                self.alert(project_id, &milestone).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn alert(&self, project_id: &ProjectId, milestone: &Milestone) -> Result<()> {
+        let status = match milestone.health {
+            MilestoneHealth::AtRisk => "is at risk",
+            _ => "is off track",
+        };
+        let forecast = milestone
+            .forecast_date
+            .map_or("no forecast".to_string(), |d| {
+                format!("forecast {}", d.format("%Y-%m-%d"))
+            });
+        let msg = format!(
+            "⚠️ Milestone <a href=\"https://koso.app/projects/{project_id}\"><b>{}</b></a> {status}\n<i>Target {}, {forecast}, {}/{} tasks done</i>",
+            escape_html(&milestone.name),
+            milestone.target_date.format("%Y-%m-%d"),
+            milestone.progress.done,
+            milestone.progress.total,
+        );
+        let recipient = milestone.owner.as_ref().unwrap_or(&milestone.creator);
+        self.notifier.notify(recipient, &msg).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2025, 9, day).unwrap()
+    }
+
+    #[test]
+    fn health_compares_forecast_to_target() {
+        let progress = TaskProgress { done: 1, total: 4 };
+        let dates = CompletionDates {
+            p50: Some(date(10)),
+            p85: Some(date(15)),
+            p95: Some(date(20)),
+        };
+        let today = date(1);
+        assert_eq!(
+            health(progress, Some(&dates), date(15), today),
+            MilestoneHealth::OnTrack
+        );
+        assert_eq!(
+            health(progress, Some(&dates), date(12), today),
+            MilestoneHealth::AtRisk
+        );
+        assert_eq!(
+            health(progress, Some(&dates), date(9), today),
+            MilestoneHealth::OffTrack
+        );
+        assert_eq!(
+            health(progress, None, date(15), today),
+            MilestoneHealth::Unknown
+        );
+        assert_eq!(
+            health(progress, Some(&dates), date(15), date(16)),
+            MilestoneHealth::OffTrack
+        );
+        assert_eq!(
+            health(TaskProgress { done: 4, total: 4 }, None, date(15), date(16)),
+            MilestoneHealth::Complete
+        );
+    }
+
+    #[test]
+    fn health_round_trips_through_str() {
+        for health in [
+            MilestoneHealth::Complete,
+            MilestoneHealth::OnTrack,
+            MilestoneHealth::AtRisk,
+            MilestoneHealth::OffTrack,
+            MilestoneHealth::Unknown,
+        ] {
+            assert_eq!(health_from_str(health_to_str(health)), Some(health));
+        }
+    }
+}
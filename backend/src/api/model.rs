@@ -1,4 +1,6 @@
-use sqlx::types::chrono::{self, Utc};
+use chrono::{NaiveDate, NaiveTime, TimeZone as _, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::Deserialize as _;
 use std::{collections::HashMap, fmt};
 
 pub(crate) type ProjectId = String;
@@ -9,6 +11,14 @@ pub(crate) struct Project {
     pub(crate) project_id: String,
     pub(crate) name: String,
     pub(crate) deleted_on: Option<chrono::DateTime<Utc>>,
+    /// Prefix of task keys, e.g. KOSO in KOSO-123. Keys are #123 when unset.
+    #[serde(default)]
+    #[sqlx(default)]
+    pub(crate) task_key_prefix: Option<String>,
+    /// The data region the project is stored in, if it's pinned to one.
+    #[serde(default)]
+    #[sqlx(default)]
+    pub(crate) region: Option<String>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -16,23 +26,19 @@ pub(crate) struct Project {
 pub(crate) struct CreateProject {
     pub(crate) name: String,
     pub(crate) project_export: Option<ProjectExport>,
+    /// The blueprint to instantiate in the new project, if any.
+    pub(crate) blueprint_id: Option<String>,
 }
 
 impl fmt::Debug for CreateProject {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("CreateProject")
             .field("name", &self.name)
+            .field("blueprint_id", &self.blueprint_id)
             .finish()
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, sqlx::FromRow)]
-#[serde(rename_all = "camelCase")]
-pub(crate) struct ProjectPermission {
-    pub(crate) project_id: ProjectId,
-    pub(crate) email: String,
-}
-
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct UpdateProjectUsers {
@@ -44,6 +50,13 @@ pub struct UpdateProjectUsers {
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct UpdateProjectUsersResponse {}
 
+/// Grants or revokes a member's admin rights, which let them review proposed changes.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UpdateProjectAdmin {
+    pub(crate) admin: bool,
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, sqlx::FromRow)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct ProjectUser {
@@ -63,16 +76,1049 @@ pub(crate) struct User {
     pub(crate) premium: bool,
 }
 
+/// A user's profile as presented to other users.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UserProfile {
+    pub(crate) email: String,
+    /// The user's display name override, if set, otherwise their Google name.
+    pub(crate) name: String,
+    /// URL of the user's avatar. Always served through Koso.
+    pub(crate) picture: String,
+    /// IANA time zone, e.g. "America/Los_Angeles".
+    pub(crate) timezone: Option<String>,
+    /// BCP 47 language tag, e.g. "en-US".
+    pub(crate) locale: Option<String>,
+    pub(crate) working_hours: Option<WorkingHours>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct WorkingHours {
+    /// Hour of the day, in the user's timezone, work starts. [0, 24)
+    pub(crate) start_hour: u32,
+    /// Hour of the day, in the user's timezone, work ends. (start_hour, 24]
+    pub(crate) end_hour: u32,
+    pub(crate) days: Vec<Weekday>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UpdateProfile {
+    pub(crate) display_name: Option<String>,
+    pub(crate) timezone: Option<String>,
+    pub(crate) locale: Option<String>,
+    pub(crate) working_hours: Option<WorkingHours>,
+}
+
+/// A named group of project members that may be used as a task assignee.
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ProjectGroup {
+    pub(crate) name: String,
+    pub(crate) members: Vec<String>,
+    pub(crate) policy: GroupPolicy,
+}
+
+/// How a task assigned to a group is assigned to the group's members.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum GroupPolicy {
+    /// Assign the task to the next member in turn.
+    RoundRobin,
+    /// Create a subtask for, and assigned to, each member.
+    Expand,
+}
+
+/// A task assigned to the requesting user, from any of their projects.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AssignedTask {
+    pub(crate) project_id: ProjectId,
+    pub(crate) project_name: String,
+    pub(crate) task_id: String,
+    pub(crate) num: String,
+    pub(crate) name: String,
+    pub(crate) status: Option<String>,
+    pub(crate) kind: Option<String>,
+    pub(crate) deadline: Option<Deadline>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Debug, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Comment {
+    pub(crate) id: String,
+    pub(crate) task_id: String,
+    /// The thread's root comment, if this comment is a reply.
+    pub(crate) parent_id: Option<String>,
+    pub(crate) author: String,
+    pub(crate) body: String,
+    /// Whether the thread is resolved. Only set on root comments.
+    pub(crate) resolved: bool,
+    pub(crate) create_time: chrono::DateTime<Utc>,
+    pub(crate) update_time: chrono::DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CreateComment {
+    pub(crate) parent_id: Option<String>,
+    pub(crate) body: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UpdateComment {
+    pub(crate) body: Option<String>,
+    pub(crate) resolved: Option<bool>,
+}
+
+/// A record of a decision made about a task, along with why and what else was considered.
+#[derive(serde::Serialize, Debug, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Decision {
+    pub(crate) id: String,
+    pub(crate) task_id: String,
+    pub(crate) title: String,
+    pub(crate) context: String,
+    pub(crate) decision: String,
+    #[sqlx(json)]
+    pub(crate) alternatives: Vec<String>,
+    #[sqlx(json)]
+    pub(crate) deciders: Vec<String>,
+    pub(crate) decision_date: NaiveDate,
+    pub(crate) author: String,
+    pub(crate) create_time: chrono::DateTime<Utc>,
+    pub(crate) update_time: chrono::DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CreateDecision {
+    pub(crate) title: String,
+    pub(crate) context: String,
+    pub(crate) decision: String,
+    #[serde(default)]
+    pub(crate) alternatives: Vec<String>,
+    #[serde(default)]
+    pub(crate) deciders: Vec<String>,
+    /// Defaults to today.
+    pub(crate) decision_date: Option<NaiveDate>,
+}
+
+/// Fields left unset are unchanged.
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UpdateDecision {
+    pub(crate) title: Option<String>,
+    pub(crate) context: Option<String>,
+    pub(crate) decision: Option<String>,
+    pub(crate) alternatives: Option<Vec<String>>,
+    pub(crate) deciders: Option<Vec<String>>,
+    pub(crate) decision_date: Option<NaiveDate>,
+}
+
+/// A file attached to a task. The content is downloaded separately.
+#[derive(serde::Serialize, Debug, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Attachment {
+    pub(crate) id: String,
+    pub(crate) task_id: String,
+    pub(crate) filename: String,
+    pub(crate) content_type: String,
+    pub(crate) size: i64,
+    pub(crate) uploader: String,
+    /// One of unscanned, pending, clean or quarantined.
+    pub(crate) scan_status: String,
+    pub(crate) create_time: chrono::DateTime<Utc>,
+}
+
+/// A project's inbound email address. Emails sent to it become tasks.
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct InboundEmailAddress {
+    pub(crate) address: String,
+    pub(crate) allow_non_members: bool,
+    pub(crate) creator: String,
+    pub(crate) create_time: chrono::DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UpdateInboundEmailAddress {
+    /// Accept emails from senders who aren't members of the project.
+    #[serde(default)]
+    pub(crate) allow_non_members: bool,
+}
+
+/// An email received at a project's inbound address.
+#[derive(serde::Serialize, Debug, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct InboundEmail {
+    pub(crate) id: String,
+    pub(crate) sender: String,
+    pub(crate) subject: String,
+    /// The task created from the email, if it was accepted.
+    pub(crate) task_id: Option<String>,
+    /// Why the email was rejected, if it was.
+    pub(crate) rejection: Option<String>,
+    pub(crate) receive_time: chrono::DateTime<Utc>,
+}
+
+/// A notification in a user's in-app inbox.
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct InboxNotification {
+    pub(crate) id: String,
+    pub(crate) kind: InboxKind,
+    pub(crate) project_id: Option<ProjectId>,
+    pub(crate) task_id: Option<String>,
+    /// Email of the user that triggered the notification, if any.
+    pub(crate) actor: Option<String>,
+    pub(crate) text: String,
+    pub(crate) create_time: chrono::DateTime<Utc>,
+    pub(crate) read_time: Option<chrono::DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum InboxKind {
+    Assigned,
+    Deadline,
+    Unblocked,
+    CommentReply,
+    ChangeProposal,
+    /// Unusual activity was held for review.
+    Anomaly,
+    /// An admin asks to impersonate the user.
+    ImpersonationRequest,
+    /// A task was assigned to a user the recipient covers for while they're away.
+    DelegatedAssignment,
+    /// Content was reported as spam or abuse.
+    AbuseReport,
+    /// An uploaded attachment was found to contain malware.
+    AttachmentQuarantined,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct MarkInboxRead {
+    /// Notifications to mark read. All notifications are marked read if absent.
+    pub(crate) ids: Option<Vec<String>>,
+}
+
+/// Search results for a single project.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ProjectSearchResults {
+    pub(crate) project_id: ProjectId,
+    pub(crate) project_name: String,
+    pub(crate) tasks: Vec<SearchHit>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SearchHit {
+    pub(crate) task_id: String,
+    pub(crate) num: String,
+    /// The task's key, e.g. KOSO-123 or #123.
+    pub(crate) key: String,
+    pub(crate) name: String,
+    pub(crate) assignee: Option<String>,
+    pub(crate) status: Option<String>,
+    pub(crate) kind: Option<String>,
+}
+
+/// A set of pending changes to a review protected project, from a single author.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ChangeProposal {
+    pub(crate) id: String,
+    pub(crate) author: String,
+    /// The automation, e.g. mcp, that made the changes on behalf of the author, if any.
+    pub(crate) agent: Option<String>,
+    /// One of "pending", "applied" or "rejected".
+    pub(crate) status: String,
+    pub(crate) reviewer: Option<String>,
+    pub(crate) create_time: chrono::DateTime<Utc>,
+    pub(crate) update_time: chrono::DateTime<Utc>,
+}
+
+/// How a proposal would change a task. `before` is absent for created tasks
+/// and `after` is absent for deleted tasks.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ProposedTaskChange {
+    pub(crate) task_id: String,
+    pub(crate) before: Option<Task>,
+    pub(crate) after: Option<Task>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UpdateReviewSettings {
+    pub(crate) require_review: bool,
+    /// Whether changes made by agents, e.g. over MCP, must be reviewed, even
+    /// when made on behalf of admins. Unchanged when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) require_agent_review: Option<bool>,
+}
+
+/// A draft copy of a project's doc that can later be merged back or discarded.
+/// Branches are edited like any other project, using their `branch_project_id`.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ProjectBranch {
+    pub(crate) branch_project_id: ProjectId,
+    pub(crate) project_id: ProjectId,
+    pub(crate) name: String,
+    pub(crate) creator: String,
+    pub(crate) create_time: chrono::DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CreateBranch {
+    pub(crate) name: String,
+}
+
+/// A named copy of a project's doc.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ProjectSnapshot {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) creator: String,
+    pub(crate) create_time: chrono::DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CreateSnapshot {
+    pub(crate) name: String,
+}
+
+/// How a project's tasks changed between two snapshots.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ProjectDiff {
+    pub(crate) added: Vec<DiffTask>,
+    pub(crate) removed: Vec<DiffTask>,
+    pub(crate) renamed: Vec<RenamedTask>,
+    pub(crate) restatused: Vec<RestatusedTask>,
+    pub(crate) moved: Vec<MovedTask>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DiffTask {
+    pub(crate) task_id: String,
+    pub(crate) num: String,
+    pub(crate) name: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RenamedTask {
+    pub(crate) task_id: String,
+    pub(crate) num: String,
+    pub(crate) from: String,
+    pub(crate) to: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RestatusedTask {
+    pub(crate) task_id: String,
+    pub(crate) num: String,
+    pub(crate) name: String,
+    pub(crate) from: Option<String>,
+    pub(crate) to: Option<String>,
+}
+
+/// A task whose parents changed.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct MovedTask {
+    pub(crate) task_id: String,
+    pub(crate) num: String,
+    pub(crate) name: String,
+    pub(crate) from: Vec<String>,
+    pub(crate) to: Vec<String>,
+}
+
+/// A board column's tasks, in display order.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BoardColumn {
+    pub(crate) status: String,
+    pub(crate) task_ids: Vec<String>,
+}
+
+/// Configures a parent task to assign its new, unassigned children automatically.
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AutoAssign {
+    pub(crate) mode: AutoAssignMode,
+    pub(crate) members: Vec<String>,
+    /// Position of the next member to assign for round robin assignment.
+    #[serde(default)]
+    pub(crate) next: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum AutoAssignMode {
+    RoundRobin,
+    /// Assign the member with the least outstanding, estimated work.
+    LeastLoaded,
+}
+
+/// A large removal of tasks that can be reverted.
+#[derive(serde::Serialize, Debug, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TaskRemoval {
+    /// Id of the transaction that removed the tasks.
+    pub(crate) id: String,
+    pub(crate) actor: String,
+    pub(crate) task_count: i64,
+    pub(crate) create_time: chrono::DateTime<Utc>,
+    pub(crate) reverted_time: Option<chrono::DateTime<Utc>>,
+}
+
+/// Metadata recorded for a stored doc update.
+#[derive(serde::Serialize, Debug, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UpdateRecord {
+    pub(crate) seq: i32,
+    /// Id of the transaction that produced the update.
+    pub(crate) txn_id: String,
+    pub(crate) who: String,
+    /// One of none, user, github or server.
+    pub(crate) actor: String,
+    pub(crate) email: Option<String>,
+    pub(crate) device: Option<String>,
+    pub(crate) request_id: Option<String>,
+    pub(crate) feature: String,
+    /// Size of the encoded update in bytes.
+    pub(crate) update_len: i32,
+    pub(crate) create_time: chrono::DateTime<Utc>,
+}
+
+/// How a transaction changed a task.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TaskChange {
+    pub(crate) task_id: String,
+    pub(crate) kind: TaskChangeKind,
+    /// Names of the task fields that changed, sorted. Empty for deleted tasks.
+    pub(crate) fields: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum TaskChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+/// The task changes made by a stored update.
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ChangeRecord {
+    pub(crate) seq: i32,
+    pub(crate) txn_id: String,
+    pub(crate) actor: String,
+    pub(crate) email: Option<String>,
+    pub(crate) feature: String,
+    pub(crate) create_time: chrono::DateTime<Utc>,
+    pub(crate) changes: Vec<TaskChange>,
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ChangeFeed {
+    pub(crate) changes: Vec<ChangeRecord>,
+    /// Pass as `since` to fetch the changes that follow.
+    pub(crate) next_since: i32,
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct StepUp {
+    /// Pass in the `x-step-up-token` header of sensitive requests.
+    pub(crate) token: String,
+    /// Until when sensitive operations are allowed.
+    pub(crate) expire_time: chrono::DateTime<Utc>,
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct StepUpNonce {
+    /// Pass to the identity provider when re-authenticating to step up.
+    pub(crate) nonce: String,
+}
+
+/// Security policy applied to all members of the owner's subscription.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SecurityPolicy {
+    /// CIDRs requests must originate from. When empty, all addresses are allowed.
+    #[serde(default)]
+    pub(crate) ip_allowlist: Vec<String>,
+    /// Maximum time since members last signed in before they must sign in again.
+    pub(crate) reauth_interval_secs: Option<i32>,
+}
+
+#[derive(serde::Serialize, Debug, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SecurityAuditEntry {
+    pub(crate) id: String,
+    pub(crate) email: String,
+    pub(crate) event: String,
+    pub(crate) client_ip: Option<String>,
+    pub(crate) details: String,
+    pub(crate) create_time: chrono::DateTime<Utc>,
+}
+
+/// What happened in a project over a window of time.
+#[derive(serde::Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct StatusReport {
+    pub(crate) project_id: ProjectId,
+    pub(crate) project_name: String,
+    /// Used to render task keys, e.g. KOSO-123.
+    pub(crate) task_key_prefix: Option<String>,
+    pub(crate) start: chrono::DateTime<Utc>,
+    pub(crate) end: chrono::DateTime<Utc>,
+    pub(crate) completed: Vec<ReportTask>,
+    pub(crate) started: Vec<ReportTask>,
+    /// Tasks whose deadline passed in the window before they were done.
+    pub(crate) slipped: Vec<ReportTask>,
+    pub(crate) created: Vec<ReportTask>,
+    /// Cycle and lead times of the tasks completed in the window.
+    pub(crate) flow: FlowMetrics,
+    pub(crate) goals: Vec<ReportGoal>,
+    /// Open risks, highest scoring first.
+    pub(crate) risks: Vec<ReportRisk>,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ReportTask {
+    pub(crate) task_id: String,
+    pub(crate) num: String,
+    pub(crate) name: String,
+    pub(crate) assignee: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ReportGoal {
+    pub(crate) goal_id: String,
+    pub(crate) title: String,
+    pub(crate) due_date: Option<NaiveDate>,
+    pub(crate) progress: TaskProgress,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ReportRisk {
+    pub(crate) risk_id: String,
+    pub(crate) title: String,
+    pub(crate) score: i16,
+    pub(crate) owner: Option<String>,
+}
+
+/// How well estimates of tasks completed in the last `days` days held up.
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EstimateAccuracyReport {
+    pub(crate) days: i64,
+    pub(crate) overall: EstimateAccuracy,
+    pub(crate) by_assignee: Vec<EstimateAccuracy>,
+    pub(crate) by_label: Vec<EstimateAccuracy>,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EstimateAccuracy {
+    /// The assignee or label, absent for the overall statistics.
+    pub(crate) key: Option<String>,
+    pub(crate) tasks: usize,
+    /// Mean ratio of final to original estimates. Above 1 means tasks grew.
+    pub(crate) mean_estimate_growth: Option<f64>,
+    /// Mean absolute error of original estimates relative to final estimates.
+    pub(crate) mean_absolute_percentage_error: Option<f64>,
+    /// Median hours from first starting a task to finishing it, per estimate point.
+    pub(crate) median_hours_per_point: Option<f64>,
+}
+
+/// How long tasks completed in the last `days` days took.
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct FlowMetricsReport {
+    pub(crate) days: i64,
+    pub(crate) overall: FlowMetrics,
+    pub(crate) by_assignee: Vec<FlowMetrics>,
+    pub(crate) by_label: Vec<FlowMetrics>,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct FlowMetrics {
+    /// The assignee or label, absent for the overall statistics.
+    pub(crate) key: Option<String>,
+    pub(crate) tasks: usize,
+    /// Hours from first starting a task to finishing it.
+    pub(crate) cycle_time: Percentiles,
+    /// Hours from creating a task to finishing it.
+    pub(crate) lead_time: Percentiles,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Percentiles {
+    pub(crate) p50: Option<f64>,
+    pub(crate) p85: Option<f64>,
+    pub(crate) p95: Option<f64>,
+}
+
+/// Leaf tasks under one or more subtrees, and how many are done.
+#[derive(serde::Serialize, Debug, PartialEq, Eq, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TaskProgress {
+    pub(crate) done: u64,
+    pub(crate) total: u64,
+}
+
+/// An objective tracked by the completion of linked task subtrees.
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Goal {
+    pub(crate) id: String,
+    pub(crate) title: String,
+    pub(crate) description: Option<String>,
+    pub(crate) owner: Option<String>,
+    pub(crate) due_date: Option<NaiveDate>,
+    pub(crate) task_ids: Vec<String>,
+    pub(crate) creator: String,
+    pub(crate) create_time: chrono::DateTime<Utc>,
+    pub(crate) update_time: chrono::DateTime<Utc>,
+    pub(crate) progress: TaskProgress,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CreateGoal {
+    pub(crate) title: String,
+    pub(crate) description: Option<String>,
+    pub(crate) owner: Option<String>,
+    pub(crate) due_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub(crate) task_ids: Vec<String>,
+}
+
+/// Fields left unset are unchanged.
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UpdateGoal {
+    pub(crate) title: Option<String>,
+    pub(crate) description: Option<String>,
+    pub(crate) owner: Option<String>,
+    pub(crate) due_date: Option<NaiveDate>,
+    pub(crate) task_ids: Option<Vec<String>>,
+}
+
+/// A target date for a set of task subtrees, distinct from their parent tasks.
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Milestone {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) target_date: NaiveDate,
+    pub(crate) owner: Option<String>,
+    pub(crate) task_ids: Vec<String>,
+    pub(crate) creator: String,
+    pub(crate) create_time: chrono::DateTime<Utc>,
+    pub(crate) update_time: chrono::DateTime<Utc>,
+    pub(crate) progress: TaskProgress,
+    pub(crate) health: MilestoneHealth,
+    /// The date by which 85% of simulated trials finished the remaining tasks.
+    pub(crate) forecast_date: Option<NaiveDate>,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum MilestoneHealth {
+    /// All linked tasks are done.
+    Complete,
+    /// Likely to be done by the target date.
+    OnTrack,
+    /// As likely as not to be done by the target date.
+    AtRisk,
+    /// Unlikely to be done by the target date, or past it.
+    OffTrack,
+    /// There's no task or throughput history to judge by.
+    Unknown,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CreateMilestone {
+    pub(crate) name: String,
+    pub(crate) target_date: NaiveDate,
+    pub(crate) owner: Option<String>,
+    #[serde(default)]
+    pub(crate) task_ids: Vec<String>,
+}
+
+/// Fields left unset are unchanged.
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UpdateMilestone {
+    pub(crate) name: Option<String>,
+    pub(crate) target_date: Option<NaiveDate>,
+    pub(crate) owner: Option<String>,
+    pub(crate) task_ids: Option<Vec<String>>,
+}
+
+/// A delivery risk threatening linked task subtrees.
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Risk {
+    pub(crate) id: String,
+    pub(crate) title: String,
+    pub(crate) description: Option<String>,
+    /// From 1 (rare) to 5 (almost certain).
+    pub(crate) likelihood: i16,
+    /// From 1 (negligible) to 5 (severe).
+    pub(crate) impact: i16,
+    pub(crate) mitigation: Option<String>,
+    pub(crate) owner: Option<String>,
+    #[sqlx(json)]
+    pub(crate) task_ids: Vec<String>,
+    pub(crate) closed: bool,
+    pub(crate) creator: String,
+    pub(crate) create_time: chrono::DateTime<Utc>,
+    pub(crate) update_time: chrono::DateTime<Utc>,
+}
+
+impl Risk {
+    pub(crate) fn score(&self) -> i16 {
+        self.likelihood * self.impact
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CreateRisk {
+    pub(crate) title: String,
+    pub(crate) description: Option<String>,
+    pub(crate) likelihood: i16,
+    pub(crate) impact: i16,
+    pub(crate) mitigation: Option<String>,
+    pub(crate) owner: Option<String>,
+    #[serde(default)]
+    pub(crate) task_ids: Vec<String>,
+}
+
+/// Fields left unset are unchanged.
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UpdateRisk {
+    pub(crate) title: Option<String>,
+    pub(crate) description: Option<String>,
+    pub(crate) likelihood: Option<i16>,
+    pub(crate) impact: Option<i16>,
+    pub(crate) mitigation: Option<String>,
+    pub(crate) owner: Option<String>,
+    pub(crate) task_ids: Option<Vec<String>>,
+    pub(crate) closed: Option<bool>,
+}
+
+/// When the remaining tasks of a subtree are likely to be done.
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Forecast {
+    pub(crate) task_id: String,
+    /// Unfinished leaf tasks in the subtree.
+    pub(crate) remaining: u64,
+    /// Days of throughput history sampled.
+    pub(crate) history_days: i64,
+    pub(crate) trials: usize,
+    /// Completion dates by which 50%, 85% and 95% of trials finished, absent
+    /// if those trials never finished.
+    pub(crate) p50: Option<NaiveDate>,
+    pub(crate) p85: Option<NaiveDate>,
+    pub(crate) p95: Option<NaiveDate>,
+}
+
+/// Daily counts of tasks per status, ready to plot as a cumulative flow diagram.
+#[derive(serde::Serialize, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CumulativeFlow {
+    pub(crate) dates: Vec<NaiveDate>,
+    /// One series per status, each with a count for every date.
+    pub(crate) series: Vec<CumulativeFlowSeries>,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CumulativeFlowSeries {
+    pub(crate) status: String,
+    pub(crate) counts: Vec<i64>,
+}
+
+/// Sends the user a weekly status report for the project.
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ReportSubscription {
+    /// Day of the week to send the report on, 0 for Monday through 6 for Sunday, in UTC.
+    pub(crate) weekday: i16,
+}
+
+/// A project's API usage over the last `days` days.
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ProjectUsage {
+    pub(crate) days: i64,
+    pub(crate) requests: i64,
+    pub(crate) errors: i64,
+    pub(crate) error_rate: f64,
+    pub(crate) daily: Vec<UsageDay>,
+    /// The most active clients, by user and user agent.
+    pub(crate) top_clients: Vec<UsageClient>,
+}
+
+#[derive(serde::Serialize, Debug, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UsageDay {
+    pub(crate) day: chrono::DateTime<Utc>,
+    /// Either rest or ws.
+    pub(crate) channel: String,
+    pub(crate) requests: i64,
+    pub(crate) errors: i64,
+}
+
+#[derive(serde::Serialize, Debug, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UsageClient {
+    pub(crate) email: String,
+    pub(crate) user_agent: String,
+    pub(crate) requests: i64,
+    pub(crate) errors: i64,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BulkOperation {
+    /// Report the tasks that would change without changing them.
+    #[serde(default)]
+    pub(crate) dry_run: bool,
+    pub(crate) action: BulkAction,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub(crate) enum BulkAction {
+    /// Set the status of every descendant of `root`.
+    SetStatus { root: String, status: String },
+    /// Move every task assigned to `from` to `to`, or unassign them.
+    Reassign { from: String, to: Option<String> },
+    /// Archive tasks that have been Done for longer than `older_than_days`.
+    ArchiveDone { older_than_days: u32 },
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BulkResult {
+    pub(crate) dry_run: bool,
+    pub(crate) changes: Vec<BulkChange>,
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BulkChange {
+    pub(crate) task_id: String,
+    pub(crate) num: String,
+    pub(crate) name: String,
+}
+
+/// Project settings, synced collaboratively through the doc's `config` map.
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ProjectConfig {
+    /// Custom workflow states. When empty, the built-in statuses are used.
+    #[serde(default)]
+    pub(crate) workflow_states: Vec<WorkflowState>,
+    #[serde(default)]
+    pub(crate) labels: Vec<Label>,
+    #[serde(default)]
+    pub(crate) iteration: Option<IterationSettings>,
+    #[serde(default)]
+    pub(crate) automation_rules: Vec<AutomationRule>,
+    #[serde(default)]
+    pub(crate) auto_archive: Option<AutoArchiveSettings>,
+    #[serde(default)]
+    pub(crate) estimates: Option<EstimateSettings>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct WorkflowState {
+    pub(crate) name: String,
+    /// The built-in status the state behaves like, e.g. for blocking and progress.
+    pub(crate) category: WorkflowCategory,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum WorkflowCategory {
+    NotStarted,
+    InProgress,
+    Blocked,
+    Done,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Label {
+    pub(crate) name: String,
+    /// Hex color, e.g. #1f883d.
+    pub(crate) color: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct IterationSettings {
+    pub(crate) length_days: u32,
+    /// Start of the first iteration. Later iterations follow back to back.
+    pub(crate) start_date: NaiveDate,
+}
+
+/// Archives tasks once they've been done for a while.
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AutoArchiveSettings {
+    /// Days after a task's status changed to a done state that it's archived.
+    pub(crate) after_days: u32,
+    /// Tasks with this label are never auto-archived.
+    #[serde(default)]
+    pub(crate) exclude_label: Option<String>,
+}
+
+/// How tasks are estimated. Estimates are stored as integers in the unit, or
+/// for t-shirt sizes, as the points of the size. Projects without settings
+/// estimate in points.
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EstimateSettings {
+    #[serde(default)]
+    pub(crate) unit: EstimateUnit,
+    /// T-shirt sizes, smallest first, with the points each is worth.
+    #[serde(default)]
+    pub(crate) sizes: Vec<EstimateSize>,
+    /// Hours per point, to compare hour estimates with points. Defaults to
+    /// [DEFAULT_HOURS_PER_POINT].
+    #[serde(default)]
+    pub(crate) hours_per_point: Option<u32>,
+    /// How much work, in the unit, each member can take on per iteration.
+    #[serde(default)]
+    pub(crate) capacity: Option<i64>,
+}
+
+pub(crate) const DEFAULT_HOURS_PER_POINT: u32 = 4;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum EstimateUnit {
+    #[default]
+    Points,
+    Hours,
+    TShirt,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EstimateSize {
+    /// e.g. S, M or L.
+    pub(crate) name: String,
+    pub(crate) points: i64,
+}
+
+impl EstimateSettings {
+    fn hours_per_point(&self) -> f64 {
+        f64::from(self.hours_per_point.unwrap_or(DEFAULT_HOURS_PER_POINT))
+    }
+
+    /// Converts an estimate in the unit to points, so estimates compare across
+    /// units and projects.
+    pub(crate) fn to_points(&self, estimate: i64) -> f64 {
+        match self.unit {
+            EstimateUnit::Points | EstimateUnit::TShirt => estimate as f64,
+            EstimateUnit::Hours => estimate as f64 / self.hours_per_point(),
+        }
+    }
+
+    /// Converts points to the nearest estimate expressible in the unit.
+    pub(crate) fn points_to_estimate(&self, points: f64) -> i64 {
+        match self.unit {
+            EstimateUnit::Points => points.round() as i64,
+            EstimateUnit::Hours => (points * self.hours_per_point()).round() as i64,
+            EstimateUnit::TShirt => self
+                .sizes
+                .iter()
+                .min_by(|a, b| {
+                    (a.points as f64 - points)
+                        .abs()
+                        .total_cmp(&(b.points as f64 - points).abs())
+                })
+                .map_or(points.round() as i64, |s| s.points),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AutomationRule {
+    pub(crate) id: String,
+    pub(crate) trigger: AutomationTrigger,
+    pub(crate) action: AutomationAction,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub(crate) enum AutomationTrigger {
+    TaskCreated,
+    StatusChanged { to: String },
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub(crate) enum AutomationAction {
+    SetStatus { status: String },
+    AddLabel { label: String },
+    Assign { assignee: String },
+}
+
 #[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct ProjectExport {
     pub(crate) project_id: ProjectId,
     pub(crate) graph: Graph,
+    #[serde(default)]
+    pub(crate) risks: Vec<Risk>,
 }
 
 pub(crate) type Graph = HashMap<String, Task>;
 
-#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Default)]
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Debug, Default)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct Task {
     pub(crate) id: String,
@@ -87,16 +1133,136 @@ pub(crate) struct Task {
     pub(crate) url: Option<String>,
     pub(crate) kind: Option<String>,
     pub(crate) estimate: Option<i64>,
-    pub(crate) deadline: Option<i64>,
+    #[serde(default, deserialize_with = "deserialize_deadline")]
+    pub(crate) deadline: Option<Deadline>,
     pub(crate) archived: Option<bool>,
+    /// Who created the task, recorded from the origin of the creating transaction.
+    /// Unlike the other fields, attribution isn't stored in the doc and is only
+    /// set on tasks returned by REST endpoints and exports.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) created_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) created_at: Option<chrono::DateTime<Utc>>,
+    /// Who last changed the task, including its children.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) last_modified_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) last_modified_at: Option<chrono::DateTime<Utc>>,
+}
+
+/// When a task is due.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Clone)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+pub(crate) enum Deadline {
+    /// A calendar date, due at the end of that day in the viewer's timezone.
+    #[serde(rename_all = "camelCase")]
+    Date { date: NaiveDate },
+    /// An exact instant, in milliseconds since the epoch, along with the
+    /// IANA time zone (e.g. "America/Los_Angeles") it was set in.
+    #[serde(rename_all = "camelCase")]
+    DateTime { millis: i64, time_zone: String },
+}
+
+impl Deadline {
+    /// Interprets a bare, legacy deadline. Clients used to store the
+    /// date picked by the user as milliseconds at midnight UTC.
+    pub(crate) fn from_legacy_millis(millis: i64) -> Deadline {
+        Deadline::Date {
+            date: Utc
+                .timestamp_millis_opt(millis)
+                .single()
+                .unwrap_or_default()
+                .date_naive(),
+        }
+    }
+
+    /// The value stored in the doc's `deadline` field.
+    /// For dates, this matches the legacy encoding so older clients keep working.
+    pub(crate) fn millis(&self) -> i64 {
+        match self {
+            Deadline::Date { date } => date.and_time(NaiveTime::MIN).and_utc().timestamp_millis(),
+            Deadline::DateTime { millis, .. } => *millis,
+        }
+    }
+
+    /// Returns the instant the deadline passes for someone in the given timezone.
+    pub(crate) fn due_at(&self, tz: &Tz) -> chrono::DateTime<Tz> {
+        match self {
+            Deadline::Date { date } => {
+                let end_of_day = date.and_hms_milli_opt(23, 59, 59, 999).unwrap_or_default();
+                tz.from_local_datetime(&end_of_day)
+                    .latest()
+                    .unwrap_or_else(|| end_of_day.and_utc().with_timezone(tz))
+            }
+            Deadline::DateTime { millis, .. } => Utc
+                .timestamp_millis_opt(*millis)
+                .single()
+                .unwrap_or_default()
+                .with_timezone(tz),
+        }
+    }
+
+    /// Renders the deadline for display to someone in the given timezone.
+    pub(crate) fn format(&self, tz: &Tz) -> String {
+        match self {
+            Deadline::Date { date } => date.format("%a, %b %-d, %Y").to_string(),
+            Deadline::DateTime { .. } => self
+                .due_at(tz)
+                .format("%a, %b %-d, %Y %H:%M %Z")
+                .to_string(),
+        }
+    }
+}
+
+/// Parses a user's timezone preference, an IANA time zone such as "Asia/Kolkata".
+/// Missing or invalid values fall back to UTC.
+pub(crate) fn parse_time_zone(tz: Option<&str>) -> Tz {
+    tz.and_then(|tz| tz.parse::<Tz>().ok()).unwrap_or(Tz::UTC)
+}
+
+/// Accepts both typed deadlines and legacy, bare millisecond deadlines.
+fn deserialize_deadline<'de, D>(deserializer: D) -> Result<Option<Deadline>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum DeadlineOrLegacy {
+        Deadline(Deadline),
+        Legacy(i64),
+    }
+
+    Ok(
+        match Option::<DeadlineOrLegacy>::deserialize(deserializer)? {
+            Some(DeadlineOrLegacy::Deadline(deadline)) => Some(deadline),
+            Some(DeadlineOrLegacy::Legacy(millis)) => Some(Deadline::from_legacy_millis(millis)),
+            None => None,
+        },
+    )
 }
 
 #[cfg(test)]
 pub(crate) mod test_utils {
-    use crate::api::model::Task;
+    use crate::api::model::{Deadline, Graph, Task};
+
+    /// A task with the given id, num and children, and every other field unset.
+    pub(crate) fn task(id: &str, num: &str, children: &[&str]) -> Task {
+        Task {
+            id: id.to_string(),
+            num: num.to_string(),
+            children: children.iter().map(|c| c.to_string()).collect(),
+            ..Task::default()
+        }
+    }
+
+    /// A graph of the tasks, keyed by id.
+    pub(crate) fn graph(tasks: impl IntoIterator<Item = Task>) -> Graph {
+        tasks.into_iter().map(|t| (t.id.clone(), t)).collect()
+    }
 
     pub(crate) fn new_with_fields_populated() -> Task {
-        // Populate all fields with non-null, non-empty values for testing.
+        // Populate all fields stored in the doc with non-null, non-empty values for testing.
+        // Attribution isn't stored in the doc, so it's left unset.
         Task {
             id: "id1".to_string(),
             num: "1".to_string(),
@@ -110,8 +1276,78 @@ pub(crate) mod test_utils {
             url: Some("https://example.com/1".to_string()),
             kind: Some("Kind1".to_string()),
             estimate: Some(0),
-            deadline: Some(152),
+            deadline: Some(Deadline::DateTime {
+                millis: 152,
+                time_zone: "Europe/Berlin".to_string(),
+            }),
             archived: Some(false),
+            ..Task::default()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize_legacy_deadline() {
+        let task: Task = serde_json::from_str(
+            r#"{"id":"1","num":"1","name":"","desc":null,"children":[],"assignee":null,"reporter":null,"status":null,"statusTime":null,"url":null,"kind":null,"estimate":null,"deadline":1735689600000,"archived":null}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            task.deadline,
+            Some(Deadline::Date {
+                date: NaiveDate::from_ymd_opt(2025, 1, 1).unwrap()
+            })
+        );
+    }
+
+    #[test]
+    fn deserialize_typed_deadline() {
+        let deadline = Deadline::DateTime {
+            millis: 1735689600000,
+            time_zone: "America/Los_Angeles".to_string(),
+        };
+        let json = serde_json::to_string(&deadline).unwrap();
+        assert_eq!(
+            json,
+            r#"{"kind":"dateTime","millis":1735689600000,"timeZone":"America/Los_Angeles"}"#
+        );
+        assert_eq!(serde_json::from_str::<Deadline>(&json).unwrap(), deadline);
+    }
+
+    #[test]
+    fn date_deadline_is_due_at_end_of_day_in_timezone() {
+        let deadline = Deadline::Date {
+            date: NaiveDate::from_ymd_opt(2025, 1, 1).unwrap(),
+        };
+        let tz = parse_time_zone(Some("America/Los_Angeles"));
+        assert_eq!(
+            deadline.due_at(&tz).to_rfc3339(),
+            "2025-01-01T23:59:59.999-08:00"
+        );
+        assert_eq!(deadline.millis(), 1735689600000);
+    }
+
+    #[test]
+    fn date_deadline_follows_daylight_saving_time() {
+        let deadline = Deadline::Date {
+            date: NaiveDate::from_ymd_opt(2025, 7, 1).unwrap(),
+        };
+        let tz = parse_time_zone(Some("America/Los_Angeles"));
+        assert_eq!(
+            deadline.due_at(&tz).to_rfc3339(),
+            "2025-07-01T23:59:59.999-07:00"
+        );
+    }
+
+    #[test]
+    fn parse_time_zone_defaults_to_utc() {
+        assert_eq!(parse_time_zone(None), Tz::UTC);
+        assert_eq!(parse_time_zone(Some("garbage")), Tz::UTC);
+        assert_eq!(parse_time_zone(Some("+05:30")), Tz::UTC);
+        assert_eq!(parse_time_zone(Some("Asia/Kolkata")), Tz::Asia__Kolkata);
+    }
+}
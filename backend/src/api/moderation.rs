@@ -0,0 +1,343 @@
+//! Reporting of spam and abuse, and the queue in which the deployment's admins
+//! review reports.
+//!
+//! Users are quarantined in a project while reports about them are open, once
+//! a project admin or enough distinct members have reported them. Changes
+//! from quarantined users are held as change proposals rather than applied,
+//! and their comments are hidden from everyone else, until the reports are
+//! reviewed.
+
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::Collab,
+        google::User,
+        inbox::Inbox,
+        model::{InboxKind, ProjectId},
+        not_found_error, verify_admin, verify_project_access,
+    },
+    postgres::PgPool,
+    settings::settings,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    routing::{get, post},
+};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    FromRow, PgExecutor,
+    types::chrono::{DateTime, Utc},
+};
+
+/// Distinct members whose open reports quarantine a user. A single report
+/// from a project admin suffices.
+const QUARANTINE_REPORTERS: i64 = 2;
+const REASONS: [&str; 3] = ["spam", "abuse", "other"];
+const MAX_DETAILS_LEN: usize = 2000;
+
+/// Routes for reporting content in a project.
+pub(super) fn router() -> Router {
+    Router::new().route("/{project_id}/abuse-reports", post(report_handler))
+}
+
+/// Routes for the deployment admins' moderation queue.
+pub(super) fn queue_router() -> Router {
+    Router::new()
+        .route("/", get(list_reports_handler))
+        .route("/{report_id}/resolve", post(resolve_handler))
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AbuseReport {
+    id: String,
+    project_id: ProjectId,
+    reporter: String,
+    reported: String,
+    task_id: Option<String>,
+    comment_id: Option<String>,
+    reason: String,
+    details: String,
+    status: String,
+    reviewer: Option<String>,
+    create_time: DateTime<Utc>,
+    review_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CreateReport {
+    /// The reported task or comment. Exactly one must be set.
+    task_id: Option<String>,
+    comment_id: Option<String>,
+    reason: String,
+    #[serde(default)]
+    details: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ListReportsQuery {
+    /// open, dismissed or upheld. Defaults to open.
+    status: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+enum Resolution {
+    /// The content is fine. The user's hidden comments are released once no
+    /// other reports about them remain open.
+    Dismiss,
+    /// The content is spam or abuse. The reported comment and the user's
+    /// hidden comments are deleted.
+    Uphold,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Resolve {
+    resolution: Resolution,
+}
+
+const REPORT_COLUMNS: &str = "id, project_id, reporter, reported, task_id, comment_id, reason, details, status, reviewer, create_time, review_time";
+
+/// Reports a task or comment, attributing it to the comment's author or the
+/// task's last editor.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn report_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+    Json(req): Json<CreateReport>,
+) -> ApiResult<Json<AbuseReport>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    if !REASONS.contains(&req.reason.as_str()) {
+        return Err(bad_request_error(
+            "INVALID_REASON",
+            &format!("Reason must be one of {}", REASONS.join(", ")),
+        ));
+    }
+    if req.details.len() > MAX_DETAILS_LEN {
+        return Err(bad_request_error(
+            "LONG_DETAILS",
+            &format!("Details cannot be longer than {MAX_DETAILS_LEN} characters"),
+        ));
+    }
+    let reported = match (&req.task_id, &req.comment_id) {
+        (Some(task_id), None) => sqlx::query_as(
+            "
+                SELECT last_modified_by
+                FROM task_attribution
+                WHERE project_id = $1 AND task_id = $2",
+        )
+        .bind(&project_id)
+        .bind(task_id)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to fetch task attribution")?,
+        (None, Some(comment_id)) => {
+            sqlx::query_as("SELECT author FROM task_comments WHERE project_id = $1 AND id = $2")
+                .bind(&project_id)
+                .bind(comment_id)
+                .fetch_optional(pool)
+                .await
+                .context("Failed to fetch comment author")?
+        }
+        _ => {
+            return Err(bad_request_error(
+                "INVALID_CONTENT",
+                "Report either a task or a comment",
+            ));
+        }
+    };
+    let Some((reported,)): Option<(String,)> = reported else {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            "No author is known for the reported content",
+        ));
+    };
+    if reported == user.email {
+        return Err(bad_request_error(
+            "SELF_REPORT",
+            "Users cannot report their own content",
+        ));
+    }
+
+    // Reporting the same user again replaces the reporter's open report.
+    let report: AbuseReport = sqlx::query_as(&format!(
+        "
+        INSERT INTO abuse_reports (id, project_id, reporter, reported, task_id, comment_id, reason, details, status)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, 'open')
+        ON CONFLICT (project_id, reported, reporter) WHERE status = 'open'
+        DO UPDATE SET
+            task_id = EXCLUDED.task_id,
+            comment_id = EXCLUDED.comment_id,
+            reason = EXCLUDED.reason,
+            details = EXCLUDED.details,
+            create_time = NOW()
+        RETURNING {REPORT_COLUMNS}"
+    ))
+    .bind(BASE64_URL_SAFE_NO_PAD.encode(uuid::Uuid::new_v4()))
+    .bind(&project_id)
+    .bind(&user.email)
+    .bind(&reported)
+    .bind(&req.task_id)
+    .bind(&req.comment_id)
+    .bind(&req.reason)
+    .bind(req.details.trim())
+    .fetch_one(pool)
+    .await
+    .context("Failed to insert abuse report")?;
+    tracing::info!(
+        "{} reported {reported} in {project_id} for {}",
+        user.email,
+        req.reason
+    );
+
+    let inbox = Inbox::new(pool, collab.messenger());
+    for admin in &settings().admins {
+        inbox
+            .deliver(
+                admin,
+                InboxKind::AbuseReport,
+                Some(&project_id),
+                req.task_id.as_deref(),
+                Some(&user.email),
+                &format!("Reported {reported} for {}", req.reason),
+            )
+            .await?;
+    }
+    Ok(Json(report))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn list_reports_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Query(query): Query<ListReportsQuery>,
+) -> ApiResult<Json<Vec<AbuseReport>>> {
+    verify_admin(&user)?;
+    let reports: Vec<AbuseReport> = sqlx::query_as(&format!(
+        "
+        SELECT {REPORT_COLUMNS}
+        FROM abuse_reports
+        WHERE status = $1
+        ORDER BY create_time DESC, id
+        LIMIT 500"
+    ))
+    .bind(query.status.as_deref().unwrap_or("open"))
+    .fetch_all(pool)
+    .await
+    .context("Failed to list abuse reports")?;
+    Ok(Json(reports))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn resolve_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(report_id): Path<String>,
+    Json(req): Json<Resolve>,
+) -> ApiResult<Json<AbuseReport>> {
+    verify_admin(&user)?;
+    let mut txn = pool.begin().await?;
+    let report: Option<AbuseReport> = sqlx::query_as(&format!(
+        "
+        UPDATE abuse_reports
+        SET status = $2, reviewer = $3, review_time = NOW()
+        WHERE id = $1 AND status = 'open'
+        RETURNING {REPORT_COLUMNS}"
+    ))
+    .bind(&report_id)
+    .bind(match req.resolution {
+        Resolution::Dismiss => "dismissed",
+        Resolution::Uphold => "upheld",
+    })
+    .bind(&user.email)
+    .fetch_optional(&mut *txn)
+    .await
+    .context("Failed to resolve abuse report")?;
+    let Some(report) = report else {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Open report {report_id} not found"),
+        ));
+    };
+
+    match req.resolution {
+        Resolution::Dismiss => {
+            if !is_quarantined(&mut *txn, &report.project_id, &report.reported).await? {
+                sqlx::query(
+                    "
+                    UPDATE task_comments
+                    SET quarantined = FALSE
+                    WHERE project_id = $1 AND author = $2 AND quarantined",
+                )
+                .bind(&report.project_id)
+                .bind(&report.reported)
+                .execute(&mut *txn)
+                .await
+                .context("Failed to release quarantined comments")?;
+            }
+        }
+        Resolution::Uphold => {
+            sqlx::query(
+                "
+                DELETE FROM task_comments
+                WHERE project_id = $1
+                AND (
+                    id = $3 OR parent_id = $3
+                    OR (author = $2 AND quarantined)
+                )",
+            )
+            .bind(&report.project_id)
+            .bind(&report.reported)
+            .bind(&report.comment_id)
+            .execute(&mut *txn)
+            .await
+            .context("Failed to delete reported comments")?;
+        }
+    }
+    txn.commit().await?;
+    tracing::info!(
+        "{} resolved report {report_id} about {}: {:?}",
+        user.email,
+        report.reported,
+        req.resolution
+    );
+    Ok(Json(report))
+}
+
+/// Whether the user's changes to the project and comments in it are held
+/// for review because of open reports about them. Project admins are never
+/// quarantined.
+pub(crate) async fn is_quarantined<'c, E: PgExecutor<'c>>(
+    executor: E,
+    project_id: &ProjectId,
+    email: &str,
+) -> Result<bool> {
+    let (quarantined,): (bool,) = sqlx::query_as(
+        "
+        SELECT COUNT(DISTINCT r.reporter) >= $3 OR COALESCE(BOOL_OR(pp.admin), FALSE)
+        FROM abuse_reports r
+        LEFT JOIN project_permissions pp ON pp.project_id = r.project_id AND pp.email = r.reporter
+        WHERE r.project_id = $1
+        AND r.reported = $2
+        AND r.status = 'open'
+        AND NOT EXISTS (
+            SELECT 1 FROM project_permissions
+            WHERE project_id = $1 AND email = $2 AND admin
+        )",
+    )
+    .bind(project_id)
+    .bind(email)
+    .bind(QUARANTINE_REPORTERS)
+    .fetch_one(executor)
+    .await
+    .context("Failed to check quarantine")?;
+    Ok(quarantined)
+}
@@ -0,0 +1,532 @@
+//! Offboarding of departing users: reporting what they're responsible for
+//! across projects, reassigning their tasks to a successor, removing them from
+//! projects and unsubscribing them from notifications. Admin only.
+
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{
+            Collab,
+            projects_state::DocBox,
+            txn_origin::{Actor, YOrigin},
+        },
+        google::User,
+        model::ProjectId,
+        not_found_error, verify_admin,
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{Extension, Json, Router, extract::Path, routing::get};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+pub(super) fn router() -> Router {
+    Router::new().route("/{email}", get(get_report_handler).post(offboard_handler))
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AssignedTask {
+    task_id: String,
+    num: String,
+    name: String,
+    status: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ProjectReport {
+    project_id: ProjectId,
+    project_name: String,
+    admin: bool,
+    /// Whether the user is the project's only admin.
+    sole_admin: bool,
+    /// Unarchived tasks assigned to the user.
+    assigned_tasks: Vec<AssignedTask>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OffboardingReport {
+    email: String,
+    projects: Vec<ProjectReport>,
+    /// Notifiers the user configured, e.g. slack.
+    notifiers: Vec<String>,
+    /// Projects the user receives weekly reports for.
+    report_subscriptions: Vec<ProjectId>,
+}
+
+#[derive(FromRow)]
+struct Membership {
+    project_id: ProjectId,
+    project_name: String,
+    admin: bool,
+    other_admins: i64,
+}
+
+/// Reports everything the user is currently responsible for.
+#[tracing::instrument(skip(user, pool))]
+async fn get_report_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(email): Path<String>,
+) -> ApiResult<Json<OffboardingReport>> {
+    verify_admin(&user)?;
+    let email = email.to_lowercase();
+    verify_user_exists(pool, &email).await?;
+    Ok(Json(report(pool, &email).await?))
+}
+
+async fn verify_user_exists(pool: &PgPool, email: &str) -> ApiResult<()> {
+    let exists: Option<(String,)> = sqlx::query_as("SELECT email FROM users WHERE email = $1")
+        .bind(email)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to look up user")?;
+    if exists.is_none() {
+        return Err(not_found_error("NOT_FOUND", &format!("No user {email}")));
+    }
+    Ok(())
+}
+
+async fn memberships(pool: &PgPool, email: &str) -> Result<Vec<Membership>> {
+    sqlx::query_as(
+        "
+        SELECT
+          pp.project_id,
+          p.name AS project_name,
+          pp.admin,
+          (
+            SELECT COUNT(*) FROM project_permissions o
+            WHERE o.project_id = pp.project_id AND o.admin AND o.email != pp.email
+          ) AS other_admins
+        FROM project_permissions pp
+        JOIN projects p ON p.project_id = pp.project_id
+        WHERE pp.email = $1 AND p.deleted_on IS NULL
+        ORDER BY p.name, pp.project_id",
+    )
+    .bind(email)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list memberships")
+}
+
+async fn report(pool: &PgPool, email: &str) -> Result<OffboardingReport> {
+    let mut projects = Vec::new();
+    for membership in memberships(pool, email).await? {
+        let assigned_tasks: Vec<AssignedTask> = sqlx::query_as(
+            "
+            SELECT task_id, num, name, status
+            FROM task_projections
+            WHERE project_id = $1 AND assignee = $2 AND NOT archived
+            ORDER BY num",
+        )
+        .bind(&membership.project_id)
+        .bind(email)
+        .fetch_all(pool)
+        .await
+        .context("Failed to list assigned tasks")?;
+        projects.push(ProjectReport {
+            project_id: membership.project_id,
+            project_name: membership.project_name,
+            admin: membership.admin,
+            sole_admin: membership.admin && membership.other_admins == 0,
+            assigned_tasks,
+        });
+    }
+    let notifiers: Vec<(String,)> = sqlx::query_as(
+        "SELECT notifier FROM user_notification_configs WHERE email = $1 ORDER BY notifier",
+    )
+    .bind(email)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list notifiers")?;
+    let report_subscriptions: Vec<(ProjectId,)> = sqlx::query_as(
+        "SELECT project_id FROM report_subscriptions WHERE email = $1 ORDER BY project_id",
+    )
+    .bind(email)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list report subscriptions")?;
+    Ok(OffboardingReport {
+        email: email.to_string(),
+        projects,
+        notifiers: notifiers.into_iter().map(|(n,)| n).collect(),
+        report_subscriptions: report_subscriptions.into_iter().map(|(p,)| p).collect(),
+    })
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OffboardRequest {
+    /// Inherits the user's tasks, and admin rights, in projects they're a member
+    /// of. Tasks are unassigned if absent.
+    successor: Option<String>,
+    /// Reports what would be done without doing it.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ProjectOutcome {
+    project_id: ProjectId,
+    reassigned_tasks: usize,
+    removed: bool,
+    successor_made_admin: bool,
+    /// Why the user was left in the project, if they were.
+    skipped_reason: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OffboardResponse {
+    dry_run: bool,
+    projects: Vec<ProjectOutcome>,
+    unsubscribed_notifiers: u64,
+    unsubscribed_reports: u64,
+}
+
+/// Reassigns the user's tasks to the successor, one server transaction per
+/// project, then removes the user from each project and unsubscribes them from
+/// notifications. The user is left in projects where they're the only admin
+/// and the successor can't take over.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn offboard_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(email): Path<String>,
+    Json(request): Json<OffboardRequest>,
+) -> ApiResult<Json<OffboardResponse>> {
+    verify_admin(&user)?;
+    let email = email.to_lowercase();
+    verify_user_exists(pool, &email).await?;
+    let successor = request.successor.as_deref().map(str::to_lowercase);
+    if let Some(successor) = &successor {
+        if *successor == email {
+            return Err(bad_request_error(
+                "SAME_SUCCESSOR",
+                "Users can't succeed themselves",
+            ));
+        }
+        verify_user_exists(pool, successor).await?;
+    }
+
+    let response = offboard(pool, &collab, &email, successor.as_deref(), request.dry_run).await?;
+    if !request.dry_run {
+        tracing::info!(
+            "{} offboarded {email}, succeeded by {successor:?}: {:?}",
+            user.email,
+            response.projects
+        );
+    }
+    Ok(Json(response))
+}
+
+async fn offboard(
+    pool: &PgPool,
+    collab: &Collab,
+    email: &str,
+    successor: Option<&str>,
+    dry_run: bool,
+) -> Result<OffboardResponse> {
+    let mut projects = Vec::new();
+    for membership in memberships(pool, email).await? {
+        let successor_member = match successor {
+            Some(successor) => is_member(pool, &membership.project_id, successor).await?,
+            None => false,
+        };
+        let assignee = successor.filter(|_| successor_member);
+        let sole_admin = membership.admin && membership.other_admins == 0;
+        let successor_made_admin = sole_admin && successor_member;
+        let skipped_reason = (sole_admin && !successor_member)
+            .then(|| "The user is the only admin and the successor isn't a member".to_string());
+
+        // Skipped projects are left as they are, tasks included.
+        let reassigned_tasks = if skipped_reason.is_some() {
+            0
+        } else if dry_run {
+            count_assigned(collab, &membership.project_id, email).await?
+        } else {
+            let reassigned = reassign(collab, &membership.project_id, email, assignee).await?;
+            remove_membership(pool, &membership.project_id, email, assignee, sole_admin).await?;
+            reassigned
+        };
+        projects.push(ProjectOutcome {
+            project_id: membership.project_id,
+            reassigned_tasks,
+            removed: skipped_reason.is_none(),
+            successor_made_admin,
+            skipped_reason,
+        });
+    }
+
+    let (unsubscribed_notifiers, unsubscribed_reports) = if dry_run {
+        let report = report(pool, email).await?;
+        (
+            report.notifiers.len() as u64,
+            report.report_subscriptions.len() as u64,
+        )
+    } else {
+        unsubscribe(pool, email).await?
+    };
+    Ok(OffboardResponse {
+        dry_run,
+        projects,
+        unsubscribed_notifiers,
+        unsubscribed_reports,
+    })
+}
+
+async fn is_member(pool: &PgPool, project_id: &ProjectId, email: &str) -> Result<bool> {
+    let member: Option<(String,)> = sqlx::query_as(
+        "SELECT email FROM project_permissions WHERE project_id = $1 AND email = $2",
+    )
+    .bind(project_id)
+    .bind(email)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to check membership")?;
+    Ok(member.is_some())
+}
+
+async fn count_assigned(collab: &Collab, project_id: &ProjectId, email: &str) -> Result<usize> {
+    let client = collab.register_local_client(project_id).await?;
+    let doc_box = client.project.doc_box.lock().await;
+    let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+    let txn = doc.transact();
+    let mut count = 0;
+    for task in doc.tasks(&txn)? {
+        if task.get_assignee(&txn)?.as_deref() == Some(email) {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Reassigns the user's tasks in a single server transaction, using the live
+/// doc rather than projections, which may lag.
+async fn reassign(
+    collab: &Collab,
+    project_id: &ProjectId,
+    email: &str,
+    successor: Option<&str>,
+) -> Result<usize> {
+    let client = collab.register_local_client(project_id).await?;
+    let doc_box = client.project.doc_box.lock().await;
+    let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+    let mut txn = doc.transact_mut_with(
+        YOrigin {
+            who: "offboarding".to_string(),
+            id: format!("offboarding_{email}"),
+            actor: Actor::Server,
+            ..Default::default()
+        }
+        .as_origin()?,
+    );
+    let mut reassigned = 0;
+    for task in doc.tasks(&txn)? {
+        if task.get_assignee(&txn)?.as_deref() == Some(email) {
+            task.set_assignee(&mut txn, successor);
+            reassigned += 1;
+        }
+    }
+    Ok(reassigned)
+}
+
+async fn remove_membership(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    email: &str,
+    successor: Option<&str>,
+    sole_admin: bool,
+) -> Result<()> {
+    let mut txn = pool.begin().await?;
+    if let Some(successor) = successor.filter(|_| sole_admin) {
+        sqlx::query(
+            "UPDATE project_permissions SET admin = TRUE WHERE project_id = $1 AND email = $2",
+        )
+        .bind(project_id)
+        .bind(successor)
+        .execute(&mut *txn)
+        .await
+        .context("Failed to make successor an admin")?;
+    }
+    sqlx::query("DELETE FROM project_permissions WHERE project_id = $1 AND email = $2")
+        .bind(project_id)
+        .bind(email)
+        .execute(&mut *txn)
+        .await
+        .context("Failed to remove membership")?;
+    txn.commit().await?;
+    Ok(())
+}
+
+/// Unsubscribes the user from notifiers and reports, discarding notifications
+/// held for later delivery.
+async fn unsubscribe(pool: &PgPool, email: &str) -> Result<(u64, u64)> {
+    let mut txn = pool.begin().await?;
+    let notifiers = sqlx::query("DELETE FROM user_notification_configs WHERE email = $1")
+        .bind(email)
+        .execute(&mut *txn)
+        .await
+        .context("Failed to delete notifier configs")?
+        .rows_affected();
+    let reports = sqlx::query("DELETE FROM report_subscriptions WHERE email = $1")
+        .bind(email)
+        .execute(&mut *txn)
+        .await
+        .context("Failed to delete report subscriptions")?
+        .rows_affected();
+    sqlx::query("DELETE FROM held_notifications WHERE email = $1")
+        .bind(email)
+        .execute(&mut *txn)
+        .await
+        .context("Failed to delete held notifications")?;
+    txn.commit().await?;
+    Ok((notifiers, reports))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{
+        model::{Task, test_utils::task},
+        yproxy::YDocProxy,
+    };
+    use yrs::{ReadTxn as _, StateVector};
+
+    const LEAVER: &str = "leaver@koso.app";
+    const SUCCESSOR: &str = "successor@koso.app";
+
+    /// Creates a project, administered by the leaver, with one task, "a",
+    /// assigned to them.
+    async fn insert_project(pool: &PgPool, project_id: &str, members: &[&str]) {
+        sqlx::query("INSERT INTO projects (project_id, name) VALUES ($1, $1)")
+            .bind(project_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO project_permissions (project_id, email, admin) VALUES ($1, $2, TRUE)",
+        )
+        .bind(project_id)
+        .bind(LEAVER)
+        .execute(pool)
+        .await
+        .unwrap();
+        for member in members {
+            sqlx::query(
+                "INSERT INTO project_permissions (project_id, email, admin) VALUES ($1, $2, FALSE)",
+            )
+            .bind(project_id)
+            .bind(member)
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+        let doc = YDocProxy::new();
+        let update = {
+            let mut txn = doc.transact_mut_with(origin());
+            doc.set(&mut txn, &task("root", "0", &["a"]));
+            doc.set(
+                &mut txn,
+                &Task {
+                    assignee: Some(LEAVER.to_string()),
+                    ..task("a", "1", &[])
+                },
+            );
+            txn.encode_state_as_update_v2(&StateVector::default())
+        };
+        sqlx::query("INSERT INTO yupdates (project_id, seq, update_v2) VALUES ($1, DEFAULT, $2)")
+            .bind(project_id)
+            .bind(update)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    fn origin() -> yrs::Origin {
+        YOrigin {
+            who: "offboarding_test".to_string(),
+            id: "test".to_string(),
+            actor: Actor::Server,
+            ..Default::default()
+        }
+        .as_origin()
+        .unwrap()
+    }
+
+    async fn assignee(collab: &Collab, project_id: &ProjectId) -> Option<String> {
+        let client = collab.register_local_client(project_id).await.unwrap();
+        let doc_box = client.project.doc_box.lock().await;
+        let doc = &DocBox::doc_or_error(doc_box.as_ref()).unwrap().ydoc;
+        let txn = doc.transact();
+        doc.get(&txn, "a").unwrap().get_assignee(&txn).unwrap()
+    }
+
+    async fn permissions(pool: &PgPool, project_id: &str) -> Vec<(String, bool)> {
+        sqlx::query_as(
+            "SELECT email, admin FROM project_permissions WHERE project_id = $1 ORDER BY email",
+        )
+        .bind(project_id)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
+
+    #[test_log::test(sqlx::test)]
+    async fn offboard_promotes_successor_and_skips_sole_admin_projects(pool: sqlx::PgPool) {
+        let pool: &'static PgPool = Box::leak(Box::new(PgPool::from(pool)));
+        let collab = Collab::new(pool).unwrap();
+        // The successor can take over the first project, but isn't a member of the second.
+        let shared = "shared".to_string();
+        let solo = "solo".to_string();
+        insert_project(pool, &shared, &[SUCCESSOR]).await;
+        insert_project(pool, &solo, &[]).await;
+
+        let dry_run = offboard(pool, &collab, LEAVER, Some(SUCCESSOR), true)
+            .await
+            .unwrap();
+        assert!(dry_run.dry_run);
+        let [shared_outcome, solo_outcome] = dry_run.projects.as_slice() else {
+            panic!("Unexpected outcomes: {:?}", dry_run.projects);
+        };
+        assert_eq!(shared_outcome.project_id, shared);
+        assert_eq!(shared_outcome.reassigned_tasks, 1);
+        assert!(shared_outcome.removed);
+        assert!(shared_outcome.successor_made_admin);
+        assert_eq!(solo_outcome.project_id, solo);
+        assert_eq!(solo_outcome.reassigned_tasks, 0);
+        assert!(!solo_outcome.removed);
+        assert!(solo_outcome.skipped_reason.is_some());
+        // Nothing is changed.
+        assert_eq!(assignee(&collab, &shared).await.as_deref(), Some(LEAVER));
+        assert_eq!(
+            permissions(pool, &shared).await,
+            vec![(LEAVER.to_string(), true), (SUCCESSOR.to_string(), false)]
+        );
+
+        let outcome = offboard(pool, &collab, LEAVER, Some(SUCCESSOR), false)
+            .await
+            .unwrap();
+        assert!(!outcome.dry_run);
+        assert_eq!(outcome.projects[0].reassigned_tasks, 1);
+        assert_eq!(outcome.projects[1].reassigned_tasks, 0);
+        assert_eq!(assignee(&collab, &shared).await.as_deref(), Some(SUCCESSOR));
+        assert_eq!(
+            permissions(pool, &shared).await,
+            vec![(SUCCESSOR.to_string(), true)]
+        );
+        // The user stays in, and keeps their tasks in, projects they can't leave.
+        assert_eq!(assignee(&collab, &solo).await.as_deref(), Some(LEAVER));
+        assert_eq!(
+            permissions(pool, &solo).await,
+            vec![(LEAVER.to_string(), true)]
+        );
+
+        collab.stop().await;
+    }
+}
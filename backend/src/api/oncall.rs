@@ -0,0 +1,405 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error, google::User, model::ProjectId, not_found_error,
+        verify_project_access, verify_project_admin,
+    },
+    postgres::{PgPool, list_project_users},
+};
+use anyhow::{Context as _, Result, anyhow};
+use axum::{Extension, Json, Router, extract::Path, routing::get, routing::put};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::sync::LazyLock;
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(reqwest::Client::new);
+
+/// The current on-call is refetched at least this often, even if PagerDuty
+/// says the shift lasts longer, so schedule edits are picked up.
+const CACHE_MINUTES: i64 = 15;
+const DEFAULT_LABEL: &str = "incident";
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route(
+            "/{project_id}/oncall",
+            get(get_integration_handler)
+                .put(set_integration_handler)
+                .delete(delete_integration_handler),
+        )
+        .route(
+            "/{project_id}/oncall/override",
+            put(set_override_handler).delete(delete_override_handler),
+        )
+        .route("/{project_id}/oncall/current", get(current_oncall_handler))
+}
+
+/// Assigns tasks with the incident label to whoever is on-call, according to
+/// a PagerDuty schedule or a manual override.
+#[derive(Serialize, FromRow, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OnCallIntegration {
+    #[serde(skip)]
+    project_id: ProjectId,
+    /// A PagerDuty REST API key. Never sent back to clients.
+    #[serde(skip)]
+    api_key: String,
+    schedule_id: String,
+    /// Tasks with this label are assigned to the on-call.
+    pub(crate) label: String,
+    override_email: Option<String>,
+    override_until: Option<DateTime<Utc>>,
+    cached_email: Option<String>,
+    cached_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SetOnCallIntegration {
+    api_key: String,
+    schedule_id: String,
+    label: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SetOverride {
+    email: String,
+    until: DateTime<Utc>,
+}
+
+#[derive(Serialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OnCall {
+    pub(crate) email: String,
+    /// override or pagerduty.
+    source: &'static str,
+}
+
+/// See https://developer.pagerduty.com/api-reference/3a6b910f11050-list-all-of-the-on-calls
+#[derive(Deserialize)]
+struct OnCallsResponse {
+    oncalls: Vec<PagerDutyOnCall>,
+}
+
+#[derive(Deserialize)]
+struct PagerDutyOnCall {
+    user: PagerDutyUser,
+    /// Null when the on-call is permanent.
+    end: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct PagerDutyUser {
+    email: String,
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn get_integration_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Option<OnCallIntegration>>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    Ok(Json(fetch_integration(pool, &project_id).await?))
+}
+
+#[tracing::instrument(skip(user, pool, req))]
+async fn set_integration_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Json(req): Json<SetOnCallIntegration>,
+) -> ApiResult<Json<OnCallIntegration>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let api_key = req.api_key.trim();
+    let schedule_id = req.schedule_id.trim();
+    if api_key.is_empty() || schedule_id.is_empty() {
+        return Err(bad_request_error(
+            "INVALID_ONCALL",
+            "An API key and schedule ID are required",
+        ));
+    }
+    let label = req
+        .label
+        .as_deref()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .unwrap_or(DEFAULT_LABEL);
+
+    // Verify the key and schedule now rather than when the first incident is filed.
+    let (email, _) = fetch_pagerduty_oncall(api_key, schedule_id)
+        .await
+        .map_err(|e| {
+            tracing::info!("Failed to verify PagerDuty schedule: {e:?}");
+            bad_request_error(
+                "ONCALL_LOOKUP_FAILED",
+                "Couldn't look up the schedule's on-call with the API key",
+            )
+        })?;
+    tracing::info!("Verified PagerDuty schedule {schedule_id}, on-call is {email}");
+
+    let integration: OnCallIntegration = sqlx::query_as(
+        "
+        INSERT INTO oncall_integrations (project_id, api_key, schedule_id, label, creator)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (project_id)
+        DO UPDATE SET
+            api_key = EXCLUDED.api_key,
+            schedule_id = EXCLUDED.schedule_id,
+            label = EXCLUDED.label,
+            creator = EXCLUDED.creator,
+            cached_email = NULL,
+            cached_until = NULL
+        RETURNING project_id, api_key, schedule_id, label, override_email, override_until, cached_email, cached_until",
+    )
+    .bind(&project_id)
+    .bind(api_key)
+    .bind(schedule_id)
+    .bind(label)
+    .bind(&user.email)
+    .fetch_one(pool)
+    .await
+    .context("Failed to upsert on-call integration")?;
+    Ok(Json(integration))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_integration_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<()>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    sqlx::query("DELETE FROM oncall_integrations WHERE project_id = $1")
+        .bind(&project_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete on-call integration")?;
+    Ok(Json(()))
+}
+
+/// Assigns incidents to the given member, instead of the scheduled on-call, until
+/// the override expires. Useful when swapping shifts without editing PagerDuty.
+#[tracing::instrument(skip(user, pool))]
+async fn set_override_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Json(req): Json<SetOverride>,
+) -> ApiResult<Json<OnCallIntegration>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let email = req.email.trim().to_lowercase();
+    if !list_project_users(pool, &project_id)
+        .await?
+        .iter()
+        .any(|u| u.email == email)
+    {
+        return Err(bad_request_error(
+            "NOT_A_MEMBER",
+            &format!("{email} is not a member of the project"),
+        ));
+    }
+    if req.until <= Utc::now() {
+        return Err(bad_request_error(
+            "INVALID_OVERRIDE",
+            "Override must end in the future",
+        ));
+    }
+    update_override(pool, &project_id, Some(&email), Some(req.until)).await
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_override_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<OnCallIntegration>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    update_override(pool, &project_id, None, None).await
+}
+
+async fn update_override(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    email: Option<&str>,
+    until: Option<DateTime<Utc>>,
+) -> ApiResult<Json<OnCallIntegration>> {
+    let integration: Option<OnCallIntegration> = sqlx::query_as(
+        "
+        UPDATE oncall_integrations
+        SET override_email = $2, override_until = $3
+        WHERE project_id = $1
+        RETURNING project_id, api_key, schedule_id, label, override_email, override_until, cached_email, cached_until",
+    )
+    .bind(project_id)
+    .bind(email)
+    .bind(until)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to update on-call override")?;
+    match integration {
+        Some(integration) => Ok(Json(integration)),
+        None => Err(not_found_error("NOT_FOUND", "No on-call integration")),
+    }
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn current_oncall_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Option<OnCall>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let Some(integration) = fetch_integration(pool, &project_id).await? else {
+        return Ok(Json(None));
+    };
+    Ok(Json(current_oncall(pool, &integration).await?))
+}
+
+pub(crate) async fn fetch_integration(
+    pool: &PgPool,
+    project_id: &ProjectId,
+) -> Result<Option<OnCallIntegration>> {
+    sqlx::query_as(
+        "
+        SELECT project_id, api_key, schedule_id, label, override_email, override_until, cached_email, cached_until
+        FROM oncall_integrations
+        WHERE project_id = $1",
+    )
+    .bind(project_id)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch on-call integration")
+}
+
+/// Returns who's on-call: the override, if active, otherwise the scheduled
+/// on-call, fetched from PagerDuty when the cached one has expired.
+pub(crate) async fn current_oncall(
+    pool: &PgPool,
+    integration: &OnCallIntegration,
+) -> Result<Option<OnCall>> {
+    let now = Utc::now();
+    if let Some(oncall) = cached_oncall(integration, now) {
+        return Ok(Some(oncall));
+    }
+
+    let (email, end) =
+        fetch_pagerduty_oncall(&integration.api_key, &integration.schedule_id).await?;
+    let max_until = now + Duration::minutes(CACHE_MINUTES);
+    let until = end.map_or(max_until, |end| end.min(max_until));
+    sqlx::query(
+        "
+        UPDATE oncall_integrations
+        SET cached_email = $2, cached_until = $3
+        WHERE project_id = $1",
+    )
+    .bind(&integration.project_id)
+    .bind(&email)
+    .bind(until)
+    .execute(pool)
+    .await
+    .context("Failed to cache on-call")?;
+    Ok(Some(OnCall {
+        email,
+        source: "pagerduty",
+    }))
+}
+
+fn cached_oncall(integration: &OnCallIntegration, now: DateTime<Utc>) -> Option<OnCall> {
+    if let (Some(email), Some(until)) = (&integration.override_email, integration.override_until) {
+        if until > now {
+            return Some(OnCall {
+                email: email.clone(),
+                source: "override",
+            });
+        }
+    }
+    match (&integration.cached_email, integration.cached_until) {
+        (Some(email), Some(until)) if until > now => Some(OnCall {
+            email: email.clone(),
+            source: "pagerduty",
+        }),
+        _ => None,
+    }
+}
+
+/// Returns the email of the schedule's current on-call and when their shift ends.
+async fn fetch_pagerduty_oncall(
+    api_key: &str,
+    schedule_id: &str,
+) -> Result<(String, Option<DateTime<Utc>>)> {
+    let res: OnCallsResponse = CLIENT
+        .get("https://api.pagerduty.com/oncalls")
+        .header("Authorization", format!("Token token={api_key}"))
+        .header("Accept", "application/vnd.pagerduty+json;version=2")
+        .query(&[
+            ("schedule_ids[]", schedule_id),
+            ("include[]", "users"),
+            ("earliest", "true"),
+        ])
+        .send()
+        .await
+        .context("Failed to reach PagerDuty")?
+        .error_for_status()
+        .context("PagerDuty rejected on-call request")?
+        .json()
+        .await
+        .context("Failed to decode PagerDuty on-calls")?;
+    let oncall = res
+        .oncalls
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No one is on-call for schedule {schedule_id}"))?;
+    Ok((oncall.user.email.to_lowercase(), oncall.end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn integration() -> OnCallIntegration {
+        OnCallIntegration {
+            project_id: "p".to_string(),
+            api_key: "key".to_string(),
+            schedule_id: "S1".to_string(),
+            label: DEFAULT_LABEL.to_string(),
+            override_email: None,
+            override_until: None,
+            cached_email: None,
+            cached_until: None,
+        }
+    }
+
+    #[test]
+    fn cached_oncall_prefers_active_override() {
+        let now = Utc::now();
+        assert_eq!(cached_oncall(&integration(), now), None);
+
+        let cached = OnCallIntegration {
+            cached_email: Some("a@koso.app".to_string()),
+            cached_until: Some(now + Duration::minutes(5)),
+            ..integration()
+        };
+        assert_eq!(
+            cached_oncall(&cached, now),
+            Some(OnCall {
+                email: "a@koso.app".to_string(),
+                source: "pagerduty"
+            })
+        );
+        assert_eq!(cached_oncall(&cached, now + Duration::minutes(6)), None);
+
+        let overridden = OnCallIntegration {
+            override_email: Some("b@koso.app".to_string()),
+            override_until: Some(now + Duration::hours(1)),
+            ..cached
+        };
+        assert_eq!(
+            cached_oncall(&overridden, now).map(|o| o.email),
+            Some("b@koso.app".to_string())
+        );
+        // Expired overrides fall back to the schedule.
+        assert_eq!(cached_oncall(&overridden, now + Duration::hours(2)), None);
+    }
+}
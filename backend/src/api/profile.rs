@@ -1,23 +1,43 @@
-use crate::api::{ApiResult, google::User};
-use crate::notifiers::UserNotificationConfig;
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        google::User,
+        model::{UpdateProfile, UserProfile, WorkingHours},
+        users::fetch_user_profiles,
+    },
+    notifiers::UserNotificationConfig,
+    postgres::PgPool,
+};
 use anyhow::{Context, Result};
-use axum::{Extension, Json, Router, routing::get};
+use axum::{
+    Extension, Json, Router,
+    body::Bytes,
+    http::{HeaderMap, header::CONTENT_TYPE},
+    routing::{get, put},
+};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
-use sqlx::postgres::PgPool;
 use sqlx::types::chrono;
 use tokio::try_join;
 
-use super::not_found_error;
+use super::{away, not_found_error, zapier};
 
 pub(crate) fn router() -> Router {
-    Router::new().route("/", get(get_profile_handler))
+    Router::new()
+        .route("/", get(get_profile_handler).patch(update_profile_handler))
+        .route(
+            "/avatar",
+            put(upload_avatar_handler).delete(delete_avatar_handler),
+        )
+        .merge(zapier::keys_router())
+        .merge(away::router())
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct Profile {
+    user: Option<UserProfile>,
     notification_configs: Vec<UserNotificationConfig>,
     plugin_connections: PluginConnections,
     subscriptions: Subscriptions,
@@ -58,17 +78,25 @@ async fn get_profile_handler(
     Extension(user): Extension<User>,
     Extension(pool): Extension<&'static PgPool>,
 ) -> ApiResult<Json<Profile>> {
-    let (notification_configs, plugin_connections, owned_subscription, subscription_end_time) = try_join!(
+    let (
+        notification_configs,
+        plugin_connections,
+        owned_subscription,
+        subscription_end_time,
+        user_profiles,
+    ) = try_join!(
         fetch_notification_configs(&user.email, pool),
         fetch_plugin_connections(&user.email, pool),
         fetch_owned_subscription(&user.email, pool),
         fetch_subscription_end_time(&user.email, pool),
+        fetch_user_profiles(pool, &user.email, std::slice::from_ref(&user.email)),
     )?;
     let Some(plugin_connections) = plugin_connections else {
         return Err(not_found_error("NOT_FOUND", "User not found"));
     };
 
     Ok(Json(Profile {
+        user: user_profiles.into_iter().next(),
         notification_configs,
         plugin_connections,
         subscriptions: Subscriptions {
@@ -88,6 +116,163 @@ async fn get_profile_handler(
     }))
 }
 
+#[tracing::instrument(skip(user, pool))]
+async fn update_profile_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Json(update): Json<UpdateProfile>,
+) -> ApiResult<Json<UserProfile>> {
+    validate_update_profile(&update)?;
+
+    let res = sqlx::query(
+        "
+        UPDATE users
+        SET display_name = $2, timezone = $3, locale = $4, working_hours = $5
+        WHERE email = $1",
+    )
+    .bind(&user.email)
+    .bind(update.display_name.as_deref().map(str::trim))
+    .bind(&update.timezone)
+    .bind(&update.locale)
+    .bind(update.working_hours.as_ref().map(sqlx::types::Json))
+    .execute(pool)
+    .await
+    .context("Failed to update profile")?;
+    if res.rows_affected() == 0 {
+        return Err(not_found_error("NOT_FOUND", "User not found"));
+    }
+
+    match fetch_user_profiles(pool, &user.email, std::slice::from_ref(&user.email))
+        .await?
+        .into_iter()
+        .next()
+    {
+        Some(profile) => Ok(Json(profile)),
+        None => Err(not_found_error("NOT_FOUND", "User not found")),
+    }
+}
+
+fn validate_update_profile(update: &UpdateProfile) -> ApiResult<()> {
+    if let Some(display_name) = &update.display_name {
+        if display_name.trim().is_empty() {
+            return Err(bad_request_error("EMPTY_NAME", "Display name is blank"));
+        }
+        const MAX_NAME_LEN: usize = 255;
+        if display_name.len() > MAX_NAME_LEN {
+            return Err(bad_request_error(
+                "LONG_NAME",
+                &format!("Display name cannot be longer than {MAX_NAME_LEN} characters"),
+            ));
+        }
+    }
+    if let Some(timezone) = &update.timezone {
+        if timezone.parse::<chrono_tz::Tz>().is_err() {
+            return Err(bad_request_error(
+                "INVALID_TIMEZONE",
+                &format!(
+                    "Timezone must be an IANA time zone such as America/Los_Angeles: {timezone}"
+                ),
+            ));
+        }
+    }
+    if let Some(locale) = &update.locale {
+        if locale.is_empty()
+            || locale.len() > 35
+            || !locale
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(bad_request_error(
+                "INVALID_LOCALE",
+                &format!("Locale must be a language tag such as en-US: {locale}"),
+            ));
+        }
+    }
+    if let Some(WorkingHours {
+        start_hour,
+        end_hour,
+        days,
+    }) = &update.working_hours
+    {
+        if *start_hour >= 24 || *end_hour > 24 || start_hour >= end_hour {
+            return Err(bad_request_error(
+                "INVALID_WORKING_HOURS",
+                &format!("Invalid working hours: {start_hour}-{end_hour}"),
+            ));
+        }
+        if days.is_empty() {
+            return Err(bad_request_error(
+                "INVALID_WORKING_HOURS",
+                "Working hours must include at least one day",
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[tracing::instrument(skip(user, pool, headers, body))]
+async fn upload_avatar_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> ApiResult<Json<UserProfile>> {
+    const CONTENT_TYPES: &[&str] = &["image/png", "image/jpeg", "image/gif", "image/webp"];
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    if !CONTENT_TYPES.contains(&content_type) {
+        return Err(bad_request_error(
+            "INVALID_CONTENT_TYPE",
+            &format!("Avatar must be one of {CONTENT_TYPES:?}, got '{content_type}'"),
+        ));
+    }
+    const MAX_AVATAR_BYTES: usize = 1024 * 1024;
+    if body.is_empty() || body.len() > MAX_AVATAR_BYTES {
+        return Err(bad_request_error(
+            "INVALID_AVATAR_SIZE",
+            &format!("Avatar must be between 1 and {MAX_AVATAR_BYTES} bytes"),
+        ));
+    }
+
+    sqlx::query(
+        "
+        INSERT INTO user_avatars (email, content_type, data, update_time)
+        VALUES ($1, $2, $3, NOW())
+        ON CONFLICT (email)
+        DO UPDATE SET content_type = EXCLUDED.content_type, data = EXCLUDED.data, update_time = NOW()",
+    )
+    .bind(&user.email)
+    .bind(content_type)
+    .bind(body.as_ref())
+    .execute(pool)
+    .await
+    .context("Failed to upload avatar")?;
+
+    match fetch_user_profiles(pool, &user.email, std::slice::from_ref(&user.email))
+        .await?
+        .into_iter()
+        .next()
+    {
+        Some(profile) => Ok(Json(profile)),
+        None => Err(not_found_error("NOT_FOUND", "User not found")),
+    }
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_avatar_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<()>> {
+    sqlx::query("DELETE FROM user_avatars WHERE email = $1")
+        .bind(&user.email)
+        .execute(pool)
+        .await
+        .context("Failed to delete avatar")?;
+    Ok(Json(()))
+}
+
 async fn fetch_notification_configs(
     email: &str,
     pool: &PgPool,
@@ -102,6 +287,15 @@ async fn fetch_notification_configs(
     .fetch_all(pool)
     .await
     .context("Failed to query notification configs")
+    .map(|configs: Vec<UserNotificationConfig>| {
+        configs
+            .into_iter()
+            .map(|config| UserNotificationConfig {
+                settings: config.settings.redacted(),
+                ..config
+            })
+            .collect()
+    })
 }
 
 async fn fetch_plugin_connections(email: &str, pool: &PgPool) -> Result<Option<PluginConnections>> {
@@ -0,0 +1,179 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{
+            Collab,
+            projects_state::DocBox,
+            txn_origin::{Actor, YOrigin},
+        },
+        google::User,
+        model::{
+            AutoArchiveSettings, AutomationRule, EstimateSettings, IterationSettings, Label,
+            ProjectConfig, ProjectId, WorkflowState,
+        },
+        proposals::transact_or_propose,
+        verify_project_access,
+        yproxy::{YDocProxy, validate_config},
+    },
+    postgres::PgPool,
+};
+use anyhow::Result;
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    routing::{get, put},
+};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::Value;
+use yrs::TransactionMut;
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route(
+            "/{project_id}/config",
+            get(get_config_handler).put(set_config_handler),
+        )
+        .route(
+            "/{project_id}/config/{setting}",
+            put(set_config_setting_handler),
+        )
+}
+
+/// The individually settable parts of a project's configuration.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+enum ConfigSetting {
+    WorkflowStates,
+    Labels,
+    Iteration,
+    AutomationRules,
+    AutoArchive,
+    Estimates,
+}
+
+#[tracing::instrument(skip(user, pool, collab))]
+async fn get_config_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<ProjectConfig>> {
+    verify_project_access(pool, &user, &project_id).await?;
+
+    let client = collab.register_local_client(&project_id).await?;
+    let doc_box = client.project.doc_box.lock().await;
+    let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+    let txn = doc.transact();
+    Ok(Json(doc.config().get(&txn)?))
+}
+
+/// Replaces the project's configuration. Clients editing the doc directly
+/// should prefer updating individual settings so concurrent edits merge.
+/// Changing how tasks are estimated converts existing estimates.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn set_config_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+    Json(config): Json<ProjectConfig>,
+) -> ApiResult<Json<ProjectConfig>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    if let Err(e) = validate_config(&config) {
+        return Err(bad_request_error("INVALID_CONFIG", &e.to_string()));
+    }
+
+    let origin = YOrigin {
+        who: "set_config_handler".to_string(),
+        id: "set_config".to_string(),
+        actor: Actor::User(user),
+        ..Default::default()
+    };
+    transact_or_propose(pool, &collab, &project_id, &origin, |doc, txn| {
+        let previous = doc.config().get_estimates(txn)?;
+        doc.config().set(txn, &config)?;
+        convert_estimates(doc, txn, previous, &config.estimates)?;
+        Ok(())
+    })
+    .await?;
+    Ok(Json(config))
+}
+
+/// Replaces a single setting, e.g. `labels`, leaving the rest as is so
+/// concurrent edits to different settings merge. Returns the resulting configuration.
+#[tracing::instrument(skip(user, pool, collab, value))]
+async fn set_config_setting_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path((project_id, setting)): Path<(ProjectId, ConfigSetting)>,
+    Json(value): Json<Value>,
+) -> ApiResult<Json<ProjectConfig>> {
+    verify_project_access(pool, &user, &project_id).await?;
+
+    let origin = YOrigin {
+        who: "set_config_setting_handler".to_string(),
+        id: "set_config_setting".to_string(),
+        actor: Actor::User(user),
+        ..Default::default()
+    };
+    let config = transact_or_propose(pool, &collab, &project_id, &origin, |doc, txn| {
+        let config = doc.config();
+        let res = match setting {
+            ConfigSetting::WorkflowStates => {
+                config.set_workflow_states(txn, &parse_setting::<Vec<WorkflowState>>(value)?)
+            }
+            ConfigSetting::Labels => config.set_labels(txn, &parse_setting::<Vec<Label>>(value)?),
+            ConfigSetting::Iteration => config.set_iteration(
+                txn,
+                parse_setting::<Option<IterationSettings>>(value)?.as_ref(),
+            ),
+            ConfigSetting::AutomationRules => {
+                config.set_automation_rules(txn, &parse_setting::<Vec<AutomationRule>>(value)?)
+            }
+            ConfigSetting::AutoArchive => config.set_auto_archive(
+                txn,
+                parse_setting::<Option<AutoArchiveSettings>>(value)?.as_ref(),
+            ),
+            ConfigSetting::Estimates => {
+                let previous = config.get_estimates(txn)?;
+                let estimates = parse_setting::<Option<EstimateSettings>>(value)?;
+                let res = config.set_estimates(txn, estimates.as_ref());
+                if res.is_ok() {
+                    convert_estimates(doc, txn, previous, &estimates)?;
+                }
+                res
+            }
+        };
+        if let Err(e) = res {
+            return Err(bad_request_error("INVALID_CONFIG", &e.to_string()));
+        }
+        Ok(config.get(txn)?)
+    })
+    .await?
+    .result;
+    Ok(Json(config))
+}
+
+fn parse_setting<T: DeserializeOwned>(value: Value) -> ApiResult<T> {
+    serde_json::from_value(value)
+        .map_err(|e| bad_request_error("INVALID_CONFIG", &format!("Invalid setting: {e}")))
+}
+
+/// Migrates existing estimates when the unit or sizes change.
+fn convert_estimates(
+    doc: &YDocProxy,
+    txn: &mut TransactionMut,
+    previous: Option<EstimateSettings>,
+    estimates: &Option<EstimateSettings>,
+) -> Result<()> {
+    if previous != *estimates {
+        let converted = doc.convert_estimates(
+            txn,
+            &previous.unwrap_or_default(),
+            &estimates.clone().unwrap_or_default(),
+        )?;
+        tracing::debug!("Converted {converted} estimates to {estimates:?}");
+    }
+    Ok(())
+}
@@ -1,28 +1,35 @@
 use crate::{
     api::{
-        ApiResult, bad_request_error,
+        ApiResult, alerts, analytics, attachments, auto_archive, auto_assign, away,
+        bad_request_error, blueprints, board, branches, bulk,
         collab::{
-            Collab, storage,
+            Collab, attribution,
+            projects_state::DocBox,
+            storage,
             txn_origin::{self, YOrigin},
         },
+        comments, decisions, demo, deployments, embed, forecast, goals,
         google::User,
+        groups, imports, inbound_email, integrations, management, milestones,
         model::{
-            CreateProject, Project, ProjectExport, ProjectUser, UpdateProjectUsers,
-            UpdateProjectUsersResponse,
+            CreateProject, Project, ProjectExport, ProjectId, ProjectUser, Task,
+            UpdateProjectAdmin, UpdateProjectUsers, UpdateProjectUsersResponse,
         },
-        verify_premium, verify_project_access,
-        yproxy::YDocProxy,
+        moderation, not_found_error, oncall, project_config, proposals, regions, release_notes,
+        reports, reverts, risks, snapshots, status_pages, step_up, transactions, unfurl, unread,
+        usage, verify_premium, verify_project_access, verify_project_admin, webhooks,
+        yproxy::{YDocProxy, is_valid_task_key_prefix},
     },
-    postgres::list_project_users,
+    postgres::{self, PgPool, ReadPool, list_project_users},
 };
 use anyhow::Result;
 use axum::{
     Extension, Json, Router,
     extract::Path,
-    routing::{delete, get, patch, post},
+    middleware,
+    routing::{delete, get, patch, post, put},
 };
 use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
-use sqlx::postgres::PgPool;
 use uuid::Uuid;
 use yrs::{ReadTxn as _, StateVector};
 
@@ -32,14 +39,68 @@ pub(super) fn router() -> Router {
         .route("/", post(create_project_handler))
         .route("/{project_id}", get(get_project_handler))
         .route("/{project_id}", patch(update_project_handler))
-        .route("/{project_id}", delete(delete_project_handler))
+        .route(
+            "/{project_id}",
+            delete(delete_project_handler)
+                .route_layer(middleware::from_fn(step_up::require_step_up)),
+        )
+        .route(
+            "/{project_id}/task-key-prefix",
+            put(set_task_key_prefix_handler),
+        )
         .route("/{project_id}/users", patch(update_project_users_handler))
         .route("/{project_id}/users", get(list_project_users_handler))
+        .route(
+            "/{project_id}/users/{email}/admin",
+            put(update_project_admin_handler),
+        )
         .route(
             "/{project_id}/updates",
             get(get_project_doc_updates_handler),
         )
-        .route("/{project_id}/export", get(export_project))
+        .route("/{project_id}/tasks/{task_id}", get(get_task_handler))
+        .route(
+            "/{project_id}/export",
+            get(export_project).route_layer(middleware::from_fn(step_up::require_step_up)),
+        )
+        .merge(groups::router())
+        .merge(proposals::router())
+        .merge(auto_assign::router())
+        .merge(management::router())
+        .merge(blueprints::projects_router())
+        .merge(away::workload_router())
+        .merge(board::router())
+        .merge(branches::router())
+        .merge(comments::router())
+        .merge(project_config::router())
+        .merge(bulk::router())
+        .merge(usage::router())
+        .merge(reverts::router())
+        .merge(transactions::router())
+        .merge(webhooks::router())
+        .merge(demo::router())
+        .merge(embed::router())
+        .merge(status_pages::router())
+        .merge(unread::router())
+        .merge(unfurl::router())
+        .merge(moderation::router())
+        .merge(snapshots::router())
+        .merge(reports::router())
+        .merge(analytics::router())
+        .merge(forecast::router())
+        .merge(goals::router())
+        .merge(milestones::router())
+        .merge(risks::router())
+        .merge(decisions::router())
+        .merge(attachments::router())
+        .merge(inbound_email::router())
+        .merge(auto_archive::router())
+        .merge(deployments::router())
+        .merge(release_notes::router())
+        .merge(alerts::router())
+        .merge(oncall::router())
+        .merge(imports::router())
+        .merge(integrations::router())
 }
 
 #[tracing::instrument(skip(user, pool))]
@@ -52,16 +113,19 @@ async fn list_projects_handler(
     Ok(Json(projects))
 }
 
-async fn list_projects(email: &String, pool: &PgPool) -> Result<Vec<Project>> {
+pub(crate) async fn list_projects(email: &String, pool: &PgPool) -> Result<Vec<Project>> {
     let projects: Vec<Project> = sqlx::query_as(
         "
         SELECT
           project_id,
           projects.name,
-          projects.deleted_on
+          projects.deleted_on,
+          projects.task_key_prefix,
+          projects.region
         FROM project_permissions 
         JOIN projects USING(project_id)
-        WHERE email = $1",
+        WHERE email = $1
+        AND project_id NOT IN (SELECT branch_project_id FROM project_branches)",
     )
     .bind(email)
     .fetch_all(pool)
@@ -86,47 +150,104 @@ async fn create_project_handler(
     }
     validate_project_name(&project.name)?;
 
-    let import_update = if let Some(import_data) = project.project_export {
+    if let Some(import_data) = &project.project_export {
+        risks::validate_import(&import_data.risks)?;
+    }
+    if project.project_export.is_some() && project.blueprint_id.is_some() {
+        return Err(bad_request_error(
+            "BLUEPRINT_WITH_IMPORT",
+            "Projects can't be both imported and created from a blueprint",
+        ));
+    }
+    let blueprint = match &project.blueprint_id {
+        Some(blueprint_id) => Some(blueprints::find(pool, &user, blueprint_id).await?),
+        None => None,
+    };
+
+    let mut blueprint_task_ids = None;
+    let (import_update, import_risks) = if let Some(import_data) = project.project_export {
         let ydoc = YDocProxy::new();
         let mut txn: yrs::TransactionMut<'_> = ydoc.transact_mut_with(
             YOrigin {
                 who: "importer".to_string(),
                 id: "import".to_string(),
                 actor: txn_origin::Actor::Server,
+                ..Default::default()
             }
             .as_origin()?,
         );
         for import_task in import_data.graph.values() {
             ydoc.set(&mut txn, import_task);
         }
-        Some(txn.encode_state_as_update_v2(&StateVector::default()))
+        (
+            Some(txn.encode_state_as_update_v2(&StateVector::default())),
+            import_data.risks,
+        )
+    } else if let Some(blueprint) = &blueprint {
+        let ydoc = YDocProxy::new();
+        let mut txn: yrs::TransactionMut<'_> = ydoc.transact_mut_with(
+            YOrigin {
+                who: "blueprint".to_string(),
+                id: format!("blueprint_{}", blueprint.id),
+                actor: txn_origin::Actor::Server,
+                ..Default::default()
+            }
+            .as_origin()?,
+        );
+        match blueprints::instantiate(&ydoc, &mut txn, &blueprint.content, &user.email) {
+            Ok(task_ids) => blueprint_task_ids = Some(task_ids),
+            Err(e) => return Err(bad_request_error("INVALID_BLUEPRINT", &e.to_string())),
+        }
+        (
+            Some(txn.encode_state_as_update_v2(&StateVector::default())),
+            vec![],
+        )
     } else {
-        None
+        (None, vec![])
     };
 
+    let (org, region) = regions::home(pool, &user.email).await?;
     let project = Project {
         project_id: BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4()),
         name: project.name,
         deleted_on: None,
+        task_key_prefix: None,
+        region,
     };
 
+    // The doc goes first, to the project's region. One left behind by a
+    // failed create is never loaded.
+    if let Some(import_update) = import_update {
+        sqlx::query("INSERT INTO yupdates (project_id, seq, update_v2) VALUES ($1, DEFAULT, $2)")
+            .bind(&project.project_id)
+            .bind(import_update)
+            .execute(postgres::region_pool(pool, project.region.as_deref())?)
+            .await?;
+    }
     let mut txn = pool.begin().await?;
-    sqlx::query("INSERT INTO projects (project_id, name) VALUES ($1, $2)")
+    sqlx::query("INSERT INTO projects (project_id, name, region, org) VALUES ($1, $2, $3, $4)")
         .bind(&project.project_id)
         .bind(&project.name)
+        .bind(&project.region)
+        .bind(&org)
         .execute(&mut *txn)
         .await?;
-    sqlx::query("INSERT INTO project_permissions (project_id, email) VALUES ($1, $2)")
+    sqlx::query("INSERT INTO project_permissions (project_id, email, admin) VALUES ($1, $2, TRUE)")
         .bind(&project.project_id)
         .bind(&user.email)
         .execute(&mut *txn)
         .await?;
-    if let Some(import_update) = import_update {
-        sqlx::query("INSERT INTO yupdates (project_id, seq, update_v2) VALUES ($1, DEFAULT, $2)")
-            .bind(&project.project_id)
-            .bind(import_update)
-            .execute(&mut *txn)
-            .await?;
+    risks::import_risks(&mut txn, &project.project_id, &import_risks).await?;
+    if let (Some(blueprint), Some(task_ids)) = (&blueprint, &blueprint_task_ids) {
+        blueprints::apply::record(
+            &mut txn,
+            &project.project_id,
+            &blueprint.id,
+            blueprint.version,
+            &blueprint.content,
+            task_ids,
+        )
+        .await?;
     }
     txn.commit().await?;
 
@@ -189,7 +310,54 @@ async fn update_project_handler(
         .bind(&project.name)
         .execute(pool)
         .await?;
-    Ok(Json(project))
+    Ok(Json(fetch_project(pool, &project_id).await?))
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SetTaskKeyPrefix {
+    task_key_prefix: Option<String>,
+}
+
+/// Sets, or clears, the prefix of the project's task keys. Task nums are
+/// unchanged, so existing references by num keep working.
+#[tracing::instrument(skip(user, pool))]
+async fn set_task_key_prefix_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<String>,
+    Json(req): Json<SetTaskKeyPrefix>,
+) -> ApiResult<Json<Project>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let prefix = req.task_key_prefix.map(|p| p.trim().to_ascii_uppercase());
+    if let Some(prefix) = &prefix {
+        if !is_valid_task_key_prefix(prefix) {
+            return Err(bad_request_error(
+                "INVALID_TASK_KEY_PREFIX",
+                "Task key prefixes must be 2 to 10 letters or digits, starting with a letter",
+            ));
+        }
+    }
+
+    sqlx::query("UPDATE projects SET task_key_prefix = $2 WHERE project_id = $1")
+        .bind(&project_id)
+        .bind(&prefix)
+        .execute(pool)
+        .await?;
+    Ok(Json(fetch_project(pool, &project_id).await?))
+}
+
+/// Returns the prefix of the project's task keys, if it has one.
+pub(crate) async fn fetch_task_key_prefix(
+    pool: &PgPool,
+    project_id: &str,
+) -> Result<Option<String>> {
+    let prefix: Option<(Option<String>,)> =
+        sqlx::query_as("SELECT task_key_prefix FROM projects WHERE project_id = $1")
+            .bind(project_id)
+            .fetch_optional(pool)
+            .await?;
+    Ok(prefix.and_then(|(prefix,)| prefix))
 }
 
 #[tracing::instrument(skip(user, pool))]
@@ -264,8 +432,8 @@ async fn update_project_users_handler(
     if !add_emails.is_empty() {
         sqlx::query(
             "
-            INSERT INTO project_permissions (project_id, email) 
-            SELECT $1, * FROM UNNEST($2)
+            INSERT INTO project_permissions (project_id, email, admin)
+            SELECT $1, email, FALSE FROM UNNEST($2) AS email
             ON CONFLICT DO NOTHING",
         )
         .bind(&update.project_id)
@@ -273,12 +441,57 @@ async fn update_project_users_handler(
         .execute(&mut *txn)
         .await?;
     }
+    if proposals::lacks_admin(&mut txn, &update.project_id).await? {
+        return Err(bad_request_error(
+            "LAST_ADMIN",
+            "Make another member an admin before removing the last one",
+        ));
+    }
 
     txn.commit().await?;
 
     Ok(Json(UpdateProjectUsersResponse {}))
 }
 
+#[tracing::instrument(skip(user, pool))]
+async fn update_project_admin_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, email)): Path<(ProjectId, String)>,
+    Json(update): Json<UpdateProjectAdmin>,
+) -> ApiResult<Json<UpdateProjectAdmin>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let email = email.to_lowercase();
+
+    let mut txn = pool.begin().await?;
+    let res = sqlx::query(
+        "
+        UPDATE project_permissions
+        SET admin = $3
+        WHERE project_id = $1 AND email = $2",
+    )
+    .bind(&project_id)
+    .bind(&email)
+    .bind(update.admin)
+    .execute(&mut *txn)
+    .await?;
+    if res.rows_affected() == 0 {
+        return Err(not_found_error(
+            "NOT_A_MEMBER",
+            &format!("User {email} is not a member of {project_id}"),
+        ));
+    }
+    if proposals::lacks_admin(&mut txn, &project_id).await? {
+        return Err(bad_request_error(
+            "LAST_ADMIN",
+            "Make another member an admin before revoking the last one",
+        ));
+    }
+    txn.commit().await?;
+
+    Ok(Json(update))
+}
+
 #[tracing::instrument(skip(user, pool))]
 async fn get_project_doc_updates_handler(
     Extension(user): Extension<User>,
@@ -301,7 +514,9 @@ async fn fetch_project(pool: &PgPool, project_id: &str) -> Result<Project> {
         SELECT
             project_id,
             projects.name,
-            projects.deleted_on
+            projects.deleted_on,
+            projects.task_key_prefix,
+            projects.region
         FROM projects
         WHERE project_id = $1",
     )
@@ -310,20 +525,56 @@ async fn fetch_project(pool: &PgPool, project_id: &str) -> Result<Project> {
     .await?)
 }
 
+/// Returns the task, including who created and last modified it.
 #[tracing::instrument(skip(user, pool, collab))]
+async fn get_task_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path((project_id, task_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<Task>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let mut task = {
+        let client = collab.register_local_client(&project_id).await?;
+        let doc_box = client.project.doc_box.lock().await;
+        let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+        let txn = doc.transact();
+        match doc.get(&txn, &task_id) {
+            Ok(task) => task.to_task(&txn)?,
+            Err(_) => {
+                return Err(not_found_error(
+                    "NOT_FOUND",
+                    &format!("Task {task_id} not found"),
+                ));
+            }
+        }
+    };
+    attribution::annotate_task(pool, &project_id, &mut task).await?;
+    Ok(Json(task))
+}
+
+#[tracing::instrument(skip(user, pool, read_pool, collab))]
 async fn export_project(
     Extension(user): Extension<User>,
     Extension(pool): Extension<&'static PgPool>,
+    Extension(read_pool): Extension<ReadPool>,
     Extension(collab): Extension<Collab>,
     Path(project_id): Path<String>,
 ) -> ApiResult<Json<ProjectExport>> {
     verify_project_access(pool, &user, &project_id).await?;
 
-    let graph = collab.get_graph(&project_id).await?;
-    Ok(Json(ProjectExport { project_id, graph }))
+    let reader = read_pool.get();
+    let mut graph = collab.get_graph(reader, &project_id).await?;
+    attribution::annotate(reader, &project_id, &mut graph).await?;
+    let risks = risks::list_risks(reader, &project_id).await?;
+    Ok(Json(ProjectExport {
+        project_id,
+        graph,
+        risks,
+    }))
 }
 
-fn validate_project_name(name: &str) -> ApiResult<()> {
+pub(super) fn validate_project_name(name: &str) -> ApiResult<()> {
     if name.is_empty() || name.chars().all(char::is_whitespace) {
         return Err(bad_request_error("EMPTY_NAME", "Project name is blank"));
     }
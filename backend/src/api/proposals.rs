@@ -0,0 +1,524 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{
+            Collab,
+            projects_state::DocBox,
+            txn_origin::{Actor, YOrigin},
+        },
+        google::User,
+        inbox::Inbox,
+        model::{
+            ChangeProposal, Graph, InboxKind, ProjectId, ProposedTaskChange, UpdateReviewSettings,
+        },
+        moderation, not_found_error, verify_project_access, verify_project_admin,
+        yproxy::YDocProxy,
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    routing::{get, post, put},
+};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use sqlx::PgConnection;
+use std::collections::BTreeSet;
+use yrs::{ReadTxn as _, StateVector, TransactionMut, Update, updates::decoder::Decode as _};
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/{project_id}/review", put(update_review_settings_handler))
+        .route("/{project_id}/proposals", get(list_proposals_handler))
+        .route(
+            "/{project_id}/proposals/{proposal_id}/preview",
+            get(preview_proposal_handler),
+        )
+        .route(
+            "/{project_id}/proposals/{proposal_id}/apply",
+            post(apply_proposal_handler),
+        )
+        .route(
+            "/{project_id}/proposals/{proposal_id}/reject",
+            post(reject_proposal_handler),
+        )
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn update_review_settings_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Json(settings): Json<UpdateReviewSettings>,
+) -> ApiResult<Json<UpdateReviewSettings>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    sqlx::query(
+        "
+        UPDATE projects
+        SET require_review = $2, require_agent_review = COALESCE($3, require_agent_review)
+        WHERE project_id = $1",
+    )
+    .bind(&project_id)
+    .bind(settings.require_review)
+    .bind(settings.require_agent_review)
+    .execute(pool)
+    .await
+    .context("Failed to update review settings")?;
+    Ok(Json(settings))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn list_proposals_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Vec<ChangeProposal>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let proposals: Vec<ChangeProposal> = sqlx::query_as(
+        "
+        SELECT id, author, agent, status, reviewer, create_time, update_time
+        FROM change_proposals
+        WHERE project_id = $1
+        ORDER BY status = 'pending' DESC, update_time DESC
+        LIMIT 100",
+    )
+    .bind(&project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list proposals")?;
+    Ok(Json(proposals))
+}
+
+/// Describes the tasks the proposal would change if applied to the live doc now.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn preview_proposal_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path((project_id, proposal_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<Vec<ProposedTaskChange>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let update = fetch_pending_update(pool, &project_id, &proposal_id).await?;
+
+    let live_state = {
+        let client = collab.register_local_client(&project_id).await?;
+        let doc_box = client.project.doc_box.lock().await;
+        DocBox::doc_or_error(doc_box.as_ref())?
+            .ydoc
+            .transact()
+            .encode_state_as_update_v2(&StateVector::default())
+    };
+    let ydoc = YDocProxy::new();
+    let mut txn = ydoc.transact_mut_with(
+        YOrigin {
+            who: "preview_proposal".to_string(),
+            id: proposal_id.clone(),
+            actor: Actor::Server,
+            ..Default::default()
+        }
+        .as_origin()?,
+    );
+    txn.apply_update(Update::decode_v2(&live_state)?)?;
+    let before = ydoc.to_graph(&txn)?;
+    txn.apply_update(update)?;
+    let after = ydoc.to_graph(&txn)?;
+
+    Ok(Json(diff_graphs(before, after)))
+}
+
+#[tracing::instrument(skip(user, pool, collab))]
+async fn apply_proposal_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path((project_id, proposal_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<()>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let update = fetch_pending_update(pool, &project_id, &proposal_id).await?;
+    collab
+        .apply_update(
+            &project_id,
+            YOrigin {
+                who: "apply_proposal".to_string(),
+                id: proposal_id.clone(),
+                actor: Actor::User(user.clone()),
+                ..Default::default()
+            },
+            update,
+        )
+        .await?;
+    close_proposal(pool, &proposal_id, "applied", &user.email).await?;
+    Ok(Json(()))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn reject_proposal_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, proposal_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<()>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    fetch_pending_update(pool, &project_id, &proposal_id).await?;
+    close_proposal(pool, &proposal_id, "rejected", &user.email).await?;
+    Ok(Json(()))
+}
+
+/// Returns the proposal's updates merged into one.
+async fn fetch_pending_update(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    proposal_id: &str,
+) -> ApiResult<Update> {
+    let pending: Option<(String,)> = sqlx::query_as(
+        "
+        SELECT id
+        FROM change_proposals
+        WHERE project_id = $1 AND id = $2 AND status = 'pending'",
+    )
+    .bind(project_id)
+    .bind(proposal_id)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch proposal")?;
+    if pending.is_none() {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Pending proposal {proposal_id} not found"),
+        ));
+    }
+
+    let updates: Vec<(Vec<u8>,)> = sqlx::query_as(
+        "
+        SELECT update_v2
+        FROM proposal_updates
+        WHERE proposal_id = $1
+        ORDER BY seq",
+    )
+    .bind(proposal_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch proposal updates")?;
+    if updates.is_empty() {
+        return Err(bad_request_error(
+            "EMPTY_PROPOSAL",
+            "Proposal has no changes",
+        ));
+    }
+    Ok(Update::merge_updates(
+        updates
+            .into_iter()
+            .map(|(update,)| Update::decode_v2(&update))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to decode proposal update")?,
+    ))
+}
+
+async fn close_proposal(
+    pool: &PgPool,
+    proposal_id: &str,
+    status: &str,
+    reviewer: &str,
+) -> Result<()> {
+    sqlx::query(
+        "
+        UPDATE change_proposals
+        SET status = $2, reviewer = $3, update_time = NOW()
+        WHERE id = $1",
+    )
+    .bind(proposal_id)
+    .bind(status)
+    .bind(reviewer)
+    .execute(pool)
+    .await
+    .context("Failed to close proposal")?;
+    Ok(())
+}
+
+/// Whether changes from the user must be reviewed before being applied,
+/// because the project requires review or the user is quarantined.
+pub(crate) async fn requires_review(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    email: &str,
+) -> Result<bool> {
+    if moderation::is_quarantined(pool, project_id, email).await? {
+        return Ok(true);
+    }
+    let (requires_review,): (bool,) = sqlx::query_as(
+        "
+        SELECT p.require_review AND NOT COALESCE(pp.admin, FALSE)
+        FROM projects p
+        LEFT JOIN project_permissions pp ON pp.project_id = p.project_id AND pp.email = $2
+        WHERE p.project_id = $1",
+    )
+    .bind(project_id)
+    .bind(email)
+    .fetch_one(pool)
+    .await
+    .context("Failed to check review requirement")?;
+    Ok(requires_review)
+}
+
+/// Whether changes made by agents on behalf of users must be reviewed.
+async fn requires_agent_review(pool: &PgPool, project_id: &ProjectId) -> Result<bool> {
+    let (requires_review,): (bool,) =
+        sqlx::query_as("SELECT require_agent_review FROM projects WHERE project_id = $1")
+            .bind(project_id)
+            .fetch_one(pool)
+            .await
+            .context("Failed to check agent review requirement")?;
+    Ok(requires_review)
+}
+
+/// The outcome of [transact_or_propose].
+pub(crate) struct Transacted<R> {
+    pub(crate) result: R,
+    /// True if the changes were held for review rather than applied.
+    pub(crate) proposed: bool,
+}
+
+/// Makes changes to the project's doc on behalf of the origin's actor.
+///
+/// Changes that must be reviewed, because the user requires review, their
+/// changes are held after unusually destructive ones, or because they're made
+/// by an agent in a project requiring agent review, are made to a scratch copy
+/// of the doc instead and added to a proposal.
+pub(crate) async fn transact_or_propose<R>(
+    pool: &'static PgPool,
+    collab: &Collab,
+    project_id: &ProjectId,
+    origin: &YOrigin,
+    f: impl FnOnce(&YDocProxy, &mut TransactionMut) -> ApiResult<R>,
+) -> ApiResult<Transacted<R>> {
+    let (author, agent) = match &origin.actor {
+        Actor::User(user) => (Some(user), None),
+        Actor::Agent(user) => (Some(user), Some(origin.who.as_str())),
+        _ => (None, None),
+    };
+    let proposer = match author {
+        Some(author) => {
+            let review = collab.is_held(project_id, &author.email)
+                || requires_review(pool, project_id, &author.email).await?
+                || (agent.is_some() && requires_agent_review(pool, project_id).await?);
+            review.then_some(author)
+        }
+        None => None,
+    };
+
+    let client = collab.register_local_client(project_id).await?;
+    let Some(author) = proposer else {
+        let doc_box = client.project.doc_box.lock().await;
+        let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+        let mut txn = doc.transact_mut_with(origin.as_origin()?);
+        return Ok(Transacted {
+            result: f(doc, &mut txn)?,
+            proposed: false,
+        });
+    };
+
+    let scratch = {
+        let doc_box = client.project.doc_box.lock().await;
+        scratch_copy(&DocBox::doc_or_error(doc_box.as_ref())?.ydoc, origin)?
+    };
+    let (result, update) = {
+        let mut txn = scratch.transact_mut_with(origin.as_origin()?);
+        let result = f(&scratch, &mut txn)?;
+        (result, txn.encode_update_v2())
+    };
+    let inbox = Inbox::new(pool, collab.messenger());
+    propose_update(pool, &inbox, project_id, author, agent, &update).await?;
+    Ok(Transacted {
+        result,
+        proposed: true,
+    })
+}
+
+/// Makes changes to a scratch copy of the project's doc and discards them,
+/// e.g. to preview the outcome of an import.
+pub(crate) async fn transact_dry_run<R>(
+    collab: &Collab,
+    project_id: &ProjectId,
+    origin: &YOrigin,
+    f: impl FnOnce(&YDocProxy, &mut TransactionMut) -> ApiResult<R>,
+) -> ApiResult<R> {
+    let client = collab.register_local_client(project_id).await?;
+    let scratch = {
+        let doc_box = client.project.doc_box.lock().await;
+        scratch_copy(&DocBox::doc_or_error(doc_box.as_ref())?.ydoc, origin)?
+    };
+    let mut txn = scratch.transact_mut_with(origin.as_origin()?);
+    f(&scratch, &mut txn)
+}
+
+fn scratch_copy(doc: &YDocProxy, origin: &YOrigin) -> Result<YDocProxy> {
+    let state = doc
+        .transact()
+        .encode_state_as_update_v2(&StateVector::default());
+    let scratch = YDocProxy::new();
+    scratch
+        .transact_mut_with(origin.as_origin()?)
+        .apply_update(Update::decode_v2(&state)?)?;
+    Ok(scratch)
+}
+
+/// Adds the update to the author's pending proposal, creating one and notifying
+/// the project's admins if necessary. Changes made by agents are kept in
+/// separate proposals, one per agent.
+pub(crate) async fn propose_update(
+    pool: &'static PgPool,
+    inbox: &Inbox,
+    project_id: &ProjectId,
+    author: &User,
+    agent: Option<&str>,
+    update: &[u8],
+) -> Result<()> {
+    let mut txn = pool.begin().await?;
+    let (proposal_id, created): (String, bool) = sqlx::query_as(
+        "
+        INSERT INTO change_proposals (id, project_id, author, agent, status)
+        VALUES ($1, $2, $3, $4, 'pending')
+        ON CONFLICT (project_id, author, COALESCE(agent, '')) WHERE status = 'pending'
+        DO UPDATE SET update_time = NOW()
+        RETURNING id, (xmax = 0) AS created",
+    )
+    .bind(BASE64_URL_SAFE_NO_PAD.encode(uuid::Uuid::new_v4()))
+    .bind(project_id)
+    .bind(&author.email)
+    .bind(agent)
+    .fetch_one(&mut *txn)
+    .await
+    .context("Failed to upsert proposal")?;
+    sqlx::query(
+        "INSERT INTO proposal_updates (proposal_id, seq, update_v2) VALUES ($1, DEFAULT, $2)",
+    )
+    .bind(&proposal_id)
+    .bind(update)
+    .execute(&mut *txn)
+    .await
+    .context("Failed to insert proposal update")?;
+    txn.commit().await?;
+
+    if created {
+        let summary = match agent {
+            Some(agent) => format!(
+                "{agent} proposed changes for review on behalf of {}",
+                author.name
+            ),
+            None => format!("{} proposed changes for review", author.name),
+        };
+        for admin in list_admins(pool, project_id).await? {
+            inbox
+                .deliver(
+                    &admin,
+                    InboxKind::ChangeProposal,
+                    Some(project_id),
+                    None,
+                    Some(&author.email),
+                    &summary,
+                )
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) async fn list_admins(pool: &PgPool, project_id: &ProjectId) -> Result<Vec<String>> {
+    let admins: Vec<(String,)> =
+        sqlx::query_as("SELECT email FROM project_permissions WHERE project_id = $1 AND admin")
+            .bind(project_id)
+            .fetch_all(pool)
+            .await
+            .context("Failed to list project admins")?;
+    Ok(admins.into_iter().map(|(admin,)| admin).collect())
+}
+
+/// Whether the project has members but none of them can review proposals.
+pub(crate) async fn lacks_admin(conn: &mut PgConnection, project_id: &ProjectId) -> Result<bool> {
+    let (lacks_admin,): (bool,) = sqlx::query_as(
+        "
+        SELECT COUNT(*) > 0 AND NOT bool_or(admin)
+        FROM project_permissions
+        WHERE project_id = $1",
+    )
+    .bind(project_id)
+    .fetch_one(conn)
+    .await
+    .context("Failed to check project admins")?;
+    Ok(lacks_admin)
+}
+
+fn diff_graphs(mut before: Graph, mut after: Graph) -> Vec<ProposedTaskChange> {
+    let ids: BTreeSet<String> = before.keys().chain(after.keys()).cloned().collect();
+    ids.into_iter()
+        .filter_map(|task_id| {
+            let before = before.remove(&task_id);
+            let after = after.remove(&task_id);
+            if before == after {
+                return None;
+            }
+            Some(ProposedTaskChange {
+                task_id,
+                before,
+                after,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::model::{
+        Task,
+        test_utils::{self, graph},
+    };
+
+    #[test]
+    fn diff_graphs_reports_changed_tasks() {
+        let before = graph([
+            Task {
+                name: "a".to_string(),
+                ..test_utils::task("1", "1", &[])
+            },
+            Task {
+                name: "b".to_string(),
+                ..test_utils::task("2", "2", &[])
+            },
+            Task {
+                name: "c".to_string(),
+                ..test_utils::task("3", "3", &[])
+            },
+        ]);
+        let after = graph([
+            Task {
+                name: "a".to_string(),
+                ..test_utils::task("1", "1", &[])
+            },
+            Task {
+                name: "B".to_string(),
+                ..test_utils::task("2", "2", &[])
+            },
+            Task {
+                name: "d".to_string(),
+                ..test_utils::task("4", "4", &[])
+            },
+        ]);
+        let changes = diff_graphs(before, after);
+        assert_eq!(
+            changes
+                .iter()
+                .map(|c| (
+                    c.task_id.as_str(),
+                    c.before.as_ref().map(|t| t.name.as_str()),
+                    c.after.as_ref().map(|t| t.name.as_str())
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("2", Some("b"), Some("B")),
+                ("3", Some("c"), None),
+                ("4", None, Some("d"))
+            ]
+        );
+    }
+}
@@ -0,0 +1,344 @@
+//! Data residency: organizations can pin their projects to a data region,
+//! e.g. "eu". A pinned project's doc is stored in its region's database, see
+//! `postgres::doc_pool`, while accounts, permissions and update metadata stay
+//! in the deployment's own. Projects are pinned to their organization's region
+//! when created and moved along with it by `koso admin migrate-org`.
+
+use crate::{
+    api::{
+        blueprints,
+        collab::{storage, tiering},
+        model::ProjectId,
+    },
+    postgres::{self, PgPool},
+    settings::settings,
+};
+use anyhow::{Context as _, Result, bail};
+use std::time::Duration;
+
+/// How long a project stays frozen before its doc is moved, long enough for
+/// every server to pick up the freeze and store the updates it already accepted.
+pub(crate) const SETTLE_TIME: Duration = Duration::from_secs(15);
+
+/// Who freezes projects while they move, as shown in the freeze list.
+const MIGRATOR: &str = "migrate-org";
+
+/// Where a project created by the user goes: the organization they belong to,
+/// if any, and its region, or the deployment's if it isn't pinned.
+pub(crate) async fn home(pool: &PgPool, email: &str) -> Result<(Option<String>, Option<String>)> {
+    let Some(org) = blueprints::org_of(pool, email).await? else {
+        return Ok((None, settings().region.clone()));
+    };
+    let region: Option<(String,)> = sqlx::query_as("SELECT region FROM org_regions WHERE org = $1")
+        .bind(&org)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to look up organization region")?;
+    let region = region
+        .map(|(region,)| region)
+        .or_else(|| settings().region.clone());
+    Ok((Some(org), region))
+}
+
+/// Pins the organization to the region and moves its projects' docs there,
+/// one project at a time. Returns how many projects were moved.
+pub(crate) async fn migrate_org(
+    pool: &PgPool,
+    org: &str,
+    region: &str,
+    settle: Duration,
+) -> Result<usize> {
+    if !postgres::serves_region(region) {
+        bail!("Region {region} has no database configured");
+    }
+    // New projects go straight to the new region.
+    sqlx::query(
+        "
+        INSERT INTO org_regions (org, region)
+        VALUES ($1, $2)
+        ON CONFLICT (org) DO UPDATE SET region = EXCLUDED.region",
+    )
+    .bind(org)
+    .bind(region)
+    .execute(pool)
+    .await
+    .context("Failed to pin organization")?;
+
+    let projects: Vec<(ProjectId,)> = sqlx::query_as(
+        "
+        SELECT project_id
+        FROM projects
+        WHERE org = $1
+        AND region IS DISTINCT FROM $2
+        ORDER BY project_id",
+    )
+    .bind(org)
+    .bind(region)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list organization projects")?;
+    let mut moved = 0;
+    for (project_id,) in &projects {
+        if migrate_project(pool, project_id, region, settle).await? {
+            moved += 1;
+        }
+    }
+    tracing::info!(
+        "Pinned {org} to region {region}, moving {moved} of {} projects",
+        projects.len()
+    );
+    Ok(moved)
+}
+
+/// Pins the project to the region, moving its doc if it's stored in another
+/// database. The project is frozen while its doc moves. Returns whether it moved.
+async fn migrate_project(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    region: &str,
+    settle: Duration,
+) -> Result<bool> {
+    let from = postgres::doc_pool(pool, project_id).await?;
+    let to = postgres::region_pool(pool, Some(region))?;
+    if std::ptr::eq(from, to) {
+        set_region(pool, project_id, region).await?;
+        return Ok(false);
+    }
+
+    let frozen = sqlx::query(
+        "
+        INSERT INTO maintenance_freezes (project_id, reason, created_by)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING",
+    )
+    .bind(project_id)
+    .bind(format!("Moving to region {region}"))
+    .bind(MIGRATOR)
+    .execute(pool)
+    .await
+    .context("Failed to freeze project")?;
+    if frozen.rows_affected() == 0 {
+        bail!("Project {project_id} is frozen, unfreeze it before moving it");
+    }
+
+    let moved = move_doc(pool, project_id, from, to, region, settle).await;
+    sqlx::query("DELETE FROM maintenance_freezes WHERE project_id = $1 AND created_by = $2")
+        .bind(project_id)
+        .bind(MIGRATOR)
+        .execute(pool)
+        .await
+        .context("Failed to unfreeze project")?;
+    moved?;
+    tracing::info!("Moved project {project_id} to region {region}");
+    Ok(true)
+}
+
+async fn move_doc(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    from: &PgPool,
+    to: &PgPool,
+    region: &str,
+    settle: Duration,
+) -> Result<()> {
+    tokio::time::sleep(settle).await;
+    // Cold snapshots are kept in the deployment's database, so bring the doc
+    // back first.
+    tiering::rehydrate(project_id, pool).await?;
+    storage::copy_updates(project_id, from, to).await?;
+    // From here on, the doc is read and written in the new region.
+    set_region(pool, project_id, region).await?;
+    if let Err(e) = storage::delete_updates(project_id, from).await {
+        tracing::warn!("Failed to delete moved doc of {project_id}: {e:?}");
+    }
+    Ok(())
+}
+
+async fn set_region(pool: &PgPool, project_id: &ProjectId, region: &str) -> Result<()> {
+    sqlx::query("UPDATE projects SET region = $2 WHERE project_id = $1")
+        .bind(project_id)
+        .bind(region)
+        .execute(pool)
+        .await
+        .context("Failed to set project region")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{
+        collab::txn_origin::{Actor, YOrigin},
+        errors::{ErrorCode, find_coded},
+        model::test_utils::task,
+        yproxy::YDocProxy,
+    };
+    use sqlx::{Executor as _, postgres::PgConnectOptions};
+    use uuid::Uuid;
+    use yrs::{ReadTxn as _, StateVector, Update, updates::encoder::Encode as _};
+
+    /// Creates and migrates a database beside the test's, standing in for
+    /// another region's.
+    async fn regional_database(pool: &sqlx::PgPool) -> (sqlx::PgPool, String) {
+        // Test database names are as long as Postgres allows, so this one
+        // can't be named after the test's.
+        let database = format!("regional_{}", Uuid::new_v4().simple());
+        pool.execute(format!(r#"CREATE DATABASE "{database}""#).as_str())
+            .await
+            .unwrap();
+        let options: PgConnectOptions = (*pool.connect_options()).clone().database(&database);
+        let regional = sqlx::PgPool::connect_with(options).await.unwrap();
+        sqlx::migrate!().run(&regional).await.unwrap();
+        (regional, database)
+    }
+
+    async fn drop_database(pool: &sqlx::PgPool, regional: sqlx::PgPool, database: &str) {
+        regional.close().await;
+        pool.execute(format!(r#"DROP DATABASE "{database}" WITH (FORCE)"#).as_str())
+            .await
+            .unwrap();
+    }
+
+    async fn insert_project(pool: &PgPool, project_id: &str, org: &str) {
+        sqlx::query("INSERT INTO projects (project_id, name, org) VALUES ($1, $1, $2)")
+            .bind(project_id)
+            .bind(org)
+            .execute(pool)
+            .await
+            .unwrap();
+        let doc = YDocProxy::new();
+        let update = {
+            let mut txn = doc.transact_mut_with(
+                YOrigin {
+                    who: "test".to_string(),
+                    id: "test".to_string(),
+                    actor: Actor::Server,
+                    ..Default::default()
+                }
+                .as_origin()
+                .unwrap(),
+            );
+            doc.set(&mut txn, &task("root", "0", &["a"]));
+            doc.set(&mut txn, &task("a", "1", &[]));
+            txn.encode_state_as_update_v2(&StateVector::default())
+        };
+        sqlx::query("INSERT INTO yupdates (project_id, seq, update_v2) VALUES ($1, DEFAULT, $2)")
+            .bind(project_id)
+            .bind(update)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    async fn count_updates(pool: &PgPool, project_id: &str) -> i64 {
+        let (count,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM yupdates WHERE project_id = $1")
+                .bind(project_id)
+                .fetch_one(pool)
+                .await
+                .unwrap();
+        count
+    }
+
+    #[test_log::test(sqlx::test)]
+    async fn migrate_org_moves_docs_to_region(raw_pool: sqlx::PgPool) {
+        let (raw_regional, database) = regional_database(&raw_pool).await;
+        let pool = PgPool::from(raw_pool.clone());
+        let regional: &'static PgPool = Box::leak(Box::new(PgPool::from(raw_regional.clone())));
+        postgres::register_region("test-moves", regional);
+
+        insert_project(&pool, "moving", "owner@example.com").await;
+        insert_project(&pool, "staying", "other@example.com").await;
+
+        assert_eq!(
+            migrate_org(&pool, "owner@example.com", "test-moves", Duration::ZERO)
+                .await
+                .unwrap(),
+            1
+        );
+
+        assert_eq!(count_updates(&pool, "moving").await, 0);
+        assert_eq!(count_updates(regional, "moving").await, 1);
+        assert_eq!(count_updates(&pool, "staying").await, 1);
+        assert!(std::ptr::eq(
+            postgres::doc_pool(&pool, "moving").await.unwrap(),
+            regional
+        ));
+        let doc = storage::load_doc_offline(&"moving".to_string(), &pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            doc.to_graph(&doc.transact()).unwrap().get("a"),
+            Some(&task("a", "1", &[]))
+        );
+        // The freeze is lifted, and new projects of the organization are pinned.
+        let (freezes,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM maintenance_freezes")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(freezes, 0);
+        let (region,): (String,) =
+            sqlx::query_as("SELECT region FROM org_regions WHERE org = 'owner@example.com'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(region, "test-moves");
+
+        // Moving again finds nothing left to move.
+        assert_eq!(
+            migrate_org(&pool, "owner@example.com", "test-moves", Duration::ZERO)
+                .await
+                .unwrap(),
+            0
+        );
+
+        drop_database(&raw_pool, raw_regional, &database).await;
+    }
+
+    #[test_log::test(sqlx::test)]
+    async fn copied_updates_are_numbered_after_the_copy(raw_pool: sqlx::PgPool) {
+        let (raw_regional, database) = regional_database(&raw_pool).await;
+        let pool = PgPool::from(raw_pool.clone());
+        let regional = PgPool::from(raw_regional.clone());
+        let project_id = "numbered".to_string();
+        for _ in 0..3 {
+            insert_project_update(&pool, &project_id).await;
+        }
+
+        storage::copy_updates(&project_id, &pool, &regional)
+            .await
+            .unwrap();
+
+        let seq = insert_project_update(&regional, &project_id).await;
+        assert!(seq > 3, "seq {seq} reuses a copied one");
+        drop_database(&raw_pool, raw_regional, &database).await;
+    }
+
+    async fn insert_project_update(pool: &PgPool, project_id: &str) -> i32 {
+        let (seq,): (i32,) = sqlx::query_as(
+            "INSERT INTO yupdates (project_id, seq, update_v2) VALUES ($1, DEFAULT, $2) RETURNING seq",
+        )
+        .bind(project_id)
+        .bind(Update::default().encode_v2())
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        seq
+    }
+
+    #[test_log::test(sqlx::test)]
+    async fn unknown_regions_are_not_served(pool: sqlx::PgPool) {
+        let pool = PgPool::from(pool);
+        assert!(std::ptr::eq(
+            postgres::region_pool(&pool, None).unwrap(),
+            &pool
+        ));
+        let err = postgres::region_pool(&pool, Some("nowhere")).unwrap_err();
+        assert_eq!(find_coded(&err).unwrap().code, ErrorCode::WrongRegion);
+        assert!(
+            migrate_org(&pool, "owner@example.com", "nowhere", Duration::ZERO)
+                .await
+                .is_err()
+        );
+    }
+}
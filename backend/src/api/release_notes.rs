@@ -0,0 +1,307 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        deployments::{self, ShippedGroup, ShippedQuery},
+        google::User,
+        model::ProjectId,
+        reports::escape_html,
+        verify_project_access,
+        yproxy::task_key,
+    },
+    notifiers::Notifiers,
+    postgres::{PgPool, list_project_users},
+};
+use anyhow::Context as _;
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    routing::{get, post},
+};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route(
+            "/{project_id}/release-notes",
+            get(get_release_notes_handler),
+        )
+        .route(
+            "/{project_id}/release-notes/send",
+            post(send_release_notes_handler),
+        )
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ReleaseNotes {
+    title: String,
+    groups: Vec<ShippedGroup>,
+    /// Names of the assignees of the shipped tasks.
+    contributors: Vec<String>,
+    markdown: String,
+}
+
+/// Everything needed to render release notes.
+struct Notes<'a> {
+    project_id: &'a str,
+    title: String,
+    task_key_prefix: Option<String>,
+    groups: Vec<ShippedGroup>,
+    /// Display names by email.
+    names: HashMap<String, String>,
+}
+
+impl Notes<'_> {
+    fn credit(&self, assignee: &str) -> String {
+        self.names
+            .get(assignee)
+            .cloned()
+            .unwrap_or_else(|| assignee.to_string())
+    }
+
+    fn contributors(&self) -> Vec<String> {
+        self.groups
+            .iter()
+            .flat_map(|g| &g.tasks)
+            .filter_map(|t| t.assignee.as_deref())
+            .map(|a| self.credit(a))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    fn task_url(&self, task_id: &str) -> String {
+        format!(
+            "https://koso.app/projects/{}?taskId={}",
+            self.project_id, task_id
+        )
+    }
+}
+
+/// Generates release notes for the tasks shipped in a tag, or in a date range,
+/// grouped by label.
+#[tracing::instrument(skip(user, pool))]
+async fn get_release_notes_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Query(query): Query<ShippedQuery>,
+) -> ApiResult<Json<ReleaseNotes>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let notes = build_notes(pool, &project_id, &query).await?;
+    Ok(Json(ReleaseNotes {
+        title: notes.title.clone(),
+        contributors: notes.contributors(),
+        markdown: render_markdown(&notes),
+        groups: notes.groups,
+    }))
+}
+
+/// Sends the release notes through the user's configured notifiers, e.g. to
+/// a team's Matrix room.
+#[tracing::instrument(skip(user, pool))]
+async fn send_release_notes_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Json(query): Json<ShippedQuery>,
+) -> ApiResult<Json<ReleaseNotes>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let notes = build_notes(pool, &project_id, &query).await?;
+    if notes.groups.is_empty() {
+        return Err(bad_request_error(
+            "NOTHING_SHIPPED",
+            "No tasks were shipped in the given range",
+        ));
+    }
+    let markdown = render_markdown(&notes);
+    Notifiers::new(pool)?
+        .notify_with_text(&user.email, &render_html(&notes), &markdown)
+        .await?;
+    Ok(Json(ReleaseNotes {
+        title: notes.title.clone(),
+        contributors: notes.contributors(),
+        markdown,
+        groups: notes.groups,
+    }))
+}
+
+async fn build_notes<'a>(
+    pool: &PgPool,
+    project_id: &'a ProjectId,
+    query: &ShippedQuery,
+) -> ApiResult<Notes<'a>> {
+    if query.version.is_none() && query.since.is_none() && query.until.is_none() {
+        return Err(bad_request_error(
+            "MISSING_RANGE",
+            "A version, or a since or until time, is required",
+        ));
+    }
+    let (project_name, task_key_prefix): (String, Option<String>) =
+        sqlx::query_as("SELECT name, task_key_prefix FROM projects WHERE project_id = $1")
+            .bind(project_id)
+            .fetch_one(pool)
+            .await
+            .context("Failed to fetch project")?;
+    let names = list_project_users(pool, project_id)
+        .await?
+        .into_iter()
+        .map(|u| (u.email, u.name))
+        .collect();
+    let tasks = deployments::fetch_shipped(pool, project_id, query).await?;
+    Ok(Notes {
+        project_id,
+        title: title(&project_name, query),
+        task_key_prefix,
+        groups: deployments::group_by_label(tasks),
+        names,
+    })
+}
+
+fn title(project_name: &str, query: &ShippedQuery) -> String {
+    let mut title = format!("{project_name} release notes");
+    if let Some(version) = &query.version {
+        title.push_str(&format!(": {version}"));
+    }
+    match (query.since, query.until) {
+        (Some(since), Some(until)) => title.push_str(&format!(
+            " ({} to {})",
+            since.format("%Y-%m-%d"),
+            until.format("%Y-%m-%d")
+        )),
+        (Some(since), None) => title.push_str(&format!(" (since {})", since.format("%Y-%m-%d"))),
+        (None, Some(until)) => title.push_str(&format!(" (until {})", until.format("%Y-%m-%d"))),
+        (None, None) => {}
+    }
+    if let Some(env) = &query.env {
+        title.push_str(&format!(" [{env}]"));
+    }
+    title
+}
+
+fn render_markdown(notes: &Notes) -> String {
+    let mut out = format!("# {}\n", notes.title);
+    if notes.groups.is_empty() {
+        out.push_str("\nNothing shipped.\n");
+    }
+    for group in &notes.groups {
+        out.push_str(&format!("\n## {}\n\n", group.label));
+        for task in &group.tasks {
+            out.push_str(&format!(
+                "- [{}]({}) {}",
+                task_key(notes.task_key_prefix.as_deref(), &task.num),
+                notes.task_url(&task.task_id),
+                task.name
+            ));
+            if let Some(assignee) = &task.assignee {
+                out.push_str(&format!(" ({})", notes.credit(assignee)));
+            }
+            out.push('\n');
+        }
+    }
+    let contributors = notes.contributors();
+    if !contributors.is_empty() {
+        out.push_str(&format!(
+            "\n## Contributors\n\nThanks to {}.\n",
+            contributors.join(", ")
+        ));
+    }
+    out
+}
+
+/// Renders the notes using only the tags supported by Telegram, so they
+/// can be sent through notifiers as is.
+fn render_html(notes: &Notes) -> String {
+    let mut out = format!("<b>{}</b>\n", escape_html(&notes.title));
+    for group in &notes.groups {
+        out.push_str(&format!("\n<b>{}</b>\n", escape_html(&group.label)));
+        for task in &group.tasks {
+            out.push_str(&format!(
+                "• <a href=\"{}\">{} {}</a>",
+                notes.task_url(&task.task_id),
+                task_key(notes.task_key_prefix.as_deref(), &task.num),
+                escape_html(&task.name)
+            ));
+            if let Some(assignee) = &task.assignee {
+                out.push_str(&format!(" ({})", escape_html(&notes.credit(assignee))));
+            }
+            out.push('\n');
+        }
+    }
+    let contributors = notes.contributors();
+    if !contributors.is_empty() {
+        out.push_str(&format!(
+            "\nThanks to {}.\n",
+            escape_html(&contributors.join(", "))
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::deployments::ShippedTask;
+
+    fn notes() -> Notes<'static> {
+        let task = |num: &str, name: &str, assignee: Option<&str>| ShippedTask {
+            task_id: format!("id{num}"),
+            num: num.to_string(),
+            name: name.to_string(),
+            labels: vec![],
+            assignee: assignee.map(String::from),
+        };
+        Notes {
+            project_id: "p",
+            title: "Koso release notes: v1.2".to_string(),
+            task_key_prefix: Some("KOSO".to_string()),
+            groups: vec![
+                ShippedGroup {
+                    label: "feature".to_string(),
+                    tasks: vec![task("1", "Dark mode", Some("a@koso.app"))],
+                },
+                ShippedGroup {
+                    label: "fix".to_string(),
+                    tasks: vec![
+                        task("2", "Fix <login>", Some("b@example.com")),
+                        task("3", "Fix crash", None),
+                    ],
+                },
+            ],
+            names: HashMap::from([("a@koso.app".to_string(), "Alice".to_string())]),
+        }
+    }
+
+    #[test]
+    fn render_markdown_groups_links_and_credits() {
+        assert_eq!(
+            render_markdown(&notes()),
+            "# Koso release notes: v1.2
+
+## feature
+
+- [KOSO-1](https://koso.app/projects/p?taskId=id1) Dark mode (Alice)
+
+## fix
+
+- [KOSO-2](https://koso.app/projects/p?taskId=id2) Fix <login> (b@example.com)
+- [KOSO-3](https://koso.app/projects/p?taskId=id3) Fix crash
+
+## Contributors
+
+Thanks to Alice, b@example.com.
+"
+        );
+    }
+
+    #[test]
+    fn render_html_escapes() {
+        let html = render_html(&notes());
+        assert!(html.starts_with("<b>Koso release notes: v1.2</b>\n"));
+        assert!(html.contains(
+            "• <a href=\"https://koso.app/projects/p?taskId=id2\">KOSO-2 Fix &lt;login&gt;</a> (b@example.com)\n"
+        ));
+        assert!(html.ends_with("\nThanks to Alice, b@example.com.\n"));
+    }
+}
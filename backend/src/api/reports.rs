@@ -0,0 +1,706 @@
+use crate::{
+    api::{
+        ApiResult, analytics, bad_request_error,
+        collab::{Collab, projects_state::DocBox},
+        goals,
+        google::User,
+        model::{
+            FlowMetrics, Graph, Percentiles, ProjectId, ReportGoal, ReportRisk, ReportSubscription,
+            ReportTask, StatusReport, Task, WorkflowCategory, WorkflowState, parse_time_zone,
+        },
+        risks, verify_project_access,
+        yproxy::{status_category, task_key},
+    },
+    i18n::Locale,
+    notifiers::Notifiers,
+    postgres::{PgPool, ReadPool},
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse as _, Response},
+    routing::{get, put},
+};
+use chrono::{DateTime, Datelike as _, Duration, Utc};
+use serde::Deserialize;
+use std::collections::HashSet;
+use tokio::task::JoinHandle;
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/{project_id}/report", get(get_report_handler))
+        .route(
+            "/{project_id}/report/subscription",
+            put(subscribe_handler).delete(unsubscribe_handler),
+        )
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+enum ReportFormat {
+    #[default]
+    Json,
+    Markdown,
+    Html,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ReportQuery {
+    days: Option<i64>,
+    #[serde(default)]
+    format: ReportFormat,
+}
+
+const DEFAULT_DAYS: i64 = 7;
+const MAX_DAYS: i64 = 90;
+/// How often to check for reports that are due.
+const SCHEDULE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Reports on the project's tasks over the last `days` days.
+#[tracing::instrument(skip(user, pool, read_pool, collab))]
+async fn get_report_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(read_pool): Extension<ReadPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+    Query(query): Query<ReportQuery>,
+) -> ApiResult<Response> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let days = query.days.unwrap_or(DEFAULT_DAYS);
+    if !(1..=MAX_DAYS).contains(&days) {
+        return Err(bad_request_error(
+            "INVALID_DAYS",
+            &format!("Days must be between 1 and {MAX_DAYS}"),
+        ));
+    }
+
+    let end = Utc::now();
+    let report = compile_report(
+        read_pool.get(),
+        &collab,
+        &project_id,
+        end - Duration::days(days),
+        end,
+    )
+    .await?;
+    let locale = Locale::of_user(pool, &user.email).await?;
+    Ok(match query.format {
+        ReportFormat::Json => Json(report).into_response(),
+        ReportFormat::Markdown => (
+            [(CONTENT_TYPE, "text/markdown; charset=utf-8")],
+            render_markdown(&report, &locale),
+        )
+            .into_response(),
+        ReportFormat::Html => (
+            [(CONTENT_TYPE, "text/html; charset=utf-8")],
+            render_html(&report, &locale),
+        )
+            .into_response(),
+    })
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn subscribe_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Json(subscription): Json<ReportSubscription>,
+) -> ApiResult<Json<ReportSubscription>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    if !(0..=6).contains(&subscription.weekday) {
+        return Err(bad_request_error(
+            "INVALID_WEEKDAY",
+            "Weekday must be between 0 (Monday) and 6 (Sunday)",
+        ));
+    }
+    sqlx::query(
+        "
+        INSERT INTO report_subscriptions (project_id, email, weekday)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (project_id, email)
+        DO UPDATE SET weekday = EXCLUDED.weekday",
+    )
+    .bind(&project_id)
+    .bind(&user.email)
+    .bind(subscription.weekday)
+    .execute(pool)
+    .await
+    .context("Failed to upsert report subscription")?;
+    Ok(Json(subscription))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn unsubscribe_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<()>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    sqlx::query("DELETE FROM report_subscriptions WHERE project_id = $1 AND email = $2")
+        .bind(&project_id)
+        .bind(&user.email)
+        .execute(pool)
+        .await
+        .context("Failed to delete report subscription")?;
+    Ok(Json(()))
+}
+
+/// Compiles a report of the project's tasks between `start` and `end`.
+pub(crate) async fn compile_report(
+    pool: &PgPool,
+    collab: &Collab,
+    project_id: &ProjectId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<StatusReport> {
+    let (project_name, task_key_prefix): (String, Option<String>) =
+        sqlx::query_as("SELECT name, task_key_prefix FROM projects WHERE project_id = $1")
+            .bind(project_id)
+            .fetch_one(pool)
+            .await
+            .context("Failed to fetch project name")?;
+
+    // Tasks created in the window, according to the stored update history.
+    let created: Vec<(String,)> = sqlx::query_as(
+        "
+        SELECT DISTINCT change->>'taskId'
+        FROM yupdate_metadata, jsonb_array_elements(changes) AS change
+        WHERE project_id = $1
+        AND create_time >= $2
+        AND create_time < $3
+        AND change->>'kind' = 'created'",
+    )
+    .bind(project_id)
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list created tasks")?;
+    let created: HashSet<String> = created.into_iter().map(|(id,)| id).collect();
+
+    let (graph, workflow_states) = {
+        let client = collab.register_local_client(project_id).await?;
+        let doc_box = client.project.doc_box.lock().await;
+        let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+        let txn = doc.transact();
+        (doc.to_graph(&txn)?, doc.config().get_workflow_states(&txn)?)
+    };
+
+    let mut report = build_report(
+        project_id,
+        project_name,
+        &graph,
+        &workflow_states,
+        &created,
+        start,
+        end,
+    );
+    report.task_key_prefix = task_key_prefix;
+    report.flow = analytics::completed_flow_metrics(pool, project_id, start, end).await?;
+    report.goals = goals::list_goals_with_progress(pool, project_id, &graph, &workflow_states)
+        .await?
+        .into_iter()
+        .map(|goal| ReportGoal {
+            goal_id: goal.id,
+            title: goal.title,
+            due_date: goal.due_date,
+            progress: goal.progress,
+        })
+        .collect();
+    report.risks = risks::list_risks(pool, project_id)
+        .await?
+        .into_iter()
+        .filter(|risk| !risk.closed)
+        .map(|risk| ReportRisk {
+            score: risk.score(),
+            risk_id: risk.id,
+            title: risk.title,
+            owner: risk.owner,
+        })
+        .collect();
+    Ok(report)
+}
+
+fn build_report(
+    project_id: &ProjectId,
+    project_name: String,
+    graph: &Graph,
+    workflow_states: &[WorkflowState],
+    created: &HashSet<String>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> StatusReport {
+    let utc = parse_time_zone(None);
+    let in_window =
+        |millis: i64| (start.timestamp_millis()..end.timestamp_millis()).contains(&millis);
+    let mut report = StatusReport {
+        project_id: project_id.clone(),
+        project_name,
+        task_key_prefix: None,
+        start,
+        end,
+        completed: vec![],
+        started: vec![],
+        slipped: vec![],
+        created: vec![],
+        flow: FlowMetrics::default(),
+        goals: vec![],
+        risks: vec![],
+    };
+
+    let mut tasks: Vec<&Task> = graph.values().collect();
+    tasks.sort_by_key(|t| (t.num.parse::<u64>().unwrap_or(u64::MAX), t.id.as_str()));
+    for task in tasks {
+        let task_category = task
+            .status
+            .as_deref()
+            .and_then(|status| status_category(workflow_states, status));
+        let status_changed = task.status_time.is_some_and(in_window);
+        match task_category {
+            Some(WorkflowCategory::Done) if status_changed => {
+                report.completed.push(report_task(task));
+            }
+            Some(WorkflowCategory::InProgress) if status_changed => {
+                report.started.push(report_task(task));
+            }
+            _ => (),
+        }
+        if task_category != Some(WorkflowCategory::Done)
+            && task
+                .deadline
+                .as_ref()
+                .is_some_and(|d| in_window(d.due_at(&utc).timestamp_millis()))
+        {
+            report.slipped.push(report_task(task));
+        }
+        if created.contains(&task.id) {
+            report.created.push(report_task(task));
+        }
+    }
+    report
+}
+
+fn report_task(task: &Task) -> ReportTask {
+    ReportTask {
+        task_id: task.id.clone(),
+        num: task.num.clone(),
+        name: task.name.clone(),
+        assignee: task.assignee.clone(),
+    }
+}
+
+fn flow_lines(flow: &FlowMetrics, locale: &Locale) -> [(String, String); 2] {
+    [
+        (
+            locale.message("report-cycle-time", &[]),
+            format_percentiles(&flow.cycle_time, locale),
+        ),
+        (
+            locale.message("report-lead-time", &[]),
+            format_percentiles(&flow.lead_time, locale),
+        ),
+    ]
+}
+
+fn format_goal(goal: &ReportGoal, locale: &Locale) -> String {
+    let percent = match goal.progress.total {
+        0 => 0,
+        total => goal.progress.done * 100 / total,
+    };
+    let (done, total) = (goal.progress.done, goal.progress.total);
+    match goal.due_date {
+        Some(due_date) => locale.message(
+            "report-goal-progress-due",
+            &[
+                ("percent", &percent),
+                ("done", &done),
+                ("total", &total),
+                ("date", &due_date.format("%Y-%m-%d")),
+            ],
+        ),
+        None => locale.message(
+            "report-goal-progress",
+            &[("percent", &percent), ("done", &done), ("total", &total)],
+        ),
+    }
+}
+
+fn format_percentiles(percentiles: &Percentiles, locale: &Locale) -> String {
+    match (percentiles.p50, percentiles.p85) {
+        (Some(p50), Some(p85)) => locale.message(
+            "report-percentiles",
+            &[("p50", &format_hours(p50)), ("p85", &format_hours(p85))],
+        ),
+        _ => locale.message("report-not-available", &[]),
+    }
+}
+
+fn format_hours(hours: f64) -> String {
+    if hours < 24.0 {
+        format!("{hours:.1}h")
+    } else {
+        format!("{:.1}d", hours / 24.0)
+    }
+}
+
+fn sections<'a>(report: &'a StatusReport, locale: &Locale) -> [(String, &'a Vec<ReportTask>); 4] {
+    [
+        (locale.message("report-completed", &[]), &report.completed),
+        (locale.message("report-started", &[]), &report.started),
+        (locale.message("report-slipped", &[]), &report.slipped),
+        (locale.message("report-created", &[]), &report.created),
+    ]
+}
+
+fn format_range(report: &StatusReport, locale: &Locale) -> String {
+    locale.message(
+        "report-range",
+        &[
+            ("start", &report.start.format("%Y-%m-%d")),
+            ("end", &report.end.format("%Y-%m-%d")),
+        ],
+    )
+}
+
+pub(crate) fn render_markdown(report: &StatusReport, locale: &Locale) -> String {
+    let mut out = format!(
+        "# {}\n\n{}\n",
+        locale.message("report-title", &[("project", &report.project_name)]),
+        format_range(report, locale)
+    );
+    for (title, tasks) in sections(report, locale) {
+        out.push_str(&format!("\n## {title} ({})\n\n", tasks.len()));
+        if tasks.is_empty() {
+            out.push_str(&locale.message("report-none", &[]));
+            out.push('\n');
+        }
+        for task in tasks {
+            out.push_str(&format!(
+                "- {} {}",
+                task_key(report.task_key_prefix.as_deref(), &task.num),
+                task.name
+            ));
+            if let Some(assignee) = &task.assignee {
+                out.push_str(&format!(" ({assignee})"));
+            }
+            out.push('\n');
+        }
+    }
+    out.push_str(&format!("\n## {}\n\n", locale.message("report-flow", &[])));
+    for (title, value) in flow_lines(&report.flow, locale) {
+        out.push_str(&format!("- {title}: {value}\n"));
+    }
+    if !report.goals.is_empty() {
+        out.push_str(&format!("\n## {}\n\n", locale.message("report-goals", &[])));
+        for goal in &report.goals {
+            out.push_str(&format!(
+                "- {}: {}\n",
+                goal.title,
+                format_goal(goal, locale)
+            ));
+        }
+    }
+    if !report.risks.is_empty() {
+        out.push_str(&format!("\n## {}\n\n", locale.message("report-risks", &[])));
+        for risk in &report.risks {
+            out.push_str("- ");
+            out.push_str(&locale.message(
+                "report-risk",
+                &[("title", &risk.title), ("score", &risk.score)],
+            ));
+            if let Some(owner) = &risk.owner {
+                out.push_str(&format!(" ({owner})"));
+            }
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Renders the report using only the tags supported by Telegram, so it
+/// can be sent through notifiers as is.
+pub(crate) fn render_html(report: &StatusReport, locale: &Locale) -> String {
+    let mut out = format!(
+        "<b>{}</b>\n<i>{}</i>\n",
+        locale.message(
+            "report-title",
+            &[("project", &escape_html(&report.project_name))]
+        ),
+        format_range(report, locale)
+    );
+    for (title, tasks) in sections(report, locale) {
+        out.push_str(&format!("\n<b>{title} ({})</b>\n", tasks.len()));
+        for task in tasks {
+            out.push_str(&format!(
+                "• <a href=\"https://koso.app/projects/{}?taskId={}\">{} {}</a>",
+                report.project_id,
+                task.task_id,
+                task_key(report.task_key_prefix.as_deref(), &task.num),
+                escape_html(&task.name)
+            ));
+            if let Some(assignee) = &task.assignee {
+                out.push_str(&format!(" ({})", escape_html(assignee)));
+            }
+            out.push('\n');
+        }
+    }
+    out.push_str(&format!(
+        "\n<b>{}</b>\n",
+        locale.message("report-flow", &[])
+    ));
+    for (title, value) in flow_lines(&report.flow, locale) {
+        out.push_str(&format!("{title}: {value}\n"));
+    }
+    if !report.goals.is_empty() {
+        out.push_str(&format!(
+            "\n<b>{}</b>\n",
+            locale.message("report-goals", &[])
+        ));
+        for goal in &report.goals {
+            out.push_str(&format!(
+                "• {}: {}\n",
+                escape_html(&goal.title),
+                format_goal(goal, locale)
+            ));
+        }
+    }
+    if !report.risks.is_empty() {
+        out.push_str(&format!(
+            "\n<b>{}</b>\n",
+            locale.message("report-risks", &[])
+        ));
+        for risk in &report.risks {
+            out.push_str("• ");
+            out.push_str(&locale.message(
+                "report-risk",
+                &[("title", &escape_html(&risk.title)), ("score", &risk.score)],
+            ));
+            if let Some(owner) = &risk.owner {
+                out.push_str(&format!(" ({})", escape_html(owner)));
+            }
+            out.push('\n');
+        }
+    }
+    out
+}
+
+pub(crate) fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Periodically sends weekly reports to subscribed users through their notifiers.
+pub(crate) struct ReportScheduler {
+    pool: &'static PgPool,
+    collab: Collab,
+    notifier: Notifiers,
+}
+
+impl ReportScheduler {
+    pub(crate) fn new(pool: &'static PgPool, collab: Collab) -> Result<Self> {
+        Ok(ReportScheduler {
+            pool,
+            collab,
+            notifier: Notifiers::new(pool)?,
+        })
+    }
+
+    pub(crate) fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SCHEDULE_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.send_due_reports().await {
+                    tracing::warn!("Failed to send reports: {e:?}");
+                }
+            }
+        })
+    }
+
+    async fn send_due_reports(&self) -> Result<()> {
+        let now = Utc::now();
+        let due: Vec<(String, String)> = sqlx::query_as(
+            "
+            SELECT s.project_id, s.email
+            FROM report_subscriptions s
+            JOIN project_permissions pp ON pp.project_id = s.project_id AND pp.email = s.email
+            JOIN projects p ON p.project_id = s.project_id
+            WHERE s.weekday = $1
+            AND p.deleted_on IS NULL
+            AND (s.last_sent_time IS NULL OR s.last_sent_time < $2 - INTERVAL '6 days')",
+        )
+        .bind(now.weekday().num_days_from_monday() as i16)
+        .bind(now)
+        .fetch_all(self.pool)
+        .await
+        .context("Failed to list due reports")?;
+
+        for (project_id, email) in due {
+            if let Err(e) = self.send_report(&project_id, &email, now).await {
+                tracing::warn!("Failed to send report for {project_id} to {email}: {e:?}");
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_report(
+        &self,
+        project_id: &ProjectId,
+        email: &str,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        let report = compile_report(
+            self.pool,
+            &self.collab,
+            project_id,
+            now - Duration::days(DEFAULT_DAYS),
+            now,
+        )
+        .await?;
+        let locale = Locale::of_user(self.pool, email).await?;
+        self.notifier
+            .notify_with_text(
+                email,
+                &render_html(&report, &locale),
+                &render_markdown(&report, &locale),
+            )
+            .await?;
+        sqlx::query(
+            "
+            UPDATE report_subscriptions
+            SET last_sent_time = $3
+            WHERE project_id = $1 AND email = $2",
+        )
+        .bind(project_id)
+        .bind(email)
+        .bind(now)
+        .execute(self.pool)
+        .await
+        .context("Failed to record sent report")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::model::{Deadline, TaskProgress, test_utils};
+
+    #[test]
+    fn build_report_categorizes_tasks() {
+        let start = DateTime::from_timestamp_millis(1_000_000).unwrap();
+        let end = start + Duration::days(7);
+        let during = start.timestamp_millis() + 1000;
+        let before = start.timestamp_millis() - 1000;
+        let mut graph = test_utils::graph([
+            Task {
+                name: "Task 1".to_string(),
+                status: Some("Done".to_string()),
+                status_time: Some(during),
+                ..test_utils::task("1", "1", &[])
+            },
+            Task {
+                name: "Task 2".to_string(),
+                status: Some("Done".to_string()),
+                status_time: Some(before),
+                ..test_utils::task("2", "2", &[])
+            },
+            Task {
+                name: "Task 3".to_string(),
+                status: Some("In Progress".to_string()),
+                status_time: Some(during),
+                ..test_utils::task("3", "3", &[])
+            },
+            Task {
+                name: "Task 4".to_string(),
+                ..test_utils::task("4", "4", &[])
+            },
+            Task {
+                name: "Task 5".to_string(),
+                status: Some("Done".to_string()),
+                status_time: Some(during),
+                ..test_utils::task("5", "5", &[])
+            },
+        ]);
+        let deadline = Deadline::DateTime {
+            millis: during,
+            time_zone: "UTC".into(),
+        };
+        graph.get_mut("4").unwrap().deadline = Some(deadline.clone());
+        graph.get_mut("5").unwrap().deadline = Some(deadline);
+
+        let report = build_report(
+            &"p".to_string(),
+            "Project".into(),
+            &graph,
+            &[],
+            &HashSet::from(["3".to_string()]),
+            start,
+            end,
+        );
+        let ids =
+            |tasks: &[ReportTask]| tasks.iter().map(|t| t.task_id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&report.completed), vec!["1", "5"]);
+        assert_eq!(ids(&report.started), vec!["3"]);
+        assert_eq!(ids(&report.slipped), vec!["4"]);
+        assert_eq!(ids(&report.created), vec!["3"]);
+
+        let markdown = render_markdown(&report, &Locale::default());
+        assert!(markdown.contains("## Completed (2)\n\n- #1 Task 1\n- #5 Task 5\n"));
+        assert!(markdown.contains("## Slipped (1)\n\n- #4 Task 4\n"));
+    }
+
+    #[test]
+    fn render_html_escapes_names() {
+        let report = StatusReport {
+            project_id: "p".into(),
+            project_name: "<Team>".into(),
+            task_key_prefix: None,
+            start: DateTime::from_timestamp_millis(0).unwrap(),
+            end: DateTime::from_timestamp_millis(0).unwrap(),
+            completed: vec![ReportTask {
+                task_id: "1".into(),
+                num: "1".into(),
+                name: "a & b".into(),
+                assignee: None,
+            }],
+            started: vec![],
+            slipped: vec![],
+            created: vec![],
+            flow: FlowMetrics {
+                tasks: 1,
+                cycle_time: Percentiles {
+                    p50: Some(5.0),
+                    p85: Some(36.0),
+                    p95: Some(36.0),
+                },
+                ..FlowMetrics::default()
+            },
+            goals: vec![ReportGoal {
+                goal_id: "g".into(),
+                title: "Ship <v2>".into(),
+                due_date: None,
+                progress: TaskProgress { done: 1, total: 3 },
+            }],
+            risks: vec![ReportRisk {
+                risk_id: "r".into(),
+                title: "Vendor delay".into(),
+                score: 12,
+                owner: Some("a@b.com".into()),
+            }],
+        };
+        let html = render_html(&report, &Locale::default());
+        assert!(html.starts_with("<b>Status report: &lt;Team&gt;</b>"));
+        assert!(html.contains(">#1 a &amp; b</a>"));
+        assert!(html.contains("Cycle time: 5.0h median, 1.5d p85\n"));
+        assert!(html.contains("Lead time: n/a\n"));
+        assert!(html.contains("• Ship &lt;v2&gt;: 33% (1/3 tasks)\n"));
+        assert!(html.contains("• Vendor delay (score 12) (a@b.com)\n"));
+    }
+}
@@ -0,0 +1,450 @@
+//! Retention of history: stored doc updates, activity, task history and
+//! notification logs. Each category is kept for the deployment's default
+//! period unless a project overrides it, and a background pruner deletes, or
+//! for doc updates compacts, whatever has aged out.
+
+use crate::{
+    api::{ApiResult, bad_request_error, google::User, model::ProjectId, verify_admin},
+    postgres,
+    postgres::PgPool,
+    settings::settings,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    routing::{get, put},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    FromRow,
+    types::chrono::{DateTime, Utc},
+};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+pub(super) fn router() -> Router {
+    Router::new().route("/", get(get_report_handler)).route(
+        "/projects/{project_id}",
+        put(set_policy_handler).delete(delete_policy_handler),
+    )
+}
+
+/// How often aged-out history is pruned.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+/// Maximum rows deleted per statement, so pruning a large backlog doesn't hold
+/// locks, or bloat the WAL, in one go.
+const BATCH_SIZE: i64 = 10000;
+const MAX_DAYS: i32 = 3650;
+/// How far back reclaimed space is reported.
+const REPORT_DAYS: i32 = 30;
+
+#[derive(Serialize, Deserialize, FromRow, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Policy {
+    pub(crate) update_days: Option<i32>,
+    pub(crate) activity_days: Option<i32>,
+    pub(crate) history_days: Option<i32>,
+    pub(crate) notification_days: Option<i32>,
+}
+
+impl Policy {
+    fn defaults() -> Self {
+        let retention = &settings().retention;
+        Policy {
+            update_days: Some(retention.update_days),
+            activity_days: Some(retention.activity_days),
+            history_days: Some(retention.history_days),
+            notification_days: Some(retention.notification_days),
+        }
+    }
+
+    fn validate(&self) -> ApiResult<()> {
+        for days in [
+            self.update_days,
+            self.activity_days,
+            self.history_days,
+            self.notification_days,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if !(1..=MAX_DAYS).contains(&days) {
+                return Err(bad_request_error(
+                    "INVALID_RETENTION",
+                    &format!("Retention must be between 1 and {MAX_DAYS} days"),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ProjectPolicy {
+    project_id: String,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    policy: Policy,
+    update_time: DateTime<Utc>,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Reclaimed {
+    category: String,
+    table_name: String,
+    rows_pruned: i64,
+    bytes_reclaimed: i64,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct TableSize {
+    table_name: String,
+    bytes: i64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RetentionReport {
+    defaults: Policy,
+    overrides: Vec<ProjectPolicy>,
+    /// Space reclaimed by pruning over the last REPORT_DAYS days.
+    reclaimed: Vec<Reclaimed>,
+    /// The current size of each pruned table, including indexes.
+    table_sizes: Vec<TableSize>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Category {
+    Activity,
+    History,
+    Notifications,
+}
+
+impl Category {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Category::Activity => "activity",
+            Category::History => "history",
+            Category::Notifications => "notifications",
+        }
+    }
+
+    /// The column of retention_policies overriding this category.
+    fn policy_column(&self) -> &'static str {
+        match self {
+            Category::Activity => "activity_days",
+            Category::History => "history_days",
+            Category::Notifications => "notification_days",
+        }
+    }
+
+    fn default_days(&self) -> i32 {
+        let retention = &settings().retention;
+        match self {
+            Category::Activity => retention.activity_days,
+            Category::History => retention.history_days,
+            Category::Notifications => retention.notification_days,
+        }
+    }
+}
+
+/// A table whose rows are deleted once older than their category's retention.
+struct Target {
+    category: Category,
+    table: &'static str,
+    time_column: &'static str,
+    /// Whether rows belong to a project, and so honor its overrides.
+    per_project: bool,
+}
+
+const TARGETS: &[Target] = &[
+    Target {
+        category: Category::Activity,
+        table: "yupdate_metadata",
+        time_column: "create_time",
+        per_project: true,
+    },
+    Target {
+        category: Category::History,
+        table: "task_status_transitions",
+        time_column: "transition_time",
+        per_project: true,
+    },
+    Target {
+        category: Category::History,
+        table: "auto_archive_runs",
+        time_column: "run_time",
+        per_project: true,
+    },
+    Target {
+        category: Category::Notifications,
+        table: "inbox_notifications",
+        time_column: "create_time",
+        per_project: true,
+    },
+    Target {
+        category: Category::Notifications,
+        table: "notification_deliveries",
+        time_column: "create_time",
+        per_project: false,
+    },
+];
+
+#[tracing::instrument(skip(user, pool))]
+async fn get_report_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<RetentionReport>> {
+    verify_admin(&user)?;
+    let overrides: Vec<ProjectPolicy> = sqlx::query_as(
+        "
+        SELECT project_id, update_days, activity_days, history_days, notification_days, update_time
+        FROM retention_policies
+        ORDER BY project_id",
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list retention policies")?;
+
+    let reclaimed: Vec<Reclaimed> = sqlx::query_as(
+        "
+        SELECT
+          category,
+          table_name,
+          SUM(rows_pruned)::bigint AS rows_pruned,
+          SUM(bytes_reclaimed)::bigint AS bytes_reclaimed
+        FROM retention_runs
+        WHERE run_time > NOW() - make_interval(days => $1)
+        GROUP BY category, table_name
+        ORDER BY category, table_name",
+    )
+    .bind(REPORT_DAYS)
+    .fetch_all(pool)
+    .await
+    .context("Failed to summarize retention runs")?;
+
+    let tables: Vec<&str> = std::iter::once("yupdates")
+        .chain(TARGETS.iter().map(|t| t.table))
+        .collect();
+    let table_sizes: Vec<TableSize> = sqlx::query_as(
+        "
+        SELECT t AS table_name, pg_total_relation_size(t::regclass) AS bytes
+        FROM unnest($1::text[]) AS t",
+    )
+    .bind(&tables)
+    .fetch_all(pool)
+    .await
+    .context("Failed to get table sizes")?;
+
+    Ok(Json(RetentionReport {
+        defaults: Policy::defaults(),
+        overrides,
+        reclaimed,
+        table_sizes,
+    }))
+}
+
+/// Overrides the deployment's retention for a project. Unset periods use the default.
+#[tracing::instrument(skip(user, pool))]
+async fn set_policy_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Json(policy): Json<Policy>,
+) -> ApiResult<Json<ProjectPolicy>> {
+    verify_admin(&user)?;
+    policy.validate()?;
+    let policy: ProjectPolicy = sqlx::query_as(
+        "
+        INSERT INTO retention_policies (project_id, update_days, activity_days, history_days, notification_days)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (project_id)
+        DO UPDATE SET
+          update_days = EXCLUDED.update_days,
+          activity_days = EXCLUDED.activity_days,
+          history_days = EXCLUDED.history_days,
+          notification_days = EXCLUDED.notification_days,
+          update_time = NOW()
+        RETURNING project_id, update_days, activity_days, history_days, notification_days, update_time",
+    )
+    .bind(&project_id)
+    .bind(policy.update_days)
+    .bind(policy.activity_days)
+    .bind(policy.history_days)
+    .bind(policy.notification_days)
+    .fetch_one(pool)
+    .await
+    .context("Failed to set retention policy")?;
+    tracing::info!("Set retention policy of {project_id}: {:?}", policy.policy);
+    Ok(Json(policy))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_policy_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<()> {
+    verify_admin(&user)?;
+    sqlx::query("DELETE FROM retention_policies WHERE project_id = $1")
+        .bind(&project_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete retention policy")?;
+    Ok(())
+}
+
+/// Periodically prunes history older than its retention period and records
+/// how much was reclaimed.
+pub(crate) struct RetentionPruner {
+    pool: &'static PgPool,
+}
+
+impl RetentionPruner {
+    pub(crate) fn new(pool: &'static PgPool) -> Self {
+        RetentionPruner { pool }
+    }
+
+    pub(crate) fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.compact_updates().await {
+                    tracing::warn!("Failed to compact aged-out updates: {e:?}");
+                }
+                for target in TARGETS {
+                    if let Err(e) = self.prune(target).await {
+                        tracing::warn!("Failed to prune {}: {e:?}", target.table);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Queues compaction of projects with more than one stored update older
+    /// than their retention, folding the aged-out updates into one. Runs before
+    /// activity is pruned since it relies on the updates' metadata for their age.
+    async fn compact_updates(&self) -> Result<()> {
+        let projects: Vec<(String,)> = sqlx::query_as(
+            "
+            SELECT y.project_id
+            FROM yupdates y
+            JOIN yupdate_metadata m ON m.project_id = y.project_id AND m.seq = y.seq
+            LEFT JOIN retention_policies r ON r.project_id = y.project_id
+            WHERE m.create_time < NOW() - make_interval(days => COALESCE(r.update_days, $1))
+            GROUP BY y.project_id
+            HAVING COUNT(*) > 1",
+        )
+        .bind(settings().retention.update_days)
+        .fetch_all(self.pool)
+        .await
+        .context("Failed to list projects with aged-out updates")?;
+        for (project_id,) in projects {
+            postgres::queue_compaction(self.pool, project_id).await;
+        }
+        Ok(())
+    }
+
+    async fn prune(&self, target: &Target) -> Result<()> {
+        let days = if target.per_project {
+            format!(
+                "COALESCE((SELECT r.{} FROM retention_policies r WHERE r.project_id = t.project_id), $1)",
+                target.category.policy_column()
+            )
+        } else {
+            "$1".to_string()
+        };
+        let query = format!(
+            "
+            DELETE FROM {table}
+            WHERE ctid IN (
+              SELECT t.ctid FROM {table} t
+              WHERE t.{time_column} < NOW() - make_interval(days => {days})
+              LIMIT $2
+            )
+            RETURNING pg_column_size({table}.*)::bigint",
+            table = target.table,
+            time_column = target.time_column,
+        );
+
+        let (mut rows, mut bytes) = (0, 0);
+        loop {
+            // Whatever's left is picked up by the next run.
+            if self.pool.shed_best_effort() {
+                break;
+            }
+            let sizes: Vec<(i64,)> = sqlx::query_as(&query)
+                .bind(target.category.default_days())
+                .bind(BATCH_SIZE)
+                .fetch_all(self.pool)
+                .await
+                .with_context(|| format!("Failed to prune {}", target.table))?;
+            rows += sizes.len() as i64;
+            bytes += sizes.iter().map(|(size,)| size).sum::<i64>();
+            if (sizes.len() as i64) < BATCH_SIZE {
+                break;
+            }
+        }
+        if rows == 0 {
+            return Ok(());
+        }
+
+        tracing::info!(
+            "Pruned {rows} rows ({bytes} bytes) of {} from {}",
+            target.category.as_str(),
+            target.table
+        );
+        sqlx::query(
+            "
+            INSERT INTO retention_runs (category, table_name, rows_pruned, bytes_reclaimed)
+            VALUES ($1, $2, $3, $4)",
+        )
+        .bind(target.category.as_str())
+        .bind(target.table)
+        .bind(rows)
+        .bind(bytes)
+        .execute(self.pool)
+        .await
+        .context("Failed to record retention run")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn validate_rejects_out_of_range_days() {
+        assert!(Policy::default().validate().is_ok());
+        assert!(
+            Policy {
+                activity_days: Some(90),
+                ..Default::default()
+            }
+            .validate()
+            .is_ok()
+        );
+        assert!(
+            Policy {
+                history_days: Some(0),
+                ..Default::default()
+            }
+            .validate()
+            .is_err()
+        );
+        assert!(
+            Policy {
+                notification_days: Some(MAX_DAYS + 1),
+                ..Default::default()
+            }
+            .validate()
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,375 @@
+use crate::{
+    api::{
+        ApiResult,
+        collab::{
+            Collab,
+            projects_state::DocBox,
+            txn_origin::{Actor, YOrigin},
+        },
+        google::User,
+        model::{ProjectId, Task, TaskRemoval},
+        not_found_error, verify_project_admin,
+        yproxy::{RemovedTask, YDocProxy},
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, types::Json as SqlJson};
+use std::collections::HashSet;
+use yrs::TransactionMut;
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/{project_id}/removals", get(list_removals_handler))
+        .route("/{project_id}/revert/{txn_id}", post(revert_handler))
+}
+
+/// Transactions removing more tasks than this are recorded for revert.
+const MAX_UNRECORDED_REMOVALS: usize = 20;
+/// How long removals can be reverted for.
+const UNDO_WINDOW_HOURS: i32 = 24;
+
+/// Where a removed task sat in its parent's children.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+struct Placement {
+    parent: String,
+    child: String,
+    index: usize,
+}
+
+/// Tasks removed by a transaction and where they sat, enough to restore them.
+#[derive(FromRow, Debug)]
+pub(crate) struct Removal {
+    #[sqlx(json)]
+    tasks: Vec<Task>,
+    #[sqlx(json)]
+    placements: Vec<Placement>,
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn list_removals_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Vec<TaskRemoval>>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let removals: Vec<TaskRemoval> = sqlx::query_as(
+        "
+        SELECT id, actor, jsonb_array_length(tasks)::bigint AS task_count, create_time, reverted_time
+        FROM task_removals
+        WHERE project_id = $1
+        AND create_time > NOW() - make_interval(hours => $2)
+        ORDER BY create_time DESC",
+    )
+    .bind(&project_id)
+    .bind(UNDO_WINDOW_HOURS)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list removals")?;
+    Ok(Json(removals))
+}
+
+/// Restores the tasks removed by the given transaction to their former parents.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn revert_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path((project_id, txn_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<()>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    // Claim the removal before restoring it so concurrent reverts can't both restore.
+    let removal: Option<Removal> = sqlx::query_as(
+        "
+        UPDATE task_removals
+        SET reverted_time = NOW()
+        WHERE project_id = $1
+        AND id = $2
+        AND reverted_time IS NULL
+        AND create_time > NOW() - make_interval(hours => $3)
+        RETURNING tasks, placements",
+    )
+    .bind(&project_id)
+    .bind(&txn_id)
+    .bind(UNDO_WINDOW_HOURS)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to claim removal")?;
+    let Some(removal) = removal else {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("No revertible removal {txn_id}"),
+        ));
+    };
+
+    if let Err(e) = restore_removal(&collab, &project_id, &txn_id, &user, &removal).await {
+        // Release the claim so the revert can be retried.
+        sqlx::query(
+            "
+            UPDATE task_removals
+            SET reverted_time = NULL
+            WHERE project_id = $1 AND id = $2",
+        )
+        .bind(&project_id)
+        .bind(&txn_id)
+        .execute(pool)
+        .await
+        .context("Failed to release removal")?;
+        return Err(e.into());
+    }
+    Ok(Json(()))
+}
+
+async fn restore_removal(
+    collab: &Collab,
+    project_id: &ProjectId,
+    txn_id: &str,
+    user: &User,
+    removal: &Removal,
+) -> Result<()> {
+    let client = collab.register_local_client(project_id).await?;
+    let doc_box = client.project.doc_box.lock().await;
+    let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+    let mut txn = doc.transact_mut_with(
+        YOrigin {
+            who: "revert_handler".to_string(),
+            id: txn_id.to_string(),
+            actor: Actor::User(user.clone()),
+            ..Default::default()
+        }
+        .as_origin()?,
+    );
+    restore(doc, &mut txn, &removal.tasks, &removal.placements)
+}
+
+/// The tasks removed by a transaction, if there are enough of them to warrant
+/// an undo.
+pub(crate) fn removal(removed: Vec<RemovedTask>) -> Option<Removal> {
+    (removed.len() > MAX_UNRECORDED_REMOVALS).then(|| removed_tasks(removed))
+}
+
+/// Records the removal for revert.
+/// `actor` is the email of the user who removed the tasks, or the `who` of the
+/// transaction for removals made by the server or plugins.
+pub(crate) async fn record_removal(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    txn_id: &str,
+    actor: &str,
+    removal: &Removal,
+) -> Result<()> {
+    sqlx::query(
+        "
+        INSERT INTO task_removals (id, project_id, actor, tasks, placements)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (project_id, id) DO NOTHING",
+    )
+    .bind(txn_id)
+    .bind(project_id)
+    .bind(actor)
+    .bind(SqlJson(&removal.tasks))
+    .bind(SqlJson(&removal.placements))
+    .execute(pool)
+    .await
+    .context("Failed to record removal")?;
+    sqlx::query(
+        "
+        DELETE FROM task_removals
+        WHERE project_id = $1
+        AND create_time < NOW() - make_interval(hours => $2)",
+    )
+    .bind(project_id)
+    .bind(UNDO_WINDOW_HOURS)
+    .execute(pool)
+    .await
+    .context("Failed to prune removals")?;
+    Ok(())
+}
+
+/// Where the removed tasks sat in the parents that survived the removal.
+fn removed_tasks(removed: Vec<RemovedTask>) -> Removal {
+    let ids: HashSet<String> = removed.iter().map(|r| r.task.id.clone()).collect();
+    let mut placements: Vec<Placement> = removed
+        .iter()
+        .flat_map(|r| {
+            r.parents
+                .iter()
+                .filter(|(parent, _)| !ids.contains(parent))
+                .map(|(parent, index)| Placement {
+                    parent: parent.clone(),
+                    child: r.task.id.clone(),
+                    index: *index,
+                })
+        })
+        .collect();
+    placements.sort_by(|a, b| (&a.parent, a.index).cmp(&(&b.parent, b.index)));
+    Removal {
+        tasks: removed.into_iter().map(|r| r.task).collect(),
+        placements,
+    }
+}
+
+/// Re-creates the removed tasks and re-attaches them to their surviving parents.
+/// Tasks whose number has since been reused are renumbered.
+fn restore(
+    doc: &YDocProxy,
+    txn: &mut TransactionMut,
+    tasks: &[Task],
+    placements: &[Placement],
+) -> Result<()> {
+    let mut nums: HashSet<String> = doc
+        .tasks(txn)?
+        .iter()
+        .map(|t| t.get_num(txn))
+        .collect::<Result<_>>()?;
+    let mut next_num = doc.next_num(txn)?;
+    for task in tasks {
+        if doc.get(txn, &task.id).is_ok() {
+            continue;
+        }
+        let mut task = task.clone();
+        if !nums.insert(task.num.clone()) {
+            while nums.contains(&next_num.to_string()) {
+                next_num += 1;
+            }
+            task.num = next_num.to_string();
+            nums.insert(task.num.clone());
+        }
+        doc.set(txn, &task);
+    }
+
+    for placement in placements {
+        let Ok(parent) = doc.get(txn, &placement.parent) else {
+            continue;
+        };
+        let mut children = parent.get_children(txn)?;
+        if children.contains(&placement.child) {
+            continue;
+        }
+        children.insert(placement.index.min(children.len()), placement.child.clone());
+        parent.set_children(txn, &children);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::model::test_utils::task;
+    use yrs::{ReadTxn as _, StateVector, Update, updates::decoder::Decode as _};
+
+    fn origin() -> yrs::Origin {
+        YOrigin {
+            who: "restore_reverts_removal".to_string(),
+            id: "test".to_string(),
+            actor: Actor::Server,
+            ..Default::default()
+        }
+        .as_origin()
+        .unwrap()
+    }
+
+    #[test]
+    fn restore_reverts_removal() {
+        let doc = YDocProxy::new();
+        {
+            let mut txn = doc.transact_mut_with(origin());
+            doc.set(&mut txn, &task("root", "0", &["a", "b", "c"]));
+            doc.set(&mut txn, &task("a", "1", &[]));
+            doc.set(&mut txn, &task("b", "2", &["d"]));
+            doc.set(&mut txn, &task("c", "3", &[]));
+            doc.set(&mut txn, &task("d", "4", &[]));
+        }
+
+        // A client removes a, b and d, and adds e reusing d's number.
+        let client = YDocProxy::new();
+        let sv = {
+            let mut txn = client.transact_mut_with(origin());
+            let state = doc
+                .transact()
+                .encode_state_as_update_v2(&StateVector::default());
+            txn.apply_update(Update::decode_v2(&state).unwrap())
+                .unwrap();
+            txn.state_vector()
+        };
+        let update = {
+            let mut txn = client.transact_mut_with(origin());
+            client
+                .get(&txn, "root")
+                .unwrap()
+                .set_children(&mut txn, &["c".to_string()]);
+            for id in ["a", "b", "d"] {
+                client.delete(&mut txn, id);
+            }
+            client.set(&mut txn, &task("e", "4", &[]));
+            txn.encode_state_as_update_v2(&sv)
+        };
+        let update = Update::decode_v2(&update).unwrap();
+        let mut txn = doc.transact_mut_with(origin());
+        doc.snapshot_deleted(&txn, update.delete_set()).unwrap();
+        txn.apply_update(update).unwrap();
+
+        let Removal {
+            mut tasks,
+            placements,
+        } = removed_tasks(doc.removed().take());
+        tasks.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(
+            tasks.iter().map(|t| t.id.as_str()).collect::<Vec<_>>(),
+            vec!["a", "b", "d"]
+        );
+        assert_eq!(tasks[1].children, vec!["d"]);
+        assert_eq!(
+            placements,
+            vec![
+                Placement {
+                    parent: "root".to_string(),
+                    child: "a".to_string(),
+                    index: 0
+                },
+                Placement {
+                    parent: "root".to_string(),
+                    child: "b".to_string(),
+                    index: 1
+                }
+            ]
+        );
+
+        restore(&doc, &mut txn, &tasks, &placements).unwrap();
+
+        let restored = doc.to_graph(&txn).unwrap();
+        assert_eq!(restored.get("root").unwrap().children, vec!["a", "b", "c"]);
+        assert_eq!(restored.get("b").unwrap().children, vec!["d"]);
+        // Task 4 was reused by e, so d is renumbered.
+        assert_eq!(restored.get("e").unwrap().num, "4");
+        assert_eq!(restored.get("d").unwrap().num, "5");
+    }
+
+    #[test]
+    fn delete_snapshots_removed_task() {
+        let doc = YDocProxy::new();
+        let mut txn = doc.transact_mut_with(origin());
+        doc.set(&mut txn, &task("root", "0", &["a"]));
+        doc.set(&mut txn, &task("a", "1", &[]));
+        doc.delete(&mut txn, "a");
+
+        let removal = removed_tasks(doc.removed().take());
+        assert_eq!(removal.tasks, vec![task("a", "1", &[])]);
+        assert_eq!(
+            removal.placements,
+            vec![Placement {
+                parent: "root".to_string(),
+                child: "a".to_string(),
+                index: 0
+            }]
+        );
+        assert!(doc.removed().take().is_empty());
+    }
+}
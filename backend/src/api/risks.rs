@@ -0,0 +1,462 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::Collab,
+        goals::{load_graph, validate_owner, validate_task_ids},
+        google::User,
+        model::{
+            CreateRisk, Graph, ProjectId, Risk, UpdateRisk, WorkflowCategory, WorkflowState,
+            parse_time_zone,
+        },
+        not_found_error,
+        reports::escape_html,
+        validate_title, verify_project_access,
+        yproxy::status_category,
+    },
+    notifiers::Notifiers,
+    postgres::{PgPool, PgTransaction},
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    routing::{get, patch},
+};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use sqlx::types::{
+    Json as SqlJson,
+    chrono::{DateTime, Utc},
+};
+use std::collections::{HashSet, VecDeque};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route(
+            "/{project_id}/risks",
+            get(list_risks_handler).post(create_risk_handler),
+        )
+        .route(
+            "/{project_id}/risks/{risk_id}",
+            patch(update_risk_handler).delete(delete_risk_handler),
+        )
+}
+
+const MAX_TEXT_LEN: usize = 10_000;
+/// How often to check for linked tasks that slipped.
+const ESCALATION_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+const RISK_COLUMNS: &str = "id, title, description, likelihood, impact, mitigation, owner, task_ids, closed, creator, create_time, update_time";
+
+/// Lists the project's risks, highest scoring first.
+#[tracing::instrument(skip(user, pool))]
+async fn list_risks_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Vec<Risk>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    Ok(Json(list_risks(pool, &project_id).await?))
+}
+
+#[tracing::instrument(skip(user, pool, collab))]
+async fn create_risk_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+    Json(risk): Json<CreateRisk>,
+) -> ApiResult<Json<Risk>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    validate_title("Risk", &risk.title)?;
+    validate_text(risk.description.as_deref())?;
+    validate_text(risk.mitigation.as_deref())?;
+    validate_rating(risk.likelihood)?;
+    validate_rating(risk.impact)?;
+    validate_owner(pool, &project_id, risk.owner.as_deref()).await?;
+    let (graph, _) = load_graph(&collab, &project_id).await?;
+    validate_task_ids(&graph, &risk.task_ids)?;
+
+    let created: Risk = sqlx::query_as(&format!(
+        "
+        INSERT INTO risks (id, project_id, title, description, likelihood, impact, mitigation, owner, task_ids, creator)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        RETURNING {RISK_COLUMNS}"
+    ))
+    .bind(BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4()))
+    .bind(&project_id)
+    .bind(risk.title.trim())
+    .bind(&risk.description)
+    .bind(risk.likelihood)
+    .bind(risk.impact)
+    .bind(&risk.mitigation)
+    .bind(&risk.owner)
+    .bind(SqlJson(&risk.task_ids))
+    .bind(&user.email)
+    .fetch_one(pool)
+    .await
+    .context("Failed to insert risk")?;
+    Ok(Json(created))
+}
+
+#[tracing::instrument(skip(user, pool, collab))]
+async fn update_risk_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path((project_id, risk_id)): Path<(ProjectId, String)>,
+    Json(update): Json<UpdateRisk>,
+) -> ApiResult<Json<Risk>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    if let Some(title) = &update.title {
+        validate_title("Risk", title)?;
+    }
+    validate_text(update.description.as_deref())?;
+    validate_text(update.mitigation.as_deref())?;
+    if let Some(likelihood) = update.likelihood {
+        validate_rating(likelihood)?;
+    }
+    if let Some(impact) = update.impact {
+        validate_rating(impact)?;
+    }
+    validate_owner(pool, &project_id, update.owner.as_deref()).await?;
+    if let Some(task_ids) = &update.task_ids {
+        let (graph, _) = load_graph(&collab, &project_id).await?;
+        validate_task_ids(&graph, task_ids)?;
+    }
+
+    let updated: Option<Risk> = sqlx::query_as(&format!(
+        "
+        UPDATE risks
+        SET title = COALESCE($3, title),
+            description = COALESCE($4, description),
+            likelihood = COALESCE($5, likelihood),
+            impact = COALESCE($6, impact),
+            mitigation = COALESCE($7, mitigation),
+            owner = COALESCE($8, owner),
+            task_ids = COALESCE($9, task_ids),
+            closed = COALESCE($10, closed),
+            update_time = NOW()
+        WHERE project_id = $1 AND id = $2
+        RETURNING {RISK_COLUMNS}"
+    ))
+    .bind(&project_id)
+    .bind(&risk_id)
+    .bind(update.title.as_deref().map(str::trim))
+    .bind(&update.description)
+    .bind(update.likelihood)
+    .bind(update.impact)
+    .bind(&update.mitigation)
+    .bind(&update.owner)
+    .bind(update.task_ids.as_ref().map(SqlJson))
+    .bind(update.closed)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to update risk")?;
+    updated
+        .map(Json)
+        .ok_or_else(|| not_found_error("NOT_FOUND", &format!("Risk {risk_id} not found")))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_risk_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, risk_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<()>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let res = sqlx::query("DELETE FROM risks WHERE project_id = $1 AND id = $2")
+        .bind(&project_id)
+        .bind(&risk_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete risk")?;
+    if res.rows_affected() == 0 {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Risk {risk_id} not found"),
+        ));
+    }
+    Ok(Json(()))
+}
+
+/// Lists the project's risks, highest scoring first.
+pub(crate) async fn list_risks(pool: &PgPool, project_id: &ProjectId) -> Result<Vec<Risk>> {
+    sqlx::query_as(&format!(
+        "
+        SELECT {RISK_COLUMNS}
+        FROM risks
+        WHERE project_id = $1
+        ORDER BY closed, likelihood * impact DESC, create_time"
+    ))
+    .bind(project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list risks")
+}
+
+/// Validates risks being imported along with a project export.
+pub(crate) fn validate_import(risks: &[Risk]) -> ApiResult<()> {
+    for risk in risks {
+        validate_title("Risk", &risk.title)?;
+        validate_text(risk.description.as_deref())?;
+        validate_text(risk.mitigation.as_deref())?;
+        validate_rating(risk.likelihood)?;
+        validate_rating(risk.impact)?;
+    }
+    Ok(())
+}
+
+/// Copies exported risks into a newly created project.
+pub(crate) async fn import_risks(
+    txn: &mut PgTransaction,
+    project_id: &ProjectId,
+    risks: &[Risk],
+) -> Result<()> {
+    for risk in risks {
+        sqlx::query(
+            "
+            INSERT INTO risks (id, project_id, title, description, likelihood, impact, mitigation, owner, task_ids, closed, creator, create_time, update_time)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)",
+        )
+        .bind(BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4()))
+        .bind(project_id)
+        .bind(&risk.title)
+        .bind(&risk.description)
+        .bind(risk.likelihood)
+        .bind(risk.impact)
+        .bind(&risk.mitigation)
+        .bind(&risk.owner)
+        .bind(SqlJson(&risk.task_ids))
+        .bind(risk.closed)
+        .bind(&risk.creator)
+        .bind(risk.create_time)
+        .bind(risk.update_time)
+        .execute(&mut **txn)
+        .await
+        .context("Failed to import risk")?;
+    }
+    Ok(())
+}
+
+fn validate_text(text: Option<&str>) -> ApiResult<()> {
+    if text.is_some_and(|t| t.len() > MAX_TEXT_LEN) {
+        return Err(bad_request_error(
+            "LONG_TEXT",
+            &format!("Risk text cannot be longer than {MAX_TEXT_LEN} characters"),
+        ));
+    }
+    Ok(())
+}
+
+fn validate_rating(rating: i16) -> ApiResult<()> {
+    if !(1..=5).contains(&rating) {
+        return Err(bad_request_error(
+            "INVALID_RATING",
+            "Likelihood and impact must be between 1 and 5",
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the tasks in the subtrees rooted at `roots` whose deadline passed
+/// before they were done, ordered by id.
+fn slipped_tasks(
+    graph: &Graph,
+    workflow_states: &[WorkflowState],
+    roots: &[String],
+    now: DateTime<Utc>,
+) -> Vec<String> {
+    let utc = parse_time_zone(None);
+    let mut slipped = vec![];
+    let mut visited = HashSet::new();
+    let mut queue: VecDeque<String> = roots.iter().cloned().collect();
+    while let Some(id) = queue.pop_front() {
+        if !visited.insert(id.clone()) {
+            continue;
+        }
+        let Some(task) = graph.get(&id) else {
+            continue;
+        };
+        if task.archived == Some(true) {
+            continue;
+        }
+        queue.extend(task.children.iter().cloned());
+        let done = task
+            .status
+            .as_deref()
+            .and_then(|status| status_category(workflow_states, status))
+            == Some(WorkflowCategory::Done);
+        if !done && task.deadline.as_ref().is_some_and(|d| d.due_at(&utc) < now) {
+            slipped.push(id);
+        }
+    }
+    slipped.sort();
+    slipped
+}
+
+#[derive(sqlx::FromRow)]
+struct EscalationRow {
+    id: String,
+    project_id: String,
+    title: String,
+    owner: Option<String>,
+    creator: String,
+    task_ids: SqlJson<Vec<String>>,
+    escalated_task_ids: SqlJson<Vec<String>>,
+}
+
+/// Periodically escalates open risks whose linked tasks slipped past their
+/// deadlines to the risk's owner, or its creator if unowned.
+pub(crate) struct RiskMonitor {
+    pool: &'static PgPool,
+    collab: Collab,
+    notifier: Notifiers,
+}
+
+impl RiskMonitor {
+    pub(crate) fn new(pool: &'static PgPool, collab: Collab) -> Result<Self> {
+        Ok(RiskMonitor {
+            pool,
+            collab,
+            notifier: Notifiers::new(pool)?,
+        })
+    }
+
+    pub(crate) fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ESCALATION_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.escalate_slipped_tasks().await {
+                    tracing::warn!("Failed to escalate risks: {e:?}");
+                }
+            }
+        })
+    }
+
+    async fn escalate_slipped_tasks(&self) -> Result<()> {
+        let risks: Vec<EscalationRow> = sqlx::query_as(
+            "
+            SELECT r.id, r.project_id, r.title, r.owner, r.creator, r.task_ids, r.escalated_task_ids
+            FROM risks r
+            JOIN projects p ON p.project_id = r.project_id
+            WHERE p.deleted_on IS NULL
+            AND NOT r.closed
+            AND r.task_ids != '[]'::jsonb
+            ORDER BY r.project_id",
+        )
+        .fetch_all(self.pool)
+        .await
+        .context("Failed to list open risks")?;
+
+        let now = Utc::now();
+        let mut loaded: Option<(String, Graph, Vec<WorkflowState>)> = None;
+        for risk in risks {
+            if loaded
+                .as_ref()
+                .is_none_or(|(id, _, _)| *id != risk.project_id)
+            {
+                match load_graph(&self.collab, &risk.project_id).await {
+                    Ok((graph, states)) => loaded = Some((risk.project_id.clone(), graph, states)),
+                    Err(e) => {
+                        tracing::warn!("Failed to load graph of {}: {e:?}", risk.project_id);
+                        loaded = None;
+                        continue;
+                    }
+                }
+            }
+            let Some((_, graph, workflow_states)) = &loaded else {
+                continue;
+            };
+            let slipped = slipped_tasks(graph, workflow_states, &risk.task_ids, now);
+            if let Err(e) = self.escalate(&risk, graph, &slipped).await {
+                tracing::warn!("Failed to escalate risk {}: {e:?}", risk.id);
+            }
+        }
+        Ok(())
+    }
+
+    async fn escalate(
+        &self,
+        risk: &EscalationRow,
+        graph: &Graph,
+        slipped: &[String],
+    ) -> Result<()> {
+        let newly_slipped: Vec<&String> = slipped
+            .iter()
+            .filter(|id| !risk.escalated_task_ids.contains(*id))
+            .collect();
+        if slipped == risk.escalated_task_ids.as_slice() {
+            return Ok(());
+        }
+        sqlx::query("UPDATE risks SET escalated_task_ids = $2 WHERE id = $1")
+            .bind(&risk.id)
+            .bind(SqlJson(slipped))
+            .execute(self.pool)
+            .await
+            .context("Failed to record escalated tasks")?;
+        if newly_slipped.is_empty() {
+            return Ok(());
+        }
+
+        let mut msg = format!(
+            "🚨 Risk <b>{}</b> escalated, linked tasks slipped:",
+            escape_html(&risk.title)
+        );
+        for id in newly_slipped {
+            let name = graph.get(id).map_or("", |t| t.name.as_str());
+            msg.push_str(&format!(
+                "\n• <a href=\"https://koso.app/projects/{}?taskId={id}\">{}</a>",
+                risk.project_id,
+                escape_html(name)
+            ));
+        }
+        let recipient = risk.owner.as_ref().unwrap_or(&risk.creator);
+        self.notifier.notify(recipient, &msg).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::model::{Deadline, Task, test_utils};
+
+    #[test]
+    fn slipped_tasks_finds_overdue_unfinished_tasks() {
+        let now = DateTime::from_timestamp_millis(1_000_000_000).unwrap();
+        let past = Some(Deadline::DateTime {
+            millis: 1_000,
+            time_zone: "UTC".into(),
+        });
+        let future = Some(Deadline::DateTime {
+            millis: 2_000_000_000,
+            time_zone: "UTC".into(),
+        });
+        let graph = test_utils::graph([
+            Task {
+                deadline: past.clone(),
+                ..test_utils::task("1", "1", &["2", "3", "4"])
+            },
+            Task {
+                status: Some("Done".to_string()),
+                deadline: past.clone(),
+                ..test_utils::task("2", "2", &[])
+            },
+            Task {
+                deadline: future,
+                ..test_utils::task("3", "3", &[])
+            },
+            Task {
+                status: Some("In Progress".to_string()),
+                deadline: past,
+                ..test_utils::task("4", "4", &[])
+            },
+        ]);
+        assert_eq!(
+            slipped_tasks(&graph, &[], &["1".to_string()], now),
+            vec!["1", "4"]
+        );
+        assert!(slipped_tasks(&graph, &[], &["3".to_string()], now).is_empty());
+    }
+}
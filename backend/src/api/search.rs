@@ -0,0 +1,189 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        google::User,
+        model::{ProjectSearchResults, SearchHit},
+        yproxy::{parse_task_key, task_key},
+    },
+    postgres::PgPool,
+};
+use anyhow::Context as _;
+use axum::{Extension, Json, Router, extract::Query, routing::get};
+use serde::Deserialize;
+
+pub(super) fn router() -> Router {
+    Router::new().route("/", get(search_handler))
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchQuery {
+    q: String,
+}
+
+const MAX_QUERY_LEN: usize = 256;
+const MAX_RESULTS: i64 = 200;
+
+/// Searches the names and numbers of tasks in every project the user can access.
+/// Results are grouped by project, best matches first.
+#[tracing::instrument(skip(user, pool))]
+async fn search_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Query(query): Query<SearchQuery>,
+) -> ApiResult<Json<Vec<ProjectSearchResults>>> {
+    Ok(Json(search_tasks(pool, &user.email, &query.q).await?))
+}
+
+pub(super) async fn search_tasks(
+    pool: &PgPool,
+    email: &str,
+    q: &str,
+) -> ApiResult<Vec<ProjectSearchResults>> {
+    let q = q.trim();
+    if q.is_empty() {
+        return Err(bad_request_error("EMPTY_QUERY", "Search query is empty"));
+    }
+    if q.len() > MAX_QUERY_LEN {
+        return Err(bad_request_error(
+            "LONG_QUERY",
+            &format!("Search query cannot be longer than {MAX_QUERY_LEN} characters"),
+        ));
+    }
+
+    // Keys match the task's num, and, if prefixed, only in projects with that prefix.
+    let (prefix, num) = parse_task_key(q).unwrap_or((None, q));
+
+    // Permissions are applied in the query itself so results never
+    // include projects the user has lost access to.
+    let rows: Vec<SearchRow> = sqlx::query_as(
+        "
+        SELECT
+            t.project_id,
+            p.name AS project_name,
+            t.task_id,
+            t.num,
+            p.task_key_prefix,
+            t.name,
+            t.assignee,
+            t.status,
+            t.kind
+        FROM task_projections t
+        JOIN project_permissions pp ON pp.project_id = t.project_id
+        JOIN projects p ON p.project_id = t.project_id
+        WHERE pp.email = $1
+        AND p.deleted_on IS NULL
+        AND NOT t.archived
+        AND (
+            (t.num = $2 AND ($6::varchar IS NULL OR p.task_key_prefix = UPPER($6)))
+            OR t.name ILIKE $3 ESCAPE '\\'
+        )
+        ORDER BY
+            (t.num = $2 AND ($6::varchar IS NULL OR p.task_key_prefix = UPPER($6))) DESC,
+            t.name ILIKE $4 ESCAPE '\\' DESC,
+            p.name,
+            t.project_id,
+            t.update_time DESC
+        LIMIT $5",
+    )
+    .bind(email)
+    .bind(num)
+    .bind(format!("%{}%", escape_like(q)))
+    .bind(format!("{}%", escape_like(q)))
+    .bind(MAX_RESULTS)
+    .bind(prefix)
+    .fetch_all(pool)
+    .await
+    .context("Failed to search tasks")?;
+
+    Ok(group_by_project(rows))
+}
+
+#[derive(sqlx::FromRow)]
+struct SearchRow {
+    project_id: String,
+    project_name: String,
+    task_id: String,
+    num: String,
+    task_key_prefix: Option<String>,
+    name: String,
+    assignee: Option<String>,
+    status: Option<String>,
+    kind: Option<String>,
+}
+
+/// Groups rows by project, preserving the order in which projects first appear.
+fn group_by_project(rows: Vec<SearchRow>) -> Vec<ProjectSearchResults> {
+    let mut results: Vec<ProjectSearchResults> = Vec::new();
+    for row in rows {
+        let hit = SearchHit {
+            task_id: row.task_id,
+            key: task_key(row.task_key_prefix.as_deref(), &row.num),
+            num: row.num,
+            name: row.name,
+            assignee: row.assignee,
+            status: row.status,
+            kind: row.kind,
+        };
+        match results.iter_mut().find(|r| r.project_id == row.project_id) {
+            Some(project) => project.tasks.push(hit),
+            None => results.push(ProjectSearchResults {
+                project_id: row.project_id,
+                project_name: row.project_name,
+                tasks: vec![hit],
+            }),
+        }
+    }
+    results
+}
+
+/// Escapes the wildcard characters in a LIKE pattern.
+fn escape_like(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_like_escapes_wildcards() {
+        assert_eq!(escape_like("50% of a_b\\c"), "50\\% of a\\_b\\\\c");
+        assert_eq!(escape_like("plain"), "plain");
+    }
+
+    #[test]
+    fn group_by_project_preserves_order() {
+        let row = |project_id: &str, task_id: &str| SearchRow {
+            project_id: project_id.to_string(),
+            project_name: project_id.to_uppercase(),
+            task_id: task_id.to_string(),
+            num: task_id.to_string(),
+            task_key_prefix: None,
+            name: String::new(),
+            assignee: None,
+            status: None,
+            kind: None,
+        };
+        let results = group_by_project(vec![row("b", "1"), row("a", "2"), row("b", "3")]);
+        assert_eq!(
+            results
+                .iter()
+                .map(|r| (
+                    r.project_id.as_str(),
+                    r.tasks
+                        .iter()
+                        .map(|t| t.task_id.as_str())
+                        .collect::<Vec<_>>()
+                ))
+                .collect::<Vec<_>>(),
+            vec![("b", vec!["1", "3"]), ("a", vec!["2"])]
+        );
+    }
+}
@@ -0,0 +1,425 @@
+use crate::{
+    api::{
+        ApiResult, XForwardedFor, bad_request_error, error_response,
+        google::User,
+        model::{SecurityAuditEntry, SecurityPolicy},
+        unauthorized_error,
+    },
+    postgres::PgPool,
+    settings::settings,
+};
+use anyhow::{Context as _, Result, anyhow};
+use axum::{
+    Extension, Json, Router,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{Extensions, HeaderMap, StatusCode, request::Parts},
+    routing::get,
+};
+use axum_extra::headers::HeaderMapExt as _;
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::LazyLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/policy", get(get_policy_handler).put(set_policy_handler))
+        .route("/audit", get(list_audit_log_handler))
+}
+
+const MAX_ALLOWLIST_LEN: usize = 100;
+const MIN_REAUTH_INTERVAL_SECS: i32 = 5 * 60;
+
+/// Returns the policy of the subscription owned by the user.
+#[tracing::instrument(skip(user, pool))]
+async fn get_policy_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<SecurityPolicy>> {
+    verify_subscription_owner(pool, &user).await?;
+    let policy: Option<SecurityPolicy> = sqlx::query_as(
+        "
+        SELECT ip_allowlist, reauth_interval_secs
+        FROM security_policies
+        WHERE owner_email = $1",
+    )
+    .bind(&user.email)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch security policy")?;
+    Ok(Json(policy.unwrap_or_default()))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn set_policy_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    ClientIp(client_ip): ClientIp,
+    Json(policy): Json<SecurityPolicy>,
+) -> ApiResult<Json<SecurityPolicy>> {
+    verify_subscription_owner(pool, &user).await?;
+
+    if policy.ip_allowlist.len() > MAX_ALLOWLIST_LEN {
+        return Err(bad_request_error(
+            "LONG_ALLOWLIST",
+            &format!("Allowlists cannot have more than {MAX_ALLOWLIST_LEN} entries"),
+        ));
+    }
+    let mut cidrs = Vec::with_capacity(policy.ip_allowlist.len());
+    for cidr in &policy.ip_allowlist {
+        match cidr.parse::<Cidr>() {
+            Ok(cidr) => cidrs.push(cidr),
+            Err(e) => return Err(bad_request_error("INVALID_CIDR", &e.to_string())),
+        }
+    }
+    // Prevent owners from locking themselves out.
+    if !cidrs.is_empty() && !client_ip.is_some_and(|ip| cidrs.iter().any(|c| c.contains(ip))) {
+        return Err(bad_request_error(
+            "SELF_LOCKOUT",
+            "The allowlist must include your current IP address",
+        ));
+    }
+    if policy
+        .reauth_interval_secs
+        .is_some_and(|secs| secs < MIN_REAUTH_INTERVAL_SECS)
+    {
+        return Err(bad_request_error(
+            "SHORT_REAUTH_INTERVAL",
+            &format!("Re-auth interval must be at least {MIN_REAUTH_INTERVAL_SECS} seconds"),
+        ));
+    }
+
+    sqlx::query(
+        "
+        INSERT INTO security_policies (owner_email, ip_allowlist, reauth_interval_secs)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (owner_email)
+        DO UPDATE SET
+          ip_allowlist = EXCLUDED.ip_allowlist,
+          reauth_interval_secs = EXCLUDED.reauth_interval_secs,
+          update_time = NOW()",
+    )
+    .bind(&user.email)
+    .bind(&policy.ip_allowlist)
+    .bind(policy.reauth_interval_secs)
+    .execute(pool)
+    .await
+    .context("Failed to set security policy")?;
+    audit(
+        pool,
+        &user.email,
+        &user.email,
+        "policyUpdated",
+        client_ip,
+        &format!("{policy:?}"),
+    )
+    .await?;
+    Ok(Json(policy))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn list_audit_log_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<Vec<SecurityAuditEntry>>> {
+    verify_subscription_owner(pool, &user).await?;
+    let entries: Vec<SecurityAuditEntry> = sqlx::query_as(
+        "
+        SELECT id, email, event, client_ip, details, create_time
+        FROM security_audit_log
+        WHERE owner_email = $1
+        ORDER BY create_time DESC
+        LIMIT 500",
+    )
+    .bind(&user.email)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list audit log")?;
+    Ok(Json(entries))
+}
+
+async fn verify_subscription_owner(pool: &PgPool, user: &User) -> ApiResult<()> {
+    let owner: Option<(String,)> =
+        sqlx::query_as("SELECT email FROM subscriptions WHERE email = $1")
+            .bind(&user.email)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to check subscription owner")?;
+    if owner.is_none() {
+        return Err(unauthorized_error(&format!(
+            "User {} does not own a subscription",
+            user.email
+        )));
+    }
+    Ok(())
+}
+
+/// Enforces the policies of every subscription the user belongs to.
+/// Called by the auth middleware, which also guards websocket upgrades.
+/// Violations are recorded in the owning subscription's audit log.
+pub(crate) async fn enforce_policies(
+    pool: &PgPool,
+    user: &User,
+    client_ip: Option<IpAddr>,
+) -> ApiResult<()> {
+    let policies: Vec<(String, Vec<String>, Option<i32>)> = sqlx::query_as(
+        "
+        SELECT sp.owner_email, sp.ip_allowlist, sp.reauth_interval_secs
+        FROM security_policies sp
+        JOIN subscriptions s ON s.email = sp.owner_email
+        WHERE s.email = $1 OR $1 = ANY(s.member_emails)",
+    )
+    .bind(&user.email)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch security policies")?;
+
+    for (owner, ip_allowlist, reauth_interval_secs) in policies {
+        if !ip_allowlist.is_empty() {
+            let allowed = client_ip.is_some_and(|ip| {
+                ip_allowlist
+                    .iter()
+                    .filter_map(|c| c.parse::<Cidr>().ok())
+                    .any(|c| c.contains(ip))
+            });
+            if !allowed {
+                audit(pool, &owner, &user.email, "ipBlocked", client_ip, "").await?;
+                return Err(error_response(
+                    StatusCode::FORBIDDEN,
+                    "IP_NOT_ALLOWED",
+                    Some(&format!(
+                        "Requests from {client_ip:?} are not allowed for {}",
+                        user.email
+                    )),
+                    None,
+                ));
+            }
+        }
+        if let Some(interval) = reauth_interval_secs {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .context("Failed to get current time")?
+                .as_secs();
+            // Refreshing a token advances its iat, so only auth_time tells when
+            // the user last signed in. Tokens without it must re-authenticate.
+            let session_age = user
+                .auth_time
+                .map(|auth_time| now.saturating_sub(auth_time as u64));
+            if session_age.is_none_or(|age| age > interval as u64) {
+                audit(
+                    pool,
+                    &owner,
+                    &user.email,
+                    "reauthRequired",
+                    client_ip,
+                    &format!("session age: {session_age:?}"),
+                )
+                .await?;
+                return Err(error_response(
+                    StatusCode::UNAUTHORIZED,
+                    "REAUTH_REQUIRED",
+                    Some(&format!("Session for {} must re-authenticate", user.email)),
+                    None,
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn audit(
+    pool: &PgPool,
+    owner: &str,
+    email: &str,
+    event: &str,
+    client_ip: Option<IpAddr>,
+    details: &str,
+) -> Result<()> {
+    sqlx::query(
+        "
+        INSERT INTO security_audit_log (id, owner_email, email, event, client_ip, details)
+        VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(BASE64_URL_SAFE_NO_PAD.encode(uuid::Uuid::new_v4()))
+    .bind(owner)
+    .bind(email)
+    .bind(event)
+    .bind(client_ip.map(|ip| ip.to_string()))
+    .bind(details)
+    .execute(pool)
+    .await
+    .context("Failed to write audit log")?;
+    Ok(())
+}
+
+/// Returns the client's IP address. Requests relayed by a trusted proxy are
+/// attributed to the address it forwarded them for.
+pub(crate) fn client_ip(headers: &HeaderMap, extensions: &Extensions) -> Option<IpAddr> {
+    static TRUSTED_PROXIES: LazyLock<Vec<Cidr>> = LazyLock::new(|| {
+        settings()
+            .trusted_proxies
+            .iter()
+            .filter_map(|cidr| match cidr.parse() {
+                Ok(cidr) => Some(cidr),
+                Err(e) => {
+                    tracing::warn!("Ignoring invalid trusted proxy: {e}");
+                    None
+                }
+            })
+            .collect()
+    });
+    let peer = extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())?;
+    let hops = headers
+        .typed_get::<XForwardedFor>()
+        .map(|forwarded_for| forwarded_for.hops)
+        .unwrap_or_default();
+    forwarded_client_ip(peer, &hops, &TRUSTED_PROXIES)
+}
+
+/// Walks X-Forwarded-For from the right, i.e. from the entry the nearest proxy
+/// added, while the hop is a trusted proxy. The first untrusted hop is the
+/// client. Anyone can prepend entries, so those left of it are ignored, as is
+/// the whole header on requests that didn't come through a trusted proxy.
+fn forwarded_client_ip(peer: IpAddr, hops: &[String], trusted_proxies: &[Cidr]) -> Option<IpAddr> {
+    let is_trusted = |ip: IpAddr| trusted_proxies.iter().any(|c| c.contains(ip));
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        if !is_trusted(client) {
+            break;
+        }
+        client = hop.parse().ok()?;
+    }
+    Some(client)
+}
+
+/// Extracts the client's IP address, if known.
+#[derive(Debug)]
+pub(crate) struct ClientIp(pub(crate) Option<IpAddr>);
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(ClientIp(client_ip(&parts.headers, &parts.extensions)))
+    }
+}
+
+/// An IP network in CIDR notation, e.g. 10.0.0.0/8. Bare addresses match only themselves.
+#[derive(Debug, PartialEq, Eq)]
+struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let network: IpAddr = addr
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("Invalid IP address: {s}"))?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(|| anyhow!("Invalid prefix length: {s}"))?,
+            None => max_len,
+        };
+        Ok(Cidr {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+impl Cidr {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(cidr: &str, ip: &str) -> bool {
+        cidr.parse::<Cidr>().unwrap().contains(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn cidr_contains() {
+        assert!(contains("10.0.0.0/8", "10.1.2.3"));
+        assert!(!contains("10.0.0.0/8", "11.1.2.3"));
+        assert!(contains("192.168.1.7", "192.168.1.7"));
+        assert!(!contains("192.168.1.7", "192.168.1.8"));
+        assert!(contains("0.0.0.0/0", "8.8.8.8"));
+        assert!(contains("10.0.0.0/8", "::ffff:10.0.0.1"));
+        assert!(contains("2001:db8::/32", "2001:db8::1"));
+        assert!(!contains("2001:db8::/32", "2001:db9::1"));
+        assert!(!contains("2001:db8::/32", "10.0.0.1"));
+    }
+
+    fn forwarded_client_ip(peer: &str, hops: &[&str]) -> Option<IpAddr> {
+        let trusted = [
+            "127.0.0.1/32".parse().unwrap(),
+            "10.0.0.0/8".parse().unwrap(),
+        ];
+        let hops = hops.iter().map(|hop| hop.to_string()).collect::<Vec<_>>();
+        super::forwarded_client_ip(peer.parse().unwrap(), &hops, &trusted)
+    }
+
+    #[test]
+    fn forwarded_client_ip_trusts_only_proxies() {
+        let ip = |ip: &str| Some(ip.parse().unwrap());
+        // The nearest proxy's entry, not the spoofable leftmost one.
+        assert_eq!(
+            forwarded_client_ip("127.0.0.1", &["1.1.1.1", "8.8.8.8"]),
+            ip("8.8.8.8")
+        );
+        // Proxies in a chain are skipped.
+        assert_eq!(
+            forwarded_client_ip("127.0.0.1", &["1.1.1.1", "8.8.8.8", "10.0.0.2"]),
+            ip("8.8.8.8")
+        );
+        // Clients connecting directly can't forward for anyone.
+        assert_eq!(forwarded_client_ip("8.8.8.8", &["1.1.1.1"]), ip("8.8.8.8"));
+        assert_eq!(forwarded_client_ip("127.0.0.1", &[]), ip("127.0.0.1"));
+        assert_eq!(forwarded_client_ip("127.0.0.1", &["nope"]), None);
+    }
+
+    #[test]
+    fn cidr_rejects_invalid() {
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+        assert!("::/129".parse::<Cidr>().is_err());
+        assert!("nope".parse::<Cidr>().is_err());
+    }
+}
@@ -0,0 +1,165 @@
+//! Shadow verification of refactored code paths. The current path keeps
+//! serving clients while, for a sample of calls, the refactored path runs in
+//! the background on the same inputs and its outcome is compared. Divergences
+//! are logged, counted and kept in memory for operators to inspect, and never
+//! affect the caller.
+
+use crate::{
+    api::{ApiResult, context, google::User, verify_admin},
+    settings::settings,
+};
+use anyhow::Result;
+use axum::{Extension, Json, Router, routing::get};
+use serde::Serialize;
+use sqlx::types::chrono::{DateTime, Utc};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    future::Future,
+    sync::{LazyLock, Mutex},
+};
+use tokio::sync::Semaphore;
+
+pub(super) fn router() -> Router {
+    Router::new().route("/", get(get_shadow_handler))
+}
+
+/// Maximum shadow runs in flight, beyond which calls aren't verified, so
+/// shadowing can't pile up work under load.
+const MAX_IN_FLIGHT: usize = 4;
+/// Number of recent divergences kept.
+const MAX_DIVERGENCES: usize = 100;
+/// Outcomes are truncated to this many characters when recorded.
+const MAX_OUTCOME_LEN: usize = 4000;
+
+static IN_FLIGHT: Semaphore = Semaphore::const_new(MAX_IN_FLIGHT);
+static DIVERGENCES: LazyLock<Mutex<VecDeque<Divergence>>> =
+    LazyLock::new(|| Mutex::new(VecDeque::new()));
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Divergence {
+    experiment: &'static str,
+    /// What the call was about, e.g. a project id.
+    subject: String,
+    expected: String,
+    actual: String,
+    time: DateTime<Utc>,
+}
+
+fn sampled(experiment: &str) -> bool {
+    settings()
+        .shadow
+        .get(experiment)
+        .is_some_and(|rate| rand::random::<f64>() < *rate)
+}
+
+/// For a sample of calls, runs `actual`, the refactored path, in the background
+/// and compares its outcome with `expected`, the current path's. Neither closure
+/// is called unless the call is sampled.
+pub(crate) fn compare<T, E, A, F>(experiment: &'static str, subject: &str, expected: E, actual: A)
+where
+    T: PartialEq + Debug + Send + 'static,
+    E: FnOnce() -> Result<T>,
+    A: FnOnce() -> F,
+    F: Future<Output = Result<T>> + Send + 'static,
+{
+    if !sampled(experiment) {
+        return;
+    }
+    let Ok(permit) = IN_FLIGHT.try_acquire() else {
+        metrics::counter!("shadow_runs_total", "experiment" => experiment, "outcome" => "skipped")
+            .increment(1);
+        return;
+    };
+    let expected = match expected() {
+        Ok(expected) => expected,
+        Err(e) => {
+            tracing::debug!("Not shadowing {experiment}, the current path failed: {e:?}");
+            return;
+        }
+    };
+    let subject = subject.to_string();
+    let actual = actual();
+    context::spawn(async move {
+        let _permit = permit;
+        let outcome = match actual.await {
+            Ok(actual) if actual == expected => "match",
+            Ok(actual) => {
+                record(
+                    experiment,
+                    subject,
+                    format!("{expected:?}"),
+                    format!("{actual:?}"),
+                );
+                "diverged"
+            }
+            Err(e) => {
+                record(
+                    experiment,
+                    subject,
+                    format!("{expected:?}"),
+                    format!("Error: {e:?}"),
+                );
+                "error"
+            }
+        };
+        metrics::counter!("shadow_runs_total", "experiment" => experiment, "outcome" => outcome)
+            .increment(1);
+    });
+}
+
+fn record(experiment: &'static str, subject: String, expected: String, actual: String) {
+    tracing::warn!("Shadow {experiment} diverged for {subject}");
+    let divergence = Divergence {
+        experiment,
+        subject,
+        expected: truncate(expected),
+        actual: truncate(actual),
+        time: Utc::now(),
+    };
+    let mut divergences = DIVERGENCES.lock().unwrap();
+    if divergences.len() == MAX_DIVERGENCES {
+        divergences.pop_front();
+    }
+    divergences.push_back(divergence);
+}
+
+fn truncate(mut outcome: String) -> String {
+    if let Some((i, _)) = outcome.char_indices().nth(MAX_OUTCOME_LEN) {
+        outcome.truncate(i);
+        outcome.push('…');
+    }
+    outcome
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ShadowStatus {
+    /// Sample rates of the running experiments.
+    experiments: HashMap<String, f64>,
+    /// Recent divergences seen by this server, newest first.
+    divergences: Vec<Divergence>,
+}
+
+#[tracing::instrument(skip(user))]
+async fn get_shadow_handler(Extension(user): Extension<User>) -> ApiResult<Json<ShadowStatus>> {
+    verify_admin(&user)?;
+    Ok(Json(ShadowStatus {
+        experiments: settings().shadow.clone(),
+        divergences: DIVERGENCES.lock().unwrap().iter().rev().cloned().collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn truncate_limits_length() {
+        assert_eq!(truncate("short".to_string()), "short");
+        let long = truncate("é".repeat(MAX_OUTCOME_LEN + 10));
+        assert_eq!(long.chars().count(), MAX_OUTCOME_LEN + 1);
+        assert!(long.ends_with('…'));
+    }
+}
@@ -0,0 +1,392 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{
+            Collab,
+            projects_state::DocBox,
+            txn_origin::{Actor, YOrigin},
+        },
+        google::User,
+        model::{
+            CreateSnapshot, DiffTask, Graph, MovedTask, ProjectDiff, ProjectId, ProjectSnapshot,
+            RenamedTask, RestatusedTask, Task,
+        },
+        not_found_error, unauthorized_error, verify_project_access,
+        yproxy::YDocProxy,
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    routing::{delete, get},
+};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use uuid::Uuid;
+use yrs::{ReadTxn as _, StateVector, Update, updates::decoder::Decode as _};
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route(
+            "/{project_id}/snapshots",
+            get(list_snapshots_handler).post(create_snapshot_handler),
+        )
+        .route(
+            "/{project_id}/snapshots/{snapshot_id}",
+            delete(delete_snapshot_handler),
+        )
+        .route("/{project_id}/diff", get(diff_handler))
+}
+
+/// Refers to the live doc rather than a stored snapshot.
+const NOW: &str = "now";
+
+#[tracing::instrument(skip(user, pool))]
+async fn list_snapshots_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Vec<ProjectSnapshot>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let snapshots: Vec<ProjectSnapshot> = sqlx::query_as(
+        "
+        SELECT id, name, creator, create_time
+        FROM project_snapshots
+        WHERE project_id = $1
+        ORDER BY create_time DESC",
+    )
+    .bind(&project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list snapshots")?;
+    Ok(Json(snapshots))
+}
+
+#[tracing::instrument(skip(user, pool, collab))]
+async fn create_snapshot_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+    Json(snapshot): Json<CreateSnapshot>,
+) -> ApiResult<Json<ProjectSnapshot>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    validate_snapshot_name(&snapshot.name)?;
+
+    let update = {
+        let client = collab.register_local_client(&project_id).await?;
+        let doc_box = client.project.doc_box.lock().await;
+        DocBox::doc_or_error(doc_box.as_ref())?
+            .ydoc
+            .transact()
+            .encode_state_as_update_v2(&StateVector::default())
+    };
+    let created: ProjectSnapshot = sqlx::query_as(
+        "
+        INSERT INTO project_snapshots (id, project_id, name, creator, update_v2)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, name, creator, create_time",
+    )
+    .bind(BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4()))
+    .bind(&project_id)
+    .bind(snapshot.name.trim())
+    .bind(&user.email)
+    .bind(update)
+    .fetch_one(pool)
+    .await
+    .context("Failed to insert snapshot")?;
+    Ok(Json(created))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_snapshot_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, snapshot_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<()>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let creator: Option<(String,)> =
+        sqlx::query_as("SELECT creator FROM project_snapshots WHERE project_id = $1 AND id = $2")
+            .bind(&project_id)
+            .bind(&snapshot_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to fetch snapshot")?;
+    let Some((creator,)) = creator else {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Snapshot {snapshot_id} not found"),
+        ));
+    };
+    if creator != user.email {
+        return Err(unauthorized_error(&format!(
+            "User {} cannot delete snapshot {snapshot_id}",
+            user.email
+        )));
+    }
+    sqlx::query("DELETE FROM project_snapshots WHERE id = $1")
+        .bind(&snapshot_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete snapshot")?;
+    Ok(Json(()))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct DiffQuery {
+    from: String,
+    /// A snapshot id or "now".
+    to: Option<String>,
+}
+
+/// Compares the project's tasks between two snapshots, or a snapshot and now.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn diff_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+    Query(query): Query<DiffQuery>,
+) -> ApiResult<Json<ProjectDiff>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let before = load_graph(pool, &collab, &project_id, &query.from).await?;
+    let after = load_graph(
+        pool,
+        &collab,
+        &project_id,
+        query.to.as_deref().unwrap_or(NOW),
+    )
+    .await?;
+    Ok(Json(diff_graphs(&before, &after)))
+}
+
+/// Materializes the project's graph as of the given snapshot, or "now".
+pub(crate) async fn load_graph(
+    pool: &PgPool,
+    collab: &Collab,
+    project_id: &ProjectId,
+    snapshot_id: &str,
+) -> ApiResult<Graph> {
+    if snapshot_id == NOW {
+        let client = collab.register_local_client(project_id).await?;
+        let doc_box = client.project.doc_box.lock().await;
+        let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+        return Ok(doc.to_graph(&doc.transact())?);
+    }
+
+    let update: Option<(Vec<u8>,)> =
+        sqlx::query_as("SELECT update_v2 FROM project_snapshots WHERE project_id = $1 AND id = $2")
+            .bind(project_id)
+            .bind(snapshot_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to fetch snapshot")?;
+    let Some((update,)) = update else {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Snapshot {snapshot_id} not found"),
+        ));
+    };
+    Ok(graph_from_update(&update)?)
+}
+
+fn graph_from_update(update: &[u8]) -> Result<Graph> {
+    let ydoc = YDocProxy::new();
+    let mut txn = ydoc.transact_mut_with(
+        YOrigin {
+            who: "snapshot".to_string(),
+            id: "load_snapshot".to_string(),
+            actor: Actor::Server,
+            ..Default::default()
+        }
+        .as_origin()?,
+    );
+    txn.apply_update(Update::decode_v2(update)?)?;
+    ydoc.to_graph(&txn)
+}
+
+/// Describes how tasks were added, removed, renamed, re-statused or moved
+/// between the two graphs. Each list is ordered by task id.
+pub(crate) fn diff_graphs(before: &Graph, after: &Graph) -> ProjectDiff {
+    let before_parents = parents(before);
+    let after_parents = parents(after);
+    let ids: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+
+    let mut diff = ProjectDiff::default();
+    for id in ids {
+        let (before_task, after_task) = match (before.get(id), after.get(id)) {
+            (None, Some(task)) => {
+                diff.added.push(diff_task(task));
+                continue;
+            }
+            (Some(task), None) => {
+                diff.removed.push(diff_task(task));
+                continue;
+            }
+            (Some(before_task), Some(after_task)) => (before_task, after_task),
+            (None, None) => continue,
+        };
+
+        if before_task.name != after_task.name {
+            diff.renamed.push(RenamedTask {
+                task_id: id.clone(),
+                num: after_task.num.clone(),
+                from: before_task.name.clone(),
+                to: after_task.name.clone(),
+            });
+        }
+        if before_task.status != after_task.status {
+            diff.restatused.push(RestatusedTask {
+                task_id: id.clone(),
+                num: after_task.num.clone(),
+                name: after_task.name.clone(),
+                from: before_task.status.clone(),
+                to: after_task.status.clone(),
+            });
+        }
+        let from = before_parents.get(id.as_str()).cloned().unwrap_or_default();
+        let to = after_parents.get(id.as_str()).cloned().unwrap_or_default();
+        if from != to {
+            diff.moved.push(MovedTask {
+                task_id: id.clone(),
+                num: after_task.num.clone(),
+                name: after_task.name.clone(),
+                from,
+                to,
+            });
+        }
+    }
+    diff
+}
+
+fn diff_task(task: &Task) -> DiffTask {
+    DiffTask {
+        task_id: task.id.clone(),
+        num: task.num.clone(),
+        name: task.name.clone(),
+    }
+}
+
+/// Maps each task to its parents' ids, sorted.
+fn parents(graph: &Graph) -> HashMap<&str, Vec<String>> {
+    let mut parents: HashMap<&str, Vec<String>> = HashMap::new();
+    for task in graph.values() {
+        for child in &task.children {
+            parents
+                .entry(child.as_str())
+                .or_default()
+                .push(task.id.clone());
+        }
+    }
+    for task_parents in parents.values_mut() {
+        task_parents.sort();
+    }
+    parents
+}
+
+fn validate_snapshot_name(name: &str) -> ApiResult<()> {
+    if name.trim().is_empty() {
+        return Err(bad_request_error("EMPTY_NAME", "Snapshot name is blank"));
+    }
+    const MAX_NAME_LEN: usize = 36;
+    if name.trim().len() > MAX_NAME_LEN {
+        return Err(bad_request_error(
+            "LONG_NAME",
+            &format!("Snapshot name cannot be longer than {MAX_NAME_LEN} characters"),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::model::test_utils;
+
+    #[test]
+    fn diff_graphs_describes_changes() {
+        let before = test_utils::graph([
+            test_utils::task("root", "root", &["1", "2", "3"]),
+            Task {
+                name: "One".to_string(),
+                ..test_utils::task("1", "1", &[])
+            },
+            Task {
+                name: "Two".to_string(),
+                status: Some("In Progress".to_string()),
+                ..test_utils::task("2", "2", &["4"])
+            },
+            Task {
+                name: "Three".to_string(),
+                ..test_utils::task("3", "3", &[])
+            },
+            Task {
+                name: "Four".to_string(),
+                ..test_utils::task("4", "4", &[])
+            },
+        ]);
+        let after = test_utils::graph([
+            test_utils::task("root", "root", &["1", "2", "5"]),
+            Task {
+                name: "One!".to_string(),
+                ..test_utils::task("1", "1", &["4"])
+            },
+            Task {
+                name: "Two".to_string(),
+                status: Some("Done".to_string()),
+                ..test_utils::task("2", "2", &[])
+            },
+            Task {
+                name: "Four".to_string(),
+                ..test_utils::task("4", "4", &[])
+            },
+            Task {
+                name: "Five".to_string(),
+                ..test_utils::task("5", "5", &[])
+            },
+        ]);
+
+        let diff = diff_graphs(&before, &after);
+        assert_eq!(
+            diff.added
+                .iter()
+                .map(|t| t.task_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["5"]
+        );
+        assert_eq!(
+            diff.removed
+                .iter()
+                .map(|t| t.task_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["3"]
+        );
+        assert_eq!(
+            diff.renamed,
+            vec![RenamedTask {
+                task_id: "1".into(),
+                num: "1".into(),
+                from: "One".into(),
+                to: "One!".into(),
+            }]
+        );
+        assert_eq!(
+            diff.restatused
+                .iter()
+                .map(|t| (t.task_id.as_str(), t.from.as_deref(), t.to.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![("2", Some("In Progress"), Some("Done"))]
+        );
+        assert_eq!(
+            diff.moved
+                .iter()
+                .map(|t| (t.task_id.as_str(), t.from.clone(), t.to.clone()))
+                .collect::<Vec<_>>(),
+            vec![("4", vec!["2".to_string()], vec!["1".to_string()])]
+        );
+    }
+}
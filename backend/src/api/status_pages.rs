@@ -0,0 +1,506 @@
+//! Public status pages, e.g. for communicating roadmap progress to customers.
+//! A project publishes selected milestones at `/status/{slug}`, rendered
+//! server-side from the milestones and `task_projections` tables rather than
+//! the doc, with per-project theming.
+
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        google::User,
+        milestones::health_from_str,
+        model::{MilestoneHealth, ProjectId, TaskProgress, WorkflowCategory},
+        not_found_error,
+        reports::escape_html,
+        verify_project_admin,
+        yproxy::status_category,
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    http::header::{CACHE_CONTROL, CONTENT_SECURITY_POLICY, CONTENT_TYPE},
+    response::{IntoResponse as _, Response},
+    routing::get,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    FromRow,
+    types::{
+        Json as SqlJson,
+        chrono::{DateTime, NaiveDate, Utc},
+    },
+};
+
+const MAX_TITLE_LEN: usize = 200;
+const MAX_INTRO_LEN: usize = 1000;
+const MAX_MILESTONES: usize = 20;
+const PAGE_CACHE_CONTROL: &str = "public, max-age=300";
+/// Pages load nothing but their inline styles.
+const PAGE_CSP: &str = "default-src 'none'; style-src 'unsafe-inline'";
+
+/// Routes for project admins to configure the project's status page.
+pub(super) fn router() -> Router {
+    Router::new().route(
+        "/{project_id}/status-page",
+        get(get_page_handler)
+            .put(set_page_handler)
+            .delete(delete_page_handler),
+    )
+}
+
+/// Unauthenticated routes serving published pages.
+pub(crate) fn public_router() -> Router {
+    Router::new().route("/{slug}", get(public_page_handler))
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Theme {
+    /// A hex color, e.g. `#2563eb`, for headings and progress bars.
+    accent_color: String,
+    dark: bool,
+    /// Plain text shown under the title.
+    intro: Option<String>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            accent_color: "#2563eb".to_string(),
+            dark: false,
+            intro: None,
+        }
+    }
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct StatusPage {
+    slug: String,
+    title: String,
+    milestone_ids: SqlJson<Vec<String>>,
+    theme: SqlJson<Theme>,
+    published: bool,
+    update_time: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SetStatusPage {
+    slug: String,
+    title: String,
+    milestone_ids: Vec<String>,
+    #[serde(default)]
+    theme: Theme,
+    published: bool,
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn get_page_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Option<StatusPage>>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let page: Option<StatusPage> = sqlx::query_as(
+        "
+        SELECT slug, title, milestone_ids, theme, published, update_time
+        FROM status_pages
+        WHERE project_id = $1",
+    )
+    .bind(&project_id)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch status page")?;
+    Ok(Json(page))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn set_page_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Json(req): Json<SetStatusPage>,
+) -> ApiResult<Json<StatusPage>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    validate(&req)?;
+    let (milestones,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM milestones WHERE project_id = $1 AND id = ANY($2)")
+            .bind(&project_id)
+            .bind(&req.milestone_ids)
+            .fetch_one(pool)
+            .await
+            .context("Failed to check milestones")?;
+    if milestones as usize != req.milestone_ids.len() {
+        return Err(bad_request_error(
+            "INVALID_MILESTONES",
+            "Milestones must belong to the project",
+        ));
+    }
+    let taken: Option<(String,)> =
+        sqlx::query_as("SELECT project_id FROM status_pages WHERE slug = $1 AND project_id != $2")
+            .bind(&req.slug)
+            .bind(&project_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to check status page slug")?;
+    if taken.is_some() {
+        return Err(bad_request_error(
+            "SLUG_TAKEN",
+            &format!("The status page {} already exists", req.slug),
+        ));
+    }
+
+    let page: StatusPage = sqlx::query_as(
+        "
+        INSERT INTO status_pages (project_id, slug, title, milestone_ids, theme, published, editor)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (project_id)
+        DO UPDATE SET
+            slug = EXCLUDED.slug,
+            title = EXCLUDED.title,
+            milestone_ids = EXCLUDED.milestone_ids,
+            theme = EXCLUDED.theme,
+            published = EXCLUDED.published,
+            editor = EXCLUDED.editor,
+            update_time = NOW()
+        RETURNING slug, title, milestone_ids, theme, published, update_time",
+    )
+    .bind(&project_id)
+    .bind(&req.slug)
+    .bind(req.title.trim())
+    .bind(SqlJson(&req.milestone_ids))
+    .bind(SqlJson(&req.theme))
+    .bind(req.published)
+    .bind(&user.email)
+    .fetch_one(pool)
+    .await
+    .context("Failed to set status page")?;
+    Ok(Json(page))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_page_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<()>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    sqlx::query("DELETE FROM status_pages WHERE project_id = $1")
+        .bind(&project_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete status page")?;
+    Ok(Json(()))
+}
+
+fn validate(req: &SetStatusPage) -> ApiResult<()> {
+    if !(3..=64).contains(&req.slug.len())
+        || !req
+            .slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(bad_request_error(
+            "INVALID_SLUG",
+            "Slugs are 3 to 64 lowercase letters, digits or dashes",
+        ));
+    }
+    if req.title.trim().is_empty() || req.title.trim().len() > MAX_TITLE_LEN {
+        return Err(bad_request_error(
+            "INVALID_TITLE",
+            &format!("Titles are 1 to {MAX_TITLE_LEN} characters"),
+        ));
+    }
+    if req.milestone_ids.len() > MAX_MILESTONES {
+        return Err(bad_request_error(
+            "TOO_MANY_MILESTONES",
+            &format!("Status pages show at most {MAX_MILESTONES} milestones"),
+        ));
+    }
+    let color = &req.theme.accent_color;
+    if !(color.len() == 4 || color.len() == 7)
+        || !color.starts_with('#')
+        || !color[1..].chars().all(|c| c.is_ascii_hexdigit())
+    {
+        return Err(bad_request_error(
+            "INVALID_COLOR",
+            "Accent colors are hex colors, e.g. #2563eb",
+        ));
+    }
+    if req
+        .theme
+        .intro
+        .as_ref()
+        .is_some_and(|intro| intro.len() > MAX_INTRO_LEN)
+    {
+        return Err(bad_request_error(
+            "LONG_INTRO",
+            &format!("Intros cannot be longer than {MAX_INTRO_LEN} characters"),
+        ));
+    }
+    Ok(())
+}
+
+/// A published milestone and its tasks.
+struct PageMilestone {
+    name: String,
+    target_date: NaiveDate,
+    health: Option<MilestoneHealth>,
+    tasks: Vec<PageTask>,
+}
+
+/// A published page, looked up by its slug.
+#[derive(FromRow)]
+struct PublicPage {
+    project_id: ProjectId,
+    title: String,
+    milestone_ids: SqlJson<Vec<String>>,
+    theme: SqlJson<Theme>,
+    update_time: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct MilestoneRow {
+    name: String,
+    target_date: NaiveDate,
+    health: Option<String>,
+    task_ids: SqlJson<Vec<String>>,
+}
+
+#[derive(FromRow)]
+struct PageTask {
+    task_id: String,
+    name: String,
+    status: Option<String>,
+    update_time: DateTime<Utc>,
+}
+
+impl PageMilestone {
+    /// Projections don't record subtasks, so progress counts the milestone's
+    /// tasks themselves.
+    fn progress(&self) -> TaskProgress {
+        TaskProgress {
+            done: self
+                .tasks
+                .iter()
+                .filter(|t| {
+                    t.status.as_deref().and_then(|s| status_category(&[], s))
+                        == Some(WorkflowCategory::Done)
+                })
+                .count() as u64,
+            total: self.tasks.len() as u64,
+        }
+    }
+}
+
+#[tracing::instrument(skip(pool))]
+async fn public_page_handler(
+    Extension(pool): Extension<&'static PgPool>,
+    Path(slug): Path<String>,
+) -> ApiResult<Response> {
+    let page: Option<PublicPage> = sqlx::query_as(
+        "
+            SELECT s.project_id, s.title, s.milestone_ids, s.theme, s.update_time
+            FROM status_pages s
+            JOIN projects p ON p.project_id = s.project_id
+            WHERE s.slug = $1 AND s.published AND p.deleted_on IS NULL",
+    )
+    .bind(&slug)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch status page")?;
+    let Some(page) = page else {
+        return Err(not_found_error("NOT_FOUND", "No such status page"));
+    };
+    let milestones = load_milestones(pool, &page.project_id, &page.milestone_ids).await?;
+    let update_time = page.update_time;
+    let last_updated = milestones
+        .iter()
+        .flat_map(|m| m.tasks.iter().map(|t| t.update_time))
+        .chain([update_time])
+        .max()
+        .unwrap_or(update_time);
+    Ok((
+        [
+            (CONTENT_TYPE, "text/html; charset=utf-8"),
+            (CACHE_CONTROL, PAGE_CACHE_CONTROL),
+            (CONTENT_SECURITY_POLICY, PAGE_CSP),
+        ],
+        render_html(&page.title, &page.theme, &milestones, last_updated),
+    )
+        .into_response())
+}
+
+async fn load_milestones(
+    pool: &PgPool,
+    project_id: &ProjectId,
+    milestone_ids: &[String],
+) -> Result<Vec<PageMilestone>> {
+    let rows: Vec<MilestoneRow> = sqlx::query_as(
+        "
+        SELECT name, target_date, health, task_ids
+        FROM milestones
+        WHERE project_id = $1 AND id = ANY($2)
+        ORDER BY target_date, create_time",
+    )
+    .bind(project_id)
+    .bind(milestone_ids)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list status page milestones")?;
+    let task_ids: Vec<String> = rows.iter().flat_map(|r| r.task_ids.0.clone()).collect();
+    let tasks: Vec<PageTask> = sqlx::query_as(
+        "
+        SELECT task_id, name, status, update_time
+        FROM task_projections
+        WHERE project_id = $1 AND task_id = ANY($2) AND NOT archived",
+    )
+    .bind(project_id)
+    .bind(&task_ids)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list status page tasks")?;
+
+    let mut tasks: std::collections::HashMap<String, PageTask> =
+        tasks.into_iter().map(|t| (t.task_id.clone(), t)).collect();
+    Ok(rows
+        .into_iter()
+        .map(|row| PageMilestone {
+            name: row.name,
+            target_date: row.target_date,
+            health: row.health.as_deref().and_then(health_from_str),
+            tasks: row
+                .task_ids
+                .0
+                .iter()
+                .filter_map(|id| tasks.remove(id))
+                .collect(),
+        })
+        .collect())
+}
+
+fn health_label(health: MilestoneHealth) -> &'static str {
+    match health {
+        MilestoneHealth::Complete => "Complete",
+        MilestoneHealth::OnTrack => "On track",
+        MilestoneHealth::AtRisk => "At risk",
+        MilestoneHealth::OffTrack => "Delayed",
+        MilestoneHealth::Unknown => "Planned",
+    }
+}
+
+fn render_html(
+    title: &str,
+    theme: &Theme,
+    milestones: &[PageMilestone],
+    last_updated: DateTime<Utc>,
+) -> String {
+    let (background, text) = if theme.dark {
+        ("#111827", "#f3f4f6")
+    } else {
+        ("#ffffff", "#111827")
+    };
+    let accent = escape_html(&theme.accent_color);
+    let title = escape_html(title);
+    let mut out = format!(
+        "<!doctype html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{title}</title><style>\
+         body{{font:15px system-ui,sans-serif;margin:0 auto;max-width:720px;padding:24px;background:{background};color:{text}}}\
+         h1,h2{{color:{accent}}}ul{{padding-left:20px}}.muted{{opacity:.7;font-size:13px}}\
+         .bar{{height:8px;border-radius:4px;background:rgba(127,127,127,.25)}}\
+         .bar div{{height:8px;border-radius:4px;background:{accent}}}\
+         </style></head><body><h1>{title}</h1>"
+    );
+    if let Some(intro) = &theme.intro {
+        out.push_str(&format!("<p>{}</p>", escape_html(intro)));
+    }
+    for milestone in milestones {
+        let progress = milestone.progress();
+        let percent = (progress.done * 100)
+            .checked_div(progress.total)
+            .unwrap_or(0);
+        out.push_str(&format!(
+            "<section><h2>{}</h2><p class=\"muted\">Target {}{} &middot; {}/{} done</p>\
+             <div class=\"bar\"><div style=\"width:{percent}%\"></div></div><ul>",
+            escape_html(&milestone.name),
+            milestone.target_date.format("%B %-d, %Y"),
+            milestone
+                .health
+                .map(|h| format!(" &middot; {}", health_label(h)))
+                .unwrap_or_default(),
+            progress.done,
+            progress.total,
+        ));
+        for task in &milestone.tasks {
+            out.push_str(&format!(
+                "<li>{} <span class=\"muted\">{}</span></li>",
+                escape_html(&task.name),
+                escape_html(task.status.as_deref().unwrap_or("Not Started")),
+            ));
+        }
+        out.push_str("</ul></section>");
+    }
+    out.push_str(&format!(
+        "<p class=\"muted\">Last updated {}</p></body></html>",
+        last_updated.format("%B %-d, %Y %H:%M UTC")
+    ));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::types::chrono::TimeZone as _;
+
+    #[test_log::test]
+    fn renders_page() {
+        let time = Utc.with_ymd_and_hms(2025, 9, 1, 12, 30, 0).unwrap();
+        let task = |num: &str, status: Option<&str>| PageTask {
+            task_id: num.to_string(),
+            name: format!("Task <{num}>"),
+            status: status.map(String::from),
+            update_time: time,
+        };
+        let milestones = [PageMilestone {
+            name: "Beta".to_string(),
+            target_date: NaiveDate::from_ymd_opt(2025, 10, 1).unwrap(),
+            health: Some(MilestoneHealth::AtRisk),
+            tasks: vec![task("1", Some("Done")), task("2", None)],
+        }];
+        assert_eq!(milestones[0].progress(), TaskProgress { done: 1, total: 2 });
+
+        let theme = Theme {
+            intro: Some("Our <roadmap>".to_string()),
+            ..Theme::default()
+        };
+        let html = render_html("Koso & co", &theme, &milestones, time);
+        assert!(html.contains("<h1>Koso &amp; co</h1>"));
+        assert!(html.contains("<p>Our &lt;roadmap&gt;</p>"));
+        assert!(html.contains("Target October 1, 2025 &middot; At risk &middot; 1/2 done"));
+        assert!(html.contains("width:50%"));
+        assert!(html.contains("Task &lt;2&gt; <span class=\"muted\">Not Started</span>"));
+        assert!(html.contains("Last updated September 1, 2025 12:30 UTC"));
+    }
+
+    #[test_log::test]
+    fn validates_page() {
+        let req = |slug: &str, color: &str| SetStatusPage {
+            slug: slug.to_string(),
+            title: "Roadmap".to_string(),
+            milestone_ids: vec![],
+            theme: Theme {
+                accent_color: color.to_string(),
+                ..Theme::default()
+            },
+            published: true,
+        };
+        assert!(validate(&req("koso-roadmap", "#abc")).is_ok());
+        assert!(validate(&req("Koso", "#abc")).is_err());
+        assert!(validate(&req("koso", "red")).is_err());
+        assert!(validate(&req("koso", "#abc;}")).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{
+    api::{
+        ApiResult, error_response,
+        google::User,
+        hash_token,
+        model::{StepUp, StepUpNonce},
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    body::Body,
+    extract::Request,
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::Response,
+    routing::post,
+};
+use chrono::{DateTime, TimeDelta, Utc};
+use uuid::Uuid;
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/stepUp", post(step_up_handler))
+        .route("/stepUp/nonce", post(nonce_handler))
+}
+
+/// Header carrying the step-up token on sensitive requests.
+pub(crate) const STEP_UP_HEADER: &str = "x-step-up-token";
+/// How recently the user must have authenticated to step up.
+const MAX_AUTH_AGE: TimeDelta = TimeDelta::minutes(5);
+/// How long sensitive operations are allowed after stepping up.
+const STEP_UP_DURATION: TimeDelta = TimeDelta::minutes(10);
+
+/// Issues a nonce for the client to pass to the identity provider when it
+/// re-authenticates the user, so the credential it steps up with is provably fresh.
+#[tracing::instrument(skip(user, pool))]
+async fn nonce_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<StepUpNonce>> {
+    let nonce = Uuid::new_v4().simple().to_string();
+    let mut txn = pool.begin().await?;
+    sqlx::query("DELETE FROM step_up_nonces WHERE email = $1 AND expire_time <= NOW()")
+        .bind(&user.email)
+        .execute(&mut *txn)
+        .await
+        .context("Failed to delete expired step up nonces")?;
+    sqlx::query(
+        "
+        INSERT INTO step_up_nonces (nonce_hash, email, expire_time)
+        VALUES ($1, $2, $3)",
+    )
+    .bind(hash_token(&nonce))
+    .bind(&user.email)
+    .bind(Utc::now() + MAX_AUTH_AGE)
+    .execute(&mut *txn)
+    .await
+    .context("Failed to record step up nonce")?;
+    txn.commit().await?;
+    Ok(Json(StepUpNonce { nonce }))
+}
+
+/// Grants the session access to sensitive operations for a short while.
+/// Clients should re-authenticate the user with their identity provider,
+/// passing a nonce from `/stepUp/nonce`, and call this with the fresh
+/// credential. Sensitive requests must then carry the returned token in the
+/// `x-step-up-token` header, so the user's other sessions aren't granted access.
+#[tracing::instrument(skip(user, pool))]
+async fn step_up_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<StepUp>> {
+    if !reauthenticated(pool, &user).await? {
+        return Err(error_response(
+            StatusCode::UNAUTHORIZED,
+            "REAUTH_REQUIRED",
+            Some(&format!(
+                "Credential for {} doesn't prove a recent sign in: auth_time {:?}, nonce {}",
+                user.email,
+                user.auth_time,
+                user.nonce.is_some()
+            )),
+            None,
+        ));
+    }
+
+    let token = format!("ksu_{}", Uuid::new_v4().simple());
+    let expire_time = Utc::now() + STEP_UP_DURATION;
+    let mut txn = pool.begin().await?;
+    sqlx::query("DELETE FROM step_ups WHERE email = $1 AND expire_time <= NOW()")
+        .bind(&user.email)
+        .execute(&mut *txn)
+        .await
+        .context("Failed to delete expired step ups")?;
+    sqlx::query(
+        "
+        INSERT INTO step_ups (token_hash, email, expire_time)
+        VALUES ($1, $2, $3)",
+    )
+    .bind(hash_token(&token))
+    .bind(&user.email)
+    .bind(expire_time)
+    .execute(&mut *txn)
+    .await
+    .context("Failed to record step up")?;
+    txn.commit().await?;
+    Ok(Json(StepUp { token, expire_time }))
+}
+
+/// Whether the credential proves the user just authenticated: either the
+/// identity provider says they did or it carries a nonce issued moments ago.
+/// A token's `iat` proves nothing, as refreshing the token advances it.
+async fn reauthenticated(pool: &PgPool, user: &User) -> Result<bool> {
+    let now = Utc::now();
+    let auth_time = user
+        .auth_time
+        .and_then(|auth_time| DateTime::from_timestamp(auth_time as i64, 0));
+    if auth_time.is_some_and(|auth_time| now - auth_time <= MAX_AUTH_AGE) {
+        return Ok(true);
+    }
+    let Some(nonce) = &user.nonce else {
+        return Ok(false);
+    };
+    // Nonces are single use, so a credential can't step up more than once.
+    let res = sqlx::query(
+        "
+        DELETE FROM step_up_nonces
+        WHERE nonce_hash = $1 AND email = $2 AND expire_time > NOW()",
+    )
+    .bind(hash_token(nonce))
+    .bind(&user.email)
+    .execute(pool)
+    .await
+    .context("Failed to consume step up nonce")?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Middleware requiring that the session recently stepped up.
+pub(crate) async fn require_step_up(request: Request, next: Next) -> ApiResult<Response<Body>> {
+    let user = request.extensions().get::<User>().unwrap();
+    let pool = *request.extensions().get::<&'static PgPool>().unwrap();
+    verify_step_up(pool, user, request.headers()).await?;
+    Ok(next.run(request).await)
+}
+
+/// Fails with STEP_UP_REQUIRED unless the session recently stepped up.
+/// Prefer the `require_step_up` middleware unless only some requests to a route are sensitive.
+pub(crate) async fn verify_step_up(
+    pool: &PgPool,
+    user: &User,
+    headers: &HeaderMap,
+) -> ApiResult<()> {
+    let token = headers
+        .get(STEP_UP_HEADER)
+        .and_then(|token| token.to_str().ok());
+    let stepped_up = match token {
+        Some(token) => has_stepped_up(pool, &user.email, token).await?,
+        None => false,
+    };
+    if !stepped_up {
+        return Err(error_response(
+            StatusCode::FORBIDDEN,
+            "STEP_UP_REQUIRED",
+            Some(&format!("User {} must re-authenticate", user.email)),
+            None,
+        ));
+    }
+    Ok(())
+}
+
+async fn has_stepped_up(pool: &PgPool, email: &str, token: &str) -> Result<bool> {
+    let step_up: Option<(DateTime<Utc>,)> = sqlx::query_as(
+        "
+        SELECT expire_time FROM step_ups
+        WHERE token_hash = $1 AND email = $2 AND expire_time > NOW()",
+    )
+    .bind(hash_token(token))
+    .bind(email)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to check step up")?;
+    Ok(step_up.is_some())
+}
@@ -0,0 +1,156 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        google::User,
+        model::{ChangeFeed, ChangeRecord, ProjectId, TaskChange, UpdateRecord},
+        verify_project_access, verify_project_admin,
+    },
+    postgres::PgPool,
+};
+use anyhow::Context as _;
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    routing::get,
+};
+use serde::Deserialize;
+use sqlx::types::{
+    Json as SqlJson,
+    chrono::{DateTime, Utc},
+};
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/{project_id}/transactions", get(list_transactions_handler))
+        .route("/{project_id}/changes", get(list_changes_handler))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ListTransactionsQuery {
+    /// Only include updates made by this user.
+    email: Option<String>,
+    /// Only include updates generated by this feature.
+    feature: Option<String>,
+    /// Only include updates stored before this time.
+    before: Option<DateTime<Utc>>,
+    limit: Option<i64>,
+}
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+/// Lists metadata about the project's stored updates, newest first.
+#[tracing::instrument(skip(user, pool))]
+async fn list_transactions_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Query(query): Query<ListTransactionsQuery>,
+) -> ApiResult<Json<Vec<UpdateRecord>>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(bad_request_error(
+            "INVALID_LIMIT",
+            &format!("Limit must be between 1 and {MAX_LIMIT}"),
+        ));
+    }
+
+    let records: Vec<UpdateRecord> = sqlx::query_as(
+        "
+        SELECT seq, txn_id, who, actor, email, device, request_id, feature, update_len, create_time
+        FROM yupdate_metadata
+        WHERE project_id = $1
+        AND ($2::varchar IS NULL OR email = $2)
+        AND ($3::varchar IS NULL OR feature = $3)
+        AND ($4::timestamptz IS NULL OR create_time < $4)
+        ORDER BY seq DESC
+        LIMIT $5",
+    )
+    .bind(&project_id)
+    .bind(query.email.map(|e| e.to_lowercase()))
+    .bind(&query.feature)
+    .bind(query.before)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list transactions")?;
+    Ok(Json(records))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ListChangesQuery {
+    /// Only include updates with a greater sequence number.
+    #[serde(default)]
+    since: i32,
+    limit: Option<i64>,
+}
+
+/// Lists the task changes made after `since`, oldest first, so integrations
+/// can stay in sync by polling instead of holding a websocket open.
+#[tracing::instrument(skip(user, pool))]
+async fn list_changes_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Query(query): Query<ListChangesQuery>,
+) -> ApiResult<Json<ChangeFeed>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(bad_request_error(
+            "INVALID_LIMIT",
+            &format!("Limit must be between 1 and {MAX_LIMIT}"),
+        ));
+    }
+
+    let rows: Vec<ChangeRow> = sqlx::query_as(
+        "
+        SELECT seq, txn_id, actor, email, feature, create_time, changes
+        FROM yupdate_metadata
+        WHERE project_id = $1
+        AND seq > $2
+        AND changes != '[]'::jsonb
+        ORDER BY seq
+        LIMIT $3",
+    )
+    .bind(&project_id)
+    .bind(query.since)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list changes")?;
+
+    let next_since = rows.last().map_or(query.since, |row| row.seq);
+    Ok(Json(ChangeFeed {
+        changes: rows.into_iter().map(ChangeRow::into_record).collect(),
+        next_since,
+    }))
+}
+
+#[derive(sqlx::FromRow)]
+struct ChangeRow {
+    seq: i32,
+    txn_id: String,
+    actor: String,
+    email: Option<String>,
+    feature: String,
+    create_time: DateTime<Utc>,
+    changes: SqlJson<Vec<TaskChange>>,
+}
+
+impl ChangeRow {
+    fn into_record(self) -> ChangeRecord {
+        ChangeRecord {
+            seq: self.seq,
+            txn_id: self.txn_id,
+            actor: self.actor,
+            email: self.email,
+            feature: self.feature,
+            create_time: self.create_time,
+            changes: self.changes.0,
+        }
+    }
+}
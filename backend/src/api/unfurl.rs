@@ -0,0 +1,489 @@
+//! Link previews for tasks' URLs, unfurled server side from OpenGraph metadata
+//! so clients don't request arbitrary sites themselves. Preview images and
+//! favicons are served through a proxy that only serves images of cached
+//! previews, and caches the images too. Pages and images are fetched with
+//! `outbound::Fetch`.
+
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{Collab, projects_state::DocBox},
+        google::User,
+        model::ProjectId,
+        not_found_error, truncate, verify_project_access,
+    },
+    notifiers::plain::html_to_text,
+    outbound::Fetch,
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    body::Body,
+    extract::{Path, Query},
+    http::header::{CACHE_CONTROL, CONTENT_SECURITY_POLICY, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS},
+    response::{IntoResponse as _, Response},
+    routing::get,
+};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use regex::Regex;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::{sync::LazyLock, time::Duration};
+
+/// Links unfurled per task, starting with its URL.
+const MAX_LINKS: usize = 5;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Only the head of a page is needed for its metadata.
+const MAX_PAGE_LEN: usize = 512 * 1024;
+const MAX_IMAGE_LEN: usize = 2 * 1024 * 1024;
+const MAX_TITLE_CHARS: usize = 200;
+const MAX_DESCRIPTION_CHARS: usize = 500;
+/// SVG is excluded as it may carry scripts.
+const IMAGE_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/x-icon",
+    "image/vnd.microsoft.icon",
+];
+const IMAGE_CACHE_CONTROL: &str = "public, max-age=86400";
+
+static URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"https?://[^\s<>()\[\]"'`]+"#).unwrap());
+static META: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)<meta\s[^>]*>").unwrap());
+static LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)<link\s[^>]*>").unwrap());
+static TITLE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap());
+static ATTR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)\b([a-z:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+
+/// Routes for unfurling a task's links.
+pub(super) fn router() -> Router {
+    Router::new().route(
+        "/{project_id}/tasks/{task_id}/unfurl",
+        get(unfurl_task_handler),
+    )
+}
+
+/// Routes for proxied preview images, loaded by browsers in `img` tags
+/// without credentials.
+pub(super) fn image_router() -> Router {
+    Router::new().route("/image", get(image_handler))
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+struct LinkPreview {
+    url: String,
+    title: Option<String>,
+    description: Option<String>,
+    site_name: Option<String>,
+    /// Path of the proxied preview image.
+    image: Option<String>,
+    /// Path of the proxied favicon.
+    favicon: Option<String>,
+}
+
+/// A page's metadata, as cached in `link_previews`.
+#[derive(FromRow, Debug, Default, PartialEq, Eq)]
+struct Metadata {
+    title: Option<String>,
+    description: Option<String>,
+    site_name: Option<String>,
+    image_url: Option<String>,
+    favicon_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ImageQuery {
+    url: String,
+}
+
+/// Previews the task's URL and links in its description. Links that can't
+/// be unfurled are left out.
+#[tracing::instrument(skip(user, pool, collab))]
+async fn unfurl_task_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path((project_id, task_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<Vec<LinkPreview>>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let (url, desc) = {
+        let client = collab.register_local_client(&project_id).await?;
+        let doc_box = client.project.doc_box.lock().await;
+        let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+        let txn = doc.transact();
+        let Ok(task) = doc.get(&txn, &task_id) else {
+            return Err(not_found_error(
+                "NOT_FOUND",
+                &format!("Task {task_id} not found"),
+            ));
+        };
+        (task.get_url(&txn)?, task.get_desc(&txn)?)
+    };
+
+    let links = links(url.as_deref(), desc.as_deref());
+    let previews = futures::future::join_all(links.iter().map(|url| preview(pool, url))).await;
+    let mut unfurled = Vec::new();
+    for (url, preview) in links.into_iter().zip(previews) {
+        match preview {
+            Ok(Some(metadata)) => unfurled.push(to_preview(url, metadata)?),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Failed to unfurl {url}: {e:?}"),
+        }
+    }
+    Ok(Json(unfurled))
+}
+
+/// Serves a preview image or favicon. Only images of cached previews are
+/// served, so the proxy can't be used to fetch arbitrary URLs, and images are
+/// cached too, so requests for them can't be used to flood their hosts.
+#[tracing::instrument(skip(pool))]
+async fn image_handler(
+    Extension(pool): Extension<&'static PgPool>,
+    Query(query): Query<ImageQuery>,
+) -> ApiResult<Response> {
+    let (known,): (bool,) = sqlx::query_as(
+        "
+        SELECT EXISTS (
+            SELECT 1 FROM link_previews
+            WHERE ok AND (image_url = $1 OR favicon_url = $1)
+        )",
+    )
+    .bind(&query.url)
+    .fetch_one(pool)
+    .await
+    .context("Failed to look up preview image")?;
+    if !known {
+        return Err(not_found_error("NOT_FOUND", "No such preview image"));
+    }
+    let url = Url::parse(&query.url)
+        .map_err(|_| bad_request_error("INVALID_URL", "Invalid image URL"))?;
+    let Some((content_type, body)) = image(pool, &url).await? else {
+        return Err(not_found_error("NOT_FOUND", "Preview image is unavailable"));
+    };
+    Ok((
+        [
+            (CONTENT_TYPE, content_type.as_str()),
+            (CACHE_CONTROL, IMAGE_CACHE_CONTROL),
+            (X_CONTENT_TYPE_OPTIONS, "nosniff"),
+            (CONTENT_SECURITY_POLICY, "default-src 'none'"),
+        ],
+        Body::from(body),
+    )
+        .into_response())
+}
+
+/// The URLs to unfurl: the task's URL, then those in its description.
+fn links(url: Option<&str>, desc: Option<&str>) -> Vec<String> {
+    let mut links: Vec<String> = Vec::new();
+    let found = desc.into_iter().flat_map(|desc| {
+        URL.find_iter(desc)
+            .map(|m| m.as_str().trim_end_matches(['.', ',', ';', ':', '!', '?']))
+    });
+    for link in url.into_iter().chain(found) {
+        if links.len() == MAX_LINKS {
+            break;
+        }
+        if Url::parse(link).is_ok() && !links.iter().any(|l| l == link) {
+            links.push(link.to_string());
+        }
+    }
+    links
+}
+
+/// Returns the page's metadata, from the cache while it's fresh. Pages that
+/// failed to unfurl are retried after a while.
+async fn preview(pool: &PgPool, url: &str) -> Result<Option<Metadata>> {
+    let cached: Option<(bool, DateTime<Utc>)> =
+        sqlx::query_as("SELECT ok, fetch_time FROM link_previews WHERE url = $1")
+            .bind(url)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to fetch cached preview")?;
+    if !cached.is_some_and(|(ok, fetch_time)| fresh(ok, fetch_time)) {
+        let metadata = match unfurl(url).await {
+            Ok(metadata) => Some(metadata),
+            Err(e) => {
+                tracing::debug!("Failed to unfurl {url}: {e:?}");
+                None
+            }
+        };
+        let metadata = metadata.as_ref();
+        sqlx::query(
+            "
+            INSERT INTO link_previews (url, ok, title, description, site_name, image_url, favicon_url, fetch_time)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
+            ON CONFLICT (url)
+            DO UPDATE SET
+                ok = EXCLUDED.ok,
+                title = EXCLUDED.title,
+                description = EXCLUDED.description,
+                site_name = EXCLUDED.site_name,
+                image_url = EXCLUDED.image_url,
+                favicon_url = EXCLUDED.favicon_url,
+                fetch_time = EXCLUDED.fetch_time",
+        )
+        .bind(url)
+        .bind(metadata.is_some())
+        .bind(metadata.and_then(|m| m.title.as_ref()))
+        .bind(metadata.and_then(|m| m.description.as_ref()))
+        .bind(metadata.and_then(|m| m.site_name.as_ref()))
+        .bind(metadata.and_then(|m| m.image_url.as_ref()))
+        .bind(metadata.and_then(|m| m.favicon_url.as_ref()))
+        .execute(pool)
+        .await
+        .context("Failed to cache preview")?;
+    }
+    sqlx::query_as(
+        "
+        SELECT title, description, site_name, image_url, favicon_url
+        FROM link_previews
+        WHERE url = $1 AND ok",
+    )
+    .bind(url)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch preview")
+}
+
+/// A proxied image, as cached in `link_preview_images`.
+#[derive(FromRow)]
+struct CachedImage {
+    ok: bool,
+    content_type: Option<String>,
+    body: Option<Vec<u8>>,
+    fetch_time: DateTime<Utc>,
+}
+
+/// Returns the image's content type and bytes, from the cache while it's
+/// fresh. Images that failed to fetch are retried after a while.
+async fn image(pool: &PgPool, url: &Url) -> Result<Option<(String, Vec<u8>)>> {
+    let cached: Option<CachedImage> = sqlx::query_as(
+        "SELECT ok, content_type, body, fetch_time FROM link_preview_images WHERE url = $1",
+    )
+    .bind(url.as_str())
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch cached image")?;
+    if let Some(cached) = cached
+        && fresh(cached.ok, cached.fetch_time)
+    {
+        return Ok(cached.content_type.zip(cached.body));
+    }
+
+    let fetched = Fetch::get(url.clone())
+        .timeout(FETCH_TIMEOUT)
+        .max_len(MAX_IMAGE_LEN)
+        .content_types(IMAGE_TYPES)
+        .send()
+        .await
+        .and_then(|fetched| fetched.error_for_status());
+    let image = match fetched {
+        Ok(fetched) => Some((fetched.content_type, fetched.body)),
+        Err(e) => {
+            tracing::debug!("Failed to proxy {url}: {e:?}");
+            None
+        }
+    };
+    sqlx::query(
+        "
+        INSERT INTO link_preview_images (url, ok, content_type, body, fetch_time)
+        VALUES ($1, $2, $3, $4, NOW())
+        ON CONFLICT (url)
+        DO UPDATE SET
+            ok = EXCLUDED.ok,
+            content_type = EXCLUDED.content_type,
+            body = EXCLUDED.body,
+            fetch_time = EXCLUDED.fetch_time",
+    )
+    .bind(url.as_str())
+    .bind(image.is_some())
+    .bind(image.as_ref().map(|(content_type, _)| content_type))
+    .bind(image.as_ref().map(|(_, body)| body))
+    .execute(pool)
+    .await
+    .context("Failed to cache image")?;
+    Ok(image)
+}
+
+/// Whether a cached fetch is still fresh. Failed fetches go stale sooner.
+fn fresh(ok: bool, fetch_time: DateTime<Utc>) -> bool {
+    let max_age = if ok {
+        ChronoDuration::hours(24)
+    } else {
+        ChronoDuration::hours(1)
+    };
+    fetch_time > Utc::now() - max_age
+}
+
+async fn unfurl(url: &str) -> Result<Metadata> {
+    let fetched = Fetch::get(Url::parse(url)?)
+        .timeout(FETCH_TIMEOUT)
+        .max_len(MAX_PAGE_LEN)
+        .truncate()
+        .content_types(&["text/html"])
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(parse_metadata(
+        &fetched.url,
+        &String::from_utf8_lossy(&fetched.body),
+    ))
+}
+
+fn to_preview(url: String, metadata: Metadata) -> Result<LinkPreview> {
+    let proxied = |image: Option<String>| -> Result<Option<String>> {
+        image
+            .map(|url| {
+                Ok(format!(
+                    "/api/unfurl/image?{}",
+                    serde_qs::to_string(&ImageQuery { url })?
+                ))
+            })
+            .transpose()
+    };
+    Ok(LinkPreview {
+        url,
+        title: metadata.title,
+        description: metadata.description,
+        site_name: metadata.site_name,
+        image: proxied(metadata.image_url)?,
+        favicon: proxied(metadata.favicon_url)?,
+    })
+}
+
+/// Extracts OpenGraph metadata, falling back to the page's title and the
+/// site's default favicon. Relative URLs are resolved against the page.
+fn parse_metadata(url: &Url, html: &str) -> Metadata {
+    let mut metadata = Metadata::default();
+    let mut description = None;
+    for tag in META.find_iter(html) {
+        let attrs = attrs(tag.as_str());
+        let key = attr(&attrs, "property").or_else(|| attr(&attrs, "name"));
+        let (Some(key), Some(content)) = (key, attr(&attrs, "content")) else {
+            continue;
+        };
+        let text = html_to_text(content);
+        match key.to_ascii_lowercase().as_str() {
+            "og:title" => metadata.title = Some(text),
+            "og:description" => metadata.description = Some(text),
+            "og:site_name" => metadata.site_name = Some(ellipsize(&text, MAX_TITLE_CHARS)),
+            "og:image" | "og:image:url" if metadata.image_url.is_none() => {
+                metadata.image_url = resolve(url, Some(content))
+            }
+            "description" => description = Some(text),
+            _ => {}
+        }
+    }
+    metadata.title = metadata
+        .title
+        .or_else(|| {
+            TITLE
+                .captures(html)
+                .map(|c| html_to_text(c[1].trim()))
+                .filter(|t| !t.is_empty())
+        })
+        .map(|t| ellipsize(&t, MAX_TITLE_CHARS));
+    metadata.description = metadata
+        .description
+        .or(description)
+        .map(|d| ellipsize(&d, MAX_DESCRIPTION_CHARS));
+    for tag in LINK.find_iter(html) {
+        let attrs = attrs(tag.as_str());
+        let is_icon = attr(&attrs, "rel").is_some_and(|rel| {
+            rel.split_whitespace()
+                .any(|r| r.eq_ignore_ascii_case("icon"))
+        });
+        if is_icon {
+            metadata.favicon_url = resolve(url, attr(&attrs, "href"));
+            break;
+        }
+    }
+    if metadata.favicon_url.is_none() {
+        metadata.favicon_url = url.join("/favicon.ico").ok().map(String::from);
+    }
+    metadata
+}
+
+fn attrs(tag: &str) -> Vec<(String, &str)> {
+    ATTR.captures_iter(tag)
+        .filter_map(|c| {
+            let value = c.get(2).or_else(|| c.get(3))?;
+            Some((c[1].to_ascii_lowercase(), value.as_str()))
+        })
+        .collect()
+}
+
+fn attr<'a>(attrs: &[(String, &'a str)], name: &str) -> Option<&'a str> {
+    attrs
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, value)| *value)
+}
+
+fn resolve(base: &Url, href: Option<&str>) -> Option<String> {
+    let url = base.join(&html_to_text(href?.trim())).ok()?;
+    matches!(url.scheme(), "http" | "https").then(|| url.into())
+}
+
+/// Truncates the text for display, marking where it was cut.
+fn ellipsize(s: &str, max_chars: usize) -> String {
+    let s = s.trim();
+    let truncated = truncate(s, max_chars);
+    if truncated.len() < s.len() {
+        format!("{truncated}…")
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_metadata_prefers_opengraph() {
+        let url = Url::parse("https://example.com/posts/1").unwrap();
+        let html = r#"
+            <html><head>
+            <title>Fallback &amp; title</title>
+            <meta property="og:title" content="Post &quot;one&quot;">
+            <meta name="description" content='A post'>
+            <meta property="og:image" content="/images/1.png">
+            <link rel="shortcut icon" href="https://cdn.example.com/icon.png">
+            </head></html>"#;
+        assert_eq!(
+            parse_metadata(&url, html),
+            Metadata {
+                title: Some("Post \"one\"".to_string()),
+                description: Some("A post".to_string()),
+                site_name: None,
+                image_url: Some("https://example.com/images/1.png".to_string()),
+                favicon_url: Some("https://cdn.example.com/icon.png".to_string()),
+            }
+        );
+
+        let metadata = parse_metadata(&url, "<title>Fallback &amp; title</title>");
+        assert_eq!(metadata.title.as_deref(), Some("Fallback & title"));
+        assert_eq!(
+            metadata.favicon_url.as_deref(),
+            Some("https://example.com/favicon.ico")
+        );
+    }
+
+    #[test]
+    fn links_starts_with_the_task_url() {
+        assert_eq!(
+            links(
+                Some("https://github.com/pr/1"),
+                Some("See https://example.com/a. And (https://github.com/pr/1), ftp://x")
+            ),
+            vec!["https://github.com/pr/1", "https://example.com/a"]
+        );
+    }
+}
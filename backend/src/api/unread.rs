@@ -0,0 +1,334 @@
+//! Per-user read and flagged state of tasks, like an IMAP mailbox's. A task
+//! is unread when someone else modified it after the user last read it, or
+//! after they first opened, or last marked all of, the project. Modifications
+//! come from `task_attribution`, so clients needn't replay history to work
+//! out what changed since the user's last visit.
+
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{Collab, projects_state::DocBox},
+        google::User,
+        model::{Graph, ProjectId},
+        not_found_error, verify_project_access,
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    routing::{get, post, put},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+
+const MAX_TASKS: usize = 1000;
+
+/// Routes for a project's read state.
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/{project_id}/unread", get(get_unread_handler))
+        .route("/{project_id}/unread/read", post(mark_read_handler))
+        .route("/{project_id}/unread/read-all", post(mark_all_read_handler))
+        .route(
+            "/{project_id}/unread/flags/{task_id}",
+            put(set_flag_handler),
+        )
+}
+
+/// Routes summarizing the user's read state across projects.
+pub(super) fn summary_router() -> Router {
+    Router::new().route("/", get(summary_handler))
+}
+
+/// The user's unread and flagged tasks in a project, sent to clients in a
+/// sidecar message after they sync.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Markers {
+    unread: BTreeSet<String>,
+    flagged: BTreeSet<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UnreadCounts {
+    unread: usize,
+    flagged: usize,
+    #[serde(flatten)]
+    markers: Markers,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ProjectUnread {
+    project_id: ProjectId,
+    unread: i64,
+    flagged: i64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UnreadQuery {
+    /// Only count tasks in this task's subtree, itself included.
+    task_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct MarkRead {
+    task_ids: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SetFlag {
+    flagged: bool,
+}
+
+#[tracing::instrument(skip(user, pool, collab))]
+async fn get_unread_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Path(project_id): Path<ProjectId>,
+    Query(query): Query<UnreadQuery>,
+) -> ApiResult<Json<UnreadCounts>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    let mut markers = markers(pool, &project_id, &user.email).await?;
+    if let Some(task_id) = &query.task_id {
+        let graph = {
+            let client = collab.register_local_client(&project_id).await?;
+            let doc_box = client.project.doc_box.lock().await;
+            let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+            doc.to_graph(&doc.transact())?
+        };
+        if !graph.contains_key(task_id) {
+            return Err(not_found_error(
+                "NOT_FOUND",
+                &format!("Task {task_id} not found"),
+            ));
+        }
+        let subtree = subtree(&graph, task_id);
+        markers.unread.retain(|id| subtree.contains(id.as_str()));
+        markers.flagged.retain(|id| subtree.contains(id.as_str()));
+    }
+    Ok(Json(UnreadCounts {
+        unread: markers.unread.len(),
+        flagged: markers.flagged.len(),
+        markers,
+    }))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn mark_read_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Json(req): Json<MarkRead>,
+) -> ApiResult<Json<()>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    if req.task_ids.len() > MAX_TASKS {
+        return Err(bad_request_error(
+            "TOO_MANY_TASKS",
+            &format!("At most {MAX_TASKS} tasks can be marked at once"),
+        ));
+    }
+    sqlx::query(
+        "
+        INSERT INTO task_reads (email, project_id, task_id, read_time)
+        SELECT $1, $2, task_id, NOW() FROM unnest($3) AS task_id
+        ON CONFLICT (email, project_id, task_id)
+        DO UPDATE SET read_time = EXCLUDED.read_time",
+    )
+    .bind(&user.email)
+    .bind(&project_id)
+    .bind(&req.task_ids)
+    .execute(pool)
+    .await
+    .context("Failed to mark tasks read")?;
+    Ok(Json(()))
+}
+
+/// Marks every task read, leaving flags alone.
+#[tracing::instrument(skip(user, pool))]
+async fn mark_all_read_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<()>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    sqlx::query(
+        "
+        INSERT INTO project_reads (email, project_id, read_time)
+        VALUES ($1, $2, NOW())
+        ON CONFLICT (email, project_id)
+        DO UPDATE SET read_time = EXCLUDED.read_time",
+    )
+    .bind(&user.email)
+    .bind(&project_id)
+    .execute(pool)
+    .await
+    .context("Failed to mark project read")?;
+    Ok(Json(()))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn set_flag_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, task_id)): Path<(ProjectId, String)>,
+    Json(req): Json<SetFlag>,
+) -> ApiResult<Json<()>> {
+    verify_project_access(pool, &user, &project_id).await?;
+    sqlx::query(
+        "
+        INSERT INTO task_reads (email, project_id, task_id, flagged)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (email, project_id, task_id)
+        DO UPDATE SET flagged = EXCLUDED.flagged",
+    )
+    .bind(&user.email)
+    .bind(&project_id)
+    .bind(&task_id)
+    .bind(req.flagged)
+    .execute(pool)
+    .await
+    .context("Failed to flag task")?;
+    Ok(Json(()))
+}
+
+/// Counts unread and flagged tasks in each of the user's projects. Projects
+/// the user never opened have nothing unread.
+#[tracing::instrument(skip(user, pool))]
+async fn summary_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<Vec<ProjectUnread>>> {
+    let counts: Vec<(ProjectId, i64, i64)> = sqlx::query_as(
+        "
+        SELECT
+            pp.project_id,
+            (
+                SELECT COUNT(*)
+                FROM task_attribution a
+                JOIN project_reads pr ON pr.project_id = a.project_id AND pr.email = $1
+                LEFT JOIN task_reads r
+                    ON r.project_id = a.project_id AND r.task_id = a.task_id AND r.email = $1
+                LEFT JOIN task_projections t
+                    ON t.project_id = a.project_id AND t.task_id = a.task_id
+                WHERE a.project_id = pp.project_id
+                AND a.last_modified_by != $1
+                AND a.last_modified_at > GREATEST(pr.read_time, r.read_time)
+                AND NOT COALESCE(t.archived, FALSE)
+            ),
+            (
+                SELECT COUNT(*)
+                FROM task_reads r
+                WHERE r.project_id = pp.project_id AND r.email = $1 AND r.flagged
+            )
+        FROM project_permissions pp
+        JOIN projects p ON p.project_id = pp.project_id
+        WHERE pp.email = $1 AND p.deleted_on IS NULL
+        ORDER BY pp.project_id",
+    )
+    .bind(&user.email)
+    .fetch_all(pool)
+    .await
+    .context("Failed to count unread tasks")?;
+    Ok(Json(
+        counts
+            .into_iter()
+            .map(|(project_id, unread, flagged)| ProjectUnread {
+                project_id,
+                unread,
+                flagged,
+            })
+            .collect(),
+    ))
+}
+
+/// Returns the user's unread and flagged tasks, starting the user's read
+/// state on their first visit so existing tasks aren't all unread.
+pub(crate) async fn markers(pool: &PgPool, project_id: &ProjectId, email: &str) -> Result<Markers> {
+    sqlx::query(
+        "
+        INSERT INTO project_reads (email, project_id, read_time)
+        VALUES ($1, $2, NOW())
+        ON CONFLICT (email, project_id) DO NOTHING",
+    )
+    .bind(email)
+    .bind(project_id)
+    .execute(pool)
+    .await
+    .context("Failed to start read state")?;
+
+    let unread: Vec<(String,)> = sqlx::query_as(
+        "
+        SELECT a.task_id
+        FROM task_attribution a
+        JOIN project_reads pr ON pr.project_id = a.project_id AND pr.email = $2
+        LEFT JOIN task_reads r
+            ON r.project_id = a.project_id AND r.task_id = a.task_id AND r.email = $2
+        LEFT JOIN task_projections t ON t.project_id = a.project_id AND t.task_id = a.task_id
+        WHERE a.project_id = $1
+        AND a.last_modified_by != $2
+        AND a.last_modified_at > GREATEST(pr.read_time, r.read_time)
+        AND NOT COALESCE(t.archived, FALSE)",
+    )
+    .bind(project_id)
+    .bind(email)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list unread tasks")?;
+    let flagged: Vec<(String,)> = sqlx::query_as(
+        "
+        SELECT task_id
+        FROM task_reads
+        WHERE project_id = $1 AND email = $2 AND flagged",
+    )
+    .bind(project_id)
+    .bind(email)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list flagged tasks")?;
+    Ok(Markers {
+        unread: unread.into_iter().map(|(id,)| id).collect(),
+        flagged: flagged.into_iter().map(|(id,)| id).collect(),
+    })
+}
+
+/// The ids of the task and its descendants.
+fn subtree<'a>(graph: &'a Graph, task_id: &'a str) -> HashSet<&'a str> {
+    let mut ids = HashSet::new();
+    let mut stack = vec![task_id];
+    while let Some(id) = stack.pop() {
+        if !ids.insert(id) {
+            continue;
+        }
+        if let Some(task) = graph.get(id) {
+            stack.extend(task.children.iter().map(String::as_str));
+        }
+    }
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::model::test_utils::{self, graph};
+
+    #[test_log::test]
+    fn subtree_includes_descendants() {
+        let graph = graph([
+            test_utils::task("root", "root", &["a", "d"]),
+            test_utils::task("a", "a", &["b", "c"]),
+            test_utils::task("b", "b", &["c"]),
+            test_utils::task("c", "c", &[]),
+            test_utils::task("d", "d", &[]),
+        ]);
+        assert_eq!(subtree(&graph, "a"), HashSet::from(["a", "b", "c"]));
+        assert_eq!(subtree(&graph, "d"), HashSet::from(["d"]));
+    }
+}
@@ -0,0 +1,298 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        google::User,
+        model::{ProjectId, ProjectUsage, UsageClient, UsageDay},
+        truncate, verify_project_admin,
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query, Request},
+    http::header::USER_AGENT,
+    middleware::Next,
+    response::Response,
+    routing::get,
+};
+use chrono::{DateTime, DurationRound as _, TimeDelta, Utc};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+
+pub(super) fn router() -> Router {
+    Router::new().route("/{project_id}/usage", get(get_usage_handler))
+}
+
+/// How long usage is kept before being pruned.
+const RETENTION_DAYS: i64 = 30;
+const DEFAULT_DAYS: i64 = 7;
+const MAX_TOP_CLIENTS: i64 = 20;
+const MAX_USER_AGENT_LEN: usize = 256;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UsageQuery {
+    days: Option<i64>,
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn get_usage_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Query(query): Query<UsageQuery>,
+) -> ApiResult<Json<ProjectUsage>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let days = query.days.unwrap_or(DEFAULT_DAYS);
+    if !(1..=RETENTION_DAYS).contains(&days) {
+        return Err(bad_request_error(
+            "INVALID_DAYS",
+            &format!("Days must be between 1 and {RETENTION_DAYS}"),
+        ));
+    }
+
+    let daily: Vec<UsageDay> = sqlx::query_as(
+        "
+        SELECT
+          date_trunc('day', bucket_start) AS day,
+          channel,
+          SUM(requests)::bigint AS requests,
+          SUM(errors)::bigint AS errors
+        FROM api_usage
+        WHERE project_id = $1
+        AND bucket_start >= NOW() - make_interval(days => $2::int)
+        GROUP BY 1, 2
+        ORDER BY 1, 2",
+    )
+    .bind(&project_id)
+    .bind(days)
+    .fetch_all(pool)
+    .await
+    .context("Failed to query daily usage")?;
+
+    let top_clients: Vec<UsageClient> = sqlx::query_as(
+        "
+        SELECT
+          email,
+          user_agent,
+          SUM(requests)::bigint AS requests,
+          SUM(errors)::bigint AS errors
+        FROM api_usage
+        WHERE project_id = $1
+        AND bucket_start >= NOW() - make_interval(days => $2::int)
+        GROUP BY email, user_agent
+        ORDER BY requests DESC, email, user_agent
+        LIMIT $3",
+    )
+    .bind(&project_id)
+    .bind(days)
+    .bind(MAX_TOP_CLIENTS)
+    .fetch_all(pool)
+    .await
+    .context("Failed to query top clients")?;
+
+    let requests = daily.iter().map(|d| d.requests).sum();
+    let errors = daily.iter().map(|d| d.errors).sum();
+    Ok(Json(ProjectUsage {
+        days,
+        requests,
+        errors,
+        error_rate: if requests > 0 {
+            errors as f64 / requests as f64
+        } else {
+            0.0
+        },
+        daily,
+        top_clients,
+    }))
+}
+
+#[derive(PartialEq, Eq, Hash, Debug)]
+struct UsageKey {
+    project_id: ProjectId,
+    bucket_start: DateTime<Utc>,
+    channel: &'static str,
+    email: String,
+    user_agent: String,
+}
+
+#[derive(Default, Debug)]
+struct UsageCount {
+    requests: i64,
+    errors: i64,
+}
+
+/// Counts project scoped requests in memory and periodically flushes them
+/// to hourly buckets in the database.
+#[derive(Clone)]
+pub(crate) struct UsageTracker {
+    pool: &'static PgPool,
+    counts: Arc<Mutex<HashMap<UsageKey, UsageCount>>>,
+}
+
+impl UsageTracker {
+    pub(crate) fn new(pool: &'static PgPool) -> Self {
+        UsageTracker {
+            pool,
+            counts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn record(&self, key: UsageKey, error: bool) {
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(key).or_default();
+        count.requests += 1;
+        if error {
+            count.errors += 1;
+        }
+    }
+
+    /// Flushes counts every minute, pruning usage older than the retention window.
+    pub(crate) fn start_flushing(&self) -> JoinHandle<()> {
+        let tracker = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                // Counts keep accumulating in memory until the pool recovers.
+                if tracker.pool.shed_best_effort() {
+                    continue;
+                }
+                if let Err(e) = tracker.flush().await {
+                    tracing::warn!("Failed to flush API usage: {e:?}");
+                }
+                if let Err(e) = tracker.prune().await {
+                    tracing::warn!("Failed to prune API usage: {e:?}");
+                }
+            }
+        })
+    }
+
+    /// Writes buffered counts to the database. Counts for users who aren't
+    /// members of the project, e.g. from denied requests, are dropped.
+    pub(crate) async fn flush(&self) -> Result<()> {
+        let counts = std::mem::take(&mut *self.counts.lock().unwrap());
+        if counts.is_empty() {
+            return Ok(());
+        }
+
+        let mut project_ids = Vec::with_capacity(counts.len());
+        let mut bucket_starts = Vec::with_capacity(counts.len());
+        let mut channels = Vec::with_capacity(counts.len());
+        let mut emails = Vec::with_capacity(counts.len());
+        let mut user_agents = Vec::with_capacity(counts.len());
+        let mut requests = Vec::with_capacity(counts.len());
+        let mut errors = Vec::with_capacity(counts.len());
+        for (key, count) in counts {
+            project_ids.push(key.project_id);
+            bucket_starts.push(key.bucket_start);
+            channels.push(key.channel.to_string());
+            emails.push(key.email);
+            user_agents.push(key.user_agent);
+            requests.push(count.requests);
+            errors.push(count.errors);
+        }
+
+        sqlx::query(
+            "
+            INSERT INTO api_usage (project_id, bucket_start, channel, email, user_agent, requests, errors)
+            SELECT u.project_id, u.bucket_start, u.channel, u.email, u.user_agent, u.requests, u.errors
+            FROM UNNEST($1::varchar[], $2::timestamptz[], $3::varchar[], $4::varchar[], $5::varchar[], $6::bigint[], $7::bigint[])
+              AS u(project_id, bucket_start, channel, email, user_agent, requests, errors)
+            JOIN project_permissions pp ON pp.project_id = u.project_id AND pp.email = u.email
+            ON CONFLICT (project_id, bucket_start, channel, email, user_agent)
+            DO UPDATE SET
+              requests = api_usage.requests + EXCLUDED.requests,
+              errors = api_usage.errors + EXCLUDED.errors",
+        )
+        .bind(&project_ids)
+        .bind(&bucket_starts)
+        .bind(&channels)
+        .bind(&emails)
+        .bind(&user_agents)
+        .bind(&requests)
+        .bind(&errors)
+        .execute(self.pool)
+        .await
+        .context("Failed to write API usage")?;
+        Ok(())
+    }
+
+    async fn prune(&self) -> Result<()> {
+        sqlx::query(
+            "DELETE FROM api_usage WHERE bucket_start < NOW() - make_interval(days => $1::int)",
+        )
+        .bind(RETENTION_DAYS)
+        .execute(self.pool)
+        .await
+        .context("Failed to prune API usage")?;
+        Ok(())
+    }
+}
+
+/// Middleware recording the usage of project scoped REST requests and websocket connections.
+pub(crate) async fn record_usage(request: Request, next: Next) -> Response {
+    let key = request.extensions().get::<User>().and_then(|user| {
+        let (channel, project_id) = parse_path(request.uri().path())?;
+        let user_agent = request
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|ua| ua.to_str().ok())
+            .unwrap_or_default();
+        Some(UsageKey {
+            project_id: project_id.to_string(),
+            bucket_start: Utc::now().duration_trunc(TimeDelta::hours(1)).ok()?,
+            channel,
+            email: user.email.clone(),
+            user_agent: truncate(user_agent, MAX_USER_AGENT_LEN).to_string(),
+        })
+    });
+    let tracker = request.extensions().get::<UsageTracker>().cloned();
+
+    let response = next.run(request).await;
+
+    if let (Some(key), Some(tracker)) = (key, tracker) {
+        let status = response.status();
+        tracker.record(key, status.is_client_error() || status.is_server_error());
+    }
+    response
+}
+
+/// Returns the channel and project of project scoped API paths,
+/// e.g. /projects/{project_id}/... or /ws/projects/{project_id}.
+fn parse_path(path: &str) -> Option<(&'static str, &str)> {
+    let mut segments = path.split('/').filter(|s| !s.is_empty()).peekable();
+    if segments.peek() == Some(&"api") {
+        segments.next();
+    }
+    let channel = match segments.next()? {
+        "projects" => "rest",
+        "ws" if segments.next()? == "projects" => "ws",
+        _ => return None,
+    };
+    segments.next().map(|project_id| (channel, project_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_path_extracts_project() {
+        assert_eq!(parse_path("/projects/abc/board"), Some(("rest", "abc")));
+        assert_eq!(parse_path("/api/projects/abc"), Some(("rest", "abc")));
+        assert_eq!(parse_path("/ws/projects/abc"), Some(("ws", "abc")));
+        assert_eq!(parse_path("/projects"), None);
+        assert_eq!(parse_path("/projects/"), None);
+        assert_eq!(parse_path("/me/tasks"), None);
+        assert_eq!(parse_path("/ws/other/abc"), None);
+    }
+}
@@ -1,6 +1,24 @@
-use crate::api::{ApiResult, google, model::User, not_found_error, verify_premium};
-use axum::{Extension, Json, Router, extract::Path, routing::get};
-use sqlx::postgres::PgPool;
+use crate::{
+    api::{
+        ApiResult, google,
+        model::{User, UserProfile, WorkingHours},
+        not_found_error, verify_premium,
+    },
+    outbound::{Fetch, Fetched},
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    body::Bytes,
+    extract::{Path, Query},
+    http::header::{CACHE_CONTROL, CONTENT_TYPE},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use reqwest::Url;
+use serde::Deserialize;
+use sqlx::types::Json as SqlJson;
 
 use super::{bad_request_error, unauthorized_error};
 
@@ -8,20 +26,32 @@ pub(super) fn router() -> Router {
     Router::new()
         .route("/", get(list_users_handler))
         .route("/{email}", get(get_user_handler))
+        .route("/{email}/avatar", get(get_avatar_handler))
+}
+
+#[derive(Deserialize, Debug)]
+struct ListUsersQuery {
+    /// Comma separated list of emails to look up.
+    ids: Option<String>,
 }
 
 #[tracing::instrument(skip(pool, user))]
 async fn list_users_handler(
     Extension(pool): Extension<&'static PgPool>,
     Extension(user): Extension<google::User>,
-) -> ApiResult<Json<Vec<User>>> {
+    Query(query): Query<ListUsersQuery>,
+) -> ApiResult<Response> {
+    if let Some(ids) = query.ids {
+        return Ok(Json(lookup_users(pool, &user, &ids).await?).into_response());
+    }
+
     verify_premium(pool, &user).await?;
 
     let mut users: Vec<User> = sqlx::query_as(
         "
         SELECT email, name, picture, premium
         FROM (
-            SELECT email, name, picture, (subscription_end_time IS NOT NULL AND subscription_end_time > now()) AS premium
+            SELECT email, name, picture, is_premium(subscription_end_time) AS premium
             FROM users
         ) WHERE premium;",
     )
@@ -29,7 +59,102 @@ async fn list_users_handler(
     .await?;
     users.sort_by(|a, b| a.name.cmp(&b.name).then(a.email.cmp(&b.email)));
 
-    Ok(Json(users))
+    Ok(Json(users).into_response())
+}
+
+async fn lookup_users(
+    pool: &PgPool,
+    user: &google::User,
+    ids: &str,
+) -> ApiResult<Vec<UserProfile>> {
+    let emails = ids
+        .split(',')
+        .map(|e| e.trim().to_lowercase())
+        .filter(|e| !e.is_empty())
+        .collect::<Vec<String>>();
+    const MAX_IDS: usize = 200;
+    if emails.len() > MAX_IDS {
+        return Err(bad_request_error(
+            "TOO_MANY_IDS",
+            &format!("Cannot look up more than {MAX_IDS} users at once"),
+        ));
+    }
+    let mut profiles = fetch_user_profiles(pool, &user.email, &emails).await?;
+    profiles.sort_by(|a, b| a.email.cmp(&b.email));
+    Ok(profiles)
+}
+
+type ProfileRow = (
+    String,
+    String,
+    String,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<SqlJson<WorkingHours>>,
+    bool,
+);
+
+/// Fetches the profiles of the given users that are visible to the viewer.
+/// Users are visible to themselves and to anyone they share a project with.
+pub(crate) async fn fetch_user_profiles(
+    pool: &PgPool,
+    viewer: &str,
+    emails: &[String],
+) -> Result<Vec<UserProfile>> {
+    if emails.is_empty() {
+        return Ok(Vec::with_capacity(0));
+    }
+
+    let rows: Vec<ProfileRow> = sqlx::query_as(
+        "
+        SELECT
+            email,
+            name,
+            picture,
+            display_name,
+            timezone,
+            locale,
+            working_hours,
+            EXISTS(SELECT 1 FROM user_avatars WHERE user_avatars.email = users.email) AS has_avatar
+        FROM users
+        WHERE email IN (SELECT * FROM unnest($1))
+          AND (email = $2 OR email IN (
+            SELECT p2.email
+            FROM project_permissions p1
+            JOIN project_permissions p2 USING (project_id)
+            WHERE p1.email = $2
+          ))",
+    )
+    .bind(emails)
+    .bind(viewer)
+    .fetch_all(pool)
+    .await
+    .context("Failed to query user profiles")?;
+
+    Ok(rows
+        .into_iter()
+        .map(
+            |(email, name, picture, display_name, timezone, locale, working_hours, has_avatar)| {
+                UserProfile {
+                    picture: if has_avatar || !picture.is_empty() {
+                        avatar_url(&email)
+                    } else {
+                        String::new()
+                    },
+                    email,
+                    name: display_name.unwrap_or(name),
+                    timezone,
+                    locale,
+                    working_hours: working_hours.map(|SqlJson(w)| w),
+                }
+            },
+        )
+        .collect())
+}
+
+fn avatar_url(email: &str) -> String {
+    format!("/api/users/{email}/avatar")
 }
 
 #[tracing::instrument(skip(pool, user))]
@@ -42,7 +167,7 @@ async fn get_user_handler(
 
     let user: Option<User> = sqlx::query_as(
         "
-        SELECT email, name, picture, (subscription_end_time IS NOT NULL AND subscription_end_time > now()) AS premium
+        SELECT email, name, picture, is_premium(subscription_end_time) AS premium
         FROM users
         WHERE email=$1;",
     )
@@ -58,6 +183,81 @@ async fn get_user_handler(
     }
 }
 
+/// Serves the user's uploaded avatar or, absent one, proxies their Google picture.
+#[tracing::instrument(skip(pool, user))]
+async fn get_avatar_handler(
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(user): Extension<google::User>,
+    Path(email): Path<String>,
+) -> ApiResult<Response> {
+    if fetch_user_profiles(pool, &user.email, std::slice::from_ref(&email))
+        .await?
+        .is_empty()
+    {
+        return Err(not_found_error(
+            "NOT_FOUND",
+            &format!("User {email} not found"),
+        ));
+    }
+
+    let avatar: Option<(String, Vec<u8>)> =
+        sqlx::query_as("SELECT content_type, data FROM user_avatars WHERE email = $1")
+            .bind(&email)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to query avatar")?;
+    if let Some((content_type, data)) = avatar {
+        return Ok(avatar_response(content_type, Bytes::from(data)));
+    }
+
+    let (picture,): (String,) = sqlx::query_as("SELECT picture FROM users WHERE email = $1")
+        .bind(&email)
+        .fetch_one(pool)
+        .await
+        .context("Failed to query picture")?;
+    let Some(picture) = google_picture_url(&picture) else {
+        return Err(not_found_error(
+            "NO_AVATAR",
+            &format!("User {email} has no avatar"),
+        ));
+    };
+    const CONTENT_TYPES: &[&str] = &["image/png", "image/jpeg", "image/gif", "image/webp"];
+    let picture = Fetch::get(picture)
+        .content_types(CONTENT_TYPES)
+        .send()
+        .await
+        .and_then(Fetched::error_for_status)
+        .context("Failed to fetch picture")?;
+    Ok(avatar_response(
+        picture.content_type,
+        Bytes::from(picture.body),
+    ))
+}
+
+/// Only proxy pictures hosted by Google to avoid fetching arbitrary URLs.
+fn google_picture_url(picture: &str) -> Option<Url> {
+    let url = Url::parse(picture).ok()?;
+    if url.scheme() != "https" {
+        return None;
+    }
+    let host = url.host_str()?;
+    if !host.ends_with(".googleusercontent.com") {
+        return None;
+    }
+    Some(url)
+}
+
+fn avatar_response(content_type: String, data: Bytes) -> Response {
+    (
+        [
+            (CONTENT_TYPE, content_type),
+            (CACHE_CONTROL, "private, max-age=3600".to_string()),
+        ],
+        data,
+    )
+        .into_response()
+}
+
 fn verify_user_access(user: &google::User, email: &str) -> ApiResult<()> {
     if email.is_empty() {
         return Err(bad_request_error("EMPTY_EMAIL", "Email must not be empty"));
@@ -70,3 +270,17 @@ fn verify_user_access(user: &google::User, email: &str) -> ApiResult<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn google_picture_url_allows_google_hosts() {
+        assert!(google_picture_url("https://lh3.googleusercontent.com/a/abc=s96-c").is_some());
+        assert!(google_picture_url("http://lh3.googleusercontent.com/a/abc").is_none());
+        assert!(google_picture_url("https://evil.com/googleusercontent.com").is_none());
+        assert!(google_picture_url("https://googleusercontent.com.evil.com/a").is_none());
+        assert!(google_picture_url("").is_none());
+    }
+}
@@ -0,0 +1,305 @@
+//! Per-project webhooks notified of task changes. Each webhook can filter
+//! changes by their origin, e.g. to only hear about GitHub state transitions
+//! and not human edits. Deliveries are best effort: they're attempted once,
+//! and consumers can catch up with the change feed.
+
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::txn_origin::PLUGINS,
+        google::User,
+        model::{ProjectId, TaskChange},
+        not_found_error, step_up, verify_project_admin,
+    },
+    outbound::{Fetch, Fetched},
+    postgres::PgPool,
+    settings::settings,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    middleware,
+    routing::{get, post, put},
+};
+use hmac::{Hmac, Mac as _};
+use reqwest::{
+    Method, Url,
+    header::{CONTENT_TYPE, HeaderName},
+};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::{
+    FromRow,
+    types::{
+        Json as SqlJson,
+        chrono::{DateTime, Utc},
+    },
+};
+use std::time::Duration;
+use uuid::Uuid;
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Receivers' responses are ignored, so only a little is read.
+const MAX_RESPONSE_LEN: usize = 64 * 1024;
+
+/// Actor kinds webhooks can filter on. See `Actor::kind`.
+const ACTORS: &[&str] = &["user", "agent", "github", "server"];
+const MAX_WEBHOOKS: i64 = 10;
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/{project_id}/webhooks", get(list_webhooks_handler))
+        .route(
+            "/{project_id}/webhooks",
+            post(create_webhook_handler).route_layer(middleware::from_fn(step_up::require_step_up)),
+        )
+        .route(
+            "/{project_id}/webhooks/{webhook_id}",
+            put(update_webhook_handler).delete(delete_webhook_handler),
+        )
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Webhook {
+    id: String,
+    url: String,
+    /// Only returned when the webhook is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[sqlx(skip)]
+    secret: Option<String>,
+    /// Actor kinds whose changes are sent. Empty sends all.
+    actors: SqlJson<Vec<String>>,
+    /// Plugins whose changes are sent. Empty sends all.
+    plugins: SqlJson<Vec<String>>,
+    creator: String,
+    create_time: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SetWebhook {
+    url: String,
+    #[serde(default)]
+    actors: Vec<String>,
+    #[serde(default)]
+    plugins: Vec<String>,
+}
+
+/// A stored update's task changes, as delivered to webhooks.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ChangeEvent {
+    pub(crate) project_id: ProjectId,
+    pub(crate) txn_id: String,
+    pub(crate) actor: &'static str,
+    pub(crate) email: Option<String>,
+    pub(crate) plugin: Option<&'static str>,
+    pub(crate) feature: String,
+    pub(crate) create_time: DateTime<Utc>,
+    pub(crate) changes: Vec<TaskChange>,
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn list_webhooks_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+) -> ApiResult<Json<Vec<Webhook>>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let webhooks: Vec<Webhook> = sqlx::query_as(
+        "
+        SELECT id, url, actors, plugins, creator, create_time
+        FROM project_webhooks
+        WHERE project_id = $1
+        ORDER BY create_time",
+    )
+    .bind(&project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list webhooks")?;
+    Ok(Json(webhooks))
+}
+
+/// Creates a webhook. Its signing secret is only returned now.
+#[tracing::instrument(skip(user, pool))]
+async fn create_webhook_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(project_id): Path<ProjectId>,
+    Json(req): Json<SetWebhook>,
+) -> ApiResult<Json<Webhook>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let req = validate(req)?;
+    let (count,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM project_webhooks WHERE project_id = $1")
+            .bind(&project_id)
+            .fetch_one(pool)
+            .await
+            .context("Failed to count webhooks")?;
+    if count >= MAX_WEBHOOKS {
+        return Err(bad_request_error(
+            "TOO_MANY_WEBHOOKS",
+            &format!("Projects may have at most {MAX_WEBHOOKS} webhooks"),
+        ));
+    }
+
+    let secret = format!("kwh_{}", Uuid::new_v4().simple());
+    let mut webhook: Webhook = sqlx::query_as(
+        "
+        INSERT INTO project_webhooks (id, project_id, url, secret, actors, plugins, creator)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, url, actors, plugins, creator, create_time",
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(&project_id)
+    .bind(&req.url)
+    .bind(&secret)
+    .bind(SqlJson(&req.actors))
+    .bind(SqlJson(&req.plugins))
+    .bind(&user.email)
+    .fetch_one(pool)
+    .await
+    .context("Failed to create webhook")?;
+    webhook.secret = Some(secret);
+    Ok(Json(webhook))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn update_webhook_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, webhook_id)): Path<(ProjectId, String)>,
+    Json(req): Json<SetWebhook>,
+) -> ApiResult<Json<Webhook>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let req = validate(req)?;
+    let webhook: Option<Webhook> = sqlx::query_as(
+        "
+        UPDATE project_webhooks
+        SET url = $3, actors = $4, plugins = $5
+        WHERE project_id = $1 AND id = $2
+        RETURNING id, url, actors, plugins, creator, create_time",
+    )
+    .bind(&project_id)
+    .bind(&webhook_id)
+    .bind(&req.url)
+    .bind(SqlJson(&req.actors))
+    .bind(SqlJson(&req.plugins))
+    .fetch_optional(pool)
+    .await
+    .context("Failed to update webhook")?;
+    match webhook {
+        Some(webhook) => Ok(Json(webhook)),
+        None => Err(not_found_error("NOT_FOUND", "Webhook not found")),
+    }
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_webhook_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path((project_id, webhook_id)): Path<(ProjectId, String)>,
+) -> ApiResult<Json<()>> {
+    verify_project_admin(pool, &user, &project_id).await?;
+    let res = sqlx::query("DELETE FROM project_webhooks WHERE project_id = $1 AND id = $2")
+        .bind(&project_id)
+        .bind(&webhook_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete webhook")?;
+    if res.rows_affected() == 0 {
+        return Err(not_found_error("NOT_FOUND", "Webhook not found"));
+    }
+    Ok(Json(()))
+}
+
+fn validate(mut req: SetWebhook) -> ApiResult<SetWebhook> {
+    let url = match Url::parse(req.url.trim()) {
+        Ok(url) if url.host().is_some() => url,
+        _ => return Err(bad_request_error("INVALID_URL", "Invalid webhook URL")),
+    };
+    if url.scheme() != "https" && !(settings().is_dev() && url.scheme() == "http") {
+        return Err(bad_request_error(
+            "INVALID_URL",
+            "Webhook URLs must use https",
+        ));
+    }
+    req.url = url.to_string();
+    for (kind, values, known) in [
+        ("actor", &mut req.actors, ACTORS),
+        ("plugin", &mut req.plugins, PLUGINS),
+    ] {
+        values.sort();
+        values.dedup();
+        if let Some(unknown) = values.iter().find(|v| !known.contains(&v.as_str())) {
+            return Err(bad_request_error(
+                "INVALID_FILTER",
+                &format!("Unknown {kind} {unknown}, expected one of {known:?}"),
+            ));
+        }
+    }
+    Ok(req)
+}
+
+/// Sends the event, in the background, to the project's webhooks whose
+/// filters match its origin.
+pub(crate) fn deliver(pool: &'static PgPool, event: ChangeEvent) {
+    if event.changes.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        if let Err(e) = deliver_internal(pool, &event).await {
+            tracing::warn!("Failed to deliver webhooks: {e:?}");
+        }
+    });
+}
+
+async fn deliver_internal(pool: &PgPool, event: &ChangeEvent) -> Result<()> {
+    let webhooks: Vec<(String, String, String)> = sqlx::query_as(
+        "
+        SELECT id, url, secret
+        FROM project_webhooks
+        WHERE project_id = $1
+        AND (actors = '[]'::jsonb OR actors ? $2)
+        AND (plugins = '[]'::jsonb OR plugins ? $3)",
+    )
+    .bind(&event.project_id)
+    .bind(event.actor)
+    .bind(event.plugin)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list matching webhooks")?;
+    if webhooks.is_empty() {
+        return Ok(());
+    }
+
+    let body = serde_json::to_vec(event)?;
+    for (id, url, secret) in webhooks {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+        mac.update(&body);
+        let signature = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+        let res = match Url::parse(&url) {
+            Ok(url) => Fetch::new(Method::POST, url)
+                .header(CONTENT_TYPE, "application/json")
+                .header(HeaderName::from_static("x-koso-signature"), &signature)
+                .body(body.clone())
+                .timeout(DELIVERY_TIMEOUT)
+                .max_len(MAX_RESPONSE_LEN)
+                .truncate()
+                .send()
+                .await
+                .and_then(Fetched::error_for_status),
+            Err(e) => Err(e.into()),
+        };
+        match res {
+            Ok(_) => metrics::counter!("webhook_deliveries_total", "result" => "sent").increment(1),
+            Err(e) => {
+                metrics::counter!("webhook_deliveries_total", "result" => "failed").increment(1);
+                tracing::info!("Failed to deliver webhook {id}: {e:?}");
+            }
+        }
+    }
+    Ok(())
+}
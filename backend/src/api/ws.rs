@@ -1,44 +1,101 @@
-use crate::api::{ApiResult, collab::Collab, google::User};
+use crate::api::{
+    ApiResult,
+    collab::{
+        Collab, capabilities::Capabilities, client::ConnectionInfo,
+        client_messages::MAX_PENDING_BYTES,
+    },
+    context,
+    google::User,
+    impersonation::Impersonation,
+    truncate,
+};
 use axum::{
     Extension, Router,
     body::Body,
-    extract::{Path, WebSocketUpgrade},
+    extract::{Path, Query, WebSocketUpgrade},
+    http::{HeaderMap, header::USER_AGENT},
     response::Response,
     routing::get,
 };
+use serde::Deserialize;
 use tracing::Instrument as _;
 use uuid::Uuid;
 
+/// Header values are truncated to keep stored metadata small.
+const MAX_HEADER_CHARS: usize = 256;
+
 pub(super) fn router() -> Router {
     Router::new().route("/projects/{project_id}", get(ws_handler))
 }
+
+/// Browsers can't set headers on websocket requests, so clients announce
+/// themselves in the query string.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ConnectQuery {
+    /// Comma-separated capabilities. Clients that predate the handshake omit it.
+    pub(crate) capabilities: Option<String>,
+    pub(crate) version: Option<String>,
+}
+
 /// The handler for the HTTP request (this gets called when the HTTP GET lands at the start
 /// of websocket negotiation). After this completes, the actual switching from HTTP to
 /// websocket protocol will occur.
 /// This is the last point where we can extract TCP/IP metadata such as IP address of the client
 /// as well as things from HTTP headers such as user-agent of the browser etc.
-#[tracing::instrument(skip(ws, user, collab), fields(who))]
+#[tracing::instrument(skip(ws, user, collab, headers), fields(who))]
 async fn ws_handler(
     ws: WebSocketUpgrade,
     Path(project_id): Path<String>,
+    Query(query): Query<ConnectQuery>,
     Extension(user): Extension<User>,
     Extension(collab): Extension<Collab>,
+    impersonation: Option<Extension<Impersonation>>,
+    headers: HeaderMap,
 ) -> ApiResult<Response<Body>> {
     let who = Uuid::new_v4().to_string();
     let cs: tracing::Span = tracing::Span::current();
+    let context = context::current();
     cs.record("who", &who);
+    let connection = ConnectionInfo {
+        device: header_value(&headers, USER_AGENT.as_str()),
+        request_id: header_value(&headers, "x-request-id"),
+        read_only: impersonation.is_some(),
+        capabilities: Capabilities::parse(query.capabilities.as_deref(), query.version.as_deref()),
+        quota: None,
+    };
+    if let Some(Extension(impersonation)) = &impersonation {
+        tracing::info!(
+            "Opening a read-only connection for {} in impersonation session {}",
+            impersonation.admin_email,
+            impersonation.session_id
+        );
+    }
 
     // finalize the upgrade process by returning upgrade callback.
     // we can customize the callback by sending additional info such as address.
     Ok(ws
         .protocols(["bearer"])
+        .max_message_size(MAX_PENDING_BYTES)
         .on_failed_upgrade(|e| tracing::warn!("Failed to upgrade socket: {e:?}"))
         .on_upgrade(move |socket: axum::extract::ws::WebSocket| {
-            async move {
-                if let Err(e) = collab.register_client(socket, who, project_id, user).await {
+            let register = async move {
+                if let Err(e) = collab
+                    .register_client(socket, who, project_id, user, connection)
+                    .await
+                {
                     tracing::warn!("Failed to register client: {e:?}");
                 }
             }
-            .instrument(cs)
+            .instrument(cs);
+            // The upgraded connection is served by another task.
+            context::within(context, register)
         }))
 }
+
+fn header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| truncate(v, MAX_HEADER_CHARS).to_string())
+}
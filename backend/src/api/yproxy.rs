@@ -1,34 +1,83 @@
-use crate::api::model::{Graph, Task};
+use crate::api::model::{
+    AutoArchiveSettings, AutoAssign, AutomationAction, AutomationRule, AutomationTrigger, Deadline,
+    EstimateSettings, EstimateUnit, Graph, IterationSettings, Label, ProjectConfig, Task,
+    WorkflowCategory, WorkflowState,
+};
 use anyhow::{Context, Result, anyhow};
+use serde::{Serialize, de::DeserializeOwned};
 use similar::{Algorithm, capture_diff_slices};
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, PoisonError},
+};
 use yrs::{
-    Any, Array, ArrayRef, DeepObservable, Doc, GetString, Map, MapRef, Observable, Origin, Out,
-    ReadTxn, Subscription, Text, TextRef, Transact, TransactionAcqError, TransactionMut,
-    UpdateEvent,
+    Any, Array, ArrayRef, BranchID, DeepObservable, DeleteSet, Doc, GetString, Map, MapRef,
+    Observable, Origin, Out, ReadTxn, StickyIndex, Subscription, Text, TextRef, Transact,
+    TransactionAcqError, TransactionMut, UpdateEvent, WriteTxn,
     types::{Events, map::MapEvent},
+    updates::decoder::Decode as _,
 };
 
 // Keep this in sync with the corresponding list in
 // frontend/yproxy.ts
 const MANAGED_KINDS: &[&str] = &["github", "github_pr"];
 
+// Keep this in sync with the corresponding list in
+// frontend/yproxy.ts
+pub(crate) const BOARD_STATUSES: &[&str] = &["Not Started", "In Progress", "Blocked", "Done"];
+
+/// Name of the root map holding the manual ordering of each board column,
+/// keyed by status.
+const BOARD_ORDER: &str = "boardOrder";
+
+/// Name of the root map holding the project's configuration.
+const CONFIG: &str = "config";
+
 pub(crate) struct YDocProxy {
     doc: Doc,
     graph: MapRef,
+    config: MapRef,
+    removed: RemovedTasks,
+}
+
+/// A task as it was before being removed from the graph.
+#[derive(Debug, Clone)]
+pub(crate) struct RemovedTask {
+    pub(crate) task: Task,
+    /// The tasks whose children included it, with its index among them.
+    pub(crate) parents: Vec<(String, usize)>,
+}
+
+/// Tasks removed from a doc, collected until taken, e.g. once the transaction
+/// removing them commits. Removed tasks can't be read back from the doc.
+#[derive(Clone, Default)]
+pub(crate) struct RemovedTasks(Arc<Mutex<Vec<RemovedTask>>>);
+
+impl RemovedTasks {
+    pub(crate) fn take(&self) -> Vec<RemovedTask> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
 }
 
 impl YDocProxy {
     pub fn new() -> Self {
         let doc = Doc::new();
         let graph = doc.get_or_insert_map("graph");
-        YDocProxy { doc, graph }
+        let config = doc.get_or_insert_map(CONFIG);
+        YDocProxy {
+            doc,
+            graph,
+            config,
+            removed: RemovedTasks::default(),
+        }
     }
 
     pub fn new_from_existing_doc<T: ReadTxn>(doc: Doc, txn: &T) -> Result<Self> {
         Ok(YDocProxy {
             doc,
             graph: txn.get_map("graph").context("graph map missing")?,
+            config: txn.get_map(CONFIG).context("config map missing")?,
+            removed: RemovedTasks::default(),
         })
     }
 
@@ -59,11 +108,77 @@ impl YDocProxy {
         y_task.set_url(txn, task.url.as_deref());
         y_task.set_kind(txn, task.kind.as_deref());
         y_task.set_estimate(txn, task.estimate);
-        y_task.set_deadline(txn, task.deadline);
+        y_task.set_deadline(txn, task.deadline.as_ref());
         y_task.set_archived(txn, task.archived);
         y_task
     }
 
+    /// Removes the task from the graph. Its parents' children aren't changed.
+    /// The task is snapshotted first, see [Self::removed].
+    pub fn delete(&self, txn: &mut TransactionMut, id: &str) {
+        if let Err(e) = self.snapshot_removed(txn, &HashSet::from([id.to_string()])) {
+            tracing::warn!("Failed to snapshot removed task {id}: {e:?}");
+        }
+        self.graph.remove(txn, id);
+    }
+
+    /// Snapshots the tasks the delete set removes from the graph, before it's
+    /// applied. Tasks are removed when the item holding them in the graph is
+    /// deleted. Other deletions, e.g. overwritten fields, are ignored.
+    pub fn snapshot_deleted<T: ReadTxn>(&self, txn: &T, delete_set: &DeleteSet) -> Result<()> {
+        if delete_set.is_empty() {
+            return Ok(());
+        }
+        let ids: HashSet<String> = self
+            .graph
+            .iter(txn)
+            .filter_map(|(id, task)| match task {
+                Out::YMap(task) => match task.as_ref().id() {
+                    BranchID::Nested(item) if delete_set.is_deleted(&item) => Some(id.to_string()),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        self.snapshot_removed(txn, &ids)
+    }
+
+    fn snapshot_removed<T: ReadTxn>(&self, txn: &T, ids: &HashSet<String>) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let mut removed = ids
+            .iter()
+            .filter_map(|id| self.get(txn, id).ok())
+            .map(|task| {
+                Ok(RemovedTask {
+                    task: task.to_task(txn)?,
+                    parents: Vec::new(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for parent in self.tasks(txn)? {
+            let children = parent.get_children(txn)?;
+            for (index, child) in children.iter().enumerate() {
+                if let Some(removed) = removed.iter_mut().find(|r| &r.task.id == child) {
+                    removed.parents.push((parent.get_id(txn)?, index));
+                }
+            }
+        }
+        self.removed
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend(removed);
+        Ok(())
+    }
+
+    /// The tasks removed, by [Self::delete] or by updates passed to
+    /// [Self::snapshot_deleted], since last taken.
+    pub fn removed(&self) -> &RemovedTasks {
+        &self.removed
+    }
+
     pub fn get<T: ReadTxn>(&self, txn: &T, id: &str) -> Result<YTaskProxy> {
         let Some(y_task) = self.graph.get(txn, id) else {
             return Err(anyhow!("task is missing: {id}"));
@@ -75,6 +190,10 @@ impl YDocProxy {
         Ok(YTaskProxy::new(y_task))
     }
 
+    pub fn config(&self) -> YProjectConfigProxy {
+        YProjectConfigProxy::new(self.config.clone())
+    }
+
     pub fn get_by_nums<T: ReadTxn>(
         &self,
         txn: &T,
@@ -84,10 +203,16 @@ impl YDocProxy {
             return Ok(Vec::with_capacity(0));
         }
 
+        // Accept keys, e.g. KOSO-123, as well as plain nums.
+        let nums: HashSet<&str> = nums
+            .iter()
+            .filter_map(|key| parse_task_key(key).map(|(_, num)| num))
+            .collect();
         let mut tasks = Vec::with_capacity(nums.len());
         for id in self.graph.keys(txn) {
             let task = self.get(txn, id)?;
-            if nums.contains(&task.get_num(txn)?) {
+            let num = task.get_num(txn)?;
+            if nums.contains(parse_task_key(&num).map_or(num.as_str(), |(_, num)| num)) {
                 tasks.push(task);
                 if tasks.len() == nums.len() {
                     break;
@@ -126,11 +251,177 @@ impl YDocProxy {
         self.doc.transact_mut_with(origin)
     }
 
+    /// Returns the outstanding work assigned to each user, in the project's
+    /// estimate unit: the sum of estimates, counting unestimated tasks as a
+    /// point, of unarchived tasks that are not Done.
+    pub fn workloads<T: ReadTxn>(&self, txn: &T) -> Result<HashMap<String, i64>> {
+        let unestimated = self
+            .config()
+            .get_estimates(txn)?
+            .unwrap_or_default()
+            .points_to_estimate(1.0);
+        let mut workloads = HashMap::new();
+        for task in self.tasks(txn)? {
+            let Some(assignee) = task.get_assignee(txn)? else {
+                continue;
+            };
+            if task.is_rollup(txn)?
+                || task.get_archived(txn)?.unwrap_or(false)
+                || task.get_status(txn)?.is_some_and(|s| s == "Done")
+            {
+                continue;
+            }
+            *workloads.entry(assignee).or_insert(0) +=
+                task.get_estimate(txn)?.unwrap_or(unestimated);
+        }
+        Ok(workloads)
+    }
+
+    /// Converts task estimates between units, e.g. when a project switches from
+    /// points to t-shirt sizes, snapping them to the nearest size. Negative
+    /// estimates are cleared. Returns the number of estimates changed.
+    pub fn convert_estimates(
+        &self,
+        txn: &mut TransactionMut,
+        from: &EstimateSettings,
+        to: &EstimateSettings,
+    ) -> Result<usize> {
+        let mut converted = 0;
+        for task in self.tasks(txn)? {
+            let Some(estimate) = task.get_estimate(txn)? else {
+                continue;
+            };
+            let new_estimate =
+                (estimate >= 0).then(|| to.points_to_estimate(from.to_points(estimate)));
+            if new_estimate != Some(estimate) {
+                task.set_estimate(txn, new_estimate);
+                converted += 1;
+            }
+        }
+        Ok(converted)
+    }
+
+    /// Tags all legacy, untyped deadlines as dates.
+    /// Returns the number of migrated tasks.
+    pub fn migrate_legacy_deadlines(&self, txn: &mut TransactionMut) -> Result<usize> {
+        let mut migrated = 0;
+        for task in self.tasks(txn)? {
+            if task.migrate_legacy_deadline(txn)? {
+                migrated += 1;
+            }
+        }
+        Ok(migrated)
+    }
+
+    /// Moves date time deadlines from UTC offsets to time zones.
+    /// Returns the number of tasks migrated.
+    pub fn migrate_deadline_offsets(&self, txn: &mut TransactionMut) -> Result<usize> {
+        let mut migrated = 0;
+        for task in self.tasks(txn)? {
+            if task.migrate_deadline_offset(txn)? {
+                migrated += 1;
+            }
+        }
+        Ok(migrated)
+    }
+
+    /// Clears negative estimates, which no unit allows, and records that docs
+    /// estimated before estimate settings existed estimate in points.
+    /// Returns the number of changes.
+    pub fn migrate_estimates(&self, txn: &mut TransactionMut) -> Result<usize> {
+        let mut migrated = 0;
+        let mut estimated = false;
+        for task in self.tasks(txn)? {
+            match task.get_estimate(txn)? {
+                Some(estimate) if estimate < 0 => {
+                    task.set_estimate(txn, None);
+                    migrated += 1;
+                }
+                Some(_) => estimated = true,
+                None => {}
+            }
+        }
+        if estimated && self.config().get_estimates(txn)?.is_none() {
+            self.config()
+                .set_field(txn, "estimates", &Some(EstimateSettings::default()))?;
+            migrated += 1;
+        }
+        Ok(migrated)
+    }
+
+    /// Returns the manual ordering of the given board column, as last written.
+    /// The stored order may be stale; see `board_column` for the effective order.
+    pub fn get_board_order<T: ReadTxn>(&self, txn: &T, status: &str) -> Result<Vec<String>> {
+        let Some(board_order) = txn.get_map(BOARD_ORDER) else {
+            return Ok(Vec::new());
+        };
+        let Some(y_order) = board_order.get(txn, status) else {
+            return Ok(Vec::new());
+        };
+        let Out::YArray(y_order) = y_order else {
+            return Err(anyhow!("invalid board order: {status}: {y_order}"));
+        };
+        y_order
+            .iter(txn)
+            .map(|item| match item {
+                Out::Any(Any::String(s)) => Ok(s.to_string()),
+                e => Err(anyhow!("invalid board order entry: {e}")),
+            })
+            .collect()
+    }
+
+    pub fn set_board_order(&self, txn: &mut TransactionMut, status: &str, order: &[String]) {
+        let board_order = txn.get_or_insert_map(BOARD_ORDER);
+        let y_order: ArrayRef = board_order.get_or_init(txn, status);
+        let old_order = match self.get_board_order(txn, status) {
+            Ok(o) => o,
+            Err(e) => {
+                tracing::warn!("invalid board order, clobbering order: {e:?}");
+                y_order.remove_range(txn, 0, y_order.len(txn));
+                y_order.insert_range(txn, 0, order.to_vec());
+                return;
+            }
+        };
+        update_array(txn, &y_order, &old_order, order);
+    }
+
+    /// Returns the ids of the tasks in the given board column in display order:
+    /// manually ordered tasks first, followed by the remaining tasks by number.
+    /// Rollup and archived tasks are excluded and tasks without a status are Not Started.
+    pub fn board_column<T: ReadTxn>(&self, txn: &T, status: &str) -> Result<Vec<String>> {
+        let mut unordered = Vec::new();
+        for task in self.tasks(txn)? {
+            if task.is_rollup(txn)? || task.get_archived(txn)?.unwrap_or(false) {
+                continue;
+            }
+            let task_status = task.get_status(txn)?;
+            if task_status.as_deref().unwrap_or("Not Started") != status {
+                continue;
+            }
+            let num = task.get_num(txn)?.parse::<u64>().unwrap_or(u64::MAX);
+            unordered.push((num, task.get_id(txn)?));
+        }
+
+        let mut column = Vec::with_capacity(unordered.len());
+        for id in self.get_board_order(txn, status)? {
+            if unordered.iter().any(|(_, u)| *u == id) && !column.contains(&id) {
+                column.push(id);
+            }
+        }
+        unordered.retain(|(_, id)| !column.contains(id));
+        unordered.sort();
+        column.extend(unordered.into_iter().map(|(_, id)| id));
+        Ok(column)
+    }
+
     /// Returns the next available task number. i.e max(num)+1
     pub fn next_num<T: ReadTxn>(&self, txn: &T) -> Result<u64> {
         let mut max_num = 0;
         for id in self.graph.keys(txn) {
-            let num = self.get(txn, id)?.get_num(txn)?.parse::<u64>()?;
+            let num = self.get(txn, id)?.get_num(txn)?;
+            let num = parse_task_key(&num)
+                .map_or(num.as_str(), |(_, num)| num)
+                .parse::<u64>()?;
             if num > max_num {
                 max_num = num;
             }
@@ -164,6 +455,7 @@ impl YTaskProxy {
             estimate: self.get_estimate(txn)?,
             deadline: self.get_deadline(txn)?,
             archived: self.get_archived(txn)?,
+            ..Task::default()
         })
     }
 
@@ -243,6 +535,18 @@ impl YTaskProxy {
         }
     }
 
+    /// Resolves a Yjs relative position, e.g. one encoded by `StickyIndex::encode_v1`,
+    /// into an offset within the description.
+    pub fn desc_index<T: ReadTxn>(&self, txn: &T, encoded: &[u8]) -> Result<Option<u32>> {
+        let Some(Out::YText(y_desc)) = self.y_task.get(txn, "desc") else {
+            return Ok(None);
+        };
+        let sticky = StickyIndex::decode_v1(encoded).context("invalid relative position")?;
+        Ok(sticky
+            .get_offset(txn)
+            .map(|offset| offset.index.min(y_desc.len(txn))))
+    }
+
     /// If this is ever actually used, implement diff ops.
     pub fn set_desc(&self, txn: &mut TransactionMut, desc: Option<&str>) {
         match desc {
@@ -286,47 +590,7 @@ impl YTaskProxy {
             }
         };
 
-        if old_children != *new_children {
-            let ops = capture_diff_slices(Algorithm::Myers, &old_children, new_children)
-                .into_iter()
-                .rev()
-                .collect::<Vec<_>>();
-
-            for ops in ops {
-                match ops {
-                    similar::DiffOp::Delete {
-                        old_index, old_len, ..
-                    } => {
-                        y_children.remove_range(txn, old_index as u32, old_len as u32);
-                    }
-                    similar::DiffOp::Insert {
-                        old_index,
-                        new_index,
-                        new_len,
-                    } => {
-                        y_children.insert_range(
-                            txn,
-                            old_index as u32,
-                            new_children[new_index..(new_index + new_len)].to_vec(),
-                        );
-                    }
-                    similar::DiffOp::Replace {
-                        old_index,
-                        old_len,
-                        new_index,
-                        new_len,
-                    } => {
-                        y_children.remove_range(txn, old_index as u32, old_len as u32);
-                        y_children.insert_range(
-                            txn,
-                            old_index as u32,
-                            new_children[new_index..(new_index + new_len)].to_vec(),
-                        );
-                    }
-                    _ => (),
-                }
-            }
-        }
+        update_array(txn, &y_children, &old_children, new_children);
     }
 
     /// Appends the given child.
@@ -401,12 +665,74 @@ impl YTaskProxy {
         self.y_task.try_update(txn, "estimate", status_time);
     }
 
-    pub fn get_deadline<T: ReadTxn>(&self, txn: &T) -> Result<Option<i64>> {
-        self.get_optional_number(txn, "deadline")
+    /// Deadlines are stored across three fields: `deadline`, the instant in millis,
+    /// `deadlineKind`, either "date" or "dateTime", and `deadlineTimeZone`, the IANA time zone
+    /// of "dateTime" deadlines. Deadlines without a kind predate typed deadlines and are dates.
+    pub fn get_deadline<T: ReadTxn>(&self, txn: &T) -> Result<Option<Deadline>> {
+        let Some(millis) = self.get_optional_number(txn, "deadline")? else {
+            return Ok(None);
+        };
+        match self.get_optional_string(txn, "deadlineKind")?.as_deref() {
+            None | Some("date") => Ok(Some(Deadline::from_legacy_millis(millis))),
+            Some("dateTime") => Ok(Some(Deadline::DateTime {
+                millis,
+                time_zone: self
+                    .get_optional_string(txn, "deadlineTimeZone")?
+                    .unwrap_or_else(|| "UTC".to_string()),
+            })),
+            Some(kind) => Err(anyhow!("invalid field: deadlineKind: {kind}")),
+        }
     }
 
-    pub fn set_deadline(&self, txn: &mut TransactionMut, status_time: Option<i64>) {
-        self.y_task.try_update(txn, "deadline", status_time);
+    pub fn set_deadline(&self, txn: &mut TransactionMut, deadline: Option<&Deadline>) {
+        match deadline {
+            Some(deadline) => {
+                self.y_task.try_update(txn, "deadline", deadline.millis());
+                match deadline {
+                    Deadline::Date { .. } => {
+                        self.y_task.try_update(txn, "deadlineKind", "date");
+                        self.y_task.try_update(txn, "deadlineTimeZone", Any::Null);
+                    }
+                    Deadline::DateTime { time_zone, .. } => {
+                        self.y_task.try_update(txn, "deadlineKind", "dateTime");
+                        self.y_task
+                            .try_update(txn, "deadlineTimeZone", time_zone.as_str());
+                    }
+                }
+            }
+            None => {
+                self.y_task.try_update(txn, "deadline", Any::Null);
+                self.y_task.try_update(txn, "deadlineKind", Any::Null);
+                self.y_task.try_update(txn, "deadlineTimeZone", Any::Null);
+            }
+        }
+    }
+
+    /// Tags a legacy, untyped deadline as a date.
+    /// Returns true if the task was migrated.
+    pub fn migrate_legacy_deadline(&self, txn: &mut TransactionMut) -> Result<bool> {
+        if self.get_optional_number(txn, "deadline")?.is_none()
+            || self.get_optional_string(txn, "deadlineKind")?.is_some()
+        {
+            return Ok(false);
+        }
+        self.y_task.try_update(txn, "deadlineKind", "date");
+        Ok(true)
+    }
+
+    /// Replaces the UTC offset that date time deadlines used to be stored with,
+    /// `deadlineOffset`, with the equivalent `Etc/GMT` time zone, or UTC if there's none.
+    /// Returns true if the task was migrated.
+    pub fn migrate_deadline_offset(&self, txn: &mut TransactionMut) -> Result<bool> {
+        let Some(offset) = self.get_optional_string(txn, "deadlineOffset")? else {
+            return Ok(false);
+        };
+        if self.get_optional_string(txn, "deadlineKind")?.as_deref() == Some("dateTime") {
+            self.y_task
+                .try_update(txn, "deadlineTimeZone", offset_time_zone(&offset));
+        }
+        self.y_task.remove(txn, "deadlineOffset");
+        Ok(true)
     }
     pub fn get_archived<T: ReadTxn>(&self, txn: &T) -> Result<Option<bool>> {
         self.get_optional_bool(txn, "archived")
@@ -416,6 +742,41 @@ impl YTaskProxy {
         self.y_task.try_update(txn, "archived", status_time);
     }
 
+    /// Auto assignment configuration is stored as a JSON encoded string.
+    pub fn get_auto_assign<T: ReadTxn>(&self, txn: &T) -> Result<Option<AutoAssign>> {
+        self.get_optional_string(txn, "autoAssign")?
+            .map(|auto_assign| {
+                serde_json::from_str(&auto_assign).context("invalid field: autoAssign")
+            })
+            .transpose()
+    }
+
+    pub fn set_auto_assign(
+        &self,
+        txn: &mut TransactionMut,
+        auto_assign: Option<&AutoAssign>,
+    ) -> Result<()> {
+        let auto_assign = auto_assign.map(serde_json::to_string).transpose()?;
+        self.y_task
+            .try_update(txn, "autoAssign", auto_assign.as_deref());
+        Ok(())
+    }
+
+    /// Labels are stored as a JSON encoded array of label names.
+    pub fn get_labels<T: ReadTxn>(&self, txn: &T) -> Result<Vec<String>> {
+        Ok(self
+            .get_optional_string(txn, "labels")?
+            .map(|labels| serde_json::from_str(&labels).context("invalid field: labels"))
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    pub fn set_labels(&self, txn: &mut TransactionMut, labels: &[String]) -> Result<()> {
+        let labels = serde_json::to_string(labels)?;
+        self.y_task.try_update(txn, "labels", labels);
+        Ok(())
+    }
+
     pub fn is_rollup<T: ReadTxn>(&self, txn: &T) -> Result<bool> {
         Ok(match self.get_kind(txn)? {
             Some(kind) => kind == "Rollup",
@@ -423,20 +784,527 @@ impl YTaskProxy {
         })
     }
 
+    /// Whether a plugin currently manages the task, keeping it in sync and
+    /// protecting it from other edits. Users can pause management of a task,
+    /// e.g. to annotate or re-parent it without the next sync undoing it.
     pub fn is_managed<T: ReadTxn>(&self, txn: &T) -> Result<bool> {
+        Ok(self.is_managed_kind(txn)? && !self.get_management_paused(txn)?)
+    }
+
+    /// Whether the task was created by a plugin, regardless of whether its
+    /// management is paused.
+    pub fn is_managed_kind<T: ReadTxn>(&self, txn: &T) -> Result<bool> {
         Ok(self
             .get_kind(txn)?
             .map(|kind| MANAGED_KINDS.contains(&kind.as_str()))
             .unwrap_or(false))
     }
+
+    pub fn get_management_paused<T: ReadTxn>(&self, txn: &T) -> Result<bool> {
+        Ok(self
+            .get_optional_bool(txn, "managementPaused")?
+            .unwrap_or(false))
+    }
+
+    pub fn set_management_paused(&self, txn: &mut TransactionMut, paused: bool) {
+        // Unset rather than false, like most tasks, when unpaused.
+        self.y_task
+            .try_update(txn, "managementPaused", paused.then_some(true));
+    }
+}
+
+/// Typed access to the project's configuration. Each setting is stored as
+/// a JSON string under its own key so concurrent edits to different settings merge.
+/// Setters validate the resulting configuration as a whole before writing.
+pub(crate) struct YProjectConfigProxy {
+    y_config: MapRef,
+}
+
+impl YProjectConfigProxy {
+    fn new(y_config: MapRef) -> Self {
+        YProjectConfigProxy { y_config }
+    }
+
+    pub fn get<T: ReadTxn>(&self, txn: &T) -> Result<ProjectConfig> {
+        Ok(ProjectConfig {
+            workflow_states: self.get_workflow_states(txn)?,
+            labels: self.get_labels(txn)?,
+            iteration: self.get_iteration(txn)?,
+            automation_rules: self.get_automation_rules(txn)?,
+            auto_archive: self.get_auto_archive(txn)?,
+            estimates: self.get_estimates(txn)?,
+        })
+    }
+
+    pub fn set(&self, txn: &mut TransactionMut, config: &ProjectConfig) -> Result<()> {
+        validate_config(config)?;
+        self.set_field(txn, "workflowStates", &config.workflow_states)?;
+        self.set_field(txn, "labels", &config.labels)?;
+        self.set_field(txn, "iteration", &config.iteration)?;
+        self.set_field(txn, "automationRules", &config.automation_rules)?;
+        self.set_field(txn, "autoArchive", &config.auto_archive)?;
+        self.set_field(txn, "estimates", &config.estimates)?;
+        Ok(())
+    }
+
+    pub fn get_workflow_states<T: ReadTxn>(&self, txn: &T) -> Result<Vec<WorkflowState>> {
+        Ok(self.get_field(txn, "workflowStates")?.unwrap_or_default())
+    }
+
+    pub fn set_workflow_states(
+        &self,
+        txn: &mut TransactionMut,
+        workflow_states: &[WorkflowState],
+    ) -> Result<()> {
+        validate_config(&ProjectConfig {
+            workflow_states: workflow_states.to_vec(),
+            ..self.get(txn)?
+        })?;
+        self.set_field(txn, "workflowStates", &workflow_states)
+    }
+
+    pub fn get_labels<T: ReadTxn>(&self, txn: &T) -> Result<Vec<Label>> {
+        Ok(self.get_field(txn, "labels")?.unwrap_or_default())
+    }
+
+    pub fn set_labels(&self, txn: &mut TransactionMut, labels: &[Label]) -> Result<()> {
+        validate_config(&ProjectConfig {
+            labels: labels.to_vec(),
+            ..self.get(txn)?
+        })?;
+        self.set_field(txn, "labels", &labels)
+    }
+
+    pub fn get_iteration<T: ReadTxn>(&self, txn: &T) -> Result<Option<IterationSettings>> {
+        Ok(self
+            .get_field::<_, Option<IterationSettings>>(txn, "iteration")?
+            .flatten())
+    }
+
+    pub fn set_iteration(
+        &self,
+        txn: &mut TransactionMut,
+        iteration: Option<&IterationSettings>,
+    ) -> Result<()> {
+        validate_config(&ProjectConfig {
+            iteration: iteration.cloned(),
+            ..self.get(txn)?
+        })?;
+        self.set_field(txn, "iteration", &iteration)
+    }
+
+    pub fn get_automation_rules<T: ReadTxn>(&self, txn: &T) -> Result<Vec<AutomationRule>> {
+        Ok(self.get_field(txn, "automationRules")?.unwrap_or_default())
+    }
+
+    pub fn set_automation_rules(
+        &self,
+        txn: &mut TransactionMut,
+        automation_rules: &[AutomationRule],
+    ) -> Result<()> {
+        validate_config(&ProjectConfig {
+            automation_rules: automation_rules.to_vec(),
+            ..self.get(txn)?
+        })?;
+        self.set_field(txn, "automationRules", &automation_rules)
+    }
+
+    pub fn get_auto_archive<T: ReadTxn>(&self, txn: &T) -> Result<Option<AutoArchiveSettings>> {
+        Ok(self
+            .get_field::<_, Option<AutoArchiveSettings>>(txn, "autoArchive")?
+            .flatten())
+    }
+
+    pub fn set_auto_archive(
+        &self,
+        txn: &mut TransactionMut,
+        auto_archive: Option<&AutoArchiveSettings>,
+    ) -> Result<()> {
+        validate_config(&ProjectConfig {
+            auto_archive: auto_archive.cloned(),
+            ..self.get(txn)?
+        })?;
+        self.set_field(txn, "autoArchive", &auto_archive)
+    }
+
+    pub fn get_estimates<T: ReadTxn>(&self, txn: &T) -> Result<Option<EstimateSettings>> {
+        Ok(self
+            .get_field::<_, Option<EstimateSettings>>(txn, "estimates")?
+            .flatten())
+    }
+
+    pub fn set_estimates(
+        &self,
+        txn: &mut TransactionMut,
+        estimates: Option<&EstimateSettings>,
+    ) -> Result<()> {
+        validate_config(&ProjectConfig {
+            estimates: estimates.cloned(),
+            ..self.get(txn)?
+        })?;
+        self.set_field(txn, "estimates", &estimates)
+    }
+
+    /// Puts back the previous config if it has since been changed to an
+    /// invalid one. Returns whether it was put back.
+    pub fn restore_if_invalid(
+        &self,
+        txn: &mut TransactionMut,
+        previous: &ProjectConfig,
+    ) -> Result<bool> {
+        let error = match self.get(txn) {
+            Ok(config) if &config == previous => return Ok(false),
+            Ok(config) => match validate_config(&config) {
+                Ok(()) => return Ok(false),
+                Err(e) => e,
+            },
+            Err(e) => e,
+        };
+        tracing::warn!("Rejecting invalid project config: {error:#}");
+        self.set(txn, previous)?;
+        Ok(true)
+    }
+
+    /// The doc's schema version, i.e. the latest doc migration applied to it.
+    /// Docs predating doc migrations are at version 0.
+    pub fn get_schema_version<T: ReadTxn>(&self, txn: &T) -> Result<u32> {
+        Ok(self.get_field(txn, "schemaVersion")?.unwrap_or(0))
+    }
+
+    pub fn set_schema_version(&self, txn: &mut TransactionMut, version: u32) -> Result<()> {
+        self.set_field(txn, "schemaVersion", &version)
+    }
+
+    /// Returns the statuses tasks may have: the custom workflow states
+    /// if any are configured, otherwise the built-in statuses.
+    pub fn statuses<T: ReadTxn>(&self, txn: &T) -> Result<Vec<String>> {
+        Ok(statuses(&self.get_workflow_states(txn)?))
+    }
+
+    pub fn status_category<T: ReadTxn>(
+        &self,
+        txn: &T,
+        status: &str,
+    ) -> Result<Option<WorkflowCategory>> {
+        Ok(status_category(&self.get_workflow_states(txn)?, status))
+    }
+
+    fn get_field<T: ReadTxn, V: DeserializeOwned>(
+        &self,
+        txn: &T,
+        field: &str,
+    ) -> Result<Option<V>> {
+        let Some(result) = self.y_config.get(txn, field) else {
+            return Ok(None);
+        };
+        match result {
+            Out::Any(Any::String(result)) => Ok(Some(
+                serde_json::from_str(&result)
+                    .with_context(|| format!("invalid config: {field}"))?,
+            )),
+            Out::Any(Any::Null) | Out::Any(Any::Undefined) => Ok(None),
+            _ => Err(anyhow!("invalid config: {field}: {result:?}")),
+        }
+    }
+
+    fn set_field<V: Serialize>(
+        &self,
+        txn: &mut TransactionMut,
+        field: &str,
+        value: &V,
+    ) -> Result<()> {
+        self.y_config
+            .try_update(txn, field, serde_json::to_string(value)?);
+        Ok(())
+    }
+}
+
+/// Returns the `Etc/GMT` time zone with the given UTC offset, e.g. "Etc/GMT-5" for "+05:00".
+/// Those zones only exist for whole hours; other offsets fall back to UTC.
+fn offset_time_zone(offset: &str) -> String {
+    let seconds = offset
+        .parse::<chrono::FixedOffset>()
+        .map(|offset| offset.local_minus_utc())
+        .unwrap_or_default();
+    if seconds == 0 || seconds % 3600 != 0 {
+        return "UTC".to_string();
+    }
+    // Etc/GMT zones are named with the sign inverted.
+    let time_zone = format!("Etc/GMT{:+}", -seconds / 3600);
+    if time_zone.parse::<chrono_tz::Tz>().is_ok() {
+        time_zone
+    } else {
+        "UTC".to_string()
+    }
+}
+
+fn statuses(workflow_states: &[WorkflowState]) -> Vec<String> {
+    if workflow_states.is_empty() {
+        BOARD_STATUSES.iter().map(|s| s.to_string()).collect()
+    } else {
+        workflow_states.iter().map(|s| s.name.clone()).collect()
+    }
+}
+
+/// Maps the status to its category, using the built-in statuses' categories
+/// when no custom workflow states are configured.
+pub(crate) fn status_category(
+    workflow_states: &[WorkflowState],
+    status: &str,
+) -> Option<WorkflowCategory> {
+    if workflow_states.is_empty() {
+        return match status {
+            "Not Started" => Some(WorkflowCategory::NotStarted),
+            "In Progress" => Some(WorkflowCategory::InProgress),
+            "Blocked" => Some(WorkflowCategory::Blocked),
+            "Done" => Some(WorkflowCategory::Done),
+            _ => None,
+        };
+    }
+    workflow_states
+        .iter()
+        .find(|s| s.name == status)
+        .map(|s| s.category)
+}
+
+/// Splits a task key, e.g. `KOSO-123`, `#123` or `123`, into its prefix, if
+/// any, and num. Returns None if the key isn't of one of those forms.
+pub(crate) fn parse_task_key(key: &str) -> Option<(Option<&str>, &str)> {
+    let key = key.trim();
+    let (prefix, num) = match key.rsplit_once('-') {
+        Some((prefix, num)) => {
+            if !is_valid_task_key_prefix(&prefix.to_ascii_uppercase()) {
+                return None;
+            }
+            (Some(prefix), num)
+        }
+        None => (None, key.strip_prefix('#').unwrap_or(key)),
+    };
+    if num.is_empty() || !num.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some((prefix, num))
+}
+
+/// Formats a task's key, e.g. `KOSO-123`, or `#123` in projects without a prefix.
+pub(crate) fn task_key(prefix: Option<&str>, num: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{prefix}-{num}"),
+        None => format!("#{num}"),
+    }
+}
+
+/// Prefixes are 2 to 10 uppercase letters or digits, starting with a letter.
+pub(crate) fn is_valid_task_key_prefix(prefix: &str) -> bool {
+    (2..=10).contains(&prefix.len())
+        && prefix.starts_with(|c: char| c.is_ascii_uppercase())
+        && prefix
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+}
+
+/// Returns the first status in the category, using the built-in statuses
+/// when no custom workflow states are configured.
+pub(crate) fn status_for_category(
+    workflow_states: &[WorkflowState],
+    category: WorkflowCategory,
+) -> Option<String> {
+    if workflow_states.is_empty() {
+        return Some(
+            match category {
+                WorkflowCategory::NotStarted => "Not Started",
+                WorkflowCategory::InProgress => "In Progress",
+                WorkflowCategory::Blocked => "Blocked",
+                WorkflowCategory::Done => "Done",
+            }
+            .to_string(),
+        );
+    }
+    workflow_states
+        .iter()
+        .find(|s| s.category == category)
+        .map(|s| s.name.clone())
+}
+
+pub(crate) const MAX_CONFIG_NAME_LEN: usize = 50;
+const MAX_ITERATION_DAYS: u32 = 90;
+const MAX_AUTO_ARCHIVE_DAYS: u32 = 3650;
+const MAX_HOURS_PER_POINT: u32 = 40;
+
+/// Checks that the configuration is internally consistent: names are present and unique,
+/// colors are hex colors and automation rules only reference configured statuses and labels.
+pub(crate) fn validate_config(config: &ProjectConfig) -> Result<()> {
+    fn validate_names<'a>(kind: &str, names: impl Iterator<Item = &'a str>) -> Result<()> {
+        let mut seen = HashSet::new();
+        for name in names {
+            if name.trim().is_empty() || name.len() > MAX_CONFIG_NAME_LEN {
+                return Err(anyhow!(
+                    "{kind} names must be between 1 and {MAX_CONFIG_NAME_LEN} characters"
+                ));
+            }
+            if !seen.insert(name.to_lowercase()) {
+                return Err(anyhow!("Duplicate {kind}: {name}"));
+            }
+        }
+        Ok(())
+    }
+
+    validate_names(
+        "workflow state",
+        config.workflow_states.iter().map(|s| s.name.as_str()),
+    )?;
+    validate_names("label", config.labels.iter().map(|l| l.name.as_str()))?;
+    validate_names(
+        "automation rule",
+        config.automation_rules.iter().map(|r| r.id.as_str()),
+    )?;
+
+    for label in &config.labels {
+        let color = label.color.strip_prefix('#').unwrap_or_default();
+        if color.len() != 6 || !color.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow!(
+                "Invalid color for label {}: {}",
+                label.name,
+                label.color
+            ));
+        }
+    }
+
+    if let Some(iteration) = &config.iteration {
+        if !(1..=MAX_ITERATION_DAYS).contains(&iteration.length_days) {
+            return Err(anyhow!(
+                "Iterations must be between 1 and {MAX_ITERATION_DAYS} days"
+            ));
+        }
+    }
+
+    if let Some(auto_archive) = &config.auto_archive {
+        if !(1..=MAX_AUTO_ARCHIVE_DAYS).contains(&auto_archive.after_days) {
+            return Err(anyhow!(
+                "Tasks must be archived after between 1 and {MAX_AUTO_ARCHIVE_DAYS} days"
+            ));
+        }
+        if let Some(label) = &auto_archive.exclude_label {
+            if !config.labels.iter().any(|l| &l.name == label) {
+                return Err(anyhow!("Unknown label: {label}"));
+            }
+        }
+    }
+
+    if let Some(estimates) = &config.estimates {
+        if estimates.unit == EstimateUnit::TShirt {
+            if estimates.sizes.is_empty() {
+                return Err(anyhow!("T-shirt estimates require at least one size"));
+            }
+            validate_names(
+                "estimate size",
+                estimates.sizes.iter().map(|s| s.name.as_str()),
+            )?;
+            if estimates.sizes.first().is_some_and(|s| s.points <= 0)
+                || estimates
+                    .sizes
+                    .windows(2)
+                    .any(|w| w[0].points >= w[1].points)
+            {
+                return Err(anyhow!(
+                    "T-shirt sizes must be worth a positive, increasing number of points"
+                ));
+            }
+        }
+        if let Some(hours_per_point) = estimates.hours_per_point {
+            if !(1..=MAX_HOURS_PER_POINT).contains(&hours_per_point) {
+                return Err(anyhow!(
+                    "Points must be worth between 1 and {MAX_HOURS_PER_POINT} hours"
+                ));
+            }
+        }
+        if estimates.capacity.is_some_and(|c| c <= 0) {
+            return Err(anyhow!("Capacity must be positive"));
+        }
+    }
+
+    let statuses = statuses(&config.workflow_states);
+    let validate_status = |status: &String| {
+        if statuses.contains(status) {
+            Ok(())
+        } else {
+            Err(anyhow!("Unknown status: {status}"))
+        }
+    };
+    for rule in &config.automation_rules {
+        match &rule.trigger {
+            AutomationTrigger::TaskCreated => {}
+            AutomationTrigger::StatusChanged { to } => validate_status(to)?,
+        }
+        match &rule.action {
+            AutomationAction::SetStatus { status } => validate_status(status)?,
+            AutomationAction::AddLabel { label } => {
+                if !config.labels.iter().any(|l| &l.name == label) {
+                    return Err(anyhow!("Unknown label: {label}"));
+                }
+            }
+            AutomationAction::Assign { assignee } => {
+                if assignee.trim().is_empty() {
+                    return Err(anyhow!("Automation rule {} has no assignee", rule.id));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Applies the minimal set of inserts and removes to transform `y_array` from `old` to `new`.
+fn update_array(txn: &mut TransactionMut, y_array: &ArrayRef, old: &[String], new: &[String]) {
+    if old != new {
+        let ops = capture_diff_slices(Algorithm::Myers, old, new)
+            .into_iter()
+            .rev()
+            .collect::<Vec<_>>();
+
+        for ops in ops {
+            match ops {
+                similar::DiffOp::Delete {
+                    old_index, old_len, ..
+                } => {
+                    y_array.remove_range(txn, old_index as u32, old_len as u32);
+                }
+                similar::DiffOp::Insert {
+                    old_index,
+                    new_index,
+                    new_len,
+                } => {
+                    y_array.insert_range(
+                        txn,
+                        old_index as u32,
+                        new[new_index..(new_index + new_len)].to_vec(),
+                    );
+                }
+                similar::DiffOp::Replace {
+                    old_index,
+                    old_len,
+                    new_index,
+                    new_len,
+                } => {
+                    y_array.remove_range(txn, old_index as u32, old_len as u32);
+                    y_array.insert_range(
+                        txn,
+                        old_index as u32,
+                        new[new_index..(new_index + new_len)].to_vec(),
+                    );
+                }
+                _ => (),
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::api::{
         collab::txn_origin::{self, YOrigin},
-        model::test_utils::new_with_fields_populated,
+        model::{EstimateSize, test_utils::new_with_fields_populated},
     };
+    use yrs::{Assoc, IndexedSequence, updates::encoder::Encode as _};
 
     use super::*;
 
@@ -488,11 +1356,422 @@ mod tests {
         }
     }
 
+    #[test]
+    fn pausing_management_unmanages_task() {
+        let ydoc = YDocProxy::new();
+        let task = Task {
+            id: "id1".to_string(),
+            num: "1".to_string(),
+            name: "PR 1".to_string(),
+            kind: Some("github_pr".to_string()),
+            ..Task::default()
+        };
+        let mut txn = ydoc.transact_mut_with(origin());
+        let y_task = ydoc.set(&mut txn, &task);
+        assert!(y_task.is_managed(&txn).unwrap());
+
+        y_task.set_management_paused(&mut txn, true);
+        assert!(!y_task.is_managed(&txn).unwrap());
+        assert!(y_task.is_managed_kind(&txn).unwrap());
+
+        y_task.set_management_paused(&mut txn, false);
+        assert!(y_task.is_managed(&txn).unwrap());
+        assert_eq!(y_task.to_task(&txn).unwrap(), task);
+    }
+
+    #[test]
+    fn migrate_legacy_deadlines_tags_dates() {
+        let ydoc = YDocProxy::new();
+        let task = Task {
+            id: "id1".to_string(),
+            num: "1".to_string(),
+            name: "Task 1".to_string(),
+            ..Task::default()
+        };
+        {
+            let mut txn = ydoc.transact_mut_with(origin());
+            let y_task = ydoc.set(&mut txn, &task);
+            // Simulate an old client writing a bare deadline.
+            y_task
+                .y_task
+                .try_update(&mut txn, "deadline", 1735689600000_i64);
+        }
+
+        let mut txn = ydoc.transact_mut_with(origin());
+        assert_eq!(ydoc.migrate_legacy_deadlines(&mut txn).unwrap(), 1);
+        assert_eq!(ydoc.migrate_legacy_deadlines(&mut txn).unwrap(), 0);
+        assert_eq!(
+            ydoc.get(&txn, "id1").unwrap().get_deadline(&txn).unwrap(),
+            Some(Deadline::from_legacy_millis(1735689600000))
+        );
+    }
+
+    #[test]
+    fn migrate_deadline_offsets_sets_time_zones() {
+        let ydoc = YDocProxy::new();
+        let mut txn = ydoc.transact_mut_with(origin());
+        for (id, num, offset) in [("a", "1", "+05:00"), ("b", "2", "+05:30")] {
+            let y_task = ydoc.set(
+                &mut txn,
+                &Task {
+                    id: id.to_string(),
+                    num: num.to_string(),
+                    ..Task::default()
+                },
+            );
+            // Simulate a date time deadline stored with a UTC offset.
+            y_task
+                .y_task
+                .try_update(&mut txn, "deadline", 1735689600000_i64);
+            y_task
+                .y_task
+                .try_update(&mut txn, "deadlineKind", "dateTime");
+            y_task.y_task.try_update(&mut txn, "deadlineOffset", offset);
+        }
+
+        assert_eq!(ydoc.migrate_deadline_offsets(&mut txn).unwrap(), 2);
+        assert_eq!(ydoc.migrate_deadline_offsets(&mut txn).unwrap(), 0);
+        assert_eq!(
+            ydoc.get(&txn, "a").unwrap().get_deadline(&txn).unwrap(),
+            Some(Deadline::DateTime {
+                millis: 1735689600000,
+                time_zone: "Etc/GMT-5".to_string()
+            })
+        );
+        assert_eq!(
+            ydoc.get(&txn, "b").unwrap().get_deadline(&txn).unwrap(),
+            Some(Deadline::DateTime {
+                millis: 1735689600000,
+                time_zone: "UTC".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn board_column_orders_manual_tasks_first() {
+        let ydoc = YDocProxy::new();
+        let mut txn = ydoc.transact_mut_with(origin());
+        for (id, num, status) in [
+            ("a", "1", None),
+            ("b", "2", Some("Not Started")),
+            ("c", "3", Some("Not Started")),
+            ("d", "4", Some("Done")),
+        ] {
+            ydoc.set(
+                &mut txn,
+                &Task {
+                    id: id.to_string(),
+                    num: num.to_string(),
+                    status: status.map(String::from),
+                    ..Task::default()
+                },
+            );
+        }
+        assert_eq!(
+            ydoc.board_column(&txn, "Not Started").unwrap(),
+            vec!["a", "b", "c"]
+        );
+
+        ydoc.set_board_order(
+            &mut txn,
+            "Not Started",
+            &["c".into(), "d".into(), "a".into()],
+        );
+        assert_eq!(
+            ydoc.get_board_order(&txn, "Not Started").unwrap(),
+            vec!["c", "d", "a"]
+        );
+        // Tasks no longer in the column are skipped and unordered tasks follow.
+        assert_eq!(
+            ydoc.board_column(&txn, "Not Started").unwrap(),
+            vec!["c", "a", "b"]
+        );
+        assert_eq!(ydoc.board_column(&txn, "Done").unwrap(), vec!["d"]);
+    }
+
+    #[test]
+    fn desc_sticky_index_follows_edits() {
+        let ydoc = YDocProxy::new();
+        let mut txn = ydoc.transact_mut_with(origin());
+        let task = ydoc.set(
+            &mut txn,
+            &Task {
+                id: "id1".to_string(),
+                num: "1".to_string(),
+                desc: Some("world".to_string()),
+                ..Task::default()
+            },
+        );
+        let Some(Out::YText(y_desc)) = task.y_task.get(&txn, "desc") else {
+            panic!("missing desc");
+        };
+        let encoded = y_desc
+            .sticky_index(&mut txn, 2, Assoc::After)
+            .unwrap()
+            .encode_v1();
+        assert_eq!(task.desc_index(&txn, &encoded).unwrap(), Some(2));
+
+        y_desc.insert(&mut txn, 0, "hello ");
+        assert_eq!(task.desc_index(&txn, &encoded).unwrap(), Some(8));
+    }
+
+    #[test]
+    fn config_set_and_get_succeeds() {
+        let ydoc = YDocProxy::new();
+        let config = ProjectConfig {
+            workflow_states: vec![
+                WorkflowState {
+                    name: "Todo".to_string(),
+                    category: crate::api::model::WorkflowCategory::NotStarted,
+                },
+                WorkflowState {
+                    name: "Shipped".to_string(),
+                    category: crate::api::model::WorkflowCategory::Done,
+                },
+            ],
+            labels: vec![Label {
+                name: "bug".to_string(),
+                color: "#d73a4a".to_string(),
+            }],
+            iteration: Some(IterationSettings {
+                length_days: 14,
+                start_date: sqlx::types::chrono::NaiveDate::from_ymd_opt(2025, 7, 7).unwrap(),
+            }),
+            automation_rules: vec![AutomationRule {
+                id: "r1".to_string(),
+                trigger: AutomationTrigger::StatusChanged {
+                    to: "Shipped".to_string(),
+                },
+                action: AutomationAction::AddLabel {
+                    label: "bug".to_string(),
+                },
+            }],
+            auto_archive: Some(AutoArchiveSettings {
+                after_days: 30,
+                exclude_label: Some("bug".to_string()),
+            }),
+            estimates: Some(EstimateSettings {
+                unit: EstimateUnit::Hours,
+                sizes: vec![],
+                hours_per_point: Some(6),
+                capacity: Some(60),
+            }),
+        };
+
+        let mut txn = ydoc.transact_mut_with(origin());
+        assert_eq!(ydoc.config().get(&txn).unwrap(), ProjectConfig::default());
+        ydoc.config().set(&mut txn, &config).unwrap();
+        assert_eq!(ydoc.config().get(&txn).unwrap(), config);
+        assert_eq!(
+            ydoc.config().statuses(&txn).unwrap(),
+            vec!["Todo".to_string(), "Shipped".to_string()]
+        );
+
+        // Removing a label referenced by a rule is rejected and leaves the config untouched.
+        assert!(ydoc.config().set_labels(&mut txn, &[]).is_err());
+        assert_eq!(ydoc.config().get(&txn).unwrap(), config);
+
+        // Settings can be changed one at a time.
+        ydoc.config().set_iteration(&mut txn, None).unwrap();
+        ydoc.config()
+            .set_estimates(&mut txn, Some(&EstimateSettings::default()))
+            .unwrap();
+        ydoc.config().set_automation_rules(&mut txn, &[]).unwrap();
+        ydoc.config().set_workflow_states(&mut txn, &[]).unwrap();
+        assert_eq!(
+            ydoc.config().get(&txn).unwrap(),
+            ProjectConfig {
+                workflow_states: vec![],
+                iteration: None,
+                automation_rules: vec![],
+                estimates: Some(EstimateSettings::default()),
+                ..config
+            }
+        );
+    }
+
+    #[test]
+    fn restore_if_invalid_reverts_invalid_config() {
+        let ydoc = YDocProxy::new();
+        let mut txn = ydoc.transact_mut_with(origin());
+        let config = ProjectConfig {
+            labels: vec![Label {
+                name: "bug".to_string(),
+                color: "#d73a4a".to_string(),
+            }],
+            ..ProjectConfig::default()
+        };
+        ydoc.config().set(&mut txn, &config).unwrap();
+        assert!(!ydoc.config().restore_if_invalid(&mut txn, &config).unwrap());
+
+        // A client writes the config map directly, bypassing validation.
+        ydoc.config
+            .insert(&mut txn, "labels", r#"[{"name":"bug","color":"red"}]"#);
+        assert!(ydoc.config().restore_if_invalid(&mut txn, &config).unwrap());
+        assert_eq!(ydoc.config().get(&txn).unwrap(), config);
+
+        ydoc.config.insert(&mut txn, "labels", "not json");
+        assert!(ydoc.config().restore_if_invalid(&mut txn, &config).unwrap());
+        assert_eq!(ydoc.config().get(&txn).unwrap(), config);
+
+        // Valid changes are kept.
+        ydoc.config.insert(&mut txn, "labels", "[]");
+        assert!(!ydoc.config().restore_if_invalid(&mut txn, &config).unwrap());
+        assert_eq!(ydoc.config().get(&txn).unwrap(), ProjectConfig::default());
+    }
+
+    #[test]
+    fn validate_config_rejects_invalid_settings() {
+        let label = |name: &str, color: &str| Label {
+            name: name.to_string(),
+            color: color.to_string(),
+        };
+        for labels in [
+            vec![label("bug", "red")],
+            vec![label("bug", "#ff0000"), label("Bug", "#00ff00")],
+            vec![label(" ", "#ff0000")],
+        ] {
+            let config = ProjectConfig {
+                labels,
+                ..ProjectConfig::default()
+            };
+            assert!(validate_config(&config).is_err(), "{config:?}");
+        }
+
+        let config = ProjectConfig {
+            automation_rules: vec![AutomationRule {
+                id: "r1".to_string(),
+                trigger: AutomationTrigger::TaskCreated,
+                action: AutomationAction::SetStatus {
+                    status: "In Progress".to_string(),
+                },
+            }],
+            ..ProjectConfig::default()
+        };
+        assert!(validate_config(&config).is_ok());
+
+        for auto_archive in [
+            AutoArchiveSettings {
+                after_days: 0,
+                exclude_label: None,
+            },
+            AutoArchiveSettings {
+                after_days: 30,
+                exclude_label: Some("keep".to_string()),
+            },
+        ] {
+            let config = ProjectConfig {
+                auto_archive: Some(auto_archive),
+                ..ProjectConfig::default()
+            };
+            assert!(validate_config(&config).is_err(), "{config:?}");
+        }
+    }
+
+    #[test]
+    fn convert_estimates_snaps_to_sizes() {
+        let ydoc = YDocProxy::new();
+        let mut txn = ydoc.transact_mut_with(origin());
+        for (id, estimate) in [("1", Some(1)), ("2", Some(4)), ("3", Some(13)), ("4", None)] {
+            ydoc.set(
+                &mut txn,
+                &Task {
+                    id: id.to_string(),
+                    num: id.to_string(),
+                    estimate,
+                    ..Task::default()
+                },
+            );
+        }
+        let size = |name: &str, points| EstimateSize {
+            name: name.to_string(),
+            points,
+        };
+        let t_shirt = EstimateSettings {
+            unit: EstimateUnit::TShirt,
+            sizes: vec![size("S", 1), size("M", 3), size("L", 8)],
+            ..EstimateSettings::default()
+        };
+        assert!(
+            validate_config(&ProjectConfig {
+                estimates: Some(t_shirt.clone()),
+                ..ProjectConfig::default()
+            })
+            .is_ok()
+        );
+
+        let converted = ydoc
+            .convert_estimates(&mut txn, &EstimateSettings::default(), &t_shirt)
+            .unwrap();
+        assert_eq!(converted, 2);
+        let estimate = |id| ydoc.get(&txn, id).unwrap().get_estimate(&txn).unwrap();
+        assert_eq!(estimate("1"), Some(1));
+        assert_eq!(estimate("2"), Some(3));
+        assert_eq!(estimate("3"), Some(8));
+        assert_eq!(estimate("4"), None);
+
+        let unordered = EstimateSettings {
+            sizes: vec![size("M", 3), size("S", 1)],
+            ..t_shirt
+        };
+        assert!(
+            validate_config(&ProjectConfig {
+                estimates: Some(unordered),
+                ..ProjectConfig::default()
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn parse_task_key_accepts_prefixed_and_plain_nums() {
+        assert_eq!(parse_task_key("KOSO-123"), Some((Some("KOSO"), "123")));
+        assert_eq!(parse_task_key("koso-7"), Some((Some("koso"), "7")));
+        assert_eq!(parse_task_key("#12"), Some((None, "12")));
+        assert_eq!(parse_task_key(" 12 "), Some((None, "12")));
+        assert_eq!(parse_task_key("KOSO-"), None);
+        assert_eq!(parse_task_key("utf-8x"), None);
+        assert_eq!(parse_task_key("1-2"), None);
+        assert_eq!(parse_task_key("name"), None);
+        assert_eq!(task_key(Some("KOSO"), "5"), "KOSO-5");
+        assert_eq!(task_key(None, "5"), "#5");
+    }
+
+    #[test]
+    fn get_by_nums_and_next_num_accept_prefixed_nums() {
+        let ydoc = YDocProxy::new();
+        let mut txn = ydoc.transact_mut_with(origin());
+        for (id, num) in [("a", "1"), ("b", "KOSO-4"), ("c", "2")] {
+            ydoc.set(
+                &mut txn,
+                &Task {
+                    id: id.to_string(),
+                    num: num.to_string(),
+                    ..Task::default()
+                },
+            );
+        }
+        assert_eq!(ydoc.next_num(&txn).unwrap(), 5);
+        let tasks = ydoc
+            .get_by_nums(
+                &txn,
+                &HashSet::from(["KOSO-1".to_string(), "#4".to_string()]),
+            )
+            .unwrap();
+        let mut ids = tasks
+            .iter()
+            .map(|t| t.get_id(&txn).unwrap())
+            .collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, vec!["a".to_string(), "b".to_string()]);
+    }
+
     fn origin() -> Origin {
         YOrigin {
             who: "set_and_get_task_succeeds".to_string(),
             id: "test".to_string(),
             actor: txn_origin::Actor::Server,
+            ..Default::default()
         }
         .as_origin()
         .unwrap()
@@ -0,0 +1,574 @@
+//! Triggers and actions for no-code automation platforms like Zapier and Make.
+//!
+//! Requests authenticate with a per-user API key, sent as a bearer token,
+//! and act on behalf of the key's owner. Responses are flat JSON objects,
+//! and triggers return arrays with a unique `id` per item, newest first,
+//! which is what Zapier expects for polling triggers and deduplication.
+
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        collab::{
+            Collab,
+            txn_origin::{Actor, TxnMetadata, YOrigin},
+        },
+        context,
+        google::User,
+        hash_token,
+        model::{ProjectId, Task},
+        not_found_error,
+        projects::{fetch_task_key_prefix, list_projects},
+        proposals::transact_or_propose,
+        step_up, unauthorized_error, verify_project_access,
+        yproxy::{YDocProxy, YTaskProxy, parse_task_key, task_key},
+    },
+    postgres::{PgPool, list_project_users},
+};
+use anyhow::Context as _;
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    http::{HeaderMap, header::AUTHORIZATION},
+    middleware,
+    routing::{get, post, put},
+};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    FromRow,
+    types::chrono::{DateTime, Utc},
+};
+use std::collections::HashSet;
+use tower_http::request_id::RequestId;
+use uuid::Uuid;
+use yrs::ReadTxn;
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 100;
+const MAX_NAME_LEN: usize = 1024;
+
+/// Routes used by users to manage their API key.
+pub(super) fn keys_router() -> Router {
+    Router::new()
+        .route(
+            "/zapier-key",
+            get(get_key_handler).delete(delete_key_handler),
+        )
+        .route(
+            "/zapier-key",
+            post(create_key_handler).route_layer(middleware::from_fn(step_up::require_step_up)),
+        )
+}
+
+/// Routes invoked by automation platforms and authenticated with API keys.
+pub(super) fn api_router() -> Router {
+    Router::new()
+        .route("/me", get(me_handler))
+        .route("/projects", get(list_projects_handler))
+        .route(
+            "/projects/{project_id}/tasks/new",
+            get(list_new_tasks_handler),
+        )
+        .route("/projects/{project_id}/tasks", post(create_task_handler))
+        .route(
+            "/projects/{project_id}/tasks/{task}/status",
+            put(update_status_handler),
+        )
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ApiKey {
+    key: String,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ApiKeyInfo {
+    create_time: DateTime<Utc>,
+    last_used_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Me {
+    email: String,
+    name: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ZapierProject {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct NewTasksQuery {
+    /// Only return tasks created after the task with this cursor.
+    cursor: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct TaskSummary {
+    id: String,
+    /// Orders tasks by creation. Pass the largest seen as the cursor of the next poll.
+    cursor: Option<i64>,
+    key: String,
+    name: String,
+    assignee: Option<String>,
+    status: Option<String>,
+    url: String,
+    /// True if the change was held for review by a project admin rather than applied.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pending_review: bool,
+}
+
+#[derive(FromRow)]
+struct NewTaskRow {
+    task_id: String,
+    seq: i64,
+    num: String,
+    name: String,
+    assignee: Option<String>,
+    status: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct CreateTask {
+    pub(super) name: String,
+    /// A task id or key. Defaults to the root.
+    pub(super) parent_id: Option<String>,
+    pub(super) description: Option<String>,
+    pub(super) assignee: Option<String>,
+    pub(super) status: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UpdateStatus {
+    status: String,
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn get_key_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<Option<ApiKeyInfo>>> {
+    let info: Option<ApiKeyInfo> = sqlx::query_as(
+        "
+        SELECT create_time, last_used_time
+        FROM zapier_keys
+        WHERE email = $1",
+    )
+    .bind(&user.email)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch API key")?;
+    Ok(Json(info))
+}
+
+/// Creates the user's API key, replacing, and so revoking, any existing one.
+/// The key is only returned once.
+#[tracing::instrument(skip(user, pool))]
+async fn create_key_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<ApiKey>> {
+    let key = format!("kzk_{}", Uuid::new_v4().simple());
+    sqlx::query(
+        "
+        INSERT INTO zapier_keys (email, key_hash)
+        VALUES ($1, $2)
+        ON CONFLICT (email)
+        DO UPDATE SET
+            key_hash = EXCLUDED.key_hash,
+            create_time = NOW(),
+            last_used_time = NULL",
+    )
+    .bind(&user.email)
+    .bind(hash_token(&key))
+    .execute(pool)
+    .await
+    .context("Failed to upsert API key")?;
+    Ok(Json(ApiKey { key }))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn delete_key_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<()>> {
+    sqlx::query("DELETE FROM zapier_keys WHERE email = $1")
+        .bind(&user.email)
+        .execute(pool)
+        .await
+        .context("Failed to delete API key")?;
+    Ok(Json(()))
+}
+
+/// Used by automation platforms to test the credentials and label the connection.
+#[tracing::instrument(skip(pool, headers))]
+async fn me_handler(
+    Extension(pool): Extension<&'static PgPool>,
+    headers: HeaderMap,
+) -> ApiResult<Json<Me>> {
+    let user = authenticate(pool, &headers).await?;
+    Ok(Json(Me {
+        email: user.email,
+        name: user.name,
+    }))
+}
+
+/// Lists the user's projects, e.g. to populate a dropdown.
+#[tracing::instrument(skip(pool, headers))]
+async fn list_projects_handler(
+    Extension(pool): Extension<&'static PgPool>,
+    headers: HeaderMap,
+) -> ApiResult<Json<Vec<ZapierProject>>> {
+    let user = authenticate(pool, &headers).await?;
+    let mut projects: Vec<ZapierProject> = list_projects(&user.email, pool)
+        .await?
+        .into_iter()
+        .filter(|p| p.deleted_on.is_none())
+        .map(|p| ZapierProject {
+            id: p.project_id,
+            name: p.name,
+        })
+        .collect();
+    projects.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(Json(projects))
+}
+
+/// Polling trigger returning tasks created after the cursor, newest first.
+#[tracing::instrument(skip(pool, headers))]
+async fn list_new_tasks_handler(
+    Extension(pool): Extension<&'static PgPool>,
+    headers: HeaderMap,
+    Path(project_id): Path<ProjectId>,
+    Query(query): Query<NewTasksQuery>,
+) -> ApiResult<Json<Vec<TaskSummary>>> {
+    let user = authenticate(pool, &headers).await?;
+    verify_project_access(pool, &user, &project_id).await?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(bad_request_error(
+            "INVALID_LIMIT",
+            &format!("Limit must be between 1 and {MAX_LIMIT}"),
+        ));
+    }
+    let task_key_prefix = fetch_task_key_prefix(pool, &project_id).await?;
+    let rows: Vec<NewTaskRow> = sqlx::query_as(
+        "
+        SELECT task_id, seq, num, name, assignee, status
+        FROM task_projections
+        WHERE project_id = $1
+        AND task_id != 'root'
+        AND seq > $2
+        ORDER BY seq DESC
+        LIMIT $3",
+    )
+    .bind(&project_id)
+    .bind(query.cursor.unwrap_or(0))
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list new tasks")?;
+    Ok(Json(
+        rows.into_iter()
+            .map(|row| TaskSummary {
+                url: task_url(&project_id, &row.task_id),
+                key: task_key(task_key_prefix.as_deref(), &row.num),
+                id: row.task_id,
+                cursor: Some(row.seq),
+                name: row.name,
+                assignee: row.assignee,
+                status: row.status,
+                pending_review: false,
+            })
+            .collect(),
+    ))
+}
+
+#[tracing::instrument(skip(pool, collab, headers))]
+async fn create_task_handler(
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Extension(request_id): Extension<RequestId>,
+    headers: HeaderMap,
+    Path(project_id): Path<ProjectId>,
+    Json(req): Json<CreateTask>,
+) -> ApiResult<Json<TaskSummary>> {
+    let user = authenticate(pool, &headers).await?;
+    let origin = origin(&user, &request_id, "create");
+    Ok(Json(
+        create_task(pool, &collab, &user, &project_id, req, origin).await?,
+    ))
+}
+
+/// Creates a task, under the root unless a parent is given.
+pub(super) async fn create_task(
+    pool: &'static PgPool,
+    collab: &Collab,
+    user: &User,
+    project_id: &ProjectId,
+    req: CreateTask,
+    origin: YOrigin,
+) -> ApiResult<TaskSummary> {
+    verify_project_access(pool, user, project_id).await?;
+    let name = req.name.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(bad_request_error(
+            "INVALID_NAME",
+            &format!("Name must be 1 to {MAX_NAME_LEN} characters"),
+        ));
+    }
+    let assignee = req
+        .assignee
+        .as_deref()
+        .map(|a| a.trim().to_lowercase())
+        .filter(|a| !a.is_empty());
+    if let Some(assignee) = &assignee {
+        verify_member(pool, project_id, assignee).await?;
+    }
+    let task_key_prefix = fetch_task_key_prefix(pool, project_id).await?;
+
+    let transacted = transact_or_propose(pool, collab, project_id, &origin, |doc, txn| {
+        let parent = match &req.parent_id {
+            Some(parent_id) => find_task(doc, txn, parent_id, task_key_prefix.as_deref())?,
+            None => doc.get(txn, "root")?,
+        };
+        if let Some(status) = &req.status {
+            verify_status(doc, txn, status)?;
+        }
+        let task = Task {
+            id: BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4()),
+            num: doc.next_num(txn)?.to_string(),
+            name: name.to_string(),
+            desc: req.description.filter(|d| !d.trim().is_empty()),
+            assignee,
+            reporter: Some(user.email.clone()),
+            status_time: req.status.as_ref().map(|_| Utc::now().timestamp_millis()),
+            status: req.status,
+            ..Task::default()
+        };
+        doc.set(txn, &task);
+        parent.push_child(txn, &task.id)?;
+        Ok(task)
+    })
+    .await?;
+    Ok(summarize(
+        project_id,
+        task_key_prefix.as_deref(),
+        transacted.result,
+        transacted.proposed,
+    ))
+}
+
+/// Moves a task, identified by its id or key, to the given status.
+#[tracing::instrument(skip(pool, collab, headers))]
+async fn update_status_handler(
+    Extension(pool): Extension<&'static PgPool>,
+    Extension(collab): Extension<Collab>,
+    Extension(request_id): Extension<RequestId>,
+    headers: HeaderMap,
+    Path((project_id, task)): Path<(ProjectId, String)>,
+    Json(req): Json<UpdateStatus>,
+) -> ApiResult<Json<TaskSummary>> {
+    let user = authenticate(pool, &headers).await?;
+    let origin = origin(&user, &request_id, "status");
+    Ok(Json(
+        update_status(
+            pool,
+            &collab,
+            &user,
+            &project_id,
+            &task,
+            &req.status,
+            origin,
+        )
+        .await?,
+    ))
+}
+
+pub(super) async fn update_status(
+    pool: &'static PgPool,
+    collab: &Collab,
+    user: &User,
+    project_id: &ProjectId,
+    task: &str,
+    status: &str,
+    origin: YOrigin,
+) -> ApiResult<TaskSummary> {
+    verify_project_access(pool, user, project_id).await?;
+    let task_key_prefix = fetch_task_key_prefix(pool, project_id).await?;
+
+    let transacted = transact_or_propose(pool, collab, project_id, &origin, |doc, txn| {
+        let task = find_task(doc, txn, task, task_key_prefix.as_deref())?;
+        if task.is_managed(txn)? {
+            return Err(bad_request_error(
+                "MANAGED_TASK",
+                "Tasks managed by a plugin can't be updated",
+            ));
+        }
+        verify_status(doc, txn, status)?;
+        if task.get_status(txn)?.is_none_or(|s| s != status) {
+            task.set_status(txn, Some(status));
+            task.set_status_time(txn, Some(Utc::now().timestamp_millis()));
+        }
+        Ok(task.to_task(txn)?)
+    })
+    .await?;
+    Ok(summarize(
+        project_id,
+        task_key_prefix.as_deref(),
+        transacted.result,
+        transacted.proposed,
+    ))
+}
+
+fn summarize(
+    project_id: &ProjectId,
+    task_key_prefix: Option<&str>,
+    task: Task,
+    pending_review: bool,
+) -> TaskSummary {
+    TaskSummary {
+        url: task_url(project_id, &task.id),
+        key: task_key(task_key_prefix, &task.num),
+        id: task.id,
+        cursor: None,
+        name: task.name,
+        assignee: task.assignee,
+        status: task.status,
+        pending_review,
+    }
+}
+
+/// Returns the owner of the bearer API key.
+pub(super) async fn authenticate(pool: &PgPool, headers: &HeaderMap) -> ApiResult<User> {
+    let Some(key) = headers
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+    else {
+        return Err(unauthorized_error("Missing API key"));
+    };
+    authenticate_key(pool, key).await
+}
+
+/// Returns the owner of the API key.
+pub(super) async fn authenticate_key(pool: &PgPool, key: &str) -> ApiResult<User> {
+    let user: Option<(String, String, String)> = sqlx::query_as(
+        "
+        UPDATE zapier_keys
+        SET last_used_time = NOW()
+        FROM users
+        WHERE zapier_keys.email = users.email
+        AND key_hash = $1
+        RETURNING users.email, users.name, users.picture",
+    )
+    .bind(hash_token(key))
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up API key")?;
+    match user {
+        Some((email, name, picture)) => {
+            let user = User {
+                email,
+                name,
+                picture,
+                exp: 0,
+                iat: None,
+                auth_time: None,
+                nonce: None,
+            };
+            context::set_user(&user);
+            Ok(user)
+        }
+        None => Err(unauthorized_error("Invalid API key")),
+    }
+}
+
+fn origin(user: &User, request_id: &RequestId, action: &str) -> YOrigin {
+    let request_id = request_id.header_value().to_str().unwrap_or("INVALID");
+    YOrigin {
+        who: "zapier".to_string(),
+        id: format!("zapier_{action}_{request_id}"),
+        actor: Actor::Agent(user.clone()),
+        metadata: TxnMetadata {
+            request_id: Some(request_id.to_string()),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Finds a task by id or, failing that, by key, e.g. KOSO-12 or #12.
+pub(super) fn find_task<T: ReadTxn>(
+    doc: &YDocProxy,
+    txn: &T,
+    task: &str,
+    task_key_prefix: Option<&str>,
+) -> ApiResult<YTaskProxy> {
+    if let Ok(found) = doc.get(txn, task) {
+        return Ok(found);
+    }
+    let num = match parse_task_key(task) {
+        Some((None, num)) => num,
+        Some((Some(prefix), num))
+            if task_key_prefix.is_some_and(|p| p.eq_ignore_ascii_case(prefix)) =>
+        {
+            num
+        }
+        _ => {
+            return Err(not_found_error(
+                "NOT_FOUND",
+                &format!("Task {task} not found"),
+            ));
+        }
+    };
+    match doc
+        .get_by_nums(txn, &HashSet::from([num.to_string()]))?
+        .into_iter()
+        .next()
+    {
+        Some(found) => Ok(found),
+        None => Err(not_found_error(
+            "NOT_FOUND",
+            &format!("Task {task} not found"),
+        )),
+    }
+}
+
+fn verify_status<T: ReadTxn>(doc: &YDocProxy, txn: &T, status: &str) -> ApiResult<()> {
+    if !doc.config().statuses(txn)?.iter().any(|s| s == status) {
+        return Err(bad_request_error(
+            "INVALID_STATUS",
+            &format!("Invalid status: {status}"),
+        ));
+    }
+    Ok(())
+}
+
+async fn verify_member(pool: &PgPool, project_id: &ProjectId, email: &str) -> ApiResult<()> {
+    if !list_project_users(pool, project_id)
+        .await?
+        .iter()
+        .any(|u| u.email == email)
+    {
+        return Err(bad_request_error(
+            "NOT_A_MEMBER",
+            &format!("{email} is not a member of the project"),
+        ));
+    }
+    Ok(())
+}
+
+pub(super) fn task_url(project_id: &str, task_id: &str) -> String {
+    format!("https://koso.app/projects/{project_id}?taskId={task_id}")
+}
@@ -0,0 +1,250 @@
+//! Localization of server-rendered content, e.g. notifications and reports,
+//! in the locale set in the recipient's profile.
+//!
+//! Messages live in per-language catalogs under `i18n/`, written in a subset
+//! of Fluent's syntax. Messages missing from a catalog fall back to English.
+//! The `en-XA` pseudo-locale renders English with accented, bracketed text,
+//! so strings that escape localization stand out when testing.
+
+use crate::postgres::PgPool;
+use anyhow::{Context as _, Result};
+use std::{collections::HashMap, fmt::Display, sync::LazyLock};
+
+const DEFAULT_LANGUAGE: &str = "en";
+const PSEUDO_LOCALE: &str = "en-XA";
+
+type Catalog = HashMap<&'static str, String>;
+
+static CATALOGS: LazyLock<HashMap<&'static str, Catalog>> = LazyLock::new(|| {
+    HashMap::from([
+        ("en", parse(include_str!("i18n/en.ftl"))),
+        ("es", parse(include_str!("i18n/es.ftl"))),
+    ])
+});
+
+/// Where messages are looked up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Locale {
+    language: &'static str,
+    pseudo: bool,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale {
+            language: DEFAULT_LANGUAGE,
+            pseudo: false,
+        }
+    }
+}
+
+impl Locale {
+    /// Picks the catalog for a language tag, e.g. `es-MX`, falling back to
+    /// English for missing and unsupported tags.
+    pub(crate) fn parse(tag: Option<&str>) -> Locale {
+        let Some(tag) = tag.map(str::trim).filter(|t| !t.is_empty()) else {
+            return Locale::default();
+        };
+        if tag.eq_ignore_ascii_case(PSEUDO_LOCALE) {
+            return Locale {
+                language: DEFAULT_LANGUAGE,
+                pseudo: true,
+            };
+        }
+        let primary = tag.split(['-', '_']).next().unwrap_or(tag);
+        match CATALOGS.get_key_value(primary.to_ascii_lowercase().as_str()) {
+            Some((language, _)) => Locale {
+                language,
+                pseudo: false,
+            },
+            None => Locale::default(),
+        }
+    }
+
+    /// The locale in the user's profile.
+    pub(crate) async fn of_user(pool: &PgPool, email: &str) -> Result<Locale> {
+        let locale: Option<(Option<String>,)> =
+            sqlx::query_as("SELECT locale FROM users WHERE email = $1")
+                .bind(email)
+                .fetch_optional(pool)
+                .await
+                .context("Failed to query user locale")?;
+        Ok(Locale::parse(locale.and_then(|(l,)| l).as_deref()))
+    }
+
+    /// Formats the message, substituting its `{ $name }` placeables with the
+    /// arguments. Arguments are substituted as is, so escape them first
+    /// where the message is HTML.
+    pub(crate) fn message(&self, id: &str, args: &[(&str, &dyn Display)]) -> String {
+        let pattern = CATALOGS
+            .get(self.language)
+            .and_then(|catalog| catalog.get(id))
+            .or_else(|| CATALOGS.get(DEFAULT_LANGUAGE)?.get(id));
+        let Some(pattern) = pattern else {
+            tracing::warn!("Missing message {id}");
+            return id.to_string();
+        };
+        if self.pseudo {
+            format_pattern(&pseudolocalize(pattern), args)
+        } else {
+            format_pattern(pattern, args)
+        }
+    }
+}
+
+/// Parses a catalog. Lines that aren't messages, or their continuations,
+/// are ignored.
+fn parse(source: &'static str) -> Catalog {
+    let mut catalog = Catalog::new();
+    let mut current: Option<&'static str> = None;
+    for line in source.lines() {
+        if line.starts_with([' ', '\t']) && !line.trim().is_empty() {
+            if let Some(value) = current.and_then(|id| catalog.get_mut(id)) {
+                if !value.is_empty() {
+                    value.push('\n');
+                }
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        current = None;
+        if line.starts_with('#') {
+            continue;
+        }
+        if let Some((id, value)) = line.split_once('=') {
+            let id = id.trim();
+            catalog.insert(id, value.trim().to_string());
+            current = Some(id);
+        }
+    }
+    catalog
+}
+
+fn format_pattern(pattern: &str, args: &[(&str, &dyn Display)]) -> String {
+    let mut out = String::with_capacity(pattern.len());
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            rest = &rest[start..];
+            break;
+        };
+        let placeable = &rest[start..start + end + 1];
+        let name = placeable[1..placeable.len() - 1]
+            .trim()
+            .trim_start_matches('$');
+        match args.iter().find(|(arg, _)| *arg == name) {
+            Some((_, value)) => out.push_str(&value.to_string()),
+            None => {
+                tracing::warn!("Missing argument {name} of message {pattern}");
+                out.push_str(placeable);
+            }
+        }
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Accents the letters of the pattern and brackets it, leaving placeables,
+/// tags and entities intact.
+fn pseudolocalize(pattern: &str) -> String {
+    let mut out = String::with_capacity(pattern.len() * 2 + 2);
+    out.push('[');
+    let mut skip_until = None;
+    for c in pattern.chars() {
+        if let Some(end) = skip_until {
+            out.push(c);
+            if c == end {
+                skip_until = None;
+            }
+            continue;
+        }
+        skip_until = match c {
+            '{' => Some('}'),
+            '<' => Some('>'),
+            '&' => Some(';'),
+            _ => None,
+        };
+        out.push(accent(c));
+    }
+    out.push(']');
+    out
+}
+
+fn accent(c: char) -> char {
+    match c {
+        'a' => 'á',
+        'c' => 'ç',
+        'e' => 'é',
+        'i' => 'í',
+        'n' => 'ñ',
+        'o' => 'ó',
+        'u' => 'ú',
+        'y' => 'ý',
+        'A' => 'Å',
+        'C' => 'Ç',
+        'E' => 'É',
+        'I' => 'Î',
+        'N' => 'Ñ',
+        'O' => 'Ö',
+        'U' => 'Ü',
+        _ => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn placeables(pattern: &str) -> BTreeSet<String> {
+        pattern
+            .split('{')
+            .skip(1)
+            .filter_map(|s| s.split_once('}').map(|(name, _)| name.trim().to_string()))
+            .collect()
+    }
+
+    #[test_log::test]
+    fn catalogs_match_english() {
+        let en = &CATALOGS[DEFAULT_LANGUAGE];
+        for (language, catalog) in CATALOGS.iter() {
+            for (id, pattern) in catalog {
+                let Some(english) = en.get(id) else {
+                    panic!("{language} has {id}, which English lacks");
+                };
+                assert_eq!(
+                    placeables(pattern),
+                    placeables(english),
+                    "{language} {id} has different placeables"
+                );
+            }
+            assert_eq!(catalog.len(), en.len(), "{language} lacks messages");
+        }
+    }
+
+    #[test_log::test]
+    fn formats_messages() {
+        let url = "https://koso.app/projects/p?taskId=t";
+        let args: [(&str, &dyn Display); 3] =
+            [("sender", &"Koso"), ("url", &url), ("task", &"Ship it")];
+        assert_eq!(
+            Locale::default().message("notify-assigned", &args),
+            "🎁 <i>Koso</i> assigned to you:\n<a href=\"https://koso.app/projects/p?taskId=t\"><b>Ship it</b></a>"
+        );
+        assert_eq!(
+            Locale::parse(Some("es-MX")).message("inbox-assigned", &args),
+            "Asignada a ti: Ship it"
+        );
+        assert_eq!(
+            Locale::parse(Some("fr")).message("report-goals", &[]),
+            "Goals"
+        );
+        assert_eq!(
+            Locale::parse(Some("en-XA")).message("digest-summary", &[("count", &3)]),
+            "[🔔 <b>3 ñótífíçátíóñs</b>]"
+        );
+        assert_eq!(Locale::default().message("missing", &[]), "missing");
+    }
+}
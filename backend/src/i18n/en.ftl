@@ -0,0 +1,47 @@
+# Server-rendered strings, in a subset of Fluent's syntax: messages of text
+# and { $variable } placeables, continued on indented lines.
+
+## Notifications, rendered as Telegram-flavored HTML.
+
+task-untitled = Task #{ $num }
+notify-assigned =
+    🎁 <i>{ $sender }</i> assigned to you:
+    <a href="{ $url }"><b>{ $task }</b></a>
+notify-delegated =
+    🏝️ <i>{ $sender }</i> assigned to { $assignee }, who's away, and you cover for:
+    <a href="{ $url }"><b>{ $task }</b></a>
+notify-deadline =
+    📅 <i>{ $sender }</i> set a deadline of <b>{ $deadline }</b> on:
+    <a href="{ $url }"><b>{ $task }</b></a>
+notify-unblocked =
+    🎁 <i>Koso</i> assigned to you:
+    <a href="{ $url }"><b>{ $task }</b></a>
+digest-summary = 🔔 <b>{ $count } notifications</b>
+digest-more = …and { $count } more.
+
+## Inbox entries, as plain text.
+
+inbox-assigned = Assigned to you: { $task }
+inbox-delegated = Assigned to { $assignee }, who's away: { $task }
+inbox-deadline = Deadline of { $deadline } set on: { $task }
+inbox-unblocked = Unblocked and ready to start: { $task }
+
+## Status reports.
+
+report-title = Status report: { $project }
+report-range = { $start } to { $end }
+report-completed = Completed
+report-started = Started
+report-slipped = Slipped
+report-created = Created
+report-none = None
+report-flow = Flow
+report-cycle-time = Cycle time
+report-lead-time = Lead time
+report-percentiles = { $p50 } median, { $p85 } p85
+report-not-available = n/a
+report-goals = Goals
+report-goal-progress = { $percent }% ({ $done }/{ $total } tasks)
+report-goal-progress-due = { $percent }% ({ $done }/{ $total } tasks), due { $date }
+report-risks = Open risks
+report-risk = { $title } (score { $score })
@@ -0,0 +1,44 @@
+## Notifications, rendered as Telegram-flavored HTML.
+
+task-untitled = Tarea #{ $num }
+notify-assigned =
+    🎁 <i>{ $sender }</i> te asignó:
+    <a href="{ $url }"><b>{ $task }</b></a>
+notify-delegated =
+    🏝️ <i>{ $sender }</i> asignó a { $assignee }, que está ausente y a quien cubres:
+    <a href="{ $url }"><b>{ $task }</b></a>
+notify-deadline =
+    📅 <i>{ $sender }</i> fijó una fecha límite de <b>{ $deadline }</b> en:
+    <a href="{ $url }"><b>{ $task }</b></a>
+notify-unblocked =
+    🎁 <i>Koso</i> te asignó:
+    <a href="{ $url }"><b>{ $task }</b></a>
+digest-summary = 🔔 <b>{ $count } notificaciones</b>
+digest-more = …y { $count } más.
+
+## Inbox entries, as plain text.
+
+inbox-assigned = Asignada a ti: { $task }
+inbox-delegated = Asignada a { $assignee }, que está ausente: { $task }
+inbox-deadline = Fecha límite del { $deadline } en: { $task }
+inbox-unblocked = Desbloqueada y lista para empezar: { $task }
+
+## Status reports.
+
+report-title = Informe de estado: { $project }
+report-range = Del { $start } al { $end }
+report-completed = Completadas
+report-started = Empezadas
+report-slipped = Retrasadas
+report-created = Creadas
+report-none = Ninguna
+report-flow = Flujo
+report-cycle-time = Tiempo de ciclo
+report-lead-time = Tiempo de entrega
+report-percentiles = { $p50 } mediana, { $p85 } p85
+report-not-available = n/d
+report-goals = Objetivos
+report-goal-progress = { $percent }% ({ $done }/{ $total } tareas)
+report-goal-progress-due = { $percent }% ({ $done }/{ $total } tareas), vence el { $date }
+report-risks = Riesgos abiertos
+report-risk = { $title } (puntuación { $score })
@@ -0,0 +1,350 @@
+//! Offline verifiable licenses for self-hosted deployments. A license file is
+//! a JWT, signed by Koso with RS256, granting premium features to a number of
+//! seats until it expires. It's verified against the public key compiled in
+//! from KOSO_LICENSE_PUBLIC_KEY, so no network access is needed.
+//!
+//! The verified license is stored in the license table, which the is_premium
+//! SQL function consults alongside Stripe subscriptions. Rather than locking
+//! users out, premium features keep working for a grace period past expiry,
+//! seats in excess are only warned about, and a license file that stops
+//! verifying leaves the last verified license in place.
+
+use crate::{
+    api::reports::escape_html, notifiers::Notifiers, postgres::PgPool, settings::settings,
+};
+use anyhow::{Context as _, Result, anyhow};
+use chrono::{DateTime, TimeDelta, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How long premium features keep working past expiry.
+const GRACE_PERIOD: TimeDelta = TimeDelta::days(30);
+/// Admins are warned once the license expires within this.
+const EXPIRY_WARNING: TimeDelta = TimeDelta::days(14);
+/// PEM encoded RSA public key licenses are signed with.
+const PUBLIC_KEY: Option<&str> = option_env!("KOSO_LICENSE_PUBLIC_KEY");
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub(crate) struct LicenseClaims {
+    /// The licensee, e.g. Acme Corp.
+    pub(crate) sub: String,
+    /// Expiry, in seconds since the epoch.
+    pub(crate) exp: i64,
+    pub(crate) seats: i32,
+    #[serde(default)]
+    pub(crate) features: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum LicenseState {
+    Valid,
+    ExpiringSoon,
+    /// More users have signed in than the license has seats for.
+    OverSeats,
+    /// Expired, but premium features still work until the grace period ends.
+    Grace,
+    /// Premium features no longer work.
+    Expired,
+}
+
+impl LicenseState {
+    fn as_str(self) -> &'static str {
+        match self {
+            LicenseState::Valid => "valid",
+            LicenseState::ExpiringSoon => "expiringSoon",
+            LicenseState::OverSeats => "overSeats",
+            LicenseState::Grace => "grace",
+            LicenseState::Expired => "expired",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LicenseStatus {
+    pub(crate) licensee: String,
+    pub(crate) seats: i32,
+    /// Users who've signed in to the deployment.
+    pub(crate) used_seats: i64,
+    pub(crate) features: Vec<String>,
+    pub(crate) expire_time: DateTime<Utc>,
+    pub(crate) grace_end_time: DateTime<Utc>,
+    pub(crate) verify_time: DateTime<Utc>,
+    #[sqlx(skip)]
+    pub(crate) state: Option<LicenseState>,
+    #[serde(skip)]
+    notify_state: Option<String>,
+}
+
+impl LicenseStatus {
+    /// The most pressing state the license is in.
+    fn state_at(&self, now: DateTime<Utc>) -> LicenseState {
+        if now >= self.grace_end_time {
+            LicenseState::Expired
+        } else if now >= self.expire_time {
+            LicenseState::Grace
+        } else if self.used_seats > i64::from(self.seats) {
+            LicenseState::OverSeats
+        } else if self.expire_time - now < EXPIRY_WARNING {
+            LicenseState::ExpiringSoon
+        } else {
+            LicenseState::Valid
+        }
+    }
+}
+
+/// Returns the deployment's license, if it has one.
+pub(crate) async fn status(pool: &PgPool) -> Result<Option<LicenseStatus>> {
+    let status: Option<LicenseStatus> = sqlx::query_as(
+        "
+        SELECT
+            licensee,
+            seats,
+            (SELECT COUNT(*) FROM users) AS used_seats,
+            features,
+            expire_time,
+            grace_end_time,
+            verify_time,
+            notify_state
+        FROM license",
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to query license")?;
+    Ok(status.map(|status| LicenseStatus {
+        state: Some(status.state_at(Utc::now())),
+        ..status
+    }))
+}
+
+/// Verifies the license file against the compiled in public key.
+pub(crate) fn verify_file(file: &str) -> Result<LicenseClaims> {
+    let Some(public_key) = PUBLIC_KEY else {
+        return Err(anyhow!(
+            "Built without KOSO_LICENSE_PUBLIC_KEY, so licenses can't be verified"
+        ));
+    };
+    let token =
+        std::fs::read_to_string(file).with_context(|| format!("Failed to read license {file}"))?;
+    verify(&token, &DecodingKey::from_rsa_pem(public_key.as_bytes())?)
+}
+
+fn verify(token: &str, key: &DecodingKey) -> Result<LicenseClaims> {
+    let mut validation = Validation::new(Algorithm::RS256);
+    // Expired licenses get a grace period rather than being rejected outright.
+    validation.validate_exp = false;
+    validation.set_required_spec_claims(&["exp", "sub"]);
+    Ok(
+        jsonwebtoken::decode::<LicenseClaims>(token.trim(), key, &validation)
+            .context("Invalid license")?
+            .claims,
+    )
+}
+
+/// Verifies the configured license file at startup and hourly after, picking
+/// up renewals, and lets admins know when it needs attention.
+pub(crate) struct LicenseMonitor {
+    pool: &'static PgPool,
+    notifier: Notifiers,
+}
+
+impl LicenseMonitor {
+    pub(crate) fn new(pool: &'static PgPool) -> Result<Self> {
+        Ok(LicenseMonitor {
+            pool,
+            notifier: Notifiers::new(pool)?,
+        })
+    }
+
+    pub(crate) fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let Some(file) = &settings().license.file else {
+                tracing::debug!("No license configured");
+                return;
+            };
+            let mut interval = tokio::time::interval(CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.check(file).await {
+                    tracing::warn!("Failed to check license: {e:?}");
+                }
+            }
+        })
+    }
+
+    async fn check(&self, file: &str) -> Result<()> {
+        match verify_file(file) {
+            Ok(claims) => self.save(&claims).await?,
+            Err(e) => tracing::error!(
+                "Failed to verify license {file}, keeping the last verified license: {e:?}"
+            ),
+        }
+        let Some(status) = status(self.pool).await? else {
+            return Ok(());
+        };
+        let state = status.state_at(Utc::now());
+        metrics::gauge!("license_used_seats").set(status.used_seats as f64);
+        if state == LicenseState::Valid {
+            return Ok(());
+        }
+        tracing::warn!("License for {} is {state:?}", status.licensee);
+        if status.notify_state.as_deref() != Some(state.as_str()) {
+            self.notify_admins(&status, state).await?;
+        }
+        Ok(())
+    }
+
+    async fn save(&self, claims: &LicenseClaims) -> Result<()> {
+        let expire_time = DateTime::from_timestamp(claims.exp, 0)
+            .ok_or_else(|| anyhow!("Invalid license expiry {}", claims.exp))?;
+        sqlx::query(
+            "
+            INSERT INTO license (id, licensee, seats, features, expire_time, grace_end_time)
+            VALUES (TRUE, $1, $2, $3, $4, $5)
+            ON CONFLICT (id)
+            DO UPDATE SET
+                licensee = EXCLUDED.licensee,
+                seats = EXCLUDED.seats,
+                features = EXCLUDED.features,
+                expire_time = EXCLUDED.expire_time,
+                grace_end_time = EXCLUDED.grace_end_time,
+                verify_time = NOW(),
+                notify_state = CASE
+                    WHEN license.expire_time = EXCLUDED.expire_time
+                        AND license.seats = EXCLUDED.seats
+                    THEN license.notify_state
+                END",
+        )
+        .bind(&claims.sub)
+        .bind(claims.seats)
+        .bind(&claims.features)
+        .bind(expire_time)
+        .bind(expire_time + GRACE_PERIOD)
+        .execute(self.pool)
+        .await
+        .context("Failed to save license")?;
+        Ok(())
+    }
+
+    async fn notify_admins(&self, status: &LicenseStatus, state: LicenseState) -> Result<()> {
+        let expiry = status.expire_time.format("%Y-%m-%d");
+        let reason = match state {
+            LicenseState::Valid => return Ok(()),
+            LicenseState::ExpiringSoon => format!("expires on {expiry}"),
+            LicenseState::OverSeats => format!(
+                "covers {} seats, but {} users have signed in",
+                status.seats, status.used_seats
+            ),
+            LicenseState::Grace => format!(
+                "expired on {expiry}. Premium features stop working on {}",
+                status.grace_end_time.format("%Y-%m-%d")
+            ),
+            LicenseState::Expired => {
+                format!("expired on {expiry} and premium features no longer work")
+            }
+        };
+        let msg = format!(
+            "🔑 The Koso license for {} {reason}. Contact Koso to renew it.",
+            escape_html(&status.licensee)
+        );
+        for admin in &settings().admins {
+            if let Err(e) = self.notifier.notify(admin, &msg).await {
+                tracing::warn!("Failed to notify {admin} about the license: {e:?}");
+            }
+        }
+        sqlx::query("UPDATE license SET notify_state = $1")
+            .bind(state.as_str())
+            .execute(self.pool)
+            .await
+            .context("Failed to record license notification")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::google::test_utils::PEM_1;
+    use jsonwebtoken::{EncodingKey, Header};
+    use rsa::{
+        RsaPrivateKey,
+        pkcs1::{DecodeRsaPrivateKey as _, EncodeRsaPublicKey as _},
+    };
+
+    fn decoding_key() -> DecodingKey {
+        let public_key = RsaPrivateKey::from_pkcs1_pem(PEM_1)
+            .unwrap()
+            .to_public_key()
+            .to_pkcs1_pem(rsa::pkcs8::LineEnding::LF)
+            .unwrap();
+        DecodingKey::from_rsa_pem(public_key.as_bytes()).unwrap()
+    }
+
+    fn sign(claims: &serde_json::Value) -> String {
+        jsonwebtoken::encode(
+            &Header::new(Algorithm::RS256),
+            claims,
+            &EncodingKey::from_rsa_pem(PEM_1.as_bytes()).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn verify_accepts_expired_licenses() {
+        let token = sign(&serde_json::json!({
+            "sub": "Acme Corp",
+            "exp": 1_000_000,
+            "seats": 25,
+            "features": ["sso"],
+        }));
+        assert_eq!(
+            verify(&token, &decoding_key()).unwrap(),
+            LicenseClaims {
+                sub: "Acme Corp".to_string(),
+                exp: 1_000_000,
+                seats: 25,
+                features: vec!["sso".to_string()],
+            }
+        );
+
+        let mut tampered = token.clone();
+        tampered.pop();
+        assert!(verify(&tampered, &decoding_key()).is_err());
+    }
+
+    #[test]
+    fn state_degrades_gracefully() {
+        let now = Utc::now();
+        let status = |expire_time: DateTime<Utc>, used_seats| LicenseStatus {
+            licensee: "Acme Corp".to_string(),
+            seats: 10,
+            used_seats,
+            features: vec![],
+            expire_time,
+            grace_end_time: expire_time + GRACE_PERIOD,
+            verify_time: now,
+            state: None,
+            notify_state: None,
+        };
+        let later = now + TimeDelta::days(100);
+        assert_eq!(status(later, 10).state_at(now), LicenseState::Valid);
+        assert_eq!(status(later, 11).state_at(now), LicenseState::OverSeats);
+        assert_eq!(
+            status(now + TimeDelta::days(1), 10).state_at(now),
+            LicenseState::ExpiringSoon
+        );
+        assert_eq!(
+            status(now - TimeDelta::days(1), 10).state_at(now),
+            LicenseState::Grace
+        );
+        assert_eq!(
+            status(now - GRACE_PERIOD, 10).state_at(now),
+            LicenseState::Expired
+        );
+    }
+}
@@ -3,10 +3,15 @@ use tokio::signal;
 use tokio_util::sync::CancellationToken;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod admin;
 mod api;
 mod healthz;
+mod i18n;
+mod license;
 mod metrics_server;
 mod notifiers;
+mod object_store;
+mod outbound;
 mod plugins;
 mod postgres;
 mod secrets;
@@ -25,11 +30,19 @@ async fn main() {
     let settings = settings::settings();
     tracing::info!("Using koso settings: {settings:?}");
 
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("admin") {
+        if let Err(e) = admin::run(&args[2..]).await {
+            eprintln!("{e:?}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let shutdown_signal = CancellationToken::new();
     tokio::join!(
         async { run_server(shutdown_signal.clone()).await.unwrap() },
         async { run_metrics_server(shutdown_signal.clone()).await.unwrap() },
-        async { run_telegram_server(shutdown_signal.clone()).await.unwrap() },
         async { signal_shutdown(shutdown_signal.clone()).await.unwrap() },
     );
 }
@@ -38,6 +51,7 @@ async fn run_server(shutdown_signal: CancellationToken) -> Result<()> {
     tokio::spawn(async move {
         let (_port, serve) = server::start_main_server(server::Config {
             shutdown_signal: shutdown_signal.clone(),
+            enable_telegram: true,
             ..Default::default()
         })
         .await?;
@@ -60,14 +74,6 @@ async fn run_metrics_server(shutdown_signal: CancellationToken) -> Result<()> {
     .await?
 }
 
-async fn run_telegram_server(shutdown_signal: CancellationToken) -> Result<()> {
-    tokio::spawn(async move {
-        notifiers::telegram::start_telegram_server(shutdown_signal.clone()).await?;
-        Ok::<(), anyhow::Error>(())
-    })
-    .await?
-}
-
 // This function waits for a shutdown signal (e.g. ctrl-c, SIGTERM)
 // and then cancels the provided CancellationToken in order
 // to enable graceful shutdown.
@@ -1,25 +1,54 @@
 use anyhow::Result;
-use axum::Router;
+use async_trait::async_trait;
+use axum::{Router, routing::get};
 use serde::{Deserialize, Serialize};
-use sqlx::{Pool, Postgres, prelude::FromRow};
-use teloxide::payloads::SendMessageSetters;
-use teloxide::prelude::Requester;
-use teloxide::types::{ParseMode, UserId};
+use sqlx::prelude::FromRow;
+use std::time::Duration;
+use tokio::task::JoinHandle;
 
-use crate::settings::settings;
+use crate::{api::reports::escape_html, i18n::Locale, postgres::PgPool, settings::settings};
 
+pub(crate) mod deliveries;
+pub(crate) mod matrix;
+pub(crate) mod plain;
+pub(crate) mod policy;
 pub(crate) mod telegram;
 
+/// How often failed deliveries are checked for retries.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub(super) struct TelegramSettings {
     pub(super) chat_id: u64,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct MatrixSettings {
+    pub(super) homeserver_url: String,
+    pub(super) access_token: String,
+    pub(super) room_id: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase", tag = "type")]
 pub(super) enum NotifierSettings {
     Telegram(TelegramSettings),
+    Matrix(MatrixSettings),
+}
+
+impl NotifierSettings {
+    /// Removes credentials that must never be sent back to clients.
+    pub(super) fn redacted(self) -> Self {
+        match self {
+            NotifierSettings::Matrix(settings) => NotifierSettings::Matrix(MatrixSettings {
+                access_token: String::new(),
+                ..settings
+            }),
+            settings => settings,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, FromRow, Debug)]
@@ -32,34 +61,187 @@ pub(super) struct UserNotificationConfig {
     pub(super) settings: NotifierSettings,
 }
 
+/// A notification, independent of the backend delivering it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct Message {
+    /// The message body, using the subset of HTML supported by Telegram.
+    pub(super) html: String,
+    /// The same content as plain text, for users who prefer it and backends
+    /// that need a fallback. Empty for messages stored before it was kept.
+    #[serde(default)]
+    pub(super) text: String,
+    /// The task the message is about, for backends that offer actions on tasks.
+    pub(super) task: Option<TaskRef>,
+}
+
+impl Message {
+    pub(super) fn new(html: String, text: Option<String>, task: Option<TaskRef>) -> Message {
+        let text = text.unwrap_or_else(|| plain::html_to_text(&html));
+        Message { html, text, task }
+    }
+
+    /// The plain-text rendering, derived from the HTML for messages stored
+    /// without one.
+    pub(super) fn text(&self) -> String {
+        if self.text.is_empty() {
+            plain::html_to_text(&self.html)
+        } else {
+            self.text.clone()
+        }
+    }
+
+    /// The message with its HTML replaced by the escaped plain text, so
+    /// backends deliver the plain rendering as is.
+    pub(super) fn to_plain(&self) -> Message {
+        let text = self.text();
+        Message {
+            html: escape_html(&text),
+            text,
+            task: self.task.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct TaskRef {
+    pub(super) project_id: String,
+    pub(super) task_id: String,
+}
+
+/// What a notifier can do beyond delivering plain text.
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct Capabilities {
+    /// Links and formatting are rendered rather than shown as plain text.
+    pub(super) html: bool,
+    /// Messages about a task include buttons to act on it.
+    pub(super) task_actions: bool,
+}
+
+/// A backend that delivers notifications to users, e.g. Telegram.
+#[async_trait]
+pub(super) trait Notifier: Send + Sync {
+    /// The notifier's ID, as stored in `user_notification_configs.notifier`.
+    fn id(&self) -> &'static str;
+
+    fn capabilities(&self) -> Capabilities;
+
+    /// Delivers the message to a single user. Returns an error if the settings
+    /// belong to a different notifier.
+    async fn send(&self, settings: &NotifierSettings, message: &Message) -> Result<()>;
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct NotifierInfo {
+    id: &'static str,
+    capabilities: Capabilities,
+}
+
 pub(super) fn router() -> Router {
-    Router::new().nest("/telegram", telegram::router())
+    Router::new()
+        .route("/capabilities", get(deliveries::capabilities_handler))
+        .nest("/deliveries", deliveries::router())
+        .nest("/preferences", policy::router())
+        .nest("/telegram", telegram::router())
+        .nest("/matrix", matrix::router())
 }
 
-pub(super) struct Notifier {
-    pool: &'static Pool<Postgres>,
-    bot: Option<teloxide::Bot>,
+/// Sends notifications using each of the recipient's configured notifiers,
+/// recording every delivery so failures can be retried and inspected.
+pub(super) struct Notifiers {
+    pool: &'static PgPool,
+    backends: Vec<Box<dyn Notifier>>,
 }
 
-impl Notifier {
-    pub(super) fn new(pool: &'static Pool<Postgres>) -> Result<Self> {
-        Ok(Self {
-            pool,
-            bot: match telegram::bot_from_secrets() {
-                Ok(bot) => Some(bot),
-                Err(e) => {
-                    if settings().is_dev() {
-                        None
-                    } else {
-                        return Err(e.context("Failed to initialize telegram bot"));
-                    }
+impl Notifiers {
+    pub(super) fn new(pool: &'static PgPool) -> Result<Self> {
+        let mut backends: Vec<Box<dyn Notifier>> = vec![Box::new(matrix::MatrixNotifier)];
+        match telegram::bot_from_secrets() {
+            Ok(bot) => backends.push(Box::new(telegram::TelegramNotifier::new(bot))),
+            Err(e) => {
+                if !settings().is_dev() {
+                    return Err(e.context("Failed to initialize telegram bot"));
                 }
-            },
-        })
+            }
+        }
+        Ok(Self { pool, backends })
+    }
+
+    fn backend(&self, id: &str) -> Option<&dyn Notifier> {
+        self.backends
+            .iter()
+            .find(|backend| backend.id() == id)
+            .map(Box::as_ref)
+    }
+
+    fn infos(&self) -> Vec<NotifierInfo> {
+        self.backends
+            .iter()
+            .map(|backend| NotifierInfo {
+                id: backend.id(),
+                capabilities: backend.capabilities(),
+            })
+            .collect()
     }
 
     pub(super) async fn notify(&self, recipient: &str, message: &str) -> Result<()> {
-        let configs: Vec<UserNotificationConfig> = sqlx::query_as(
+        self.send(recipient, &Message::new(message.to_string(), None, None))
+            .await
+    }
+
+    /// Notifies the recipient with a plain-text rendering of the same content
+    /// rather than one derived from the HTML, e.g. for structured reports.
+    pub(super) async fn notify_with_text(
+        &self,
+        recipient: &str,
+        html: &str,
+        text: &str,
+    ) -> Result<()> {
+        self.send(
+            recipient,
+            &Message::new(html.to_string(), Some(text.to_string()), None),
+        )
+        .await
+    }
+
+    /// Notifies the recipient that a task was assigned to them, with buttons to
+    /// act on the task where the notifier supports them.
+    pub(super) async fn notify_assignment(
+        &self,
+        recipient: &str,
+        message: &str,
+        project_id: &str,
+        task_id: &str,
+    ) -> Result<()> {
+        let task = TaskRef {
+            project_id: project_id.to_string(),
+            task_id: task_id.to_string(),
+        };
+        self.send(
+            recipient,
+            &Message::new(message.to_string(), None, Some(task)),
+        )
+        .await
+    }
+
+    /// Sends the message now, or holds it for a later summary if the recipient's
+    /// quiet hours or batching policy say so.
+    async fn send(&self, recipient: &str, message: &Message) -> Result<()> {
+        let configs = self.configs(recipient).await?;
+        if configs.is_empty() {
+            return Ok(());
+        }
+        if let policy::Decision::Hold(reason) = policy::decide(self.pool, recipient).await? {
+            return policy::hold(self.pool, recipient, message, reason).await;
+        }
+        self.deliver(configs, message).await
+    }
+
+    async fn configs(&self, recipient: &str) -> Result<Vec<UserNotificationConfig>> {
+        Ok(sqlx::query_as(
             "
             SELECT email, notifier, enabled, settings
             FROM user_notification_configs
@@ -67,20 +249,87 @@ impl Notifier {
         )
         .bind(recipient)
         .fetch_all(self.pool)
-        .await?;
+        .await?)
+    }
 
+    /// Sends the message with each notifier. Delivery failures are recorded for
+    /// retry rather than returned.
+    async fn deliver(&self, configs: Vec<UserNotificationConfig>, message: &Message) -> Result<()> {
+        let Some(recipient) = configs.first().map(|c| c.email.clone()) else {
+            return Ok(());
+        };
+        // Store the rendering the user prefers, so retries deliver it too.
+        let plain;
+        let message = if policy::prefers_plain_text(self.pool, &recipient).await? {
+            plain = message.to_plain();
+            &plain
+        } else {
+            message
+        };
         for config in configs {
-            match config.settings {
-                NotifierSettings::Telegram(settings) => {
-                    if let Some(bot) = &self.bot {
-                        bot.send_message(UserId(settings.chat_id), message)
-                            .parse_mode(ParseMode::Html)
-                            .await?;
-                    }
-                }
+            let Some(backend) = self.backend(&config.notifier) else {
+                tracing::debug!("Notifier {} is unavailable", config.notifier);
+                continue;
+            };
+            let delivery_id = deliveries::insert(self.pool, &config, message).await?;
+            let result = backend.send(&config.settings, message).await;
+            deliveries::record_attempt(self.pool, &delivery_id, result).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends a summary of each recipient's held messages once they're releasable.
+    async fn release_held(&self) -> Result<()> {
+        for (recipient, messages) in policy::take_releasable(self.pool).await? {
+            let locale = Locale::of_user(self.pool, &recipient).await?;
+            if let Some(summary) = policy::summarize(messages, &locale) {
+                let configs = self.configs(&recipient).await?;
+                self.deliver(configs, &summary).await?;
             }
         }
+        Ok(())
+    }
 
+    /// Retries failed deliveries whose backoff has elapsed.
+    async fn retry_failed(&self) -> Result<()> {
+        for delivery in deliveries::take_due(self.pool).await? {
+            let config: Option<UserNotificationConfig> = sqlx::query_as(
+                "
+                SELECT email, notifier, enabled, settings
+                FROM user_notification_configs
+                WHERE email = $1 AND notifier = $2",
+            )
+            .bind(&delivery.email)
+            .bind(&delivery.notifier)
+            .fetch_optional(self.pool)
+            .await?;
+            let result = match (config, self.backend(&delivery.notifier)) {
+                (Some(config), Some(backend)) => {
+                    backend.send(&config.settings, &delivery.message).await
+                }
+                (None, _) => Err(anyhow::anyhow!("Notifier is no longer configured")),
+                (_, None) => Err(anyhow::anyhow!("Notifier is unavailable")),
+            };
+            deliveries::record_attempt(self.pool, &delivery.id, result).await?;
+        }
         Ok(())
     }
+
+    /// Periodically releases held messages and retries failed deliveries. Old deliveries
+    /// are pruned by the retention pruner.
+    pub(crate) fn start_retrying(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RETRY_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.release_held().await {
+                    tracing::warn!("Failed to release held notifications: {e:?}");
+                }
+                if let Err(e) = self.retry_failed().await {
+                    tracing::warn!("Failed to retry notification deliveries: {e:?}");
+                }
+            }
+        })
+    }
 }
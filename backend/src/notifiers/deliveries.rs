@@ -0,0 +1,204 @@
+use crate::{
+    api::{ApiResult, bad_request_error, google::User, not_found_error},
+    notifiers::{Message, NotifierInfo, Notifiers, UserNotificationConfig},
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    routing::{get, post},
+};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use serde::Serialize;
+use sqlx::{
+    FromRow, PgExecutor,
+    types::chrono::{DateTime, Utc},
+};
+
+/// Attempts after which a delivery is moved to the dead-letter queue.
+const MAX_ATTEMPTS: i32 = 5;
+const MAX_ERROR_LEN: usize = 1000;
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/", get(list_deliveries_handler))
+        .route("/{delivery_id}/retry", post(retry_delivery_handler))
+}
+
+/// The record of a single message sent with a single notifier.
+///
+/// Deliveries start `pending` and become `sent` or `failed`. Failed deliveries
+/// are retried with exponential backoff until they succeed or, after
+/// `MAX_ATTEMPTS`, become `dead`.
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct Delivery {
+    pub(super) id: String,
+    pub(super) email: String,
+    pub(super) notifier: String,
+    #[sqlx(json)]
+    pub(super) message: Message,
+    status: String,
+    attempts: i32,
+    last_error: Option<String>,
+    create_time: DateTime<Utc>,
+    update_time: DateTime<Utc>,
+}
+
+#[tracing::instrument(skip(pool))]
+pub(super) async fn capabilities_handler(
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<Vec<NotifierInfo>>> {
+    Ok(Json(Notifiers::new(pool)?.infos()))
+}
+
+/// Lists the user's most recent deliveries, for troubleshooting missing notifications.
+#[tracing::instrument(skip(user, pool))]
+async fn list_deliveries_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<Vec<Delivery>>> {
+    let deliveries: Vec<Delivery> = sqlx::query_as(
+        "
+        SELECT id, email, notifier, message, status, attempts, last_error, create_time, update_time
+        FROM notification_deliveries
+        WHERE email = $1
+        ORDER BY create_time DESC
+        LIMIT 100",
+    )
+    .bind(&user.email)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list notification deliveries")?;
+    Ok(Json(deliveries))
+}
+
+/// Requeues a failed or dead delivery for an immediate, final, retry.
+#[tracing::instrument(skip(user, pool))]
+async fn retry_delivery_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Path(delivery_id): Path<String>,
+) -> ApiResult<Json<Delivery>> {
+    let delivery: Option<Delivery> = sqlx::query_as(
+        "
+        SELECT id, email, notifier, message, status, attempts, last_error, create_time, update_time
+        FROM notification_deliveries
+        WHERE id = $1 AND email = $2",
+    )
+    .bind(&delivery_id)
+    .bind(&user.email)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch notification delivery")?;
+    let Some(delivery) = delivery else {
+        return Err(not_found_error("NOT_FOUND", "Delivery not found"));
+    };
+    if delivery.status != "failed" && delivery.status != "dead" {
+        return Err(bad_request_error(
+            "NOT_RETRYABLE",
+            &format!("Delivery is {}", delivery.status),
+        ));
+    }
+
+    let delivery: Delivery = sqlx::query_as(
+        "
+        UPDATE notification_deliveries
+        SET status = 'failed',
+            attempts = LEAST(attempts, $2),
+            next_attempt_time = NOW(),
+            update_time = NOW()
+        WHERE id = $1
+        RETURNING id, email, notifier, message, status, attempts, last_error, create_time, update_time",
+    )
+    .bind(&delivery.id)
+    .bind(MAX_ATTEMPTS - 1)
+    .fetch_one(pool)
+    .await
+    .context("Failed to requeue notification delivery")?;
+    Ok(Json(delivery))
+}
+
+/// Records a pending delivery and returns its ID.
+pub(super) async fn insert<'c, E: PgExecutor<'c>>(
+    executor: E,
+    config: &UserNotificationConfig,
+    message: &Message,
+) -> Result<String> {
+    let id = BASE64_URL_SAFE_NO_PAD.encode(uuid::Uuid::new_v4());
+    sqlx::query(
+        "
+        INSERT INTO notification_deliveries (id, email, notifier, message, status)
+        VALUES ($1, $2, $3, $4, 'pending')",
+    )
+    .bind(&id)
+    .bind(&config.email)
+    .bind(&config.notifier)
+    .bind(sqlx::types::Json(message))
+    .execute(executor)
+    .await
+    .context("Failed to insert notification delivery")?;
+    Ok(id)
+}
+
+/// Records the outcome of an attempt, scheduling a retry after 1, 4, 16, ...
+/// minutes or dead-lettering the delivery once it's out of attempts.
+pub(super) async fn record_attempt<'c, E: PgExecutor<'c>>(
+    executor: E,
+    delivery_id: &str,
+    result: Result<()>,
+) -> Result<()> {
+    let error = result.err().map(|e| {
+        tracing::warn!("Failed to deliver notification {delivery_id}: {e:?}");
+        format!("{e:#}")
+            .chars()
+            .take(MAX_ERROR_LEN)
+            .collect::<String>()
+    });
+    sqlx::query(
+        "
+        UPDATE notification_deliveries
+        SET status = CASE
+                WHEN $2::varchar IS NULL THEN 'sent'
+                WHEN attempts + 1 >= $3 THEN 'dead'
+                ELSE 'failed'
+            END,
+            next_attempt_time = CASE
+                WHEN $2::varchar IS NULL OR attempts + 1 >= $3 THEN NULL
+                ELSE NOW() + INTERVAL '1 minute' * POWER(4, attempts)
+            END,
+            attempts = attempts + 1,
+            last_error = $2,
+            update_time = NOW()
+        WHERE id = $1",
+    )
+    .bind(delivery_id)
+    .bind(error)
+    .bind(MAX_ATTEMPTS)
+    .execute(executor)
+    .await
+    .context("Failed to record notification delivery attempt")?;
+    Ok(())
+}
+
+/// Claims failed deliveries that are due for a retry.
+pub(super) async fn take_due<'c, E: PgExecutor<'c>>(executor: E) -> Result<Vec<Delivery>> {
+    sqlx::query_as(
+        "
+        UPDATE notification_deliveries
+        SET status = 'pending', next_attempt_time = NULL, update_time = NOW()
+        WHERE id IN (
+            SELECT id
+            FROM notification_deliveries
+            WHERE status = 'failed' AND next_attempt_time <= NOW()
+            ORDER BY next_attempt_time
+            LIMIT 100
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, email, notifier, message, status, attempts, last_error, create_time, update_time",
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to claim due notification deliveries")
+}
@@ -0,0 +1,235 @@
+use crate::{
+    api::{ApiResult, bad_request_error, google::User},
+    notifiers::{
+        Capabilities, MatrixSettings, Message, Notifier, NotifierSettings, UserNotificationConfig,
+        plain::html_to_text,
+    },
+    outbound::Fetch,
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result, anyhow};
+use async_trait::async_trait;
+use axum::{
+    Extension, Json, Router,
+    routing::{delete, post},
+};
+use reqwest::{Method, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+pub(super) fn router() -> Router {
+    Router::new()
+        .route("/", post(authorize_matrix))
+        .route("/", delete(deauthorize_matrix))
+        .route("/test", post(send_test_message_handler))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AuthorizeMatrix {
+    homeserver_url: String,
+    access_token: String,
+    room_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Empty {}
+
+/// See https://spec.matrix.org/v1.11/client-server-api/#get_matrixclientv3accountwhoami
+#[derive(Deserialize)]
+struct WhoAmI {
+    user_id: String,
+}
+
+#[tracing::instrument(skip(user, pool, req))]
+async fn authorize_matrix(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Json(req): Json<AuthorizeMatrix>,
+) -> ApiResult<Json<NotifierSettings>> {
+    let homeserver_url = parse_homeserver_url(&req.homeserver_url)?;
+    if req.access_token.trim().is_empty() {
+        return Err(bad_request_error(
+            "INVALID_ACCESS_TOKEN",
+            "Access token is blank",
+        ));
+    }
+    if !req.room_id.starts_with('!') || !req.room_id.contains(':') {
+        return Err(bad_request_error(
+            "INVALID_ROOM_ID",
+            "Room ID must look like !room:example.org",
+        ));
+    }
+
+    // Verify the token before saving it, so users find out about typos now
+    // rather than when notifications silently fail to arrive.
+    let mut url = homeserver_url.clone();
+    url.path_segments_mut()
+        .map_err(|_| anyhow!("Invalid homeserver url"))?
+        .pop_if_empty()
+        .extend(["_matrix", "client", "v3", "account", "whoami"]);
+    let res = Fetch::get(url)
+        .bearer_auth(&req.access_token)
+        .send()
+        .await
+        .context("Failed to reach matrix homeserver")?;
+    if !res.status.is_success() {
+        return Err(bad_request_error(
+            "MATRIX_AUTH_REJECTED",
+            &format!("Homeserver rejected the access token: {}", res.status),
+        ));
+    }
+    let whoami: WhoAmI = res
+        .json()
+        .context("Failed to decode matrix whoami response")?;
+    tracing::info!("Authorized matrix user {}", whoami.user_id);
+
+    let settings = NotifierSettings::Matrix(MatrixSettings {
+        homeserver_url: homeserver_url.to_string(),
+        access_token: req.access_token.trim().to_string(),
+        room_id: req.room_id,
+    });
+
+    sqlx::query(
+        "
+        INSERT INTO user_notification_configs (email, notifier, enabled, settings)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (email, notifier)
+        DO UPDATE SET enabled = EXCLUDED.enabled, settings = EXCLUDED.settings",
+    )
+    .bind(user.email)
+    .bind("matrix")
+    .bind(true)
+    .bind(sqlx::types::Json(&settings))
+    .execute(pool)
+    .await?;
+
+    Ok(Json(settings.redacted()))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn deauthorize_matrix(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<Empty>> {
+    sqlx::query(
+        "
+        DELETE FROM user_notification_configs
+        WHERE email = $1 AND notifier = 'matrix'",
+    )
+    .bind(user.email)
+    .execute(pool)
+    .await?;
+
+    Ok(Json(Empty {}))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn send_test_message_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<Empty>> {
+    let config: UserNotificationConfig = sqlx::query_as(
+        "
+        SELECT email, notifier, enabled, settings
+        FROM user_notification_configs
+        WHERE email = $1 AND notifier = 'matrix'",
+    )
+    .bind(user.email)
+    .fetch_one(pool)
+    .await?;
+
+    let NotifierSettings::Matrix(settings) = config.settings else {
+        return Err(bad_request_error(
+            "NOT_CONFIGURED",
+            "Matrix notifications are not configured",
+        ));
+    };
+
+    let html = "Hello from Koso! This is a test notification. Change your setting <a href=\"https://koso.app/profile\">here</a>.";
+    send(&settings, html, &html_to_text(html)).await?;
+
+    Ok(Json(Empty {}))
+}
+
+pub(super) struct MatrixNotifier;
+
+#[async_trait]
+impl Notifier for MatrixNotifier {
+    fn id(&self) -> &'static str {
+        "matrix"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            html: true,
+            // Matrix has no equivalent of Telegram's inline keyboards.
+            task_actions: false,
+        }
+    }
+
+    async fn send(&self, settings: &NotifierSettings, message: &Message) -> Result<()> {
+        let NotifierSettings::Matrix(settings) = settings else {
+            return Err(anyhow!("Expected matrix settings"));
+        };
+        send(settings, &message.html, &message.text()).await
+    }
+}
+
+/// Sends an HTML message, with its plain text fallback, to the configured room.
+/// See https://spec.matrix.org/v1.11/client-server-api/#put_matrixclientv3roomsroomidsendeventtypetxnid
+pub(super) async fn send(settings: &MatrixSettings, html: &str, text: &str) -> Result<()> {
+    let mut url = Url::parse(&settings.homeserver_url).context("Invalid matrix homeserver url")?;
+    // The transaction ID makes retries of the same request idempotent.
+    let txn_id = uuid::Uuid::new_v4().simple().to_string();
+    url.path_segments_mut()
+        .map_err(|_| anyhow!("Invalid homeserver url"))?
+        .pop_if_empty()
+        .extend([
+            "_matrix",
+            "client",
+            "v3",
+            "rooms",
+            &settings.room_id,
+            "send",
+            "m.room.message",
+            &txn_id,
+        ]);
+    Fetch::new(Method::PUT, url)
+        .bearer_auth(&settings.access_token)
+        .json(&json!({
+            "msgtype": "m.text",
+            "body": text,
+            "format": "org.matrix.custom.html",
+            "formatted_body": html,
+        }))?
+        .send()
+        .await
+        .context("Failed to send matrix message")?
+        .error_for_status()
+        .context("Matrix homeserver rejected message")?;
+    Ok(())
+}
+
+fn parse_homeserver_url(url: &str) -> ApiResult<Url> {
+    match Url::parse(url.trim()) {
+        Ok(url) if url.scheme() == "https" && url.host().is_some() => Ok(url),
+        _ => Err(bad_request_error(
+            "INVALID_HOMESERVER_URL",
+            "Homeserver URL must be an https URL",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_homeserver_url_requires_https() {
+        assert!(parse_homeserver_url("https://matrix.example.org").is_ok());
+        assert!(parse_homeserver_url("http://matrix.example.org").is_err());
+        assert!(parse_homeserver_url("matrix.example.org").is_err());
+    }
+}
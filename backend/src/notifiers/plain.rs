@@ -0,0 +1,71 @@
+//! Plain-text renderings of notifications, for users who prefer them, e.g.
+//! with screen readers, and as the fallback body backends require.
+
+/// Converts the subset of HTML used in notifications to plain text. Tags are
+/// dropped, links are followed by their URL unless it's their text, and
+/// entities are unescaped.
+pub(crate) fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut href: Option<String> = None;
+    let mut link_start = 0;
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&unescape(&rest[..start]));
+        let Some(end) = rest[start..].find('>') else {
+            rest = &rest[start..];
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        if let Some(attrs) = tag.strip_prefix("a ") {
+            href = attrs
+                .split_once("href=\"")
+                .and_then(|(_, value)| value.split_once('"'))
+                .map(|(value, _)| unescape(value));
+            link_start = text.len();
+        } else if tag == "/a" {
+            if let Some(href) = href.take() {
+                if text[link_start..].trim() != href {
+                    text.push_str(&format!(" ({href})"));
+                }
+            }
+        } else if tag == "br" || tag == "br/" {
+            text.push('\n');
+        }
+        rest = &rest[start + end + 1..];
+    }
+    text.push_str(&unescape(rest));
+    text
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifiers::Message;
+
+    #[test]
+    fn html_to_text_spells_out_links() {
+        assert_eq!(
+            html_to_text(
+                "🎁 <i>Koso</i> assigned to you:\n<a href=\"https://koso.app/p?a=1&amp;b=2\"><b>a &amp; b &lt;c&gt;</b></a>"
+            ),
+            "🎁 Koso assigned to you:\na & b <c> (https://koso.app/p?a=1&b=2)"
+        );
+        assert_eq!(
+            html_to_text("<a href=\"https://koso.app\">https://koso.app</a>"),
+            "https://koso.app"
+        );
+        assert_eq!(html_to_text("&amp;lt;"), "&lt;");
+
+        let message = Message::new("<b>1 &lt; 2</b>".to_string(), None, None);
+        assert_eq!(message.text, "1 < 2");
+        assert_eq!(message.to_plain().html, "1 &lt; 2");
+    }
+}
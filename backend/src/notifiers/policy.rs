@@ -0,0 +1,377 @@
+use crate::{
+    api::{ApiResult, bad_request_error, google::User, model::parse_time_zone},
+    i18n::Locale,
+    notifiers::{Message, plain::html_to_text},
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{Extension, Json, Router, routing::get};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use chrono::{DateTime, Timelike as _, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, types::Json as SqlJson};
+
+const DEFAULT_BATCH_THRESHOLD: i32 = 5;
+const DEFAULT_BATCH_WINDOW_MINUTES: i32 = 10;
+/// Held batches are released once no message has been added for this long.
+const SETTLE_MINUTES: i32 = 2;
+/// Maximum number of messages quoted in a summary.
+const MAX_SUMMARY_ITEMS: usize = 10;
+
+pub(super) fn router() -> Router {
+    Router::new().route(
+        "/",
+        get(get_preferences_handler).put(update_preferences_handler),
+    )
+}
+
+/// Hours of the day, in the user's timezone, when notifications are held.
+/// May wrap around midnight, e.g. 22 to 7.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct QuietHours {
+    /// [0, 24)
+    start_hour: u32,
+    /// [0, 24), and not equal to start_hour.
+    end_hour: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct NotificationPreferences {
+    quiet_hours: Option<QuietHours>,
+    /// Once this many messages have been sent within the batch window, further
+    /// messages are held and sent together as one summary. Zero disables batching.
+    batch_threshold: i32,
+    batch_window_minutes: i32,
+    /// Deliver messages as plain text, e.g. for screen readers, rather than
+    /// formatted HTML.
+    #[serde(default)]
+    plain_text: bool,
+}
+
+impl Default for NotificationPreferences {
+    fn default() -> Self {
+        NotificationPreferences {
+            quiet_hours: None,
+            batch_threshold: DEFAULT_BATCH_THRESHOLD,
+            batch_window_minutes: DEFAULT_BATCH_WINDOW_MINUTES,
+            plain_text: false,
+        }
+    }
+}
+
+/// A user's timezone and, if they've set any, their preferences.
+#[derive(FromRow)]
+struct PreferencesRow {
+    timezone: Option<String>,
+    quiet_hours: Option<SqlJson<QuietHours>>,
+    batch_threshold: Option<i32>,
+    batch_window_minutes: Option<i32>,
+    plain_text: Option<bool>,
+}
+
+/// Whether a message should be sent now or held for a later summary.
+pub(super) enum Decision {
+    Send,
+    Hold(&'static str),
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn get_preferences_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+) -> ApiResult<Json<NotificationPreferences>> {
+    let (preferences, _) = fetch_preferences(pool, &user.email).await?;
+    Ok(Json(preferences))
+}
+
+#[tracing::instrument(skip(user, pool))]
+async fn update_preferences_handler(
+    Extension(user): Extension<User>,
+    Extension(pool): Extension<&'static PgPool>,
+    Json(preferences): Json<NotificationPreferences>,
+) -> ApiResult<Json<NotificationPreferences>> {
+    if let Some(QuietHours {
+        start_hour,
+        end_hour,
+    }) = &preferences.quiet_hours
+    {
+        if *start_hour >= 24 || *end_hour >= 24 || start_hour == end_hour {
+            return Err(bad_request_error(
+                "INVALID_QUIET_HOURS",
+                &format!("Invalid quiet hours: {start_hour}-{end_hour}"),
+            ));
+        }
+    }
+    if preferences.batch_threshold < 0 || preferences.batch_threshold > 1000 {
+        return Err(bad_request_error(
+            "INVALID_BATCH_THRESHOLD",
+            "Batch threshold must be between 0 and 1000",
+        ));
+    }
+    if preferences.batch_window_minutes < 1 || preferences.batch_window_minutes > 24 * 60 {
+        return Err(bad_request_error(
+            "INVALID_BATCH_WINDOW",
+            "Batch window must be between 1 minute and 24 hours",
+        ));
+    }
+
+    sqlx::query(
+        "
+        INSERT INTO notification_preferences (email, quiet_hours, batch_threshold, batch_window_minutes, plain_text)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (email)
+        DO UPDATE SET
+            quiet_hours = EXCLUDED.quiet_hours,
+            batch_threshold = EXCLUDED.batch_threshold,
+            batch_window_minutes = EXCLUDED.batch_window_minutes,
+            plain_text = EXCLUDED.plain_text",
+    )
+    .bind(&user.email)
+    .bind(preferences.quiet_hours.as_ref().map(SqlJson))
+    .bind(preferences.batch_threshold)
+    .bind(preferences.batch_window_minutes)
+    .bind(preferences.plain_text)
+    .execute(pool)
+    .await
+    .context("Failed to update notification preferences")?;
+    Ok(Json(preferences))
+}
+
+/// Returns the user's preferences, or the defaults, and their timezone.
+async fn fetch_preferences(
+    pool: &PgPool,
+    email: &str,
+) -> Result<(NotificationPreferences, Option<String>)> {
+    let row: Option<PreferencesRow> = sqlx::query_as(
+        "
+        SELECT users.timezone, prefs.quiet_hours, prefs.batch_threshold, prefs.batch_window_minutes, prefs.plain_text
+        FROM users
+        LEFT JOIN notification_preferences prefs USING (email)
+        WHERE users.email = $1",
+    )
+    .bind(email)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch notification preferences")?;
+    let Some(PreferencesRow {
+        timezone,
+        quiet_hours,
+        batch_threshold: Some(batch_threshold),
+        batch_window_minutes: Some(batch_window_minutes),
+        plain_text: Some(plain_text),
+    }) = row
+    else {
+        return Ok((
+            NotificationPreferences::default(),
+            row.and_then(|row| row.timezone),
+        ));
+    };
+    Ok((
+        NotificationPreferences {
+            quiet_hours: quiet_hours.map(|SqlJson(q)| q),
+            batch_threshold,
+            batch_window_minutes,
+            plain_text,
+        },
+        timezone,
+    ))
+}
+
+/// Decides whether a message to the recipient should be sent now. Messages are
+/// held during quiet hours, and when the recipient has already been sent
+/// `batch_threshold` messages within the batch window.
+pub(super) async fn decide(pool: &PgPool, recipient: &str) -> Result<Decision> {
+    let (preferences, timezone) = fetch_preferences(pool, recipient).await?;
+    if let Some(quiet_hours) = &preferences.quiet_hours {
+        if is_quiet(quiet_hours, timezone.as_deref(), Utc::now()) {
+            return Ok(Decision::Hold("quiet"));
+        }
+    }
+    if preferences.batch_threshold == 0 {
+        return Ok(Decision::Send);
+    }
+
+    // Keep adding to a batch that's already being held, so a burst becomes one summary.
+    let (held, recent): (i64, i64) = sqlx::query_as(
+        "
+        SELECT
+            (SELECT COUNT(*) FROM held_notifications WHERE email = $1),
+            (SELECT COUNT(DISTINCT message)
+             FROM notification_deliveries
+             WHERE email = $1 AND create_time > NOW() - make_interval(mins => $2))",
+    )
+    .bind(recipient)
+    .bind(preferences.batch_window_minutes)
+    .fetch_one(pool)
+    .await
+    .context("Failed to count recent notifications")?;
+    if held > 0 || recent >= i64::from(preferences.batch_threshold) {
+        return Ok(Decision::Hold("batched"));
+    }
+    Ok(Decision::Send)
+}
+
+/// Whether the recipient prefers messages as plain text.
+pub(super) async fn prefers_plain_text(pool: &PgPool, recipient: &str) -> Result<bool> {
+    let (preferences, _) = fetch_preferences(pool, recipient).await?;
+    Ok(preferences.plain_text)
+}
+
+pub(super) async fn hold(
+    pool: &PgPool,
+    recipient: &str,
+    message: &Message,
+    reason: &str,
+) -> Result<()> {
+    tracing::debug!("Holding notification to {recipient}: {reason}");
+    sqlx::query(
+        "
+        INSERT INTO held_notifications (id, email, message, reason)
+        VALUES ($1, $2, $3, $4)",
+    )
+    .bind(BASE64_URL_SAFE_NO_PAD.encode(uuid::Uuid::new_v4()))
+    .bind(recipient)
+    .bind(SqlJson(message))
+    .bind(reason)
+    .execute(pool)
+    .await
+    .context("Failed to hold notification")?;
+    Ok(())
+}
+
+/// Removes and returns, per recipient, held messages that are ready to be sent:
+/// the recipient is out of quiet hours and the batch has settled.
+pub(super) async fn take_releasable(pool: &PgPool) -> Result<Vec<(String, Vec<Message>)>> {
+    let emails: Vec<(String,)> = sqlx::query_as(
+        "
+        SELECT email
+        FROM held_notifications
+        GROUP BY email
+        HAVING MAX(create_time) < NOW() - make_interval(mins => $1)",
+    )
+    .bind(SETTLE_MINUTES)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list held notifications")?;
+
+    let mut releasable = Vec::new();
+    for (email,) in emails {
+        let (preferences, timezone) = fetch_preferences(pool, &email).await?;
+        if let Some(quiet_hours) = &preferences.quiet_hours {
+            if is_quiet(quiet_hours, timezone.as_deref(), Utc::now()) {
+                continue;
+            }
+        }
+        let mut held: Vec<(SqlJson<Message>, DateTime<Utc>)> = sqlx::query_as(
+            "
+            DELETE FROM held_notifications
+            WHERE email = $1
+            RETURNING message, create_time",
+        )
+        .bind(&email)
+        .fetch_all(pool)
+        .await
+        .context("Failed to take held notifications")?;
+        held.sort_by_key(|(_, create_time)| *create_time);
+        releasable.push((
+            email,
+            held.into_iter()
+                .map(|(SqlJson(message), _)| message)
+                .collect(),
+        ));
+    }
+    Ok(releasable)
+}
+
+/// Coalesces held messages into one. A single message is sent as is.
+pub(super) fn summarize(mut messages: Vec<Message>, locale: &Locale) -> Option<Message> {
+    if messages.len() <= 1 {
+        return messages.pop();
+    }
+    let mut html = locale.message("digest-summary", &[("count", &messages.len())]);
+    let mut text = html_to_text(&html);
+    for message in messages.iter().take(MAX_SUMMARY_ITEMS) {
+        html.push_str("\n\n");
+        html.push_str(&message.html);
+        text.push_str("\n\n");
+        text.push_str(&message.text());
+    }
+    if messages.len() > MAX_SUMMARY_ITEMS {
+        let more = locale.message(
+            "digest-more",
+            &[("count", &(messages.len() - MAX_SUMMARY_ITEMS))],
+        );
+        html.push_str("\n\n");
+        html.push_str(&more);
+        text.push_str("\n\n");
+        text.push_str(&html_to_text(&more));
+    }
+    Some(Message::new(html, Some(text), None))
+}
+
+fn is_quiet(quiet_hours: &QuietHours, timezone: Option<&str>, now: DateTime<Utc>) -> bool {
+    let hour = now.with_timezone(&parse_time_zone(timezone)).hour();
+    if quiet_hours.start_hour < quiet_hours.end_hour {
+        hour >= quiet_hours.start_hour && hour < quiet_hours.end_hour
+    } else {
+        hour >= quiet_hours.start_hour || hour < quiet_hours.end_hour
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone as _;
+
+    #[test]
+    fn is_quiet_wraps_midnight_and_respects_timezone() {
+        let quiet_hours = QuietHours {
+            start_hour: 22,
+            end_hour: 7,
+        };
+        let at = |hour| Utc.with_ymd_and_hms(2025, 9, 3, hour, 30, 0).unwrap();
+        assert!(is_quiet(&quiet_hours, None, at(2)));
+        assert!(is_quiet(&quiet_hours, None, at(23)));
+        assert!(!is_quiet(&quiet_hours, None, at(7)));
+        assert!(!is_quiet(&quiet_hours, None, at(12)));
+        // 12:30 UTC is 02:30 in Honolulu.
+        assert!(is_quiet(&quiet_hours, Some("Pacific/Honolulu"), at(12)));
+    }
+
+    #[test]
+    fn is_quiet_within_a_day() {
+        let quiet_hours = QuietHours {
+            start_hour: 12,
+            end_hour: 13,
+        };
+        let at = |hour| Utc.with_ymd_and_hms(2025, 9, 3, hour, 0, 0).unwrap();
+        assert!(is_quiet(&quiet_hours, None, at(12)));
+        assert!(!is_quiet(&quiet_hours, None, at(13)));
+        assert!(!is_quiet(&quiet_hours, None, at(2)));
+    }
+
+    #[test]
+    fn summarize_coalesces_messages() {
+        let message = |i: usize| Message::new(format!("<b>message {i}</b>"), None, None);
+        let locale = Locale::default();
+        assert!(summarize(vec![], &locale).is_none());
+        assert_eq!(
+            summarize(vec![message(0)], &locale).unwrap().html,
+            "<b>message 0</b>"
+        );
+
+        let summary = summarize((0..80).map(message).collect(), &locale).unwrap();
+        assert!(summary.html.starts_with("🔔 <b>80 notifications</b>"));
+        assert!(summary.html.contains("message 9"));
+        assert!(!summary.html.contains("message 10"));
+        assert!(summary.html.ends_with("…and 70 more."));
+        assert!(
+            summary
+                .text
+                .starts_with("🔔 80 notifications\n\nmessage 0\n\n")
+        );
+        assert!(!summary.text.contains("<b>"));
+    }
+}
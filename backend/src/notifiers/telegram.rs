@@ -1,8 +1,14 @@
-use crate::api::{ApiResult, error_response, google::User};
-use crate::notifiers::{NotifierSettings, TelegramSettings, UserNotificationConfig};
-use crate::secrets::{Secret, read_secret};
-use crate::settings::settings;
-use anyhow::Result;
+use crate::{
+    api::{ApiResult, bad_request_error, collab::Collab, error_response, google::User},
+    notifiers::{
+        Capabilities, Message, Notifier, NotifierSettings, TelegramSettings, UserNotificationConfig,
+    },
+    postgres::PgPool,
+    secrets::{Secret, read_secret},
+    settings::settings,
+};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
 use axum::{
     Extension, Json, Router,
     routing::{delete, post},
@@ -11,7 +17,6 @@ use dptree::case;
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
-use sqlx::postgres::PgPool;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use teloxide::{
     Bot,
@@ -20,10 +25,17 @@ use teloxide::{
     macros::BotCommands,
     payloads::SendMessageSetters,
     prelude::{Dispatcher, Requester},
-    types::{ParseMode, Update, UserId},
+    types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, ParseMode, Update, UserId},
 };
+use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 
+mod actions;
+
+/// How often snoozed notifications are checked for reminders to send.
+const REMINDER_INTERVAL: Duration = Duration::from_secs(60);
+const NOT_CONNECTED: &str = "This chat isn't connected to Koso yet. Send /token to connect it.";
+
 pub(super) fn router() -> Router {
     Router::new()
         .route("/", post(authorize_telegram))
@@ -120,7 +132,12 @@ async fn send_test_message_handler(
     .fetch_one(pool)
     .await?;
 
-    let NotifierSettings::Telegram(settings) = config.settings;
+    let NotifierSettings::Telegram(settings) = config.settings else {
+        return Err(bad_request_error(
+            "NOT_CONFIGURED",
+            "Telegram notifications are not configured",
+        ));
+    };
 
     let bot = bot_from_secrets()?;
     bot.send_message(
@@ -137,6 +154,51 @@ async fn send_test_message_handler(
 #[command(rename_rule = "lowercase")]
 enum Command {
     Token,
+    Tasks,
+    Done(String),
+}
+
+pub(super) struct TelegramNotifier {
+    bot: Bot,
+}
+
+impl TelegramNotifier {
+    pub(super) fn new(bot: Bot) -> Self {
+        TelegramNotifier { bot }
+    }
+}
+
+#[async_trait]
+impl Notifier for TelegramNotifier {
+    fn id(&self) -> &'static str {
+        "telegram"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            html: true,
+            task_actions: true,
+        }
+    }
+
+    async fn send(&self, settings: &NotifierSettings, message: &Message) -> Result<()> {
+        let NotifierSettings::Telegram(settings) = settings else {
+            return Err(anyhow!("Expected telegram settings"));
+        };
+        let request = self
+            .bot
+            .send_message(UserId(settings.chat_id), &message.html)
+            .parse_mode(ParseMode::Html);
+        match message
+            .task
+            .as_ref()
+            .and_then(|task| assignment_keyboard(&task.project_id, &task.task_id))
+        {
+            Some(keyboard) => request.reply_markup(keyboard).await?,
+            None => request.await?,
+        };
+        Ok(())
+    }
 }
 
 pub(super) fn bot_from_secrets() -> Result<Bot> {
@@ -154,54 +216,67 @@ fn encoding_key_from_secrets() -> Result<EncodingKey> {
     Ok(EncodingKey::from_base64_secret(&secret.data)?)
 }
 
-pub(crate) async fn start_telegram_server(cancel_token: CancellationToken) -> Result<()> {
+/// Starts the bot, which runs until `cancel_token` is cancelled.
+pub(crate) fn start_telegram_server(
+    cancel_token: CancellationToken,
+    pool: &'static PgPool,
+    collab: Collab,
+) -> Result<JoinHandle<()>> {
     let bot = match bot_from_secrets() {
         Ok(bot) => bot,
         Err(error) => {
             if settings().is_dev() {
                 tracing::warn!("Telegram bot not started because token is not set.");
-                return Ok(());
+                return Ok(tokio::spawn(async {}));
             } else {
                 return Err(error);
             }
         }
     };
     let key = encoding_key_from_secrets()?;
-    let schema = Update::filter_message()
-        .filter_map(|update: Update| update.from().cloned())
+    let schema = dptree::entry()
         .branch(
-            teloxide::filter_command::<Command, _>()
-                .branch(case![Command::Token].endpoint(send_token)),
+            Update::filter_message()
+                .filter_map(|update: Update| update.from().cloned())
+                .branch(
+                    teloxide::filter_command::<Command, _>()
+                        .branch(case![Command::Token].endpoint(send_token))
+                        .branch(case![Command::Tasks].endpoint(send_tasks))
+                        .branch(case![Command::Done(num)].endpoint(complete_task)),
+                )
+                .branch(dptree::endpoint(send_usage)),
         )
-        .branch(dptree::endpoint(send_usage));
-    let mut dis = Dispatcher::builder(bot, schema)
-        .dependencies(dptree::deps![key])
+        .branch(Update::filter_callback_query().endpoint(handle_callback));
+    let mut dis = Dispatcher::builder(bot.clone(), schema)
+        .dependencies(dptree::deps![key, pool, collab])
         .build();
 
-    let token = dis.shutdown_token();
-    let abort_token = tokio::spawn(async move { dis.dispatch().await });
+    Ok(tokio::spawn(async move {
+        let token = dis.shutdown_token();
+        let abort_token = tokio::spawn(async move { dis.dispatch().await });
+        let reminders = tokio::spawn(send_reminders(bot, pool));
 
-    cancel_token.cancelled().await;
-    match token.shutdown() {
-        Err(error) => {
-            tracing::warn!("Error while shutting down Teloxide: {error}");
-        }
-        Ok(f) => {
-            if tokio::time::timeout(Duration::from_secs(2), f)
-                .await
-                .is_err()
-            {}
+        cancel_token.cancelled().await;
+        reminders.abort();
+        match token.shutdown() {
+            Err(error) => {
+                tracing::warn!("Error while shutting down Teloxide: {error}");
+            }
+            Ok(f) => {
+                if tokio::time::timeout(Duration::from_secs(2), f)
+                    .await
+                    .is_err()
+                {}
+            }
         }
-    }
-    // Finally, in case we weren't able to cleanly shut down the dispatcher,
-    // abort the dispatcher task. This can happen when shutdown races with
-    // startup and the call to shutdown() above returns an error, or when
-    // waiting for the shutdown future to complete times out.
-    abort_token.abort();
-
-    tracing::info!("Telegram bot shutdown.");
+        // Finally, in case we weren't able to cleanly shut down the dispatcher,
+        // abort the dispatcher task. This can happen when shutdown races with
+        // startup and the call to shutdown() above returns an error, or when
+        // waiting for the shutdown future to complete times out.
+        abort_token.abort();
 
-    Ok(())
+        tracing::info!("Telegram bot shutdown.");
+    }))
 }
 
 async fn send_usage(bot: Bot, user: teloxide::types::User) -> Result<()> {
@@ -209,8 +284,10 @@ async fn send_usage(bot: Bot, user: teloxide::types::User) -> Result<()> {
     bot.send_message(
         user.id,
         concat!(
-            "I can help you authorize Koso to send notifications.\n\n",
-            "/token - start the authorization flow"
+            "I can help you authorize Koso to send notifications and manage your tasks.\n\n",
+            "/token - start the authorization flow\n",
+            "/tasks - list your open tasks\n",
+            "/done <number> - complete one of your tasks"
         ),
     )
     .await?;
@@ -233,3 +310,124 @@ async fn send_token(bot: Bot, key: EncodingKey, user: teloxide::types::User) ->
         .await?;
     Ok(())
 }
+
+async fn send_tasks(
+    bot: Bot,
+    user: teloxide::types::User,
+    pool: &'static PgPool,
+    collab: Collab,
+) -> Result<()> {
+    let text = match actions::user_for_chat(pool, user.id.0).await? {
+        Some(koso_user) => actions::list_open_tasks(pool, &collab, &koso_user).await?,
+        None => NOT_CONNECTED.to_string(),
+    };
+    bot.send_message(user.id, text)
+        .parse_mode(ParseMode::Html)
+        .await?;
+    Ok(())
+}
+
+async fn complete_task(
+    bot: Bot,
+    user: teloxide::types::User,
+    num: String,
+    pool: &'static PgPool,
+    collab: Collab,
+) -> Result<()> {
+    let text = match actions::user_for_chat(pool, user.id.0).await? {
+        Some(koso_user) => actions::complete_task(pool, &collab, &koso_user, &num).await?,
+        None => NOT_CONNECTED.to_string(),
+    };
+    bot.send_message(user.id, text)
+        .parse_mode(ParseMode::Html)
+        .await?;
+    Ok(())
+}
+
+/// Handles the buttons on assignment notifications.
+async fn handle_callback(
+    bot: Bot,
+    query: CallbackQuery,
+    pool: &'static PgPool,
+    collab: Collab,
+) -> Result<()> {
+    bot.answer_callback_query(query.id.clone()).await?;
+    let Some(callback) = query.data.as_deref().and_then(actions::Callback::decode) else {
+        tracing::debug!("Discarding unknown callback: {:?}", query.data);
+        return Ok(());
+    };
+    let chat_id = query.from.id;
+    let Some(koso_user) = actions::user_for_chat(pool, chat_id.0).await? else {
+        bot.send_message(chat_id, NOT_CONNECTED).await?;
+        return Ok(());
+    };
+    let reply = actions::handle_callback(pool, &collab, &koso_user, chat_id.0, callback).await?;
+    let message = bot
+        .send_message(chat_id, reply.text)
+        .parse_mode(ParseMode::Html);
+    if reply.choices.is_empty() {
+        message.await?;
+    } else {
+        message.reply_markup(keyboard(reply.choices)).await?;
+    }
+    Ok(())
+}
+
+/// Periodically resends snoozed assignment notifications.
+async fn send_reminders(bot: Bot, pool: &'static PgPool) {
+    let mut interval = tokio::time::interval(REMINDER_INTERVAL);
+    loop {
+        interval.tick().await;
+        let reminders = match actions::take_due_reminders(pool).await {
+            Ok(reminders) => reminders,
+            Err(e) => {
+                tracing::warn!("Failed to take due reminders: {e:?}");
+                continue;
+            }
+        };
+        for (chat_id, project_id, task_id, message) in reminders {
+            let message = bot
+                .send_message(UserId(chat_id as u64), message)
+                .parse_mode(ParseMode::Html);
+            let res = match assignment_keyboard(&project_id, &task_id) {
+                Some(keyboard) => message.reply_markup(keyboard).await,
+                None => message.await,
+            };
+            if let Err(e) = res {
+                tracing::warn!("Failed to send reminder to {chat_id}: {e:?}");
+            }
+        }
+    }
+}
+
+/// Buttons to accept, snooze or reassign a newly assigned task, if the task's
+/// ids fit in Telegram's callback data.
+fn assignment_keyboard(project_id: &str, task_id: &str) -> Option<InlineKeyboardMarkup> {
+    let row = [
+        ("👍 Accept", actions::TaskAction::Accept),
+        ("💤 Snooze", actions::TaskAction::Snooze),
+        ("👥 Reassign", actions::TaskAction::Reassign),
+    ]
+    .into_iter()
+    .map(|(label, action)| {
+        let data = actions::Callback {
+            action,
+            project_id: project_id.to_string(),
+            task_id: task_id.to_string(),
+        }
+        .encode()?;
+        Some(InlineKeyboardButton::callback(label, data))
+    })
+    .collect::<Option<Vec<_>>>()?;
+    Some(InlineKeyboardMarkup::new(vec![row]))
+}
+
+/// One button per row.
+fn keyboard(choices: Vec<(String, actions::Callback)>) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(choices.into_iter().filter_map(|(label, callback)| {
+        Some(vec![InlineKeyboardButton::callback(
+            label,
+            callback.encode()?,
+        )])
+    }))
+}
@@ -0,0 +1,510 @@
+//! Task actions available from the Telegram bot's commands and inline buttons.
+
+use crate::{
+    api::{
+        collab::{
+            Collab,
+            txn_origin::{Actor, YOrigin},
+        },
+        goals,
+        google::User,
+        model::{Task, WorkflowCategory, WorkflowState},
+        projects::list_projects,
+        proposals::transact_or_propose,
+        reports::escape_html,
+        yproxy::{self, YTaskProxy},
+    },
+    postgres::{PgPool, list_project_users},
+};
+use anyhow::{Context as _, Result};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use chrono::{TimeDelta, Utc};
+use std::time::SystemTime;
+use uuid::Uuid;
+use yrs::TransactionMut;
+
+const MAX_LISTED_TASKS: usize = 20;
+const MAX_REASSIGN_MEMBERS: usize = 8;
+/// Telegram limits callback data to 64 bytes.
+const MAX_CALLBACK_DATA_LEN: usize = 64;
+
+/// An action on a task triggered by an inline button.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum TaskAction {
+    Accept,
+    Snooze,
+    /// Lists the members the task can be reassigned to.
+    Reassign,
+    /// Reassigns the task to the listed member at the index.
+    ReassignTo(usize),
+}
+
+/// The data attached to an inline button, e.g. "a:<project id>:<task id>".
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct Callback {
+    pub(super) action: TaskAction,
+    pub(super) project_id: String,
+    pub(super) task_id: String,
+}
+
+impl Callback {
+    /// Encodes the callback, if it fits in Telegram's limit.
+    pub(super) fn encode(&self) -> Option<String> {
+        let code = match self.action {
+            TaskAction::Accept => "a".to_string(),
+            TaskAction::Snooze => "s".to_string(),
+            TaskAction::Reassign => "r".to_string(),
+            TaskAction::ReassignTo(index) => format!("r{index}"),
+        };
+        let data = format!("{code}:{}:{}", self.project_id, self.task_id);
+        (data.len() <= MAX_CALLBACK_DATA_LEN).then_some(data)
+    }
+
+    pub(super) fn decode(data: &str) -> Option<Callback> {
+        let mut parts = data.splitn(3, ':');
+        let action = match parts.next()? {
+            "a" => TaskAction::Accept,
+            "s" => TaskAction::Snooze,
+            "r" => TaskAction::Reassign,
+            code => TaskAction::ReassignTo(code.strip_prefix('r')?.parse().ok()?),
+        };
+        Some(Callback {
+            action,
+            project_id: parts.next()?.to_string(),
+            task_id: parts.next()?.to_string(),
+        })
+    }
+}
+
+/// The bot's reply to an action, with optional follow up buttons.
+pub(super) struct Reply {
+    pub(super) text: String,
+    pub(super) choices: Vec<(String, Callback)>,
+}
+
+impl Reply {
+    fn text(text: String) -> Reply {
+        Reply {
+            text,
+            choices: vec![],
+        }
+    }
+}
+
+/// The outcome of an action on a task, described for the user.
+enum Edit {
+    Changed(String),
+    /// Nothing was changed, e.g. because the task was already done.
+    Unchanged(String),
+}
+
+/// Finds the Koso user who connected the chat.
+pub(super) async fn user_for_chat(pool: &PgPool, chat_id: u64) -> Result<Option<User>> {
+    let user: Option<(String, String, String)> = sqlx::query_as(
+        "
+        SELECT email, name, picture
+        FROM user_notification_configs
+        JOIN users USING (email)
+        WHERE notifier = 'telegram'
+        AND (settings->>'chatId')::bigint = $1",
+    )
+    .bind(chat_id as i64)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up telegram user")?;
+    Ok(user.map(|(email, name, picture)| User {
+        email,
+        name,
+        picture,
+        exp: 0,
+        iat: None,
+        auth_time: None,
+        nonce: None,
+    }))
+}
+
+struct OpenTask {
+    project_id: String,
+    project_name: String,
+    task: Task,
+}
+
+/// Lists the unfinished tasks assigned to the user across their projects.
+async fn open_tasks(pool: &PgPool, collab: &Collab, user: &User) -> Result<Vec<OpenTask>> {
+    let mut open = vec![];
+    for project in list_projects(&user.email, pool).await? {
+        if project.deleted_on.is_some() {
+            continue;
+        }
+        let (graph, workflow_states) = goals::load_graph(collab, &project.project_id).await?;
+        let mut tasks: Vec<Task> = graph
+            .into_values()
+            .filter(|task| {
+                task.assignee.as_ref() == Some(&user.email)
+                    && task.archived != Some(true)
+                    && task.children.is_empty()
+                    && task
+                        .status
+                        .as_deref()
+                        .and_then(|s| yproxy::status_category(&workflow_states, s))
+                        != Some(WorkflowCategory::Done)
+            })
+            .collect();
+        tasks.sort_by_key(|task| task.num.parse::<u64>().unwrap_or_default());
+        open.extend(tasks.into_iter().map(|task| OpenTask {
+            project_id: project.project_id.clone(),
+            project_name: project.name.clone(),
+            task,
+        }));
+    }
+    Ok(open)
+}
+
+/// Formats the user's open tasks for the `/tasks` command.
+pub(super) async fn list_open_tasks(pool: &PgPool, collab: &Collab, user: &User) -> Result<String> {
+    let open = open_tasks(pool, collab, user).await?;
+    if open.is_empty() {
+        return Ok("🎉 You have no open tasks.".to_string());
+    }
+    let mut text = format!("📋 <b>Your open tasks ({})</b>", open.len());
+    for task in open.iter().take(MAX_LISTED_TASKS) {
+        text.push_str(&format!(
+            "\n• {} <i>{}</i>",
+            task_link(&task.project_id, &task.task),
+            escape_html(&task.project_name)
+        ));
+    }
+    if open.len() > MAX_LISTED_TASKS {
+        text.push_str(&format!("\n…and {} more", open.len() - MAX_LISTED_TASKS));
+    }
+    text.push_str("\n\nComplete one with /done &lt;number&gt;");
+    Ok(text)
+}
+
+/// Completes the open task with the given number for the `/done` command.
+pub(super) async fn complete_task(
+    pool: &'static PgPool,
+    collab: &Collab,
+    user: &User,
+    num: &str,
+) -> Result<String> {
+    let num = num.trim().trim_start_matches('#');
+    if num.is_empty() {
+        return Ok("Usage: /done &lt;number&gt;".to_string());
+    }
+    let matches: Vec<OpenTask> = open_tasks(pool, collab, user)
+        .await?
+        .into_iter()
+        .filter(|t| t.task.num == num)
+        .collect();
+    let open = match matches.as_slice() {
+        [] => {
+            return Ok(format!(
+                "No open task #{} is assigned to you.",
+                escape_html(num)
+            ));
+        }
+        [open] => open,
+        _ => {
+            return Ok(format!(
+                "More than one of your projects has an open task #{}. Complete it in Koso instead.",
+                escape_html(num)
+            ));
+        }
+    };
+    update_task(
+        pool,
+        collab,
+        user,
+        &open.project_id,
+        &open.task.id,
+        |txn, task, workflow_states| {
+            let Some(status) = yproxy::status_for_category(workflow_states, WorkflowCategory::Done)
+            else {
+                return Ok(Edit::Unchanged(
+                    "The project has no done status.".to_string(),
+                ));
+            };
+            task.set_status(txn, Some(&status));
+            task.set_status_time(txn, Some(now()?));
+            Ok(Edit::Changed(format!(
+                "✅ Completed {}",
+                task_link(&open.project_id, &open.task)
+            )))
+        },
+    )
+    .await
+}
+
+/// Handles an inline button pressed on an assignment notification.
+pub(super) async fn handle_callback(
+    pool: &'static PgPool,
+    collab: &Collab,
+    user: &User,
+    chat_id: u64,
+    callback: Callback,
+) -> Result<Reply> {
+    let has_access: Option<(i32,)> =
+        sqlx::query_as("SELECT 1 FROM project_permissions WHERE email = $1 AND project_id = $2")
+            .bind(&user.email)
+            .bind(&callback.project_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to check project access")?;
+    if has_access.is_none() {
+        return Ok(Reply::text(
+            "You no longer have access to this project.".to_string(),
+        ));
+    }
+
+    let project_id = &callback.project_id;
+    match callback.action {
+        TaskAction::Accept => Ok(Reply::text(
+            update_task(
+                pool,
+                collab,
+                user,
+                project_id,
+                &callback.task_id,
+                |txn, task, workflow_states| {
+                    let link = ytask_link(txn, project_id, task)?;
+                    let category = task
+                        .get_status(txn)?
+                        .and_then(|s| yproxy::status_category(workflow_states, &s));
+                    if category.is_some_and(|c| c != WorkflowCategory::NotStarted) {
+                        return Ok(Edit::Unchanged(format!("{link} is already underway.")));
+                    }
+                    let Some(status) =
+                        yproxy::status_for_category(workflow_states, WorkflowCategory::InProgress)
+                    else {
+                        return Ok(Edit::Unchanged(
+                            "The project has no in progress status.".to_string(),
+                        ));
+                    };
+                    task.set_status(txn, Some(&status));
+                    task.set_status_time(txn, Some(now()?));
+                    Ok(Edit::Changed(format!("👍 Accepted {link}")))
+                },
+            )
+            .await?,
+        )),
+        TaskAction::Snooze => {
+            let (graph, _) = goals::load_graph(collab, project_id).await?;
+            let Some(task) = graph.get(&callback.task_id) else {
+                return Ok(Reply::text("The task no longer exists.".to_string()));
+            };
+            sqlx::query(
+                "
+                INSERT INTO telegram_reminders (id, chat_id, project_id, task_id, message, remind_time)
+                VALUES ($1, $2, $3, $4, $5, $6)",
+            )
+            .bind(BASE64_URL_SAFE_NO_PAD.encode(Uuid::new_v4()))
+            .bind(chat_id as i64)
+            .bind(project_id)
+            .bind(&callback.task_id)
+            .bind(format!("⏰ Reminder: {}", task_link(project_id, task)))
+            .bind(Utc::now() + TimeDelta::days(1))
+            .execute(pool)
+            .await
+            .context("Failed to insert telegram reminder")?;
+            Ok(Reply::text(format!(
+                "💤 Snoozed {} until tomorrow",
+                task_link(project_id, task)
+            )))
+        }
+        TaskAction::Reassign => {
+            let members = reassign_members(pool, project_id, user).await?;
+            if members.is_empty() {
+                return Ok(Reply::text(
+                    "There's no one else in the project to reassign to.".to_string(),
+                ));
+            }
+            Ok(Reply {
+                text: "Reassign to:".to_string(),
+                choices: members
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, (_, label))| {
+                        (
+                            label,
+                            Callback {
+                                action: TaskAction::ReassignTo(index),
+                                project_id: project_id.clone(),
+                                task_id: callback.task_id.clone(),
+                            },
+                        )
+                    })
+                    .collect(),
+            })
+        }
+        TaskAction::ReassignTo(index) => {
+            let members = reassign_members(pool, project_id, user).await?;
+            let Some((email, _)) = members.get(index) else {
+                return Ok(Reply::text(
+                    "The project's members changed. Try again.".to_string(),
+                ));
+            };
+            Ok(Reply::text(
+                update_task(
+                    pool,
+                    collab,
+                    user,
+                    project_id,
+                    &callback.task_id,
+                    |txn, task, _| {
+                        let link = ytask_link(txn, project_id, task)?;
+                        if task.get_assignee(txn)?.as_ref() != Some(&user.email) {
+                            return Ok(Edit::Unchanged(format!(
+                                "{link} is no longer assigned to you."
+                            )));
+                        }
+                        task.set_assignee(txn, Some(email));
+                        Ok(Edit::Changed(format!(
+                            "👥 Reassigned {link} to {}",
+                            escape_html(email)
+                        )))
+                    },
+                )
+                .await?,
+            ))
+        }
+    }
+}
+
+/// Removes and returns due reminders as (chat id, project id, task id, message) tuples.
+pub(super) async fn take_due_reminders(
+    pool: &PgPool,
+) -> Result<Vec<(i64, String, String, String)>> {
+    sqlx::query_as(
+        "
+        DELETE FROM telegram_reminders
+        WHERE remind_time <= NOW()
+        RETURNING chat_id, project_id, task_id, message",
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to take due telegram reminders")
+}
+
+/// Lists the other project members, as (email, label) pairs, in a stable order.
+async fn reassign_members(
+    pool: &PgPool,
+    project_id: &String,
+    user: &User,
+) -> Result<Vec<(String, String)>> {
+    let mut members: Vec<(String, String)> = list_project_users(pool, project_id)
+        .await?
+        .into_iter()
+        .filter(|u| u.email != user.email)
+        .map(|u| {
+            let label = if u.name.is_empty() {
+                u.email.clone()
+            } else {
+                u.name.clone()
+            };
+            (u.email, label)
+        })
+        .collect();
+    members.sort();
+    members.truncate(MAX_REASSIGN_MEMBERS);
+    Ok(members)
+}
+
+/// Applies `f` to the task in a transaction attributed to the user. Changes
+/// that must be reviewed are proposed instead, and the user is told so.
+async fn update_task<F>(
+    pool: &'static PgPool,
+    collab: &Collab,
+    user: &User,
+    project_id: &String,
+    task_id: &str,
+    f: F,
+) -> Result<String>
+where
+    F: FnOnce(&mut TransactionMut, &YTaskProxy, &[WorkflowState]) -> Result<Edit>,
+{
+    let origin = YOrigin {
+        who: "telegram".to_string(),
+        id: format!("telegram_{task_id}"),
+        actor: Actor::User(user.clone()),
+        ..Default::default()
+    };
+    let transacted = transact_or_propose(pool, collab, project_id, &origin, |doc, txn| {
+        let Ok(task) = doc.get(txn, task_id) else {
+            return Ok(Edit::Unchanged("The task no longer exists.".to_string()));
+        };
+        let workflow_states = doc.config().get_workflow_states(txn)?;
+        Ok(f(txn, &task, &workflow_states)?)
+    })
+    .await
+    .map_err(|e| e.as_err())?;
+    Ok(match transacted.result {
+        Edit::Changed(text) if transacted.proposed => {
+            format!("{text}\n\n📝 Your change is waiting for review by the project's admins.")
+        }
+        Edit::Changed(text) | Edit::Unchanged(text) => text,
+    })
+}
+
+fn task_link(project_id: &str, task: &Task) -> String {
+    let name = if task.name.is_empty() {
+        format!("Task #{}", task.num)
+    } else {
+        format!("#{} {}", task.num, task.name)
+    };
+    format!(
+        "<a href=\"https://koso.app/projects/{project_id}?taskId={}\">{}</a>",
+        task.id,
+        escape_html(&name)
+    )
+}
+
+fn ytask_link(txn: &TransactionMut, project_id: &str, task: &YTaskProxy) -> Result<String> {
+    Ok(task_link(project_id, &task.to_task(txn)?))
+}
+
+fn now() -> Result<i64> {
+    Ok(SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_millis()
+        .try_into()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn callback_round_trips() {
+        for action in [
+            TaskAction::Accept,
+            TaskAction::Snooze,
+            TaskAction::Reassign,
+            TaskAction::ReassignTo(3),
+        ] {
+            let callback = Callback {
+                action,
+                project_id: "JgQ1sAoaRnWX2f8bjRCWSw".to_string(),
+                task_id: "fcqhGLxPQkuHZbqGEzUrJw".to_string(),
+            };
+            let data = callback.encode().unwrap();
+            assert_eq!(Callback::decode(&data), Some(callback));
+        }
+    }
+
+    #[test]
+    fn callback_rejects_invalid_data() {
+        assert_eq!(Callback::decode("x:p:t"), None);
+        assert_eq!(Callback::decode("rx:p:t"), None);
+        assert_eq!(Callback::decode("a:p"), None);
+        assert_eq!(
+            Callback {
+                action: TaskAction::Accept,
+                project_id: "p".repeat(40),
+                task_id: "t".repeat(40),
+            }
+            .encode(),
+            None
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! A minimal object store for blobs too cold to keep in Postgres, backed by
+//! Google Cloud Storage in production and a local directory in development.
+
+use crate::settings::settings;
+use anyhow::{Context as _, Result, anyhow};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::{
+    path::PathBuf,
+    sync::{LazyLock, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+const GCS_API: &str = "https://storage.googleapis.com";
+/// Issues access tokens for the service account the server runs as.
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+/// Tokens are refreshed this long before they expire.
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(reqwest::Client::new);
+
+pub(crate) enum ObjectStore {
+    Gcs {
+        bucket: String,
+        token: Mutex<Option<(String, Instant)>>,
+    },
+    Local {
+        dir: PathBuf,
+    },
+}
+
+/// Returns the configured object store, or None if none is configured.
+pub(crate) fn object_store() -> Option<&'static ObjectStore> {
+    static STORE: OnceLock<Option<ObjectStore>> = OnceLock::new();
+    STORE
+        .get_or_init(|| {
+            let url = settings().cold_storage.url.as_deref()?;
+            match ObjectStore::from_url(url) {
+                Ok(store) => Some(store),
+                Err(e) => {
+                    tracing::error!("Invalid object store URL {url}: {e:?}");
+                    None
+                }
+            }
+        })
+        .as_ref()
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: String,
+    expires_in: u64,
+}
+
+impl ObjectStore {
+    /// Parses a gs://bucket or file:///path URL.
+    fn from_url(url: &str) -> Result<ObjectStore> {
+        if let Some(bucket) = url.strip_prefix("gs://") {
+            return Ok(ObjectStore::Gcs {
+                bucket: bucket.trim_end_matches('/').to_string(),
+                token: Mutex::new(None),
+            });
+        }
+        if let Some(dir) = url.strip_prefix("file://") {
+            return Ok(ObjectStore::Local {
+                dir: PathBuf::from(dir),
+            });
+        }
+        Err(anyhow!("Expected a gs:// or file:// URL"))
+    }
+
+    pub(crate) async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        match self {
+            ObjectStore::Gcs { bucket, .. } => {
+                CLIENT
+                    .post(format!("{GCS_API}/upload/storage/v1/b/{bucket}/o"))
+                    .query(&[("uploadType", "media"), ("name", key)])
+                    .bearer_auth(self.access_token().await?)
+                    .body(data)
+                    .send()
+                    .await?
+                    .error_for_status()
+                    .with_context(|| format!("Failed to upload {key}"))?;
+            }
+            ObjectStore::Local { dir } => {
+                let path = dir.join(key);
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                tokio::fs::write(&path, data)
+                    .await
+                    .with_context(|| format!("Failed to write {path:?}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        match self {
+            ObjectStore::Gcs { bucket, .. } => Ok(CLIENT
+                .get(Self::gcs_object_url(bucket, key))
+                .query(&[("alt", "media")])
+                .bearer_auth(self.access_token().await?)
+                .send()
+                .await?
+                .error_for_status()
+                .with_context(|| format!("Failed to download {key}"))?
+                .bytes()
+                .await?
+                .to_vec()),
+            ObjectStore::Local { dir } => {
+                let path = dir.join(key);
+                tokio::fs::read(&path)
+                    .await
+                    .with_context(|| format!("Failed to read {path:?}"))
+            }
+        }
+    }
+
+    /// Deletes the object. Deleting a missing object succeeds.
+    pub(crate) async fn delete(&self, key: &str) -> Result<()> {
+        match self {
+            ObjectStore::Gcs { bucket, .. } => {
+                let res = CLIENT
+                    .delete(Self::gcs_object_url(bucket, key))
+                    .bearer_auth(self.access_token().await?)
+                    .send()
+                    .await?;
+                if res.status() != StatusCode::NOT_FOUND {
+                    res.error_for_status()
+                        .with_context(|| format!("Failed to delete {key}"))?;
+                }
+            }
+            ObjectStore::Local { dir } => {
+                let path = dir.join(key);
+                if let Err(e) = tokio::fs::remove_file(&path).await {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        return Err(e).with_context(|| format!("Failed to delete {path:?}"));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn gcs_object_url(bucket: &str, key: &str) -> String {
+        // Object names are a single path segment, so slashes must be escaped.
+        format!(
+            "{GCS_API}/storage/v1/b/{bucket}/o/{}",
+            key.replace('%', "%25").replace('/', "%2F")
+        )
+    }
+
+    async fn access_token(&self) -> Result<String> {
+        let ObjectStore::Gcs { token, .. } = self else {
+            return Err(anyhow!("Only GCS requires access tokens"));
+        };
+        let mut token = token.lock().await;
+        if let Some((access_token, expiry)) = token.as_ref() {
+            if Instant::now() < *expiry {
+                return Ok(access_token.clone());
+            }
+        }
+
+        let res: AccessToken = CLIENT
+            .get(METADATA_TOKEN_URL)
+            .header("Metadata-Flavor", "Google")
+            .send()
+            .await?
+            .error_for_status()
+            .context("Failed to get access token from the metadata server")?
+            .json()
+            .await?;
+        let expiry = Instant::now() + Duration::from_secs(res.expires_in)
+            - TOKEN_EXPIRY_MARGIN.min(Duration::from_secs(res.expires_in));
+        *token = Some((res.access_token.clone(), expiry));
+        Ok(res.access_token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test(tokio::test)]
+    async fn local_store_round_trip() {
+        let dir = std::env::temp_dir().join(format!("koso-object-store-{}", uuid::Uuid::new_v4()));
+        let store = ObjectStore::from_url(&format!("file://{}", dir.display())).unwrap();
+
+        store.put("a/b", vec![1, 2, 3]).await.unwrap();
+        assert_eq!(store.get("a/b").await.unwrap(), vec![1, 2, 3]);
+        store.delete("a/b").await.unwrap();
+        store.delete("a/b").await.unwrap();
+        assert!(store.get("a/b").await.is_err());
+
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[test_log::test]
+    fn from_url_rejects_unknown_schemes() {
+        assert!(ObjectStore::from_url("s3://bucket").is_err());
+        assert!(matches!(
+            ObjectStore::from_url("gs://bucket/").unwrap(),
+            ObjectStore::Gcs { bucket, .. } if bucket == "bucket"
+        ));
+    }
+}
@@ -0,0 +1,361 @@
+//! Server-initiated requests to user-supplied URLs, e.g. webhooks, link
+//! unfurls, avatars and Matrix homeservers. Every such request goes through
+//! `Fetch` so it can't be pointed at the server's own network.
+//!
+//! Hosts are resolved up front and requests are refused if any address isn't
+//! public, e.g. loopback, private or link-local. Connections are pinned to
+//! the checked address, so a second DNS lookup can't point elsewhere.
+//! Redirects are followed manually, re-resolving and checking each hop.
+//! Responses are limited in time, size and, optionally, content type.
+
+use crate::settings::settings;
+use anyhow::{Context as _, Result, anyhow};
+use reqwest::{
+    Method, StatusCode, Url,
+    header::{AUTHORIZATION, CONTENT_TYPE, HeaderName, LOCATION},
+    redirect::Policy,
+};
+use serde::{Serialize, de::DeserializeOwned};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+const MAX_REDIRECTS: usize = 3;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_MAX_LEN: usize = 1024 * 1024;
+const USER_AGENT: &str = "KosoBot/1.0 (+https://koso.app)";
+
+/// A request to a user-supplied URL.
+pub(crate) struct Fetch {
+    method: Method,
+    url: Url,
+    headers: Vec<(HeaderName, String)>,
+    body: Option<Vec<u8>>,
+    timeout: Duration,
+    max_len: usize,
+    content_types: Option<&'static [&'static str]>,
+    truncate: bool,
+}
+
+/// A response, read in full.
+#[derive(Debug)]
+pub(crate) struct Fetched {
+    /// The final URL, after redirects.
+    pub(crate) url: Url,
+    pub(crate) status: StatusCode,
+    /// The media type, lowercased and without parameters.
+    pub(crate) content_type: String,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Fetch {
+    pub(crate) fn new(method: Method, url: Url) -> Self {
+        Fetch {
+            method,
+            url,
+            headers: Vec::new(),
+            body: None,
+            timeout: DEFAULT_TIMEOUT,
+            max_len: DEFAULT_MAX_LEN,
+            content_types: None,
+            truncate: false,
+        }
+    }
+
+    pub(crate) fn get(url: Url) -> Self {
+        Fetch::new(Method::GET, url)
+    }
+
+    pub(crate) fn header(mut self, name: HeaderName, value: &str) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    pub(crate) fn bearer_auth(self, token: &str) -> Self {
+        self.header(AUTHORIZATION, &format!("Bearer {}", token.trim()))
+    }
+
+    pub(crate) fn body(mut self, body: Vec<u8>) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    pub(crate) fn json<T: Serialize>(self, value: &T) -> Result<Self> {
+        let body = serde_json::to_vec(value)?;
+        Ok(self.header(CONTENT_TYPE, "application/json").body(body))
+    }
+
+    /// Limits the whole request, redirects included.
+    pub(crate) fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub(crate) fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Fails successful responses of other media types.
+    pub(crate) fn content_types(mut self, content_types: &'static [&'static str]) -> Self {
+        self.content_types = Some(content_types);
+        self
+    }
+
+    /// Cuts off bodies longer than the limit rather than failing, e.g. for
+    /// pages whose metadata is at their start.
+    pub(crate) fn truncate(mut self) -> Self {
+        self.truncate = true;
+        self
+    }
+
+    /// Sends the request. Only GET requests follow redirects.
+    pub(crate) async fn send(self) -> Result<Fetched> {
+        tokio::time::timeout(self.timeout, self.send_internal())
+            .await
+            .map_err(|_| anyhow!("Timed out fetching {}", self.url))?
+    }
+
+    async fn send_internal(&self) -> Result<Fetched> {
+        let mut url = self.url.clone();
+        let mut headers = self.headers.clone();
+        for _ in 0..=MAX_REDIRECTS {
+            let mut request = client_for(&url)
+                .await?
+                .request(self.method.clone(), url.clone());
+            for (name, value) in &headers {
+                request = request.header(name, value);
+            }
+            if let Some(body) = &self.body {
+                request = request.body(body.clone());
+            }
+            let mut response = request
+                .send()
+                .await
+                .with_context(|| format!("Failed to fetch {url}"))?;
+            let status = response.status();
+            if status.is_redirection() && self.method == Method::GET {
+                let location = response
+                    .headers()
+                    .get(LOCATION)
+                    .and_then(|l| l.to_str().ok())
+                    .ok_or_else(|| anyhow!("Redirect without a location"))?;
+                let next = url.join(location).context("Invalid redirect location")?;
+                // Credentials are only for the origin they were given for.
+                if next.origin() != url.origin() {
+                    headers.retain(|(name, _)| *name != AUTHORIZATION);
+                }
+                url = next;
+                continue;
+            }
+
+            let content_type = response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|t| t.to_str().ok())
+                .and_then(|t| t.split(';').next())
+                .map(|t| t.trim().to_ascii_lowercase())
+                .unwrap_or_default();
+            if let Some(content_types) = self.content_types {
+                if status.is_success() && !content_types.contains(&content_type.as_str()) {
+                    return Err(anyhow!("Unexpected content type {content_type:?}"));
+                }
+            }
+            if !self.truncate
+                && response
+                    .content_length()
+                    .is_some_and(|len| len > self.max_len as u64)
+            {
+                return Err(anyhow!("Response is too long"));
+            }
+            let mut body = Vec::new();
+            while let Some(chunk) = response.chunk().await? {
+                if body.len() + chunk.len() > self.max_len {
+                    if !self.truncate {
+                        return Err(anyhow!("Response is too long"));
+                    }
+                    body.extend_from_slice(&chunk[..self.max_len - body.len()]);
+                    break;
+                }
+                body.extend_from_slice(&chunk);
+            }
+            return Ok(Fetched {
+                url,
+                status,
+                content_type,
+                body,
+            });
+        }
+        Err(anyhow!("Too many redirects"))
+    }
+}
+
+impl Fetched {
+    pub(crate) fn error_for_status(self) -> Result<Self> {
+        if !self.status.is_success() {
+            return Err(anyhow!("{} responded with {}", self.url, self.status));
+        }
+        Ok(self)
+    }
+
+    pub(crate) fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_slice(&self.body)
+            .with_context(|| format!("Invalid JSON response from {}", self.url))
+    }
+}
+
+/// Builds a client that connects to the URL's host only at a checked
+/// address.
+async fn client_for(url: &Url) -> Result<reqwest::Client> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(anyhow!("Unsupported scheme {}", url.scheme()));
+    }
+    let host = url.host_str().ok_or_else(|| anyhow!("URL has no host"))?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    let builder = reqwest::Client::builder()
+        .redirect(Policy::none())
+        .user_agent(USER_AGENT);
+    let builder = match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(ip) => {
+            verify_allowed(host, ip)?;
+            builder
+        }
+        Err(_) => {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+                .await
+                .with_context(|| format!("Failed to resolve {host}"))?
+                .collect();
+            for addr in &addrs {
+                verify_allowed(host, addr.ip())?;
+            }
+            let addr = addrs
+                .first()
+                .ok_or_else(|| anyhow!("{host} has no addresses"))?;
+            builder.resolve(host, *addr)
+        }
+    };
+    Ok(builder.build()?)
+}
+
+/// Refuses non-public addresses, other than in development, where webhooks
+/// and the like are tested against local servers.
+fn verify_allowed(host: &str, ip: IpAddr) -> Result<()> {
+    if is_public(ip) || settings().is_dev() {
+        return Ok(());
+    }
+    metrics::counter!("outbound_fetches_blocked_total").increment(1);
+    Err(anyhow!("{host} resolves to non-public address {ip}"))
+}
+
+/// Whether the address is routable on the public internet, as opposed to
+/// e.g. loopback, private, link-local or reserved.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match embedded_v4(ip) {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+/// The IPv4 address an IPv6 address reaches, if it's IPv4-mapped, the deprecated
+/// IPv4-compatible form (::a.b.c.d), NAT64 (64:ff9b::/96) or 6to4 (2002::/16).
+fn embedded_v4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    if let Some(ip) = ip.to_ipv4() {
+        return Some(ip);
+    }
+    let octets = ip.octets();
+    match ip.segments() {
+        [0x64, 0xff9b, 0, 0, 0, 0, _, _] => Some(Ipv4Addr::new(
+            octets[12], octets[13], octets[14], octets[15],
+        )),
+        [0x2002, ..] => Some(Ipv4Addr::new(octets[2], octets[3], octets[4], octets[5])),
+        _ => None,
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || a == 0
+        // Shared address space, used by carrier-grade NAT.
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking.
+        || (a == 198 && (18..20).contains(&b))
+        // Reserved.
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local.
+        || (first & 0xfe00) == 0xfc00
+        // Link-local.
+        || (first & 0xffc0) == 0xfe80
+        // Documentation.
+        || (first == 0x2001 && ip.segments()[1] == 0xdb8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_public_rejects_internal_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "64:ff9b::10.0.0.1",
+            "2002:7f00:1::",
+            "2002:c0a8:101::1",
+            "::7f00:1",
+            "::10.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "93.184.216.34",
+            "8.8.8.8",
+            "2606:4700::1111",
+            "64:ff9b::808:808",
+            "2002:808:808::1",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn embedded_v4_handles_ipv4_compatible_addresses() {
+        assert_eq!(
+            embedded_v4("::7f00:1".parse().unwrap()),
+            Some(Ipv4Addr::LOCALHOST)
+        );
+        assert_eq!(
+            embedded_v4("::808:808".parse().unwrap()),
+            Some(Ipv4Addr::new(8, 8, 8, 8))
+        );
+        assert_eq!(embedded_v4("2606:4700::1111".parse().unwrap()), None);
+    }
+}
@@ -1,5 +1,9 @@
 mod config;
+pub(crate) mod credentials;
 pub mod github;
+pub(crate) mod identities;
+pub mod slack;
+pub(crate) mod status;
 
 #[derive(Default, Clone)]
 pub(crate) struct PluginSettings {
@@ -1,6 +1,7 @@
+use crate::postgres::PgPool;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use sqlx::{PgPool, types::Json};
+use sqlx::types::Json;
 
 #[derive(Clone)]
 pub(super) struct ConfigStorage {
@@ -20,10 +21,48 @@ pub(crate) struct Config {
 #[serde(rename_all = "camelCase", tag = "type")]
 pub(crate) enum Settings {
     Github(GithubSettings),
+    Slack(SlackSettings),
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GithubSettings {
+    /// Keywords in pushed commit messages that move the referenced task,
+    /// e.g. "fixes KOSO-12". When None, the default keywords are used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) commit_keywords: Option<Vec<CommitKeyword>>,
+    /// Limits which PRs are synced. When None, all of the installation's are.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) scope: Option<SyncScope>,
+}
+
+/// Which of an installation's PRs a project syncs. Empty lists don't restrict.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct SyncScope {
+    /// Repositories to sync, e.g. "kosolabs/koso". Matched case insensitively.
+    pub(crate) repos: Vec<String>,
+    /// Only PRs with at least one of these labels are synced.
+    pub(crate) labels: Vec<String>,
+    pub(crate) exclude_drafts: bool,
+    /// Excludes PRs opened by bots, e.g. Dependabot.
+    pub(crate) exclude_bots: bool,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
-pub(crate) struct GithubSettings {}
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CommitKeyword {
+    /// Matched case insensitively, e.g. "fixes".
+    pub(crate) keyword: String,
+    /// The status the referenced task is moved to, e.g. "Done".
+    pub(crate) status: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SlackSettings {
+    pub(crate) team_name: String,
+}
 
 type ConfigRow = (String, String, String, Json<Settings>);
 
@@ -58,6 +97,31 @@ impl ConfigStorage {
         Ok(rows_to_configs(configs))
     }
 
+    /// Lists the project's configurations for the given plugin.
+    pub(super) async fn list_for_project(
+        &self,
+        plugin_id: &str,
+        project_id: &str,
+    ) -> Result<Vec<Config>> {
+        let configs: Vec<ConfigRow> = sqlx::query_as(
+            "
+            SELECT
+                project_id,
+                plugin_id,
+                external_id,
+                settings
+            FROM plugin_configs
+            WHERE plugin_id=$1 AND project_id=$2",
+        )
+        .bind(plugin_id)
+        .bind(project_id)
+        .fetch_all(self.pool)
+        .await
+        .with_context(|| format!("Failed to list plugin configs for {plugin_id}:{project_id}"))?;
+
+        Ok(rows_to_configs(configs))
+    }
+
     /// Lists all configurations for the given plugin.
     pub(super) async fn list_for_plugin(&self, plugin_id: &str) -> Result<Vec<Config>> {
         let configs: Vec<ConfigRow> = sqlx::query_as(
@@ -95,6 +159,47 @@ impl ConfigStorage {
         .await?;
         Ok(())
     }
+
+    /// Deletes the project's configuration for the given plugin and external id.
+    /// Returns the number of configurations deleted.
+    pub(super) async fn delete(
+        &self,
+        plugin_id: &str,
+        project_id: &str,
+        external_id: &str,
+    ) -> Result<u64> {
+        let res = sqlx::query(
+            "
+            DELETE FROM plugin_configs
+            WHERE plugin_id=$1 AND project_id=$2 AND external_id=$3",
+        )
+        .bind(plugin_id)
+        .bind(project_id)
+        .bind(external_id)
+        .execute(self.pool)
+        .await
+        .with_context(|| {
+            format!("Failed to delete plugin config for {plugin_id}:{project_id}:{external_id}")
+        })?;
+        Ok(res.rows_affected())
+    }
+
+    /// Deletes all configurations for the given plugin and external id.
+    pub(super) async fn delete_for_external_id(
+        &self,
+        plugin_id: &str,
+        external_id: &str,
+    ) -> Result<()> {
+        sqlx::query("DELETE FROM plugin_configs WHERE plugin_id=$1 AND external_id=$2")
+            .bind(plugin_id)
+            .bind(external_id)
+            .execute(self.pool)
+            .await
+            .with_context(|| {
+                format!("Failed to delete plugin configs for {plugin_id}:{external_id}")
+            })?;
+        Ok(())
+    }
 }
 
 fn rows_to_configs(configs: Vec<ConfigRow>) -> Vec<Config> {
@@ -123,7 +228,8 @@ mod tests {
     }
 
     #[test_log::test(sqlx::test)]
-    async fn config_test(pool: PgPool) -> Result<()> {
+    async fn config_test(pool: sqlx::PgPool) -> Result<()> {
+        let pool = PgPool::from(pool);
         let pool = Box::leak(Box::new(pool.clone()));
         let storage = ConfigStorage { pool };
 
@@ -137,7 +243,7 @@ mod tests {
                 project_id: "project_id_1".to_string(),
                 plugin_id: "plugin_id_1".to_string(),
                 external_id: "external_id_1".to_string(),
-                settings: Settings::Github(GithubSettings {}),
+                settings: Settings::Github(GithubSettings::default()),
             })
             .await?;
 
@@ -145,7 +251,7 @@ mod tests {
             project_id: "project_id_1".to_string(),
             plugin_id: "plugin_id_1".to_string(),
             external_id: "external_id_1".to_string(),
-            settings: Settings::Github(GithubSettings {}),
+            settings: Settings::Github(GithubSettings::default()),
         }];
 
         let actual: Vec<Config> = storage.list_for_plugin("plugin_id_1").await.unwrap();
@@ -167,7 +273,8 @@ mod tests {
     }
 
     #[test_log::test(sqlx::test)]
-    async fn list_excludes_deleted_projects(pool: PgPool) -> Result<()> {
+    async fn list_excludes_deleted_projects(pool: sqlx::PgPool) -> Result<()> {
+        let pool = PgPool::from(pool);
         let pool = Box::leak(Box::new(pool.clone()));
         let storage = ConfigStorage { pool };
 
@@ -182,7 +289,7 @@ mod tests {
                 project_id: "project_id_1".to_string(),
                 plugin_id: "plugin_id_1".to_string(),
                 external_id: "external_id_1".to_string(),
-                settings: Settings::Github(GithubSettings {}),
+                settings: Settings::Github(GithubSettings::default()),
             })
             .await?;
 
@@ -197,4 +304,47 @@ mod tests {
 
         Ok(())
     }
+
+    #[test_log::test(sqlx::test)]
+    async fn delete_removes_only_the_mapping(pool: sqlx::PgPool) -> Result<()> {
+        let pool = PgPool::from(pool);
+        let pool = Box::leak(Box::new(pool.clone()));
+        let storage = ConfigStorage { pool };
+
+        for project_id in ["project_id_1", "project_id_2"] {
+            sqlx::query("INSERT INTO projects (project_id, name) VALUES ($1, $2)")
+                .bind(project_id)
+                .bind("config_test")
+                .execute(&*pool)
+                .await?;
+            storage
+                .insert_or_update(&Config {
+                    project_id: project_id.to_string(),
+                    plugin_id: "plugin_id_1".to_string(),
+                    external_id: "external_id_1".to_string(),
+                    settings: Settings::Github(GithubSettings::default()),
+                })
+                .await?;
+        }
+
+        let deleted = storage
+            .delete("plugin_id_1", "project_id_1", "external_id_1")
+            .await?;
+        assert_eq!(deleted, 1);
+
+        let actual: Vec<Config> = storage
+            .list_for_external_id("plugin_id_1", "external_id_1")
+            .await?;
+        assert_eq!(
+            actual,
+            vec![Config {
+                project_id: "project_id_2".to_string(),
+                plugin_id: "plugin_id_1".to_string(),
+                external_id: "external_id_1".to_string(),
+                settings: Settings::Github(GithubSettings::default()),
+            }]
+        );
+
+        Ok(())
+    }
 }
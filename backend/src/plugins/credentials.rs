@@ -0,0 +1,360 @@
+//! OAuth credentials plugins act with, e.g. users' GitHub tokens and Slack
+//! bot tokens. They're stored centrally so that they're refreshed before
+//! they expire, and so that failing ones reach their owners and admins
+//! rather than syncs silently stopping.
+
+use crate::{api::reports::escape_html, notifiers::Notifiers, postgres::PgPool};
+use anyhow::{Context as _, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
+
+const MONITOR_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// Access tokens are refreshed once they expire within this.
+const REFRESH_MARGIN: TimeDelta = TimeDelta::hours(1);
+/// Owners are asked to reconnect once they'd have to within this.
+const RECONNECT_WARNING: TimeDelta = TimeDelta::days(7);
+const MAX_ERROR_LEN: usize = 500;
+
+#[derive(Debug, Clone, FromRow)]
+pub(crate) struct Credential {
+    pub(crate) access_token: String,
+    pub(crate) refresh_token: Option<String>,
+    pub(crate) expires_at: Option<DateTime<Utc>>,
+    /// When the refresh token expires, after which the owner must reconnect.
+    pub(crate) refresh_expires_at: Option<DateTime<Utc>>,
+}
+
+impl Credential {
+    /// A token that doesn't expire, e.g. a Slack bot token.
+    pub(crate) fn long_lived(access_token: String) -> Credential {
+        Credential {
+            access_token,
+            refresh_token: None,
+            expires_at: None,
+            refresh_expires_at: None,
+        }
+    }
+
+    fn needs_refresh(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at - Utc::now() < REFRESH_MARGIN)
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+
+    fn refreshable_token(&self) -> Option<&str> {
+        match self.refresh_expires_at {
+            Some(refresh_expires_at) if refresh_expires_at <= Utc::now() => None,
+            _ => self.refresh_token.as_deref(),
+        }
+    }
+}
+
+/// Exchanges a plugin's refresh tokens for new credentials.
+#[async_trait]
+pub(crate) trait Refresher: Send + Sync {
+    fn plugin_id(&self) -> &'static str;
+
+    async fn refresh(&self, refresh_token: &str) -> Result<Credential>;
+}
+
+#[derive(Clone)]
+pub(super) struct CredentialStore {
+    pool: &'static PgPool,
+}
+
+impl CredentialStore {
+    pub(super) fn new(pool: &'static PgPool) -> CredentialStore {
+        CredentialStore { pool }
+    }
+
+    /// Stores a newly connected credential, clearing any past failures.
+    pub(super) async fn save(
+        &self,
+        plugin_id: &str,
+        account: &str,
+        owner: &str,
+        credential: &Credential,
+    ) -> Result<()> {
+        sqlx::query(
+            "
+            INSERT INTO plugin_credentials (plugin_id, account, owner, access_token, refresh_token, expires_at, refresh_expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (plugin_id, account)
+            DO UPDATE SET
+                owner = EXCLUDED.owner,
+                access_token = EXCLUDED.access_token,
+                refresh_token = EXCLUDED.refresh_token,
+                expires_at = EXCLUDED.expires_at,
+                refresh_expires_at = EXCLUDED.refresh_expires_at,
+                last_error = NULL,
+                failure_time = NULL,
+                notify_time = NULL,
+                update_time = NOW()",
+        )
+        .bind(plugin_id)
+        .bind(account)
+        .bind(owner)
+        .bind(&credential.access_token)
+        .bind(&credential.refresh_token)
+        .bind(credential.expires_at)
+        .bind(credential.refresh_expires_at)
+        .execute(self.pool)
+        .await
+        .with_context(|| format!("Failed to save {plugin_id} credential"))?;
+        Ok(())
+    }
+
+    /// Returns a usable access token for the account, first refreshing it if
+    /// it's about to expire. None if there's no credential, or it expired
+    /// and can't be refreshed.
+    pub(super) async fn access_token(
+        &self,
+        plugin_id: &str,
+        account: &str,
+        refresher: Option<&dyn Refresher>,
+    ) -> Result<Option<String>> {
+        let credential: Option<Credential> = sqlx::query_as(
+            "
+            SELECT access_token, refresh_token, expires_at, refresh_expires_at
+            FROM plugin_credentials
+            WHERE plugin_id = $1 AND account = $2",
+        )
+        .bind(plugin_id)
+        .bind(account)
+        .fetch_optional(self.pool)
+        .await
+        .with_context(|| format!("Failed to fetch {plugin_id} credential"))?;
+        let Some(credential) = credential else {
+            return Ok(None);
+        };
+        if !credential.needs_refresh() {
+            return Ok(Some(credential.access_token));
+        }
+        if let (Some(refresher), Some(refresh_token)) = (refresher, credential.refreshable_token())
+        {
+            return match refresher.refresh(refresh_token).await {
+                Ok(refreshed) => {
+                    self.update(plugin_id, account, &refreshed).await?;
+                    Ok(Some(refreshed.access_token))
+                }
+                Err(e) => {
+                    self.record_failure(plugin_id, account, &format!("Refresh failed: {e:#}"))
+                        .await?;
+                    Err(e)
+                }
+            };
+        }
+        if credential.is_expired() {
+            self.record_failure(plugin_id, account, "Expired").await?;
+            return Ok(None);
+        }
+        Ok(Some(credential.access_token))
+    }
+
+    async fn update(&self, plugin_id: &str, account: &str, credential: &Credential) -> Result<()> {
+        sqlx::query(
+            "
+            UPDATE plugin_credentials
+            SET
+                access_token = $3,
+                refresh_token = $4,
+                expires_at = $5,
+                refresh_expires_at = $6,
+                last_error = NULL,
+                failure_time = NULL,
+                notify_time = NULL,
+                update_time = NOW()
+            WHERE plugin_id = $1 AND account = $2",
+        )
+        .bind(plugin_id)
+        .bind(account)
+        .bind(&credential.access_token)
+        .bind(&credential.refresh_token)
+        .bind(credential.expires_at)
+        .bind(credential.refresh_expires_at)
+        .execute(self.pool)
+        .await
+        .with_context(|| format!("Failed to update {plugin_id} credential"))?;
+        Ok(())
+    }
+
+    /// Marks the credential as failing, e.g. when it was revoked, so its
+    /// owner is asked to reconnect. Reconnecting clears it.
+    pub(super) async fn record_failure(
+        &self,
+        plugin_id: &str,
+        account: &str,
+        error: &str,
+    ) -> Result<()> {
+        tracing::warn!("{plugin_id} credential of {account} is failing: {error}");
+        sqlx::query(
+            "
+            UPDATE plugin_credentials
+            SET last_error = $3, failure_time = COALESCE(failure_time, NOW())
+            WHERE plugin_id = $1 AND account = $2",
+        )
+        .bind(plugin_id)
+        .bind(account)
+        .bind(error.chars().take(MAX_ERROR_LEN).collect::<String>())
+        .execute(self.pool)
+        .await
+        .with_context(|| format!("Failed to record {plugin_id} credential failure"))?;
+        Ok(())
+    }
+}
+
+/// A credential that's failing or soon needs reconnecting, for admins.
+#[derive(Serialize, Debug, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CredentialHealth {
+    pub(crate) plugin_id: String,
+    pub(crate) account: String,
+    pub(crate) owner: String,
+    pub(crate) last_error: Option<String>,
+    pub(crate) failure_time: Option<DateTime<Utc>>,
+    /// When the owner must reconnect by, if the credential can't be refreshed past it.
+    pub(crate) reconnect_by: Option<DateTime<Utc>>,
+    /// When the owner was asked to reconnect.
+    pub(crate) notify_time: Option<DateTime<Utc>>,
+}
+
+/// When a credential will stop working unless reconnected: when its refresh
+/// token expires or, without one, when the access token does.
+const RECONNECT_BY: &str =
+    "CASE WHEN refresh_token IS NULL THEN expires_at ELSE refresh_expires_at END";
+
+/// Lists credentials that are failing or must be reconnected soon, most
+/// urgent first.
+pub(crate) async fn list_unhealthy(pool: &PgPool) -> Result<Vec<CredentialHealth>> {
+    sqlx::query_as(&format!(
+        "
+        SELECT plugin_id, account, owner, last_error, failure_time, {RECONNECT_BY} AS reconnect_by, notify_time
+        FROM plugin_credentials
+        WHERE failure_time IS NOT NULL OR {RECONNECT_BY} < $1
+        ORDER BY failure_time NULLS LAST, reconnect_by"
+    ))
+    .bind(Utc::now() + RECONNECT_WARNING)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list unhealthy credentials")
+}
+
+/// Refreshes credentials ahead of their expiry and asks owners to reconnect
+/// those that are failing or can't be refreshed for much longer.
+pub(crate) struct CredentialMonitor {
+    pool: &'static PgPool,
+    store: CredentialStore,
+    notifier: Notifiers,
+    refreshers: HashMap<&'static str, Arc<dyn Refresher>>,
+}
+
+impl CredentialMonitor {
+    pub(crate) fn new(pool: &'static PgPool) -> Result<Self> {
+        Ok(CredentialMonitor {
+            pool,
+            store: CredentialStore::new(pool),
+            notifier: Notifiers::new(pool)?,
+            refreshers: HashMap::new(),
+        })
+    }
+
+    pub(crate) fn refresher(mut self, refresher: Arc<dyn Refresher>) -> Self {
+        self.refreshers.insert(refresher.plugin_id(), refresher);
+        self
+    }
+
+    pub(crate) fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(MONITOR_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.refresh_expiring().await {
+                    tracing::warn!("Failed to refresh credentials: {e:?}");
+                }
+                if let Err(e) = self.notify_owners().await {
+                    tracing::warn!("Failed to notify credential owners: {e:?}");
+                }
+            }
+        })
+    }
+
+    async fn refresh_expiring(&self) -> Result<()> {
+        let expiring: Vec<(String, String)> = sqlx::query_as(
+            "
+            SELECT plugin_id, account
+            FROM plugin_credentials
+            WHERE expires_at < $1
+              AND refresh_token IS NOT NULL
+              AND (refresh_expires_at IS NULL OR refresh_expires_at > NOW())
+              AND failure_time IS NULL",
+        )
+        .bind(Utc::now() + REFRESH_MARGIN)
+        .fetch_all(self.pool)
+        .await
+        .context("Failed to list expiring credentials")?;
+        for (plugin_id, account) in expiring {
+            let Some(refresher) = self.refreshers.get(plugin_id.as_str()) else {
+                continue;
+            };
+            if let Err(e) = self
+                .store
+                .access_token(&plugin_id, &account, Some(refresher.as_ref()))
+                .await
+            {
+                tracing::warn!("Failed to refresh {plugin_id} credential of {account}: {e:?}");
+            }
+        }
+        Ok(())
+    }
+
+    async fn notify_owners(&self) -> Result<()> {
+        for credential in list_unhealthy(self.pool).await? {
+            if credential.notify_time.is_some() {
+                continue;
+            }
+            let reason = match (&credential.last_error, credential.reconnect_by) {
+                (Some(_), _) => "stopped working".to_string(),
+                (None, Some(reconnect_by)) => {
+                    format!("expires on {}", reconnect_by.format("%Y-%m-%d"))
+                }
+                (None, None) => continue,
+            };
+            let name = escape_html(plugin_name(&credential.plugin_id));
+            let msg = format!(
+                "🔌 Your {name} connection {reason}. <a href=\"https://koso.app/profile\">Reconnect {name}</a> to keep it syncing."
+            );
+            if let Err(e) = self.notifier.notify(&credential.owner, &msg).await {
+                tracing::warn!("Failed to ask {} to reconnect: {e:?}", credential.owner);
+                continue;
+            }
+            sqlx::query(
+                "
+                UPDATE plugin_credentials
+                SET notify_time = NOW()
+                WHERE plugin_id = $1 AND account = $2",
+            )
+            .bind(&credential.plugin_id)
+            .bind(&credential.account)
+            .execute(self.pool)
+            .await
+            .context("Failed to record reconnect notification")?;
+        }
+        Ok(())
+    }
+}
+
+fn plugin_name(plugin_id: &str) -> &str {
+    match plugin_id {
+        "github" => "GitHub",
+        "slack" => "Slack",
+        plugin_id => plugin_id,
+    }
+}
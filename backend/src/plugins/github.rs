@@ -5,26 +5,37 @@ use crate::{
         model::Task,
         yproxy::{YDocProxy, YTaskProxy},
     },
-    plugins::{PluginSettings, config::ConfigStorage, github::app::AppGithub},
+    plugins::{
+        PluginSettings,
+        config::ConfigStorage,
+        credentials::Refresher,
+        github::app::AppGithub,
+        identities::{IdentityStore, Provider},
+        status::StatusStorage,
+    },
+    postgres::PgPool,
 };
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Result, anyhow};
 use auth::Auth;
 use axum::{Router, middleware};
 use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
 use connect::ConnectHandler;
+use installations::InstallationsHandler;
 use octocrab::models::pulls::PullRequest;
 use poller::Poller;
 use regex::Regex;
-use sqlx::PgPool;
-use std::{cell::LazyCell, collections::HashSet, time::SystemTime};
+use std::{cell::LazyCell, collections::HashSet, sync::Arc, time::SystemTime};
 use tokio::task::JoinHandle;
 use webhook::Webhook;
-use yrs::TransactionMut;
+use yrs::{ReadTxn, TransactionMut};
 
 mod app;
 mod auth;
+mod commits;
 mod connect;
+mod installations;
 mod poller;
+mod scope;
 mod webhook;
 
 const PLUGIN_KIND: &Kind = &Kind::new("github", "GitHub");
@@ -34,6 +45,7 @@ const PR_KIND: &Kind = &Kind::new_nested(PLUGIN_KIND, "github_pr", "GitHub PR");
 pub(crate) struct Plugin {
     collab: Collab,
     config_storage: ConfigStorage,
+    status: StatusStorage,
     client: AppGithub,
     pool: &'static PgPool,
     settings: PluginSettings,
@@ -49,10 +61,13 @@ impl Plugin {
         PR_KIND.validate()?;
         let client: AppGithub = AppGithub::new().await?;
         let config_storage = ConfigStorage::new(pool)?;
+        let status = StatusStorage::new(pool);
+        status.clear_pending(PLUGIN_KIND.id).await?;
         Ok(Plugin {
             collab,
             client,
             config_storage,
+            status,
             pool,
             settings,
         })
@@ -64,37 +79,64 @@ impl Plugin {
         if !self.settings.disable_polling {
             tokio::spawn(self.poller().poll())
         } else {
-            tokio::spawn(async { tracing::debug!("Plugin polling disabled") })
+            let status = self.status.clone();
+            tokio::spawn(async move {
+                tracing::debug!("Plugin polling disabled");
+                if let Err(e) = status.schedule_poll(PLUGIN_KIND.id, None).await {
+                    tracing::warn!("Failed to clear poll schedule: {e:?}");
+                }
+            })
         }
     }
 
     /// Returns a router that binds webhook (push) and poll endpoints.
     pub(crate) fn router(&self) -> Result<Router> {
-        let auth = Auth::new()?;
+        let auth = Auth::new(self.pool)?;
+        let connect = ConnectHandler::new(
+            auth.clone(),
+            self.pool,
+            self.config_storage.clone(),
+            self.poller().clone(),
+        )?;
         Ok(Router::new()
             .merge(auth.clone().router())
+            .merge(connect.clone().router())
             .merge(
-                ConnectHandler::new(
-                    auth.clone(),
-                    self.pool,
+                InstallationsHandler::new(
+                    connect,
+                    self.client.clone(),
                     self.config_storage.clone(),
-                    self.poller().clone(),
-                )?
+                    self.poller(),
+                    self.pool,
+                )
                 .router(),
             )
             .layer((middleware::from_fn(google::authenticate),))
             // Webhook and poller are unauthenticated, so add it AFTER adding the authentication layers.
             .merge(
-                Webhook::new(self.collab.clone(), self.config_storage.clone(), self.pool)?.router(),
+                Webhook::new(
+                    self.collab.clone(),
+                    self.config_storage.clone(),
+                    self.status.clone(),
+                    self.pool,
+                )?
+                .router(),
             )
             .merge(self.poller().router()))
     }
 
+    /// Refreshes users' GitHub tokens before they expire.
+    pub(crate) fn credential_refresher(&self) -> Result<Arc<dyn Refresher>> {
+        Ok(Arc::new(Auth::new(self.pool)?))
+    }
+
     fn poller(&self) -> Poller {
         poller::Poller::new(
             self.collab.clone(),
             self.client.clone(),
             self.config_storage.clone(),
+            self.status.clone(),
+            self.pool,
         )
     }
 }
@@ -148,8 +190,18 @@ struct ExternalTask {
     name: String,
     description: String,
     user_id: Option<String>,
+    /// The author's GitHub email until resolved to a Koso user's by resolve_author.
     koso_user_email: Option<String>,
     status: String,
+    /// The name of the PR's head branch, e.g. koso-12-fix-login.
+    branch: String,
+    /// The PR's repository, lowercased, e.g. kosolabs/koso.
+    repo: String,
+    /// The PR's labels, lowercased.
+    labels: Vec<String>,
+    draft: bool,
+    /// Whether the PR was opened by a bot, e.g. Dependabot.
+    bot: bool,
 }
 
 impl ExternalTask {
@@ -161,7 +213,17 @@ impl ExternalTask {
         }
         let description = pr.body.unwrap_or_default();
         let user_id = pr.user.as_ref().map(|u| u.id.to_string());
+        let bot = pr.user.as_ref().is_some_and(|u| u.r#type == "Bot");
         let koso_user_email = pr.user.and_then(|u| u.email);
+        let branch = pr.head.ref_field.clone();
+        let repo = repo_from_url(&url);
+        let labels = pr
+            .labels
+            .unwrap_or_default()
+            .into_iter()
+            .map(|label| label.name.to_lowercase())
+            .collect();
+        let draft = pr.draft.unwrap_or(false);
         let status = match pr.state {
             Some(octocrab::models::IssueState::Open) => "In Progress".to_string(),
             Some(octocrab::models::IssueState::Closed) => "Done".to_string(),
@@ -176,8 +238,29 @@ impl ExternalTask {
             user_id,
             koso_user_email,
             status,
+            branch,
+            repo,
+            labels,
+            draft,
+            bot,
         })
     }
+
+    /// The text searched for references to Koso tasks.
+    fn searchable_text(&self) -> [&str; 3] {
+        [&self.description, &self.name, &self.branch]
+    }
+}
+
+/// Returns the owner/name of the repository of a PR's URL, e.g. kosolabs/koso
+/// for https://github.com/kosolabs/koso/pull/121.
+fn repo_from_url(url: &str) -> String {
+    url.trim_start_matches("https://github.com/")
+        .split('/')
+        .take(2)
+        .collect::<Vec<_>>()
+        .join("/")
+        .to_lowercase()
 }
 
 fn new_task(external_task: &ExternalTask, num: u64, kind: &Kind) -> Result<Task> {
@@ -231,16 +314,37 @@ fn resolve_task(txn: &mut TransactionMut, task: &YTaskProxy) -> Result<()> {
 }
 
 /// Adds the given task ID as a child of any tasks referenced in the github task.
+/// Returns the URLs of the kind's tasks whose management users paused,
+/// wherever in the doc they were moved. Syncs leave these tasks alone rather
+/// than updating them or creating duplicates.
+fn paused_task_urls<T: ReadTxn>(txn: &T, doc: &YDocProxy, kind: &Kind) -> Result<HashSet<String>> {
+    let mut urls = HashSet::new();
+    for task in doc.tasks(txn)? {
+        if task.get_kind(txn)?.is_some_and(|k| k == kind.id) && task.get_management_paused(txn)? {
+            if let Some(url) = task.get_url(txn)? {
+                urls.insert(url);
+            }
+        }
+    }
+    Ok(urls)
+}
+
+/// `task_key_prefix` is the project's prefix, if any, e.g. KOSO for KOSO-123.
 fn add_referenced_task_links(
     txn: &mut TransactionMut,
     doc: &YDocProxy,
     task_id: &str,
     github_task: &ExternalTask,
+    task_key_prefix: Option<&str>,
 ) -> Result<()> {
-    for link_task in doc.get_by_nums(txn, &find_referenced_task_nums(github_task))? {
+    for link_task in doc.get_by_nums(
+        txn,
+        &find_referenced_task_nums(github_task, task_key_prefix),
+    )? {
         // Disallow linking to managed links this, additionally, prevents circular links
-        // because the given task is itself always managed.
-        if link_task.is_managed(txn)? {
+        // because the given task is itself always managed. Paused tasks are still
+        // skipped so that unpausing them can't leave a cycle behind.
+        if link_task.is_managed_kind(txn)? {
             continue;
         }
 
@@ -253,15 +357,36 @@ thread_local! {
     static RE: LazyCell<Regex> = LazyCell::new(|| Regex::new(r"(?i)(?-u:\b)koso[#_-](\d+)").unwrap());
 }
 
-/// Searches the external task's name and description for references to Koso Tasks
-/// of the form: koso#<num>, koso_<num> or koso-<num>
-fn find_referenced_task_nums(github_task: &ExternalTask) -> HashSet<String> {
-    RE.with(|re| {
-        re.captures_iter(&github_task.description)
-            .chain(re.captures_iter(&github_task.name))
+/// Searches the external task's name, description and branch for references to Koso Tasks
+/// of the form: koso#<num>, koso_<num> or koso-<num>, and, when the project has
+/// a task key prefix, <prefix>-<num> or <prefix>_<num>. Matching is case insensitive
+/// so branch names like kos-12-fix-login resolve.
+fn find_referenced_task_nums(
+    github_task: &ExternalTask,
+    task_key_prefix: Option<&str>,
+) -> HashSet<String> {
+    let mut nums: HashSet<String> = RE.with(|re| {
+        github_task
+            .searchable_text()
+            .into_iter()
+            .flat_map(|text| re.captures_iter(text))
             .map(|g| g[1].to_owned())
             .collect()
-    })
+    });
+    if let Some(prefix) = task_key_prefix {
+        let re = Regex::new(&format!(r"(?i)(?-u:\b){}[_-](\d+)", regex::escape(prefix)));
+        match re {
+            Ok(re) => nums.extend(
+                github_task
+                    .searchable_text()
+                    .into_iter()
+                    .flat_map(|text| re.captures_iter(text))
+                    .map(|g| g[1].to_owned()),
+            ),
+            Err(e) => tracing::warn!("Invalid task key prefix {prefix}: {e:?}"),
+        }
+    }
+    nums
 }
 
 fn now() -> Result<i64> {
@@ -271,15 +396,23 @@ fn now() -> Result<i64> {
         .try_into()?)
 }
 
-async fn lookup_by_github_user_id(github_user_id: &str, pool: &PgPool) -> Result<Option<String>> {
+/// Translates the PR's author to the Koso user with their GitHub identity, if any.
+async fn resolve_author(task: &mut ExternalTask, pool: &'static PgPool) -> Result<()> {
     // TODO: Cache and batch these lookups.
-    let email: Option<(String,)> =
-        sqlx::query_as("SELECT email FROM users WHERE github_user_id=$1;")
-            .bind(github_user_id)
-            .fetch_optional(pool)
-            .await
-            .context("Failed to query user by github user id")?;
-    Ok(email.map(|e| e.0))
+    task.koso_user_email = match &task.user_id {
+        Some(user_id) => {
+            IdentityStore::new(pool)
+                .resolve(
+                    Provider::Github,
+                    user_id,
+                    None,
+                    task.koso_user_email.as_deref(),
+                )
+                .await?
+        }
+        None => None,
+    };
+    Ok(())
 }
 
 struct Kind<'a> {
@@ -357,14 +490,22 @@ mod tests {
     #[test_log::test]
     fn find_referenced_task_nums_matches_name() {
         assert_eq!(
-            find_referenced_task_nums(&ExternalTask {
-                url: "https://github.com/kosolabs/koso/pull/121".into(),
-                name: "koso-15: Something else".into(),
-                description: "Something something".into(),
-                user_id: Some("123".to_string()),
-                koso_user_email: Some("foo@example.com".to_string()),
-                status: "In Progress".to_string(),
-            }),
+            find_referenced_task_nums(
+                &ExternalTask {
+                    url: "https://github.com/kosolabs/koso/pull/121".into(),
+                    name: "koso-15: Something else".into(),
+                    description: "Something something".into(),
+                    user_id: Some("123".to_string()),
+                    koso_user_email: Some("foo@example.com".to_string()),
+                    status: "In Progress".to_string(),
+                    branch: "main".to_string(),
+                    repo: "kosolabs/koso".to_string(),
+                    labels: vec![],
+                    draft: false,
+                    bot: false,
+                },
+                None
+            ),
             HashSet::from_iter(vec!["15".to_string()].into_iter())
         );
     }
@@ -372,14 +513,22 @@ mod tests {
     #[test_log::test]
     fn find_referenced_task_nums_matches_description() {
         assert_eq!(
-            find_referenced_task_nums(&ExternalTask {
-                url: "https://github.com/kosolabs/koso/pull/121".into(),
-                name: "Something else".into(),
-                description: "Something something koso#17, koso#19".into(),
-                user_id: Some("123".to_string()),
-                koso_user_email: Some("foo@example.com".to_string()),
-                status: "In Progress".to_string(),
-            }),
+            find_referenced_task_nums(
+                &ExternalTask {
+                    url: "https://github.com/kosolabs/koso/pull/121".into(),
+                    name: "Something else".into(),
+                    description: "Something something koso#17, koso#19".into(),
+                    user_id: Some("123".to_string()),
+                    koso_user_email: Some("foo@example.com".to_string()),
+                    status: "In Progress".to_string(),
+                    branch: "main".to_string(),
+                    repo: "kosolabs/koso".to_string(),
+                    labels: vec![],
+                    draft: false,
+                    bot: false,
+                },
+                None
+            ),
             HashSet::from_iter(vec!["17".to_string(), "19".to_string()].into_iter())
         );
     }
@@ -387,17 +536,58 @@ mod tests {
     #[test_log::test]
     fn find_referenced_task_nums_matches_description_and_name() {
         assert_eq!(
-            find_referenced_task_nums(&ExternalTask {
-                url: "https://github.com/kosolabs/koso/pull/121".into(),
-                name: "Something else KoSo_18".into(),
-                description: "Somethingkoso#14 something KOSO-17, koso#19".into(),
-                user_id: Some("123".to_string()),
-                koso_user_email: Some("foo@example.com".to_string()),
-                status: "In Progress".to_string(),
-            }),
+            find_referenced_task_nums(
+                &ExternalTask {
+                    url: "https://github.com/kosolabs/koso/pull/121".into(),
+                    name: "Something else KoSo_18".into(),
+                    description: "Somethingkoso#14 something KOSO-17, koso#19".into(),
+                    user_id: Some("123".to_string()),
+                    koso_user_email: Some("foo@example.com".to_string()),
+                    status: "In Progress".to_string(),
+                    branch: "main".to_string(),
+                    repo: "kosolabs/koso".to_string(),
+                    labels: vec![],
+                    draft: false,
+                    bot: false,
+                },
+                None
+            ),
             HashSet::from_iter(
                 vec!["17".to_string(), "18".to_string(), "19".to_string()].into_iter()
             )
         );
     }
+
+    #[test_log::test]
+    fn find_referenced_task_nums_matches_task_key_prefix() {
+        let task = ExternalTask {
+            url: "https://github.com/kosolabs/koso/pull/121".into(),
+            name: "web-7 Fix login".into(),
+            description: "Fixes WEB-12 and koso#19, not WEBX-13 or web#14".into(),
+            user_id: Some("123".to_string()),
+            koso_user_email: Some("foo@example.com".to_string()),
+            status: "In Progress".to_string(),
+            branch: "web-21-fix-login".to_string(),
+            repo: "kosolabs/koso".to_string(),
+            labels: vec![],
+            draft: false,
+            bot: false,
+        };
+        assert_eq!(
+            find_referenced_task_nums(&task, Some("WEB")),
+            HashSet::from_iter(
+                vec![
+                    "7".to_string(),
+                    "12".to_string(),
+                    "19".to_string(),
+                    "21".to_string()
+                ]
+                .into_iter()
+            )
+        );
+        assert_eq!(
+            find_referenced_task_nums(&task, None),
+            HashSet::from_iter(vec!["19".to_string()].into_iter())
+        );
+    }
 }
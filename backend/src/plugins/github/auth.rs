@@ -1,18 +1,19 @@
 use crate::{
     api::{ApiResult, bad_request_error, google::User},
+    plugins::{
+        credentials::{Credential, CredentialStore, Refresher},
+        github::PLUGIN_KIND,
+    },
+    postgres::PgPool,
     secrets::{Secret, read_secret},
     settings::settings,
 };
 use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
 use axum::{Extension, Json, Router, routing::post};
+use chrono::{TimeDelta, Utc};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::{
-    collections::HashMap,
-    sync::Arc,
-    time::{Duration, Instant},
-};
-use tokio::sync::Mutex;
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -34,15 +35,25 @@ enum GithubOAuthResponse {
 }
 
 #[derive(Deserialize, Clone)]
-pub(super) struct OAuth {
-    pub(super) access_token: String,
+struct OAuth {
+    access_token: String,
     // token_type: String,
     // scope: String,
     expires_in: Option<u64>,
-    // refresh_token: Option<String>,
-    // refresh_token_expires_in: Option<u64>,
-    #[serde(skip_deserializing, default = "Instant::now")]
-    created_at: Instant,
+    refresh_token: Option<String>,
+    refresh_token_expires_in: Option<u64>,
+}
+
+impl OAuth {
+    fn credential(self) -> Credential {
+        let expiry = |secs: u64| Utc::now() + TimeDelta::seconds(secs as i64);
+        Credential {
+            access_token: self.access_token,
+            refresh_token: self.refresh_token,
+            expires_at: self.expires_in.map(expiry),
+            refresh_expires_at: self.refresh_token_expires_in.map(expiry),
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -56,19 +67,19 @@ pub(super) struct Auth {
     client_id: String,
     client_secret: ClientSecret,
     client: Client,
-    tokens: Arc<Mutex<HashMap<String, OAuth>>>,
+    credentials: CredentialStore,
 }
 
 /// Contains the Github app's client secret.
 type ClientSecret = Secret<String>;
 
 impl Auth {
-    pub(super) fn new() -> Result<Auth> {
+    pub(super) fn new(pool: &'static PgPool) -> Result<Auth> {
         Ok(Auth {
             client_id: settings().plugins.github.client_id.clone(),
             client_secret: read_secret("github/client_secret")?,
             client: Client::default(),
-            tokens: Arc::new(Mutex::new(HashMap::new())),
+            credentials: CredentialStore::new(pool),
         })
     }
 
@@ -87,23 +98,34 @@ impl Auth {
         if request.code.is_empty() {
             return Err(bad_request_error("EMPTY_CODE", "Code is blank"));
         }
-        let oauth = auth.generate_access_token(&request.code).await?;
+        let oauth = auth
+            .request_access_token(&[("code", &request.code)])
+            .await?;
         let expires_in = oauth.expires_in.unwrap_or(60 * 60 * 4);
-        auth.tokens.lock().await.insert(user.email, oauth);
+        auth.credentials
+            .save(
+                PLUGIN_KIND.id,
+                &user.email,
+                &user.email,
+                &oauth.credential(),
+            )
+            .await?;
         Ok(Json(AuthResult { expires_in }))
     }
 
-    async fn generate_access_token(&self, code: &String) -> ApiResult<OAuth> {
+    /// Exchanges a code or refresh token for an access token.
+    /// See https://docs.github.com/en/apps/creating-github-apps/authenticating-with-a-github-app/refreshing-user-access-tokens
+    async fn request_access_token(&self, grant: &[(&str, &str)]) -> ApiResult<OAuth> {
         let res = self
             .client
             .post("https://github.com/login/oauth/access_token")
             .header("ACCEPT", "application/json")
             .header("Content-Type", "application/json")
             .query(&[
-                ("client_id", &self.client_id),
-                ("client_secret", &self.client_secret.data),
-                ("code", code),
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.data.as_str()),
             ])
+            .query(grant)
             .send()
             .await?;
         let status = res.status();
@@ -127,26 +149,36 @@ impl Auth {
         Ok(oauth)
     }
 
-    pub(super) async fn user_access_token(&self, user: &User) -> ApiResult<OAuth> {
-        let token = {
-            let tokens = self.tokens.lock().await;
-            tokens.get(&user.email).cloned()
-        };
+    /// Returns the user's access token, refreshed if it's about to expire.
+    pub(super) async fn user_access_token(&self, user: &User) -> ApiResult<String> {
+        let token = self
+            .credentials
+            .access_token(PLUGIN_KIND.id, &user.email, Some(self))
+            .await?;
         let Some(token) = token else {
             return Err(bad_request_error(
                 "GITHUB_UNAUTHENTICATED",
                 "User is not authenticated with Github.",
             ));
         };
-
-        if token.expires_in.is_some_and(|expires_in| {
-            token.created_at.elapsed() > Duration::from_secs(expires_in - 60 * 60)
-        }) {
-            return Err(bad_request_error(
-                "GITHUB_UNAUTHENTICATED",
-                "User's Github authentication expired.",
-            ));
-        }
         Ok(token)
     }
 }
+
+#[async_trait]
+impl Refresher for Auth {
+    fn plugin_id(&self) -> &'static str {
+        PLUGIN_KIND.id
+    }
+
+    async fn refresh(&self, refresh_token: &str) -> Result<Credential> {
+        let oauth = self
+            .request_access_token(&[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token),
+            ])
+            .await
+            .map_err(|e| anyhow!("GitHub refused to refresh token: {}", e.message()))?;
+        Ok(oauth.credential())
+    }
+}
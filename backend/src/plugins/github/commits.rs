@@ -0,0 +1,199 @@
+use crate::{
+    api::{
+        ApiResult, bad_request_error,
+        model::{WorkflowCategory, WorkflowState},
+        yproxy::{YDocProxy, status_category, status_for_category},
+    },
+    plugins::{
+        config::{CommitKeyword, Settings},
+        github::now,
+    },
+};
+use anyhow::Result;
+use regex::Regex;
+use std::collections::HashSet;
+use yrs::TransactionMut;
+
+/// Maximum number of commit keywords a project may configure.
+const MAX_COMMIT_KEYWORDS: usize = 20;
+
+/// The status default keywords move tasks to. In projects with custom
+/// workflow states, the first done state is used instead.
+const DEFAULT_STATUS: &str = "Done";
+
+/// Used when a project hasn't configured its own keywords. Mirrors the keywords
+/// GitHub uses to close issues.
+fn default_commit_keywords() -> Vec<CommitKeyword> {
+    [
+        "close", "closes", "closed", "fix", "fixes", "fixed", "resolve", "resolves", "resolved",
+    ]
+    .into_iter()
+    .map(|keyword| CommitKeyword {
+        keyword: keyword.to_string(),
+        status: DEFAULT_STATUS.to_string(),
+    })
+    .collect()
+}
+
+pub(super) fn commit_keywords(settings: &Settings) -> Vec<CommitKeyword> {
+    match settings {
+        Settings::Github(settings) => settings
+            .commit_keywords
+            .clone()
+            .unwrap_or_else(default_commit_keywords),
+        _ => default_commit_keywords(),
+    }
+}
+
+/// Searches the commit message for a keyword followed by a task reference,
+/// e.g. "fixes KOSO-12" or "Closes: koso#3", and returns the referenced
+/// task nums with the status each should move to, in order of appearance.
+///
+/// Bare references like "fixes #12" are ignored as they refer to GitHub issues.
+pub(super) fn find_keyword_moves(
+    message: &str,
+    keywords: &[CommitKeyword],
+    task_key_prefix: Option<&str>,
+) -> Result<Vec<(String, String)>> {
+    if keywords.is_empty() {
+        return Ok(Vec::new());
+    }
+    let alternatives = keywords
+        .iter()
+        .map(|k| regex::escape(&k.keyword))
+        .collect::<Vec<_>>()
+        .join("|");
+    let reference = match task_key_prefix {
+        Some(prefix) => format!(r"(?:koso[#_-]|{}[_-])", regex::escape(prefix)),
+        None => r"koso[#_-]".to_string(),
+    };
+    let re = Regex::new(&format!(
+        r"(?i)(?-u:\b)(?<keyword>{alternatives}):?\s+{reference}(?<num>\d+)"
+    ))?;
+    Ok(re
+        .captures_iter(message)
+        .filter_map(|c| {
+            let keyword = &c["keyword"];
+            keywords
+                .iter()
+                .find(|k| k.keyword.eq_ignore_ascii_case(keyword))
+                .map(|k| (c["num"].to_string(), k.status.clone()))
+        })
+        .collect())
+}
+
+/// Moves the referenced tasks to their new status. Tasks managed by a plugin,
+/// or moved to a status the project doesn't have, are skipped.
+/// Returns the number of tasks moved.
+pub(super) fn apply_keyword_moves(
+    txn: &mut TransactionMut,
+    doc: &YDocProxy,
+    moves: &[(String, String)],
+) -> Result<usize> {
+    let workflow_states = doc.config().get_workflow_states(txn)?;
+    let mut moved = 0;
+    for (num, status) in moves {
+        let Some(status) = resolve_status(&workflow_states, status) else {
+            tracing::warn!("Skipping move of task {num} to unknown status {status}");
+            continue;
+        };
+        for task in doc.get_by_nums(txn, &HashSet::from([num.clone()]))? {
+            if task.is_managed(txn)? || task.get_status(txn)?.is_some_and(|s| s == status) {
+                continue;
+            }
+            task.set_status(txn, Some(&status));
+            task.set_status_time(txn, Some(now()?));
+            moved += 1;
+        }
+    }
+    Ok(moved)
+}
+
+fn resolve_status(workflow_states: &[WorkflowState], status: &str) -> Option<String> {
+    if status_category(workflow_states, status).is_some() {
+        return Some(status.to_string());
+    }
+    if status == DEFAULT_STATUS {
+        return status_for_category(workflow_states, WorkflowCategory::Done);
+    }
+    None
+}
+
+/// Trims and lowercases the keywords, rejecting malformed ones.
+pub(super) fn normalize_commit_keywords(
+    mut commit_keywords: Vec<CommitKeyword>,
+) -> ApiResult<Vec<CommitKeyword>> {
+    if commit_keywords.len() > MAX_COMMIT_KEYWORDS {
+        return Err(bad_request_error(
+            "INVALID_COMMIT_KEYWORDS",
+            &format!("At most {MAX_COMMIT_KEYWORDS} commit keywords are allowed"),
+        ));
+    }
+    for commit_keyword in commit_keywords.iter_mut() {
+        commit_keyword.keyword = commit_keyword.keyword.trim().to_lowercase();
+        commit_keyword.status = commit_keyword.status.trim().to_string();
+        if commit_keyword.keyword.is_empty()
+            || !commit_keyword
+                .keyword
+                .chars()
+                .all(|c| c.is_alphabetic() || c == ' ')
+            || commit_keyword.status.is_empty()
+        {
+            return Err(bad_request_error(
+                "INVALID_COMMIT_KEYWORDS",
+                &format!(
+                    "Invalid commit keyword: {} -> {}",
+                    commit_keyword.keyword, commit_keyword.status
+                ),
+            ));
+        }
+    }
+    Ok(commit_keywords)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn find_keyword_moves_matches_keywords_and_prefixes() {
+        let keywords = default_commit_keywords();
+        assert_eq!(
+            find_keyword_moves(
+                "Fixes KOSO-12, closes: koso#3 and refs koso-4\n\nResolved web-7; fixes #9",
+                &keywords,
+                Some("WEB")
+            )
+            .unwrap(),
+            vec![
+                ("12".to_string(), "Done".to_string()),
+                ("3".to_string(), "Done".to_string()),
+                ("7".to_string(), "Done".to_string()),
+            ]
+        );
+        assert_eq!(
+            find_keyword_moves("resolved web-7", &keywords, None).unwrap(),
+            vec![]
+        );
+        assert_eq!(
+            find_keyword_moves("prefixes koso-1", &keywords, None).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test_log::test]
+    fn find_keyword_moves_uses_configured_statuses() {
+        let keywords = vec![CommitKeyword {
+            keyword: "starts".to_string(),
+            status: "In Progress".to_string(),
+        }];
+        assert_eq!(
+            find_keyword_moves("Starts koso-5, fixes koso-6", &keywords, None).unwrap(),
+            vec![("5".to_string(), "In Progress".to_string())]
+        );
+        assert_eq!(
+            find_keyword_moves("fixes koso-6", &[], None).unwrap(),
+            vec![]
+        );
+    }
+}
@@ -1,19 +1,24 @@
 use crate::{
-    api::{self, ApiResult, google::User, not_found_error, unauthorized_error},
+    api::{self, ApiResult, context, google::User, not_found_error, unauthorized_error},
     plugins::{
-        config::{Config, ConfigStorage, GithubSettings, Settings},
-        github::{self, Poller, auth::Auth},
+        config::{CommitKeyword, Config, ConfigStorage, GithubSettings, Settings, SyncScope},
+        github::{
+            self, Poller,
+            auth::Auth,
+            commits::normalize_commit_keywords,
+            scope::{normalize_scope, scope_includes},
+        },
     },
+    postgres::PgPool,
     settings::settings,
 };
 use anyhow::Result;
 use axum::{
     Extension, Json, Router,
-    routing::{delete, get, post},
+    routing::{delete, get, post, put},
 };
 use octocrab::{Octocrab, OctocrabBuilder, models::Installation};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
 use tracing::Instrument;
 
 #[derive(Deserialize, Debug)]
@@ -35,6 +40,46 @@ struct ConnectUserRequest {}
 #[serde(rename_all = "camelCase")]
 struct ConnectUserResponse {}
 
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UpdateSettingsRequest {
+    project_id: String,
+    /// None restores the default keywords. An empty list disables them.
+    commit_keywords: Option<Vec<CommitKeyword>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UpdateScopeRequest {
+    project_id: String,
+    /// None syncs all of the installation's PRs.
+    scope: Option<SyncScope>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PreviewScopeRequest {
+    project_id: String,
+    scope: SyncScope,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ScopePreview {
+    /// Open PRs that would be imported under the scope.
+    included: Vec<PreviewTask>,
+    /// Open PRs the scope leaves out.
+    excluded: Vec<PreviewTask>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PreviewTask {
+    url: String,
+    name: String,
+    repo: String,
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 struct InitResponse {
@@ -75,6 +120,9 @@ impl ConnectHandler {
         Router::new()
             .route("/connect", post(Self::connect_project_handler))
             .route("/init", get(Self::init_handler))
+            .route("/settings", put(Self::update_settings_handler))
+            .route("/scope", put(Self::update_scope_handler))
+            .route("/scope/preview", post(Self::preview_scope_handler))
             .route("/userConnections", post(Self::connect_user_handler))
             .route(
                 "/userConnections",
@@ -120,18 +168,132 @@ impl ConnectHandler {
             project_id: request.project_id,
             plugin_id: github::PLUGIN_KIND.id.to_string(),
             external_id: request.installation_id,
-            settings: Settings::Github(GithubSettings {}),
+            settings: Settings::Github(GithubSettings::default()),
         };
         self.storage.insert_or_update(&config).await?;
 
         // Trigger an initial poll in the background.
         let poller = self.poller.clone();
-        tokio::spawn(async move { poller.poll_installation(config).await }.in_current_span());
+        context::spawn(async move { poller.poll_installation(config).await }.in_current_span());
 
         Ok(Json(ConnectResponse {}))
     }
 
-    async fn verify_installation_access(
+    #[tracing::instrument(skip(user, handler))]
+    async fn update_settings_handler(
+        Extension(user): Extension<User>,
+        Extension(handler): Extension<ConnectHandler>,
+        Json(request): Json<UpdateSettingsRequest>,
+    ) -> ApiResult<Json<GithubSettings>> {
+        api::verify_project_admin(handler.pool, &user, &request.project_id).await?;
+
+        let commit_keywords = request
+            .commit_keywords
+            .map(normalize_commit_keywords)
+            .transpose()?;
+        let settings = handler
+            .update_github_settings(&request.project_id, |settings| {
+                settings.commit_keywords = commit_keywords.clone()
+            })
+            .await?;
+        Ok(Json(settings))
+    }
+
+    #[tracing::instrument(skip(user, handler))]
+    async fn update_scope_handler(
+        Extension(user): Extension<User>,
+        Extension(handler): Extension<ConnectHandler>,
+        Json(request): Json<UpdateScopeRequest>,
+    ) -> ApiResult<Json<GithubSettings>> {
+        api::verify_project_admin(handler.pool, &user, &request.project_id).await?;
+
+        let scope = request.scope.map(normalize_scope).transpose()?;
+        let settings = handler
+            .update_github_settings(&request.project_id, |settings| {
+                settings.scope = scope.clone()
+            })
+            .await?;
+        Ok(Json(settings))
+    }
+
+    /// Applies the update to the GitHub settings of each of the project's
+    /// installations, leaving the rest of them, e.g. mapped repos, as they are.
+    /// Returns the settings of the first installation.
+    async fn update_github_settings(
+        &self,
+        project_id: &str,
+        update: impl Fn(&mut GithubSettings),
+    ) -> ApiResult<GithubSettings> {
+        let configs = self
+            .storage
+            .list_for_project(github::PLUGIN_KIND.id, project_id)
+            .await?;
+        let mut updated = None;
+        for mut config in configs {
+            let mut settings = match config.settings {
+                Settings::Github(settings) => settings,
+                _ => GithubSettings::default(),
+            };
+            update(&mut settings);
+            config.settings = Settings::Github(settings.clone());
+            self.storage.insert_or_update(&config).await?;
+            updated.get_or_insert(settings);
+        }
+        updated.ok_or_else(|| not_found_error("NOT_FOUND", "Project isn't connected to GitHub"))
+    }
+
+    /// Lists the open PRs that would, and wouldn't, be imported into the
+    /// project under the candidate scope, without changing anything.
+    #[tracing::instrument(skip(user, handler))]
+    async fn preview_scope_handler(
+        Extension(user): Extension<User>,
+        Extension(handler): Extension<ConnectHandler>,
+        Json(request): Json<PreviewScopeRequest>,
+    ) -> ApiResult<Json<ScopePreview>> {
+        api::verify_project_access(handler.pool, &user, &request.project_id).await?;
+
+        let scope = normalize_scope(request.scope)?;
+        let configs = handler
+            .storage
+            .list_for_project(github::PLUGIN_KIND.id, &request.project_id)
+            .await?;
+        if configs.is_empty() {
+            return Err(not_found_error(
+                "NOT_FOUND",
+                "Project isn't connected to GitHub",
+            ));
+        }
+
+        let mut preview = ScopePreview {
+            included: Vec::new(),
+            excluded: Vec::new(),
+        };
+        for config in configs {
+            for task in handler
+                .poller
+                .fetch_tasks_from_github(&config)
+                .await?
+                .into_values()
+            {
+                let included = scope_includes(&scope, &task);
+                let task = PreviewTask {
+                    url: task.url,
+                    name: task.name,
+                    repo: task.repo,
+                };
+                if included {
+                    preview.included.push(task);
+                } else {
+                    preview.excluded.push(task);
+                }
+            }
+        }
+        preview.included.sort_by(|a, b| a.url.cmp(&b.url));
+        preview.excluded.sort_by(|a, b| a.url.cmp(&b.url));
+        Ok(Json(preview))
+    }
+
+    pub(super) async fn verify_installation_access(
         &self,
         user: &User,
         installation_id: &str,
@@ -148,9 +310,9 @@ impl ConnectHandler {
         Ok(())
     }
 
-    async fn fetch_installations(&self, user: &User) -> ApiResult<Vec<Installation>> {
+    pub(super) async fn fetch_installations(&self, user: &User) -> ApiResult<Vec<Installation>> {
         let token = self.auth.user_access_token(user).await?;
-        let crab = self.crab.user_access_token(token.access_token.as_str())?;
+        let crab = self.crab.user_access_token(token.as_str())?;
 
         let installations = crab
             .current()
@@ -186,7 +348,7 @@ impl ConnectHandler {
 
     async fn fetch_user(&self, user: &User) -> ApiResult<octocrab::models::Author> {
         let token = self.auth.user_access_token(user).await?;
-        let crab = self.crab.user_access_token(token.access_token.as_str())?;
+        let crab = self.crab.user_access_token(token.as_str())?;
         Ok(crab.current().user().await?)
     }
 
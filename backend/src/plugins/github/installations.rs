@@ -0,0 +1,297 @@
+//! Management of the GitHub App's installations, e.g. on a whole org, and of
+//! which of their repos each project syncs.
+
+use crate::{
+    api::{self, ApiResult, bad_request_error, context, google::User, not_found_error},
+    plugins::{
+        config::{Config, ConfigStorage, GithubSettings, Settings, SyncScope},
+        github::{
+            PLUGIN_KIND, Poller,
+            app::{AppGithub, InstallationRef},
+            commits::normalize_commit_keywords,
+            connect::ConnectHandler,
+            scope::normalize_scope,
+        },
+    },
+    postgres::PgPool,
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    routing::{get, put},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use tracing::Instrument;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InstallationResponse {
+    installation_id: String,
+    /// The org or user the app is installed on, e.g. kosolabs.
+    account: String,
+    /// Organization or User.
+    account_type: String,
+    /// Mappings to the projects the user can access.
+    mappings: Vec<Mapping>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Mapping {
+    project_id: String,
+    /// The mapped repos. Empty when the project syncs all of them.
+    repos: Vec<String>,
+    settings: GithubSettings,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InstallationRepo {
+    /// e.g. kosolabs/koso
+    full_name: String,
+    /// Projects, of those the user can access, the repo is mapped to.
+    project_ids: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct MappingRequest {
+    /// The repos to sync, e.g. kosolabs/koso. Empty syncs all of the
+    /// installation's repos.
+    repos: Vec<String>,
+    /// The rest of the mapping's settings. The scope's repos are replaced by
+    /// `repos`.
+    #[serde(default)]
+    settings: GithubSettings,
+}
+
+#[derive(Clone)]
+pub(super) struct InstallationsHandler {
+    connect: ConnectHandler,
+    client: AppGithub,
+    storage: ConfigStorage,
+    poller: Poller,
+    pool: &'static PgPool,
+}
+
+impl InstallationsHandler {
+    pub(super) fn new(
+        connect: ConnectHandler,
+        client: AppGithub,
+        storage: ConfigStorage,
+        poller: Poller,
+        pool: &'static PgPool,
+    ) -> InstallationsHandler {
+        InstallationsHandler {
+            connect,
+            client,
+            storage,
+            poller,
+            pool,
+        }
+    }
+
+    pub(super) fn router(self) -> Router {
+        Router::new()
+            .route("/installations", get(Self::list_installations_handler))
+            .route(
+                "/installations/{installation_id}/repos",
+                get(Self::list_repos_handler),
+            )
+            .route(
+                "/installations/{installation_id}/mappings/{project_id}",
+                put(Self::map_handler).delete(Self::unmap_handler),
+            )
+            .layer((Extension(self),))
+    }
+
+    /// Lists the installations the user can access, with their mappings.
+    #[tracing::instrument(skip(user, handler))]
+    async fn list_installations_handler(
+        Extension(user): Extension<User>,
+        Extension(handler): Extension<InstallationsHandler>,
+    ) -> ApiResult<Json<Vec<InstallationResponse>>> {
+        let installations = handler.connect.fetch_installations(&user).await?;
+        let mut mappings = handler.list_mappings(&user).await?;
+        Ok(Json(
+            installations
+                .into_iter()
+                .map(|installation| {
+                    let installation_id = installation.id.0.to_string();
+                    InstallationResponse {
+                        mappings: mappings.remove(&installation_id).unwrap_or_default(),
+                        installation_id,
+                        account: installation.account.login,
+                        account_type: installation.account.r#type,
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    /// Lists the installation's repos and the projects each is mapped to.
+    #[tracing::instrument(skip(user, handler))]
+    async fn list_repos_handler(
+        Extension(user): Extension<User>,
+        Extension(handler): Extension<InstallationsHandler>,
+        Path(installation_id): Path<String>,
+    ) -> ApiResult<Json<Vec<InstallationRepo>>> {
+        handler
+            .connect
+            .verify_installation_access(&user, &installation_id)
+            .await?;
+        let mappings = handler
+            .list_mappings(&user)
+            .await?
+            .remove(&installation_id)
+            .unwrap_or_default();
+        let repos = handler
+            .fetch_repos(&installation_id)
+            .await?
+            .into_iter()
+            .map(|full_name| InstallationRepo {
+                project_ids: mappings
+                    .iter()
+                    .filter(|m| m.repos.is_empty() || m.repos.contains(&full_name))
+                    .map(|m| m.project_id.clone())
+                    .collect(),
+                full_name,
+            })
+            .collect();
+        Ok(Json(repos))
+    }
+
+    /// Maps the installation's repos to the project, replacing any existing
+    /// mapping between them.
+    #[tracing::instrument(skip(user, handler))]
+    async fn map_handler(
+        Extension(user): Extension<User>,
+        Extension(handler): Extension<InstallationsHandler>,
+        Path((installation_id, project_id)): Path<(String, String)>,
+        Json(request): Json<MappingRequest>,
+    ) -> ApiResult<Json<Mapping>> {
+        api::verify_project_admin(handler.pool, &user, &project_id).await?;
+        handler
+            .connect
+            .verify_installation_access(&user, &installation_id)
+            .await?;
+
+        let mut settings = request.settings;
+        settings.commit_keywords = settings
+            .commit_keywords
+            .map(normalize_commit_keywords)
+            .transpose()?;
+        let scope = normalize_scope(SyncScope {
+            repos: request.repos,
+            ..settings.scope.unwrap_or_default()
+        })?;
+        let installed_repos = handler.fetch_repos(&installation_id).await?;
+        if let Some(repo) = scope.repos.iter().find(|r| !installed_repos.contains(r)) {
+            return Err(bad_request_error(
+                "UNKNOWN_REPO",
+                &format!("Installation {installation_id} doesn't have access to {repo}"),
+            ));
+        }
+        let repos = scope.repos.clone();
+        settings.scope = (scope != SyncScope::default()).then_some(scope);
+
+        tracing::info!("Mapping installation {installation_id} repos {repos:?} to {project_id}");
+        let config = Config {
+            project_id: project_id.clone(),
+            plugin_id: PLUGIN_KIND.id.to_string(),
+            external_id: installation_id,
+            settings: Settings::Github(settings.clone()),
+        };
+        handler.storage.insert_or_update(&config).await?;
+
+        // Sync the newly mapped repos in the background.
+        let poller = handler.poller.clone();
+        context::spawn(async move { poller.poll_installation(config).await }.in_current_span());
+
+        Ok(Json(Mapping {
+            project_id,
+            repos,
+            settings,
+        }))
+    }
+
+    /// Unmaps the installation from the project. Tasks already synced are
+    /// left in place.
+    #[tracing::instrument(skip(user, handler))]
+    async fn unmap_handler(
+        Extension(user): Extension<User>,
+        Extension(handler): Extension<InstallationsHandler>,
+        Path((installation_id, project_id)): Path<(String, String)>,
+    ) -> ApiResult<Json<()>> {
+        api::verify_project_admin(handler.pool, &user, &project_id).await?;
+
+        let deleted = handler
+            .storage
+            .delete(PLUGIN_KIND.id, &project_id, &installation_id)
+            .await?;
+        if deleted == 0 {
+            return Err(not_found_error(
+                "NOT_FOUND",
+                &format!("Installation {installation_id} isn't mapped to {project_id}"),
+            ));
+        }
+        tracing::info!("Unmapped installation {installation_id} from {project_id}");
+        Ok(Json(()))
+    }
+
+    /// Returns the mappings of the projects the user can access, by
+    /// installation id.
+    async fn list_mappings(&self, user: &User) -> Result<HashMap<String, Vec<Mapping>>> {
+        let project_ids: HashSet<String> =
+            sqlx::query_scalar("SELECT project_id FROM project_permissions WHERE email = $1")
+                .bind(&user.email)
+                .fetch_all(self.pool)
+                .await
+                .context("Failed to list user's projects")?
+                .into_iter()
+                .collect();
+
+        let mut mappings: HashMap<String, Vec<Mapping>> = HashMap::new();
+        for config in self.storage.list_for_plugin(PLUGIN_KIND.id).await? {
+            if !project_ids.contains(&config.project_id) {
+                continue;
+            }
+            let settings = match config.settings {
+                Settings::Github(settings) => settings,
+                _ => GithubSettings::default(),
+            };
+            mappings
+                .entry(config.external_id)
+                .or_default()
+                .push(Mapping {
+                    project_id: config.project_id,
+                    repos: settings
+                        .scope
+                        .as_ref()
+                        .map(|scope| scope.repos.clone())
+                        .unwrap_or_default(),
+                    settings,
+                });
+        }
+        Ok(mappings)
+    }
+
+    /// Returns the full names, lowercased, of the installation's repos.
+    async fn fetch_repos(&self, installation_id: &str) -> Result<Vec<String>> {
+        let client = self
+            .client
+            .installation_github(InstallationRef::InstallationId {
+                id: installation_id.parse::<u64>()?,
+            })
+            .await?;
+        Ok(client
+            .fetch_install_repos()
+            .await?
+            .into_iter()
+            .filter_map(|repo| repo.full_name)
+            .map(|full_name| full_name.to_lowercase())
+            .collect())
+    }
+}
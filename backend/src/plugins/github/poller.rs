@@ -6,6 +6,7 @@ use crate::{
             projects_state::DocBox,
             txn_origin::{Actor, YOrigin},
         },
+        projects::fetch_task_key_prefix,
         yproxy::{YDocProxy, YTaskProxy},
     },
     plugins::{
@@ -13,15 +14,20 @@ use crate::{
         github::{
             ExternalTask, Kind, PLUGIN_KIND, PR_KIND, add_referenced_task_links,
             app::{AppGithub, InstallationRef},
-            get_or_create_kind_parent, new_task, resolve_task, update_task,
+            get_or_create_kind_parent, new_task, paused_task_urls, resolve_author, resolve_task,
+            scope::in_scope,
+            update_task,
         },
+        status::StatusStorage,
     },
+    postgres::PgPool,
     settings::settings,
 };
 use anyhow::Result;
 use axum::{Extension, Router, routing::post};
+use chrono::{TimeDelta, Utc};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     time::{Duration, Instant},
 };
 use yrs::{Origin, ReadTxn};
@@ -34,14 +40,24 @@ pub(super) struct Poller {
     collab: Collab,
     client: AppGithub,
     config_storage: ConfigStorage,
+    status: StatusStorage,
+    pool: &'static PgPool,
 }
 
 impl Poller {
-    pub(super) fn new(collab: Collab, client: AppGithub, config_storage: ConfigStorage) -> Poller {
+    pub(super) fn new(
+        collab: Collab,
+        client: AppGithub,
+        config_storage: ConfigStorage,
+        status: StatusStorage,
+        pool: &'static PgPool,
+    ) -> Poller {
         Poller {
             collab,
             client,
             config_storage,
+            status,
+            pool,
         }
     }
 
@@ -65,15 +81,32 @@ impl Poller {
     pub(super) async fn poll(self) {
         // Wait awhile before starting polling to avoid
         // competing with client reconnections after a server restart.
+        self.schedule_poll(INIT_POLL_DELAY).await;
         tokio::time::sleep(INIT_POLL_DELAY).await;
         loop {
             if let Err(e) = self.poll_all_installations().await {
                 tracing::warn!("Failed poll: {e:?}");
             }
+            self.schedule_poll(POLL_DELAY).await;
             tokio::time::sleep(POLL_DELAY).await;
         }
     }
 
+    /// Records when the next poll is due, shown in each config's sync status.
+    async fn schedule_poll(&self, delay: Duration) {
+        let scheduled = match TimeDelta::from_std(delay) {
+            Ok(delay) => {
+                self.status
+                    .schedule_poll(PLUGIN_KIND.id, Some(Utc::now() + delay))
+                    .await
+            }
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = scheduled {
+            tracing::warn!("Failed to record poll schedule: {e:?}");
+        }
+    }
+
     async fn poll_all_installations(&self) -> Result<()> {
         // TODO: Multiple configs can refer to the same installation. If
         // needed, we could optimize the retrieval from GitHub by
@@ -113,18 +146,28 @@ impl Poller {
         fields(gh_installation_id=config.external_id, project_id=config.project_id)
     )]
     pub(super) async fn poll_installation(&self, config: Config) -> Result<()> {
-        if let Err(e) = self.poll_installation_internal(config).await {
+        let result = self.poll_installation_internal(&config).await;
+        if let Err(e) = &result {
             tracing::warn!("Failed installation poll: {e:?}");
-            return Err(e);
         }
-        Ok(())
+        self.status.record(&config, &result).await;
+        result
     }
 
-    async fn poll_installation_internal(&self, config: Config) -> Result<()> {
+    async fn poll_installation_internal(&self, config: &Config) -> Result<()> {
         tracing::debug!("Polling installation");
 
-        let github_tasks_by_url = self.fetch_tasks_from_github(&config).await?;
+        let (mut github_tasks_by_url, out_of_scope): (HashMap<_, _>, HashMap<_, _>) = self
+            .fetch_tasks_from_github(config)
+            .await?
+            .into_iter()
+            .partition(|(_, task)| in_scope(&config.settings, task));
         tracing::trace!("Fetched Github tasks: {:?}", github_tasks_by_url.values());
+        let out_of_scope_urls = out_of_scope.into_keys().collect();
+        for task in github_tasks_by_url.values_mut() {
+            resolve_author(task, self.pool).await?;
+        }
+        let task_key_prefix = fetch_task_key_prefix(self.pool, &config.project_id).await?;
 
         let client = self
             .collab
@@ -136,8 +179,10 @@ impl Poller {
                 let doc_box = client.project.doc_box.lock().await;
                 self.merge_tasks(
                 &github_tasks_by_url,
-                &config,
+                &out_of_scope_urls,
+                config,
                 &DocBox::doc_or_error(doc_box.as_ref())?.ydoc,
+                task_key_prefix.as_deref(),
             )?};
 
         tracing::debug!(
@@ -149,7 +194,7 @@ impl Poller {
         Ok(())
     }
 
-    async fn fetch_tasks_from_github(
+    pub(super) async fn fetch_tasks_from_github(
         &self,
         config: &Config,
     ) -> Result<HashMap<String, ExternalTask>> {
@@ -200,12 +245,16 @@ impl Poller {
         Ok(results)
     }
 
+    /// Out of scope PRs are left as they are rather than resolved, as they're
+    /// still open.
     // Note: This function should remain synchronous to avoid blocking the doc_box lock.
     fn merge_tasks(
         &self,
         github_tasks_by_url: &HashMap<String, ExternalTask>,
+        out_of_scope_urls: &HashSet<String>,
         config: &Config,
         doc: &YDocProxy,
+        task_key_prefix: Option<&str>,
     ) -> Result<usize> {
         let mut txn = doc.transact_mut_with(origin(config)?);
 
@@ -219,14 +268,25 @@ impl Poller {
                 .collect::<Result<Vec<_>>>()
         );
 
+        let paused_urls = paused_task_urls(&txn, doc, PR_KIND)?;
+
         // Resolve or update tasks that already exist in the doc.
         for (url, task) in doc_tasks_by_url.iter() {
+            if paused_urls.contains(url) || out_of_scope_urls.contains(url) {
+                continue;
+            }
             match github_tasks_by_url.get(url) {
                 Some(github_task) => {
                     update_task(&mut txn, task, github_task)?;
 
                     let task_id = task.get_id(&txn)?;
-                    add_referenced_task_links(&mut txn, doc, &task_id, github_task)?;
+                    add_referenced_task_links(
+                        &mut txn,
+                        doc,
+                        &task_id,
+                        github_task,
+                        task_key_prefix,
+                    )?;
                 }
                 None => {
                     // Note: we didn't fetch the closed PR so we can't call add_referenced_task_links
@@ -242,6 +302,9 @@ impl Poller {
         let mut next_num: u64 = doc.next_num(&txn)?;
         let mut children = parent.get_children(&txn)?;
         for github_task in github_tasks_by_url.values() {
+            if paused_urls.contains(&github_task.url) {
+                continue;
+            }
             match doc_tasks_by_url.get(&github_task.url) {
                 Some(_) => {}
                 None => {
@@ -251,7 +314,13 @@ impl Poller {
                     doc.set(&mut txn, &task);
                     children.push(task.id.clone());
 
-                    add_referenced_task_links(&mut txn, doc, &task.id, github_task)?;
+                    add_referenced_task_links(
+                        &mut txn,
+                        doc,
+                        &task.id,
+                        github_task,
+                        task_key_prefix,
+                    )?;
                 }
             }
         }
@@ -266,6 +335,7 @@ fn origin(config: &Config) -> Result<Origin> {
         who: "github_poller".to_string(),
         id: format!("install_{}", config.external_id),
         actor: Actor::GitHub,
+        ..Default::default()
     }
     .as_origin()
 }
@@ -0,0 +1,131 @@
+use crate::{
+    api::{ApiResult, bad_request_error},
+    plugins::{
+        config::{Settings, SyncScope},
+        github::ExternalTask,
+    },
+};
+
+/// Maximum number of repos, and of labels, a project's scope may list.
+pub(super) const MAX_SCOPE_ENTRIES: usize = 50;
+
+/// Whether the project's settings sync the task. Tasks are in scope when the
+/// project hasn't configured one.
+pub(super) fn in_scope(settings: &Settings, task: &ExternalTask) -> bool {
+    match settings {
+        Settings::Github(settings) => settings
+            .scope
+            .as_ref()
+            .is_none_or(|scope| scope_includes(scope, task)),
+        _ => true,
+    }
+}
+
+pub(super) fn scope_includes(scope: &SyncScope, task: &ExternalTask) -> bool {
+    (scope.repos.is_empty() || scope.repos.contains(&task.repo))
+        && (scope.labels.is_empty() || task.labels.iter().any(|l| scope.labels.contains(l)))
+        && !(scope.exclude_drafts && task.draft)
+        && !(scope.exclude_bots && task.bot)
+}
+
+/// Lowercases and dedupes the scope's entries, rejecting malformed ones.
+pub(super) fn normalize_scope(mut scope: SyncScope) -> ApiResult<SyncScope> {
+    if scope.repos.len() > MAX_SCOPE_ENTRIES || scope.labels.len() > MAX_SCOPE_ENTRIES {
+        return Err(bad_request_error(
+            "INVALID_SCOPE",
+            &format!("At most {MAX_SCOPE_ENTRIES} repos and labels are allowed"),
+        ));
+    }
+    scope.repos = normalize_entries(scope.repos);
+    scope.labels = normalize_entries(scope.labels);
+    if let Some(repo) = scope.repos.iter().find(|repo| !is_repo(repo)) {
+        return Err(bad_request_error(
+            "INVALID_SCOPE",
+            &format!("Invalid repo {repo}, expected owner/name"),
+        ));
+    }
+    Ok(scope)
+}
+
+fn normalize_entries(entries: Vec<String>) -> Vec<String> {
+    let mut entries: Vec<String> = entries
+        .into_iter()
+        .map(|entry| entry.trim().to_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .collect();
+    entries.sort();
+    entries.dedup();
+    entries
+}
+
+fn is_repo(repo: &str) -> bool {
+    let mut parts = repo.split('/');
+    matches!(
+        (parts.next(), parts.next(), parts.next()),
+        (Some(owner), Some(name), None) if !owner.is_empty() && !name.is_empty()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task() -> ExternalTask {
+        ExternalTask {
+            url: "https://github.com/kosolabs/koso/pull/1".to_string(),
+            name: "Fix login".to_string(),
+            description: String::new(),
+            user_id: None,
+            koso_user_email: None,
+            status: "In Progress".to_string(),
+            branch: "fix-login".to_string(),
+            repo: "kosolabs/koso".to_string(),
+            labels: vec!["bug".to_string()],
+            draft: false,
+            bot: false,
+        }
+    }
+
+    #[test]
+    fn scope_includes_matching_tasks() {
+        let scope = normalize_scope(SyncScope {
+            repos: vec![" KosoLabs/Koso ".to_string()],
+            labels: vec!["Bug".to_string(), "feature".to_string()],
+            exclude_drafts: true,
+            exclude_bots: true,
+        })
+        .unwrap();
+        assert!(scope_includes(&scope, &task()));
+        assert!(scope_includes(&SyncScope::default(), &task()));
+
+        let other_repo = ExternalTask {
+            repo: "kosolabs/other".to_string(),
+            ..task()
+        };
+        assert!(!scope_includes(&scope, &other_repo));
+        let unlabeled = ExternalTask {
+            labels: vec![],
+            ..task()
+        };
+        assert!(!scope_includes(&scope, &unlabeled));
+        let draft = ExternalTask {
+            draft: true,
+            ..task()
+        };
+        assert!(!scope_includes(&scope, &draft));
+        let bot = ExternalTask {
+            bot: true,
+            ..task()
+        };
+        assert!(!scope_includes(&scope, &bot));
+    }
+
+    #[test]
+    fn normalize_scope_rejects_invalid_repos() {
+        let scope = SyncScope {
+            repos: vec!["koso".to_string()],
+            ..SyncScope::default()
+        };
+        assert!(normalize_scope(scope).is_err());
+    }
+}
@@ -4,8 +4,10 @@ use crate::{
         collab::{
             Collab,
             projects_state::DocBox,
-            txn_origin::{Actor, YOrigin},
+            txn_origin::{Actor, TxnMetadata, YOrigin},
         },
+        context,
+        projects::fetch_task_key_prefix,
         unauthorized_error,
         yproxy::{YDocProxy, YTaskProxy},
     },
@@ -13,10 +15,14 @@ use crate::{
         config::{Config, ConfigStorage},
         github::{
             ExternalTask, Kind, PLUGIN_KIND, PR_KIND, add_referenced_task_links,
-            get_or_create_kind_parent, lookup_by_github_user_id, new_task, resolve_task,
+            commits::{apply_keyword_moves, commit_keywords, find_keyword_moves},
+            get_or_create_kind_parent, new_task, paused_task_urls, resolve_author, resolve_task,
+            scope::in_scope,
             update_task,
         },
+        status::StatusStorage,
     },
+    postgres::PgPool,
     secrets::{Secret, read_secret},
 };
 use anyhow::{Result, anyhow};
@@ -28,10 +34,10 @@ use axum::{
 };
 use hmac::{Hmac, Mac};
 use octocrab::models::webhook_events::{
-    WebhookEvent, WebhookEventPayload, payload::PullRequestWebhookEventAction,
+    EventInstallation, WebhookEvent, WebhookEventPayload,
+    payload::{InstallationWebhookEventAction, PullRequestWebhookEventAction},
 };
 use sha2::Sha256;
-use sqlx::PgPool;
 use tower_http::request_id::RequestId;
 use tracing::Instrument as _;
 use yrs::{Origin, ReadTxn, TransactionMut};
@@ -60,6 +66,14 @@ struct KosoGithubEvent {
     task: ExternalTask,
 }
 
+/// Commits pushed to a repository's default branch.
+#[derive(Clone, Debug)]
+struct KosoPushEvent {
+    request_id: String,
+    installation_id: u64,
+    commit_messages: Vec<String>,
+}
+
 #[derive(Clone, Debug)]
 enum KosoGithubEventAction {
     Opened,
@@ -71,6 +85,7 @@ enum KosoGithubEventAction {
 pub(super) struct Webhook {
     collab: Collab,
     config_storage: ConfigStorage,
+    status: StatusStorage,
     secret: WebhookSecret,
     pool: &'static PgPool,
 }
@@ -79,11 +94,13 @@ impl Webhook {
     pub(super) fn new(
         collab: Collab,
         config_storage: ConfigStorage,
+        status: StatusStorage,
         pool: &'static PgPool,
     ) -> Result<Webhook> {
         Ok(Webhook {
             collab,
             config_storage,
+            status,
             secret: read_secret("github/webhook_secret")?,
             pool,
         })
@@ -207,23 +224,20 @@ impl Webhook {
     async fn process_webhook_event(self, event: WebhookEvent, request_id: String) -> ApiResult<()> {
         match event.specific {
             WebhookEventPayload::PullRequest(pr_event) => {
-                let installation_id: u64 = match event
-                    .installation
-                    .ok_or_else(|| anyhow!("Missing installation field."))?
-                {
-                    octocrab::models::webhook_events::EventInstallation::Full(installation) => {
-                        *installation.id
-                    }
-                    octocrab::models::webhook_events::EventInstallation::Minimal(
-                        installation_id,
-                    ) => *installation_id.id,
-                };
+                let installation_id = installation_id(event.installation)?;
                 let task = ExternalTask::new(pr_event.pull_request)?;
                 let action = match pr_event.action {
                     PullRequestWebhookEventAction::Opened
                     | PullRequestWebhookEventAction::Reopened => KosoGithubEventAction::Opened,
                     PullRequestWebhookEventAction::Closed => KosoGithubEventAction::Closed,
-                    PullRequestWebhookEventAction::Edited => KosoGithubEventAction::Edited,
+                    // Labels and drafts can move the PR in or out of a project's scope.
+                    PullRequestWebhookEventAction::Edited
+                    | PullRequestWebhookEventAction::Labeled
+                    | PullRequestWebhookEventAction::Unlabeled
+                    | PullRequestWebhookEventAction::ReadyForReview
+                    | PullRequestWebhookEventAction::ConvertedToDraft => {
+                        KosoGithubEventAction::Edited
+                    }
                     _ => {
                         tracing::trace!(
                             "Discarding unhandled PR action type: {:?}",
@@ -241,30 +255,154 @@ impl Webhook {
                     task,
                 };
 
-                tokio::spawn(
+                self.add_pending(installation_id).await;
+                context::spawn(
                     async move {
                         if let Err(e) = self.process_koso_event(event).await {
                             tracing::warn!("Failed to process koso event: {e:?}")
                         }
+                        self.remove_pending(installation_id).await;
+                    }
+                    .in_current_span(),
+                );
+            }
+            WebhookEventPayload::Push(push_event) => {
+                let installation_id = installation_id(event.installation)?;
+                // Like GitHub's own closing keywords, only act once commits land
+                // on the default branch rather than on every push to a feature branch.
+                let default_branch = event
+                    .repository
+                    .and_then(|repository| repository.default_branch);
+                let Some(default_branch) = default_branch else {
+                    tracing::trace!("Discarding push to repository without a default branch");
+                    return Ok(());
+                };
+                if push_event.r#ref != format!("refs/heads/{default_branch}") {
+                    tracing::trace!("Discarding push to {}", push_event.r#ref);
+                    return Ok(());
+                }
+                let event = KosoPushEvent {
+                    request_id,
+                    installation_id,
+                    commit_messages: push_event
+                        .commits
+                        .into_iter()
+                        .map(|commit| commit.message)
+                        .collect(),
+                };
+                if event.commit_messages.is_empty() {
+                    return Ok(());
+                }
+
+                self.add_pending(installation_id).await;
+                context::spawn(
+                    async move {
+                        if let Err(e) = self.process_push_event(event).await {
+                            tracing::warn!("Failed to process push event: {e:?}")
+                        }
+                        self.remove_pending(installation_id).await;
                     }
                     .in_current_span(),
                 );
             }
+            WebhookEventPayload::Installation(installation_event) => {
+                if !matches!(
+                    installation_event.action,
+                    InstallationWebhookEventAction::Deleted
+                ) {
+                    tracing::trace!(
+                        "Discarding installation event: {:?}",
+                        installation_event.action
+                    );
+                    return Ok(());
+                }
+                // The app was uninstalled, e.g. from an org, so unmap it from every project.
+                let installation_id = installation_id(event.installation)?;
+                tracing::info!("Unmapping deleted installation {installation_id}");
+                self.config_storage
+                    .delete_for_external_id(PLUGIN_KIND.id, &installation_id.to_string())
+                    .await?;
+            }
             _ => tracing::trace!("Discarding unhandled event."),
         };
 
         Ok(())
     }
 
+    /// Counts an event as pending in the installation's sync status until
+    /// it's applied.
+    async fn add_pending(&self, installation_id: u64) {
+        if let Err(e) = self
+            .status
+            .add_pending(PLUGIN_KIND.id, &installation_id.to_string())
+            .await
+        {
+            tracing::warn!("Failed to record pending event: {e:?}");
+        }
+    }
+
+    async fn remove_pending(&self, installation_id: u64) {
+        if let Err(e) = self
+            .status
+            .remove_pending(PLUGIN_KIND.id, &installation_id.to_string())
+            .await
+        {
+            tracing::warn!("Failed to record applied event: {e:?}");
+        }
+    }
+
+    async fn process_push_event(&self, event: KosoPushEvent) -> Result<()> {
+        tracing::debug!("Processing push event: {event:?}");
+        let configs = self
+            .config_storage
+            .list_for_external_id(PLUGIN_KIND.id, &event.installation_id.to_string())
+            .await?;
+        for config in configs {
+            let result = self.move_referenced_tasks(&event, &config).await;
+            if let Err(e) = &result {
+                tracing::warn!(
+                    "Failed to move tasks referenced by commits in {}: {e:?}",
+                    config.project_id
+                );
+            }
+            self.status.record(&config, &result).await;
+        }
+        Ok(())
+    }
+
+    /// Moves tasks referenced by commit keywords, e.g. "fixes KOSO-12".
+    async fn move_referenced_tasks(&self, event: &KosoPushEvent, config: &Config) -> Result<()> {
+        let task_key_prefix = fetch_task_key_prefix(self.pool, &config.project_id).await?;
+        let keywords = commit_keywords(&config.settings);
+        let mut moves = Vec::new();
+        for message in &event.commit_messages {
+            moves.extend(find_keyword_moves(
+                message,
+                &keywords,
+                task_key_prefix.as_deref(),
+            )?);
+        }
+        if moves.is_empty() {
+            return Ok(());
+        }
+
+        let client = self
+            .collab
+            .register_local_client(&config.project_id)
+            .await?;
+        let doc_box = client.project.doc_box.lock().await;
+        let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+        let mut txn = doc.transact_mut_with(push_origin(event)?);
+        let moved = apply_keyword_moves(&mut txn, doc, &moves)?;
+        tracing::debug!("Moved {moved} tasks referenced by commits");
+        Ok(())
+    }
+
     async fn process_koso_event(&self, mut event: KosoGithubEvent) -> Result<()> {
         tracing::debug!("Processing Koso event: {event:?}");
 
         // Populate the email of the author if we're able to.
-        if let Some(user_id) = &event.task.user_id {
-            if let Some(email) = lookup_by_github_user_id(user_id, self.pool).await? {
-                event.task.koso_user_email = Some(email);
-            }
-        }
+        resolve_author(&mut event.task, self.pool).await?;
 
         let configs = self
             .config_storage
@@ -293,27 +431,47 @@ impl Webhook {
         fields(project_id=config.project_id)
     )]
     async fn merge_task(&self, event: KosoGithubEvent, config: Config) {
-        if let Err(e) = self.merge_task_internal(event, config).await {
+        let result = self.merge_task_internal(event, &config).await;
+        if let Err(e) = &result {
             tracing::warn!("Failed to process event for config: {e:?}");
         }
+        self.status.record(&config, &result).await;
     }
 
-    async fn merge_task_internal(&self, event: KosoGithubEvent, config: Config) -> Result<()> {
+    async fn merge_task_internal(&self, event: KosoGithubEvent, config: &Config) -> Result<()> {
+        if !in_scope(&config.settings, &event.task) {
+            tracing::trace!("Discarding event for task outside the project's scope");
+            return Ok(());
+        }
         let client = self
             .collab
             .register_local_client(&config.project_id)
             .await?;
+        let task_key_prefix = fetch_task_key_prefix(self.pool, &config.project_id).await?;
 
         // Avoid any expensive, async work while holding the doc_box lock.
         {
             let doc_box = client.project.doc_box.lock().await;
-            self.apply_task_changes(&event, &DocBox::doc_or_error(doc_box.as_ref())?.ydoc)
+            self.apply_task_changes(
+                &event,
+                &DocBox::doc_or_error(doc_box.as_ref())?.ydoc,
+                task_key_prefix.as_deref(),
+            )
         }
     }
 
     // Note: This function should remain synchronous to avoid blocking the doc_box lock.
-    fn apply_task_changes(&self, event: &KosoGithubEvent, doc: &YDocProxy) -> Result<()> {
+    fn apply_task_changes(
+        &self,
+        event: &KosoGithubEvent,
+        doc: &YDocProxy,
+        task_key_prefix: Option<&str>,
+    ) -> Result<()> {
         let mut txn = doc.transact_mut_with(origin(event)?);
+        if paused_task_urls(&txn, doc, PR_KIND)?.contains(&event.task.url) {
+            tracing::trace!("Discarding event for task with paused management");
+            return Ok(());
+        }
         match (
             get_doc_task(&txn, doc, &event.task.url, PR_KIND)?,
             &event.action,
@@ -322,14 +480,14 @@ impl Webhook {
                 update_task(&mut txn, &task, &event.task)?;
 
                 let task_id = task.get_id(&txn)?;
-                add_referenced_task_links(&mut txn, doc, &task_id, &event.task)?;
+                add_referenced_task_links(&mut txn, doc, &task_id, &event.task, task_key_prefix)?;
             }
             (None, KosoGithubEventAction::Opened | KosoGithubEventAction::Edited) => {
-                create_task(&mut txn, doc, &event.task)?;
+                create_task(&mut txn, doc, &event.task, task_key_prefix)?;
             }
             (Some(task), KosoGithubEventAction::Closed) => {
                 let task_id = task.get_id(&txn)?;
-                add_referenced_task_links(&mut txn, doc, &task_id, &event.task)?;
+                add_referenced_task_links(&mut txn, doc, &task_id, &event.task, task_key_prefix)?;
 
                 resolve_task(&mut txn, &task)?;
             }
@@ -366,6 +524,7 @@ fn create_task(
     txn: &mut TransactionMut,
     doc: &YDocProxy,
     external_task: &ExternalTask,
+    task_key_prefix: Option<&str>,
 ) -> Result<()> {
     let parent = get_or_create_kind_parent(txn, doc, PR_KIND)?;
     let mut children: Vec<String> = parent.get_children(txn)?;
@@ -377,11 +536,37 @@ fn create_task(
     children.push(task.id.clone());
     parent.set_children(txn, &children);
 
-    add_referenced_task_links(txn, doc, &task.id, external_task)?;
+    add_referenced_task_links(txn, doc, &task.id, external_task, task_key_prefix)?;
 
     Ok(())
 }
 
+fn installation_id(installation: Option<EventInstallation>) -> Result<u64> {
+    Ok(
+        match installation.ok_or_else(|| anyhow!("Missing installation field."))? {
+            EventInstallation::Full(installation) => *installation.id,
+            EventInstallation::Minimal(installation_id) => *installation_id.id,
+        },
+    )
+}
+
+fn push_origin(event: &KosoPushEvent) -> Result<Origin> {
+    YOrigin {
+        who: "github_webhook".to_string(),
+        id: format!(
+            "install_{}_request_{}",
+            event.installation_id, event.request_id
+        ),
+        actor: Actor::GitHub,
+        metadata: TxnMetadata {
+            request_id: Some(event.request_id.clone()),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+    .as_origin()
+}
+
 fn origin(event: &KosoGithubEvent) -> Result<Origin> {
     YOrigin {
         who: "github_webhook".to_string(),
@@ -390,6 +575,11 @@ fn origin(event: &KosoGithubEvent) -> Result<Origin> {
             event.installation_id, event.request_id
         ),
         actor: Actor::GitHub,
+        metadata: TxnMetadata {
+            request_id: Some(event.request_id.clone()),
+            ..Default::default()
+        },
+        ..Default::default()
     }
     .as_origin()
 }
@@ -0,0 +1,244 @@
+//! Koso users' identities in external systems, e.g. GitHub users or Slack
+//! members. Plugins resolve identities here when translating assignees and
+//! reporters rather than each matching users their own way. Identities whose
+//! email matches a user's are suggested automatically and used until the
+//! user rejects them. Users' connected GitHub accounts are always confirmed.
+
+use crate::postgres::PgPool;
+use anyhow::{Context as _, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum Provider {
+    Github,
+    Gitlab,
+    Slack,
+    Jira,
+}
+
+impl Provider {
+    fn as_str(self) -> &'static str {
+        match self {
+            Provider::Github => "github",
+            Provider::Gitlab => "gitlab",
+            Provider::Slack => "slack",
+            Provider::Jira => "jira",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum IdentityStatus {
+    /// Matched by email, and used until the user rejects it.
+    Suggested,
+    Confirmed,
+    Rejected,
+}
+
+impl IdentityStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            IdentityStatus::Suggested => "suggested",
+            IdentityStatus::Confirmed => "confirmed",
+            IdentityStatus::Rejected => "rejected",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Identity {
+    pub(crate) provider: String,
+    pub(crate) external_id: String,
+    pub(crate) display_name: Option<String>,
+    pub(crate) status: String,
+    pub(crate) update_time: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub(crate) struct IdentityStore {
+    pool: &'static PgPool,
+}
+
+impl IdentityStore {
+    pub(crate) fn new(pool: &'static PgPool) -> IdentityStore {
+        IdentityStore { pool }
+    }
+
+    /// Returns the email of the user with the external identity, if any.
+    /// Identities not yet mapped are suggested to the user with the
+    /// identity's email, if there is one.
+    pub(super) async fn resolve(
+        &self,
+        provider: Provider,
+        external_id: &str,
+        display_name: Option<&str>,
+        external_email: Option<&str>,
+    ) -> Result<Option<String>> {
+        let mapped: Option<(String, String)> = sqlx::query_as(
+            "
+            SELECT email, $3
+            FROM users
+            WHERE $1 = 'github' AND github_user_id = $2
+            UNION ALL
+            (SELECT email, status
+            FROM external_identities
+            WHERE provider = $1 AND external_id = $2)
+            LIMIT 1",
+        )
+        .bind(provider.as_str())
+        .bind(external_id)
+        .bind(IdentityStatus::Confirmed.as_str())
+        .fetch_optional(self.pool)
+        .await
+        .context("Failed to query external identity")?;
+        if let Some((email, status)) = mapped {
+            return Ok((status != IdentityStatus::Rejected.as_str()).then_some(email));
+        }
+
+        let Some(external_email) = external_email else {
+            return Ok(None);
+        };
+        let suggested: Option<(String,)> = sqlx::query_as(
+            "
+            INSERT INTO external_identities (provider, external_id, email, display_name, status)
+            SELECT $1, $2, email, $3, $4
+            FROM users
+            WHERE lower(email) = lower($5)
+            LIMIT 1
+            ON CONFLICT (provider, external_id) DO NOTHING
+            RETURNING email",
+        )
+        .bind(provider.as_str())
+        .bind(external_id)
+        .bind(display_name)
+        .bind(IdentityStatus::Suggested.as_str())
+        .bind(external_email)
+        .fetch_optional(self.pool)
+        .await
+        .context("Failed to suggest external identity")?;
+        if let Some((email,)) = &suggested {
+            tracing::debug!(
+                "Suggested {} identity {external_id} for {email}",
+                provider.as_str()
+            );
+        }
+        Ok(suggested.map(|(email,)| email))
+    }
+
+    pub(crate) async fn list_for_user(&self, email: &str) -> Result<Vec<Identity>> {
+        sqlx::query_as(
+            "
+            SELECT provider, external_id, display_name, status, update_time
+            FROM external_identities
+            WHERE email = $1
+            ORDER BY provider, external_id",
+        )
+        .bind(email)
+        .fetch_all(self.pool)
+        .await
+        .context("Failed to list external identities")
+    }
+
+    /// Confirms or rejects one of the user's identities. Returns whether the
+    /// user had the identity.
+    pub(crate) async fn set_status(
+        &self,
+        provider: Provider,
+        external_id: &str,
+        email: &str,
+        status: IdentityStatus,
+    ) -> Result<bool> {
+        let res = sqlx::query(
+            "
+            UPDATE external_identities
+            SET status = $4, update_time = NOW()
+            WHERE provider = $1 AND external_id = $2 AND email = $3",
+        )
+        .bind(provider.as_str())
+        .bind(external_id)
+        .bind(email)
+        .bind(status.as_str())
+        .execute(self.pool)
+        .await
+        .context("Failed to update external identity")?;
+        Ok(res.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn insert_user(pool: &PgPool, email: &str) -> Result<()> {
+        sqlx::query("INSERT INTO users (email, name, picture) VALUES ($1, $2, $3)")
+            .bind(email)
+            .bind("Test User")
+            .bind("")
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    #[test_log::test(sqlx::test)]
+    async fn resolve_suggests_until_rejected(pool: sqlx::PgPool) -> Result<()> {
+        let pool = PgPool::from(pool);
+        let pool = Box::leak(Box::new(pool));
+        let store = IdentityStore::new(pool);
+        insert_user(pool, "a@koso.app").await?;
+
+        assert_eq!(
+            store
+                .resolve(Provider::Slack, "U1", Some("a"), Some("A@koso.app"))
+                .await?,
+            Some("a@koso.app".to_string())
+        );
+        assert_eq!(
+            store.resolve(Provider::Slack, "U1", None, None).await?,
+            Some("a@koso.app".to_string())
+        );
+        assert_eq!(
+            store
+                .resolve(Provider::Slack, "U2", None, Some("b@koso.app"))
+                .await?,
+            None
+        );
+
+        assert!(
+            store
+                .set_status(
+                    Provider::Slack,
+                    "U1",
+                    "a@koso.app",
+                    IdentityStatus::Rejected
+                )
+                .await?
+        );
+        assert_eq!(
+            store
+                .resolve(Provider::Slack, "U1", None, Some("a@koso.app"))
+                .await?,
+            None
+        );
+
+        assert!(
+            store
+                .set_status(
+                    Provider::Slack,
+                    "U1",
+                    "a@koso.app",
+                    IdentityStatus::Confirmed
+                )
+                .await?
+        );
+        assert_eq!(
+            store.resolve(Provider::Slack, "U1", None, None).await?,
+            Some("a@koso.app".to_string())
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{
+    api::{
+        collab::{
+            Collab,
+            projects_state::DocBox,
+            txn_origin::{Actor, TxnMetadata, YOrigin},
+        },
+        google,
+        model::{ProjectId, Task},
+    },
+    plugins::{
+        config::ConfigStorage,
+        credentials::CredentialStore,
+        identities::{IdentityStore, Provider},
+    },
+    postgres::{PgPool, list_project_users},
+};
+use anyhow::{Context as _, Result, anyhow};
+use axum::{Router, middleware};
+use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+use connect::ConnectHandler;
+use serde::Deserialize;
+
+mod commands;
+mod connect;
+
+const PLUGIN_ID: &str = "slack";
+const MAX_NAME_LEN: usize = 200;
+const MAX_DESC_LEN: usize = 20_000;
+
+/// Lets Slack workspaces create tasks with the `/koso` command and the
+/// "Create Koso task" message action.
+#[derive(Clone)]
+pub(crate) struct Plugin {
+    collab: Collab,
+    config_storage: ConfigStorage,
+    credentials: CredentialStore,
+    identities: IdentityStore,
+    pool: &'static PgPool,
+    client: reqwest::Client,
+}
+
+/// A task created from Slack.
+struct CreatedTask {
+    project_id: ProjectId,
+    id: String,
+    num: String,
+    name: String,
+}
+
+impl CreatedTask {
+    /// Formats a link to the task using Slack's mrkdwn.
+    fn link(&self) -> String {
+        format!(
+            "<https://koso.app/projects/{}?taskId={}|#{} {}>",
+            self.project_id,
+            self.id,
+            self.num,
+            escape_mrkdwn(&self.name)
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct UserInfoResponse {
+    ok: bool,
+    error: Option<String>,
+    user: Option<UserInfo>,
+}
+
+#[derive(Deserialize)]
+struct UserInfo {
+    name: Option<String>,
+    profile: UserProfile,
+}
+
+#[derive(Deserialize)]
+struct UserProfile {
+    email: Option<String>,
+}
+
+impl Plugin {
+    pub(crate) fn new(collab: Collab, pool: &'static PgPool) -> Result<Plugin> {
+        Ok(Plugin {
+            collab,
+            config_storage: ConfigStorage::new(pool)?,
+            credentials: CredentialStore::new(pool),
+            identities: IdentityStore::new(pool),
+            pool,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    pub(crate) fn router(&self) -> Router {
+        Router::new()
+            .merge(ConnectHandler::new(self.clone()).router())
+            .layer((middleware::from_fn(google::authenticate),))
+            // Commands and interactions are invoked by Slack, not users, and are verified
+            // using the app's signing secret. Add them AFTER the authentication layers.
+            .merge(commands::router(self.clone()))
+    }
+
+    /// Returns the project the workspace is connected to, and the workspace's bot token.
+    async fn workspace(&self, team_id: &str) -> Result<Option<(ProjectId, String)>> {
+        let Some(config) = self
+            .config_storage
+            .list_for_external_id(PLUGIN_ID, team_id)
+            .await?
+            .into_iter()
+            .next()
+        else {
+            return Ok(None);
+        };
+        let token = self
+            .credentials
+            .access_token(PLUGIN_ID, team_id, None)
+            .await?;
+        Ok(token.map(|token| (config.project_id, token)))
+    }
+
+    /// Maps a Slack user to the email of a member of the project, if they are one.
+    async fn reporter(
+        &self,
+        project_id: &ProjectId,
+        team_id: &str,
+        bot_token: &str,
+        user_id: &str,
+    ) -> Result<Option<String>> {
+        let res: UserInfoResponse = self
+            .client
+            .get("https://slack.com/api/users.info")
+            .bearer_auth(bot_token)
+            .query(&[("user", user_id)])
+            .send()
+            .await
+            .context("Failed to fetch slack user")?
+            .json()
+            .await
+            .context("Failed to decode slack user")?;
+        if !res.ok {
+            let error = res.error.unwrap_or_default();
+            // See https://api.slack.com/methods/users.info#errors
+            if matches!(
+                error.as_str(),
+                "invalid_auth" | "token_revoked" | "token_expired" | "account_inactive"
+            ) {
+                self.credentials
+                    .record_failure(PLUGIN_ID, team_id, &error)
+                    .await?;
+            }
+            return Err(anyhow!("Failed to fetch slack user {user_id}: {error}"));
+        }
+        let Some(user) = res.user else {
+            return Ok(None);
+        };
+        let Some(email) = self
+            .identities
+            .resolve(
+                Provider::Slack,
+                user_id,
+                user.name.as_deref(),
+                user.profile.email.as_deref(),
+            )
+            .await?
+        else {
+            return Ok(None);
+        };
+        Ok(list_project_users(self.pool, project_id)
+            .await?
+            .into_iter()
+            .any(|u| u.email == email)
+            .then_some(email))
+    }
+
+    /// Creates a task, under the root, in the given project.
+    async fn create_task(
+        &self,
+        project_id: &ProjectId,
+        name: &str,
+        desc: Option<&str>,
+        reporter: Option<&str>,
+        request_id: &str,
+    ) -> Result<CreatedTask> {
+        let client = self.collab.register_local_client(project_id).await?;
+        let doc_box = client.project.doc_box.lock().await;
+        let doc = &DocBox::doc_or_error(doc_box.as_ref())?.ydoc;
+        let mut txn = doc.transact_mut_with(
+            YOrigin {
+                who: "slack".to_string(),
+                id: format!("slack_request_{request_id}"),
+                actor: Actor::Server,
+                metadata: TxnMetadata {
+                    request_id: Some(request_id.to_string()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+            .as_origin()?,
+        );
+        let task = Task {
+            id: BASE64_URL_SAFE_NO_PAD.encode(uuid::Uuid::new_v4()),
+            num: doc.next_num(&txn)?.to_string(),
+            name: name.chars().take(MAX_NAME_LEN).collect(),
+            desc: desc.map(|d| d.chars().take(MAX_DESC_LEN).collect()),
+            reporter: reporter.map(String::from),
+            ..Task::default()
+        };
+        doc.set(&mut txn, &task);
+        doc.get(&txn, "root")?.push_child(&mut txn, &task.id)?;
+        Ok(CreatedTask {
+            project_id: project_id.clone(),
+            id: task.id,
+            num: task.num,
+            name: task.name,
+        })
+    }
+}
+
+/// Escapes the characters Slack requires be escaped in message text.
+/// See https://api.slack.com/reference/surfaces/formatting#escaping
+fn escape_mrkdwn(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
@@ -0,0 +1,326 @@
+use crate::{
+    api::{ApiResult, bad_request_error, context, unauthorized_error},
+    plugins::slack::Plugin,
+    secrets::{Secret, read_secret},
+};
+use anyhow::{Context as _, Result};
+use axum::{
+    Extension, Json, Router,
+    body::{Body, Bytes},
+    http::HeaderMap,
+    routing::post,
+};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::types::chrono::Utc;
+use tower_http::request_id::RequestId;
+use tracing::Instrument as _;
+
+/// Maximum size of request body in bytes.
+const BODY_LIMIT: usize = 1024 * 1024;
+/// Callback id of the "Create Koso task" message action.
+const CREATE_TASK_CALLBACK_ID: &str = "create_koso_task";
+
+pub(super) fn router(plugin: Plugin) -> Router {
+    Router::new()
+        .route("/commands", post(command_handler))
+        .route("/interactions", post(interaction_handler))
+        .layer((Extension(plugin),))
+}
+
+/// See https://api.slack.com/interactivity/slash-commands#app_command_handling
+#[derive(Deserialize, Debug)]
+struct SlashCommand {
+    team_id: String,
+    user_id: String,
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct InteractionForm {
+    payload: String,
+}
+
+/// See https://api.slack.com/reference/interaction-payloads/shortcuts#message_actions
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Interaction {
+    MessageAction {
+        callback_id: String,
+        team: Id,
+        user: Id,
+        message: Message,
+        response_url: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+struct Id {
+    id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Message {
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Serialize, Debug)]
+struct Reply {
+    response_type: &'static str,
+    text: String,
+}
+
+impl Reply {
+    /// A reply only visible to the user who invoked the command.
+    fn ephemeral(text: String) -> Reply {
+        Reply {
+            response_type: "ephemeral",
+            text,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command<'a> {
+    Add(&'a str),
+    Help,
+}
+
+/// Handles `/koso add <task name>`.
+#[tracing::instrument(skip(plugin, request_id, headers, body))]
+async fn command_handler(
+    Extension(plugin): Extension<Plugin>,
+    Extension(request_id): Extension<RequestId>,
+    headers: HeaderMap,
+    body: Body,
+) -> ApiResult<Json<Reply>> {
+    let body = read_verified_body(&headers, body).await?;
+    let command: SlashCommand = serde_qs::from_bytes(&body)
+        .map_err(|e| bad_request_error("INVALID_BODY", &format!("Invalid command: {e}")))?;
+
+    let name = match parse_command(&command.text) {
+        Command::Add(name) => name,
+        Command::Help => {
+            return Ok(Json(Reply::ephemeral(
+                "Usage: `/koso add <task name>`".to_string(),
+            )));
+        }
+    };
+    let Some((project_id, bot_token)) = plugin.workspace(&command.team_id).await? else {
+        return Ok(Json(Reply::ephemeral(not_connected_text())));
+    };
+    let reporter = plugin
+        .reporter(&project_id, &command.team_id, &bot_token, &command.user_id)
+        .await?;
+    let task = plugin
+        .create_task(
+            &project_id,
+            name,
+            None,
+            reporter.as_deref(),
+            request_id.header_value().to_str().unwrap_or("INVALID"),
+        )
+        .await?;
+    Ok(Json(Reply::ephemeral(format!("Created {}", task.link()))))
+}
+
+/// Handles the "Create Koso task" message action. Slack expects an acknowledgement
+/// within 3 seconds, so the task is created in the background and the user is
+/// replied to via the interaction's response URL.
+#[tracing::instrument(skip(plugin, request_id, headers, body))]
+async fn interaction_handler(
+    Extension(plugin): Extension<Plugin>,
+    Extension(request_id): Extension<RequestId>,
+    headers: HeaderMap,
+    body: Body,
+) -> ApiResult<()> {
+    let body = read_verified_body(&headers, body).await?;
+    let form: InteractionForm = serde_qs::from_bytes(&body)
+        .map_err(|e| bad_request_error("INVALID_BODY", &format!("Invalid interaction: {e}")))?;
+    let interaction: Interaction = serde_json::from_str(&form.payload)
+        .map_err(|e| bad_request_error("INVALID_BODY", &format!("Invalid payload: {e}")))?;
+
+    let Interaction::MessageAction {
+        callback_id,
+        team,
+        user,
+        message,
+        response_url,
+    } = interaction
+    else {
+        tracing::trace!("Discarding unhandled interaction");
+        return Ok(());
+    };
+    if callback_id != CREATE_TASK_CALLBACK_ID {
+        tracing::trace!("Discarding unhandled message action: {callback_id}");
+        return Ok(());
+    }
+
+    let request_id = request_id
+        .header_value()
+        .to_str()
+        .unwrap_or("INVALID")
+        .to_string();
+    context::spawn(
+        async move {
+            let text = match create_task_from_message(
+                &plugin,
+                &team.id,
+                &user.id,
+                &message.text,
+                &request_id,
+            )
+            .await
+            {
+                Ok(text) => text,
+                Err(e) => {
+                    tracing::warn!("Failed to create task from slack message: {e:?}");
+                    "Failed to create the task. Please try again.".to_string()
+                }
+            };
+            if let Err(e) = plugin
+                .client
+                .post(&response_url)
+                .json(&Reply::ephemeral(text))
+                .send()
+                .await
+                .and_then(|res| res.error_for_status())
+            {
+                tracing::warn!("Failed to reply to slack message action: {e:?}");
+            }
+        }
+        .in_current_span(),
+    );
+    Ok(())
+}
+
+/// Creates a task named after the first line of the message and returns the reply text.
+async fn create_task_from_message(
+    plugin: &Plugin,
+    team_id: &str,
+    user_id: &str,
+    text: &str,
+    request_id: &str,
+) -> Result<String> {
+    let Some((project_id, bot_token)) = plugin.workspace(team_id).await? else {
+        return Ok(not_connected_text());
+    };
+    let Some(name) = text.lines().map(str::trim).find(|l| !l.is_empty()) else {
+        return Ok("Cannot create a task from an empty message.".to_string());
+    };
+    let reporter = plugin
+        .reporter(&project_id, team_id, &bot_token, user_id)
+        .await?;
+    let task = plugin
+        .create_task(
+            &project_id,
+            name,
+            Some(text.trim()),
+            reporter.as_deref(),
+            request_id,
+        )
+        .await?;
+    Ok(format!("Created {}", task.link()))
+}
+
+fn not_connected_text() -> String {
+    "This workspace isn't connected to a Koso project. Connect it from your project's settings."
+        .to_string()
+}
+
+fn parse_command(text: &str) -> Command<'_> {
+    match text.trim().split_once(char::is_whitespace) {
+        Some(("add", name)) if !name.trim().is_empty() => Command::Add(name.trim()),
+        _ => Command::Help,
+    }
+}
+
+async fn read_verified_body(headers: &HeaderMap, body: Body) -> ApiResult<Bytes> {
+    let body: Bytes = axum::body::to_bytes(body, BODY_LIMIT)
+        .await
+        .map_err(|_| bad_request_error("INVALID_BODY", "Invalid body"))?;
+    let secret: Secret<String> = read_secret("slack/signing_secret")?;
+    validate_signature(headers, &body, &secret, Utc::now().timestamp())?;
+    Ok(body)
+}
+
+/// Validate the authenticity of the request.
+/// See https://api.slack.com/authentication/verifying-requests-from-slack
+fn validate_signature(
+    headers: &HeaderMap,
+    body: &[u8],
+    secret: &Secret<String>,
+    now: i64,
+) -> ApiResult<()> {
+    let Some(timestamp) = headers
+        .get("X-Slack-Request-Timestamp")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<i64>().ok())
+    else {
+        return Err(unauthorized_error("Missing or invalid timestamp."));
+    };
+    // Reject old requests to prevent replays.
+    if (now - timestamp).abs() > 300 {
+        return Err(unauthorized_error(&format!(
+            "Stale request at time {timestamp}"
+        )));
+    }
+    let Some(signature) = headers
+        .get("X-Slack-Signature")
+        .and_then(|h| h.as_bytes().strip_prefix(b"v0="))
+        .and_then(|h| hex::decode(h).ok())
+    else {
+        return Err(unauthorized_error("Invalid signature."));
+    };
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.data.as_bytes()).context("Invalid signing secret")?;
+    mac.update(format!("v0:{timestamp}:").as_bytes());
+    mac.update(body);
+    if let Err(err) = mac.verify_slice(&signature) {
+        tracing::warn!("Received slack request with invalid signature: {err:?}");
+        return Err(unauthorized_error("Invalid signature."));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_command_add() {
+        assert_eq!(
+            parse_command(" add  Fix the build "),
+            Command::Add("Fix the build")
+        );
+        assert_eq!(parse_command("add"), Command::Help);
+        assert_eq!(parse_command("add   "), Command::Help);
+        assert_eq!(parse_command("help"), Command::Help);
+        assert_eq!(parse_command(""), Command::Help);
+    }
+
+    #[test]
+    fn validate_signature_checks_hmac_and_timestamp() {
+        let secret = Secret {
+            data: "8f742231b10e8888abcd99yyyzzz85a5".to_string(),
+        };
+        let body = b"token=xyz&team_id=T1&text=add+thing";
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.data.as_bytes()).unwrap();
+        mac.update(b"v0:1000:");
+        mac.update(body);
+        let signature = format!("v0={}", hex::encode(mac.finalize().into_bytes()));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Slack-Request-Timestamp", "1000".parse().unwrap());
+        headers.insert("X-Slack-Signature", signature.parse().unwrap());
+        assert!(validate_signature(&headers, body, &secret, 1010).is_ok());
+        assert!(validate_signature(&headers, body, &secret, 2000).is_err());
+        assert!(validate_signature(&headers, b"tampered", &secret, 1010).is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use crate::{
+    api::{self, ApiResult, bad_request_error, google::User},
+    plugins::{
+        config::{Config, Settings, SlackSettings},
+        credentials::Credential,
+        slack::{PLUGIN_ID, Plugin},
+    },
+    secrets::{Secret, read_secret},
+};
+use anyhow::Context as _;
+use axum::{
+    Extension, Json, Router,
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ConnectRequest {
+    project_id: String,
+    /// The code Slack redirected back with after the user approved the install.
+    code: String,
+    redirect_uri: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConnectResponse {
+    team_name: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InitResponse {
+    client_id: String,
+}
+
+/// See https://api.slack.com/methods/oauth.v2.access
+#[derive(Deserialize)]
+struct OAuthResponse {
+    ok: bool,
+    error: Option<String>,
+    access_token: Option<String>,
+    team: Option<Team>,
+}
+
+#[derive(Deserialize)]
+struct Team {
+    id: String,
+    name: String,
+}
+
+#[derive(Clone)]
+pub(super) struct ConnectHandler {
+    plugin: Plugin,
+}
+
+impl ConnectHandler {
+    pub(super) fn new(plugin: Plugin) -> ConnectHandler {
+        ConnectHandler { plugin }
+    }
+
+    pub(super) fn router(self) -> Router {
+        Router::new()
+            .route("/init", get(Self::init_handler))
+            .route("/connect", post(Self::connect_project_handler))
+            .layer((Extension(self),))
+    }
+
+    #[tracing::instrument()]
+    async fn init_handler() -> ApiResult<Json<InitResponse>> {
+        let client_id: Secret<String> = read_secret("slack/client_id")?;
+        Ok(Json(InitResponse {
+            client_id: client_id.data,
+        }))
+    }
+
+    /// Completes the OAuth install flow and connects the workspace to the project,
+    /// replacing any project it was previously connected to.
+    #[tracing::instrument(skip(user, handler))]
+    async fn connect_project_handler(
+        Extension(user): Extension<User>,
+        Extension(handler): Extension<ConnectHandler>,
+        Json(request): Json<ConnectRequest>,
+    ) -> ApiResult<Json<ConnectResponse>> {
+        let plugin = &handler.plugin;
+        api::verify_project_access(plugin.pool, &user, &request.project_id).await?;
+        if request.code.is_empty() {
+            return Err(bad_request_error("EMPTY_CODE", "Code is blank"));
+        }
+
+        let client_id: Secret<String> = read_secret("slack/client_id")?;
+        let client_secret: Secret<String> = read_secret("slack/client_secret")?;
+        let res: OAuthResponse = plugin
+            .client
+            .post("https://slack.com/api/oauth.v2.access")
+            .form(&[
+                ("client_id", client_id.data.as_str()),
+                ("client_secret", client_secret.data.as_str()),
+                ("code", request.code.as_str()),
+                ("redirect_uri", request.redirect_uri.as_str()),
+            ])
+            .send()
+            .await
+            .context("Failed to exchange slack code")?
+            .json()
+            .await
+            .context("Failed to decode slack oauth response")?;
+        let (true, Some(access_token), Some(team)) = (res.ok, res.access_token, res.team) else {
+            return Err(bad_request_error(
+                "SLACK_AUTH_REJECTED",
+                &format!(
+                    "Slack install rejected: '{}'",
+                    res.error.unwrap_or_default()
+                ),
+            ));
+        };
+
+        tracing::info!(
+            "Connecting project {} to slack workspace {} ({})",
+            request.project_id,
+            team.id,
+            team.name
+        );
+        sqlx::query(
+            "
+            INSERT INTO slack_installations (team_id, team_name, installer)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (team_id)
+            DO UPDATE SET
+                team_name = EXCLUDED.team_name,
+                installer = EXCLUDED.installer,
+                create_time = NOW()",
+        )
+        .bind(&team.id)
+        .bind(&team.name)
+        .bind(&user.email)
+        .execute(plugin.pool)
+        .await
+        .context("Failed to upsert slack installation")?;
+        plugin
+            .credentials
+            .save(
+                PLUGIN_ID,
+                &team.id,
+                &user.email,
+                &Credential::long_lived(access_token),
+            )
+            .await?;
+
+        // Each workspace creates tasks in a single project.
+        plugin
+            .config_storage
+            .delete_for_external_id(PLUGIN_ID, &team.id)
+            .await?;
+        plugin
+            .config_storage
+            .insert_or_update(&Config {
+                project_id: request.project_id,
+                plugin_id: PLUGIN_ID.to_string(),
+                external_id: team.id,
+                settings: Settings::Slack(SlackSettings {
+                    team_name: team.name.clone(),
+                }),
+            })
+            .await?;
+
+        Ok(Json(ConnectResponse {
+            team_name: team.name,
+        }))
+    }
+}
@@ -0,0 +1,222 @@
+//! Sync status of each plugin config: when it last synced, the webhook events
+//! still being applied, recent failures and the next scheduled poll.
+
+use crate::{api::model::ProjectId, plugins::config::Config, postgres::PgPool};
+use anyhow::{Context as _, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+
+/// Failures kept per config.
+const MAX_RECENT_ERRORS: usize = 5;
+const MAX_ERROR_LEN: usize = 500;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SyncStatus {
+    pub(crate) plugin_id: String,
+    pub(crate) external_id: String,
+    pub(crate) last_sync_time: Option<DateTime<Utc>>,
+    pub(crate) pending_events: i32,
+    pub(crate) recent_errors: Vec<SyncError>,
+    pub(crate) next_poll_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SyncError {
+    pub(crate) time: DateTime<Utc>,
+    pub(crate) message: String,
+}
+
+type StatusRow = (
+    String,
+    String,
+    Option<DateTime<Utc>>,
+    Option<i32>,
+    Option<Json<Vec<SyncError>>>,
+    Option<DateTime<Utc>>,
+);
+
+/// Lists the status of each of the project's plugin configs, including
+/// those that haven't synced yet.
+pub(crate) async fn list_for_project(
+    pool: &PgPool,
+    project_id: &ProjectId,
+) -> Result<Vec<SyncStatus>> {
+    let rows: Vec<StatusRow> = sqlx::query_as(
+        "
+        SELECT
+            plugin_id,
+            external_id,
+            last_sync_time,
+            pending_events,
+            recent_errors,
+            next_poll_time
+        FROM plugin_configs
+        LEFT JOIN plugin_sync_status USING (project_id, plugin_id, external_id)
+        WHERE project_id = $1
+        ORDER BY plugin_id, external_id",
+    )
+    .bind(project_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to list plugin sync status")?;
+    Ok(rows
+        .into_iter()
+        .map(
+            |(
+                plugin_id,
+                external_id,
+                last_sync_time,
+                pending_events,
+                recent_errors,
+                next_poll_time,
+            )| {
+                SyncStatus {
+                    plugin_id,
+                    external_id,
+                    last_sync_time,
+                    pending_events: pending_events.unwrap_or_default(),
+                    recent_errors: recent_errors.map(|Json(e)| e).unwrap_or_default(),
+                    next_poll_time,
+                }
+            },
+        )
+        .collect())
+}
+
+#[derive(Clone)]
+pub(super) struct StatusStorage {
+    pool: &'static PgPool,
+}
+
+impl StatusStorage {
+    pub(super) fn new(pool: &'static PgPool) -> StatusStorage {
+        StatusStorage { pool }
+    }
+
+    /// Records the outcome of syncing the config, e.g. a poll or webhook
+    /// event. Failing to record it doesn't fail the sync.
+    pub(super) async fn record(&self, config: &Config, result: &Result<()>) {
+        let recorded = match result {
+            Ok(()) => self.record_success(config).await,
+            Err(e) => self.record_error(config, e).await,
+        };
+        if let Err(e) = recorded {
+            tracing::warn!("Failed to record sync status: {e:?}");
+        }
+    }
+
+    async fn record_success(&self, config: &Config) -> Result<()> {
+        sqlx::query(
+            "
+            INSERT INTO plugin_sync_status (project_id, plugin_id, external_id, last_sync_time)
+            VALUES ($1, $2, $3, NOW())
+            ON CONFLICT (project_id, plugin_id, external_id)
+            DO UPDATE SET last_sync_time = EXCLUDED.last_sync_time",
+        )
+        .bind(&config.project_id)
+        .bind(&config.plugin_id)
+        .bind(&config.external_id)
+        .execute(self.pool)
+        .await
+        .context("Failed to record plugin sync")?;
+        Ok(())
+    }
+
+    async fn record_error(&self, config: &Config, error: &anyhow::Error) -> Result<()> {
+        let error = vec![SyncError {
+            time: Utc::now(),
+            message: format!("{error:#}").chars().take(MAX_ERROR_LEN).collect(),
+        }];
+        sqlx::query(&format!(
+            "
+            INSERT INTO plugin_sync_status (project_id, plugin_id, external_id, recent_errors)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (project_id, plugin_id, external_id)
+            DO UPDATE SET recent_errors = jsonb_path_query_array(
+                EXCLUDED.recent_errors || plugin_sync_status.recent_errors,
+                '$[0 to {}]')",
+            MAX_RECENT_ERRORS - 1
+        ))
+        .bind(&config.project_id)
+        .bind(&config.plugin_id)
+        .bind(&config.external_id)
+        .bind(Json(error))
+        .execute(self.pool)
+        .await
+        .context("Failed to record plugin sync error")?;
+        Ok(())
+    }
+
+    /// Counts a webhook event as pending for every config of the external id,
+    /// e.g. each project connected to a GitHub installation.
+    pub(super) async fn add_pending(&self, plugin_id: &str, external_id: &str) -> Result<()> {
+        sqlx::query(
+            "
+            INSERT INTO plugin_sync_status (project_id, plugin_id, external_id, pending_events)
+            SELECT project_id, plugin_id, external_id, 1
+            FROM plugin_configs
+            WHERE plugin_id = $1 AND external_id = $2
+            ON CONFLICT (project_id, plugin_id, external_id)
+            DO UPDATE SET pending_events = plugin_sync_status.pending_events + 1",
+        )
+        .bind(plugin_id)
+        .bind(external_id)
+        .execute(self.pool)
+        .await
+        .context("Failed to record pending plugin event")?;
+        Ok(())
+    }
+
+    pub(super) async fn remove_pending(&self, plugin_id: &str, external_id: &str) -> Result<()> {
+        sqlx::query(
+            "
+            UPDATE plugin_sync_status
+            SET pending_events = GREATEST(pending_events - 1, 0)
+            WHERE plugin_id = $1 AND external_id = $2",
+        )
+        .bind(plugin_id)
+        .bind(external_id)
+        .execute(self.pool)
+        .await
+        .context("Failed to record applied plugin event")?;
+        Ok(())
+    }
+
+    /// Clears the plugin's pending events, which can't still be in flight
+    /// when the server starts.
+    pub(super) async fn clear_pending(&self, plugin_id: &str) -> Result<()> {
+        sqlx::query("UPDATE plugin_sync_status SET pending_events = 0 WHERE plugin_id = $1")
+            .bind(plugin_id)
+            .execute(self.pool)
+            .await
+            .context("Failed to clear pending plugin events")?;
+        Ok(())
+    }
+
+    /// Records when each of the plugin's configs will next be polled, or
+    /// that they won't be with None.
+    pub(super) async fn schedule_poll(
+        &self,
+        plugin_id: &str,
+        next_poll_time: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        sqlx::query(
+            "
+            INSERT INTO plugin_sync_status (project_id, plugin_id, external_id, next_poll_time)
+            SELECT project_id, plugin_id, external_id, $2
+            FROM plugin_configs
+            WHERE plugin_id = $1
+            ON CONFLICT (project_id, plugin_id, external_id)
+            DO UPDATE SET next_poll_time = EXCLUDED.next_poll_time",
+        )
+        .bind(plugin_id)
+        .bind(next_poll_time)
+        .execute(self.pool)
+        .await
+        .context("Failed to schedule plugin poll")?;
+        Ok(())
+    }
+}
@@ -1,22 +1,98 @@
-use crate::api::model::{ProjectId, ProjectUser};
+use crate::{
+    api::{
+        errors::{CodedError, ErrorCode},
+        jobs::{self, Job, JobHandler, NewJob},
+        model::{ProjectId, ProjectUser},
+    },
+    settings::settings,
+};
 use anyhow::Result;
 use anyhow::anyhow;
-use sqlx::PgPool;
+use async_trait::async_trait;
+use chrono::{TimeDelta, Utc};
+use futures::{FutureExt as _, StreamExt as _, future::BoxFuture, stream::BoxStream};
+use sqlx::{
+    Describe, Either, Execute, Executor, PgConnection, Postgres, Transaction,
+    postgres::{PgQueryResult, PgRow, PgStatement, PgTypeInfo},
+};
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    sync::{
+        Arc, LazyLock, PoisonError, RwLock,
+        atomic::{AtomicBool, AtomicI64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
 use yrs::{
     Update,
     updates::{decoder::Decode, encoder::Encode},
 };
 
+const COMPACT_JOB: &str = "compact";
+/// Replicas further behind the primary than this stop serving reads.
+const MAX_REPLICA_LAG: Duration = Duration::from_secs(10);
+const REPLICA_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const POOL_METRICS_INTERVAL: Duration = Duration::from_secs(1);
+/// The fraction of a pool's connections in use at which best-effort work is shed.
+const SATURATION: f64 = 0.8;
+/// How long best-effort work stays shed after the pool was last saturated,
+/// so it doesn't pile straight back on.
+const BREAKER_COOLDOWN: Duration = Duration::from_secs(30);
+/// Compaction waits a little, so a project that's closed and reopened
+/// repeatedly is compacted once.
+const COMPACTION_DELAY: TimeDelta = TimeDelta::minutes(1);
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Compaction {
+    project_id: ProjectId,
+}
+
+/// Queues compaction of the project's updates, unless it's already queued.
 #[tracing::instrument(skip(pool))]
-pub(crate) async fn compact(pool: &PgPool, project_id: ProjectId) {
-    if let Err(e) = _compact(pool, project_id).await {
-        tracing::warn!("Failed to compact: {e:?}");
+pub(crate) async fn queue_compaction(pool: &PgPool, project_id: ProjectId) {
+    if let Err(e) = _queue_compaction(pool, project_id).await {
+        tracing::warn!("Failed to queue compaction: {e:?}");
+    }
+}
+
+async fn _queue_compaction(pool: &PgPool, project_id: ProjectId) -> Result<()> {
+    let job = NewJob::new(
+        COMPACT_JOB,
+        &Compaction {
+            project_id: project_id.clone(),
+        },
+    )?
+    .key(project_id)
+    .run_at(Utc::now() + COMPACTION_DELAY);
+    jobs::enqueue(pool, job).await?;
+    Ok(())
+}
+
+/// Runs compaction jobs. Concurrent compactions of a project conflict,
+/// so they run one at a time.
+pub(crate) struct Compactor {
+    pub(crate) pool: &'static PgPool,
+}
+
+#[async_trait]
+impl JobHandler for Compactor {
+    fn kind(&self) -> &'static str {
+        COMPACT_JOB
+    }
+
+    async fn run(&self, job: &Job) -> Result<()> {
+        let compaction: Compaction = job.payload()?;
+        compact(self.pool, compaction.project_id).await
     }
 }
 
-async fn _compact(pool: &PgPool, project_id: ProjectId) -> Result<()> {
+#[tracing::instrument(skip(pool))]
+async fn compact(pool: &PgPool, project_id: ProjectId) -> Result<()> {
     tracing::debug!("Starting compaction");
-    let mut txn = pool.begin().await?;
+    let mut txn = doc_pool(pool, &project_id).await?.begin().await?;
 
     let updates: Vec<(i32, Vec<u8>)> = sqlx::query_as(
         "
@@ -93,7 +169,7 @@ pub(crate) async fn list_project_users(
 
     let users: Vec<ProjectUser> = sqlx::query_as(
         "
-        SELECT project_id, email, name, picture, (subscription_end_time IS NOT NULL AND subscription_end_time > now()) AS premium
+        SELECT project_id, email, name, picture, is_premium(subscription_end_time) AS premium
         FROM project_permissions
         JOIN users USING (email)
         WHERE project_id = $1;
@@ -105,3 +181,360 @@ pub(crate) async fn list_project_users(
 
     Ok(users)
 }
+
+/// Routes reads that tolerate a few seconds of staleness, e.g. exports and
+/// analytics, to a read replica when one is configured and caught up, falling
+/// back to the primary otherwise. Writes, permission checks and the collab
+/// path always use the primary.
+#[derive(Clone)]
+pub(crate) struct ReadPool {
+    primary: &'static PgPool,
+    replica: Option<&'static PgPool>,
+    caught_up: Arc<AtomicBool>,
+}
+
+impl ReadPool {
+    pub(crate) fn new(primary: &'static PgPool, replica: Option<&'static PgPool>) -> Self {
+        ReadPool {
+            primary,
+            replica,
+            // Until the first check, assume the worst.
+            caught_up: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// The pool to read from.
+    pub(crate) fn get(&self) -> &'static PgPool {
+        match self.replica {
+            Some(replica) if self.caught_up.load(Ordering::Relaxed) => replica,
+            _ => self.primary,
+        }
+    }
+
+    /// Checks the replica's lag every few seconds, if there's a replica.
+    pub(crate) fn start_monitoring(&self) -> Option<JoinHandle<()>> {
+        let replica = self.replica?;
+        let caught_up = Arc::clone(&self.caught_up);
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(REPLICA_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                let lag = match replica_lag(replica).await {
+                    Ok(lag) => lag,
+                    Err(e) => {
+                        tracing::warn!("Failed to check replica lag: {e:?}");
+                        None
+                    }
+                };
+                if let Some(lag) = lag {
+                    metrics::gauge!("replica_lag_seconds").set(lag.as_secs_f64());
+                }
+                let ok = lag.is_some_and(|lag| lag <= MAX_REPLICA_LAG);
+                if caught_up.swap(ok, Ordering::Relaxed) != ok {
+                    if ok {
+                        tracing::info!("Replica caught up, reading from it: {lag:?}");
+                    } else {
+                        tracing::warn!("Replica fell behind, reading from the primary: {lag:?}");
+                    }
+                }
+            }
+        }))
+    }
+}
+
+/// Returns how far the replica is behind the primary, or None if it
+/// hasn't replayed anything yet or isn't streaming from the primary.
+async fn replica_lag(replica: &PgPool) -> Result<Option<Duration>> {
+    // A replica that's replayed everything it received isn't behind, even if
+    // the last replayed transaction is old because the primary is idle. Unless
+    // it's disconnected, in which case it hasn't received what it's missing.
+    let (lag,): (Option<f64>,) = sqlx::query_as(
+        "
+        SELECT CASE
+          WHEN NOT pg_is_in_recovery() THEN 0
+          WHEN NOT EXISTS (SELECT 1 FROM pg_stat_wal_receiver WHERE status = 'streaming') THEN NULL
+          WHEN pg_last_wal_receive_lsn() = pg_last_wal_replay_lsn() THEN 0
+          ELSE EXTRACT(EPOCH FROM NOW() - pg_last_xact_replay_timestamp())
+        END::float8",
+    )
+    .fetch_one(replica)
+    .await?;
+    Ok(lag.map(|lag| Duration::from_secs_f64(lag.max(0.0))))
+}
+
+/// Databases of other data regions, by region, connected at startup. Docs of
+/// projects pinned to those regions are stored there, everything else in the
+/// deployment's own database.
+static REGIONS: LazyLock<RwLock<HashMap<String, &'static PgPool>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Routes docs of projects pinned to the region to its database.
+pub(crate) fn register_region(region: &str, pool: &'static PgPool) {
+    REGIONS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(region.to_string(), pool);
+}
+
+/// Whether docs of projects pinned to the region can be served from here.
+pub(crate) fn serves_region(region: &str) -> bool {
+    settings().region.as_deref() == Some(region)
+        || REGIONS
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains_key(region)
+}
+
+/// The pool of the region's database. Projects that aren't pinned, or are
+/// pinned to this deployment's region, are stored in `pool`.
+pub(crate) fn region_pool<'a>(pool: &'a PgPool, region: Option<&str>) -> Result<&'a PgPool> {
+    let Some(region) = region.filter(|r| settings().region.as_deref() != Some(*r)) else {
+        return Ok(pool);
+    };
+    let regional = REGIONS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(region)
+        .copied();
+    regional.ok_or_else(|| {
+        CodedError::new(
+            ErrorCode::WrongRegion,
+            format!("Region {region} isn't served here"),
+        )
+        .into()
+    })
+}
+
+/// The pool of the database storing the project's doc, looking up the
+/// project's region through `pool`.
+pub(crate) async fn doc_pool<'a>(pool: &'a PgPool, project_id: &str) -> Result<&'a PgPool> {
+    let region: Option<(Option<String>,)> =
+        sqlx::query_as("SELECT region FROM projects WHERE project_id = $1")
+            .bind(project_id)
+            .fetch_optional(pool)
+            .await?;
+    region_pool(pool, region.and_then(|(region,)| region).as_deref())
+}
+
+/// A connection pool that records the latency of every query and transaction
+/// run on it and sheds best-effort work when it's saturated. Derefs to the
+/// sqlx pool for everything else.
+#[derive(Clone, Debug)]
+pub(crate) struct PgPool {
+    pool: sqlx::PgPool,
+    /// Tags the pool's metrics, e.g. "primary" or "replica".
+    name: &'static str,
+    /// When best-effort work was last shed, in milliseconds since the Unix epoch,
+    /// shared by the pool's clones.
+    tripped_at: Arc<AtomicI64>,
+}
+
+impl PgPool {
+    pub(crate) fn new(name: &'static str, pool: sqlx::PgPool) -> Self {
+        PgPool {
+            pool,
+            name,
+            tripped_at: Arc::new(AtomicI64::new(0)),
+        }
+    }
+
+    /// Whether best-effort work, e.g. analytics snapshots, should back off to
+    /// leave connections for the collab write path. Trips when the pool is
+    /// nearly exhausted and stays tripped for a cooldown afterwards.
+    pub(crate) fn shed_best_effort(&self) -> bool {
+        let now = Utc::now().timestamp_millis();
+        let cooldown = BREAKER_COOLDOWN.as_millis() as i64;
+        if self.utilization() >= SATURATION {
+            let tripped_at = self.tripped_at.swap(now, Ordering::Relaxed);
+            if now - tripped_at >= cooldown {
+                tracing::warn!(
+                    "Database pool {} saturated, shedding best-effort work",
+                    self.name
+                );
+            }
+        }
+        let shed = now - self.tripped_at.load(Ordering::Relaxed) < cooldown;
+        if shed {
+            metrics::counter!("db_best_effort_shed_total", "pool" => self.name).increment(1);
+        }
+        shed
+    }
+
+    /// Begins a transaction, recording how long it waited for a connection and
+    /// how long it held it.
+    pub(crate) async fn begin(&self) -> Result<PgTransaction, sqlx::Error> {
+        let acquire = Timer::start("db_connection_acquire_duration_seconds", self.name);
+        let txn = self.pool.begin().await?;
+        drop(acquire);
+        Ok(PgTransaction {
+            txn,
+            _timer: Timer::start("db_transaction_duration_seconds", self.name),
+        })
+    }
+
+    fn utilization(&self) -> f64 {
+        let in_use = (self.pool.size() as usize).saturating_sub(self.pool.num_idle());
+        in_use as f64 / self.pool.options().get_max_connections() as f64
+    }
+}
+
+impl From<sqlx::PgPool> for PgPool {
+    fn from(pool: sqlx::PgPool) -> Self {
+        PgPool::new("primary", pool)
+    }
+}
+
+impl Deref for PgPool {
+    type Target = sqlx::PgPool;
+
+    fn deref(&self) -> &Self::Target {
+        &self.pool
+    }
+}
+
+impl<'p> Executor<'p> for &'_ PgPool {
+    type Database = Postgres;
+
+    fn fetch_many<'e, 'q: 'e, E>(
+        self,
+        query: E,
+    ) -> BoxStream<'e, Result<Either<PgQueryResult, PgRow>, sqlx::Error>>
+    where
+        'p: 'e,
+        E: 'q + Execute<'q, Postgres>,
+    {
+        let timer = Timer::start("db_query_duration_seconds", self.name);
+        self.pool
+            .fetch_many(query)
+            .map(move |row| {
+                let _timer = &timer;
+                row
+            })
+            .boxed()
+    }
+
+    fn fetch_optional<'e, 'q: 'e, E>(
+        self,
+        query: E,
+    ) -> BoxFuture<'e, Result<Option<PgRow>, sqlx::Error>>
+    where
+        'p: 'e,
+        E: 'q + Execute<'q, Postgres>,
+    {
+        let timer = Timer::start("db_query_duration_seconds", self.name);
+        let row = self.pool.fetch_optional(query);
+        async move {
+            let _timer = timer;
+            row.await
+        }
+        .boxed()
+    }
+
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [PgTypeInfo],
+    ) -> BoxFuture<'e, Result<PgStatement<'q>, sqlx::Error>>
+    where
+        'p: 'e,
+    {
+        self.pool.prepare_with(sql, parameters)
+    }
+
+    fn describe<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+    ) -> BoxFuture<'e, Result<Describe<Postgres>, sqlx::Error>>
+    where
+        'p: 'e,
+    {
+        self.pool.describe(sql)
+    }
+}
+
+/// A transaction begun on a PgPool. Derefs to its connection, like sqlx's
+/// transactions, and records how long it was open when committed or
+/// dropped, i.e. rolled back.
+pub(crate) struct PgTransaction {
+    txn: Transaction<'static, Postgres>,
+    _timer: Timer,
+}
+
+impl PgTransaction {
+    pub(crate) async fn commit(self) -> Result<(), sqlx::Error> {
+        self.txn.commit().await
+    }
+}
+
+impl Deref for PgTransaction {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.txn
+    }
+}
+
+impl DerefMut for PgTransaction {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.txn
+    }
+}
+
+/// Records how long something run on a pool took when dropped, tagged with
+/// its call site.
+struct Timer {
+    metric: &'static str,
+    pool: &'static str,
+    call_site: String,
+    start: Instant,
+}
+
+impl Timer {
+    fn start(metric: &'static str, pool: &'static str) -> Self {
+        Timer {
+            metric,
+            pool,
+            call_site: call_site(),
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        metrics::histogram!(
+            self.metric,
+            "pool" => self.pool,
+            "call_site" => std::mem::take(&mut self.call_site)
+        )
+        .record(self.start.elapsed().as_secs_f64());
+    }
+}
+
+/// Names the call site after the innermost instrumented function, e.g.
+/// "koso::api::reverts::revert_handler". Unlike the SQL, which varies with
+/// how it's built, function names are static, so the metrics' cardinality
+/// stays bounded.
+fn call_site() -> String {
+    tracing::Span::current()
+        .metadata()
+        .map(|m| format!("{}::{}", m.module_path().unwrap_or(m.target()), m.name()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Records how many of the pool's connections are in use every second.
+pub(crate) fn start_pool_metrics(pool: &'static PgPool) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let name = pool.name;
+        let mut interval = tokio::time::interval(POOL_METRICS_INTERVAL);
+        loop {
+            interval.tick().await;
+            let idle = pool.num_idle() as f64;
+            let open = pool.size() as f64;
+            metrics::gauge!("db_pool_connections", "pool" => name, "state" => "idle").set(idle);
+            metrics::gauge!("db_pool_connections", "pool" => name, "state" => "in_use")
+                .set(open - idle);
+            metrics::gauge!("db_pool_utilization", "pool" => name).set(pool.utilization());
+        }
+    })
+}
@@ -1,29 +1,45 @@
 use crate::{
     api::{
-        self, XForwardedFor,
-        collab::Collab,
+        self,
+        analytics::AnalyticsSnapshotter,
+        attachments::scanner::AttachmentScanner,
+        auto_archive::AutoArchiver,
+        collab::{Collab, tiering::ColdStorageMover},
+        demo::DemoResetter,
         google::{self, KeySet},
+        imports::ImportRunner,
+        jobs::JobQueue,
+        milestones::MilestoneMonitor,
+        reports::ReportScheduler,
+        retention::RetentionPruner,
+        risks::RiskMonitor,
+        security,
+        usage::UsageTracker,
     },
     healthz,
+    license::LicenseMonitor,
+    notifiers::{Notifiers, telegram},
     plugins::{
         PluginSettings,
+        credentials::CredentialMonitor,
         github::{self},
+        slack,
     },
+    postgres::{self, Compactor, PgPool, ReadPool},
     settings::settings,
 };
 use anyhow::{Context, Result};
 use axum::{
     Extension, Router,
-    extract::{ConnectInfo, MatchedPath, Request},
+    extract::{MatchedPath, Request},
     http::{HeaderName, HeaderValue},
     middleware::{self, Next},
     response::{IntoResponse, Response},
 };
-use axum_extra::headers::HeaderMapExt;
 use listenfd::ListenFd;
 use sqlx::{
     ConnectOptions,
-    postgres::{PgConnectOptions, PgPool, PgPoolOptions},
+    postgres::{PgConnectOptions, PgPoolOptions},
 };
 use std::{
     net::SocketAddr,
@@ -46,36 +62,34 @@ use tracing::{Level, Span};
 #[derive(Default)]
 pub struct Config {
     pub pool: Option<&'static PgPool>,
+    /// A read replica, connected to per the settings when unset.
+    pub replica_pool: Option<&'static PgPool>,
     pub port: Option<u16>,
     pub shutdown_signal: CancellationToken,
     pub key_set: Option<KeySet>,
     pub plugin_settings: Option<PluginSettings>,
+    /// Whether to run the Telegram bot. Only one bot may poll for updates at a time.
+    pub enable_telegram: bool,
 }
 
 #[tracing::instrument(skip(config))]
 pub async fn start_main_server(config: Config) -> Result<(SocketAddr, JoinHandle<Result<()>>)> {
     let pool = match config.pool {
         Some(pool) => pool,
-        None => {
-            // Connect to the Postgres database.
-            let db_connection_str = &settings().database_url;
-            tracing::info!("Connecting to database: {}", db_connection_str);
-
-            Box::leak(Box::new(
-                PgPoolOptions::new()
-                    .max_connections(5)
-                    .acquire_timeout(Duration::from_secs(3))
-                    .connect_with(
-                        db_connection_str
-                            .parse::<PgConnectOptions>()?
-                            // Enable query trace logging. Must enable `sqlx=trace`
-                            .log_statements(tracing::log::LevelFilter::Trace),
-                    )
-                    .await
-                    .context("Can't connect to database")?,
-            ))
-        }
+        None => connect("primary", &settings().database_url).await?,
     };
+    let replica_pool = match (config.replica_pool, &settings().replica_database_url) {
+        (Some(replica_pool), _) => Some(replica_pool),
+        (None, Some(url)) => Some(connect("replica", url).await?),
+        (None, None) => None,
+    };
+    connect_regions().await?;
+    let read_pool = ReadPool::new(pool, replica_pool);
+    let replica_monitor_handle = read_pool.start_monitoring();
+    let mut pool_metrics_handles = vec![postgres::start_pool_metrics(pool)];
+    if let Some(replica_pool) = replica_pool {
+        pool_metrics_handles.push(postgres::start_pool_metrics(replica_pool));
+    }
 
     let collab = Collab::new(pool).context("Failed to init collab")?;
     let key_set = match config.key_set {
@@ -90,16 +104,60 @@ pub async fn start_main_server(config: Config) -> Result<(SocketAddr, JoinHandle
     )
     .await?;
     let github_poll_handle = github_plugin.start_polling();
+    let slack_plugin = slack::Plugin::new(collab.clone(), pool)?;
+    let usage = UsageTracker::new(pool);
+    let usage_flush_handle = usage.start_flushing();
+    let maintenance_handle = collab.maintenance().start_refreshing();
+    let report_handle = ReportScheduler::new(pool, collab.clone())?.start();
+    let snapshot_handle = AnalyticsSnapshotter::new(pool, collab.clone()).start();
+    let auto_archive_handle = AutoArchiver::new(pool, collab.clone()).start();
+    let demo_handle = DemoResetter::new(pool, collab.clone()).start();
+    let retention_handle = RetentionPruner::new(pool).start();
+    let tiering_handle = ColdStorageMover::new(pool).start();
+    let milestone_handle = MilestoneMonitor::new(pool, collab.clone())?.start();
+    let risk_handle = RiskMonitor::new(pool, collab.clone())?.start();
+    let notification_retry_handle = Notifiers::new(pool)?.start_retrying();
+    let credential_handle = CredentialMonitor::new(pool)?
+        .refresher(github_plugin.credential_refresher()?)
+        .start();
+    let license_handle = LicenseMonitor::new(pool)?.start();
+    let job_handle = JobQueue::new(pool)
+        .register(Compactor { pool })
+        .register(ImportRunner {
+            pool,
+            collab: collab.clone(),
+        })
+        .register(AttachmentScanner {
+            pool,
+            collab: collab.clone(),
+        })
+        .start();
+    let telegram_handle = if config.enable_telegram {
+        Some(telegram::start_telegram_server(
+            config.shutdown_signal.clone(),
+            pool,
+            collab.clone(),
+        )?)
+    } else {
+        None
+    };
 
     let app = Router::new()
         .nest("/api", api::router()?.fallback(api::handler_404))
         .nest("/healthz", healthz::router())
+        // Unauthenticated, read-only views framed by other sites.
+        .nest("/embed", api::embed::embed_router())
+        // Unauthenticated, published status pages.
+        .nest("/status", api::status_pages::public_router())
         .nest("/plugins/github", github_plugin.router()?)
+        .nest("/plugins/slack", slack_plugin.router())
         // Apply these layers to all non-static routes.
         .layer((
             Extension(pool),
+            Extension(read_pool),
             Extension(collab.clone()),
             Extension(key_set),
+            Extension(usage.clone()),
             middleware::from_fn(emit_request_metrics),
             SetRequestIdLayer::new(HeaderName::from_static("x-request-id"), MakeRequestUuid),
             PropagateRequestIdLayer::new(HeaderName::from_static("x-request-id")),
@@ -107,6 +165,8 @@ pub async fn start_main_server(config: Config) -> Result<(SocketAddr, JoinHandle
             TraceLayer::new_for_http()
                 .make_span_with(KosoMakeSpan {})
                 .on_request(KosoOnRequest {}),
+            // Must follow the request id and trace layers, which it builds on.
+            middleware::from_fn(api::context::propagate),
         ))
         .fallback_service(
             ServiceBuilder::new()
@@ -154,9 +214,40 @@ pub async fn start_main_server(config: Config) -> Result<(SocketAddr, JoinHandle
 
         // Now that the server is shutdown, it's safe to clean things up.
         github_poll_handle.abort();
+        usage_flush_handle.abort();
+        maintenance_handle.abort();
+        report_handle.abort();
+        snapshot_handle.abort();
+        auto_archive_handle.abort();
+        demo_handle.abort();
+        retention_handle.abort();
+        tiering_handle.abort();
+        milestone_handle.abort();
+        risk_handle.abort();
+        notification_retry_handle.abort();
+        credential_handle.abort();
+        license_handle.abort();
+        job_handle.abort();
+        if let Some(replica_monitor_handle) = replica_monitor_handle {
+            replica_monitor_handle.abort();
+        }
+        for handle in pool_metrics_handles {
+            handle.abort();
+        }
+        if let Some(telegram_handle) = telegram_handle {
+            if let Err(e) = telegram_handle.await {
+                tracing::warn!("Telegram bot failed: {e:?}");
+            }
+        }
+        if let Err(e) = usage.flush().await {
+            tracing::warn!("Failed to flush API usage: {e:?}");
+        }
         collab.stop().await;
         tracing::info!("Closing database pool...");
         pool.close().await;
+        if let Some(replica_pool) = replica_pool {
+            replica_pool.close().await;
+        }
         tracing::info!("Database pool closed.");
         Ok(())
     });
@@ -164,6 +255,37 @@ pub async fn start_main_server(config: Config) -> Result<(SocketAddr, JoinHandle
     Ok((addr, serve))
 }
 
+/// Connects to the databases of the other data regions in the settings, unless
+/// already connected, e.g. by another server in the same process.
+pub(crate) async fn connect_regions() -> Result<()> {
+    for (region, url) in &settings().regional_database_urls {
+        if !postgres::serves_region(region) {
+            postgres::register_region(region, connect("regional", url).await?);
+        }
+    }
+    Ok(())
+}
+
+/// Connects to the Postgres database. `name` tags the pool's metrics.
+pub(crate) async fn connect(
+    name: &'static str,
+    db_connection_str: &str,
+) -> Result<&'static PgPool> {
+    tracing::info!("Connecting to database: {}", db_connection_str);
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .acquire_timeout(Duration::from_secs(3))
+        .connect_with(
+            db_connection_str
+                .parse::<PgConnectOptions>()?
+                // Enable query trace logging. Must enable `sqlx=trace`
+                .log_statements(tracing::log::LevelFilter::Trace),
+        )
+        .await
+        .context("Can't connect to database")?;
+    Ok(Box::leak(Box::new(PgPool::new(name, pool))))
+}
+
 async fn emit_request_metrics(req: Request, next: Next) -> impl IntoResponse {
     let start = Instant::now();
     let path = if let Some(matched_path) = req.extensions().get::<MatchedPath>() {
@@ -264,12 +386,9 @@ fn version_from_ws_header<B>(request: &Request<B>) -> Option<&str> {
 }
 
 fn client_ip<B>(request: &Request<B>) -> String {
-    match request.headers().typed_get::<XForwardedFor>() {
-        Some(forwarded_for) => forwarded_for.client_ip,
-        None => match request.extensions().get::<ConnectInfo<SocketAddr>>() {
-            Some(ConnectInfo(addr)) => addr.to_string(),
-            None => "UNKNOWN_IP".to_string(),
-        },
+    match security::client_ip(request.headers(), request.extensions()) {
+        Some(ip) => ip.to_string(),
+        None => "UNKNOWN_IP".to_string(),
     }
 }
 
@@ -1,16 +1,58 @@
 use anyhow::{Context, Result, anyhow};
 use config::{Environment, File, FileFormat};
 use serde::Deserialize;
-use std::sync::OnceLock;
+use std::{collections::HashMap, sync::OnceLock};
 
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub(crate) struct Settings {
     pub(crate) env: String,
     pub(crate) database_url: String,
+    /// A read replica of the database, e.g. one closer to EU users, for reads
+    /// that tolerate slight staleness.
+    #[serde(default)]
+    pub(crate) replica_database_url: Option<String>,
+    /// The data region this deployment's database is in, e.g. "us". Unset
+    /// for single region deployments.
+    #[serde(default)]
+    pub(crate) region: Option<String>,
+    /// Databases of other data regions, by region, e.g. {"eu": "postgresql://..."}.
+    /// Docs of projects pinned to those regions are stored there.
+    #[serde(default)]
+    pub(crate) regional_database_urls: HashMap<String, String>,
     pub(crate) secrets_dir: String,
     pub(crate) plugins: Plugins,
     pub(crate) stripe: Stripe,
+    pub(crate) inbound_email: InboundEmail,
+    /// How long, in milliseconds, broadcasts of doc updates are held so updates
+    /// arriving in quick succession, e.g. while typing, go out as one. Zero
+    /// disables coalescing.
+    #[serde(default = "default_coalesce_window_ms")]
+    pub(crate) coalesce_window_ms: u64,
+    /// How long history is kept, by default. Projects may override each period.
+    #[serde(default)]
+    pub(crate) retention: Retention,
+    #[serde(default)]
+    pub(crate) cold_storage: ColdStorage,
+    /// Malware scanning of uploaded attachments.
+    #[serde(default)]
+    pub(crate) attachment_scanning: AttachmentScanning,
+    /// Shadow experiments to run, by name, each with the fraction of calls to
+    /// verify, e.g. {"merged_load": 0.1}. Experiments not listed don't run.
+    #[serde(default)]
+    pub(crate) shadow: HashMap<String, f64>,
+    /// Addresses, in CIDR notation, of the reverse proxies in front of the
+    /// server, e.g. the load balancer. X-Forwarded-For is only trusted on
+    /// requests from one of them.
+    #[serde(default = "default_trusted_proxies")]
+    pub(crate) trusted_proxies: Vec<String>,
+    /// Emails of the users who administer the deployment, e.g. inspecting the job queue.
+    #[serde(default)]
+    pub(crate) admins: Vec<String>,
+    /// The license of a self-hosted deployment. Unset for the hosted one,
+    /// which bills through Stripe.
+    #[serde(default)]
+    pub(crate) license: License,
 }
 
 #[derive(Debug, Deserialize)]
@@ -34,6 +76,85 @@ pub(crate) struct Stripe {
     pub(crate) enable_unathenticated_webhook: bool,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct InboundEmail {
+    /// The domain inbound project addresses are hosted on.
+    pub(crate) domain: String,
+}
+
+/// Retention periods, in days, for each category of history.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct Retention {
+    /// Stored doc updates, which are compacted into a single update once older than this.
+    pub(crate) update_days: i32,
+    /// Who changed what, e.g. as shown in project activity.
+    pub(crate) activity_days: i32,
+    /// Task status transitions and auto-archive runs.
+    pub(crate) history_days: i32,
+    /// Inbox notifications and notification delivery logs.
+    pub(crate) notification_days: i32,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention {
+            update_days: 30,
+            activity_days: 365,
+            history_days: 730,
+            notification_days: 30,
+        }
+    }
+}
+
+/// Where, and after how long, inactive project docs are moved out of Postgres.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct ColdStorage {
+    /// A gs://bucket or file:///path to store docs in. Tiering is disabled when unset.
+    pub(crate) url: Option<String>,
+    /// Projects without updates for this long are moved to cold storage.
+    pub(crate) inactive_days: i32,
+}
+
+impl Default for ColdStorage {
+    fn default() -> Self {
+        ColdStorage {
+            url: None,
+            inactive_days: 180,
+        }
+    }
+}
+
+/// A scanner uploaded attachments are checked with before they can be
+/// downloaded. Scanning is disabled when neither is set.
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct AttachmentScanning {
+    /// A clamd socket, e.g. tcp://clamav:3310 or unix:///run/clamav/clamd.ctl.
+    pub(crate) clamd_url: Option<String>,
+    /// An HTTP API attachments are POSTed to, responding with
+    /// {"infected": bool, "signature": string}. Authenticated with the
+    /// attachment_scanner/token secret. Used when clamd_url is unset.
+    pub(crate) api_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct License {
+    /// Path to the license file issued by Koso, e.g. /etc/koso/license.jwt.
+    pub(crate) file: Option<String>,
+}
+
+fn default_coalesce_window_ms() -> u64 {
+    20
+}
+
+fn default_trusted_proxies() -> Vec<String> {
+    vec!["127.0.0.1/32".to_string(), "::1/128".to_string()]
+}
+
 pub fn settings() -> &'static Settings {
     static SETTINGS: OnceLock<Settings> = OnceLock::new();
     SETTINGS.get_or_init(|| {
@@ -5,13 +5,15 @@ use crate::{
         collab::{
             awareness::AwarenessState,
             msg_sync::{self, MSG_SYNC, MSG_SYNC_REQUEST, MSG_SYNC_RESPONSE, MSG_SYNC_UPDATE},
+            projections,
             txn_origin::{self, YOrigin},
         },
         google::test_utils::{Claims, KID_1, PEM_1, encode_token, testonly_key_set},
-        model::{CreateProject, Project, ProjectExport, Task},
+        model::{CreateProject, Project, ProjectExport, Task, test_utils},
         yproxy::YDocProxy,
     },
     plugins::PluginSettings,
+    postgres::PgPool,
     server::{self, Config},
     tests::msg_sync::{MSG_KOSO_AWARENESS, MSG_KOSO_AWARENESS_STATE},
 };
@@ -20,7 +22,6 @@ use axum::http::HeaderValue;
 use futures::{SinkExt, StreamExt, stream::FusedStream};
 use reqwest::{Client, Response, StatusCode};
 use serde_json::Value;
-use sqlx::PgPool;
 use tokio::{net::TcpStream, task::JoinHandle};
 use tokio_tungstenite::{
     MaybeTlsStream, WebSocketStream,
@@ -41,7 +42,8 @@ use yrs::{
 };
 
 #[test_log::test(sqlx::test)]
-async fn database_connectivity_test(pool: PgPool) -> sqlx::Result<()> {
+async fn database_connectivity_test(pool: sqlx::PgPool) -> sqlx::Result<()> {
+    let pool = PgPool::from(pool);
     let users: Vec<(String,)> = sqlx::query_as("SELECT email FROM users")
         .fetch_all(&pool)
         .await
@@ -50,10 +52,74 @@ async fn database_connectivity_test(pool: PgPool) -> sqlx::Result<()> {
     Ok(())
 }
 
+#[test_log::test(sqlx::test)]
+async fn task_projections_test(pool: sqlx::PgPool) -> sqlx::Result<()> {
+    let pool = PgPool::from(pool);
+    let project_id = "projections-project".to_string();
+    let as_of = sqlx::types::chrono::Utc::now();
+    projections::upsert_task(
+        &pool,
+        &project_id,
+        &Task {
+            name: "Newer".to_string(),
+            assignee: Some("a@koso.app".to_string()),
+            ..test_utils::task("1", "1", &[])
+        },
+    )
+    .await
+    .unwrap();
+
+    // A rebuild from an older snapshot must not clobber newer updates.
+    projections::rebuild(
+        &pool,
+        &project_id,
+        &[
+            Task {
+                name: "Older".to_string(),
+                assignee: Some("a@koso.app".to_string()),
+                ..test_utils::task("1", "1", &[])
+            },
+            Task {
+                name: "Other".to_string(),
+                assignee: Some("a@koso.app".to_string()),
+                ..test_utils::task("2", "2", &[])
+            },
+        ],
+        as_of,
+    )
+    .await
+    .unwrap();
+    let names: Vec<(String, String)> = sqlx::query_as(
+        "SELECT task_id, name FROM task_projections WHERE project_id = $1 ORDER BY task_id",
+    )
+    .bind(&project_id)
+    .fetch_all(&pool)
+    .await?;
+    assert_eq!(
+        names,
+        vec![
+            ("1".to_string(), "Newer".to_string()),
+            ("2".to_string(), "Other".to_string())
+        ]
+    );
+
+    projections::prune(&pool, &project_id, &["2".to_string()])
+        .await
+        .unwrap();
+    let ids: Vec<(String,)> =
+        sqlx::query_as("SELECT task_id FROM task_projections WHERE project_id = $1")
+            .bind(&project_id)
+            .fetch_all(&pool)
+            .await?;
+    assert_eq!(ids, vec![("2".to_string(),)]);
+    Ok(())
+}
+
 type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
 #[test_log::test(sqlx::test)]
-async fn api_test(pool: PgPool) -> sqlx::Result<()> {
+async fn api_test(pool: sqlx::PgPool) -> sqlx::Result<()> {
+    let pool = PgPool::from(pool);
     let (server, addr) = start_server(&pool).await;
     let client = Client::default();
 
@@ -260,7 +326,8 @@ async fn api_test(pool: PgPool) -> sqlx::Result<()> {
 }
 
 #[test_log::test(sqlx::test)]
-async fn not_invite_user(pool: PgPool) -> sqlx::Result<()> {
+async fn not_invite_user(pool: sqlx::PgPool) -> sqlx::Result<()> {
+    let pool = PgPool::from(pool);
     let (server, addr) = start_server(&pool).await;
     let client = Client::default();
 
@@ -299,6 +366,7 @@ async fn assert_not_premium(res: Response) {
     let error: Value = serde_json::from_str(res.text().await.unwrap().as_str()).unwrap();
     let error = error.as_object().unwrap();
     assert_eq!(error.get("status").unwrap().as_i64().unwrap(), 403);
+    assert_eq!(error.get("code").unwrap().as_str().unwrap(), "FORBIDDEN");
     let details = error.get("details").unwrap().as_array().unwrap();
     assert_eq!(details.len(), 1);
     let detail = details.first().unwrap().as_object().unwrap();
@@ -310,7 +378,8 @@ async fn assert_not_premium(res: Response) {
 }
 
 #[test_log::test(sqlx::test)]
-async fn create_and_delete_project(pool: PgPool) -> sqlx::Result<()> {
+async fn create_and_delete_project(pool: sqlx::PgPool) -> sqlx::Result<()> {
+    let pool = PgPool::from(pool);
     let (mut server, addr) = start_server(&pool).await;
     let client = Client::default();
 
@@ -322,7 +391,35 @@ async fn create_and_delete_project(pool: PgPool) -> sqlx::Result<()> {
     assert_eq!(project.name, "A Project to Delete");
     assert_eq!(project.deleted_on, None);
 
-    let project = delete_project(&client, &addr, &token, &project.project_id)
+    // Deleting requires a recent step up.
+    let res = client
+        .delete(format!("http://{addr}/api/projects/{}", project.project_id))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    // Refreshed credentials don't prove a recent sign in.
+    let res = client
+        .post(format!("http://{addr}/api/auth/stepUp"))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    // Stepping up only grants the session that stepped up.
+    let step_up_token = step_up(&client, &addr, &token).await.unwrap();
+    let res = client
+        .delete(format!("http://{addr}/api/projects/{}", project.project_id))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let project = delete_project(&client, &addr, &token, &step_up_token, &project.project_id)
         .await
         .unwrap();
     assert_eq!(project.name, "A Project to Delete");
@@ -334,7 +431,8 @@ async fn create_and_delete_project(pool: PgPool) -> sqlx::Result<()> {
 }
 
 #[test_log::test(sqlx::test)]
-async fn ws_test(pool: PgPool) -> sqlx::Result<()> {
+async fn ws_test(pool: sqlx::PgPool) -> sqlx::Result<()> {
+    let pool = PgPool::from(pool);
     let (mut server, addr) = start_server(&pool).await;
     let client = Client::default();
 
@@ -583,9 +681,11 @@ async fn ws_test(pool: PgPool) -> sqlx::Result<()> {
 
     // Export the project.
     {
+        let step_up_token = step_up(&client, &addr, &token).await.unwrap();
         let res = client
             .get(format!("http://{addr}/api/projects/{project_id}/export"))
             .bearer_auth(&token)
+            .header("x-step-up-token", &step_up_token)
             .send()
             .await
             .expect("Failed to send request.");
@@ -597,6 +697,7 @@ async fn ws_test(pool: PgPool) -> sqlx::Result<()> {
         let create_req = CreateProject {
             name: "Imported project".to_string(),
             project_export: Some(export),
+            blueprint_id: None,
         };
         let res = client
             .post(format!("http://{addr}/api/projects"))
@@ -630,15 +731,14 @@ async fn ws_test(pool: PgPool) -> sqlx::Result<()> {
             .await
             .unwrap();
     }
-    for _ in 0..10 {
-        ydoc_2
-            .transact_mut_with(origin())
-            .apply_update(read_sync_update(socket_2).await)
-            .unwrap();
-        ydoc_3
-            .transact_mut_with(origin())
-            .apply_update(read_sync_update(socket_3).await)
-            .unwrap();
+    // Updates arriving together are coalesced, so peers may get fewer messages.
+    let expected = ydoc_1.to_graph(&ydoc_1.transact()).unwrap();
+    for (socket, ydoc) in [(&mut *socket_2, &ydoc_2), (&mut *socket_3, &ydoc_3)] {
+        while ydoc.to_graph(&ydoc.transact()).unwrap() != expected {
+            ydoc.transact_mut_with(origin())
+                .apply_update(read_sync_update(socket).await)
+                .unwrap();
+        }
     }
     assert_eq!(
         ydoc_1.to_graph(&ydoc_1.transact()).unwrap(),
@@ -649,6 +749,53 @@ async fn ws_test(pool: PgPool) -> sqlx::Result<()> {
         ydoc_2.to_graph(&ydoc_2.transact()).unwrap()
     );
 
+    // Each stored update records who made it. Updates are stored after they're
+    // broadcast, so wait for the last of them.
+    let mut metadata: Vec<(String, Option<String>, String)> = Vec::new();
+    for _ in 0..50 {
+        metadata = sqlx::query_as(
+            "SELECT actor, email, feature FROM yupdate_metadata WHERE project_id = $1 ORDER BY seq DESC LIMIT 10",
+        )
+        .bind(&project_id)
+        .fetch_all(&pool)
+        .await?;
+        if metadata.len() == 10 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(metadata.len(), 10);
+    for (actor, email, feature) in metadata {
+        assert_eq!(actor, "user");
+        assert_eq!(email.as_deref(), Some(claims.email.as_str()));
+        assert_eq!(feature, "sync");
+    }
+
+    // The changefeed reports the created tasks.
+    {
+        let res = client
+            .get(format!(
+                "http://{addr}/api/projects/{project_id}/changes?since=0"
+            ))
+            .bearer_auth(&token)
+            .send()
+            .await
+            .expect("Failed to send request.");
+        assert_eq!(res.status(), StatusCode::OK);
+        let feed: Value = res.json().await.unwrap();
+        let created: Vec<&str> = feed["changes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|record| record["changes"].as_array().unwrap())
+            .filter(|change| change["kind"] == "created")
+            .map(|change| change["taskId"].as_str().unwrap())
+            .collect();
+        for i in 0..10 {
+            assert!(created.contains(&format!("id{i}").as_str()));
+        }
+    }
+
     // Test other valid and invalid message types.
     {
         let (mut socket, response) = tokio_tungstenite::connect_async(req.clone()).await.unwrap();
@@ -731,7 +878,81 @@ async fn ws_test(pool: PgPool) -> sqlx::Result<()> {
 }
 
 #[test_log::test(sqlx::test)]
-async fn plugin_test(pool: PgPool) -> Result<()> {
+async fn ws_follow_up_test(pool: sqlx::PgPool) -> sqlx::Result<()> {
+    let pool = PgPool::from(pool);
+    let (mut server, addr) = start_server(&pool).await;
+    let client = Client::default();
+    let token = login(&client, &addr, &pool).await.unwrap();
+    let project = create_project(&client, &addr, &token, "Follow Ups")
+        .await
+        .unwrap();
+
+    let mut req = format!("ws://{addr}/api/ws/projects/{}", project.project_id)
+        .into_client_request()
+        .unwrap();
+    req.headers_mut().insert(
+        "Sec-Websocket-Protocol",
+        HeaderValue::from_str(
+            format!("bearer, {token}, koso-client-version, testversion").as_str(),
+        )
+        .unwrap(),
+    );
+    let (mut socket_1, response) = tokio_tungstenite::connect_async(req.clone()).await.unwrap();
+    let socket_1 = &mut socket_1;
+    assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+    assert_eq!(read_sync_request(socket_1).await, StateVector::default());
+    let (mut socket_2, response) = tokio_tungstenite::connect_async(req.clone()).await.unwrap();
+    let socket_2 = &mut socket_2;
+    assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+    assert_eq!(read_sync_request(socket_2).await, StateVector::default());
+
+    // Create two tasks with the same number. Peers get the updates relayed as
+    // sent, then the server's follow-up renumbering the second task.
+    let ydoc_1 = YDocProxy::new();
+    for id in ["id1", "id2"] {
+        let mut txn = ydoc_1.transact_mut_with(origin());
+        ydoc_1.set(
+            &mut txn,
+            &Task {
+                id: id.to_string(),
+                num: "1".to_string(),
+                name: id.to_string(),
+                ..Task::default()
+            },
+        );
+        let update = txn.encode_update_v2();
+        socket_1
+            .send(Message::binary(msg_sync::sync_update(&update)))
+            .await
+            .unwrap();
+    }
+    let ydoc_2 = YDocProxy::new();
+    for (socket, ydoc) in [(&mut *socket_2, &ydoc_2), (&mut *socket_1, &ydoc_1)] {
+        loop {
+            ydoc.transact_mut_with(origin())
+                .apply_update(read_sync_update(socket).await)
+                .unwrap();
+            let graph = ydoc.to_graph(&ydoc.transact()).unwrap();
+            if graph.get("id2").is_some_and(|task| task.num == "2") {
+                break;
+            }
+        }
+    }
+    assert_eq!(
+        ydoc_1.to_graph(&ydoc_1.transact()).unwrap(),
+        ydoc_2.to_graph(&ydoc_2.transact()).unwrap()
+    );
+
+    close_socket(socket_1).await;
+    close_socket(socket_2).await;
+    server.start_shutdown().await;
+    server.wait_for_shutdown().await.unwrap();
+    Ok(())
+}
+
+#[test_log::test(sqlx::test)]
+async fn plugin_test(pool: sqlx::PgPool) -> Result<()> {
+    let pool = PgPool::from(pool);
     let (server, addr) = start_server(&pool).await;
     let client = Client::default();
 
@@ -920,6 +1141,374 @@ async fn plugin_test(pool: PgPool) -> Result<()> {
     Ok(())
 }
 
+#[test_log::test(sqlx::test)]
+async fn set_config_setting_keeps_other_settings(pool: sqlx::PgPool) -> Result<()> {
+    let pool = PgPool::from(pool);
+    let (server, addr) = start_server(&pool).await;
+    let client = Client::default();
+    let token = login(&client, &addr, &pool).await?;
+    let project = create_project(&client, &addr, &token, "Configured").await?;
+    let project_id = project.project_id;
+
+    let res = client
+        .put(format!(
+            "http://{addr}/api/projects/{project_id}/config/labels"
+        ))
+        .bearer_auth(&token)
+        .header("Content-Type", "application/json")
+        .body(r##"[{"name":"Bug","color":"#d73a4a"}]"##)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Settings are validated against the rest of the configuration.
+    let res = client
+        .put(format!(
+            "http://{addr}/api/projects/{project_id}/config/autoArchive"
+        ))
+        .bearer_auth(&token)
+        .header("Content-Type", "application/json")
+        .body(r#"{"afterDays":30,"excludeLabel":"Missing"}"#)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let res = client
+        .put(format!(
+            "http://{addr}/api/projects/{project_id}/config/autoArchive"
+        ))
+        .bearer_auth(&token)
+        .header("Content-Type", "application/json")
+        .body(r#"{"afterDays":30,"excludeLabel":"Bug"}"#)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+    let config: Value = serde_json::from_str(res.text().await.unwrap().as_str()).unwrap();
+    assert_eq!(
+        config["labels"],
+        serde_json::json!([{"name":"Bug","color":"#d73a4a"}])
+    );
+    assert_eq!(
+        config["autoArchive"],
+        serde_json::json!({"afterDays":30,"excludeLabel":"Bug"})
+    );
+
+    server.shutdown_and_wait().await.unwrap();
+    Ok(())
+}
+
+#[test_log::test(sqlx::test)]
+async fn set_config_requires_review(pool: sqlx::PgPool) -> Result<()> {
+    let pool = PgPool::from(pool);
+    let (server, addr) = start_server(&pool).await;
+    let client = Client::default();
+    let (token, reviewed_token, project_id) =
+        create_reviewed_project(&client, &addr, &pool).await?;
+
+    let res = client
+        .put(format!("http://{addr}/api/projects/{project_id}/config"))
+        .bearer_auth(&reviewed_token)
+        .header("Content-Type", "application/json")
+        .body(r##"{"labels":[{"name":"Bug","color":"#d73a4a"}]}"##)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(count_proposed_updates(&pool, &project_id).await?, 1);
+
+    let res = client
+        .get(format!("http://{addr}/api/projects/{project_id}/config"))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+    let config: Value = serde_json::from_str(res.text().await.unwrap().as_str()).unwrap();
+    assert_eq!(config["labels"], serde_json::json!([]));
+
+    server.shutdown_and_wait().await.unwrap();
+    Ok(())
+}
+
+#[test_log::test(sqlx::test)]
+async fn set_board_column_requires_review(pool: sqlx::PgPool) -> Result<()> {
+    let pool = PgPool::from(pool);
+    let (server, addr) = start_server(&pool).await;
+    let client = Client::default();
+    let (_, reviewed_token, project_id) = create_reviewed_project(&client, &addr, &pool).await?;
+
+    let res = client
+        .put(format!(
+            "http://{addr}/api/projects/{project_id}/board/Not%20Started"
+        ))
+        .bearer_auth(&reviewed_token)
+        .header("Content-Type", "application/json")
+        .body(r#"{"status":"Not Started","taskIds":["1"]}"#)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(count_proposed_updates(&pool, &project_id).await?, 1);
+
+    server.shutdown_and_wait().await.unwrap();
+    Ok(())
+}
+
+#[test_log::test(sqlx::test)]
+async fn set_auto_assign_requires_review(pool: sqlx::PgPool) -> Result<()> {
+    let pool = PgPool::from(pool);
+    let (server, addr) = start_server(&pool).await;
+    let client = Client::default();
+    let (_, reviewed_token, project_id) = create_reviewed_project(&client, &addr, &pool).await?;
+
+    let res = client
+        .put(format!(
+            "http://{addr}/api/projects/{project_id}/tasks/1/autoAssign"
+        ))
+        .bearer_auth(&reviewed_token)
+        .header("Content-Type", "application/json")
+        .body(format!(
+            "{{\"mode\":\"roundRobin\",\"members\":[\"{REVIEWED_USER_EMAIL}\"]}}"
+        ))
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(count_proposed_updates(&pool, &project_id).await?, 1);
+
+    server.shutdown_and_wait().await.unwrap();
+    Ok(())
+}
+
+#[test_log::test(sqlx::test)]
+async fn merge_branch_requires_review(pool: sqlx::PgPool) -> Result<()> {
+    let pool = PgPool::from(pool);
+    let (server, addr) = start_server(&pool).await;
+    let client = Client::default();
+    let (token, reviewed_token, project_id) =
+        create_reviewed_project(&client, &addr, &pool).await?;
+
+    // Branches belong to their creator, so edits to them aren't reviewed.
+    let res = client
+        .post(format!("http://{addr}/api/projects/{project_id}/branches"))
+        .bearer_auth(&reviewed_token)
+        .header("Content-Type", "application/json")
+        .body(r#"{"name":"Draft"}"#)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+    let branch: Value = serde_json::from_str(res.text().await.unwrap().as_str()).unwrap();
+    let branch_id = branch["branchProjectId"].as_str().unwrap().to_string();
+    let res = client
+        .put(format!("http://{addr}/api/projects/{branch_id}/config"))
+        .bearer_auth(&reviewed_token)
+        .header("Content-Type", "application/json")
+        .body(r##"{"labels":[{"name":"Bug","color":"#d73a4a"}]}"##)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(count_proposed_updates(&pool, &branch_id).await?, 0);
+
+    // Merging them is.
+    let res = client
+        .post(format!(
+            "http://{addr}/api/projects/{project_id}/branches/{branch_id}/merge"
+        ))
+        .bearer_auth(&reviewed_token)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(count_proposed_updates(&pool, &project_id).await?, 1);
+
+    let res = client
+        .get(format!("http://{addr}/api/projects/{project_id}/config"))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+    let config: Value = serde_json::from_str(res.text().await.unwrap().as_str()).unwrap();
+    assert_eq!(config["labels"], serde_json::json!([]));
+
+    server.shutdown_and_wait().await.unwrap();
+    Ok(())
+}
+
+#[test_log::test(sqlx::test)]
+async fn new_member_made_admin_can_review_proposals(pool: sqlx::PgPool) -> Result<()> {
+    let pool = PgPool::from(pool);
+    let (server, addr) = start_server(&pool).await;
+    let client = Client::default();
+    let (token, reviewed_token, project_id) =
+        create_reviewed_project(&client, &addr, &pool).await?;
+
+    // Add a new member, who isn't an admin.
+    let reviewer_email = "new-reviewer@koso.app";
+    let reviewer_token = login_as(&client, &addr, &pool, reviewer_email).await?;
+    let res = client
+        .patch(format!("http://{addr}/api/projects/{project_id}/users"))
+        .bearer_auth(&token)
+        .header("Content-Type", "application/json")
+        .body(format!(
+            "{{\"projectId\":\"{project_id}\", \"addEmails\":[\"{reviewer_email}\"], \"removeEmails\":[]}}"
+        ))
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let proposal_id = propose_label(&client, &addr, &reviewed_token, &project_id, "Bug").await?;
+    let res = client
+        .post(format!(
+            "http://{addr}/api/projects/{project_id}/proposals/{proposal_id}/apply"
+        ))
+        .bearer_auth(&reviewer_token)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    // Members can't make themselves admins.
+    let res = client
+        .put(format!(
+            "http://{addr}/api/projects/{project_id}/users/{reviewer_email}/admin"
+        ))
+        .bearer_auth(&reviewer_token)
+        .header("Content-Type", "application/json")
+        .body(r#"{"admin":true}"#)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res = client
+        .put(format!(
+            "http://{addr}/api/projects/{project_id}/users/{reviewer_email}/admin"
+        ))
+        .bearer_auth(&token)
+        .header("Content-Type", "application/json")
+        .body(r#"{"admin":true}"#)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = client
+        .post(format!(
+            "http://{addr}/api/projects/{project_id}/proposals/{proposal_id}/apply"
+        ))
+        .bearer_auth(&reviewer_token)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let proposal_id = propose_label(&client, &addr, &reviewed_token, &project_id, "Chore").await?;
+    let res = client
+        .post(format!(
+            "http://{addr}/api/projects/{project_id}/proposals/{proposal_id}/reject"
+        ))
+        .bearer_auth(&reviewer_token)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let (status, reviewer): (String, Option<String>) =
+        sqlx::query_as("SELECT status, reviewer FROM change_proposals WHERE id = $1")
+            .bind(&proposal_id)
+            .fetch_one(&pool)
+            .await?;
+    assert_eq!(status, "rejected");
+    assert_eq!(reviewer.as_deref(), Some(reviewer_email));
+
+    server.shutdown_and_wait().await.unwrap();
+    Ok(())
+}
+
+#[test_log::test(sqlx::test)]
+async fn last_admin_cannot_be_revoked(pool: sqlx::PgPool) -> Result<()> {
+    let pool = PgPool::from(pool);
+    let (server, addr) = start_server(&pool).await;
+    let client = Client::default();
+    let (token, _, project_id) = create_reviewed_project(&client, &addr, &pool).await?;
+    let email = Claims::default().email;
+
+    let res = client
+        .put(format!(
+            "http://{addr}/api/projects/{project_id}/users/{email}/admin"
+        ))
+        .bearer_auth(&token)
+        .header("Content-Type", "application/json")
+        .body(r#"{"admin":false}"#)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let res = client
+        .patch(format!("http://{addr}/api/projects/{project_id}/users"))
+        .bearer_auth(&token)
+        .header("Content-Type", "application/json")
+        .body(format!(
+            "{{\"projectId\":\"{project_id}\", \"addEmails\":[], \"removeEmails\":[\"{email}\"]}}"
+        ))
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let admins: Vec<(String,)> =
+        sqlx::query_as("SELECT email FROM project_permissions WHERE project_id = $1 AND admin")
+            .bind(&project_id)
+            .fetch_all(&pool)
+            .await?;
+    assert_eq!(admins, vec![(email,)]);
+
+    server.shutdown_and_wait().await.unwrap();
+    Ok(())
+}
+
+/// Proposes replacing the project's labels with the given label as [REVIEWED_USER_EMAIL],
+/// returning the pending proposal's id.
+async fn propose_label(
+    client: &Client,
+    addr: &SocketAddr,
+    reviewed_token: &str,
+    project_id: &str,
+    label: &str,
+) -> Result<String> {
+    let res = client
+        .put(format!("http://{addr}/api/projects/{project_id}/config"))
+        .bearer_auth(reviewed_token)
+        .header("Content-Type", "application/json")
+        .body(format!(
+            r##"{{"labels":[{{"name":"{label}","color":"#d73a4a"}}]}}"##
+        ))
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = client
+        .get(format!("http://{addr}/api/projects/{project_id}/proposals"))
+        .bearer_auth(reviewed_token)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+    let proposals: Value = serde_json::from_str(res.text().await.unwrap().as_str()).unwrap();
+    let proposal = &proposals.as_array().unwrap()[0];
+    assert_eq!(proposal["status"], "pending");
+    Ok(proposal["id"].as_str().unwrap().to_string())
+}
+
 async fn read_sync_request(socket: &mut Socket) -> StateVector {
     let sync_request = next_with_timeout(socket).await.unwrap().unwrap();
 
@@ -1071,11 +1660,13 @@ async fn delete_project(
     client: &Client,
     addr: &SocketAddr,
     token: &str,
+    step_up_token: &str,
     project_id: &str,
 ) -> Result<Project> {
     let res = client
         .delete(format!("http://{addr}/api/projects/{project_id}"))
         .bearer_auth(token)
+        .header("x-step-up-token", step_up_token)
         .header("Content-Type", "application/json")
         .send()
         .await
@@ -1103,7 +1694,20 @@ async fn create_project(
 }
 
 async fn login(client: &Client, addr: &SocketAddr, pool: &PgPool) -> Result<String> {
-    let claims = Claims::default();
+    login_as(client, addr, pool, &Claims::default().email).await
+}
+
+/// Logs in as a premium user with the given email, returning their token.
+async fn login_as(
+    client: &Client,
+    addr: &SocketAddr,
+    pool: &PgPool,
+    email: &str,
+) -> Result<String> {
+    let claims = Claims {
+        email: email.to_string(),
+        ..Claims::default()
+    };
     let token: String = encode_token(&claims, KID_1, PEM_1).unwrap();
 
     // Login
@@ -1118,6 +1722,107 @@ async fn login(client: &Client, addr: &SocketAddr, pool: &PgPool) -> Result<Stri
     Ok(token)
 }
 
+/// Re-authenticates with a step up nonce and steps up, returning the step-up token.
+async fn step_up(client: &Client, addr: &SocketAddr, token: &str) -> Result<String> {
+    let res = client
+        .post(format!("http://{addr}/api/auth/stepUp/nonce"))
+        .bearer_auth(token)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+    let nonce: Value = serde_json::from_str(res.text().await.unwrap().as_str()).unwrap();
+    let claims = Claims {
+        nonce: Some(nonce["nonce"].as_str().unwrap().to_string()),
+        ..Claims::default()
+    };
+    let fresh_token = encode_token(&claims, KID_1, PEM_1).unwrap();
+
+    let res = client
+        .post(format!("http://{addr}/api/auth/stepUp"))
+        .bearer_auth(&fresh_token)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+    let step_up: Value = serde_json::from_str(res.text().await.unwrap().as_str()).unwrap();
+
+    // Nonces are single use.
+    let res = client
+        .post(format!("http://{addr}/api/auth/stepUp"))
+        .bearer_auth(&fresh_token)
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    Ok(step_up["token"].as_str().unwrap().to_string())
+}
+
+const REVIEWED_USER_EMAIL: &str = "reviewed-user@koso.app";
+
+/// Creates a project requiring review, with one task, "1", and a member,
+/// [REVIEWED_USER_EMAIL], whose changes must be reviewed.
+/// Returns the owner's token, the member's token and the project's id.
+async fn create_reviewed_project(
+    client: &Client,
+    addr: &SocketAddr,
+    pool: &PgPool,
+) -> Result<(String, String, String)> {
+    let token = login(client, addr, pool).await?;
+    let create_req = CreateProject {
+        name: "Reviewed project".to_string(),
+        project_export: Some(ProjectExport {
+            project_id: "export".to_string(),
+            graph: test_utils::graph([
+                test_utils::task("root", "0", &["1"]),
+                test_utils::task("1", "1", &[]),
+            ]),
+            risks: vec![],
+        }),
+        blueprint_id: None,
+    };
+    let res = client
+        .post(format!("http://{addr}/api/projects"))
+        .bearer_auth(&token)
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_string(&create_req).unwrap())
+        .send()
+        .await
+        .expect("Failed to send request.");
+    assert_eq!(res.status(), StatusCode::OK);
+    let project: Project = serde_json::from_str(res.text().await.unwrap().as_str()).unwrap();
+
+    let reviewed_token = login_as(client, addr, pool, REVIEWED_USER_EMAIL).await?;
+    sqlx::query(
+        "INSERT INTO project_permissions (project_id, email, admin) VALUES ($1, $2, FALSE)",
+    )
+    .bind(&project.project_id)
+    .bind(REVIEWED_USER_EMAIL)
+    .execute(pool)
+    .await?;
+    sqlx::query("UPDATE projects SET require_review = TRUE WHERE project_id = $1")
+        .bind(&project.project_id)
+        .execute(pool)
+        .await?;
+    Ok((token, reviewed_token, project.project_id))
+}
+
+/// Counts the updates [REVIEWED_USER_EMAIL] proposed to the project.
+async fn count_proposed_updates(pool: &PgPool, project_id: &str) -> Result<i64> {
+    let (count,): (i64,) = sqlx::query_as(
+        "
+        SELECT COUNT(*)
+        FROM proposal_updates u
+        JOIN change_proposals p ON p.id = u.proposal_id
+        WHERE p.project_id = $1 AND p.author = $2",
+    )
+    .bind(project_id)
+    .bind(REVIEWED_USER_EMAIL)
+    .fetch_one(pool)
+    .await?;
+    Ok(count)
+}
+
 async fn set_user_premium(email: &str, pool: &PgPool) -> Result<()> {
     sqlx::query(
         "
@@ -1136,6 +1841,7 @@ fn origin() -> Origin {
         who: "tests.rs".to_string(),
         id: "test".to_string(),
         actor: txn_origin::Actor::Server,
+        ..Default::default()
     }
     .as_origin()
     .unwrap()
@@ -1177,6 +1883,7 @@ async fn start_server(pool: &PgPool) -> (ServerHandle, SocketAddr) {
         plugin_settings: Some(PluginSettings {
             disable_polling: true,
         }),
+        ..Default::default()
     })
     .await
     .unwrap();
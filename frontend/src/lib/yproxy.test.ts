@@ -68,6 +68,17 @@ describe("YTaskProxy", () => {
     expect(task.deadline).toStrictEqual(now);
   });
 
+  it("should reset the deadline kind when setting a deadline", () => {
+    const yTask = doc.getMap("graph").get("task-1") as Y.Map<unknown>;
+    yTask.set("deadlineKind", "dateTime");
+    yTask.set("deadlineTimeZone", "America/Los_Angeles");
+    task.deadline = 1735689600000;
+    expect(yTask.get("deadlineKind")).toStrictEqual("date");
+    expect(yTask.has("deadlineTimeZone")).toBeFalsy();
+    task.deadline = null;
+    expect(yTask.get("deadlineKind")).toBeNull();
+  });
+
   describe("Kind operations", () => {
     describe("Auto", () => {
       it("Rollup", () => {
@@ -255,8 +255,14 @@ export class YTaskProxy {
     return (this.#yTask.get("deadline") as number) || null;
   }
 
+  /**
+   * Sets a date deadline, as millis at midnight UTC. Also resets the kind so
+   * the server doesn't reinterpret the date as a previously set date time.
+   */
   set deadline(value: number | null) {
     this.#yTask.set("deadline", value);
+    this.#yTask.set("deadlineKind", value !== null ? "date" : null);
+    this.#yTask.delete("deadlineTimeZone");
   }
 
   get archived(): boolean | null {